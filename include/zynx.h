@@ -0,0 +1,53 @@
+// C ABI of zynx for native LiteLoader libraries, mirrored by `NativeContext` in
+// src/bridge-shared/src/policy/liteloader.rs.
+
+#ifndef ZYNX_H
+#define ZYNX_H
+
+#include <jni.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define ZYNX_CONTEXT_VERSION 3
+
+// Called on each entry of a hooked method with its `ArtMethod *`, before running it. Runs in
+// managed code on the calling thread: it must not call JNI nor block.
+typedef void (*zynx_art_entry_callback)(void *method, void *user_data);
+
+// Minimal ART hooks for tracing, valid for the lifetime of the process. Calls from compiled
+// code and JNI go through hooked entries, calls between interpreted methods may not, nor calls
+// from code compiled before hooking (e.g. the boot image) which inlined the method.
+typedef struct zynx_art_api {
+    // `ArtMethod *` of a method of `clazz`, NULL if not found
+    void *(*find_method)(JNIEnv *env, jclass clazz, const char *name, const char *signature,
+                         bool is_static);
+    // Call `callback` on entry of `method`, returns 0 on success. `method` must come from
+    // `find_method`, which initializes its class
+    int (*hook_entry)(void *method, zynx_art_entry_callback callback, void *user_data);
+    // Since version 3, restore the entry of a hooked `method`, returns 0 on success
+    int (*unhook_entry)(void *method);
+} zynx_art_api;
+
+// Fields are only ever appended, check `version` before reading newer ones. Pointers may be
+// NULL, and are only valid during the call of `zynx_native_entry`.
+typedef struct zynx_context {
+    uint32_t version;
+    int32_t uid;
+    // ro.build.version.sdk
+    int32_t api_level;
+    JNIEnv *env;
+    const char *nice_name;
+    const char *app_data_dir;
+    const char *package_name;
+    // `data` of the library manifest
+    const uint8_t *data;
+    size_t data_len;
+    // Since version 2, NULL if hooks aren't supported on this release
+    const zynx_art_api *art;
+} zynx_context;
+
+// Export it to be called right after the library is loaded, before the manifest `entry`
+void zynx_native_entry(const zynx_context *context);
+
+#endif // ZYNX_H
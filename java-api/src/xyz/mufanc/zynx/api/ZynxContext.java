@@ -0,0 +1,59 @@
+package xyz.mufanc.zynx.api;
+
+import java.util.Collections;
+import java.util.Map;
+
+/**
+ * Typed view of the map passed to the {@code public static void init(Map<String, Object>)}
+ * entry of LiteLoader dex libraries. Values the daemon couldn't tell are {@code null}.
+ *
+ * <pre>{@code
+ * public class Main {
+ *     public static void init(Map<String, Object> args) {
+ *         ZynxContext context = new ZynxContext(args);
+ *         Log.i("Example", "loaded into " + context.getPackageName());
+ *     }
+ * }
+ * }</pre>
+ */
+public final class ZynxContext {
+
+    /** Package the process runs, the first one for shared uids */
+    public static final String KEY_PACKAGE_NAME = "package_name";
+
+    /** Name of the process, e.g. {@code com.example.app:remote} */
+    public static final String KEY_PROCESS_NAME = "process_name";
+
+    /** Data directory of the app */
+    public static final String KEY_DATA_DIR = "data_dir";
+
+    /** {@code data} of the library manifest, as UTF-8 bytes */
+    public static final String KEY_DATA = "data";
+
+    private final Map<String, Object> args;
+
+    public ZynxContext(Map<String, Object> args) {
+        this.args = Collections.unmodifiableMap(args);
+    }
+
+    public String getPackageName() {
+        return (String) args.get(KEY_PACKAGE_NAME);
+    }
+
+    public String getProcessName() {
+        return (String) args.get(KEY_PROCESS_NAME);
+    }
+
+    public String getDataDir() {
+        return (String) args.get(KEY_DATA_DIR);
+    }
+
+    public byte[] getData() {
+        return (byte[]) args.get(KEY_DATA);
+    }
+
+    /** The raw map, holding keys added by later versions as well */
+    public Map<String, Object> asMap() {
+        return args;
+    }
+}
@@ -1,7 +1,12 @@
 use crate::zygote::ProviderBundle;
 use anyhow::Result;
+use std::cell::RefCell;
 use zynx_bridge_shared::zygote::{ProviderType, SpecializeArgs};
 
+thread_local! {
+    static CURRENT_MODULE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
 pub trait ProviderHandler: Send + Sync + 'static {
     const TYPE: ProviderType;
 
@@ -12,4 +17,26 @@ pub trait ProviderHandler: Send + Sync + 'static {
     fn on_specialize_post(_args: &SpecializeArgs, _bundle: &mut ProviderBundle) -> Result<()> {
         Ok(())
     }
+
+    /// Called once the bridge is loaded into a native process, before its `main` runs.
+    fn on_native_start(_bundle: &mut ProviderBundle) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Run code of `module` (an [`Attachment::module`](crate::zygote::Attachment::module)), so that
+/// a panic in it is blamed on that module only, instead of on every module of the bundle.
+pub fn in_module<R>(module: Option<&str>, f: impl FnOnce() -> R) -> R {
+    let previous = CURRENT_MODULE.replace(module.map(String::from));
+    let result = f();
+
+    // left in place if `f` panics, for `take_panicked_module`
+    CURRENT_MODULE.set(previous);
+
+    result
+}
+
+/// Module that was running when a handler panicked, see [`in_module`].
+pub fn take_panicked_module() -> Option<String> {
+    CURRENT_MODULE.take()
 }
@@ -1,10 +1,12 @@
 use std::os::fd::OwnedFd;
-use zynx_bridge_shared::zygote::ProviderType;
+use zynx_bridge_shared::zygote::{ArgsMutation, ProviderType};
 
 #[derive(Debug)]
 pub struct Attachment {
     pub fd: Option<OwnedFd>,
     pub data: Option<Vec<u8>>,
+    /// Module this attachment loads, as `<provider>:<name>`, see [`crate::injector::in_module`]
+    pub module: Option<String>,
 }
 
 #[derive(Debug)]
@@ -12,4 +14,6 @@ pub struct ProviderBundle {
     pub ty: ProviderType,
     pub attachments: Vec<Attachment>,
     pub data: Option<Vec<u8>>,
+    /// Already applied to the args passed to the handler
+    pub mutation: Option<ArgsMutation>,
 }
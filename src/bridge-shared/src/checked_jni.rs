@@ -0,0 +1,191 @@
+//! Exception-safe access to JNI from the bridge.
+//!
+//! A pending exception left behind by the bridge surfaces later in unrelated app code, or
+//! aborts the runtime on the next JNI call with CheckJNI. [`with_env`] runs each interaction
+//! in its own local frame and turns any exception it leaves into an error.
+
+use anyhow::{Error, Result, anyhow, bail};
+use jni::sys::{JNI_OK, JNIEnv, jstring};
+use jni::{Env, EnvOutcome, EnvUnowned, Outcome};
+use std::ffi::{CStr, c_char};
+use std::ptr;
+
+/// Local references each interaction may create before the runtime grows the frame
+const LOCAL_FRAME_CAPACITY: i32 = 32;
+
+/// Call `$func` of the JNI function table, for envs of the calling thread only. The args are
+/// raw JNI handles, evaluating them can't be unsafe by itself.
+#[macro_export]
+macro_rules! jni_call {
+    ($env: expr, $func: ident $(, $args: expr)*) => {{
+        let env: *mut jni::sys::JNIEnv = $env;
+        #[allow(clippy::macro_metavars_in_unsafe)]
+        let result = unsafe { ((**env).v1_6.$func)(env $(, $args)*) };
+        result
+    }};
+}
+
+/// Clear the pending exception of `env`, returning its `toString()`.
+fn take_exception(env: *mut JNIEnv) -> Option<String> {
+    if !jni_call!(env, ExceptionCheck) {
+        return None;
+    }
+
+    let throwable = jni_call!(env, ExceptionOccurred);
+
+    jni_call!(env, ExceptionClear);
+
+    let class = jni_call!(env, GetObjectClass, throwable);
+    let method = jni_call!(
+        env,
+        GetMethodID,
+        class,
+        c"toString".as_ptr(),
+        c"()Ljava/lang/String;".as_ptr()
+    );
+    let message: jstring = jni_call!(env, CallObjectMethodA, throwable, method, ptr::null());
+
+    // thrown by toString itself
+    if jni_call!(env, ExceptionCheck) || message.is_null() {
+        jni_call!(env, ExceptionClear);
+        return Some("(unknown exception)".into());
+    }
+
+    let chars: *const c_char = jni_call!(env, GetStringUTFChars, message, ptr::null_mut());
+    let value = unsafe { CStr::from_ptr(chars) }
+        .to_string_lossy()
+        .into_owned();
+
+    jni_call!(env, ReleaseStringUTFChars, message, chars);
+
+    Some(value)
+}
+
+/// Run `f` with the env of the calling thread, inside a local frame popped on return. An
+/// exception left pending, e.g. by a failed call `f` bailed on, is cleared and returned as
+/// the error instead.
+pub fn with_env<T>(env: JNIEnv, f: impl FnOnce(&mut Env) -> Result<T>) -> Result<T> {
+    let raw: *mut JNIEnv = env as _;
+
+    if jni_call!(raw, PushLocalFrame, LOCAL_FRAME_CAPACITY) != JNI_OK {
+        let exception = take_exception(raw);
+        bail!("failed to push local frame: {exception:?}");
+    }
+
+    let mut unowned = unsafe { EnvUnowned::from_raw(raw as _) };
+    let outcome: EnvOutcome<T, Error> = unowned.with_env_no_catch(f);
+
+    let result = match outcome.into_outcome() {
+        Outcome::Ok(value) => Ok(value),
+        Outcome::Err(err) => Err(err),
+        Outcome::Panic(_) => Err(anyhow!("panicked in jni call")),
+    };
+
+    let exception = take_exception(raw);
+
+    jni_call!(raw, PopLocalFrame, ptr::null_mut());
+
+    match exception {
+        Some(exception) => Err(anyhow!("java exception: {exception}")),
+        None => result,
+    }
+}
+
+#[cfg(all(test, feature = "host-jvm"))]
+mod tests {
+    use super::*;
+    use jni::{InitArgsBuilder, JavaVM};
+    use std::ffi::c_void;
+    use std::sync::LazyLock;
+
+    /// One JVM per process, CheckJNI reports misuse such as calls with an exception pending
+    static JVM: LazyLock<JavaVM> = LazyLock::new(|| {
+        let args = InitArgsBuilder::new()
+            .option("-Xcheck:jni")
+            .build()
+            .expect("invalid JVM args");
+
+        JavaVM::new(args).expect("failed to start the JVM, is JAVA_HOME set?")
+    });
+
+    /// Env of the current test thread, attached like threads of the bridge calling into the
+    /// runtime are.
+    fn attach() -> JNIEnv {
+        let vm = JVM.get_raw();
+        let mut env: *mut c_void = ptr::null_mut();
+        let result = unsafe { ((**vm).v1_4.AttachCurrentThread)(vm, &mut env, ptr::null_mut()) };
+
+        assert_eq!(result, JNI_OK, "failed to attach the thread");
+
+        env as _
+    }
+
+    fn throw(env: *mut JNIEnv, class: &CStr, message: &CStr) {
+        let class = jni_call!(env, FindClass, class.as_ptr());
+        jni_call!(env, ThrowNew, class, message.as_ptr());
+    }
+
+    #[test]
+    fn value_is_returned() {
+        let env = attach();
+        let raw: *mut JNIEnv = env as _;
+
+        let len = with_env(env, |env| {
+            // released with the frame
+            for index in 0..LOCAL_FRAME_CAPACITY {
+                env.new_string(format!("local {index}"))?;
+            }
+
+            Ok(env.new_string("zynx")?.to_string().len())
+        });
+
+        assert_eq!(len.unwrap(), 4);
+        assert!(!jni_call!(raw, ExceptionCheck));
+    }
+
+    #[test]
+    fn pending_exception_is_an_error() {
+        let env = attach();
+        let raw: *mut JNIEnv = env as _;
+
+        let result = with_env(env, |env| {
+            throw(env.get_raw(), c"java/lang/IllegalStateException", c"boom");
+            Ok(())
+        });
+
+        let err = result.unwrap_err().to_string();
+
+        assert_eq!(err, "java exception: java.lang.IllegalStateException: boom");
+        assert!(!jni_call!(raw, ExceptionCheck));
+    }
+
+    #[test]
+    fn exception_replaces_the_error_it_caused() {
+        let env = attach();
+
+        let result: Result<()> = with_env(env, |env| {
+            let raw = env.get_raw();
+            let class = jni_call!(raw, FindClass, c"zynx/Missing".as_ptr());
+
+            if class.is_null() {
+                bail!("class not found");
+            }
+
+            Ok(())
+        });
+
+        let err = result.unwrap_err().to_string();
+
+        assert!(
+            err.starts_with("java exception: java.lang.NoClassDefFoundError: zynx/Missing"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn error_without_exception_is_kept() {
+        let result: Result<()> = with_env(attach(), |_| bail!("failed on our own"));
+
+        assert_eq!(result.unwrap_err().to_string(), "failed on our own");
+    }
+}
@@ -1,3 +1,6 @@
+pub mod checked_jni;
+pub mod log_buffer;
 pub mod policy;
 pub mod remote_lib;
+pub mod rpc;
 pub mod zygote;
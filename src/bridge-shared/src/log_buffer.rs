@@ -0,0 +1,201 @@
+use anyhow::{Result, bail};
+use nix::libc;
+use nix::libc::{MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE};
+use parking_lot::Mutex;
+use std::fs::File;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::{mem, ptr};
+
+/// Size of the data area of a bridge log buffer.
+pub const LOG_BUFFER_DATA_SIZE: usize = 256 * 1024;
+
+/// Total size of the memfd backing a bridge log buffer (header + data).
+pub const LOG_BUFFER_SIZE: usize = size_of::<LogBufferHeader>() + LOG_BUFFER_DATA_SIZE;
+
+const LOG_BUFFER_MAGIC: u32 = u32::from_le_bytes(*b"ZXLG");
+
+/// Header of the shared log ring buffer.
+///
+/// `write_pos` and `read_pos` grow monotonically, the data offset of a position is
+/// `pos % LOG_BUFFER_DATA_SIZE`. The bridge is the only writer of `write_pos` and
+/// `dropped`, the core is the only writer of `read_pos`.
+#[repr(C)]
+pub struct LogBufferHeader {
+    magic: u32,
+    dropped: AtomicU32,
+    write_pos: AtomicU64,
+    read_pos: AtomicU64,
+}
+
+/// Bridge side of the log buffer, backed by a shared mapping of the memfd.
+pub struct LogBufferWriter {
+    base: *mut u8,
+    lock: Mutex<()>,
+}
+
+unsafe impl Send for LogBufferWriter {}
+unsafe impl Sync for LogBufferWriter {}
+
+impl LogBufferWriter {
+    pub fn from_fd(fd: OwnedFd) -> Result<Self> {
+        let file = File::from(fd);
+
+        if file.metadata()?.len() < LOG_BUFFER_SIZE as u64 {
+            bail!("log buffer too small");
+        }
+
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                LOG_BUFFER_SIZE,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+
+        if base == MAP_FAILED {
+            bail!("failed to mmap log buffer");
+        }
+
+        let instance = Self {
+            base: base as _,
+            lock: Mutex::new(()),
+        };
+
+        if instance.header().magic != LOG_BUFFER_MAGIC {
+            bail!("invalid log buffer magic");
+        }
+
+        Ok(instance)
+    }
+
+    fn header(&self) -> &LogBufferHeader {
+        unsafe { &*(self.base as *const LogBufferHeader) }
+    }
+
+    /// Append `data` to the buffer. Records that don't fit are dropped and counted.
+    pub fn write(&self, data: &[u8]) -> bool {
+        let _guard = self.lock.lock();
+        let header = self.header();
+
+        let write_pos = header.write_pos.load(Ordering::Relaxed);
+        let read_pos = header.read_pos.load(Ordering::Acquire);
+        let free = LOG_BUFFER_DATA_SIZE - (write_pos - read_pos) as usize;
+
+        if data.len() > free {
+            header.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        let data_base = unsafe { self.base.add(size_of::<LogBufferHeader>()) };
+        let offset = write_pos as usize % LOG_BUFFER_DATA_SIZE;
+        let first = data.len().min(LOG_BUFFER_DATA_SIZE - offset);
+
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), data_base.add(offset), first);
+            ptr::copy_nonoverlapping(data[first..].as_ptr(), data_base, data.len() - first);
+        }
+
+        header
+            .write_pos
+            .store(write_pos + data.len() as u64, Ordering::Release);
+
+        true
+    }
+
+    /// Hold the lock across `fork`, so that the forked process doesn't copy the buffer while
+    /// a record is half written. Released by [`Self::unlock_after_fork`] in the parent.
+    pub fn lock_for_fork(&self) {
+        mem::forget(self.lock.lock());
+    }
+
+    /// # Safety
+    ///
+    /// Must pair with a [`Self::lock_for_fork`] on the same thread. Not to be called in the
+    /// child, where unparking waiters may deadlock on threads that don't exist anymore.
+    pub unsafe fn unlock_after_fork(&self) {
+        unsafe { self.lock.force_unlock() }
+    }
+
+    /// Drop the mapping of the buffer in a forked process, leaving the lock held so that
+    /// nothing writes to it anymore.
+    ///
+    /// # Safety
+    ///
+    /// Must be called in the child after a [`Self::lock_for_fork`] on the same thread, and the
+    /// writer never dropped.
+    pub unsafe fn unmap_in_child(&self) {
+        unsafe {
+            libc::munmap(self.base as _, LOG_BUFFER_SIZE);
+        }
+    }
+}
+
+impl Drop for LogBufferWriter {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base as _, LOG_BUFFER_SIZE);
+        }
+    }
+}
+
+/// Core side of the log buffer, accessing the memfd with positional reads/writes.
+pub struct LogBufferReader {
+    file: File,
+}
+
+impl LogBufferReader {
+    /// Initialize a freshly created memfd as an empty log buffer.
+    pub fn create(file: File) -> Result<Self> {
+        file.set_len(LOG_BUFFER_SIZE as _)?;
+        file.write_all_at(&LOG_BUFFER_MAGIC.to_ne_bytes(), 0)?;
+
+        Ok(Self { file })
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    fn read_u64(&self, offset: usize) -> Result<u64> {
+        let mut buffer = [0u8; 8];
+        self.file.read_exact_at(&mut buffer, offset as _)?;
+        Ok(u64::from_ne_bytes(buffer))
+    }
+
+    /// Take all pending data out of the buffer, along with the number of dropped records.
+    pub fn drain(&self) -> Result<(Vec<u8>, u32)> {
+        let read_pos = self.read_u64(mem::offset_of!(LogBufferHeader, read_pos))?;
+        let write_pos = self.read_u64(mem::offset_of!(LogBufferHeader, write_pos))?;
+
+        let mut dropped = [0u8; 4];
+        self.file
+            .read_exact_at(&mut dropped, mem::offset_of!(LogBufferHeader, dropped) as _)?;
+
+        let pending = match write_pos.checked_sub(read_pos) {
+            Some(pending) if pending as usize <= LOG_BUFFER_DATA_SIZE => pending as usize,
+            _ => bail!("corrupted log buffer: {read_pos} -> {write_pos}"),
+        };
+
+        let mut data = vec![0u8; pending];
+        let offset = read_pos as usize % LOG_BUFFER_DATA_SIZE;
+        let first = pending.min(LOG_BUFFER_DATA_SIZE - offset);
+        let data_base = size_of::<LogBufferHeader>();
+
+        self.file
+            .read_exact_at(&mut data[..first], (data_base + offset) as _)?;
+        self.file
+            .read_exact_at(&mut data[first..], data_base as _)?;
+
+        self.file.write_all_at(
+            &write_pos.to_ne_bytes(),
+            mem::offset_of!(LogBufferHeader, read_pos) as _,
+        )?;
+
+        Ok((data, u32::from_ne_bytes(dropped)))
+    }
+}
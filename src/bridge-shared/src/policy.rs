@@ -1,3 +1,4 @@
 pub mod debugger;
 pub mod liteloader;
+pub mod native;
 pub mod zygisk;
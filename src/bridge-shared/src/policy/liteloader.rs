@@ -1,9 +1,78 @@
+use jni::sys::{JNIEnv, jclass};
+use std::ffi::{c_char, c_int, c_void};
 use wincode::{SchemaRead, SchemaWrite};
 
+/// Entry class of `.dex` libraries without a manifest
+pub const DEFAULT_JAVA_ENTRY: &str = "xyz.mufanc.zynx.Main";
+
+/// Symbol of an `extern "C" fn(*const NativeContext)`, called right after loading `.so`
+/// libraries exporting it, before their manifest entry
+pub const NATIVE_ENTRY: &str = "zynx_native_entry";
+
+/// Bumped whenever fields are appended to [`NativeContext`] or [`ArtApi`]
+pub const NATIVE_CONTEXT_VERSION: u32 = 3;
+
+/// Called on each entry of a hooked method, `zynx_art_entry_callback` in `include/zynx.h`.
+/// Runs in managed code on the calling thread: it must not call JNI nor block.
+pub type ArtEntryCallback = extern "C" fn(method: *mut c_void, user_data: *mut c_void);
+
+/// Minimal ART hooks for tracing, `zynx_art_api` in `include/zynx.h`. Valid for the lifetime
+/// of the process.
+#[repr(C)]
+pub struct ArtApi {
+    /// `ArtMethod*` of a method of `class`, null if not found
+    pub find_method: extern "C" fn(
+        env: JNIEnv,
+        class: jclass,
+        name: *const c_char,
+        signature: *const c_char,
+        is_static: bool,
+    ) -> *mut c_void,
+    /// Call `callback` on entry of `method` before running it, returns 0 on success. `method`
+    /// must come from `find_method`
+    pub hook_entry: extern "C" fn(
+        method: *mut c_void,
+        callback: ArtEntryCallback,
+        user_data: *mut c_void,
+    ) -> c_int,
+    /// Since version 3, restore the entry of a hooked `method`, returns 0 on success
+    pub unhook_entry: extern "C" fn(method: *mut c_void) -> c_int,
+}
+
+/// Argument of [`NATIVE_ENTRY`], `zynx_context` in `include/zynx.h`. Pointers may be null, and
+/// are only valid during the call.
+#[repr(C)]
+#[derive(Debug)]
+pub struct NativeContext {
+    pub version: u32,
+    pub uid: i32,
+    /// `ro.build.version.sdk`
+    pub api_level: i32,
+    pub env: JNIEnv,
+    pub nice_name: *const c_char,
+    pub app_data_dir: *const c_char,
+    pub package_name: *const c_char,
+    /// `data` of the library manifest
+    pub data: *const u8,
+    pub data_len: usize,
+    /// Since version 2, null if hooks aren't supported on this release
+    pub art: *const ArtApi,
+}
+
 #[derive(Debug, Clone, SchemaRead, SchemaWrite)]
 pub struct LiteLoaderParams {
     pub lib_name: String,
     pub kind: LibraryKind,
+    /// Symbol of an `extern "C" fn(JNIEnv)` for `.so`, or class with `init(Map)` or
+    /// `main(String[])` for `.dex`
+    pub entry: Option<String>,
+    pub phase: LoadPhase,
+    /// Package the process runs, handed to the entries of `.dex` and `.so` libraries
+    pub package_name: Option<String>,
+    /// `data` of the library manifest, handed to the entries of `.dex` and `.so` libraries
+    pub data: Option<Vec<u8>>,
+    /// `dlclose` the `.so` once its entries return
+    pub unload: bool,
 }
 
 #[derive(Debug, Clone, SchemaRead, SchemaWrite)]
@@ -11,3 +80,10 @@ pub enum LibraryKind {
     Native,
     Java,
 }
+
+/// When the library gets loaded, relative to `SpecializeCommon`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SchemaRead, SchemaWrite)]
+pub enum LoadPhase {
+    Pre,
+    Post,
+}
@@ -0,0 +1,8 @@
+use wincode::{SchemaRead, SchemaWrite};
+
+#[derive(Debug, Clone, SchemaRead, SchemaWrite)]
+pub struct NativeParams {
+    pub lib_name: String,
+    /// Symbol of an `extern "C" fn()` called after dlopen
+    pub entry: Option<String>,
+}
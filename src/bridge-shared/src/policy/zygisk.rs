@@ -3,4 +3,15 @@ use wincode::{SchemaRead, SchemaWrite};
 #[derive(Debug, Clone, SchemaRead, SchemaWrite)]
 pub struct ZygiskParams {
     pub module_name: String,
+    pub kind: ZygiskAttachmentKind,
+}
+
+/// What the fd of a zygisk attachment refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SchemaRead, SchemaWrite)]
+pub enum ZygiskAttachmentKind {
+    Library,
+    /// Root directory of the module, handed out by `getModuleDir`
+    ModuleDir,
+    /// Socket to the module's companion hosted by the daemon, used by `connectCompanion`
+    Companion,
 }
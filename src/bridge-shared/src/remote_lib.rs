@@ -1,14 +1,19 @@
+use crate::checked_jni;
 use anyhow::{Context, Error, Result, anyhow, bail};
-use jni::objects::{JClass, JObject, JString, JValue};
+use jni::objects::{JClass, JObject, JValue};
 use jni::refs::Global;
-use jni::{EnvOutcome, EnvUnowned, Outcome, jni_sig, jni_str};
+use jni::{Env, jni_sig, jni_str};
 use log::{info, warn};
 use nix::libc;
-use nix::libc::{MAP_FAILED, MAP_PRIVATE, PROT_READ, RTLD_NOW, c_int, off64_t, size_t};
+use nix::libc::{
+    MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE, MREMAP_FIXED, MREMAP_MAYMOVE, PROT_EXEC, PROT_NONE,
+    PROT_READ, PROT_WRITE, RTLD_NOW, c_int, off64_t, size_t,
+};
 use std::ffi::{CStr, CString, c_void};
 use std::fs::File;
 use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
-use std::ptr;
+use std::os::unix::fs::MetadataExt;
+use std::{fs, ptr};
 
 mod system {
     use crate::remote_lib::DlextInfo;
@@ -60,9 +65,137 @@ impl FromRawFd for DlextInfo {
     }
 }
 
+/// Name of the memfd `fd`, reused as the soname so that the library looks the same in both
+/// the maps and the linker.
+fn memfd_name(fd: RawFd) -> Option<CString> {
+    let link = fs::read_link(format!("/proc/self/fd/{fd}")).ok()?;
+    let name = link.to_str()?.strip_prefix("/memfd:")?;
+    let name = name.strip_suffix(" (deleted)").unwrap_or(name);
+
+    CString::new(name).ok()
+}
+
+/// Reclaims the pages of a range right away, since Linux 5.4
+const MADV_PAGEOUT: c_int = 21;
+
+/// A mapping of `/proc/self/maps`.
+struct Mapping {
+    start: usize,
+    end: usize,
+    prot: c_int,
+}
+
+impl Mapping {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let (start, end) = fields.next()?.split_once('-')?;
+        let perms = fields.next()?.as_bytes();
+
+        let prot = [(b'r', PROT_READ), (b'w', PROT_WRITE), (b'x', PROT_EXEC)]
+            .iter()
+            .zip(perms)
+            .filter(|((flag, _), perm)| flag == *perm)
+            .fold(PROT_NONE, |prot, ((_, bit), _)| prot | bit);
+
+        Some(Self {
+            start: usize::from_str_radix(start, 16).ok()?,
+            end: usize::from_str_radix(end, 16).ok()?,
+            prot,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Replace the mapping with an anonymous copy, so that it's no longer named after the
+    /// memfd. The swap is atomic, code running from it on other threads isn't disturbed. Apps
+    /// may not be allowed to map anonymous code, which then stays mapped from the memfd.
+    fn scrub(&self) -> Result<()> {
+        if self.prot != PROT_NONE && self.prot & PROT_READ == 0 {
+            bail!("{:#x} can't be copied, it isn't readable", self.start);
+        }
+
+        let copy = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                self.len(),
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+
+        if copy == MAP_FAILED {
+            bail!("failed to map a copy of {:#x}", self.start);
+        }
+
+        let swap = || -> Result<()> {
+            unsafe {
+                if self.prot != PROT_NONE {
+                    ptr::copy_nonoverlapping(self.start as *const u8, copy as _, self.len());
+                }
+
+                if libc::mprotect(copy, self.len(), self.prot) != 0 {
+                    bail!(
+                        "failed to protect the copy of {:#x}: {}",
+                        self.start,
+                        std::io::Error::last_os_error()
+                    );
+                }
+
+                let moved = libc::mremap(
+                    copy,
+                    self.len(),
+                    self.len(),
+                    MREMAP_MAYMOVE | MREMAP_FIXED,
+                    self.start,
+                );
+
+                if moved == MAP_FAILED {
+                    bail!("failed to move the copy over {:#x}", self.start);
+                }
+            }
+
+            Ok(())
+        };
+
+        swap().inspect_err(|_| unsafe {
+            libc::munmap(copy, self.len());
+        })?;
+
+        // best effort, the copy is unlikely to run again, don't keep it resident
+        unsafe {
+            libc::madvise(self.start as _, self.len(), MADV_PAGEOUT);
+        }
+
+        Ok(())
+    }
+}
+
+/// Mappings of the file with `inode` in the current process.
+fn mappings_of(inode: u64) -> Result<Vec<Mapping>> {
+    let maps = fs::read_to_string("/proc/self/maps")?;
+
+    Ok(maps
+        .lines()
+        .filter(|line| {
+            // address perms offset dev inode path
+            line.split_whitespace()
+                .nth(4)
+                .and_then(|it| it.parse::<u64>().ok())
+                == Some(inode)
+        })
+        .filter_map(Mapping::parse)
+        .collect())
+}
+
 pub struct NativeLibrary {
     name: String,
     fd: Option<OwnedFd>,
+    /// Of the library file, identifying its mappings
+    inode: Option<u64>,
     handle: Option<*const c_void>,
     auto_close: bool,
 }
@@ -72,6 +205,7 @@ impl NativeLibrary {
         Self {
             name,
             fd: Some(fd),
+            inode: None,
             handle: None,
             auto_close: false,
         }
@@ -82,8 +216,12 @@ impl NativeLibrary {
 
         info!("dlopen library: {}, fd = {}", self.name, fd.as_raw_fd());
 
-        let info = unsafe { DlextInfo::from_raw_fd(fd.as_raw_fd()) };
-        let handle = unsafe { system::android_dlopen_ext(c"jit-cache".as_ptr(), RTLD_NOW, &info) };
+        let file = File::from(fd);
+        self.inode = file.metadata().ok().map(|meta| meta.ino());
+
+        let soname = memfd_name(file.as_raw_fd()).unwrap_or_else(|| c"jit-cache".into());
+        let info = unsafe { DlextInfo::from_raw_fd(file.as_raw_fd()) };
+        let handle = unsafe { system::android_dlopen_ext(soname.as_ptr(), RTLD_NOW, &info) };
 
         if handle.is_null() {
             return Err(anyhow!(
@@ -133,6 +271,44 @@ impl NativeLibrary {
     pub fn auto_close_on_drop(&mut self) {
         self.auto_close = true
     }
+
+    /// `dlclose` the library, and make sure the linker really unmapped it. Libraries pinned
+    /// by `DF_1_NODELETE`, or by other libraries, stay mapped: their code may still run, so
+    /// their mappings are only scrubbed of the name of the memfd, and paged out.
+    pub fn unload(self) -> Result<()> {
+        let inode = self.inode.context("library not opened")?;
+        let name = self.name.clone();
+
+        self.dlclose();
+
+        let left = mappings_of(inode)?;
+
+        if left.is_empty() {
+            info!("unloaded library: {name}");
+            return Ok(());
+        }
+
+        let errors: Vec<_> = left
+            .iter()
+            .filter_map(|mapping| mapping.scrub().err())
+            .collect();
+
+        if !errors.is_empty() {
+            bail!(
+                "library {name} is still mapped after dlclose, {} of {} mappings kept its \
+                 name: {errors:?}",
+                errors.len(),
+                left.len()
+            );
+        }
+
+        warn!(
+            "library {name} is still mapped after dlclose, its {} mappings were made anonymous",
+            left.len()
+        );
+
+        Ok(())
+    }
 }
 
 impl Drop for NativeLibrary {
@@ -147,6 +323,63 @@ impl Drop for NativeLibrary {
     }
 }
 
+/// Keys of the map passed to `init(Map)`, mirrored by `xyz.mufanc.zynx.api.ZynxContext`
+const KEY_PACKAGE_NAME: &str = "package_name";
+const KEY_PROCESS_NAME: &str = "process_name";
+const KEY_DATA_DIR: &str = "data_dir";
+const KEY_DATA: &str = "data";
+
+/// What a java library learns about the process it's loaded into, through `init(Map)`.
+#[derive(Debug, Default, Clone)]
+pub struct JavaContext {
+    pub package_name: Option<String>,
+    pub process_name: Option<String>,
+    pub data_dir: Option<String>,
+    /// Provided by the policy provider, e.g. from the library manifest
+    pub data: Option<Vec<u8>>,
+}
+
+/// Read-only mapping of a whole file, unmapped on drop.
+struct FileMapping {
+    addr: *mut c_void,
+    len: usize,
+}
+
+impl FileMapping {
+    fn new(file: &File) -> Result<Self> {
+        let len = file.metadata()?.len() as usize;
+
+        if len == 0 {
+            bail!("empty file");
+        }
+
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ,
+                MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+
+        if addr == MAP_FAILED {
+            bail!("failed to mmap file");
+        }
+
+        Ok(Self { addr, len })
+    }
+}
+
+impl Drop for FileMapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.addr, self.len);
+        }
+    }
+}
+
 pub struct JavaLibrary {
     name: String,
     fd: Option<OwnedFd>,
@@ -154,6 +387,19 @@ pub struct JavaLibrary {
 }
 
 impl JavaLibrary {
+    fn put(env: &mut Env<'_>, map: &JObject, key: &str, value: &JObject) -> Result<()> {
+        let key = env.new_string(key)?;
+
+        env.call_method(
+            map,
+            jni_str!("put"),
+            jni_sig!("(Ljava/lang/Object;Ljava/lang/Object;)Ljava/lang/Object;"),
+            &[JValue::Object(&key), JValue::Object(value)],
+        )?;
+
+        Ok(())
+    }
+
     pub fn new(name: String, fd: OwnedFd) -> Self {
         Self {
             name,
@@ -162,7 +408,14 @@ impl JavaLibrary {
         }
     }
 
-    pub fn load(&mut self, env: jni::sys::JNIEnv) -> Result<()> {
+    /// Load the dex and call `init(Map)` of `entry_class` with `context`, or `main(String[])`
+    /// if it has no such method.
+    pub fn load(
+        &mut self,
+        env: jni::sys::JNIEnv,
+        entry_class: &str,
+        context: &JavaContext,
+    ) -> Result<()> {
         // Read dex content from fd using mmap to avoid race conditions
         let fd = self.fd.take().context("duplicate called")?;
         let file: File = fd.into();
@@ -173,30 +426,10 @@ impl JavaLibrary {
             file.as_raw_fd()
         );
 
-        let file_size = file.metadata()?.len() as usize;
-        let mut file_data = vec![0; file_size];
-
-        unsafe {
-            let addr = libc::mmap(
-                ptr::null_mut(),
-                file_size,
-                PROT_READ,
-                MAP_PRIVATE,
-                file.as_raw_fd(),
-                0,
-            );
-
-            if addr == MAP_FAILED {
-                bail!("failed to mmap file")
-            }
+        // the sealed memfd is handed to ART as is, which copies it once into its own mapping
+        let mapping = FileMapping::new(&file)?;
 
-            ptr::copy_nonoverlapping(addr as _, file_data.as_mut_ptr(), file_size);
-
-            libc::munmap(addr, file_size);
-        };
-
-        let mut unowned = unsafe { EnvUnowned::from_raw(env as _) };
-        let outcome: EnvOutcome<(), Error> = unowned.with_env_no_catch(|env| {
+        let result = checked_jni::with_env(env, |env| {
             // Create InMemoryDexClassLoader with system classloader as parent
             let class_loader_class = env.find_class(jni_str!("java/lang/ClassLoader"))?;
             let system_class_loader = env.call_static_method(
@@ -209,8 +442,16 @@ impl JavaLibrary {
             let inmem_class_loader_class =
                 env.find_class(jni_str!("dalvik/system/InMemoryDexClassLoader"))?;
 
-            let buffer =
-                unsafe { env.new_direct_byte_buffer(file_data.as_mut_ptr(), file_data.len())? };
+            // read-only, the mapping can't be written to
+            let direct = unsafe { env.new_direct_byte_buffer(mapping.addr as _, mapping.len)? };
+            let buffer = env
+                .call_method(
+                    &direct,
+                    jni_str!("asReadOnlyBuffer"),
+                    jni_sig!("()Ljava/nio/ByteBuffer;"),
+                    &[],
+                )?
+                .l()?;
 
             let class_loader = env.new_object(
                 inmem_class_loader_class,
@@ -220,10 +461,10 @@ impl JavaLibrary {
 
             self.class_loader = Some(env.new_global_ref(&class_loader)?);
             env.delete_local_ref(buffer);
+            env.delete_local_ref(direct);
 
             // Load entry class via ClassLoader.loadClass (env.find_class uses system classloader)
-            // Todo: Make entry class configurable
-            let class_name = env.new_string("xyz.mufanc.zynx.Main")?;
+            let class_name = env.new_string(entry_class)?;
             let main_class = env.call_method(
                 &class_loader,
                 jni_str!("loadClass"),
@@ -232,39 +473,63 @@ impl JavaLibrary {
             )?;
             let main_class = JClass::cast_local(env, main_class.l()?)?;
 
-            // Invoke Main.main(String[]) with empty args
-            let empty_args =
-                env.new_object_array(0, jni_str!("java/lang/String"), JObject::null())?;
-
-            env.call_static_method(
-                main_class,
-                jni_str!("main"),
-                jni_sig!("([Ljava/lang/String;)V"),
-                &[JValue::Object(&empty_args)],
-            )?;
-
-            let exception = env.exception_occurred();
-
-            if let Some(exception) = exception
-                && !exception.is_null()
-            {
-                let message = env.call_method(
-                    exception,
-                    jni_str!("toString"),
-                    jni_sig!("()Ljava/lang/String;"),
-                    &[],
+            let has_init = env
+                .get_static_method_id(
+                    &main_class,
+                    jni_str!("init"),
+                    jni_sig!("(Ljava/util/Map;)V"),
+                )
+                .is_ok();
+
+            if has_init {
+                let map_class = env.find_class(jni_str!("java/util/HashMap"))?;
+                let map = env.new_object(map_class, jni_sig!("()V"), &[])?;
+
+                let strings = [
+                    (KEY_PACKAGE_NAME, &context.package_name),
+                    (KEY_PROCESS_NAME, &context.process_name),
+                    (KEY_DATA_DIR, &context.data_dir),
+                ];
+
+                for (key, value) in strings {
+                    if let Some(value) = value {
+                        let value = env.new_string(value)?;
+                        Self::put(env, &map, key, &value)?;
+                    }
+                }
+
+                if let Some(data) = &context.data {
+                    let data = env.byte_array_from_slice(data)?;
+                    Self::put(env, &map, KEY_DATA, &data)?;
+                }
+
+                env.call_static_method(
+                    main_class,
+                    jni_str!("init"),
+                    jni_sig!("(Ljava/util/Map;)V"),
+                    &[JValue::Object(&map)],
                 )?;
-                let message = JString::cast_local(env, message.l()?)?;
+            } else {
+                // not found, NoSuchMethodError is pending
+                env.exception_clear();
 
-                warn!("failed to call entry: {:?}", message.to_string());
+                // Invoke Main.main(String[]) with empty args
+                let empty_args =
+                    env.new_object_array(0, jni_str!("java/lang/String"), JObject::null())?;
 
-                env.exception_clear();
+                env.call_static_method(
+                    main_class,
+                    jni_str!("main"),
+                    jni_sig!("([Ljava/lang/String;)V"),
+                    &[JValue::Object(&empty_args)],
+                )?;
             }
 
             Ok(())
         });
 
-        if let Outcome::Err(err) = outcome.into_outcome() {
+        // exceptions of the entry included, they don't fail the other libraries
+        if let Err(err) = result {
             warn!("failed to load java library: {err:?}");
         }
 
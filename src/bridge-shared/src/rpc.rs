@@ -0,0 +1,197 @@
+use anyhow::{Result, bail};
+use nix::errno::Errno;
+use nix::libc;
+use nix::libc::{POLLIN, POLLOUT, c_short, pollfd};
+use std::fs::File;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::mem::ManuallyDrop;
+use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd};
+use std::time::{Duration, Instant};
+
+/// Maximum payload size of a single request or reply frame.
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024; // 1MB
+
+/// Default timeout of a single read or write, waiting for the peer to make progress.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Length-prefixed request/reply channel on top of a companion socket.
+///
+/// Request layout: `[u32 payload_length] [u32 reply_capacity] [payload]`, reply layout:
+/// `[u32 payload_length] [payload]`, all little-endian. Buffers are sized from the lengths
+/// read, and the reply capacity lets the companion size its reply buffer. Every read and
+/// write is bounded by the timeout, so a peer that stops responding results in an error
+/// instead of blocking the caller forever.
+pub struct RpcChannel<'fd> {
+    fd: BorrowedFd<'fd>,
+    timeout: Duration,
+}
+
+impl<'fd> RpcChannel<'fd> {
+    pub fn new(fd: BorrowedFd<'fd>) -> Self {
+        Self {
+            fd,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Module side: send `request` and wait for a reply of at most `reply_cap` bytes.
+    pub fn call(&self, request: &[u8], reply_cap: usize) -> Result<Vec<u8>> {
+        let reply_cap = reply_cap.min(MAX_FRAME_SIZE);
+
+        check_size(request.len())?;
+
+        self.write_all(&(request.len() as u32).to_le_bytes())?;
+        self.write_all(&(reply_cap as u32).to_le_bytes())?;
+        self.write_all(request)?;
+
+        let len = self.read_len(Some(self.timeout))?;
+        if len > reply_cap {
+            bail!("reply too large: {len} bytes (capacity {reply_cap})");
+        }
+
+        self.read_data(len)
+    }
+
+    /// Companion side: wait for one request, pass it to `handler` along with the capacity
+    /// of the reply, and send back its reply. Waiting for the request is not bounded by the
+    /// timeout, the module may send it any time.
+    ///
+    /// Returns `Ok(false)` if the peer closed the connection before sending a request.
+    pub fn serve_one<F>(&self, handler: F) -> Result<bool>
+    where
+        F: FnOnce(&[u8], usize) -> Vec<u8>,
+    {
+        let len = match self.read_len(None) {
+            Ok(len) => len,
+            Err(err) if is_eof(&err) => return Ok(false),
+            Err(err) => return Err(err),
+        };
+
+        let reply_cap = self.read_len(Some(self.timeout))?;
+        let request = self.read_data(len)?;
+
+        let reply = handler(&request, reply_cap);
+
+        if reply.len() > reply_cap {
+            bail!(
+                "reply too large: {} bytes (capacity {reply_cap})",
+                reply.len()
+            );
+        }
+
+        self.write_all(&(reply.len() as u32).to_le_bytes())?;
+        self.write_all(&reply)?;
+
+        Ok(true)
+    }
+
+    fn read_len(&self, timeout: Option<Duration>) -> Result<usize> {
+        let mut len_buf = [0u8; 4];
+
+        self.read_exact(&mut len_buf, timeout)?;
+
+        let len = u32::from_le_bytes(len_buf) as usize;
+        check_size(len)?;
+
+        Ok(len)
+    }
+
+    fn read_data(&self, len: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; len];
+
+        self.read_exact(&mut data, Some(self.timeout))?;
+
+        Ok(data)
+    }
+
+    fn file(&self) -> ManuallyDrop<File> {
+        // the fd is borrowed, never let `File` close it
+        ManuallyDrop::new(unsafe { File::from_raw_fd(self.fd.as_raw_fd()) })
+    }
+
+    fn write_all(&self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            self.wait_for(POLLOUT, Some(self.timeout))?;
+
+            match self.file().write(data) {
+                Ok(0) => bail!("connection closed by peer"),
+                Ok(n) => data = &data[n..],
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read `buffer` full, the timeout applying to each read. The first read is waited for
+    /// without a timeout if `timeout` is none.
+    fn read_exact(&self, mut buffer: &mut [u8], mut timeout: Option<Duration>) -> Result<()> {
+        while !buffer.is_empty() {
+            self.wait_for(POLLIN, timeout)?;
+
+            match self.file().read(buffer) {
+                Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(n) => buffer = &mut buffer[n..],
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+
+            timeout = Some(self.timeout);
+        }
+
+        Ok(())
+    }
+
+    fn wait_for(&self, events: c_short, timeout: Option<Duration>) -> Result<()> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            let timeout_ms = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        bail!("rpc timeout after {:?}", self.timeout);
+                    }
+
+                    remaining.as_millis().clamp(1, i32::MAX as _) as _
+                }
+                None => -1,
+            };
+
+            let mut pfd = pollfd {
+                fd: self.fd.as_raw_fd(),
+                events,
+                revents: 0,
+            };
+
+            let res = unsafe { libc::poll(&mut pfd, 1, timeout_ms) };
+
+            match Errno::result(res) {
+                Ok(0) => continue,
+                Ok(_) => return Ok(()),
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+fn check_size(len: usize) -> Result<()> {
+    if len > MAX_FRAME_SIZE {
+        bail!("frame too large: {len} bytes (max {MAX_FRAME_SIZE})");
+    }
+
+    Ok(())
+}
+
+fn is_eof(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|err| err.kind() == ErrorKind::UnexpectedEof)
+}
@@ -1,30 +1,215 @@
-use std::mem::size_of;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::mem::{ManuallyDrop, size_of};
 use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{Result, bail};
 use jni::sys::{JNIEnv, jint, jintArray, jlong, jobjectArray, jstring};
 use log::debug;
 use nix::libc::{c_int, c_long};
-use strum_macros::{AsRefStr, EnumIter};
+use strum::{EnumCount, IntoEnumIterator};
+use strum_macros::{EnumCount, EnumIter};
 use uds::UnixSeqpacketConn;
 use wincode::{SchemaRead, SchemaWrite};
 
-#[derive(Debug, Copy, Clone, PartialOrd, PartialEq, AsRefStr, EnumIter)]
+pub mod arrays;
+
+/// Params of SpecializeCommon, in the order they appear in its signature across releases.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, EnumIter, EnumCount)]
 #[repr(u8)]
-pub enum SpecializeVersion {
-    #[strum(
-        serialize = "_ZN12_GLOBAL__N_116SpecializeCommonEP7_JNIEnvjjP10_jintArrayiP13_jobjectArraylliP8_jstringS7_bbS7_S7_bS5_S5_bb"
-    )]
-    R = 30,
-    #[strum(
-        serialize = "_ZN12_GLOBAL__N_116SpecializeCommonEP7_JNIEnvjjP10_jintArrayiP13_jobjectArrayllliP8_jstringS7_bbS7_S7_bS5_S5_bbb"
-    )]
-    V = 35,
+pub enum SpecializeField {
+    Env,
+    Uid,
+    Gid,
+    Gids,
+    RuntimeFlags,
+    Rlimits,
+    PermittedCapabilities,
+    EffectiveCapabilities,
+    BoundingCapabilities,
+    MountExternal,
+    ManagedSeInfo,
+    ManagedNiceName,
+    IsSystemServer,
+    IsChildZygote,
+    ManagedInstructionSet,
+    ManagedAppDataDir,
+    IsTopApp,
+    PkgDataInfoList,
+    AllowlistedDataInfoList,
+    MountDataDirs,
+    MountStorageDirs,
+    MountSyspropOverrides,
+}
+
+impl SpecializeField {
+    /// Demangled type of the param, without spaces
+    fn param_type(self) -> &'static str {
+        match self {
+            Self::Env => "_JNIEnv*",
+            Self::Uid | Self::Gid => "unsignedint",
+            Self::Gids => "_jintArray*",
+            Self::RuntimeFlags | Self::MountExternal => "int",
+            Self::Rlimits | Self::PkgDataInfoList | Self::AllowlistedDataInfoList => {
+                "_jobjectArray*"
+            }
+            Self::PermittedCapabilities
+            | Self::EffectiveCapabilities
+            | Self::BoundingCapabilities => "long",
+            Self::ManagedSeInfo
+            | Self::ManagedNiceName
+            | Self::ManagedInstructionSet
+            | Self::ManagedAppDataDir => "_jstring*",
+            Self::IsSystemServer
+            | Self::IsChildZygote
+            | Self::IsTopApp
+            | Self::MountDataDirs
+            | Self::MountStorageDirs
+            | Self::MountSyspropOverrides => "bool",
+        }
+    }
+
+    /// Params the policy checks rely on, or common to all known releases. A signature
+    /// without them isn't understood.
+    fn is_required(self) -> bool {
+        matches!(
+            self,
+            Self::Env
+                | Self::Uid
+                | Self::Gid
+                | Self::Gids
+                | Self::RuntimeFlags
+                | Self::Rlimits
+                | Self::PermittedCapabilities
+                | Self::EffectiveCapabilities
+                | Self::ManagedNiceName
+                | Self::IsSystemServer
+                | Self::IsChildZygote
+                | Self::ManagedAppDataDir
+        )
+    }
+}
+
+/// Index of each [`SpecializeField`] in the args of SpecializeCommon, inferred by core from
+/// its signature and passed to the bridge.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SpecializeLayout {
+    indices: [u8; SpecializeField::COUNT],
+    /// The signature isn't understood, only args common to all known releases are read,
+    /// and none are rewritten
+    forward_compat: bool,
+}
+
+impl SpecializeLayout {
+    const ABSENT: u8 = u8::MAX;
+
+    /// Match the demangled `param_types` against the fields in order. Optional fields may be
+    /// absent, but params are only ever appended to a run of fields of the same type, so an
+    /// optional field is absent if any before it in its run is. Fails unless exactly one
+    /// assignment of params to fields exists, as the layout can't be trusted otherwise.
+    pub fn infer<S: AsRef<str>>(param_types: &[S]) -> Result<Self> {
+        let params: Vec<_> = param_types
+            .iter()
+            .map(|ty| ty.as_ref().replace(' ', ""))
+            .collect();
+        let fields: Vec<_> = SpecializeField::iter().collect();
+        let same_run =
+            |i: usize| i + 1 < fields.len() && fields[i].param_type() == fields[i + 1].param_type();
+
+        // ways[j][i][skipped]: assignments of params[j..] to fields[i..], capped at 2, where
+        // `skipped` tells an optional field before `i` in its run is absent
+        let mut ways = vec![[[0u8; 2]; SpecializeField::COUNT + 1]; params.len() + 1];
+        ways[params.len()][fields.len()] = [1, 1];
+
+        for i in (0..fields.len()).rev() {
+            let next = |skipped: bool| (same_run(i) && skipped) as usize;
+
+            for j in (0..=params.len()).rev() {
+                for skipped in [false, true] {
+                    let field = fields[i];
+                    let mut count = 0;
+
+                    if j < params.len()
+                        && params[j] == field.param_type()
+                        && (field.is_required() || !skipped)
+                    {
+                        count += ways[j + 1][i + 1][next(skipped)];
+                    }
+
+                    if !field.is_required() {
+                        count += ways[j][i + 1][next(true)];
+                    }
+
+                    ways[j][i][skipped as usize] = count.min(2);
+                }
+            }
+        }
+
+        match ways[0][0][0] {
+            0 => bail!("params don't match SpecializeCommon of any known release: {params:?}"),
+            1 => {}
+            _ => bail!("params match SpecializeCommon ambiguously: {params:?}"),
+        }
+
+        let mut indices = [Self::ABSENT; SpecializeField::COUNT];
+        let (mut j, mut skipped) = (0, false);
+
+        for (i, field) in fields.iter().enumerate() {
+            let next = |skipped: bool| same_run(i) && skipped;
+
+            if j < params.len()
+                && params[j] == field.param_type()
+                && (field.is_required() || !skipped)
+                && ways[j + 1][i + 1][next(skipped) as usize] > 0
+            {
+                indices[*field as usize] = j as _;
+                j += 1;
+                skipped = next(skipped);
+            } else {
+                skipped = next(true);
+            }
+        }
+
+        Ok(Self {
+            indices,
+            forward_compat: false,
+        })
+    }
+
+    /// Layout of the args common to all known releases.
+    pub fn forward_compat() -> Self {
+        let mut indices = [Self::ABSENT; SpecializeField::COUNT];
+
+        for (index, field) in SpecializeField::iter()
+            .take(SpecializeArgs::STABLE_ARGS_COUNT)
+            .enumerate()
+        {
+            indices[field as usize] = index as _;
+        }
+
+        Self {
+            indices,
+            forward_compat: true,
+        }
+    }
+
+    pub fn is_forward_compat(&self) -> bool {
+        self.forward_compat
+    }
+
+    pub fn index_of(&self, field: SpecializeField) -> Option<usize> {
+        let index = self.indices[field as usize];
+        (index != Self::ABSENT).then_some(index as _)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct SpecializeArgs {
-    pub version: SpecializeVersion,
+    pub layout: SpecializeLayout,
     pub env: JNIEnv,
     pub uid: jint,
     pub gid: jint,
@@ -50,112 +235,221 @@ pub struct SpecializeArgs {
 }
 
 impl SpecializeArgs {
-    #[allow(unused_mut)]
-    #[allow(unused_variables)]
-    pub fn new<T: AsRef<[c_long]>>(args: T, version: SpecializeVersion) -> Self {
-        let args = args.as_ref().as_ptr();
-        let mut index = 0;
-
-        macro_rules! iota {
-            () => {
-                unsafe {
-                    index += 1;
-                    *(args.add(index - 1) as *const _)
+    /// Args at the start of all known releases, the only ones read in forward-compat mode
+    pub const STABLE_ARGS_COUNT: usize = 8;
+
+    pub fn new<T: AsRef<[c_long]>>(args: T, layout: SpecializeLayout) -> Self {
+        let args = args.as_ref();
+
+        macro_rules! get {
+            ($field: ident) => {
+                match layout.index_of(SpecializeField::$field) {
+                    Some(index) if index < args.len() => unsafe {
+                        *(args.as_ptr().add(index) as *const _)
+                    },
+                    _ => unsafe { std::mem::zeroed() },
                 }
             };
         }
 
-        macro_rules! require {
-            ($version: ident) => {
-                if version >= crate::zygote::SpecializeVersion::$version {
-                    iota!()
-                } else {
-                    unsafe { std::mem::zeroed() }
-                }
-            };
+        Self {
+            layout,
+            env: get!(Env),
+            uid: get!(Uid),
+            gid: get!(Gid),
+            gids: get!(Gids),
+            runtime_flags: get!(RuntimeFlags),
+            rlimits: get!(Rlimits),
+            permitted_capabilities: get!(PermittedCapabilities),
+            effective_capabilities: get!(EffectiveCapabilities),
+            bounding_capabilities: get!(BoundingCapabilities),
+            mount_external: get!(MountExternal),
+            managed_se_info: get!(ManagedSeInfo),
+            managed_nice_name: get!(ManagedNiceName),
+            is_system_server: get!(IsSystemServer),
+            is_child_zygote: get!(IsChildZygote),
+            managed_instruction_set: get!(ManagedInstructionSet),
+            managed_app_data_dir: get!(ManagedAppDataDir),
+            is_top_app: get!(IsTopApp),
+            pkg_data_info_list: get!(PkgDataInfoList),
+            allowlisted_data_info_list: get!(AllowlistedDataInfoList),
+            mount_data_dirs: get!(MountDataDirs),
+            mount_storage_dirs: get!(MountStorageDirs),
+            mount_sysprop_overrides: get!(MountSyspropOverrides),
         }
+    }
+
+    pub fn is_forward_compat(&self) -> bool {
+        self.layout.is_forward_compat()
+    }
 
-        Self {
-            version,
-            env: iota!(),
-            uid: iota!(),
-            gid: iota!(),
-            gids: iota!(),
-            runtime_flags: iota!(),
-            rlimits: iota!(),
-            permitted_capabilities: iota!(),
-            effective_capabilities: iota!(),
-            bounding_capabilities: iota!(),
-            mount_external: require!(V),
-            managed_se_info: iota!(),
-            managed_nice_name: iota!(),
-            is_system_server: iota!(),
-            is_child_zygote: iota!(),
-            managed_instruction_set: iota!(),
-            managed_app_data_dir: iota!(),
-            is_top_app: iota!(),
-            pkg_data_info_list: iota!(),
-            allowlisted_data_info_list: iota!(),
-            mount_data_dirs: iota!(),
-            mount_storage_dirs: iota!(),
-            mount_sysprop_overrides: require!(V),
-        }
-    }
-
-    #[allow(unused_mut)]
-    #[allow(unused_variables)]
-    #[allow(unused_assignments)]
     pub fn write_back_to_slice(&self, args: &mut [c_long]) {
-        let mut index = 0;
+        if self.is_forward_compat() {
+            return;
+        }
 
         macro_rules! put {
-            ($member: ident) => {{
-                args[index] = self.$member as _;
-                index += 1;
-            }};
-            ($member: ident, $version: ident) => {
-                if self.version >= crate::zygote::SpecializeVersion::$version {
-                    put!($member)
+            ($member: ident, $field: ident) => {
+                if let Some(index) = self.layout.index_of(SpecializeField::$field)
+                    && index < args.len()
+                {
+                    args[index] = self.$member as _;
                 }
             };
         }
 
-        put!(env);
-        put!(uid);
-        put!(gid);
-        put!(gids);
-        put!(runtime_flags);
-        put!(rlimits);
-        put!(permitted_capabilities);
-        put!(effective_capabilities);
-        put!(bounding_capabilities);
-        put!(mount_external, V);
-        put!(managed_se_info);
-        put!(managed_nice_name);
-        put!(is_system_server);
-        put!(is_child_zygote);
-        put!(managed_instruction_set);
-        put!(managed_app_data_dir);
-        put!(is_top_app);
-        put!(pkg_data_info_list);
-        put!(allowlisted_data_info_list);
-        put!(mount_data_dirs);
-        put!(mount_storage_dirs);
-        put!(mount_sysprop_overrides, V);
-    }
-}
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, SchemaRead, SchemaWrite)]
+        put!(env, Env);
+        put!(uid, Uid);
+        put!(gid, Gid);
+        put!(gids, Gids);
+        put!(runtime_flags, RuntimeFlags);
+        put!(rlimits, Rlimits);
+        put!(permitted_capabilities, PermittedCapabilities);
+        put!(effective_capabilities, EffectiveCapabilities);
+        put!(bounding_capabilities, BoundingCapabilities);
+        put!(mount_external, MountExternal);
+        put!(managed_se_info, ManagedSeInfo);
+        put!(managed_nice_name, ManagedNiceName);
+        put!(is_system_server, IsSystemServer);
+        put!(is_child_zygote, IsChildZygote);
+        put!(managed_instruction_set, ManagedInstructionSet);
+        put!(managed_app_data_dir, ManagedAppDataDir);
+        put!(is_top_app, IsTopApp);
+        put!(pkg_data_info_list, PkgDataInfoList);
+        put!(allowlisted_data_info_list, AllowlistedDataInfoList);
+        put!(mount_data_dirs, MountDataDirs);
+        put!(mount_storage_dirs, MountStorageDirs);
+        put!(mount_sysprop_overrides, MountSyspropOverrides);
+    }
+}
+
+/// Gids granted by [`ArgsMutation::extra_gids`]: those of system features apps get through
+/// normal permissions, never of privileged ones like `shell`, `log` or `net_admin`.
+const EXTRA_GIDS: [(jint, &str); 5] = [
+    (1015, "sdcard_rw"),
+    (1028, "sdcard_r"),
+    (3001, "net_bt_admin"),
+    (3002, "net_bt"),
+    (3003, "inet"),
+];
+/// Most gids granted by [`ArgsMutation::extra_gids`]
+const MAX_EXTRA_GIDS: usize = 16;
+
+/// Changes to the specialize args requested by a policy provider, applied by the bridge
+/// before the pre-specialize handlers run. Limited to fields that don't change who the
+/// process is, nor how the bridge talks to core.
+#[derive(Debug, Default, Clone, PartialEq, Eq, SchemaRead, SchemaWrite)]
+pub struct ArgsMutation {
+    /// Bits set in `runtime_flags`, after those cleared
+    pub runtime_flags_set: jint,
+    pub runtime_flags_clear: jint,
+    pub mount_external: Option<jint>,
+    pub mount_data_dirs: Option<bool>,
+    pub mount_storage_dirs: Option<bool>,
+    /// Supplementary gids appended to `gids`, for sdcard, network or bluetooth access. Only
+    /// gids of `EXTRA_GIDS` are granted, see [`Self::validate`].
+    pub extra_gids: Vec<jint>,
+}
+
+impl ArgsMutation {
+    pub fn runtime_flags(set: jint) -> Self {
+        Self {
+            runtime_flags_set: set,
+            ..Self::default()
+        }
+    }
+
+    pub fn extra_gids(gids: Vec<jint>) -> Self {
+        Self {
+            extra_gids: gids,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Refuse mutations granting gids outside of `EXTRA_GIDS`, or too many of them.
+    pub fn validate(&self) -> Result<()> {
+        if self.extra_gids.len() > MAX_EXTRA_GIDS {
+            bail!(
+                "{} extra gids, at most {MAX_EXTRA_GIDS} are granted",
+                self.extra_gids.len()
+            );
+        }
+
+        if let Some(gid) = self
+            .extra_gids
+            .iter()
+            .find(|gid| !EXTRA_GIDS.iter().any(|(allowed, _)| allowed == *gid))
+        {
+            let allowed: Vec<_> = EXTRA_GIDS.iter().map(|(_, name)| *name).collect();
+            bail!("extra gid {gid} isn't one of {}", allowed.join(", "));
+        }
+
+        Ok(())
+    }
+
+    /// Apply to `args`, reaching `SpecializeCommon` through [`SpecializeArgs::write_back_to_slice`].
+    /// Nothing is changed if the mutation isn't valid.
+    pub fn apply(&self, args: &mut SpecializeArgs) -> Result<()> {
+        self.validate()?;
+
+        args.runtime_flags =
+            (args.runtime_flags & !self.runtime_flags_clear) | self.runtime_flags_set;
+
+        if let Some(mount_external) = self.mount_external {
+            args.mount_external = mount_external;
+        }
+
+        if let Some(mount_data_dirs) = self.mount_data_dirs {
+            args.mount_data_dirs = mount_data_dirs;
+        }
+
+        if let Some(mount_storage_dirs) = self.mount_storage_dirs {
+            args.mount_storage_dirs = mount_storage_dirs;
+        }
+
+        if !self.extra_gids.is_empty() {
+            let mut gids = args.read_gids()?.unwrap_or_default();
+            let missing: Vec<_> = self
+                .extra_gids
+                .iter()
+                .filter(|gid| !gids.contains(gid))
+                .copied()
+                .collect();
+
+            if !missing.is_empty() {
+                gids.extend(missing);
+                args.set_gids(&gids)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Part of the IPC wire format, variants must never be gated by feature flags: core and
+/// the bridge may be built separately, and any layout mismatch breaks the IPC.
+/// Ordered by declaration, which is also the order the bridge applies [`ArgsMutation`]s in.
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, EnumCount, SchemaRead, SchemaWrite,
+)]
 pub enum ProviderType {
     Debugger,
     LiteLoader,
     Zygisk,
+    /// Only sent to native processes, never to apps
+    Native,
 }
 
 #[derive(Debug, Clone, SchemaRead, SchemaWrite)]
 pub struct AttachmentWire {
     pub has_fd: bool,
     pub data: Option<Vec<u8>>,
+    /// Module the attachment loads, as `<provider>:<name>`, named in crash reports
+    pub module: Option<String>,
 }
 
 #[derive(Debug, SchemaRead, SchemaWrite)]
@@ -163,11 +457,67 @@ pub struct ProviderBundleWire {
     pub ty: ProviderType,
     pub attachments: Vec<AttachmentWire>,
     pub data: Option<Vec<u8>>,
+    pub mutation: Option<ArgsMutation>,
+}
+
+/// Marks the header of an [`IpcPayload`], `"ZYNX"`
+const IPC_MAGIC: usize = 0x5a594e58;
+
+/// Bumped on any change to the wire format of [`IpcPayload`] and [`BridgeReport`]. A bridge
+/// inherited from an app zygote injected before a daemon update may speak an older one.
+pub const IPC_VERSION: usize = 4;
+
+/// `[magic, version, payload length, fds count]`, sent ahead of the payload
+type IpcHeader = [usize; 4];
+
+/// Payloads are streamed in frames of at most this size, well below the default socket
+/// buffer, a single seqpacket of a multi-MB dex would fail with `EMSGSIZE`
+const IPC_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Progress of large payloads is logged each time this many bytes got through
+const IPC_PROGRESS_STEP: usize = 1024 * 1024;
+
+fn log_progress(action: &str, done: usize, total: usize) {
+    if total > IPC_CHUNK_SIZE && (done % IPC_PROGRESS_STEP < IPC_CHUNK_SIZE || done == total) {
+        debug!("{action} IPC payload: {done}/{total} bytes");
+    }
+}
+
+/// Wire index of each [`ProviderType`] as of IPC version 4. Adding or renaming a variant
+/// breaks the match, and reordering the drift guard below: bump [`IPC_VERSION`] then list the
+/// variants again.
+const fn wire_index(ty: ProviderType) -> usize {
+    match ty {
+        ProviderType::Debugger => 0,
+        ProviderType::LiteLoader => 1,
+        ProviderType::Zygisk => 2,
+        ProviderType::Native => 3,
+    }
 }
 
+// drift guard of the variants listed above
+const _: () = {
+    assert!(
+        IPC_VERSION == 4,
+        "IPC_VERSION bumped, list the variants again"
+    );
+    assert!(ProviderType::COUNT == 4);
+    assert!(wire_index(ProviderType::Debugger) == ProviderType::Debugger as usize);
+    assert!(wire_index(ProviderType::LiteLoader) == ProviderType::LiteLoader as usize);
+    assert!(wire_index(ProviderType::Zygisk) == ProviderType::Zygisk as usize);
+    assert!(
+        wire_index(ProviderType::Native) == ProviderType::Native as usize,
+        "wire format changed, bump IPC_VERSION"
+    );
+};
+
 #[derive(Debug, SchemaRead, SchemaWrite)]
 pub struct IpcPayload {
     pub providers: Vec<ProviderBundleWire>,
+    /// If set, the first fd is a bridge log buffer (diagnostics mode)
+    pub has_log_fd: bool,
+    /// If set, the bridge audits its process once the handlers ran, see [`AuditReport`]
+    pub audit: Option<AuditRequest>,
 }
 
 impl IpcPayload {
@@ -198,56 +548,374 @@ impl IpcPayload {
 
         let conn = unsafe { UnixSeqpacketConn::from_raw_fd(conn_fd.into_raw_fd()) };
 
-        conn.send(bytemuck::bytes_of(&[data.len(), raw_fds.len()]))?;
-        conn.send_fds(&data, &raw_fds)?;
+        let header: IpcHeader = [IPC_MAGIC, IPC_VERSION, data.len(), raw_fds.len()];
+
+        conn.send(bytemuck::bytes_of(&header))?;
+
+        // fds go along with the first frame, even for an empty payload
+        let mut chunks = data.chunks(IPC_CHUNK_SIZE);
+        let mut sent = conn.send_fds(chunks.next().unwrap_or_default(), &raw_fds)?;
+
+        log_progress("sent", sent, data.len());
+
+        for chunk in chunks {
+            let len = conn.send(chunk)?;
+
+            if len != chunk.len() {
+                bail!("short IPC frame: sent {len} of {} bytes", chunk.len());
+            }
+
+            sent += len;
+            log_progress("sent", sent, data.len());
+        }
 
         Ok(())
     }
 
     pub fn recv_from(conn_fd: OwnedFd) -> Result<(Self, Vec<OwnedFd>)> {
         let conn = unsafe { UnixSeqpacketConn::from_raw_fd(conn_fd.into_raw_fd()) };
-        let mut buffer = [0u8; size_of::<[usize; 2]>()];
+        let mut buffer = [0u8; size_of::<IpcHeader>()];
 
         let received = conn.recv(&mut buffer)?;
-        if received != size_of::<[usize; 2]>() {
+        if received != size_of::<IpcHeader>() {
             bail!(
                 "incomplete IPC header: expected {} bytes, got {received}",
-                size_of::<[usize; 2]>()
+                size_of::<IpcHeader>()
             );
         }
 
-        let pair: &[usize; 2] = bytemuck::from_bytes(&buffer);
-        let (buffer_len, fds_len) = (pair[0], pair[1]);
+        let &[magic, version, buffer_len, fds_len]: &IpcHeader = bytemuck::from_bytes(&buffer);
+
+        if magic != IPC_MAGIC {
+            bail!("bad IPC magic: {magic:#x}");
+        }
+
+        // the fds left in the queue are closed along with the connection
+        if version != IPC_VERSION {
+            bail!(
+                "IPC version mismatch: expected {IPC_VERSION}, got {version}, restart the zygote"
+            );
+        }
 
         let mut buffer: Vec<_> = vec![0; buffer_len];
         let mut raw_fds: Vec<RawFd> = vec![0; fds_len];
 
-        let (bytes_received, truncated, fds_received) = conn.recv_fds(&mut buffer, &mut raw_fds)?;
+        let first_len = buffer_len.min(IPC_CHUNK_SIZE);
+        let (mut received, truncated, fds_received) =
+            conn.recv_fds(&mut buffer[..first_len], &mut raw_fds)?;
+        // owned right away, so that they're closed on errors
+        let fds: Vec<_> = raw_fds[..fds_received]
+            .iter()
+            .map(|&fd| unsafe { OwnedFd::from_raw_fd(fd) })
+            .collect();
 
         if truncated {
             bail!("IPC payload was truncated");
         }
 
-        if bytes_received != buffer_len {
-            bail!("incomplete IPC payload: expected {buffer_len} bytes, got {bytes_received}");
-        }
-
         if fds_received != fds_len {
             bail!("incomplete IPC fds: expected {fds_len} fds, got {fds_received}");
         }
 
+        log_progress("received", received, buffer_len);
+
+        while received < buffer_len {
+            let end = buffer_len.min(received + IPC_CHUNK_SIZE);
+            let len = conn.recv(&mut buffer[received..end])?;
+
+            if len == 0 {
+                bail!("incomplete IPC payload: expected {buffer_len} bytes, got {received}");
+            }
+
+            received += len;
+            log_progress("received", received, buffer_len);
+        }
+
         let payload: IpcPayload = wincode::deserialize(&buffer)?;
-        let fds = raw_fds
-            .into_iter()
-            .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
-            .collect();
 
         Ok((payload, fds))
     }
 }
 
+/// Sent when the handler of a provider panics, the panic having been caught to keep the
+/// process alive.
+#[derive(Debug, SchemaRead, SchemaWrite)]
+pub struct CrashReport {
+    pub provider: ProviderType,
+    /// Module whose code panicked, none if it was the handler's own
+    pub modules: Vec<String>,
+    pub message: String,
+}
+
+/// Result of the handlers of one provider.
+#[derive(Debug, Clone, SchemaRead, SchemaWrite)]
+pub struct ProviderLoad {
+    pub provider: ProviderType,
+    /// Modules of the bundle the handlers were dispatched with
+    pub modules: Vec<String>,
+    /// Errors of the handlers, prefixed by their hook
+    pub errors: Vec<String>,
+    /// Time spent in the handlers, all hooks included
+    pub duration_us: u64,
+}
+
+/// Sent once the handlers of all providers ran, i.e. after post-specialize for apps.
+#[derive(Debug, SchemaRead, SchemaWrite)]
+pub struct LoadReport {
+    pub providers: Vec<ProviderLoad>,
+}
+
+/// What the bridge looks for when auditing its process, with `--cfg-audit`.
+#[derive(Debug, Clone, SchemaRead, SchemaWrite)]
+pub struct AuditRequest {
+    /// Names zynx leaves, matched in maps, fds, threads, sockets and the environment
+    pub names: Vec<String>,
+    /// Inodes of the memfds sent to the process, matched in maps and fds whatever their name
+    pub inodes: Vec<u64>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, SchemaRead, SchemaWrite)]
+pub enum ArtifactKind {
+    Map,
+    Fd,
+    Thread,
+    Socket,
+    Env,
+    Selinux,
+}
+
+/// Something left by zynx that the process itself can see.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, SchemaRead, SchemaWrite)]
+pub struct Artifact {
+    pub kind: ArtifactKind,
+    pub detail: String,
+    pub reason: String,
+}
+
+/// Sent after the [`LoadReport`] if [`IpcPayload::audit`] was set.
+#[derive(Debug, SchemaRead, SchemaWrite)]
+pub struct AuditReport {
+    /// SELinux context of the process
+    pub context: String,
+    pub artifacts: Vec<Artifact>,
+}
+
+/// Sent back by the bridge over the connection of [`IpcPayload`], which stays open until
+/// the process exits.
+#[derive(Debug, SchemaRead, SchemaWrite)]
+pub enum BridgeReport {
+    Crash(CrashReport),
+    Load(LoadReport),
+    Audit(AuditReport),
+}
+
+impl BridgeReport {
+    pub fn send_to(&self, conn_fd: BorrowedFd) -> Result<()> {
+        // borrowed, the connection stays open for further reports
+        let conn =
+            ManuallyDrop::new(unsafe { UnixSeqpacketConn::from_raw_fd(conn_fd.as_raw_fd()) });
+
+        conn.send(&wincode::serialize(self)?)?;
+
+        Ok(())
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        Ok(wincode::deserialize(data)?)
+    }
+}
+
+/// Identifies one injection, shared by core logs, injection events and bridge logs so that
+/// they can be correlated with a single grep. `0` means no session.
+#[repr(transparent)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SessionId(pub u32);
+
+impl SessionId {
+    /// Unique within a daemon run, and unlikely to repeat across restarts.
+    pub fn generate() -> Self {
+        static SEED: OnceLock<u32> = OnceLock::new();
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+
+        let seed = *SEED.get_or_init(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .subsec_nanos()
+        });
+
+        // odd multiplier, a bijection on u32
+        let id = seed.wrapping_add(
+            NEXT.fetch_add(1, Ordering::Relaxed)
+                .wrapping_mul(0x9e3779b9),
+        );
+
+        Self(if id == 0 { 1 } else { id })
+    }
+
+    pub fn is_some(&self) -> bool {
+        self.0 != 0
+    }
+}
+
+impl Display for SessionId {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{:08x}", self.0)
+    }
+}
+
 #[repr(C)]
 pub struct BridgeArgs {
     pub conn_fd: c_int,
-    pub specialize_version: SpecializeVersion,
+    pub specialize_layout: SpecializeLayout,
+    pub session: SessionId,
+}
+
+/// Arguments of `native_start`, the bridge entry for native processes.
+#[repr(C)]
+pub struct NativeBridgeArgs {
+    pub conn_fd: c_int,
+    pub session: SessionId,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Demangled params of SpecializeCommon on R
+    const R_PARAMS: [&str; 20] = [
+        "_JNIEnv *",
+        "unsigned int",
+        "unsigned int",
+        "_jintArray *",
+        "int",
+        "_jobjectArray *",
+        "long",
+        "long",
+        "int",
+        "_jstring *",
+        "_jstring *",
+        "bool",
+        "bool",
+        "_jstring *",
+        "_jstring *",
+        "bool",
+        "_jobjectArray *",
+        "_jobjectArray *",
+        "bool",
+        "bool",
+    ];
+
+    /// Demangled params of SpecializeCommon on V, the bounding capabilities and sysprop
+    /// overrides added
+    fn v_params() -> Vec<&'static str> {
+        let mut params = R_PARAMS.to_vec();
+        params.insert(8, "long");
+        params.push("bool");
+        params
+    }
+
+    #[test]
+    fn infers_r() {
+        let layout = SpecializeLayout::infer(&R_PARAMS).unwrap();
+
+        assert!(!layout.is_forward_compat());
+        assert_eq!(
+            layout.index_of(SpecializeField::EffectiveCapabilities),
+            Some(7)
+        );
+        assert_eq!(layout.index_of(SpecializeField::BoundingCapabilities), None);
+        assert_eq!(layout.index_of(SpecializeField::MountExternal), Some(8));
+        assert_eq!(layout.index_of(SpecializeField::ManagedNiceName), Some(10));
+        assert_eq!(layout.index_of(SpecializeField::MountDataDirs), Some(18));
+        assert_eq!(layout.index_of(SpecializeField::MountStorageDirs), Some(19));
+        assert_eq!(
+            layout.index_of(SpecializeField::MountSyspropOverrides),
+            None
+        );
+    }
+
+    #[test]
+    fn infers_v() {
+        let layout = SpecializeLayout::infer(&v_params()).unwrap();
+
+        for (index, field) in SpecializeField::iter().enumerate() {
+            assert_eq!(layout.index_of(field), Some(index), "{field:?}");
+        }
+    }
+
+    #[test]
+    fn infers_new_param_in_run() {
+        // a release appending one more bool to the trailing run is still understood
+        let mut params = R_PARAMS.to_vec();
+        params.push("bool");
+
+        let layout = SpecializeLayout::infer(&params).unwrap();
+
+        assert_eq!(layout.index_of(SpecializeField::BoundingCapabilities), None);
+        assert_eq!(
+            layout.index_of(SpecializeField::MountSyspropOverrides),
+            Some(20)
+        );
+    }
+
+    #[test]
+    fn rejects_missing_required() {
+        let mut params = R_PARAMS.to_vec();
+        params.remove(1);
+
+        assert!(SpecializeLayout::infer(&params).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_param() {
+        let mut params = v_params();
+        params.push("_jstring *");
+
+        assert!(SpecializeLayout::infer(&params).is_err());
+    }
+
+    #[test]
+    fn rejects_ambiguous() {
+        // without the data info lists, the trailing bools could start at either `is_top_app`
+        // or `mount_data_dirs`
+        let mut params = R_PARAMS.to_vec();
+        params.drain(15..18);
+        params.push("bool");
+
+        assert!(SpecializeLayout::infer(&params).is_err());
+    }
+
+    #[test]
+    fn forward_compat_reads_stable_args() {
+        let layout = SpecializeLayout::forward_compat();
+
+        assert!(layout.is_forward_compat());
+        assert_eq!(
+            layout.index_of(SpecializeField::EffectiveCapabilities),
+            Some(7)
+        );
+        assert_eq!(layout.index_of(SpecializeField::ManagedNiceName), None);
+    }
+
+    #[test]
+    fn extra_gids_of_system_features_only() {
+        // inet, sdcard_rw
+        assert!(
+            ArgsMutation::extra_gids(vec![3003, 1015])
+                .validate()
+                .is_ok()
+        );
+
+        // root, system, log, shell, net_admin, readproc, an app
+        for gid in [0, 1000, 1007, 2000, 3005, 3009, 10123, -1] {
+            assert!(
+                ArgsMutation::extra_gids(vec![3003, gid])
+                    .validate()
+                    .is_err()
+            );
+        }
+
+        assert!(ArgsMutation::extra_gids(vec![3003; 17]).validate().is_err());
+    }
 }
@@ -0,0 +1,345 @@
+//! Typed access to the array and string arguments of `SpecializeCommon`.
+//!
+//! These go through the JNI function table of the calling thread, so they can only be used
+//! inside the specializing process (i.e. from the bridge), never on args read from core.
+
+use crate::jni_call;
+use crate::zygote::SpecializeArgs;
+use anyhow::{Context, Result, bail};
+use jni::sys::{JNIEnv, jint, jintArray, jobject, jobjectArray, jsize, jstring};
+use std::ffi::{CStr, CString, c_char};
+use std::ptr;
+
+/// Entries of `rlimits` are `int[3]`, see `ZygoteConnection.applyRlimitsToArgs`
+const RLIMIT_LEN: usize = 3;
+
+/// `pkg_data_info_list` and `allowlisted_data_info_list` are flattened
+/// `(package name, volume uuid, inode)` triples
+const DATA_INFO_LEN: usize = 3;
+
+/// Volume UUID of the internal storage in data info lists
+const INTERNAL_VOLUME_UUID: &str = "null";
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rlimit {
+    pub resource: jint,
+    pub soft: jint,
+    pub hard: jint,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataInfo {
+    pub package_name: String,
+    /// `None` for the internal storage
+    pub volume_uuid: Option<String>,
+    /// Inode of the CE data directory, used to locate it while it's still locked
+    pub ce_data_inode: u64,
+}
+
+impl DataInfo {
+    /// Decode a flattened data info list, also used by core on lists read through ptrace.
+    pub fn parse_list(strings: &[Option<String>]) -> Result<Vec<DataInfo>> {
+        let len = strings.len();
+
+        if !len.is_multiple_of(DATA_INFO_LEN) {
+            bail!("malformed data info list, length {len} is not a multiple of {DATA_INFO_LEN}");
+        }
+
+        let mut result = Vec::with_capacity(len / DATA_INFO_LEN);
+
+        for (index, entry) in strings.chunks_exact(DATA_INFO_LEN).enumerate() {
+            let (package_name, volume_uuid, inode) = (&entry[0], &entry[1], &entry[2]);
+
+            let package_name = package_name
+                .clone()
+                .context(format!("null package name at {}", index * DATA_INFO_LEN))?;
+            let ce_data_inode = inode
+                .as_ref()
+                .context(format!("null inode for {package_name}"))?
+                .parse()
+                .context(format!("malformed inode for {package_name}"))?;
+
+            result.push(DataInfo {
+                package_name,
+                volume_uuid: volume_uuid
+                    .clone()
+                    .filter(|uuid| uuid != INTERNAL_VOLUME_UUID),
+                ce_data_inode,
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Env(*mut JNIEnv);
+
+impl Env {
+    fn new(env: JNIEnv) -> Self {
+        Self(env as _)
+    }
+
+    fn check_exception(&self) -> Result<()> {
+        if jni_call!(self.0, ExceptionCheck) {
+            jni_call!(self.0, ExceptionClear);
+            bail!("java exception thrown");
+        }
+
+        Ok(())
+    }
+
+    fn array_len(&self, array: jobject) -> usize {
+        jni_call!(self.0, GetArrayLength, array) as _
+    }
+
+    fn read_ints(&self, array: jintArray) -> Result<Vec<jint>> {
+        let mut values = vec![0; self.array_len(array)];
+
+        jni_call!(
+            self.0,
+            GetIntArrayRegion,
+            array,
+            0,
+            values.len() as jsize,
+            values.as_mut_ptr()
+        );
+        self.check_exception()?;
+
+        Ok(values)
+    }
+
+    fn new_ints(&self, values: &[jint]) -> Result<jintArray> {
+        let array = jni_call!(self.0, NewIntArray, values.len() as jsize);
+
+        self.check_exception()?;
+        jni_call!(
+            self.0,
+            SetIntArrayRegion,
+            array,
+            0,
+            values.len() as jsize,
+            values.as_ptr()
+        );
+        self.check_exception()?;
+
+        Ok(array)
+    }
+
+    fn new_object_array(&self, len: usize, class: &CStr) -> Result<jobjectArray> {
+        let class = jni_call!(self.0, FindClass, class.as_ptr());
+
+        self.check_exception()?;
+
+        let array = jni_call!(self.0, NewObjectArray, len as jsize, class, ptr::null_mut());
+
+        self.delete_local_ref(class);
+        self.check_exception()?;
+
+        Ok(array)
+    }
+
+    fn get_element(&self, array: jobjectArray, index: usize) -> Result<jobject> {
+        let element = jni_call!(self.0, GetObjectArrayElement, array, index as jsize);
+
+        self.check_exception()?;
+
+        Ok(element)
+    }
+
+    /// Store `value` into `array` and release the local reference.
+    fn set_element(&self, array: jobjectArray, index: usize, value: jobject) -> Result<()> {
+        jni_call!(self.0, SetObjectArrayElement, array, index as jsize, value);
+
+        self.delete_local_ref(value);
+        self.check_exception()
+    }
+
+    /// Read without releasing the reference, e.g. for args still owned by the caller.
+    fn get_string(&self, string: jstring) -> Result<Option<String>> {
+        if string.is_null() {
+            return Ok(None);
+        }
+
+        let chars: *const c_char = jni_call!(self.0, GetStringUTFChars, string, ptr::null_mut());
+
+        self.check_exception()?;
+
+        let value = unsafe { CStr::from_ptr(chars) }
+            .to_string_lossy()
+            .into_owned();
+
+        jni_call!(self.0, ReleaseStringUTFChars, string, chars);
+
+        Ok(Some(value))
+    }
+
+    /// Read and release the local reference.
+    fn read_string(&self, string: jstring) -> Result<Option<String>> {
+        let value = self.get_string(string);
+
+        self.delete_local_ref(string);
+
+        value
+    }
+
+    fn new_string(&self, value: &str) -> Result<jstring> {
+        let value = CString::new(value)?;
+        let string = jni_call!(self.0, NewStringUTF, value.as_ptr());
+
+        self.check_exception()?;
+
+        Ok(string)
+    }
+
+    fn delete_local_ref(&self, obj: jobject) {
+        if !obj.is_null() {
+            jni_call!(self.0, DeleteLocalRef, obj);
+        }
+    }
+}
+
+pub fn read_string(env: JNIEnv, string: jstring) -> Result<Option<String>> {
+    Env::new(env).get_string(string)
+}
+
+pub fn read_gids(env: JNIEnv, gids: jintArray) -> Result<Option<Vec<jint>>> {
+    if gids.is_null() {
+        return Ok(None);
+    }
+
+    Env::new(env).read_ints(gids).map(Some)
+}
+
+pub fn new_gids(env: JNIEnv, gids: &[jint]) -> Result<jintArray> {
+    Env::new(env).new_ints(gids)
+}
+
+pub fn read_rlimits(env: JNIEnv, rlimits: jobjectArray) -> Result<Option<Vec<Rlimit>>> {
+    if rlimits.is_null() {
+        return Ok(None);
+    }
+
+    let env = Env::new(env);
+    let mut result = Vec::new();
+
+    for index in 0..env.array_len(rlimits) {
+        let entry = env.get_element(rlimits, index)?;
+
+        if entry.is_null() {
+            bail!("null rlimit at {index}");
+        }
+
+        let values = env.read_ints(entry);
+
+        env.delete_local_ref(entry);
+
+        let &[resource, soft, hard] = values?.as_slice() else {
+            bail!("malformed rlimit at {index}, expected {RLIMIT_LEN} ints");
+        };
+
+        result.push(Rlimit {
+            resource,
+            soft,
+            hard,
+        });
+    }
+
+    Ok(Some(result))
+}
+
+pub fn new_rlimits(env: JNIEnv, rlimits: &[Rlimit]) -> Result<jobjectArray> {
+    let env = Env::new(env);
+    let array = env.new_object_array(rlimits.len(), c"[I")?;
+
+    for (index, rlimit) in rlimits.iter().enumerate() {
+        let entry = env.new_ints(&[rlimit.resource, rlimit.soft, rlimit.hard])?;
+        env.set_element(array, index, entry)?;
+    }
+
+    Ok(array)
+}
+
+pub fn read_data_info_list(env: JNIEnv, list: jobjectArray) -> Result<Option<Vec<DataInfo>>> {
+    if list.is_null() {
+        return Ok(None);
+    }
+
+    let env = Env::new(env);
+    let strings: Vec<_> = (0..env.array_len(list))
+        .map(|index| env.read_string(env.get_element(list, index)?))
+        .collect::<Result<_>>()?;
+
+    DataInfo::parse_list(&strings).map(Some)
+}
+
+pub fn new_data_info_list(env: JNIEnv, list: &[DataInfo]) -> Result<jobjectArray> {
+    let env = Env::new(env);
+    let array = env.new_object_array(list.len() * DATA_INFO_LEN, c"java/lang/String")?;
+
+    for (index, info) in list.iter().enumerate() {
+        let index = index * DATA_INFO_LEN;
+        let volume_uuid = info.volume_uuid.as_deref().unwrap_or(INTERNAL_VOLUME_UUID);
+
+        env.set_element(array, index, env.new_string(&info.package_name)?)?;
+        env.set_element(array, index + 1, env.new_string(volume_uuid)?)?;
+        env.set_element(
+            array,
+            index + 2,
+            env.new_string(&info.ce_data_inode.to_string())?,
+        )?;
+    }
+
+    Ok(array)
+}
+
+/// Readers return `None` for null arrays. Setters replace the argument with a new array,
+/// which reaches `SpecializeCommon` only when called before it (i.e. in pre-specialize).
+///
+/// All supported versions (R and later) pass the same arrays in the same format.
+impl SpecializeArgs {
+    pub fn read_nice_name(&self) -> Result<Option<String>> {
+        read_string(self.env, self.managed_nice_name)
+    }
+
+    pub fn read_app_data_dir(&self) -> Result<Option<String>> {
+        read_string(self.env, self.managed_app_data_dir)
+    }
+
+    pub fn read_gids(&self) -> Result<Option<Vec<jint>>> {
+        read_gids(self.env, self.gids)
+    }
+
+    pub fn set_gids(&mut self, gids: &[jint]) -> Result<()> {
+        self.gids = new_gids(self.env, gids)?;
+        Ok(())
+    }
+
+    pub fn read_rlimits(&self) -> Result<Option<Vec<Rlimit>>> {
+        read_rlimits(self.env, self.rlimits)
+    }
+
+    pub fn set_rlimits(&mut self, rlimits: &[Rlimit]) -> Result<()> {
+        self.rlimits = new_rlimits(self.env, rlimits)?;
+        Ok(())
+    }
+
+    pub fn read_pkg_data_info_list(&self) -> Result<Option<Vec<DataInfo>>> {
+        read_data_info_list(self.env, self.pkg_data_info_list)
+    }
+
+    pub fn set_pkg_data_info_list(&mut self, list: &[DataInfo]) -> Result<()> {
+        self.pkg_data_info_list = new_data_info_list(self.env, list)?;
+        Ok(())
+    }
+
+    pub fn read_allowlisted_data_info_list(&self) -> Result<Option<Vec<DataInfo>>> {
+        read_data_info_list(self.env, self.allowlisted_data_info_list)
+    }
+
+    pub fn set_allowlisted_data_info_list(&mut self, list: &[DataInfo]) -> Result<()> {
+        self.allowlisted_data_info_list = new_data_info_list(self.env, list)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // Record the wire-relevant features next to the library, core embeds the bridge
+    // and checks them against its own features at compile time.
+    let project_root = env::var("ROOT_DIR")?;
+    let profile = env::var("PROFILE")?;
+    let target = env::var("TARGET")?;
+    let target_dir = format!("{project_root}/target/{target}/{profile}");
+
+    let zygisk = env::var_os("CARGO_FEATURE_ZYGISK").is_some();
+
+    fs::create_dir_all(&target_dir)?;
+    fs::write(
+        format!("{target_dir}/libzynx_bridge.features"),
+        format!("zygisk={}", zygisk as u8),
+    )?;
+
+    Ok(())
+}
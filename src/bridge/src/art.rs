@@ -0,0 +1,405 @@
+//! Entry hooks of Java methods for native LiteLoader libraries, handed out as [`ArtApi`].
+//!
+//! Only enough for tracing: the entry point of the `ArtMethod` is replaced by a trampoline
+//! calling the callback, then the original entry point. Calls from compiled code and JNI go
+//! through it, calls between interpreted methods may not. Hooked methods are kept from being
+//! compiled or inlined from then on, code compiled before (e.g. the boot image) may still
+//! have them inlined.
+
+use crate::fork;
+use anyhow::{Result, bail};
+use dynasmrt::aarch64::Aarch64Relocation;
+use dynasmrt::{DynasmApi, DynasmLabelApi, VecAssembler, dynasm};
+use jni::sys::{JNIEnv, jclass, jmethodID, jobject};
+use log::{info, warn};
+use nix::libc;
+use nix::libc::{MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE, PROT_EXEC, PROT_READ, PROT_WRITE};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, c_char, c_int, c_void};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::{mem, ptr};
+use zynx_bridge_shared::checked_jni;
+use zynx_bridge_shared::jni_call;
+use zynx_bridge_shared::policy::liteloader::{ArtApi, ArtEntryCallback};
+use zynx_misc::ext::ResultExt;
+use zynx_misc::props;
+
+pub static API: ArtApi = ArtApi {
+    find_method,
+    hook_entry,
+    unhook_entry,
+};
+
+/// Android 11, the oldest release the layout below is known for
+const MIN_API_LEVEL: i32 = 30;
+
+/// `ArtMethod::access_flags_`
+const ACCESS_FLAGS_OFFSET: usize = 4;
+
+/// Keeps the JIT from compiling the method, and replacing the hooked entry point, as well as
+/// from inlining it into the methods it compiles
+const ACC_COMPILE_DONT_BOTHER: u32 = 0x0200_0000;
+const ACC_ABSTRACT: u32 = 0x0400;
+/// Compiled code inlines intrinsics regardless, and the flag above is part of their ordinal
+const ACC_INTRINSIC: u32 = 0x8000_0000;
+
+/// Bytes of the trampoline saving the quick ABI argument registers around the callback
+const TRAMPOLINE_SIZE: usize = 4096;
+
+static API_LEVEL: LazyLock<i32> = LazyLock::new(|| {
+    props::get("ro.build.version.sdk")
+        .and_then(|sdk| sdk.parse().ok())
+        .unwrap_or_default()
+});
+
+/// `ArtMethod*` returned by [`find_method`], whose class the lookup initialized
+static FOUND: LazyLock<Mutex<HashSet<usize>>> = LazyLock::new(Mutex::default);
+
+/// Methods hooked, by `ArtMethod*`
+static HOOKS: LazyLock<Mutex<HashMap<usize, Hook>>> = LazyLock::new(Mutex::default);
+
+struct Hook {
+    original: usize,
+    /// Whether [`ACC_COMPILE_DONT_BOTHER`] was set before hooking
+    compile_dont_bother: bool,
+}
+
+/// Hold the locks of hooks across `fork`, so that the forked process doesn't copy them while
+/// a hook is half recorded. Left held in the child, see [`ensure_not_forked`].
+pub fn lock_for_fork() {
+    mem::forget(FOUND.lock());
+    mem::forget(HOOKS.lock());
+}
+
+/// # Safety
+///
+/// Must pair with a [`lock_for_fork`] on the same thread, in the parent only.
+pub unsafe fn unlock_after_fork() {
+    unsafe {
+        HOOKS.force_unlock();
+        FOUND.force_unlock();
+    }
+}
+
+fn ensure_not_forked() -> Result<()> {
+    if fork::is_forked() {
+        bail!("art hooks are unavailable in forked processes");
+    }
+
+    Ok(())
+}
+
+/// Whether the `ArtMethod` layout of this release is known.
+pub fn is_supported() -> bool {
+    cfg!(target_arch = "aarch64") && *API_LEVEL >= MIN_API_LEVEL
+}
+
+/// `ArtMethod::ptr_sized_fields_.entry_point_from_quick_compiled_code_` on 64-bit, moved by
+/// the removal of `dex_code_item_offset_` in Android 12.
+fn entry_point_offset() -> usize {
+    if *API_LEVEL >= 31 { 24 } else { 32 }
+}
+
+fn access_flags(method: usize) -> &'static AtomicU32 {
+    unsafe { &*((method + ACCESS_FLAGS_OFFSET) as *const AtomicU32) }
+}
+
+fn entry_point(method: usize) -> &'static AtomicUsize {
+    unsafe { &*((method + entry_point_offset()) as *const AtomicUsize) }
+}
+
+/// `ArtMethod*` held by the reflected `method`, the field may be blocked by hidden API checks.
+fn reflected_art_method(
+    env: JNIEnv,
+    class: jclass,
+    method: jmethodID,
+    is_static: bool,
+) -> Result<*mut c_void> {
+    ensure_not_forked()?;
+
+    let raw: *mut JNIEnv = env as _;
+
+    checked_jni::with_env(env, |_| {
+        let reflected: jobject = jni_call!(raw, ToReflectedMethod, class, method, is_static);
+
+        if reflected.is_null() {
+            bail!("failed to reflect method");
+        }
+
+        let executable = jni_call!(raw, FindClass, c"java/lang/reflect/Executable".as_ptr());
+
+        if executable.is_null() {
+            bail!("no class java.lang.reflect.Executable");
+        }
+
+        let field = jni_call!(
+            raw,
+            GetFieldID,
+            executable,
+            c"artMethod".as_ptr(),
+            c"J".as_ptr()
+        );
+
+        if field.is_null() {
+            bail!("no field Executable.artMethod");
+        }
+
+        Ok(jni_call!(raw, GetLongField, reflected, field) as _)
+    })
+}
+
+/// Look up a method, which also initializes its class, so that the initialization doesn't
+/// reset the entry point of the method once hooked.
+fn find(
+    env: JNIEnv,
+    class: jclass,
+    name: &CStr,
+    signature: &CStr,
+    is_static: bool,
+) -> Result<*mut c_void> {
+    ensure_not_forked()?;
+
+    let raw: *mut JNIEnv = env as _;
+
+    let method = checked_jni::with_env(env, |_| {
+        let method = if is_static {
+            jni_call!(
+                raw,
+                GetStaticMethodID,
+                class,
+                name.as_ptr(),
+                signature.as_ptr()
+            )
+        } else {
+            jni_call!(raw, GetMethodID, class, name.as_ptr(), signature.as_ptr())
+        };
+
+        if method.is_null() {
+            bail!("no method {name:?}{signature:?}");
+        }
+
+        Ok(method)
+    })?;
+
+    // jmethodIDs are indices, tagged by the low bit, in debuggable processes, the field of the
+    // reflected method always holds the `ArtMethod*`
+    let art_method = match reflected_art_method(env, class, method, is_static) {
+        Ok(art_method) => art_method,
+        Err(err) if method as usize & 1 == 0 => {
+            warn!("{err:?}, using the jmethodID of {name:?} as its ArtMethod");
+            method as _
+        }
+        Err(err) => return Err(err.context(format!("failed to find ArtMethod of {name:?}"))),
+    };
+
+    FOUND.lock().insert(art_method as _);
+
+    Ok(art_method)
+}
+
+extern "C" fn find_method(
+    env: JNIEnv,
+    class: jclass,
+    name: *const c_char,
+    signature: *const c_char,
+    is_static: bool,
+) -> *mut c_void {
+    if env.is_null() || class.is_null() || name.is_null() || signature.is_null() {
+        return ptr::null_mut();
+    }
+
+    let (name, signature) = unsafe { (CStr::from_ptr(name), CStr::from_ptr(signature)) };
+
+    find(env, class, name, signature, is_static)
+        .ok_or_warn()
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Map a trampoline calling `callback(method, user_data)`, then tail-calling `original`.
+fn create_trampoline(
+    callback: ArtEntryCallback,
+    user_data: *mut c_void,
+    original: usize,
+) -> Result<usize> {
+    let mut ops: VecAssembler<Aarch64Relocation> = VecAssembler::new(0);
+
+    // x0 is the `ArtMethod*`, args are in x1-x7 and d0-d7, the others spill to the caller's
+    // frame which is left untouched. x16 and x17 are scratch registers of the quick ABI too.
+    dynasm!(ops
+        ; .arch aarch64
+        ; sub sp, sp, #0xb0
+        ; stp x0, x1, [sp, #0x00]
+        ; stp x2, x3, [sp, #0x10]
+        ; stp x4, x5, [sp, #0x20]
+        ; stp x6, x7, [sp, #0x30]
+        ; stp d0, d1, [sp, #0x40]
+        ; stp d2, d3, [sp, #0x50]
+        ; stp d4, d5, [sp, #0x60]
+        ; stp d6, d7, [sp, #0x70]
+        ; stp x16, x17, [sp, #0x80]
+        ; stp x29, x30, [sp, #0x90]
+        ; ldr x1, >user_data
+        ; ldr x16, >callback
+        ; blr x16
+        ; ldp x0, x1, [sp, #0x00]
+        ; ldp x2, x3, [sp, #0x10]
+        ; ldp x4, x5, [sp, #0x20]
+        ; ldp x6, x7, [sp, #0x30]
+        ; ldp d0, d1, [sp, #0x40]
+        ; ldp d2, d3, [sp, #0x50]
+        ; ldp d4, d5, [sp, #0x60]
+        ; ldp d6, d7, [sp, #0x70]
+        ; ldp x16, x17, [sp, #0x80]
+        ; ldp x29, x30, [sp, #0x90]
+        ; add sp, sp, #0xb0
+        ; ldr x16, >original
+        ; br x16
+
+        ; .align 8
+        ; callback:
+        ;; ops.push_u64(callback as usize as _)
+        ; user_data:
+        ;; ops.push_u64(user_data as usize as _)
+        ; original:
+        ;; ops.push_u64(original as _)
+    );
+
+    let code = ops.finalize()?;
+
+    if code.len() > TRAMPOLINE_SIZE {
+        bail!("trampoline too large: {} bytes", code.len());
+    }
+
+    unsafe {
+        let addr = libc::mmap(
+            ptr::null_mut(),
+            TRAMPOLINE_SIZE,
+            PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+
+        if addr == MAP_FAILED {
+            bail!("failed to map trampoline");
+        }
+
+        ptr::copy_nonoverlapping(code.as_ptr(), addr as *mut u8, code.len());
+
+        if libc::mprotect(addr, TRAMPOLINE_SIZE, PROT_READ | PROT_EXEC) != 0 {
+            libc::munmap(addr, TRAMPOLINE_SIZE);
+            bail!("failed to protect trampoline");
+        }
+
+        let start = addr as *mut c_char;
+        clear_cache(start, start.add(code.len()));
+
+        Ok(addr as _)
+    }
+}
+
+unsafe extern "C" {
+    #[link_name = "__clear_cache"]
+    fn clear_cache(start: *mut c_char, end: *mut c_char);
+}
+
+fn hook(method: usize, callback: ArtEntryCallback, user_data: *mut c_void) -> Result<()> {
+    if !is_supported() {
+        bail!("art hooks are unsupported on api level {}", *API_LEVEL);
+    }
+
+    ensure_not_forked()?;
+
+    if !FOUND.lock().contains(&method) {
+        bail!("method {method:#x} wasn't found by find_method, its class may be uninitialized");
+    }
+
+    let mut hooks = HOOKS.lock();
+
+    if hooks.contains_key(&method) {
+        bail!("method {method:#x} is already hooked");
+    }
+
+    let flags = access_flags(method);
+    let old_flags = flags.load(Ordering::Relaxed);
+
+    if old_flags & ACC_ABSTRACT != 0 {
+        bail!("method {method:#x} is abstract");
+    }
+
+    if old_flags & ACC_INTRINSIC != 0 {
+        bail!("method {method:#x} is an intrinsic");
+    }
+
+    let entry_point = entry_point(method);
+    let original = entry_point.load(Ordering::Acquire);
+    let trampoline = create_trampoline(callback, user_data, original)?;
+
+    flags.fetch_or(ACC_COMPILE_DONT_BOTHER, Ordering::Relaxed);
+    entry_point.store(trampoline, Ordering::Release);
+
+    hooks.insert(
+        method,
+        Hook {
+            original,
+            compile_dont_bother: old_flags & ACC_COMPILE_DONT_BOTHER != 0,
+        },
+    );
+
+    info!("hooked entry of method {method:#x}, original entry {original:#x}");
+
+    Ok(())
+}
+
+/// Restore the entry point of `method`. Its trampoline stays mapped, a thread may be running it.
+fn unhook(method: usize) -> Result<()> {
+    ensure_not_forked()?;
+
+    let Some(hook) = HOOKS.lock().remove(&method) else {
+        bail!("method {method:#x} isn't hooked");
+    };
+
+    entry_point(method).store(hook.original, Ordering::Release);
+
+    if !hook.compile_dont_bother {
+        access_flags(method).fetch_and(!ACC_COMPILE_DONT_BOTHER, Ordering::Relaxed);
+    }
+
+    info!("unhooked entry of method {method:#x}");
+
+    Ok(())
+}
+
+extern "C" fn hook_entry(
+    method: *mut c_void,
+    callback: ArtEntryCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    if method.is_null() {
+        return -1;
+    }
+
+    match hook(method as _, callback, user_data) {
+        Ok(()) => 0,
+        Err(err) => {
+            warn!("failed to hook method: {err:?}");
+            -1
+        }
+    }
+}
+
+extern "C" fn unhook_entry(method: *mut c_void) -> c_int {
+    if method.is_null() {
+        return -1;
+    }
+
+    match unhook(method as _) {
+        Ok(()) => 0,
+        Err(err) => {
+            warn!("failed to unhook method: {err:?}");
+            -1
+        }
+    }
+}
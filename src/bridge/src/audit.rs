@@ -0,0 +1,201 @@
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::os::unix::fs::MetadataExt;
+use std::{env, fs};
+use zynx_bridge_shared::zygote::{Artifact, ArtifactKind, AuditReport, AuditRequest};
+use zynx_misc::ext::ResultExt;
+use zynx_misc::selinux;
+
+/// Labels given to files by root managers, never seen by unmodified apps
+const ROOT_FILE_TYPES: &[&str] = &["magisk_file", "ksu_file"];
+
+/// Paths only reachable with root, leaking through mappings or fds
+const ROOT_PATHS: &[&str] = &["/data/adb", "/sbin/.magisk", "/debug_ramdisk"];
+
+/// Domains an app must have left by the time it runs
+const FOREIGN_DOMAINS: &[&str] = &[":zygote:", ":magisk:", ":su:", ":ksu:"];
+
+struct Auditor<'a> {
+    request: &'a AuditRequest,
+    artifacts: BTreeSet<Artifact>,
+}
+
+fn root_label(path: &str) -> Option<String> {
+    let context = selinux::getcon(path).ok()?;
+
+    ROOT_FILE_TYPES
+        .iter()
+        .any(|ty| context.contains(ty))
+        .then(|| format!("labeled {context}"))
+}
+
+impl Auditor<'_> {
+    fn add(&mut self, kind: ArtifactKind, detail: impl Into<String>, reason: String) {
+        self.artifacts.insert(Artifact {
+            kind,
+            detail: detail.into(),
+            reason,
+        });
+    }
+
+    fn suspicious(&self, text: &str) -> Option<String> {
+        if let Some(name) = self.request.names.iter().find(|name| text.contains(*name)) {
+            return Some(format!("named after zynx ({name})"));
+        }
+
+        ROOT_PATHS
+            .iter()
+            .find(|path| text.contains(*path))
+            .map(|path| format!("points into {path}"))
+    }
+
+    fn sent(&self, inode: u64) -> Option<String> {
+        self.request
+            .inodes
+            .contains(&inode)
+            .then(|| "memfd sent by zynx".into())
+    }
+
+    fn audit_maps(&mut self) -> Result<()> {
+        for line in fs::read_to_string("/proc/self/maps")?.lines() {
+            // start-end perms offset dev inode name
+            let mut fields = line.splitn(6, ' ');
+            let (Some(range), Some(perms), Some(inode)) =
+                (fields.next(), fields.next(), fields.nth(2))
+            else {
+                continue;
+            };
+
+            let name = fields.next().unwrap_or_default().trim_start();
+            let inode = inode.parse().unwrap_or(0);
+
+            if name.is_empty() && inode == 0 {
+                continue;
+            }
+
+            let detail = format!("{range} {perms} {name}");
+
+            for reason in [
+                self.suspicious(name),
+                self.sent(inode),
+                // memfds and deleted files keep their label, which the name can't hide
+                root_label(&format!("/proc/self/map_files/{range}")),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                self.add(ArtifactKind::Map, detail.clone(), reason);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn audit_fds(&mut self) -> Result<()> {
+        for entry in fs::read_dir("/proc/self/fd")?.flatten() {
+            let path = entry.path();
+            let Ok(target) = fs::read_link(&path) else {
+                continue;
+            };
+
+            let target = target.to_string_lossy();
+            let inode = fs::metadata(&path).map(|meta| meta.ino()).unwrap_or(0);
+            let detail = format!("{} -> {target}", entry.file_name().to_string_lossy());
+
+            for reason in [
+                self.suspicious(&target),
+                self.sent(inode),
+                root_label(&path.to_string_lossy()),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                self.add(ArtifactKind::Fd, detail.clone(), reason);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn audit_threads(&mut self) -> Result<()> {
+        for entry in fs::read_dir("/proc/self/task")?.flatten() {
+            let Ok(comm) = fs::read_to_string(entry.path().join("comm")) else {
+                continue;
+            };
+
+            let comm = comm.trim_end();
+
+            if let Some(reason) = self.suspicious(comm) {
+                let tid = entry.file_name().to_string_lossy().into_owned();
+                self.add(ArtifactKind::Thread, format!("{tid} {comm}"), reason);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sockets of the whole system, apps can't read them on recent versions
+    fn audit_sockets(&mut self) {
+        let Ok(sockets) = fs::read_to_string("/proc/net/unix") else {
+            return;
+        };
+
+        for path in sockets.lines().skip(1).filter_map(|line| {
+            // Num RefCount Protocol Flags Type St Inode Path
+            line.split_whitespace().nth(7)
+        }) {
+            if let Some(reason) = self.suspicious(path) {
+                self.add(ArtifactKind::Socket, path, reason);
+            }
+        }
+    }
+
+    fn audit_env(&mut self) {
+        for (key, value) in env::vars_os() {
+            let key = key.to_string_lossy();
+            let entry = format!("{key}={}", value.to_string_lossy());
+
+            if let Some(reason) = self.suspicious(&entry) {
+                self.add(ArtifactKind::Env, entry, reason);
+            } else if key.to_ascii_uppercase().contains("ZYNX") {
+                self.add(ArtifactKind::Env, entry, "named after zynx".into());
+            } else if key == "LD_PRELOAD" {
+                self.add(ArtifactKind::Env, entry, "preloads libraries".into());
+            }
+        }
+    }
+
+    fn audit_selinux(&mut self, context: &str) {
+        if let Some(domain) = FOREIGN_DOMAINS
+            .iter()
+            .find(|domain| context.contains(*domain))
+        {
+            let reason = format!("runs in the {} domain", domain.trim_matches(':'));
+            self.add(ArtifactKind::Selinux, context, reason);
+        }
+    }
+}
+
+/// Look for what zynx left in the current process, as seen from within it.
+pub fn audit(request: &AuditRequest) -> AuditReport {
+    let context = fs::read_to_string("/proc/self/attr/current")
+        .map(|context| context.trim_end_matches(['\0', '\n']).to_string())
+        .unwrap_or_default();
+
+    let mut auditor = Auditor {
+        request,
+        artifacts: BTreeSet::new(),
+    };
+
+    auditor.audit_maps().log_if_error();
+    auditor.audit_fds().log_if_error();
+    auditor.audit_threads().log_if_error();
+    auditor.audit_sockets();
+    auditor.audit_env();
+    auditor.audit_selinux(&context);
+
+    AuditReport {
+        context,
+        artifacts: auditor.artifacts.into_iter().collect(),
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{audit, fork};
+use anyhow::{Result, bail};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::os::fd::{AsFd, OwnedFd};
+use std::panic::AssertUnwindSafe;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::{mem, panic};
+use zynx_bridge_api::injector;
+use zynx_bridge_shared::zygote::{
+    AuditRequest, BridgeReport, CrashReport, LoadReport, ProviderLoad, ProviderType,
+};
+use zynx_misc::debug::panic_message;
+use zynx_misc::ext::ResultExt;
+
+/// Connection to the daemon, kept open after receiving the payload for reports until the
+/// handlers finished, see [`report_loads`]
+static CHANNEL: Mutex<Option<OwnedFd>> = Mutex::new(None);
+
+/// Modules of the received bundles, named in reports
+static MODULES: OnceLock<HashMap<ProviderType, Vec<String>>> = OnceLock::new();
+
+/// Set if the daemon asked for an audit of the process, run once the handlers are done
+static AUDIT: OnceLock<AuditRequest> = OnceLock::new();
+
+pub fn install(
+    conn_fd: OwnedFd,
+    modules: HashMap<ProviderType, Vec<String>>,
+    audit: Option<AuditRequest>,
+) {
+    *CHANNEL.lock() = Some(conn_fd);
+    let _ = MODULES.set(modules);
+
+    if let Some(audit) = audit {
+        let _ = AUDIT.set(audit);
+    }
+}
+
+/// Hold the connection across `fork`, so that the forked process doesn't copy it while a
+/// report is sent.
+pub fn lock_for_fork() {
+    mem::forget(CHANNEL.lock());
+}
+
+/// # Safety
+///
+/// Must pair with a [`lock_for_fork`] on the same thread, in the parent only.
+pub unsafe fn unlock_after_fork() {
+    unsafe { CHANNEL.force_unlock() }
+}
+
+/// Close the copy of the connection inherited by a forked process, which would keep the
+/// daemon watching reports after its parent exited. The lock stays held, nothing is reported
+/// from forked processes.
+///
+/// # Safety
+///
+/// Must be called in the child after a [`lock_for_fork`] on the same thread.
+pub unsafe fn release_in_child() {
+    unsafe { (*CHANNEL.data_ptr()).take() };
+}
+
+fn modules_of(provider: ProviderType) -> Vec<String> {
+    MODULES
+        .get()
+        .and_then(|modules| modules.get(&provider))
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn send(report: BridgeReport) {
+    // the daemon would blame the modules of the parent
+    if fork::is_forked() {
+        return;
+    }
+
+    let channel = CHANNEL.lock();
+
+    let Some(conn) = channel.as_ref() else {
+        return;
+    };
+
+    report.send_to(conn.as_fd()).log_if_error();
+}
+
+/// Report a panic of a `provider` handler, blaming the module it was running if any. The
+/// handler's own code isn't the fault of any module.
+fn report(provider: ProviderType, message: &str) {
+    send(BridgeReport::Crash(CrashReport {
+        provider,
+        modules: injector::take_panicked_module().into_iter().collect(),
+        message: message.into(),
+    }));
+}
+
+/// Report how the handlers of each provider went, as `(provider, errors, time spent)`, then
+/// the audit if requested, and close the connection, the handlers being done.
+pub fn report_loads(loads: Vec<(ProviderType, Vec<String>, Duration)>) {
+    let providers = loads
+        .into_iter()
+        .map(|(provider, errors, duration)| ProviderLoad {
+            provider,
+            modules: modules_of(provider),
+            errors,
+            duration_us: duration.as_micros() as _,
+        })
+        .collect();
+
+    send(BridgeReport::Load(LoadReport { providers }));
+
+    if let Some(request) = AUDIT.get() {
+        send(BridgeReport::Audit(audit::audit(request)));
+    }
+
+    CHANNEL.lock().take();
+}
+
+/// Run a handler of `provider`, turning a panic into an error reported to the daemon, so
+/// that a faulty module doesn't take the process down with it.
+pub fn guard(provider: ProviderType, f: impl FnOnce() -> Result<()>) -> Result<()> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = panic_message(&*payload);
+
+            report(provider, &message);
+
+            bail!("handler panicked: {message}")
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{LOG_BUFFER, SESSION, art, crash};
+use log::{info, warn};
+use nix::libc;
+use std::process;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set in processes forked by the injected one after specialization, which inherit the
+/// bridge and loaded modules, but not the threads or the session of their parent. The locks
+/// of the bridge stay held in them, what they guard can't be used anymore.
+static FORKED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_forked() -> bool {
+    FORKED.load(Ordering::Relaxed)
+}
+
+// no thread of the bridge is left halfway through its state once all its locks are held
+
+extern "C" fn prepare() {
+    crash::lock_for_fork();
+    art::lock_for_fork();
+
+    if let Some(buffer) = LOG_BUFFER.get() {
+        buffer.lock_for_fork();
+    }
+}
+
+extern "C" fn parent() {
+    unsafe {
+        if let Some(buffer) = LOG_BUFFER.get() {
+            buffer.unlock_after_fork();
+        }
+
+        art::unlock_after_fork();
+        crash::unlock_after_fork();
+    }
+}
+
+extern "C" fn child() {
+    // The log buffer is shared with the parent, which is still writing to it and is the one
+    // the daemon drains. Leave its lock held, unmap it and fall back to logcat instead.
+    FORKED.store(true, Ordering::Relaxed);
+    SESSION.store(0, Ordering::Relaxed);
+
+    unsafe {
+        crash::release_in_child();
+
+        if let Some(buffer) = LOG_BUFFER.get() {
+            buffer.unmap_in_child();
+        }
+    }
+
+    info!("forked from an injected process, pid {}", process::id());
+}
+
+/// Quiesce the bridge around `fork` of the process, and detach forked children from the
+/// state of their parent.
+pub fn install_handlers() {
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        let res = unsafe { libc::pthread_atfork(Some(prepare), Some(parent), Some(child)) };
+
+        if res != 0 {
+            warn!("failed to install fork handlers: {res}");
+        }
+    });
+}
@@ -1,14 +1,23 @@
 mod debugger;
 mod liteloader;
+mod native;
 
 use crate::injector::debugger::DebuggerProviderHandler;
 use crate::injector::liteloader::LiteLoaderProviderHandler;
+use crate::injector::native::NativeProviderHandler;
+use crate::{crash, install_log_buffer};
 use anyhow::Result;
 use log::error;
-use std::collections::HashMap;
+use nix::libc::c_int;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
 use zynx_bridge_api::injector::ProviderHandler;
-use zynx_bridge_api::zygote::ProviderBundle;
-use zynx_bridge_shared::zygote::{ProviderType, SpecializeArgs};
+use zynx_bridge_api::zygote::{Attachment, ProviderBundle};
+use zynx_bridge_shared::log_buffer::LogBufferWriter;
+use zynx_bridge_shared::zygote::{IpcPayload, ProviderType, SpecializeArgs};
+use zynx_misc::ext::ResultExt;
 #[cfg(feature = "zygisk")]
 use zynx_zygisk_compat::ZygiskProviderHandler;
 
@@ -16,11 +25,15 @@ use zynx_zygisk_compat::ZygiskProviderHandler;
 struct Handler {
     on_specialize_pre: Box<dyn Fn(&mut SpecializeArgs, &mut ProviderBundle) -> Result<()>>,
     on_specialize_post: Box<dyn Fn(&SpecializeArgs, &mut ProviderBundle) -> Result<()>>,
+    on_native_start: Box<dyn Fn(&mut ProviderBundle) -> Result<()>>,
 }
 
 #[derive(Default)]
 pub struct ProviderHandlerRegistry {
-    handlers: HashMap<ProviderType, Handler>,
+    /// Dispatched in registration order
+    handlers: Vec<(ProviderType, Handler)>,
+    /// Errors and time spent per provider, in dispatch order
+    loads: RefCell<Vec<(ProviderType, Vec<String>, Duration)>>,
 }
 
 impl ProviderHandlerRegistry {
@@ -29,6 +42,7 @@ impl ProviderHandlerRegistry {
 
         instance.register(DebuggerProviderHandler);
         instance.register(LiteLoaderProviderHandler);
+        instance.register(NativeProviderHandler);
 
         #[cfg(feature = "zygisk")]
         instance.register(ZygiskProviderHandler);
@@ -37,25 +51,50 @@ impl ProviderHandlerRegistry {
     }
 
     fn register<P: ProviderHandler>(&mut self, _: P) {
-        self.handlers.insert(
+        self.handlers.push((
             P::TYPE,
             Handler {
                 on_specialize_pre: Box::new(P::on_specialize_pre),
                 on_specialize_post: Box::new(P::on_specialize_post),
+                on_native_start: Box::new(P::on_native_start),
             },
-        );
+        ));
+    }
+
+    /// Run a hook of `provider_type`, accounting its time and errors for the load report.
+    fn run(&self, provider_type: ProviderType, hook: &str, f: impl FnOnce() -> Result<()>) {
+        let start = Instant::now();
+        let result = crash::guard(provider_type, f);
+        let mut loads = self.loads.borrow_mut();
+
+        let index = match loads.iter().position(|(ty, ..)| *ty == provider_type) {
+            Some(index) => index,
+            None => {
+                loads.push((provider_type, Vec::new(), Duration::ZERO));
+                loads.len() - 1
+            }
+        };
+
+        let (_, errors, duration) = &mut loads[index];
+
+        *duration += start.elapsed();
+
+        if let Err(err) = result {
+            error!("failed to dispatch {hook} hook for provider type {provider_type:?}: {err:?}");
+            errors.push(format!("{hook}: {err:#}"));
+        }
     }
 
     pub fn dispatch_pre(
         &self,
         args: &mut SpecializeArgs,
-        groups: &mut HashMap<ProviderType, ProviderBundle>,
+        groups: &mut BTreeMap<ProviderType, ProviderBundle>,
     ) {
         for (provider_type, handler) in &self.handlers {
-            if let Some(bundle) = groups.get_mut(provider_type)
-                && let Err(err) = (handler.on_specialize_pre)(args, bundle)
-            {
-                error!("failed to dispatch pre hook for provider type {provider_type:?}: {err:?}");
+            if let Some(bundle) = groups.get_mut(provider_type) {
+                self.run(*provider_type, "pre", || {
+                    (handler.on_specialize_pre)(args, bundle)
+                });
             }
         }
     }
@@ -63,14 +102,81 @@ impl ProviderHandlerRegistry {
     pub fn dispatch_post(
         &self,
         args: &SpecializeArgs,
-        groups: &mut HashMap<ProviderType, ProviderBundle>,
+        groups: &mut BTreeMap<ProviderType, ProviderBundle>,
     ) {
         for (provider_type, handler) in &self.handlers {
-            if let Some(bundle) = groups.get_mut(provider_type)
-                && let Err(err) = (handler.on_specialize_post)(args, bundle)
-            {
-                error!("failed to dispatch post hook for provider type {provider_type:?}: {err:?}");
+            if let Some(bundle) = groups.get_mut(provider_type) {
+                self.run(*provider_type, "post", || {
+                    (handler.on_specialize_post)(args, bundle)
+                });
             }
         }
     }
+
+    pub fn dispatch_native(&self, groups: &mut BTreeMap<ProviderType, ProviderBundle>) {
+        for (provider_type, handler) in &self.handlers {
+            if let Some(bundle) = groups.get_mut(provider_type) {
+                self.run(*provider_type, "native", || {
+                    (handler.on_native_start)(bundle)
+                });
+            }
+        }
+    }
+
+    /// Send the results of the hooks dispatched so far to the daemon, once all have run.
+    pub fn report(&self) {
+        crash::report_loads(self.loads.take());
+    }
+}
+
+/// Receive the payload sent by core on `conn_fd`, and group its bundles by provider type.
+/// The connection is kept open to report crashes and results of their handlers.
+pub fn receive_bundles(conn_fd: c_int) -> Result<BTreeMap<ProviderType, ProviderBundle>> {
+    let conn_fd = unsafe { OwnedFd::from_raw_fd(conn_fd) };
+    let reports_fd = conn_fd.try_clone()?;
+    let (payload, fds) = IpcPayload::recv_from(conn_fd)?;
+
+    let mut fds = fds.into_iter();
+
+    if payload.has_log_fd
+        && let Some(fd) = fds.next()
+    {
+        LogBufferWriter::from_fd(fd)
+            .map(install_log_buffer)
+            .log_if_error();
+    }
+
+    let mut groups: BTreeMap<ProviderType, ProviderBundle> = BTreeMap::new();
+    let mut modules: HashMap<ProviderType, Vec<String>> = HashMap::new();
+
+    for wire in payload.providers {
+        modules.insert(
+            wire.ty,
+            wire.attachments
+                .iter()
+                .filter_map(|aw| aw.module.clone())
+                .collect(),
+        );
+
+        let bundle = ProviderBundle {
+            ty: wire.ty,
+            attachments: wire
+                .attachments
+                .into_iter()
+                .map(|aw| Attachment {
+                    fd: if aw.has_fd { fds.next() } else { None },
+                    data: aw.data,
+                    module: aw.module,
+                })
+                .collect(),
+            data: wire.data,
+            mutation: wire.mutation,
+        };
+
+        groups.insert(bundle.ty, bundle);
+    }
+
+    crash::install(reports_fd, modules, payload.audit);
+
+    Ok(groups)
 }
@@ -1,24 +1,42 @@
 use anyhow::Result;
+use jni::{jni_sig, jni_str};
+use log::{info, warn};
 use zynx_bridge_api::injector::ProviderHandler;
 use zynx_bridge_api::zygote::ProviderBundle;
+use zynx_bridge_shared::checked_jni;
 use zynx_bridge_shared::policy::debugger::DebuggerParams;
 use zynx_bridge_shared::zygote::{ProviderType, SpecializeArgs};
 
+fn is_jdwp_enabled(env: jni::sys::JNIEnv) -> Result<bool> {
+    checked_jni::with_env(env, |env| {
+        let vm_debug_class = env.find_class(jni_str!("dalvik/system/VMDebug"))?;
+        let enabled = env.call_static_method(
+            vm_debug_class,
+            jni_str!("isDebuggingEnabled"),
+            jni_sig!("()Z"),
+            &[],
+        )?;
+
+        Ok(enabled.z()?)
+    })
+}
+
 pub struct DebuggerProviderHandler;
 
 impl ProviderHandler for DebuggerProviderHandler {
     const TYPE: ProviderType = ProviderType::Debugger;
 
-    fn on_specialize_pre(args: &mut SpecializeArgs, bundle: &mut ProviderBundle) -> Result<()> {
+    fn on_specialize_post(args: &SpecializeArgs, bundle: &mut ProviderBundle) -> Result<()> {
         if let Some(bytes) = &bundle.data {
             let params: DebuggerParams = wincode::deserialize(bytes)?;
 
+            // the runtime registers with adbd on its own, `adb jdwp` lists the process then
             if params.force_debuggable {
-                // https://cs.android.com/android/platform/superproject/main/+/main:frameworks/base/services/core/java/com/android/server/am/ProcessList.java;l=1946;drc=61197364367c9e404c7da6900658f1b16c42d0da
-                args.runtime_flags |= 1 // DEBUG_ENABLE_JDWP
-                    | (1 << 25) // DEBUG_ENABLE_PTRACE
-                    | (1 << 8) // DEBUG_JAVA_DEBUGGABLE
-                    | (1 << 1); // DEBUG_ENABLE_CHECKJNI
+                if is_jdwp_enabled(args.env)? {
+                    info!("forced debuggable, jdwp enabled");
+                } else {
+                    warn!("forced debuggable, but the runtime didn't enable jdwp");
+                }
             }
         }
 
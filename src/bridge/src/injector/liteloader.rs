@@ -1,45 +1,160 @@
+use crate::art;
 use anyhow::Result;
-use log::warn;
-use zynx_bridge_api::injector::ProviderHandler;
+use jni::sys::JNIEnv;
+use log::{info, warn};
+use std::ffi::{CString, c_void};
+use std::{mem, ptr};
+use zynx_bridge_api::injector::{self, ProviderHandler};
 use zynx_bridge_api::zygote::ProviderBundle;
-use zynx_bridge_shared::policy::liteloader::{LibraryKind, LiteLoaderParams};
-use zynx_bridge_shared::remote_lib::{JavaLibrary, NativeLibrary};
+use zynx_bridge_shared::checked_jni;
+use zynx_bridge_shared::policy::liteloader::{
+    DEFAULT_JAVA_ENTRY, LibraryKind, LiteLoaderParams, LoadPhase, NATIVE_CONTEXT_VERSION,
+    NATIVE_ENTRY, NativeContext,
+};
+use zynx_bridge_shared::remote_lib::{JavaContext, JavaLibrary, NativeLibrary};
 use zynx_bridge_shared::zygote::{ProviderType, SpecializeArgs};
 use zynx_misc::ext::ResultExt;
+use zynx_misc::props;
 
 pub struct LiteLoaderProviderHandler;
 
-impl ProviderHandler for LiteLoaderProviderHandler {
-    const TYPE: ProviderType = ProviderType::LiteLoader;
-
-    fn on_specialize_post(args: &SpecializeArgs, bundle: &mut ProviderBundle) -> Result<()> {
+impl LiteLoaderProviderHandler {
+    fn load_libraries(args: &SpecializeArgs, bundle: &mut ProviderBundle, phase: LoadPhase) {
         for attachment in bundle.attachments.iter_mut() {
-            if let Some(fd) = attachment.fd.take() {
-                let params: LiteLoaderParams = match attachment
-                    .data
-                    .as_ref()
-                    .and_then(|data| wincode::deserialize(data).ok())
-                {
-                    Some(params) => params,
-                    None => {
-                        warn!("failed to deserialize LiteLoaderParams");
-                        continue;
+            let params: LiteLoaderParams = match attachment
+                .data
+                .as_ref()
+                .and_then(|data| wincode::deserialize(data).ok())
+            {
+                Some(params) => params,
+                None => {
+                    warn!("failed to deserialize LiteLoaderParams");
+                    continue;
+                }
+            };
+
+            if params.phase != phase {
+                continue;
+            }
+
+            if matches!(params.kind, LibraryKind::Java) && args.is_forward_compat() {
+                warn!(
+                    "skipping java library {} in forward-compat mode",
+                    params.lib_name
+                );
+                continue;
+            }
+
+            let Some(fd) = attachment.fd.take() else {
+                continue;
+            };
+
+            injector::in_module(attachment.module.as_deref(), || match params.kind {
+                LibraryKind::Native => {
+                    let mut lib = NativeLibrary::new(params.lib_name.clone(), fd);
+
+                    let Ok(()) = lib.open().inspect_log_error() else {
+                        return;
+                    };
+
+                    if let Ok(native_entry) = lib.dlsym(NATIVE_ENTRY) {
+                        Self::call_native_context_entry(&lib, native_entry, args, &params)
+                            .log_if_error();
                     }
-                };
 
-                match params.kind {
-                    LibraryKind::Native => {
-                        let mut lib = NativeLibrary::new(params.lib_name, fd);
-                        lib.open().log_if_error();
+                    if let Some(entry) = params.entry {
+                        Self::call_native_entry(&lib, &entry, args.env).log_if_error();
                     }
-                    LibraryKind::Java => {
-                        let mut lib = JavaLibrary::new(params.lib_name, fd);
-                        lib.load(args.env).log_if_error();
+
+                    if params.unload {
+                        lib.unload().log_if_error();
                     }
                 }
-            }
+                LibraryKind::Java => {
+                    let entry = params.entry.as_deref().unwrap_or(DEFAULT_JAVA_ENTRY);
+                    let context = JavaContext {
+                        package_name: params.package_name,
+                        process_name: args.read_nice_name().ok_or_warn().flatten(),
+                        data_dir: args.read_app_data_dir().ok_or_warn().flatten(),
+                        data: params.data,
+                    };
+
+                    let mut lib = JavaLibrary::new(params.lib_name, fd);
+                    lib.load(args.env, entry, &context).log_if_error();
+                }
+            });
         }
+    }
+
+    fn call_native_context_entry(
+        lib: &NativeLibrary,
+        entry: *const c_void,
+        args: &SpecializeArgs,
+        params: &LiteLoaderParams,
+    ) -> Result<()> {
+        let entry_fn: extern "C" fn(*const NativeContext) = unsafe { mem::transmute(entry) };
 
+        let to_cstring = |value: Option<String>| value.and_then(|it| CString::new(it).ok());
+        let as_ptr = |value: &Option<CString>| value.as_ref().map_or(ptr::null(), |it| it.as_ptr());
+
+        // owned here, outliving the call
+        let nice_name = to_cstring(args.read_nice_name().ok_or_warn().flatten());
+        let app_data_dir = to_cstring(args.read_app_data_dir().ok_or_warn().flatten());
+        let package_name = to_cstring(params.package_name.clone());
+
+        let context = NativeContext {
+            version: NATIVE_CONTEXT_VERSION,
+            uid: args.uid,
+            api_level: props::get("ro.build.version.sdk")
+                .and_then(|sdk| sdk.parse().ok())
+                .unwrap_or_default(),
+            env: args.env,
+            nice_name: as_ptr(&nice_name),
+            app_data_dir: as_ptr(&app_data_dir),
+            package_name: as_ptr(&package_name),
+            data: params
+                .data
+                .as_ref()
+                .map_or(ptr::null(), |data| data.as_ptr()),
+            data_len: params.data.as_ref().map_or(0, |data| data.len()),
+            art: if art::is_supported() {
+                &art::API
+            } else {
+                ptr::null()
+            },
+        };
+
+        info!("calling {NATIVE_ENTRY} of {}", lib.name());
+
+        // in a local frame, clearing what the library left pending
+        checked_jni::with_env(args.env, |_| {
+            entry_fn(&context);
+            Ok(())
+        })
+    }
+
+    fn call_native_entry(lib: &NativeLibrary, entry: &str, env: JNIEnv) -> Result<()> {
+        let entry_fn: extern "C" fn(JNIEnv) = unsafe { mem::transmute(lib.dlsym(entry)?) };
+
+        info!("calling entry {entry} of {}", lib.name());
+
+        checked_jni::with_env(env, |_| {
+            entry_fn(env);
+            Ok(())
+        })
+    }
+}
+
+impl ProviderHandler for LiteLoaderProviderHandler {
+    const TYPE: ProviderType = ProviderType::LiteLoader;
+
+    fn on_specialize_pre(args: &mut SpecializeArgs, bundle: &mut ProviderBundle) -> Result<()> {
+        Self::load_libraries(args, bundle, LoadPhase::Pre);
+        Ok(())
+    }
+
+    fn on_specialize_post(args: &SpecializeArgs, bundle: &mut ProviderBundle) -> Result<()> {
+        Self::load_libraries(args, bundle, LoadPhase::Post);
         Ok(())
     }
 }
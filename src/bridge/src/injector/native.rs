@@ -0,0 +1,57 @@
+use anyhow::Result;
+use log::{info, warn};
+use std::mem;
+use zynx_bridge_api::injector::{self, ProviderHandler};
+use zynx_bridge_api::zygote::ProviderBundle;
+use zynx_bridge_shared::policy::native::NativeParams;
+use zynx_bridge_shared::remote_lib::NativeLibrary;
+use zynx_bridge_shared::zygote::ProviderType;
+use zynx_misc::ext::ResultExt;
+
+pub struct NativeProviderHandler;
+
+impl NativeProviderHandler {
+    fn call_entry(lib: &NativeLibrary, entry: &str) -> Result<()> {
+        let entry_fn: extern "C" fn() = unsafe { mem::transmute(lib.dlsym(entry)?) };
+
+        info!("calling entry {entry} of {}", lib.name());
+        entry_fn();
+
+        Ok(())
+    }
+}
+
+impl ProviderHandler for NativeProviderHandler {
+    const TYPE: ProviderType = ProviderType::Native;
+
+    fn on_native_start(bundle: &mut ProviderBundle) -> Result<()> {
+        for attachment in bundle.attachments.iter_mut() {
+            let Some(params) = attachment
+                .data
+                .as_ref()
+                .and_then(|data| wincode::deserialize::<NativeParams>(data).ok())
+            else {
+                warn!("failed to deserialize NativeParams");
+                continue;
+            };
+
+            let Some(fd) = attachment.fd.take() else {
+                continue;
+            };
+
+            injector::in_module(attachment.module.as_deref(), || {
+                let mut lib = NativeLibrary::new(params.lib_name, fd);
+
+                let Ok(()) = lib.open().inspect_log_error() else {
+                    return;
+                };
+
+                if let Some(entry) = params.entry {
+                    Self::call_entry(&lib, &entry).log_if_error();
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -1,16 +1,106 @@
-use log::LevelFilter;
+use android_logger::AndroidLogger;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Once, OnceLock};
+use zynx_bridge_shared::log_buffer::LogBufferWriter;
+use zynx_bridge_shared::zygote::SessionId;
 
+mod art;
+mod audit;
+mod crash;
+mod fork;
 mod injector;
+mod native;
 mod zygote;
 
+/// Shared log buffer installed by the daemon in diagnostics mode.
+static LOG_BUFFER: OnceLock<LogBufferWriter> = OnceLock::new();
+
+/// Injection session assigned by the daemon, prefixed to every log record, `0` in forked
+/// processes, which aren't part of it.
+static SESSION: AtomicU32 = AtomicU32::new(0);
+
+fn session() -> Option<SessionId> {
+    Some(SessionId(SESSION.load(Ordering::Relaxed))).filter(SessionId::is_some)
+}
+
+/// Writes to the shared log buffer if installed, falls back to logcat otherwise.
+struct BridgeLogger {
+    android: AndroidLogger,
+}
+
+impl Log for BridgeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.android.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        // a forked child must not write to the buffer of its parent
+        let Some(buffer) = LOG_BUFFER.get().filter(|_| !fork::is_forked()) else {
+            return match session() {
+                Some(session) => self.android.log(
+                    &Record::builder()
+                        .metadata(record.metadata().clone())
+                        .args(format_args!("[session={session}] {}", record.args()))
+                        .module_path(record.module_path())
+                        .file(record.file())
+                        .line(record.line())
+                        .build(),
+                ),
+                None => self.android.log(record),
+            };
+        };
+
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut line = Vec::new();
+        let _ = write!(line, "{} {}: ", record.level(), record.target());
+
+        if let Some(session) = session() {
+            let _ = write!(line, "[session={session}] ");
+        }
+
+        let _ = writeln!(line, "{}", record.args());
+
+        buffer.write(&line);
+    }
+
+    fn flush(&self) {}
+}
+
 fn init_logger() {
-    android_logger::init_once(
-        android_logger::Config::default()
-            .with_max_level(if cfg!(debug_assertions) {
-                LevelFilter::Debug
-            } else {
-                LevelFilter::Info
-            })
-            .with_tag("zynx::bridge"),
-    );
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        let level = if cfg!(debug_assertions) {
+            LevelFilter::Debug
+        } else {
+            LevelFilter::Info
+        };
+
+        let logger = BridgeLogger {
+            android: AndroidLogger::new(
+                android_logger::Config::default()
+                    .with_max_level(level)
+                    .with_tag("zynx::bridge"),
+            ),
+        };
+
+        if log::set_boxed_logger(Box::new(logger)).is_ok() {
+            log::set_max_level(level);
+        }
+    });
+}
+
+fn set_session(session: SessionId) {
+    if session.is_some() {
+        SESSION.store(session.0, Ordering::Relaxed);
+    }
+}
+
+fn install_log_buffer(buffer: LogBufferWriter) {
+    let _ = LOG_BUFFER.set(buffer);
 }
@@ -0,0 +1,32 @@
+use crate::injector::{ProviderHandlerRegistry, receive_bundles};
+use crate::{fork, init_logger, set_session};
+use anyhow::Result;
+use log::debug;
+use zynx_bridge_shared::zygote::NativeBridgeArgs;
+use zynx_misc::ext::ResultExt;
+
+fn on_native_start(args: &NativeBridgeArgs) -> Result<()> {
+    debug!("connection fd: {}", args.conn_fd);
+
+    let mut groups = receive_bundles(args.conn_fd)?;
+
+    let handler = ProviderHandlerRegistry::new();
+
+    handler.dispatch_native(&mut groups);
+    handler.report();
+
+    Ok(())
+}
+
+/// Called by core through a remote call, with the native process stopped at its entry point.
+#[unsafe(no_mangle)]
+extern "C" fn native_start(args: *const NativeBridgeArgs) {
+    let args = unsafe { &*args };
+
+    init_logger();
+    fork::install_handlers();
+    set_session(args.session);
+    debug!("native start");
+
+    on_native_start(args).log_if_error()
+}
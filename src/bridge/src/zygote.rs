@@ -1,20 +1,19 @@
-use crate::init_logger;
-use crate::injector::ProviderHandlerRegistry;
+use crate::injector::{ProviderHandlerRegistry, receive_bundles};
+use crate::{fork, init_logger, set_session};
 use anyhow::Result;
 use log::{debug, info};
 use nix::libc::c_long;
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::os::fd::{FromRawFd, OwnedFd};
+use std::collections::BTreeMap;
 use std::slice;
-use zynx_bridge_api::zygote::{Attachment, ProviderBundle};
-use zynx_bridge_shared::zygote::{BridgeArgs, IpcPayload, ProviderType, SpecializeArgs};
+use zynx_bridge_api::zygote::ProviderBundle;
+use zynx_bridge_shared::zygote::{BridgeArgs, ProviderType, SpecializeArgs};
 use zynx_misc::ext::ResultExt;
 
 struct SpecializeContext {
     args: SpecializeArgs,
     handler: ProviderHandlerRegistry,
-    groups: HashMap<ProviderType, ProviderBundle>,
+    groups: BTreeMap<ProviderType, ProviderBundle>,
 }
 
 thread_local! {
@@ -22,34 +21,25 @@ thread_local! {
 }
 
 fn on_specialize_pre(args: &mut [c_long], bridge_args: &BridgeArgs) -> Result<()> {
-    let mut args_struct = SpecializeArgs::new(&mut *args, bridge_args.specialize_version);
+    let mut args_struct = SpecializeArgs::new(&mut *args, bridge_args.specialize_layout);
 
     info!("specialize args: {args_struct:?}");
 
     if bridge_args.conn_fd >= 0 {
         debug!("connection fd: {}", bridge_args.conn_fd);
 
-        let (payload, fds) =
-            IpcPayload::recv_from(unsafe { OwnedFd::from_raw_fd(bridge_args.conn_fd) })?;
-
-        let mut fds = fds.into_iter();
-        let mut groups: HashMap<ProviderType, ProviderBundle> = HashMap::new();
-
-        for wire in payload.providers {
-            let bundle = ProviderBundle {
-                ty: wire.ty,
-                attachments: wire
-                    .attachments
-                    .into_iter()
-                    .map(|aw| Attachment {
-                        fd: if aw.has_fd { fds.next() } else { None },
-                        data: aw.data,
-                    })
-                    .collect(),
-                data: wire.data,
-            };
-
-            groups.insert(bundle.ty, bundle);
+        let mut groups = receive_bundles(bridge_args.conn_fd)?;
+
+        if args_struct.is_forward_compat() {
+            debug!("args are read-only in forward-compat mode, mutations ignored");
+        } else {
+            // in a fixed order, later mutations win where they conflict
+            for bundle in groups.values() {
+                if let Some(mutation) = &bundle.mutation {
+                    info!("{:?} mutates args: {mutation:?}", bundle.ty);
+                    mutation.apply(&mut args_struct).log_if_error();
+                }
+            }
         }
 
         let handler = ProviderHandlerRegistry::new();
@@ -72,6 +62,7 @@ fn on_specialize_post() -> Result<()> {
     G_CONTEXT.with(|cell| {
         if let Some(mut ctx) = cell.borrow_mut().take() {
             ctx.handler.dispatch_post(&ctx.args, &mut ctx.groups);
+            ctx.handler.report();
         }
     });
     Ok(())
@@ -83,6 +74,8 @@ extern "C" fn specialize_pre(args: *mut c_long, args_count: usize, bridge_args:
     let bridge_args = unsafe { &*bridge_args };
 
     init_logger();
+    fork::install_handlers();
+    set_session(bridge_args.session);
     debug!("specialize args: {args:?}");
 
     on_specialize_pre(args, bridge_args).log_if_error()
@@ -30,6 +30,8 @@ fn main() -> Result<(), Box<dyn Error>> {
     let commit_hash = String::from_utf8(output.stdout)?.trim().to_string();
 
     println!("cargo:rustc-env=GIT_COMMIT_HASH={commit_hash}");
+    // the bridge is built for the same target, core embeds it from there
+    println!("cargo:rustc-env=TARGET_TRIPLE={}", env::var("TARGET")?);
     println!("cargo:rerun-if-changed={}/.git/HEAD", env!("ROOT_DIR"));
 
     Ok(())
@@ -1,2 +1,4 @@
 pub mod inotify;
 pub mod packages;
+pub mod proc_visibility;
+pub mod root;
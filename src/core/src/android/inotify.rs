@@ -7,11 +7,24 @@ use zynx_misc::ext::ResultExt;
 
 pub struct AsyncInotify {
     rx: Receiver<Result<Event>>,
-    _watcher: INotifyWatcher,
+    watcher: INotifyWatcher,
 }
 
 impl AsyncInotify {
     pub fn new<P: AsRef<Path>>(path: P, mask: EventKindMask) -> Result<Self> {
+        Self::with_mode(path, mask, RecursiveMode::NonRecursive)
+    }
+
+    /// Watch `path` and all its subdirectories, including ones created later.
+    pub fn new_recursive<P: AsRef<Path>>(path: P, mask: EventKindMask) -> Result<Self> {
+        Self::with_mode(path, mask, RecursiveMode::Recursive)
+    }
+
+    fn with_mode<P: AsRef<Path>>(
+        path: P,
+        mask: EventKindMask,
+        mode: RecursiveMode,
+    ) -> Result<Self> {
         let (tx, rx) = mpsc::channel(1);
         let mut watcher = INotifyWatcher::new(
             move |res: notify::Result<Event>| {
@@ -21,12 +34,16 @@ impl AsyncInotify {
             Config::default().with_event_kinds(mask),
         )?;
 
-        watcher.watch(path.as_ref(), RecursiveMode::NonRecursive)?;
+        watcher.watch(path.as_ref(), mode)?;
+
+        Ok(Self { rx, watcher })
+    }
 
-        Ok(Self {
-            rx,
-            _watcher: watcher,
-        })
+    /// Also watch `path`, not recursively. Watching a path again is harmless.
+    pub fn add_path<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.watcher
+            .watch(path.as_ref(), RecursiveMode::NonRecursive)?;
+        Ok(())
     }
 
     pub async fn wait(&mut self) -> Result<Event> {
@@ -1,11 +1,11 @@
 use crate::android::inotify::AsyncInotify;
+use crate::injector::DecisionCache;
 use anyhow::{Result, anyhow};
-use log::{debug, error, info, warn};
 use nix::unistd::{Gid, Uid};
 use notify::event::{ModifyKind, RenameMode};
 use notify::{EventKind, EventKindMask};
 use once_cell::sync::Lazy;
-use parking_lot::{MappedRwLockReadGuard, RwLock, RwLockReadGuard};
+use parking_lot::RwLock;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
@@ -13,11 +13,13 @@ use std::path::PathBuf;
 use std::sync::{Arc, OnceLock};
 use tokio::task;
 use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
 
 static PACKAGE_LIST_FILE: Lazy<PathBuf> = Lazy::new(|| "/data/system/packages.list".into());
 static PACKAGE_INFO_SERVICE: OnceLock<PackageInfoService> = OnceLock::new();
 
-pub type PackageInfoListLocked<'a> = MappedRwLockReadGuard<'a, [PackageInfo]>;
+/// Packages sharing a uid, a snapshot that outlives reloads of packages.list
+pub type PackageInfoList = Arc<[PackageInfo]>;
 
 #[derive(Clone, Debug)]
 pub struct PackageInfo {
@@ -29,6 +31,27 @@ pub struct PackageInfo {
     pub gids: Vec<Gid>,
 }
 
+impl PackageInfo {
+    /// Line of packages.list describing the package, as far as it's parsed.
+    pub fn to_line(&self) -> String {
+        let gids: Vec<_> = self.gids.iter().map(|gid| gid.to_string()).collect();
+        let gids = if gids.is_empty() {
+            "none".into()
+        } else {
+            gids.join(",")
+        };
+
+        format!(
+            "{} {} {} {} {} {gids}",
+            self.name,
+            self.uid,
+            u8::from(self.debuggable),
+            self.data_dir,
+            self.seinfo
+        )
+    }
+}
+
 fn parse_gids(gids_str: &str) -> Option<Vec<Gid>> {
     if gids_str.is_empty() || gids_str == "none" {
         return Some(Vec::new());
@@ -79,8 +102,9 @@ pub fn parse_package_list() -> Result<Vec<PackageInfo>> {
 }
 
 pub struct PackageInfoService {
-    data: Arc<RwLock<HashMap<Uid, Vec<PackageInfo>>>>,
-    _watch_task: JoinHandle<()>,
+    data: Arc<RwLock<HashMap<Uid, PackageInfoList>>>,
+    /// Absent if packages.list isn't watched
+    _watch_task: Option<JoinHandle<()>>,
 }
 
 impl PackageInfoService {
@@ -109,7 +133,22 @@ impl PackageInfoService {
         PACKAGE_INFO_SERVICE
             .set(Self {
                 data,
-                _watch_task: watch_task,
+                _watch_task: Some(watch_task),
+            })
+            .map_err(|_| anyhow!("duplicate called"))?;
+
+        Ok(())
+    }
+
+    /// Serve the packages of `lines` of packages.list instead, never reloaded, e.g. to replay
+    /// a trace off-device.
+    pub fn init_static<'a>(lines: impl Iterator<Item = &'a str>) -> Result<()> {
+        let packages = lines.filter_map(parse_line).collect();
+
+        PACKAGE_INFO_SERVICE
+            .set(Self {
+                data: Arc::new(RwLock::new(Self::build_map(packages))),
+                _watch_task: None,
             })
             .map_err(|_| anyhow!("duplicate called"))?;
 
@@ -122,22 +161,33 @@ impl PackageInfoService {
             .expect("package info service not initialized")
     }
 
-    pub fn query(&self, uid: Uid) -> Option<PackageInfoListLocked<'_>> {
-        let lock = self.data.read();
-        RwLockReadGuard::try_map(lock, |map| map.get(&uid).map(|v| v.as_slice())).ok()
+    pub fn query(&self, uid: Uid) -> Option<PackageInfoList> {
+        self.data.read().get(&uid).cloned()
+    }
+
+    /// Uids of the packages `filter` accepts.
+    pub fn uids_of(&self, filter: impl Fn(&PackageInfo) -> bool) -> Vec<Uid> {
+        self.data
+            .read()
+            .iter()
+            .filter(|(_, pkgs)| pkgs.iter().any(&filter))
+            .map(|(uid, _)| *uid)
+            .collect()
     }
 
-    fn build_map(packages: Vec<PackageInfo>) -> HashMap<Uid, Vec<PackageInfo>> {
+    fn build_map(packages: Vec<PackageInfo>) -> HashMap<Uid, PackageInfoList> {
         let mut map: HashMap<Uid, Vec<PackageInfo>> = HashMap::new();
         for info in packages {
             map.entry(info.uid).or_default().push(info);
         }
-        map
+        map.into_iter()
+            .map(|(uid, list)| (uid, list.into()))
+            .collect()
     }
 
     async fn watch_loop(
         mut inotify: AsyncInotify,
-        data: Arc<RwLock<HashMap<Uid, Vec<PackageInfo>>>>,
+        data: Arc<RwLock<HashMap<Uid, PackageInfoList>>>,
     ) -> Result<()> {
         loop {
             let event = inotify.wait().await?;
@@ -151,7 +201,7 @@ impl PackageInfoService {
         }
     }
 
-    fn reload_packages(data: &RwLock<HashMap<Uid, Vec<PackageInfo>>>) {
+    fn reload_packages(data: &RwLock<HashMap<Uid, PackageInfoList>>) {
         match parse_package_list() {
             Ok(packages) => {
                 let new_map = Self::build_map(packages);
@@ -162,6 +212,7 @@ impl PackageInfoService {
                 drop(data);
 
                 info!("reloaded {count} packages from packages.list");
+                DecisionCache::instance().invalidate("packages.list reloaded");
             }
             Err(err) => {
                 warn!("failed to reload packages.list: {err:?}, keeping old data");
@@ -0,0 +1,100 @@
+use once_cell::sync::Lazy;
+use std::fmt::{Display, Formatter};
+use std::{fmt, fs};
+use tracing::{info, warn};
+
+static INSTANCE: Lazy<ProcVisibility> = Lazy::new(ProcVisibility::detect);
+
+/// Mount options of `/proc` that restrict what we can see of other processes.
+#[derive(Debug, Default)]
+pub struct ProcVisibility {
+    /// Value of the `hidepid=` option, if any (`1`/`2`/`invisible`/`noaccess`/`ptraceable`)
+    pub hidepid: Option<String>,
+    /// Value of the `gid=` option, members of this group are exempted from `hidepid`
+    pub gid: Option<String>,
+    /// Whether mounted with `subset=pid`, which hides everything except `/proc/<pid>`
+    pub subset_pid: bool,
+}
+
+impl ProcVisibility {
+    fn detect() -> Self {
+        let mut visibility = Self::default();
+
+        let Ok(mounts) = fs::read_to_string("/proc/self/mounts") else {
+            warn!("failed to read /proc/self/mounts, assuming unrestricted procfs");
+            return visibility;
+        };
+
+        let Some(options) = mounts.lines().find_map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            (fields.get(1) == Some(&"/proc") && fields.get(2) == Some(&"proc"))
+                .then(|| fields.get(3).copied())
+                .flatten()
+        }) else {
+            return visibility;
+        };
+
+        for option in options.split(',') {
+            match option.split_once('=') {
+                Some(("hidepid", value)) if value != "0" && value != "off" => {
+                    visibility.hidepid = Some(value.into())
+                }
+                Some(("gid", value)) => visibility.gid = Some(value.into()),
+                Some(("subset", "pid")) => visibility.subset_pid = true,
+                _ => {}
+            }
+        }
+
+        if visibility.is_restricted() {
+            warn!("restricted procfs detected: {visibility}");
+        } else {
+            info!("procfs visibility: unrestricted");
+        }
+
+        visibility
+    }
+
+    pub fn instance() -> &'static Self {
+        &INSTANCE
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        self.hidepid.is_some() || self.subset_pid
+    }
+
+    /// Hint appended to errors of procfs reads, naming the mount options involved.
+    pub fn hint(&self) -> String {
+        if !self.is_restricted() {
+            return "procfs is not restricted by mount options, check SELinux denials".into();
+        }
+
+        let mut hints = Vec::new();
+
+        if let Some(hidepid) = &self.hidepid {
+            let exemption = match &self.gid {
+                Some(gid) => format!(" or run as a member of gid {gid}"),
+                None => String::new(),
+            };
+
+            hints.push(format!(
+                "/proc is mounted with `hidepid={hidepid}`, remount with `hidepid=0`{exemption}"
+            ));
+        }
+
+        if self.subset_pid {
+            hints.push("/proc is mounted with `subset=pid`, remount without it".into());
+        }
+
+        hints.join("; ")
+    }
+}
+
+impl Display for ProcVisibility {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "hidepid = {:?}, gid = {:?}, subset=pid: {}",
+            self.hidepid, self.gid, self.subset_pid
+        )
+    }
+}
@@ -0,0 +1,122 @@
+pub mod contexts;
+
+use crate::misc::rooted;
+use once_cell::sync::Lazy;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{fmt, fs};
+use zynx_misc::selinux;
+
+pub const MODULES_DIR: &str = "/data/adb/modules";
+
+const KSUD: &str = "/data/adb/ksud";
+const APD: &str = "/data/adb/apd";
+const MAGISK_DIR: &str = "/data/adb/magisk";
+
+/// Patches the live policy on Magisk, and bundled with APatch
+const MAGISKPOLICY: &str = "magiskpolicy";
+const APATCH_MAGISKPOLICY: &str = "/data/adb/ap/bin/magiskpolicy";
+
+const KSU_FILE_CONTEXT: &str = "u:object_r:ksu_file:s0";
+
+static INSTANCE: Lazy<Option<RootManager>> = Lazy::new(RootManager::detect);
+
+static MODULE_DIR: Lazy<Option<PathBuf>> = Lazy::new(|| env::var_os("MODDIR").map(PathBuf::from));
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RootManager {
+    Magisk,
+    KernelSU,
+    APatch,
+}
+
+impl RootManager {
+    /// The root manager installed on the device, if any is recognized.
+    pub fn instance() -> Option<Self> {
+        *INSTANCE
+    }
+
+    fn detect() -> Option<Self> {
+        // set for module scripts by KernelSU and APatch, Magisk sets none
+        let flag = |name| env::var(name).is_ok_and(|value| value == "true");
+
+        if flag("KSU") {
+            return Some(Self::KernelSU);
+        }
+
+        if flag("APATCH") {
+            return Some(Self::APatch);
+        }
+
+        // KernelSU and APatch may coexist with leftovers of Magisk, check them first
+        if Path::new(KSUD).exists() {
+            Some(Self::KernelSU)
+        } else if Path::new(APD).exists() {
+            Some(Self::APatch)
+        } else if Path::new(MAGISK_DIR).is_dir() || env::var_os("MODDIR").is_some() {
+            Some(Self::Magisk)
+        } else {
+            None
+        }
+    }
+
+    /// Contexts of files readable by all domains, defined by the policy patches of the root
+    /// manager, in order of preference.
+    pub fn file_contexts(self) -> &'static [&'static str] {
+        match self {
+            Self::KernelSU => &[KSU_FILE_CONTEXT, selinux::MAGISK_FILE_CONTEXT],
+            Self::Magisk | Self::APatch => &[selinux::MAGISK_FILE_CONTEXT],
+        }
+    }
+
+    /// Command patching the live policy with `rules`.
+    pub fn sepolicy_command(self, rules: &[String]) -> Command {
+        match self {
+            Self::KernelSU => {
+                let mut command = Command::new(KSUD);
+                command.args(["sepolicy", "patch"]).arg(rules.join("; "));
+                command
+            }
+            Self::Magisk | Self::APatch => {
+                let program = if self == Self::APatch && Path::new(APATCH_MAGISKPOLICY).exists() {
+                    APATCH_MAGISKPOLICY
+                } else {
+                    MAGISKPOLICY
+                };
+
+                let mut command = Command::new(program);
+                command.arg("--live").args(rules);
+                command
+            }
+        }
+    }
+}
+
+impl Display for RootManager {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Magisk => fmt.write_str("Magisk"),
+            Self::KernelSU => fmt.write_str("KernelSU"),
+            Self::APatch => fmt.write_str("APatch"),
+        }
+    }
+}
+
+/// Directory of the zynx module, if started by a root manager rather than by hand.
+pub fn module_dir() -> Option<&'static Path> {
+    MODULE_DIR.as_deref()
+}
+
+/// Directory holding the modules of the root manager, next to the zynx module.
+pub fn modules_dir() -> &'static Path {
+    static DIR: Lazy<PathBuf> = Lazy::new(|| {
+        module_dir()
+            .and_then(|dir| fs::canonicalize(dir).ok())
+            .and_then(|dir| dir.parent().map(Path::to_path_buf))
+            .unwrap_or_else(|| rooted(MODULES_DIR))
+    });
+
+    &DIR
+}
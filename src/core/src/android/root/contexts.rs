@@ -0,0 +1,155 @@
+use super::{RootManager, module_dir};
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::fd::AsFd;
+use tracing::{debug, info, warn};
+use zynx_bridge_shared::zygote::ProviderType;
+use zynx_misc::selinux;
+
+/// SELinux contexts tried for library memfds, in order, replacing those of the root manager.
+///
+/// ```toml
+/// default = ["u:object_r:magisk_file:s0"]  # for all providers
+///
+/// [providers]                              # keyed by lowercase provider name
+/// native = ["u:object_r:system_lib_file:s0"]
+/// ```
+pub const CONTEXTS_FILE: &str = "/data/adb/zynx/contexts.toml";
+
+/// Mapped by every domain for system libraries, the last resort
+const SYSTEM_FILE_CONTEXT: &str = "u:object_r:system_file:s0";
+
+static FILE: Lazy<ContextsFile> = Lazy::new(|| {
+    load()
+        .inspect_err(|err| warn!("ignoring {CONTEXTS_FILE}: {err:?}"))
+        .unwrap_or_default()
+});
+
+/// Provider of libraries and domain loading them, `None` if not checked
+type Target = (ProviderType, Option<String>);
+
+/// Context that last labeled libraries of a provider for a domain, tried first for the next
+/// ones
+static SELECTED: Lazy<Mutex<HashMap<Target, String>>> = Lazy::new(Mutex::default);
+
+#[derive(Default, Deserialize)]
+struct ContextsFile {
+    default: Option<Vec<String>>,
+    #[serde(default)]
+    providers: HashMap<String, Vec<String>>,
+}
+
+fn load() -> Result<ContextsFile> {
+    match fs::read_to_string(CONTEXTS_FILE) {
+        Ok(content) => toml::from_str(&content).context("failed to parse contexts"),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(ContextsFile::default()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Contexts to try for libraries of `provider`: configured ones or those of the root
+/// manager, then [`SYSTEM_FILE_CONTEXT`].
+fn chain(provider: ProviderType) -> Vec<String> {
+    let key = format!("{provider:?}").to_lowercase();
+
+    let mut chain = FILE
+        .providers
+        .get(&key)
+        .or(FILE.default.as_ref())
+        .cloned()
+        .unwrap_or_else(|| {
+            RootManager::instance()
+                .unwrap_or(RootManager::Magisk)
+                .file_contexts()
+                .iter()
+                .map(|it| it.to_string())
+                .collect()
+        });
+
+    if !chain.iter().any(|it| it == SYSTEM_FILE_CONTEXT) {
+        chain.push(SYSTEM_FILE_CONTEXT.into());
+    }
+
+    chain
+}
+
+/// Whether the policy lets `domain` map libraries of `context`, assumed if it can't be
+/// queried or no domain is given.
+fn loadable(domain: Option<&str>, context: &str) -> bool {
+    let Some(domain) = domain else {
+        return true;
+    };
+
+    if !selinux::is_enforcing() {
+        return true;
+    }
+
+    match selinux::check_access(
+        domain,
+        context,
+        "file",
+        &["read", "getattr", "map", "execute"],
+    ) {
+        Ok(denied) => denied.is_empty(),
+        Err(err) => {
+            debug!("failed to query access to {context}: {err:?}");
+            true
+        }
+    }
+}
+
+/// Context libraries of `provider` get labeled with for `domain`, for reporting missing rules.
+pub fn selected(provider: ProviderType, domain: &str) -> String {
+    if let Some(context) = SELECTED.lock().get(&(provider, Some(domain.into()))) {
+        return context.clone();
+    }
+
+    chain(provider).swap_remove(0)
+}
+
+/// Label a library memfd of `provider` so that `domain`, the one of the process loading it,
+/// may map it, with the first context of the chain the policy defines and allows. Without a
+/// domain, e.g. for native services running in domains of their own, the first context the
+/// policy defines is taken. Run by hand rather than as a module, the policy is expected to be
+/// permissive.
+pub fn label<F: AsFd>(file: F, provider: ProviderType, domain: Option<&str>) {
+    if module_dir().is_none() {
+        return;
+    }
+
+    let key = (provider, domain.map(str::to_string));
+    let cached = SELECTED.lock().get(&key).cloned();
+
+    if let Some(context) = cached {
+        if selinux::fsetcon(&file, &context).is_ok() {
+            return;
+        }
+
+        SELECTED.lock().remove(&key);
+    }
+
+    let target = domain.unwrap_or("its target");
+
+    for context in chain(provider) {
+        if !loadable(domain, &context) {
+            debug!("{provider:?}: {context} can't be mapped by {target}, skipped");
+            continue;
+        }
+
+        match selinux::fsetcon(&file, &context) {
+            Ok(()) => {
+                info!("{provider:?}: labeling libraries for {target} as {context}");
+                SELECTED.lock().insert(key, context);
+                return;
+            }
+            Err(err) => debug!("{provider:?}: failed to label as {context}: {err}"),
+        }
+    }
+
+    warn!("{provider:?}: no usable SELinux context for libraries of {target}, left unlabeled");
+}
@@ -0,0 +1,60 @@
+use crate::config::ZynxConfigs;
+use once_cell::sync::Lazy;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::process;
+use tracing::{info, warn};
+
+/// Written to by atrace as well, picked up by perfetto through the `ftrace/print` event
+const TRACE_MARKERS: [&str; 2] = [
+    "/sys/kernel/tracing/trace_marker",
+    "/sys/kernel/debug/tracing/trace_marker",
+];
+
+static MARKER: Lazy<Option<File>> = Lazy::new(|| {
+    if !ZynxConfigs::instance().atrace {
+        return None;
+    }
+
+    for path in TRACE_MARKERS {
+        if let Ok(file) = OpenOptions::new().write(true).open(path) {
+            info!("emitting trace slices to {path}");
+            return Some(file);
+        }
+    }
+
+    warn!("no trace marker available, trace slices are disabled");
+    None
+});
+
+fn write_marker(marker: &File, record: &str) {
+    // a record has to be written at once, or it gets split into several events
+    let _ = (&*marker).write_all(record.as_bytes());
+}
+
+/// A slice on the current thread in system traces, ended when dropped. Slices on the same
+/// thread must not overlap, but may nest.
+#[must_use]
+pub struct Slice(Option<&'static File>);
+
+impl Drop for Slice {
+    fn drop(&mut self) {
+        if let Some(marker) = self.0 {
+            write_marker(marker, &format!("E|{}", process::id()));
+        }
+    }
+}
+
+/// Begin a slice, `name` is only formatted if tracing is enabled, e.g. with `format_args!`.
+pub fn slice(name: impl Display) -> Slice {
+    let Some(marker) = MARKER.as_ref() else {
+        return Slice(None);
+    };
+
+    let name = name.to_string().replace(['|', '\n'], " ");
+
+    write_marker(marker, &format!("B|{}|{name}", process::id()));
+
+    Slice(Some(marker))
+}
@@ -1,2 +1,4 @@
+pub mod cache;
 pub mod cpp;
 pub mod library;
+pub mod offsets;
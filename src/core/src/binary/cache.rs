@@ -0,0 +1,235 @@
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::{debug, info};
+use zynx_misc::ext::ResultExt;
+use zynx_misc::props;
+
+pub const CACHE_DIR: &str = "/data/adb/zynx/cache";
+
+const SYMBOLS_FILE: &str = "/data/adb/zynx/cache/symbols.toml";
+
+/// Inserts are written to disk together, this long after the first of them
+const FLUSH_DELAY: Duration = Duration::from_secs(2);
+
+static INSTANCE: Lazy<SymbolCache> = Lazy::new(SymbolCache::load);
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct CacheFile {
+    fingerprint: String,
+    libraries: BTreeMap<String, LibraryEntry>,
+    /// Names of the symbols ever resolved in each library, kept when their offsets are
+    /// dropped, so that they're resolved ahead again
+    resolved: BTreeMap<String, BTreeSet<String>>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+struct FileStamp {
+    mtime_ns: u64,
+    size: u64,
+}
+
+impl FileStamp {
+    fn of(path: &str) -> Result<Self> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(Self {
+            mtime_ns: metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos() as _,
+            size: metadata.len(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LibraryEntry {
+    stamp: FileStamp,
+    symbols: BTreeMap<String, usize>,
+}
+
+/// Symbol offsets resolved by previous runs, so that system libraries don't need to be
+/// parsed again (and their `.gnu_debugdata` decompressed) on every start. Entries are
+/// keyed by library path, and dropped when the library or the build fingerprint changes,
+/// the names of their symbols are kept for [`SymbolCache::resolved`].
+pub struct SymbolCache {
+    file: Mutex<CacheFile>,
+    flush_scheduled: AtomicBool,
+    /// Held while writing to disk, by one flush at a time
+    saving: Mutex<()>,
+}
+
+impl SymbolCache {
+    pub fn instance() -> &'static Self {
+        &INSTANCE
+    }
+
+    fn fingerprint() -> String {
+        props::get("ro.build.fingerprint")
+            .map(|it| it.to_string())
+            .unwrap_or_default()
+    }
+
+    fn load() -> Self {
+        let fingerprint = Self::fingerprint();
+
+        let mut file = match fs::read_to_string(SYMBOLS_FILE) {
+            Ok(content) => toml::from_str(&content)
+                .context(format!("failed to parse {SYMBOLS_FILE}"))
+                .ok_or_warn()
+                .unwrap_or_default(),
+            Err(err) if err.kind() == ErrorKind::NotFound => CacheFile::default(),
+            Err(err) => {
+                debug!("failed to read {SYMBOLS_FILE}: {err}");
+                CacheFile::default()
+            }
+        };
+
+        // caches written before names were tracked have them in their entries only
+        for (path, entry) in &file.libraries {
+            file.resolved
+                .entry(path.clone())
+                .or_default()
+                .extend(entry.symbols.keys().cloned());
+        }
+
+        if file.fingerprint != fingerprint {
+            if !file.libraries.is_empty() {
+                info!("build fingerprint changed, dropping symbol cache");
+            }
+
+            file = CacheFile {
+                fingerprint,
+                libraries: BTreeMap::new(),
+                resolved: file.resolved,
+            };
+        }
+
+        file.libraries
+            .retain(|path, entry| FileStamp::of(path).is_ok_and(|stamp| stamp == entry.stamp));
+
+        Self {
+            file: Mutex::new(file),
+            flush_scheduled: AtomicBool::new(false),
+            saving: Mutex::new(()),
+        }
+    }
+
+    pub fn get(&self, path: &str, symbol: &str) -> Option<usize> {
+        self.file
+            .lock()
+            .libraries
+            .get(path)?
+            .symbols
+            .get(symbol)
+            .copied()
+    }
+
+    /// Names of the symbols resolved in the library at `path` by this run or previous ones.
+    pub fn resolved(&self, path: &str) -> Vec<String> {
+        self.file
+            .lock()
+            .resolved
+            .get(path)
+            .map(|symbols| symbols.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Remember the offset of `symbol` in the library at `path`. It's written to disk in the
+    /// background, along with those inserted within [`FLUSH_DELAY`], so that resolving many
+    /// symbols doesn't serialize the whole cache for each of them.
+    pub fn insert(&'static self, path: &str, symbol: &str, addr: usize) {
+        let Some(stamp) = FileStamp::of(path).ok_or_warn() else {
+            return;
+        };
+
+        let mut file = self.file.lock();
+
+        file.resolved
+            .entry(path.into())
+            .or_default()
+            .insert(symbol.into());
+
+        match file.libraries.get_mut(path) {
+            Some(entry) if entry.stamp == stamp => {
+                entry.symbols.insert(symbol.into(), addr);
+            }
+            _ => {
+                let symbols = BTreeMap::from([(symbol.into(), addr)]);
+                file.libraries
+                    .insert(path.into(), LibraryEntry { stamp, symbols });
+            }
+        }
+
+        drop(file);
+
+        self.schedule_flush();
+    }
+
+    /// Stop resolving `symbol` of the library at `path` ahead, it no longer resolves. Dropped
+    /// from disk along with the next inserts, or by the next [`SymbolCache::flush`].
+    pub fn forget(&'static self, path: &str, symbol: &str) {
+        let mut file = self.file.lock();
+
+        let Some(symbols) = file.resolved.get_mut(path) else {
+            return;
+        };
+
+        if !symbols.remove(symbol) {
+            return;
+        }
+
+        if symbols.is_empty() {
+            file.resolved.remove(path);
+        }
+
+        drop(file);
+
+        self.schedule_flush();
+    }
+
+    fn schedule_flush(&'static self) {
+        if !self.flush_scheduled.swap(true, Ordering::AcqRel) {
+            thread::spawn(move || {
+                thread::sleep(FLUSH_DELAY);
+                self.flush();
+            });
+        }
+    }
+
+    /// Write what was inserted or forgotten since the last flush to disk, if anything.
+    pub fn flush(&self) {
+        let _saving = self.saving.lock();
+
+        // cleared before taking the snapshot, so that later inserts schedule another flush
+        if !self.flush_scheduled.swap(false, Ordering::AcqRel) {
+            return;
+        }
+
+        let save = || -> Result<()> {
+            // serialized under the lock, written without holding it
+            let content = toml::to_string(&*self.file.lock())?;
+            Self::save(&content)
+        };
+
+        save().log_if_error();
+    }
+
+    fn save(content: &str) -> Result<()> {
+        let path = Path::new(SYMBOLS_FILE);
+        let temp = path.with_extension("tmp");
+
+        fs::create_dir_all(CACHE_DIR)?;
+        fs::write(&temp, content)?;
+        fs::rename(&temp, path)?;
+
+        Ok(())
+    }
+}
@@ -1,21 +1,32 @@
 use anyhow::Result;
 use cpp_demangle::{DemangleOptions, DemangleWrite, Symbol};
-use log::debug;
 use std::fmt;
+use tracing::debug;
 
+/// Collects the param types of a demangled function symbol.
 #[derive(Default)]
-pub struct ArgCounter(usize);
+pub struct ArgCounter {
+    params: Vec<String>,
+    current: Option<String>,
+}
 
 impl ArgCounter {
     fn new() -> Self {
         Self::default()
     }
 
-    fn count(&self) -> usize {
-        self.0 + 1
+    fn finish(mut self) -> Vec<String> {
+        if let Some(param) = self.current.take()
+            && !param.trim().is_empty()
+        {
+            self.params.push(param.trim().into());
+        }
+
+        self.params
     }
 
-    pub fn count_args_for_symbol(symbol_name: &str) -> Result<usize> {
+    /// Demangled types of the params of `symbol_name`, e.g. `["_JNIEnv*", "unsigned int"]`.
+    pub fn param_types_for_symbol(symbol_name: &str) -> Result<Vec<String>> {
         let sym = Symbol::new(symbol_name)?;
         let options = DemangleOptions::default();
 
@@ -24,7 +35,7 @@ impl ArgCounter {
         let mut counter = Self::new();
         sym.structured_demangle(&mut counter, &options)?;
 
-        Ok(counter.count())
+        Ok(counter.finish())
     }
 }
 
@@ -33,9 +44,27 @@ impl DemangleWrite for ArgCounter {
         // e.g. (anonymous namespace)::SpecializeCommon(_JNIEnv*, unsigned int, unsigned int, _jintArray*, int, _jobjectArray*, long, long, int, _jstring*, _jstring*, bool, bool, _jstring*, _jstring*, bool, _jobjectArray*, _jobjectArray*, bool, bool)
 
         match token.trim() {
-            "(" => self.0 = 0,
-            "," => self.0 += 1,
-            _ => (),
+            "(" => {
+                self.params.clear();
+                self.current = Some(String::new());
+            }
+            "," => {
+                if let Some(param) = self.current.replace(String::new()) {
+                    self.params.push(param.trim().into());
+                }
+            }
+            ")" => {
+                if let Some(param) = self.current.take()
+                    && !param.trim().is_empty()
+                {
+                    self.params.push(param.trim().into());
+                }
+            }
+            _ => {
+                if let Some(param) = &mut self.current {
+                    param.push_str(token);
+                }
+            }
         }
 
         Ok(())
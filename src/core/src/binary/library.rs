@@ -1,10 +1,43 @@
+use crate::binary::cache::SymbolCache;
+use crate::binary::offsets;
 use anyhow::Result;
 use once_cell::sync::Lazy;
 use once_map::OnceMap;
 use r3solvr::{CachedResolver, Symbol, SymbolResolver};
+use std::collections::BTreeSet;
+use std::thread;
+use std::time::Instant;
+use tracing::debug;
+use zynx_misc::ext::ResultExt;
 
 static SYSTEM_LIBRARY_RESOLVER: Lazy<SystemLibraryResolver> = Lazy::new(SystemLibraryResolver::new);
 
+/// Symbols the injector always calls in remote processes, resolved ahead by
+/// [`SystemLibraryResolver::prefetch`] along with those resolved by previous runs
+const REMOTE_SYMBOLS: &[(&str, &[&str])] = &[
+    (
+        "libc",
+        &[
+            "__close",
+            "__errno",
+            "getpid",
+            "madvise",
+            "mmap",
+            "mprotect",
+            "munmap",
+            "prctl",
+            "recvmsg",
+            "socketpair",
+            "syscall",
+        ],
+    ),
+    ("libdl", &["android_dlopen_ext", "dlerror", "dlsym"]),
+];
+
+fn library_path(library_name: &str) -> String {
+    format!("/system/lib64/{library_name}.so")
+}
+
 pub struct SystemLibraryResolver {
     resolvers: OnceMap<String, CachedResolver>,
 }
@@ -16,12 +49,67 @@ impl SystemLibraryResolver {
         }
     }
 
-    pub fn resolve(&self, library_name: &str, symbol_name: &str) -> Result<Symbol> {
-        Ok(self.resolvers.map_try_insert(
+    /// Resolve the offset of a symbol in a system library. User-provided offsets
+    /// take precedence over the symbol cache, which takes precedence over the symbol tables.
+    pub fn resolve(&self, library_name: &str, symbol_name: &str) -> Result<usize> {
+        if let Some(addr) = offsets::current().and_then(|it| it.symbol(library_name, symbol_name)) {
+            return Ok(addr);
+        }
+
+        let path = library_path(library_name);
+        let cache = SymbolCache::instance();
+
+        if let Some(addr) = cache.get(&path, symbol_name) {
+            return Ok(addr);
+        }
+
+        let lookup = self.resolvers.map_try_insert(
             library_name.into(),
-            |name| CachedResolver::from_file(format!("/system/lib64/{name}.so")),
+            |_| CachedResolver::from_file(&path),
             |_, v| v.lookup_symbol(symbol_name),
-        )??)
+        )?;
+
+        // the library was parsed, the symbol is gone from it
+        let symbol: Symbol = lookup.inspect_err(|_| cache.forget(&path, symbol_name))?;
+
+        cache.insert(&path, symbol_name, symbol.addr);
+
+        Ok(symbol.addr)
+    }
+
+    /// Resolve ahead the symbols of `libraries` the injector calls, or resolved by this run or
+    /// previous ones, one thread per library, so that they aren't parsed on the fork path, e.g.
+    /// on the first start or after an update of the system dropped their cached offsets.
+    /// Libraries are still parsed lazily, only if some of their symbols are neither provided
+    /// nor cached.
+    pub fn prefetch(&self, libraries: &[&str]) {
+        let start = Instant::now();
+
+        thread::scope(|scope| {
+            for library in libraries {
+                let mut symbols: BTreeSet<_> = SymbolCache::instance()
+                    .resolved(&library_path(library))
+                    .into_iter()
+                    .collect();
+
+                if let Some((_, remote)) = REMOTE_SYMBOLS.iter().find(|(name, _)| name == library) {
+                    symbols.extend(remote.iter().map(|symbol| symbol.to_string()));
+                }
+
+                scope.spawn(move || {
+                    for symbol in symbols {
+                        self.resolve(library, &symbol).log_if_error();
+                    }
+                });
+            }
+        });
+
+        SymbolCache::instance().flush();
+
+        debug!(
+            "prefetched symbols of {libraries:?} in {:?}",
+            start.elapsed()
+        );
     }
 
     pub fn instance() -> &'static Self {
@@ -0,0 +1,97 @@
+use anyhow::{Context, Result, bail};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use tracing::{info, warn};
+use zynx_misc::props;
+
+/// User-provided offsets for ROMs where automatic symbol resolution fails.
+///
+/// Entries are keyed by `ro.build.fingerprint`, and only the entry of the running ROM is
+/// used. Whatever it provides takes precedence over automatic resolution, anything it
+/// doesn't provide is still resolved automatically.
+///
+/// ```toml
+/// [[rom]]
+/// fingerprint = "vendor/device/device:15/AP3A.240905.015/12345:user/release-keys"
+///
+/// [rom.specialize_common]
+/// version = "V"      # a known release, or the mangled `symbol` instead, forward-compat mode if neither is
+/// addr = 0x2a5f10    # offset in libandroid_runtime.so
+/// args_count = 21    # defaults to the arg count of the signature, required without it
+///
+/// [rom.symbols.libc] # symbol offsets, keyed by library name
+/// mmap = 0x5d3c0
+/// ```
+pub const OFFSETS_FILE: &str = "/data/adb/zynx/offsets.toml";
+
+static OFFSETS: Lazy<Option<RomOffsets>> = Lazy::new(|| {
+    load()
+        .inspect_err(|err| warn!("ignoring {OFFSETS_FILE}: {err:?}"))
+        .ok()
+        .flatten()
+});
+
+#[derive(Deserialize)]
+struct OffsetsFile {
+    #[serde(default)]
+    rom: Vec<RomOffsets>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RomOffsets {
+    pub fingerprint: String,
+    pub specialize_common: Option<SpecializeCommonOffsets>,
+    #[serde(default)]
+    pub symbols: HashMap<String, HashMap<String, usize>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpecializeCommonOffsets {
+    pub version: Option<String>,
+    pub symbol: Option<String>,
+    pub addr: usize,
+    pub args_count: Option<usize>,
+}
+
+impl RomOffsets {
+    pub fn symbol(&self, library: &str, symbol: &str) -> Option<usize> {
+        self.symbols.get(library)?.get(symbol).copied()
+    }
+}
+
+fn load() -> Result<Option<RomOffsets>> {
+    let content = match fs::read_to_string(OFFSETS_FILE) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let file: OffsetsFile = toml::from_str(&content).context("failed to parse offsets")?;
+    let fingerprint = props::get("ro.build.fingerprint").context("unknown build fingerprint")?;
+
+    let mut matches = file
+        .rom
+        .into_iter()
+        .filter(|rom| rom.fingerprint == *fingerprint);
+
+    let Some(offsets) = matches.next() else {
+        info!("no offsets provided for `{}`", &*fingerprint);
+        return Ok(None);
+    };
+
+    if matches.next().is_some() {
+        bail!("duplicate entries for `{}`", &*fingerprint);
+    }
+
+    info!("using user-provided offsets: {offsets:?}");
+
+    Ok(Some(offsets))
+}
+
+/// Offsets provided for the running ROM, if any.
+pub fn current() -> Option<&'static RomOffsets> {
+    OFFSETS.as_ref()
+}
@@ -1,4 +1,6 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use zynx_ebpf_shared::ObserveOnly;
 
 #[derive(Parser)]
 #[command(about = "Zynx - an eBPF-based Android process injection framework", version, long_version = concat!(env!("CARGO_PKG_VERSION"), " (commit ", env!("GIT_COMMIT_HASH"), ")"))]
@@ -14,11 +16,129 @@ pub struct Cli {
 pub enum Command {
     /// Run as daemon (for KernelSU/Magisk module)
     Daemon,
+    /// Print metrics of the running daemon
+    Metrics,
+    /// Print health of the running daemon's components, e.g. managed filter services
+    Status,
+    /// Print the recent logs the running daemon keeps in memory
+    Logs,
+    /// Print recent injection events of the running daemon
+    Events {
+        /// Only show events containing this text (e.g. a package name or `uid=10123`)
+        filter: Option<String>,
+    },
+    /// Show modules' crash counters and quarantine status
+    Quarantine {
+        /// Release a quarantined module, e.g. `liteloader:com.example.app-hook`
+        #[arg(long)]
+        release: Option<String>,
+    },
+    /// List packages forced debuggable, persisting across reboots
+    Debuggable {
+        /// Force a package debuggable, `*` and `?` work as wildcards, e.g. `com.example.*`
+        #[arg(long)]
+        add: Option<String>,
+        /// Stop forcing a package debuggable, as added
+        #[arg(long)]
+        remove: Option<String>,
+    },
+    /// Print sepolicy rules allowing the SELinux denials in the kernel log
+    Sepolicy {
+        /// Only consider denials of this process, e.g. an app that failed to be injected
+        #[arg(long)]
+        pid: Option<i32>,
+        /// Patch the live policy with the rules through the root manager
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Move LiteLoader libraries from the v1 naming convention to v2 groups
+    MigrateLiteloader {
+        /// Only print what would be moved
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Stage a new zynx binary, verified by its .sig file, for the next daemon start
+    Update {
+        /// The new binary, with its sidecar file next to it
+        binary: PathBuf,
+        /// Restart the running daemon to apply it now instead of on next boot
+        #[arg(long)]
+        restart: bool,
+    },
+    /// Create a native LiteLoader module crate from template
+    NewModule {
+        /// Name of the module, also used for the crate and the library
+        name: String,
+        /// Directory to create the module in, defaults to the current directory
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Report what zynx left behind in an app audited with --cfg-audit, failing if there's anything
+    Audit {
+        /// PID of the audited app
+        pid: i32,
+    },
+    /// Check the kernel, permissions, SELinux, symbols and zygote state the daemon depends on
+    Doctor,
+    /// Feed the policy checks of a trace recorded with --cfg-record-trace through the providers
+    /// again, reporting those deciding otherwise (development only)
+    Replay {
+        /// The recorded trace
+        trace: PathBuf,
+        /// Read lists, modules and libraries below this directory rather than `/`, to replay
+        /// off the device against a copy of `/data/adb`
+        #[arg(long)]
+        root: Option<PathBuf>,
+    },
     /// Attach to a running zygote process
     AttachZygote {
         /// PID of the zygote64 process
         pid: i32,
     },
+    /// Host the companion of a zygisk module, spawned by the daemon
+    #[cfg(feature = "zygisk")]
+    #[command(name = "zygisk-companion", hide = true)]
+    ZygiskCompanion {
+        /// The module library exporting `zygisk_companion_entry`
+        library: PathBuf,
+    },
+    /// Interactive shell to inspect and call into a process (development only)
+    #[cfg(feature = "debug-shell")]
+    DebugShell {
+        /// PID of the target process
+        pid: i32,
+    },
+    /// Run a fake zygote through fork detection, breakpoint and trampoline (development only)
+    #[cfg(feature = "self-test")]
+    SelfTest {
+        /// Native module to call the entry of in the fake app, e.g. one made by `new-module`
+        #[arg(long)]
+        module: Option<PathBuf>,
+        /// Passed to the module as the `data` of its manifest
+        #[arg(long, requires = "module")]
+        data: Option<String>,
+    },
+    /// Fork on demand like a zygote, spawned by `self-test`
+    #[cfg(feature = "self-test")]
+    #[command(name = "fake-zygote", hide = true)]
+    FakeZygote {
+        #[arg(long)]
+        module: Option<PathBuf>,
+        #[arg(long, requires = "module")]
+        data: Option<String>,
+    },
+    /// Benchmark injection primitives against a synthetic target (development only)
+    #[cfg(feature = "bench")]
+    Bench {
+        /// Only run benchmarks matching this regex
+        filter: Option<String>,
+        /// Save results as a named baseline
+        #[arg(long, conflicts_with = "baseline")]
+        save_baseline: Option<String>,
+        /// Compare results against a saved baseline
+        #[arg(long)]
+        baseline: Option<String>,
+    },
 }
 
 #[derive(Args, Clone)]
@@ -35,6 +155,270 @@ pub struct CfgOptions {
 
     #[clap(long, global = true, help = "Enable liteloader")]
     pub cfg_enable_liteloader: bool,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Capture bridge logs through shared memory instead of logcat (diagnostics)"
+    )]
+    pub cfg_capture_bridge_logs: bool,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Strict mode: only inject into apps listed in /data/adb/zynx/allowlist"
+    )]
+    pub cfg_strict_mode: bool,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Refuse to inject libraries without a valid .sha256 or .sig file"
+    )]
+    pub cfg_verify_libraries: bool,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Inject libraries in /data/adb/zynx/services into native services"
+    )]
+    pub cfg_enable_services: bool,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 0x1000,
+        help = "Size in bytes of the eBPF message channel, raise it if messages get dropped"
+    )]
+    pub cfg_channel_size: u32,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 1,
+        help = "Depth below init of the processes matched as services, raise it for services started by a launcher wrapper"
+    )]
+    pub cfg_service_depth: u32,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 0x100,
+        help = "Capacity of the eBPF maps holding target paths and names"
+    )]
+    pub cfg_max_targets: u32,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 0x1000,
+        help = "Capacity of the eBPF maps tracking services and zygote forks, raise it if inserts fail on busy devices"
+    )]
+    pub cfg_max_tasks: u32,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 64,
+        help = "Maximum number of libraries injected into a process, 0 for no limit"
+    )]
+    pub cfg_max_libraries: usize,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 256 << 20,
+        help = "Maximum total size in bytes of libraries injected into a process, 0 for no limit"
+    )]
+    pub cfg_max_library_bytes: u64,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Inject native LiteLoader libraries into apps already running on daemon start"
+    )]
+    pub cfg_late_injection: bool,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Emit atrace slices of event handling, policy checks and injections (diagnostics)"
+    )]
+    pub cfg_atrace: bool,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Map the trampoline writable, then executable, instead of both at once (W^X)"
+    )]
+    pub cfg_wx_trampoline: bool,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Break on SpecializeCommon with a hardware breakpoint instead of patching its code, when the CPU has one"
+    )]
+    pub cfg_hw_breakpoint: bool,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Apply sepolicy rules for SELinux denials of failed injections through the root manager"
+    )]
+    pub cfg_apply_sepolicy: bool,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 0,
+        help = "Seconds to reuse LiteLoader and Zygisk decisions for the same app, 0 to check on every fork"
+    )]
+    pub cfg_decision_cache_ttl: u64,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 1000,
+        help = "Milliseconds policy providers may take to decide for a forked process, pending ones are treated as Deny afterwards, 0 for no limit"
+    )]
+    pub cfg_check_budget_ms: u64,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 64,
+        help = "Forked processes traced at the same time, others wait in a queue"
+    )]
+    pub cfg_max_tracers: usize,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 500,
+        help = "Milliseconds a forked process may wait in the queue, it starts without injection afterwards"
+    )]
+    pub cfg_queue_timeout_ms: u64,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 3,
+        help = "Consecutive failures before a LiteLoader library or Zygisk module is quarantined, 0 to never quarantine"
+    )]
+    pub cfg_quarantine_threshold: u32,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Give memfds and mappings left in injected processes plausible names, changing on every boot"
+    )]
+    pub cfg_camouflage_names: bool,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Have the bridge audit what zynx left in each app it's loaded into, see `zynx audit`"
+    )]
+    pub cfg_audit: bool,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Write JSON logs with per-injection spans and timings into /data/adb/zynx/logs"
+    )]
+    pub cfg_json_logs: bool,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 1 << 20,
+        help = "Size in bytes of /data/adb/zynx/logs/zynx.log before it's rotated and compressed, 0 to keep logs in memory only"
+    )]
+    pub cfg_log_file_size: u64,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = MonitorBackend::Auto,
+        help = "How to watch for forks and execs: eBPF, or the less precise netlink proc connector"
+    )]
+    pub cfg_monitor: MonitorBackend,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Don't stop zygote forks of apps no policy provider targets, when every provider can tell its targets"
+    )]
+    pub cfg_uid_filter: bool,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Stop forked processes at SpecializeCommon with an eBPF uprobe, instead of patching a breakpoint into them"
+    )]
+    pub cfg_specialize_uprobe: bool,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = InjectorBackend::Ptrace,
+        help = "How forked processes are injected: through ptrace, or by patching their memory without attaching to them (mem-inject builds only)"
+    )]
+    pub cfg_injector: InjectorBackend,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = ObserveTargets::None,
+        help = "Only report matched processes instead of stopping and injecting them"
+    )]
+    pub cfg_observe_only: ObserveTargets,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Record monitor messages, policy checks and injection outcomes into this file, for `zynx replay` (diagnostics)"
+    )]
+    pub cfg_record_trace: Option<PathBuf>,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum MonitorBackend {
+    /// eBPF, falling back to the proc connector if the kernel lacks the ring buffer or tracepoints,
+    /// or blocks loading
+    Auto,
+    Ebpf,
+    ProcConnector,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum InjectorBackend {
+    Ptrace,
+    /// `/proc/<pid>/mem` and a loader connecting to the daemon, embryos stopped by the uprobe
+    /// of `SpecializeCommon` only
+    Mem,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum ObserveTargets {
+    None,
+    /// Native services matched by path
+    Services,
+    /// Apps, and other processes forked from zygotes
+    Apps,
+    All,
+}
+
+impl From<ObserveTargets> for ObserveOnly {
+    fn from(targets: ObserveTargets) -> Self {
+        ObserveOnly {
+            services: matches!(targets, ObserveTargets::Services | ObserveTargets::All),
+            embryos: matches!(targets, ObserveTargets::Apps | ObserveTargets::All),
+        }
+    }
 }
 
 impl Cli {
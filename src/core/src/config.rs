@@ -1,5 +1,6 @@
-use crate::cli::CfgOptions;
-use anyhow::{Result, anyhow};
+use crate::cli::{CfgOptions, InjectorBackend, MonitorBackend, ObserveTargets};
+use anyhow::{Result, anyhow, bail};
+use std::path::PathBuf;
 use std::sync::OnceLock;
 
 static INSTANCE: OnceLock<ZynxConfigs> = OnceLock::new();
@@ -9,14 +10,81 @@ pub struct ZynxConfigs {
     pub enable_debugger: bool,
     pub enable_zygisk: bool,
     pub enable_liteloader: bool,
+    pub capture_bridge_logs: bool,
+    pub strict_mode: bool,
+    pub verify_libraries: bool,
+    pub enable_services: bool,
+    pub channel_size: u32,
+    pub service_depth: u32,
+    pub max_targets: u32,
+    pub max_tasks: u32,
+    pub max_libraries: usize,
+    pub max_library_bytes: u64,
+    pub late_injection: bool,
+    pub atrace: bool,
+    pub wx_trampoline: bool,
+    pub hw_breakpoint: bool,
+    pub apply_sepolicy: bool,
+    pub decision_cache_ttl: u64,
+    pub check_budget_ms: u64,
+    pub max_tracers: usize,
+    pub queue_timeout_ms: u64,
+    pub quarantine_threshold: u32,
+    pub camouflage_names: bool,
+    pub audit: bool,
+    pub json_logs: bool,
+    pub log_file_size: u64,
+    pub monitor: MonitorBackend,
+    pub uid_filter: bool,
+    pub specialize_uprobe: bool,
+    pub injector: InjectorBackend,
+    pub observe_only: ObserveTargets,
+    pub record_trace: Option<PathBuf>,
 }
 
 impl ZynxConfigs {
     pub fn init(config: &CfgOptions) -> Result<()> {
+        let mem_injector = matches!(config.cfg_injector, InjectorBackend::Mem);
+
+        if mem_injector && !cfg!(feature = "mem-inject") {
+            bail!("`--cfg-injector mem` needs a build with the `mem-inject` feature");
+        }
+
         let instance = Self {
             enable_debugger: config.cfg_enable_debugger,
             enable_zygisk: config.cfg_enable_zygisk,
             enable_liteloader: config.cfg_enable_liteloader,
+            capture_bridge_logs: config.cfg_capture_bridge_logs,
+            strict_mode: config.cfg_strict_mode,
+            verify_libraries: config.cfg_verify_libraries,
+            enable_services: config.cfg_enable_services,
+            channel_size: config.cfg_channel_size,
+            service_depth: config.cfg_service_depth,
+            max_targets: config.cfg_max_targets,
+            max_tasks: config.cfg_max_tasks,
+            max_libraries: config.cfg_max_libraries,
+            max_library_bytes: config.cfg_max_library_bytes,
+            late_injection: config.cfg_late_injection,
+            atrace: config.cfg_atrace,
+            wx_trampoline: config.cfg_wx_trampoline,
+            hw_breakpoint: config.cfg_hw_breakpoint,
+            apply_sepolicy: config.cfg_apply_sepolicy,
+            decision_cache_ttl: config.cfg_decision_cache_ttl,
+            check_budget_ms: config.cfg_check_budget_ms,
+            max_tracers: config.cfg_max_tracers,
+            queue_timeout_ms: config.cfg_queue_timeout_ms,
+            quarantine_threshold: config.cfg_quarantine_threshold,
+            camouflage_names: config.cfg_camouflage_names,
+            audit: config.cfg_audit,
+            json_logs: config.cfg_json_logs,
+            log_file_size: config.cfg_log_file_size,
+            monitor: config.cfg_monitor,
+            uid_filter: config.cfg_uid_filter,
+            // the mem injector only handles embryos stopped by the uprobe
+            specialize_uprobe: config.cfg_specialize_uprobe || mem_injector,
+            injector: config.cfg_injector,
+            observe_only: config.cfg_observe_only,
+            record_trace: config.cfg_record_trace.clone(),
         };
 
         INSTANCE
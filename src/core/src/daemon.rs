@@ -1,6 +1,8 @@
+use crate::android::root;
+use crate::android::root::RootManager;
+use crate::update;
 use anyhow::{Context, Result};
 use daemonize::Daemonize;
-use log::info;
 use nix::sys::signal;
 use nix::sys::signal::Signal;
 use nix::unistd::Pid;
@@ -14,9 +16,10 @@ use tokio::signal::unix;
 use tokio::signal::unix::SignalKind;
 use tokio::sync::oneshot;
 use tokio::{task, time};
+use tracing::{info, warn};
 use zynx_misc::ext::ResultExt;
 
-const ENV_LAUNCHER_PID: &str = "LAUNCHER_PID";
+pub const ENV_LAUNCHER_PID: &str = "LAUNCHER_PID";
 
 static NOTIFY_ONCE: Once = Once::new();
 
@@ -42,7 +45,16 @@ async fn launch_daemon_async() -> Result<()> {
     let start = Instant::now();
     let args: Vec<String> = env::args().skip(1).filter(|arg| arg != "daemon").collect();
 
-    let _ = Command::new(env::current_exe()?)
+    // resolved before the update replaces it, `/proc/self/exe` gets a ` (deleted)` suffix
+    let exe = env::current_exe()?;
+
+    match update::apply_staged(&exe) {
+        Ok(true) => info!("launching updated daemon"),
+        Ok(false) => {}
+        Err(err) => warn!("failed to apply staged update: {err:?}"),
+    }
+
+    let _ = Command::new(exe)
         .args(&args)
         .env(ENV_LAUNCHER_PID, format!("{}", process::id()))
         .spawn()?;
@@ -67,11 +79,23 @@ pub fn daemonize_if_needed() -> Result<()> {
         return Ok(());
     }
 
-    let exe = env::current_exe()?; // e.g. /data/adb/modules/zynx/bin/zynx
-    let mut dir = PathBuf::from(exe.parent().unwrap());
+    let dir = match root::module_dir() {
+        Some(dir) => dir.to_path_buf(),
+        None => {
+            let exe = env::current_exe()?; // e.g. /data/adb/modules/zynx/bin/zynx
+            let mut dir = PathBuf::from(exe.parent().unwrap());
+
+            while !dir.join("module.prop").exists() {
+                dir = PathBuf::from(dir.parent().context("module.prop not found")?)
+            }
+
+            dir
+        }
+    };
 
-    while !dir.join("module.prop").exists() {
-        dir = PathBuf::from(dir.parent().context("module.prop not found")?)
+    match RootManager::instance() {
+        Some(manager) => info!("running as a {manager} module in {}", dir.display()),
+        None => warn!("no root manager recognized, running in {}", dir.display()),
     }
 
     Daemonize::new().working_directory(dir).start()?;
@@ -0,0 +1,215 @@
+use anyhow::{Context, Result};
+use nix::unistd::{Pid, Uid};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fmt, fs};
+use tokio::time;
+use tracing::debug;
+use zynx_bridge_shared::zygote::{ProviderLoad, ProviderType, SessionId};
+use zynx_misc::ext::ResultExt;
+
+pub const EVENTS_FILE: &str = "/data/adb/zynx/events";
+
+const EVENTS_CAPACITY: usize = 512;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+static INSTANCE: Lazy<EventLog> = Lazy::new(EventLog::default);
+
+#[derive(Debug)]
+pub enum InjectionOutcome {
+    Injected,
+    /// All policy providers denied the process
+    Denied,
+    /// The process died halfway, e.g. killed by the low memory killer
+    Vanished,
+    /// The seccomp policy of the process rejected a remote call
+    Seccomp(String),
+    Failed(String),
+}
+
+impl Display for InjectionOutcome {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            InjectionOutcome::Injected => fmt.write_str("injected"),
+            InjectionOutcome::Denied => fmt.write_str("denied"),
+            InjectionOutcome::Vanished => fmt.write_str("vanished"),
+            InjectionOutcome::Seccomp(reason) => write!(fmt, "seccomp({reason})"),
+            InjectionOutcome::Failed(reason) => write!(fmt, "failed({reason})"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InjectionEvent {
+    pub time: SystemTime,
+    pub pid: Pid,
+    /// Also found in core and bridge logs of the injection
+    pub session: SessionId,
+    pub uid: Uid,
+    pub packages: Vec<String>,
+    pub providers: Vec<ProviderType>,
+    pub outcome: InjectionOutcome,
+    pub duration: Duration,
+    /// Modules dropped because a per-process cap was hit
+    pub truncated: Vec<String>,
+    /// Results of the provider handlers, as reported by the bridge
+    pub loads: Vec<ProviderLoad>,
+}
+
+impl Display for InjectionEvent {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        let time = self
+            .time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        write!(
+            fmt,
+            "{time} pid={} session={} uid={} packages=[{}] providers={:?} outcome={} duration={:.2?}",
+            self.pid,
+            self.session,
+            self.uid,
+            self.packages.join(","),
+            self.providers,
+            self.outcome.to_string().replace('\n', " "),
+            self.duration
+        )?;
+
+        if !self.truncated.is_empty() {
+            write!(fmt, " truncated=[{}]", self.truncated.join(","))?;
+        }
+
+        if !self.loads.is_empty() {
+            let loads: Vec<_> = self
+                .loads
+                .iter()
+                .map(|load| {
+                    let status = if load.errors.is_empty() {
+                        "ok"
+                    } else {
+                        "failed"
+                    };
+                    let duration = Duration::from_micros(load.duration_us);
+
+                    format!("{:?}:{status}:{duration:.2?}", load.provider)
+                })
+                .collect();
+
+            write!(fmt, " loads=[{}]", loads.join(","))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Ring buffer of recent injection events, periodically persisted to [`EVENTS_FILE`].
+#[derive(Default)]
+pub struct EventLog {
+    events: Mutex<VecDeque<InjectionEvent>>,
+    /// Load reports that arrived before the event of their process got recorded
+    pending_loads: Mutex<HashMap<Pid, Vec<ProviderLoad>>>,
+    dirty: AtomicBool,
+}
+
+impl EventLog {
+    pub fn instance() -> &'static Self {
+        &INSTANCE
+    }
+
+    pub fn record(&self, mut event: InjectionEvent) {
+        if let Some(loads) = self.pending_loads.lock().remove(&event.pid) {
+            event.loads = loads;
+        }
+
+        debug!("injection event: {event}");
+
+        let mut events = self.events.lock();
+
+        if events.len() >= EVENTS_CAPACITY {
+            events.pop_front();
+        }
+
+        events.push_back(event);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Attach the load report of the bridge to the latest event of `pid`.
+    pub fn on_load_report(&self, pid: Pid, loads: Vec<ProviderLoad>) {
+        let mut events = self.events.lock();
+
+        match events.iter_mut().rev().find(|event| event.pid == pid) {
+            Some(event) => {
+                event.loads = loads;
+                self.dirty.store(true, Ordering::Relaxed);
+            }
+            None => {
+                let mut pending = self.pending_loads.lock();
+
+                // not all processes get an event, e.g. native services
+                if pending.len() >= EVENTS_CAPACITY {
+                    pending.clear();
+                }
+
+                pending.insert(pid, loads);
+            }
+        }
+    }
+
+    fn flush(&self) -> Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let content: String = self
+            .events
+            .lock()
+            .iter()
+            .map(|event| format!("{event}\n"))
+            .collect();
+
+        let path = Path::new(EVENTS_FILE);
+        let temp = path.with_extension("tmp");
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&temp, content)?;
+        fs::rename(&temp, path)?;
+
+        Ok(())
+    }
+
+    /// Spawn the background task persisting events to [`EVENTS_FILE`].
+    pub fn spawn_writer() {
+        tokio::spawn(async {
+            let mut interval = time::interval(FLUSH_INTERVAL);
+
+            loop {
+                interval.tick().await;
+                Self::instance().flush().log_if_error();
+            }
+        });
+    }
+}
+
+/// Print persisted events, optionally only those mentioning `filter` (a package name, uid, ...).
+pub fn print_events(filter: Option<&str>) -> Result<()> {
+    let content = fs::read_to_string(EVENTS_FILE).context(format!(
+        "failed to read {EVENTS_FILE}, is the daemon running?"
+    ))?;
+
+    for line in content.lines() {
+        if filter.is_none_or(|filter| line.contains(filter)) {
+            println!("{line}");
+        }
+    }
+
+    Ok(())
+}
@@ -1,72 +1,192 @@
 use crate::android::packages::PackageInfoService;
-use crate::injector::app::policy::PolicyProviderManager;
+use crate::android::proc_visibility::ProcVisibility;
+use crate::binary::library::SystemLibraryResolver;
+use crate::config::ZynxConfigs;
+use crate::events::EventLog;
+use crate::metrics::Metrics;
 use crate::monitor::{Message, Monitor};
-use crate::{daemon, monitor};
+use crate::quarantine::Quarantine;
+use crate::status::Status;
+use crate::{atrace, daemon, logging, monitor};
 use anyhow::{Result, bail};
-use app::zygote::ZYGOTE_NAME;
 use app::zygote::ZygoteTracer;
-use log::{error, info};
+use app::zygote::{ZYGOTE_NAME, ZYGOTE_NAMES};
+use nix::sys::signal;
+use nix::sys::signal::Signal;
 use nix::unistd;
 use nix::unistd::{Pid, SysconfVar};
 use once_cell::sync::Lazy;
 use procfs::process::Process;
+use service::ServiceInjector;
+use service::policy::NativePolicyProvider;
+use std::time::Duration;
+use tokio::{task, time};
+use tracing::{debug_span, error, info};
+use zynx_misc::ext::ResultExt;
 
 mod app;
+mod arch;
 mod asm;
+mod audit;
+#[cfg(feature = "bench")]
+mod bench;
 mod bridge;
+mod camouflage;
+mod doctor;
+mod late;
 mod misc;
 mod ptrace;
+#[cfg(feature = "self-test")]
+mod self_test;
+mod service;
+#[cfg(feature = "debug-shell")]
+mod shell;
+mod shutdown;
+mod trace;
+
+pub use app::policy::caps::Cap;
+pub use app::policy::debugger::manage_debuggable;
+pub use app::policy::decision_cache::DecisionCache;
+pub use app::policy::liteloader::migrate_layout;
+#[cfg(feature = "zygisk")]
+pub use app::policy::zygisk_companion;
+pub use app::policy::{PolicyProviderManager, ProviderKind};
+pub use app::preflight;
+pub use app::{SC_CONFIG, SC_LIBRARY_PATH};
+pub use audit::audit;
+#[cfg(feature = "bench")]
+pub use bench::{Baseline, bench};
+pub use doctor::doctor;
+#[cfg(feature = "self-test")]
+pub use self_test::{TestModule, fake_zygote, self_test};
+#[cfg(feature = "debug-shell")]
+pub use shell::debug_shell;
+pub use shutdown::Shutdown;
+pub use trace::replay;
+
+/// Libraries the injector calls into remote processes, prefetched at start
+const PREFETCH_LIBRARIES: [&str; 2] = ["libc", "libdl"];
+
+/// While shutting down, the event channel is considered drained after this long without events
+const PENDING_EVENT_TIMEOUT: Duration = Duration::from_millis(50);
 
 pub static PAGE_SIZE: Lazy<usize> =
     Lazy::new(|| unistd::sysconf(SysconfVar::PAGE_SIZE).unwrap().unwrap() as _);
 
 fn handle_event(event: &Message) -> Result<()> {
+    let _slice = atrace::slice(format_args!("zynx: {event:?}"));
+    let _span = debug_span!("event", ?event).entered();
+
+    trace::on_message(event);
+
+    let observe_only = Monitor::instance().observe_only();
+
     match event {
-        Message::PathMatches(pid, path) => {
-            // Todo:
+        Message::PathMatches(pid, path) if observe_only.services => {
+            ServiceInjector::on_exec_observed(*pid, path);
             Ok(())
         }
+        Message::PathMatches(pid, path) => ServiceInjector::on_exec(*pid, path),
         Message::NameMatches(pid, name) => {
-            if name == ZYGOTE_NAME {
-                ptrace::spin_wait(*pid)?;
-
-                let args = Process::new(pid.as_raw())?.cmdline()?;
-
-                if args.iter().any(|arg| arg == "--start-system-server") {
-                    return ZygoteTracer::create(*pid);
-                }
-
-                info!("found `{ZYGOTE_NAME}` without system server argument: {pid} -> {args:?}")
+            if ZYGOTE_NAMES.contains(&name.as_str()) {
+                return ZygoteTracer::create(*pid, name);
             }
 
             // Todo:
             Ok(())
         }
-        Message::ZygoteFork(pid) => ZygoteTracer::on_fork(*pid),
-        Message::ZygoteCrashed(_pid) => ZygoteTracer::reset(),
+        Message::ZygoteFork(zygote, pid) if observe_only.embryos => {
+            ZygoteTracer::on_fork_observed(*zygote, *pid)
+        }
+        Message::ZygoteFork(zygote, pid) => ZygoteTracer::on_fork(*zygote, *pid),
+        Message::ZygoteSpecialize(zygote, pid) if observe_only.embryos => {
+            ZygoteTracer::on_fork_observed(*zygote, *pid)
+        }
+        Message::ZygoteSpecialize(zygote, pid) => ZygoteTracer::on_specialize(*zygote, *pid),
+        Message::ZygoteCrashed(pid) => ZygoteTracer::reset(*pid),
     }
 }
 
-pub async fn run() -> Result<()> {
-    let config = monitor::Config {
-        target_paths: vec![],
-        target_names: vec![ZYGOTE_NAME.into()],
+/// Resume the process stopped by eBPF for this event, instead of handling it.
+fn release_event(event: &Message) {
+    if let Some(pid) = event.stopped_pid(Monitor::instance().observe_only()) {
+        signal::kill(pid, Signal::SIGCONT).log_if_error();
+    }
+}
+
+/// Stop eBPF from stopping new processes, release the ones it stopped meanwhile and wait
+/// for tracer threads to detach.
+async fn shutdown_gracefully() {
+    let monitor = Monitor::instance();
+
+    monitor.detach_programs().log_if_error();
+
+    let release_pending = async {
+        while let Ok(Some(event)) = time::timeout(PENDING_EVENT_TIMEOUT, monitor.recv_msg()).await {
+            release_event(&event);
+        }
     };
 
+    tokio::join!(Shutdown::instance().drain(), release_pending);
+
+    info!("shutdown completed");
+}
+
+/// Start the daemon services, with the monitor matching `config`.
+async fn init(config: monitor::Config) -> Result<()> {
+    if let Some(path) = &ZynxConfigs::instance().record_trace {
+        trace::start_recording(path)?;
+    }
+
+    task::spawn_blocking(|| SystemLibraryResolver::instance().prefetch(&PREFETCH_LIBRARIES));
+
+    ProcVisibility::instance();
     PackageInfoService::init()?;
     PolicyProviderManager::init().await?;
     Monitor::init(config)?;
+    Metrics::spawn_writer();
+    EventLog::spawn_writer();
+    Status::spawn_writer();
+    logging::spawn_dump_handler()?;
+    Quarantine::instance().publish();
+    Shutdown::install()?;
+
+    Ok(())
+}
+
+pub async fn run() -> Result<()> {
+    NativePolicyProvider::init()?;
+
+    let config = monitor::Config::new(
+        NativePolicyProvider::instance().target_paths(),
+        &ZYGOTE_NAMES,
+    );
+
+    init(config).await?;
+    late::spawn_scan();
     daemon::notify_launcher_if_needed();
 
     let monitor = Monitor::instance();
+    let shutdown = Shutdown::instance();
+
+    loop {
+        tokio::select! {
+            event = monitor.recv_msg() => {
+                let Some(event) = event else {
+                    bail!("monitor exited unexpectedly");
+                };
 
-    while let Some(event) = monitor.recv_msg().await {
-        if let Err(err) = handle_event(&event) {
-            error!("error while handling event {event:?}: {err:?}");
+                if let Err(err) = handle_event(&event) {
+                    error!("error while handling event {event:?}: {err:?}");
+                }
+            }
+            _ = shutdown.requested() => break,
         }
     }
 
-    bail!("monitor exited unexpectedly");
+    shutdown_gracefully().await;
+
+    Ok(())
 }
 
 pub async fn attach_zygote(pid: i32) -> Result<()> {
@@ -79,32 +199,39 @@ pub async fn attach_zygote(pid: i32) -> Result<()> {
         bail!("process {pid} is not zygote64 (cmdline = {cmdline:?})");
     }
 
-    let config = monitor::Config {
-        target_paths: vec![],
-        target_names: vec![ZYGOTE_NAME.into()],
-    };
+    let config = monitor::Config::new(vec![], &ZYGOTE_NAMES);
 
-    PackageInfoService::init()?;
-    PolicyProviderManager::init().await?;
-    Monitor::init(config)?;
+    init(config).await?;
 
     ZygoteTracer::create_attach(pid)?;
 
     let monitor = Monitor::instance();
-
-    while let Some(event) = monitor.recv_msg().await {
-        match &event {
-            Message::ZygoteCrashed(_) => {
-                info!("zygote process exited, shutting down");
-                return Ok(());
-            }
-            _ => {
-                if let Err(err) = handle_event(&event) {
-                    error!("error while handling event {event:?}: {err:?}");
+    let shutdown = Shutdown::instance();
+
+    loop {
+        tokio::select! {
+            event = monitor.recv_msg() => {
+                let Some(event) = event else {
+                    bail!("monitor exited unexpectedly");
+                };
+
+                match &event {
+                    Message::ZygoteCrashed(crashed) if *crashed == pid => {
+                        info!("zygote process exited, shutting down");
+                        shutdown.release_all();
+                    }
+                    _ => {
+                        if let Err(err) = handle_event(&event) {
+                            error!("error while handling event {event:?}: {err:?}");
+                        }
+                    }
                 }
             }
+            _ = shutdown.requested() => break,
         }
     }
 
-    bail!("monitor exited unexpectedly");
+    shutdown_gracefully().await;
+
+    Ok(())
 }
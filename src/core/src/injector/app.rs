@@ -1,58 +1,304 @@
+use crate::binary::cache::SymbolCache;
 use crate::binary::cpp::ArgCounter;
-use anyhow::{Context, Result};
-use log::info;
+use crate::binary::offsets;
+use crate::binary::offsets::SpecializeCommonOffsets;
+use anyhow::{Context, Result, bail};
 use once_cell::sync::Lazy;
-use r3solvr::{BasicResolver, Query, Section, Symbol, SymbolResolver};
-use strum::IntoEnumIterator;
-use zynx_bridge_shared::zygote::SpecializeVersion;
+use r3solvr::{BasicResolver, Query, SymbolResolver};
+use std::collections::HashSet;
+use std::fs;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use zynx_bridge_shared::zygote::{SpecializeArgs, SpecializeLayout};
 
-mod embryo;
+pub mod bridge_log;
+pub mod embryo;
 pub mod ipc;
+#[cfg(feature = "mem-inject")]
+pub mod loader;
+pub mod pipeline;
 pub mod policy;
+pub mod preflight;
+pub mod trampoline;
 pub mod zygote;
 
 pub const SC_LIBRARY_PATH: &str = "/system/lib64/libandroid_runtime.so";
 
+/// Mangled SpecializeCommon symbols of known releases, keyed by the name used in offsets.
+/// The layout of their args is inferred from the signature all the same.
+const SC_SYMBOLS: [(&str, &str); 2] = [
+    (
+        "R",
+        "_ZN12_GLOBAL__N_116SpecializeCommonEP7_JNIEnvjjP10_jintArrayiP13_jobjectArraylliP8_jstringS7_bbS7_S7_bS5_S5_bb",
+    ),
+    (
+        "V",
+        "_ZN12_GLOBAL__N_116SpecializeCommonEP7_JNIEnvjjP10_jintArrayiP13_jobjectArrayllliP8_jstringS7_bbS7_S7_bS5_S5_bbb",
+    ),
+];
+
+/// Mangled SpecializeCommon symbols start with this, up to the first param `JNIEnv*`
+const SC_SYMBOL_PREFIX: &str = "_ZN12_GLOBAL__N_116SpecializeCommonEP7_JNIEnv";
+
+/// Params a new release may add to SpecializeCommon: bool, int, unsigned int, long, and the
+/// substitutions of `jobjectArray*` and `jstring*`
+const SC_NEW_PARAMS: [&str; 6] = ["b", "i", "j", "l", "S5_", "S7_"];
+
+/// Guessed symbols looked up at most, and the time spent on them, the likeliest first
+const MAX_GUESSES: usize = 256;
+const GUESS_BUDGET: Duration = Duration::from_secs(2);
+
+/// Split mangled params into one token per param, only covering the types SpecializeCommon
+/// takes: builtin types, pointers to named types and substitutions.
+fn split_params(mangled: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let mut rest = mangled;
+
+    while !rest.is_empty() {
+        let len = match rest.as_bytes()[0] {
+            b'P' => {
+                let digits = rest[1..].bytes().take_while(u8::is_ascii_digit).count();
+                let name_len: usize = rest[1..1 + digits].parse().unwrap_or(0);
+                1 + digits + name_len
+            }
+            b'S' => rest.find('_').map_or(rest.len(), |end| end + 1),
+            _ => 1,
+        };
+
+        let len = len.min(rest.len());
+
+        params.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+
+    params
+}
+
+/// Guesses of the symbol of a SpecializeCommon with one param more than a known release,
+/// or two more at its end, at most [`MAX_GUESSES`]. Releases append params more often than
+/// they insert them, so appended ones come first.
+fn guess_symbols() -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+
+    let mut push = |params: Vec<&str>| {
+        let symbol = format!("{SC_SYMBOL_PREFIX}{}", params.concat());
+
+        if seen.insert(symbol.clone()) {
+            candidates.push(symbol);
+        }
+    };
+
+    // the latest release first, new ones most likely derive from it
+    for (_, known) in SC_SYMBOLS.iter().rev() {
+        let params = split_params(&known[SC_SYMBOL_PREFIX.len()..]);
+
+        for param in SC_NEW_PARAMS {
+            let mut guess = params.clone();
+            guess.push(param);
+            push(guess);
+        }
+
+        for first in SC_NEW_PARAMS {
+            for second in SC_NEW_PARAMS {
+                let mut guess = params.clone();
+                guess.extend([first, second]);
+                push(guess);
+            }
+        }
+
+        for index in (0..params.len()).rev() {
+            for param in SC_NEW_PARAMS {
+                let mut guess = params.clone();
+                guess.insert(index, param);
+                push(guess);
+            }
+        }
+    }
+
+    candidates.truncate(MAX_GUESSES);
+    candidates
+}
+
+/// Infer the layout of the args of SpecializeCommon from its symbol, in forward-compat mode
+/// if the signature isn't understood. Also returns the arg count.
+fn layout_of(symbol: &str) -> Result<(SpecializeLayout, usize)> {
+    let params = ArgCounter::param_types_for_symbol(symbol)?;
+
+    let layout = match SpecializeLayout::infer(&params) {
+        Ok(layout) => layout,
+        Err(err) => {
+            warn!(
+                "unknown SpecializeCommon signature {params:?} ({err}), running in forward-compat mode"
+            );
+
+            if params.len() < SpecializeArgs::STABLE_ARGS_COUNT {
+                bail!("too few args: {}", params.len());
+            }
+
+            SpecializeLayout::forward_compat()
+        }
+    };
+
+    Ok((layout, params.len()))
+}
+
 #[allow(unused)]
 #[derive(Debug)]
 pub struct SpecializeCommonConfig {
     pub lib: &'static str,
-    pub ver: SpecializeVersion,
-    pub sym: Symbol,
-    pub sec: Section,
+    pub layout: SpecializeLayout,
+    /// Offset of SpecializeCommon in the library
+    pub addr: usize,
     pub args_cnt: usize,
+    /// Whether the config comes from user-provided offsets
+    pub user_provided: bool,
 }
 
 impl SpecializeCommonConfig {
+    /// Whether the signature isn't understood, in which case only args common to all known
+    /// releases are read, none are rewritten, and providers depending on them are disabled.
+    pub fn is_forward_compat(&self) -> bool {
+        self.layout.is_forward_compat()
+    }
+
     fn resolve() -> Result<Self> {
+        let cache = SymbolCache::instance();
+
+        let cached = SC_SYMBOLS
+            .iter()
+            .map(|(_, name)| name.to_string())
+            .chain(guess_symbols())
+            .find_map(|name| Some((cache.get(SC_LIBRARY_PATH, &name)?, name)));
+
+        if let Some((addr, name)) = cached {
+            let (layout, args_count) = layout_of(&name)?;
+
+            info!("SpecializeCommon symbol: {name} @ {addr:#x} (cached)");
+
+            return Ok(Self {
+                lib: SC_LIBRARY_PATH,
+                layout,
+                addr,
+                args_cnt: args_count,
+                user_provided: false,
+            });
+        }
+
         let resolver = BasicResolver::from_file(SC_LIBRARY_PATH)?;
 
-        let (sym, ver) = SpecializeVersion::iter()
-            .find_map(|ver| {
-                resolver
-                    .lookup_symbol(Query::new(ver.as_ref()).with_debugdata(true))
-                    .map(|sym| (sym, ver))
-                    .ok()
+        let lookup = |name: &str| {
+            resolver
+                .lookup_symbol(Query::new(name).with_debugdata(true))
+                .ok()
+        };
+
+        let sym = SC_SYMBOLS
+            .iter()
+            .find_map(|&(_, name)| lookup(name))
+            .or_else(|| {
+                let start = Instant::now();
+
+                guess_symbols()
+                    .iter()
+                    .take_while(|_| start.elapsed() < GUESS_BUDGET)
+                    .find_map(|name| lookup(name.as_str()))
             })
-            .context("no known SpecializeCommon symbol found in libandroid_runtime.so")?;
+            .context("no SpecializeCommon symbol found in libandroid_runtime.so")?;
 
         let sec = resolver.lookup_section(sym.section_index)?;
-        let args_count = ArgCounter::count_args_for_symbol(&sym.name)?;
+        let (layout, args_count) = layout_of(&sym.name)?;
+
+        info!("SpecializeCommon symbol: {sym:?}, section: {sec:?}");
+        cache.insert(SC_LIBRARY_PATH, &sym.name, sym.addr);
 
         Ok(Self {
             lib: SC_LIBRARY_PATH,
-            ver,
-            sym,
-            sec,
+            layout,
+            addr: sym.addr,
             args_cnt: args_count,
+            user_provided: false,
         })
     }
+
+    fn from_offsets(offsets: &SpecializeCommonOffsets) -> Result<Self> {
+        let known = offsets.version.as_ref().and_then(|version| {
+            let symbol = SC_SYMBOLS
+                .iter()
+                .find(|(name, _)| name == version)
+                .map(|(_, symbol)| *symbol);
+
+            if symbol.is_none() {
+                warn!("unknown SpecializeCommon version: {version}");
+            }
+
+            symbol
+        });
+        let symbol = known.or(offsets.symbol.as_deref());
+
+        let (layout, args_count) = match symbol {
+            Some(symbol) => {
+                let (layout, expected_args) = layout_of(symbol)?;
+                let args_count = offsets.args_count.unwrap_or(expected_args);
+
+                if args_count < expected_args {
+                    bail!("args_count {args_count} is less than {expected_args} of the signature");
+                }
+
+                (layout, args_count)
+            }
+            None => {
+                let args_count = offsets
+                    .args_count
+                    .context("args_count is required without version or symbol")?;
+
+                if args_count < SpecializeArgs::STABLE_ARGS_COUNT {
+                    bail!(
+                        "args_count {args_count} is less than {} common to all releases",
+                        SpecializeArgs::STABLE_ARGS_COUNT
+                    );
+                }
+
+                warn!("SpecializeCommon signature not provided, running in forward-compat mode");
+
+                (SpecializeLayout::forward_compat(), args_count)
+            }
+        };
+
+        let lib_size = fs::metadata(SC_LIBRARY_PATH)?.len();
+
+        if offsets.addr == 0 || offsets.addr as u64 >= lib_size {
+            bail!(
+                "addr 0x{:x} is out of range of {SC_LIBRARY_PATH} (size 0x{lib_size:x})",
+                offsets.addr
+            );
+        }
+
+        Ok(Self {
+            lib: SC_LIBRARY_PATH,
+            layout,
+            addr: offsets.addr,
+            args_cnt: args_count,
+            user_provided: true,
+        })
+    }
+
+    /// User-provided offsets take precedence, automatic resolution is the fallback if
+    /// there are none or they are invalid.
+    pub fn load() -> Result<Self> {
+        if let Some(offsets) = offsets::current().and_then(|it| it.specialize_common.as_ref()) {
+            match Self::from_offsets(offsets) {
+                Ok(config) => return Ok(config),
+                Err(err) => warn!("invalid SpecializeCommon offsets, falling back: {err:?}"),
+            }
+        }
+
+        Self::resolve()
+    }
 }
 
 pub static SC_CONFIG: Lazy<SpecializeCommonConfig> = Lazy::new(|| {
-    let config = SpecializeCommonConfig::resolve().expect("failed to resolve SpecializeCommon");
+    let config = SpecializeCommonConfig::load().expect("failed to resolve SpecializeCommon");
     info!("SpecializeCommon config: {config:?}");
     config
 });
-
-pub static SC_BRK: [u8; 4] = [0x00, 0x00, 0x20, 0xd4]; // brk #0
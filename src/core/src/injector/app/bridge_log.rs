@@ -0,0 +1,101 @@
+use crate::injector::camouflage;
+use crate::injector::camouflage::NameKind;
+use anyhow::Result;
+use memfd::MemfdOptions;
+use nix::unistd::Pid;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use procfs::process::Process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+use zynx_bridge_shared::log_buffer::LogBufferReader;
+use zynx_misc::ext::ResultExt;
+
+const DRAIN_INTERVAL: Duration = Duration::from_millis(500);
+
+static COLLECTOR: Lazy<BridgeLogCollector> = Lazy::new(BridgeLogCollector::new);
+
+/// Name of the log buffer of `pid`, before camouflage
+pub fn buffer_name(pid: Pid) -> String {
+    format!("zynx::log::{pid}")
+}
+
+struct BufferEntry {
+    pid: Pid,
+    reader: Arc<LogBufferReader>,
+    dropped: u32,
+}
+
+/// Collects bridge logs from injected processes in diagnostics mode.
+///
+/// Each injected process gets a shared memfd ring buffer, the bridge writes its logs
+/// there instead of logcat (where they may be dropped by logd rate limiting), and the
+/// collector periodically drains all buffers into the daemon log.
+pub struct BridgeLogCollector {
+    buffers: Mutex<Vec<BufferEntry>>,
+}
+
+impl BridgeLogCollector {
+    fn new() -> Self {
+        thread::spawn(|| {
+            loop {
+                thread::sleep(DRAIN_INTERVAL);
+                COLLECTOR.drain_all();
+            }
+        });
+
+        Self {
+            buffers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn instance() -> &'static Self {
+        &COLLECTOR
+    }
+
+    /// Create a log buffer for the process `pid`, the returned reader owns the memfd
+    /// that should be sent to the bridge.
+    pub fn create_buffer(&self, pid: Pid) -> Result<Arc<LogBufferReader>> {
+        let name = camouflage::name(NameKind::Buffer, &buffer_name(pid));
+        let fd = MemfdOptions::default().create(&name)?;
+        let reader = Arc::new(LogBufferReader::create(fd.into_file())?);
+
+        self.buffers.lock().push(BufferEntry {
+            pid,
+            reader: reader.clone(),
+            dropped: 0,
+        });
+
+        Ok(reader)
+    }
+
+    fn drain_all(&self) {
+        let mut buffers = self.buffers.lock();
+
+        buffers.retain_mut(|entry| {
+            Self::drain(entry).log_if_error();
+            Process::new(entry.pid.as_raw()).is_ok_and(|proc| proc.is_alive())
+        });
+    }
+
+    fn drain(entry: &mut BufferEntry) -> Result<()> {
+        let (data, dropped) = entry.reader.drain()?;
+
+        for line in String::from_utf8_lossy(&data).lines() {
+            info!(target: "zynx::bridge", "[{}] {line}", entry.pid);
+        }
+
+        if dropped != entry.dropped {
+            warn!(
+                "[{}] {} bridge log records dropped (buffer full)",
+                entry.pid,
+                dropped - entry.dropped
+            );
+            entry.dropped = dropped;
+        }
+
+        Ok(())
+    }
+}
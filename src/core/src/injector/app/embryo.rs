@@ -1,40 +1,102 @@
 use crate::android::packages::PackageInfoService;
-use crate::injector::app::policy::{EmbryoCheckArgs, PolicyProviderManager, ProviderBundle};
-use crate::injector::app::zygote::ZygoteMaps;
-use crate::injector::app::{SC_BRK, SC_CONFIG, ipc};
+use crate::config::ZynxConfigs;
+use crate::events::{EventLog, InjectionEvent, InjectionOutcome};
+use crate::injector::PAGE_SIZE;
+use crate::injector::app::bridge_log::BridgeLogCollector;
+use crate::injector::app::pipeline::{SlowArgs, Tracee, TraceeRequest};
+use crate::injector::app::policy::{EmbryoCheckArgs, PolicyProviderManager, ProviderBundle, caps};
+use crate::injector::app::trampoline::TrampolineInputs;
+use crate::injector::app::zygote::{ZygoteMaps, ZygoteTracer};
+use crate::injector::app::{SC_CONFIG, ipc, pipeline, preflight, trampoline};
+use crate::injector::arch::{Arch, Current};
 use crate::injector::bridge::Bridge;
+use crate::injector::camouflage;
+use crate::injector::camouflage::NameKind;
 use crate::injector::ptrace::ext::WaitStatusExt;
 use crate::injector::ptrace::ext::base::PtraceExt;
-use crate::injector::ptrace::ext::ipc::{MmapOptions, PtraceIpcExt};
+use crate::injector::ptrace::ext::batch::{CallChain, PokeBatch, PtraceBatchExt};
+use crate::injector::ptrace::ext::ipc::{
+    FdMessage, MmapOptions, PtraceIpcExt, RemoteFd, SocketConnection,
+};
 use crate::injector::ptrace::ext::jni::PtraceJniExt;
 use crate::injector::ptrace::ext::remote_call::{PtraceRemoteCallExt, RemoteLibraryResolver};
-use crate::injector::ptrace::{RegSet, RemoteProcess};
-use crate::injector::{PAGE_SIZE, misc};
-use crate::{build_args, dynasm};
+use crate::injector::ptrace::{
+    RegSet, RemoteProcess, RemoteProcessOps, SeccompError, TraceeVanished,
+};
+use crate::injector::shutdown::Shutdown;
+use crate::injector::trace;
+use crate::metrics::Metrics;
+use crate::quarantine::Quarantine;
+use crate::{atrace, build_args, sepolicy};
 use anyhow::{Context, Result, bail};
-use dynasmrt::VecAssembler;
-use dynasmrt::aarch64::Aarch64Relocation;
-use log::{debug, info, trace, warn};
 use nix::libc::{
-    MADV_DONTNEED, MAP_ANONYMOUS, MAP_PRIVATE, PROT_EXEC, PROT_READ, PROT_WRITE, RTLD_NOW, c_long,
+    AF_UNIX, MADV_DONTNEED, MAP_ANONYMOUS, MAP_PRIVATE, PR_SET_VMA, PR_SET_VMA_ANON_NAME,
+    PROT_EXEC, PROT_READ, PROT_WRITE, SOCK_SEQPACKET, c_long,
 };
 use nix::sys::signal::Signal;
 use nix::sys::wait::WaitStatus;
 use nix::unistd::{Gid, Pid, Uid};
 use once_cell::sync::Lazy;
 use scopeguard::defer;
+use std::ffi::CString;
 use std::fmt::{Display, Formatter};
 use std::ops::Deref;
-use std::os::fd::{AsFd, FromRawFd};
-use std::{fmt, mem};
-use syscalls::Sysno;
-use tokio::runtime::Handle;
-use zynx_bridge_shared::remote_lib::DlextInfo;
-use zynx_bridge_shared::zygote::{BridgeArgs, SpecializeArgs};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd, RawFd};
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime};
+use std::fmt;
+use tracing::{Span, debug, field, info, info_span, trace, warn};
+use zynx_bridge_shared::zygote::arrays::DataInfo;
+use zynx_bridge_shared::zygote::{BridgeArgs, ProviderType, SessionId, SpecializeArgs};
+use zynx_ebpf_shared::EntryRegs;
 use zynx_misc::ext::ResultExt;
 
+#[cfg(feature = "mem-inject")]
+mod mem;
+
 static TRAMPOLINE_SIZE: Lazy<usize> = Lazy::new(|| *PAGE_SIZE * 16);
 
+/// Slots written by the trampoline at runtime live in its last page, apart from its code,
+/// so that the code can be mapped read-only in W^X mode
+static TRAMPOLINE_SLOTS_OFFSET: Lazy<usize> = Lazy::new(|| *TRAMPOLINE_SIZE - *PAGE_SIZE);
+
+/// Uid of system_server
+const AID_SYSTEM: u32 = 1000;
+
+/// Name of the trampoline mapping, before camouflage
+pub const TRAMPOLINE_NAME: &str = "zynx::trampoline";
+
+/// Name of the socket the loader of `pid` connects to, before camouflage
+pub fn loader_socket_name(pid: Pid, session: SessionId) -> String {
+    format!("zynx:{pid}:{session}")
+}
+
+/// Scratch memory in the trampoline region, past the call chains written at its start
+const SCRATCH_NAME_OFFSET: usize = 0x800;
+const SCRATCH_PAIR_OFFSET: usize = 0x880;
+const SCRATCH_MESSAGE_OFFSET: usize = 0x900;
+
+/// How the embryo is stopped at the specialize function
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Breakpoint {
+    /// Its code is patched, in the private copy of the page
+    Software,
+    /// A debug register of the thread, in the given slot, the code is untouched
+    Hardware(usize),
+}
+
+/// How the embryo has been stopped at the specialize function
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Stop {
+    /// By a breakpoint, seized beforehand
+    Breakpoint(Breakpoint),
+    /// By the uprobe, seized afterwards
+    Uprobe,
+    /// By the uprobe, never attached to by the mem injector
+    #[cfg(feature = "mem-inject")]
+    Unattached,
+}
+
 /// Handles injection into a newly forked process (embryo) before it specializes
 /// into a specific app. Works by:
 /// 1. Installing a software breakpoint at the specialize function
@@ -43,14 +105,29 @@ static TRAMPOLINE_SIZE: Lazy<usize> = Lazy::new(|| *PAGE_SIZE * 16);
 /// 4. If yes, assembling and deploying a trampoline that loads the bridge
 ///    library, calls pre/post hooks around the original specialize function,
 ///    and cleans itself up afterwards
-pub struct EmbryoInjector {
-    tracee: RemoteProcess,
+///
+/// The injection itself is generic over the tracee, so that it can run against a mock one.
+pub struct EmbryoInjector<P = RemoteProcess> {
+    tracee: P,
     maps: ZygoteMaps,
     /// Address of the SpecializeCommon function in the remote process
     specialize_fn: usize,
+    /// Whether the embryo was forked from an app zygote
+    from_app_zygote: bool,
+    /// Whether the bridge is already loaded in the embryo, inherited from an injected app zygote
+    inherits_bridge: bool,
+    /// Whether the embryo is the first fork of a primary zygote traced since it started, which
+    /// is always system_server
+    first_fork: bool,
+    /// Assigned when the breakpoint is hit, tags every log and event of this injection
+    session: OnceLock<SessionId>,
+    /// Modules dropped by the per-process caps
+    truncated: OnceLock<Vec<String>>,
+    /// Packages of the uid of the embryo, queried on first use
+    packages: OnceLock<Vec<String>>,
 }
 
-impl RemoteLibraryResolver for EmbryoInjector {
+impl<P> RemoteLibraryResolver for EmbryoInjector<P> {
     fn find_library_base(&self, library: &str) -> Result<usize> {
         self.maps
             .find_library_base_by_name(library)
@@ -59,34 +136,67 @@ impl RemoteLibraryResolver for EmbryoInjector {
 }
 
 impl EmbryoInjector {
-    pub fn new(pid: Pid, maps: ZygoteMaps, specialize_fn: usize) -> Self {
-        Self {
-            tracee: RemoteProcess::new(pid),
+    pub fn new(
+        pid: Pid,
+        maps: ZygoteMaps,
+        specialize_fn: usize,
+        from_app_zygote: bool,
+        inherits_bridge: bool,
+        first_fork: bool,
+    ) -> Self {
+        Self::with_tracee(
+            RemoteProcess::new(pid),
             maps,
             specialize_fn,
-        }
+            from_app_zygote,
+            inherits_bridge,
+            first_fork,
+        )
     }
 
     /// Main entry point: installs a breakpoint, waits for it to be hit,
     /// then decides whether to inject into the embryo process.
     pub fn start(&self) -> Result<()> {
-        // Install a software breakpoint at the specialize function entry
-        self.poke_data_ignore_perm(self.specialize_fn, &SC_BRK)?;
-
-        // Attach to the process via PTRACE_SEIZE and resume it
+        // Attach to the process via PTRACE_SEIZE, it stays stopped until resumed
         self.seize()?;
-        self.kill(Signal::SIGCONT)?;
 
         defer! {
             self.detach(None).log_if_error();
         }
 
+        // Install a breakpoint at the specialize function entry, then resume the process
+        let Some(breakpoint) = self.install_breakpoint()? else {
+            info!("{self} not traced, shutting down");
+            return self.kill(Signal::SIGCONT);
+        };
+
+        debug!(
+            "{self} {breakpoint:?} breakpoint at 0x{:x}",
+            self.specialize_fn
+        );
+
+        self.kill(Signal::SIGCONT)?;
+
         // Event loop: wait for the breakpoint or process termination
         loop {
             let status = self.wait()?;
 
             trace!("{self} status = {status:?}");
 
+            // SIGCONT from `Shutdown::release_all` at the latest, let the embryo go untouched
+            if Shutdown::instance().is_requested()
+                && !matches!(status, WaitStatus::Exited(..) | WaitStatus::Signaled(..))
+            {
+                Shutdown::instance().restore(self)?;
+
+                if let Breakpoint::Hardware(slot) = breakpoint {
+                    self.clear_hw_breakpoint(slot)?;
+                }
+
+                info!("{self} released, shutting down");
+                break;
+            }
+
             match status {
                 WaitStatus::Exited(_, code) => {
                     warn!("embryo exited with code: {code}");
@@ -98,91 +208,396 @@ impl EmbryoInjector {
                 }
                 // SIGTRAP means the breakpoint was hit (specialize function called)
                 WaitStatus::Stopped(_, Signal::SIGTRAP) => {
-                    // Capture registers and read the specialize function arguments
                     let regs = self.get_regs()?;
-                    let mut raw_args = vec![0; SC_CONFIG.args_cnt];
 
-                    self.get_args(&mut raw_args)?;
-                    // Restore the original code at the breakpoint site
-                    self.restore_swbp()?;
+                    self.on_specialize(regs, Stop::Breakpoint(breakpoint))?;
+
+                    break;
+                }
+                _ => {}
+            }
+
+            // Forward any pending signals and continue the tracee
+            self.cont(status.sig())?;
+        }
+
+        Ok(())
+    }
+
+    /// Entry point for an embryo stopped at the specialize function by its uprobe, which has
+    /// run the probed instruction already: rewinds it to `entry_regs`, recorded by the uprobe,
+    /// then decides whether to inject, without any breakpoint.
+    pub fn start_probed(&self, entry_regs: &EntryRegs) -> Result<()> {
+        // Attach to the process via PTRACE_SEIZE and resume it, it stops again on the SIGCONT
+        // before running any code
+        self.seize()?;
+
+        defer! {
+            self.detach(None).log_if_error();
+        }
+
+        self.kill(Signal::SIGCONT)?;
+
+        loop {
+            let status = self.wait()?;
+
+            trace!("{self} status = {status:?}");
 
-                    // Parse the raw args into a structured form
-                    let args = SpecializeArgs::new(&raw_args, SC_CONFIG.ver);
+            match status {
+                WaitStatus::Exited(_, code) => {
+                    warn!("embryo exited with code: {code}");
+                    break;
+                }
+                WaitStatus::Signaled(_, sig, _) => {
+                    warn!("embryo killed by {sig}");
+                    break;
+                }
+                WaitStatus::Stopped(_, Signal::SIGCONT) => {
+                    if Shutdown::instance().is_requested() {
+                        info!("{self} released, shutting down");
+                        break;
+                    }
 
-                    debug!("{self} specialize args: {args:?}");
+                    let mut regs = self.get_regs()?;
 
-                    // Query policy providers to determine if injection is needed
-                    let handle = Handle::current();
-                    let inject_payload = handle.block_on(self.check_process(&args))?;
+                    regs.load_entry_regs(entry_regs);
 
-                    if let Some(payload) = inject_payload {
-                        // Injection required: deploy trampoline and inject libraries
-                        self.do_inject(regs, &raw_args, payload)?;
-                    } else {
-                        // No injection needed: just restore registers and let it continue
-                        self.set_regs(&regs)?;
+                    if regs.get_pc() != self.specialize_fn {
+                        bail!(
+                            "{self} probed at 0x{:x} instead of specialize",
+                            regs.get_pc()
+                        );
                     }
 
+                    self.on_specialize(regs, Stop::Uprobe)?;
+
                     break;
                 }
                 _ => {}
             }
 
-            // Forward any pending signals and continue the tracee
             self.cont(status.sig())?;
         }
 
         Ok(())
     }
 
-    fn restore_swbp(&self) -> Result<()> {
-        debug!("{self} restore swbp: {}", self.specialize_fn);
+    fn packages(&self, args: &SpecializeArgs) -> &[String] {
+        self.packages.get_or_init(|| {
+            PackageInfoService::instance()
+                .query(Uid::from_raw(args.uid as _))
+                .map(|list| list.iter().map(|info| info.name.clone()).collect())
+                .unwrap_or_default()
+        })
+    }
 
-        // note: no writeback is required because MADV_DONTNEED immediately unmaps the memory,
-        // subsequent accesses to this region will trigger page faults and reload data from the file.
-        // self.poke_data_ignore_perm(swbp.addr(), swbp.backup())?;
+    fn record_event(
+        &self,
+        args: &SpecializeArgs,
+        providers: Vec<ProviderType>,
+        outcome: InjectionOutcome,
+        start: Instant,
+    ) {
+        let event = InjectionEvent {
+            time: SystemTime::now(),
+            pid: self.pid,
+            session: self.session(),
+            uid: Uid::from_raw(args.uid as _),
+            packages: self.packages(args).to_vec(),
+            providers,
+            outcome,
+            duration: start.elapsed(),
+            truncated: self.truncated.get().cloned().unwrap_or_default(),
+            loads: Vec::new(),
+        };
 
-        #[rustfmt::skip]
-        let result = self.call_remote_auto(
-            ("libc", "madvise"),
-            build_args!(misc::floor_to_page_size(self.specialize_fn), *PAGE_SIZE, MADV_DONTNEED)
+        trace::on_outcome(&event);
+        EventLog::instance().record(event);
+    }
+
+    /// Run the policy check as a task, serving its reads of the embryo meanwhile.
+    fn check_process(
+        &self,
+        args: &SpecializeArgs,
+        stop: Stop,
+    ) -> Result<Option<Vec<ProviderBundle>>> {
+        let _slice = atrace::slice("zynx: policy check");
+        let _span = info_span!("policy_check").entered();
+
+        let fast_args = self.fast_args(args);
+        let forward_compat = args.is_forward_compat();
+        let tag = self.to_string();
+
+        let (bundles, truncated) = pipeline::run_check(
+            |tracee| check_policy(self.pid, fast_args, forward_compat, tag, tracee),
+            |request| match request {
+                TraceeRequest::SlowArgs(reply) => {
+                    let slow_args = match stop {
+                        Stop::Breakpoint(_) | Stop::Uprobe => self.slow_args(args),
+                        // read with remote JNI calls, checks go on without them
+                        #[cfg(feature = "mem-inject")]
+                        Stop::Unattached => Ok(SlowArgs::default()),
+                    };
+
+                    let _ = reply.send(slow_args);
+                }
+            },
         )?;
 
-        if result == -1 {
-            bail!("failed to restore swbp");
+        if !truncated.is_empty() {
+            let _ = self.truncated.set(truncated);
         }
 
-        Ok(())
+        Ok(bundles)
     }
 
-    async fn check_process(&self, args: &SpecializeArgs) -> Result<Option<Vec<ProviderBundle>>> {
-        // Todo: selinux check execmem?
-
+    fn fast_args(&self, args: &SpecializeArgs) -> EmbryoCheckArgs {
         let uid = Uid::from_raw(args.uid as _);
         let package_info = PackageInfoService::instance().query(uid);
-        let fast_args = EmbryoCheckArgs::new_fast(
+
+        // the flag is unknown in forward-compat mode, system_server is the first process the
+        // primary zygote forks, apps sharing the system uid come later
+        let is_system_server = if args.is_forward_compat() {
+            self.first_fork && uid.as_raw() == AID_SYSTEM
+        } else {
+            args.is_system_server
+        };
+
+        EmbryoCheckArgs::new_fast(
             uid,
             Gid::from_raw(args.gid as _),
-            args.is_system_server,
+            is_system_server,
             args.is_child_zygote,
+            self.from_app_zygote,
+            self.inherits_bridge,
             package_info,
-        );
+        )
+    }
 
-        let manager = PolicyProviderManager::instance();
-        let mut result = manager.check(&fast_args).await;
+    fn slow_args(&self, args: &SpecializeArgs) -> Result<SlowArgs> {
+        let _slice = atrace::slice("zynx: policy recheck");
+
+        let gids = self.read_jint_array(args.env, args.gids)?;
+        let pkg_data_info_list = self
+            .read_jstring_array(args.env, args.pkg_data_info_list)?
+            .and_then(|strings| DataInfo::parse_list(&strings).ok_or_warn());
+
+        Ok(SlowArgs {
+            nice_name: self.read_jstring(args.env, args.managed_nice_name)?,
+            app_data_dir: self.read_jstring(args.env, args.managed_app_data_dir)?,
+            gids: gids.map(|gids| {
+                gids.into_iter()
+                    .map(|gid| Gid::from_raw(gid as _))
+                    .collect()
+            }),
+            pkg_data_info_list,
+        })
+    }
 
-        if result.more_info {
-            let slow_args = fast_args.into_slow(
-                self.read_jstring(args.env, args.managed_nice_name)?,
-                self.read_jstring(args.env, args.managed_app_data_dir)?,
-            );
-            manager.recheck_slow(&slow_args, &mut result).await;
+    /// The embryo is stopped at the entry of the specialize function with `regs`, as told by
+    /// `stop`: decide whether to inject, then let it go on.
+    fn on_specialize(&self, mut regs: RegSet, stop: Stop) -> Result<()> {
+        let _ = self.session.set(SessionId::generate());
+        let _slice = atrace::slice(format_args!(
+            "zynx: specialize pid={} session={}",
+            self.pid,
+            self.session()
+        ));
+        let _span = info_span!(
+            "injection",
+            pid = self.pid.as_raw(),
+            session = %self.session(),
+            uid = field::Empty,
+            package = field::Empty,
+        )
+        .entered();
+
+        // hardware breakpoints stop before the instruction on all arches
+        if stop == Stop::Breakpoint(Breakpoint::Software) {
+            regs.rewind_breakpoint();
         }
 
-        Ok(manager.aggregate(&result.decisions))
+        // Read the specialize function arguments
+        let mut raw_args = vec![0; SC_CONFIG.args_cnt];
+
+        self.get_args_with_regs(&regs, &mut raw_args)?;
+
+        // Remove the breakpoint, restoring the original code if it was patched
+        if let Stop::Breakpoint(breakpoint) = stop {
+            self.remove_breakpoint(breakpoint)?;
+        }
+
+        // Parse the raw args into a structured form
+        let args = SpecializeArgs::new(&raw_args, SC_CONFIG.layout);
+        let span = Span::current();
+
+        span.record("uid", args.uid);
+
+        if !span.is_disabled()
+            && let Some(package) = self.packages(&args).first()
+        {
+            span.record("package", package.as_str());
+        }
+
+        debug!("{self} specialize args: {args:?}");
+
+        // App zygotes fork app processes on their own, track them before release
+        if args.is_child_zygote {
+            ZygoteTracer::create_child(
+                self.pid,
+                self.maps.clone(),
+                self.specialize_fn,
+                self.inherits_bridge,
+            )
+            .log_if_error();
+        }
+
+        // Query policy providers to determine if injection is needed
+        let check_start = Instant::now();
+        let inject_payload = self
+            .check_process(&args, stop)
+            .map_err(|err| self.classify_error(err))
+            .inspect_err(|err| {
+                let outcome = outcome_of_error(err);
+                self.record_event(&args, Vec::new(), outcome, check_start);
+            })?;
+
+        if let Some(payload) = inject_payload {
+            // Injection required: deploy trampoline and inject libraries
+            let metrics = Metrics::instance();
+            let providers = payload.iter().map(|bundle| bundle.ty).collect();
+            let modules: Vec<_> = payload
+                .iter()
+                .flat_map(|bundle| &bundle.attachments)
+                .filter_map(|attachment| attachment.module.clone())
+                .collect();
+            let start = Instant::now();
+            let log_start = sepolicy::log_time();
+
+            metrics.on_inject_start();
+
+            let result = match stop {
+                Stop::Breakpoint(_) | Stop::Uprobe => self.do_inject(regs, &raw_args, payload),
+                #[cfg(feature = "mem-inject")]
+                Stop::Unattached => self.do_inject_mem(&regs, payload),
+            };
+            let result = result.map_err(|err| self.classify_error(err));
+
+            let outcome = match &result {
+                Ok(()) => InjectionOutcome::Injected,
+                Err(err) => outcome_of_error(err),
+            };
+
+            metrics.on_inject_finish(&outcome, start.elapsed());
+
+            if matches!(outcome, InjectionOutcome::Failed(_)) {
+                sepolicy::on_injection_failed(self.pid, log_start);
+            }
+
+            if matches!(outcome, InjectionOutcome::Injected) {
+                Quarantine::watch(self.pid, modules);
+
+                if args.is_child_zygote {
+                    ZygoteTracer::mark_injected(self.pid);
+                }
+            }
+
+            self.record_event(&args, providers, outcome, check_start);
+            result?;
+        } else {
+            // No injection needed: just restore registers and let it continue, an unattached
+            // embryo goes on past the probed instruction once resumed
+            match stop {
+                Stop::Breakpoint(_) | Stop::Uprobe => self.set_regs(&regs)?,
+                #[cfg(feature = "mem-inject")]
+                Stop::Unattached => {}
+            }
+
+            self.record_event(&args, Vec::new(), InjectionOutcome::Denied, check_start);
+        }
+
+        Ok(())
     }
 
-    /// Core injection routine. Assembles an AArch64 trampoline in the remote
+    /// Break on the specialize function with a hardware breakpoint if enabled and the CPU
+    /// has one, by patching its code otherwise. Returns `None` if shutdown has been requested.
+    fn install_breakpoint(&self) -> Result<Option<Breakpoint>> {
+        if ZynxConfigs::instance().hw_breakpoint && !Shutdown::instance().is_requested() {
+            match self.set_hw_breakpoint(self.specialize_fn) {
+                Ok(Some(slot)) => return Ok(Some(Breakpoint::Hardware(slot))),
+                Ok(None) => debug!("{self} no free hardware breakpoint, patching code"),
+                Err(err) => warn!("{self} hardware breakpoint unavailable, patching code: {err:#}"),
+            }
+        }
+
+        if !Shutdown::instance().patch(self, self.specialize_fn, Current::BREAKPOINT)? {
+            return Ok(None);
+        }
+
+        Ok(Some(Breakpoint::Software))
+    }
+
+    fn remove_breakpoint(&self, breakpoint: Breakpoint) -> Result<()> {
+        match breakpoint {
+            Breakpoint::Hardware(slot) => self.clear_hw_breakpoint(slot),
+            Breakpoint::Software => {
+                self.restore_swbp()?;
+                Shutdown::instance().on_restored(self.pid);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<P: RemoteProcessOps> EmbryoInjector<P> {
+    pub fn with_tracee(
+        tracee: P,
+        maps: ZygoteMaps,
+        specialize_fn: usize,
+        from_app_zygote: bool,
+        inherits_bridge: bool,
+        first_fork: bool,
+    ) -> Self {
+        Self {
+            tracee,
+            maps,
+            specialize_fn,
+            from_app_zygote,
+            inherits_bridge,
+            first_fork,
+            session: OnceLock::new(),
+            truncated: OnceLock::new(),
+            packages: OnceLock::new(),
+        }
+    }
+
+    fn session(&self) -> SessionId {
+        self.session.get().copied().unwrap_or_default()
+    }
+
+    pub(crate) fn restore_swbp(&self) -> Result<()> {
+        debug!("{self} restore swbp: {}", self.specialize_fn);
+
+        // note: no writeback is required because MADV_DONTNEED immediately unmaps the memory,
+        // subsequent accesses to this region will trigger page faults and reload data from the file.
+        // self.poke_data_ignore_perm(swbp.addr(), swbp.backup())?;
+
+        let pages = Current::patch_pages(self.specialize_fn, Current::BREAKPOINT.len());
+
+        #[rustfmt::skip]
+        let result = self.call_remote_auto(
+            ("libc", "madvise"),
+            build_args!(pages.start, pages.len(), MADV_DONTNEED)
+        )?;
+
+        if result == -1 {
+            bail!("failed to restore swbp");
+        }
+
+        Ok(())
+    }
+
+    /// Core injection routine. Assembles a trampoline (see [`trampoline`]) in the remote
     /// process that performs the following steps:
     ///
     /// 1. Save the original specialize args (x0-x7) on the stack
@@ -190,11 +605,12 @@ impl EmbryoInjector {
     /// 3. Close the bridge fd (no longer needed after dlopen)
     /// 4. Resolve `specialize_pre` and `specialize_post` hook symbols via dlsym
     /// 5. Call the pre-hook with the saved args and bridge configuration
-    /// 6. Replace LR so that SpecializeCommon returns to our trampoline
+    /// 6. Replace LR (the return address on x86_64) so that SpecializeCommon returns to our
+    ///    trampoline
     /// 7. Restore args and tail-call the original SpecializeCommon
     /// 8. On return (via trampoline): call the post-hook
     /// 9. Clean up by munmap-ing the trampoline and returning to the real caller
-    fn do_inject(
+    pub(crate) fn do_inject(
         &self,
         mut regs: RegSet,
         raw_args: &[c_long],
@@ -202,235 +618,411 @@ impl EmbryoInjector {
     ) -> Result<()> {
         info!("injecting process: {self}, raw_args = {raw_args:?}");
 
-        // Allocate RWX memory in the remote process for the trampoline code
-        let trampoline_addr = self.mmap_ex(
-            MmapOptions::new(
+        let _slice = atrace::slice("zynx: inject");
+        let _span = info_span!("inject").entered();
+
+        let libraries = preflight::label_libraries(self.pid(), &bundles);
+        preflight::check(self.pid(), &libraries, false)?;
+
+        let wx = ZynxConfigs::instance().wx_trampoline;
+
+        // Allocate memory in the remote process for the trampoline code, used as scratch
+        // memory until the trampoline is written. In W^X mode it's made executable after
+        // the trampoline is written, so the calls before can't be batched
+        let trampoline_addr = if wx {
+            self.mmap_ex(
+                MmapOptions::new(
+                    *TRAMPOLINE_SIZE,
+                    PROT_READ | PROT_WRITE,
+                    MAP_PRIVATE | MAP_ANONYMOUS,
+                )
+                .name(&camouflage::name(NameKind::Mapping, TRAMPOLINE_NAME)),
+            )?
+        } else {
+            self.mmap(
+                0,
                 *TRAMPOLINE_SIZE,
                 PROT_READ | PROT_WRITE | PROT_EXEC,
                 MAP_PRIVATE | MAP_ANONYMOUS,
-            )
-            .name("zynx::trampoline"),
-        )?;
+                None,
+                0,
+            )?
+        };
 
         let unmap_on_fail = scopeguard::guard_on_success((), |_| {
-            self.munmap(trampoline_addr, *TRAMPOLINE_SIZE)
-                .log_if_error();
+            // nothing to clean up in the remote if the embryo is already gone
+            if self.is_alive() {
+                self.munmap(trampoline_addr, *TRAMPOLINE_SIZE)
+                    .log_if_error();
+            }
         });
 
-        // Establish a unix socket connection with the remote process for IPC
-        let conn = self.connect(trampoline_addr)?;
-
-        // Install the bridge library fd into the remote process
-        let bridge = Bridge::instance();
-        let bridge_fd = self.install_fd(trampoline_addr, &conn, bridge.as_fd())?;
+        // Establish a unix socket connection with the remote process for IPC, and install
+        // the bridge library fd into the remote process. If there are no bundles to send,
+        // the socket is closed right away
+        let keep_conn = !bundles.is_empty();
+        let (conn, bridge_fd) = if wx {
+            self.install_bridge(trampoline_addr, keep_conn)?
+        } else {
+            self.install_bridge_batched(trampoline_addr, keep_conn)?
+        };
 
         debug!("{self} bridge fd: {bridge_fd:?}");
 
         let bridge_fd = bridge_fd.forget();
+        let (conn_fd_local, conn_fd_remote) = conn.map(SocketConnection::forget).unzip();
+
+        // Assemble the trampoline and write it into the trampoline region
+        let deploy_slice = atrace::slice("zynx: deploy trampoline");
+        let bytecode = self.assemble_trampoline(trampoline_addr, bridge_fd, conn_fd_remote)?;
+
+        self.poke_data(trampoline_addr, &bytecode)?;
+
+        if wx {
+            let code_size = bytecode.len().next_multiple_of(*PAGE_SIZE);
+            self.mprotect(trampoline_addr, code_size, PROT_READ | PROT_EXEC)?;
+        }
+
+        std::mem::forget(unmap_on_fail);
+
+        // Redirect execution to the trampoline and release the process
+        regs.set_pc(trampoline_addr);
 
-        // If there are bundles to inject, keep the socket open for sending
-        // payload later; otherwise close it immediately
-        let (conn_fd_local, conn_fd_remote) = if !bundles.is_empty() {
-            let (local, remote) = conn.forget();
-            (Some(local), Some(remote))
+        self.set_regs(&regs)?;
+        self.detach(None)?;
+
+        drop(deploy_slice);
+
+        // Send payload over the socket so the bridge can load libraries
+        if let Some(conn_fd) = conn_fd_local {
+            self.transfer_bundles(conn_fd, bundles)?;
+        }
+
+        Ok(())
+    }
+
+    /// Send `bundles` to the bridge over `conn_fd`, then watch its reports.
+    fn transfer_bundles(&self, conn_fd: OwnedFd, bundles: Vec<ProviderBundle>) -> Result<()> {
+        let _slice = atrace::slice("zynx: transfer bundles");
+        let log_buffer = if ZynxConfigs::instance().capture_bridge_logs {
+            BridgeLogCollector::instance()
+                .create_buffer(self.pid())
+                .ok_or_warn()
         } else {
-            conn.close(self)?;
-            (None, None)
+            None
         };
 
-        // Assemble the AArch64 trampoline code using dynasm
-        let mut ops: VecAssembler<Aarch64Relocation> = VecAssembler::new(0);
+        let log_fd = log_buffer.as_ref().map(|buffer| buffer.file().as_fd());
+
+        let reports_fd = conn_fd.try_clone()?;
+
+        ipc::transfer_data(conn_fd, bundles, log_fd, Some((self.pid(), self.session())))?;
+        ipc::watch_reports(self.pid(), reports_fd).log_if_error();
+
+        Ok(())
+    }
+
+    /// [`PtraceIpcExt::mmap_ex`] naming the trampoline region, then [`PtraceIpcExt::connect`],
+    /// batched into a single remote call. Also returns the remote copy of the local end of
+    /// the socket, which is left to be closed by the next batch.
+    fn connect_batched(&self, trampoline_addr: usize) -> Result<(SocketConnection, RemoteFd)> {
+        let name = CString::new(camouflage::name(NameKind::Mapping, TRAMPOLINE_NAME))?;
+        let name = name.as_bytes_with_nul();
+        let name_addr = trampoline_addr + SCRATCH_NAME_OFFSET;
+        let pair_addr = trampoline_addr + SCRATCH_PAIR_OFFSET;
 
-        // Prepare dlopen info: load bridge library from the installed fd
-        let info = unsafe { DlextInfo::from_raw_fd(bridge_fd) };
+        let mut chain = CallChain::default();
 
-        // Arguments passed to the bridge's pre-hook function
-        let bridge_args = BridgeArgs {
-            conn_fd: conn_fd_remote.unwrap_or(-1),
-            specialize_version: SC_CONFIG.ver,
+        #[rustfmt::skip]
+        chain.push(
+            self.resolve_fn(("libc", "prctl"))?,
+            build_args!(PR_SET_VMA, PR_SET_VMA_ANON_NAME, trampoline_addr, name.len(), name_addr)
+        )?;
+        chain.push(
+            self.resolve_fn(("libc", "socketpair"))?,
+            build_args!(AF_UNIX, SOCK_SEQPACKET, 0, pair_addr),
+        )?;
+
+        let mut batch = PokeBatch::default();
+
+        batch.push(name_addr, name);
+
+        let results = self.call_chain(trampoline_addr, &chain, batch)?;
+
+        if results[1] != 0 {
+            bail!("{self} failed to call socketpair")
+        }
+
+        let pair = self.peek(pair_addr)?;
+
+        let local_fd_num = (pair & 0xffffffff) as i32;
+        let remote_fd_num = (pair >> 32) as i32;
+
+        let local_fd = self.take_fd(local_fd_num)?;
+
+        Ok((
+            SocketConnection::new(local_fd, RemoteFd::new(remote_fd_num)),
+            RemoteFd::new(local_fd_num),
+        ))
+    }
+
+    /// [`PtraceIpcExt::connect`] and [`PtraceIpcExt::install_fd`] of the bridge, as separate
+    /// remote calls. Returns the connection if `keep_conn` is set, or closes it.
+    fn install_bridge(
+        &self,
+        trampoline_addr: usize,
+        keep_conn: bool,
+    ) -> Result<(Option<SocketConnection>, RemoteFd)> {
+        let conn = {
+            let _slice = atrace::slice("zynx: connect");
+            self.connect(trampoline_addr)?
         };
 
-        dynasm!(ops
-            // Step 1: Save specialize args (x0-x7) onto the stack
-            ; stp x6, x7, [sp, #-16]!
-            ; stp x4, x5, [sp, #-16]!
-            ; stp x2, x3, [sp, #-16]!
-            ; stp x0, x1, [sp, #-16]!
-
-            // Step 2: Load the bridge library via android_dlopen_ext
-            //   x0 = library name ("zynx::bridge"), x1 = RTLD_NOW, x2 = DlextInfo
-            ; stp fp, lr, [sp, #-16]!
-            ; ldr ip, >dlopen
-            ; adr x0, >lib_name
-            ; mov x1, RTLD_NOW as _
-            ; adr x2, >lib_info
-            ; blr ip
-            ; ldp fp, lr, [sp], #16
-
-            // Step 3: Close the bridge fd via syscall (no longer needed after dlopen)
-            //   x0 = dlopen handle (saved/restored around the syscall)
-            ; stp x0, xzr, [sp, #-16]!
-            ; mov x8, Sysno::close as _
-            ; mov x0, bridge_fd as _
-            ; svc #0
-            ; ldp x0, xzr, [sp], #16
-
-            // Step 4a: Resolve the post-hook symbol and store its address
-            //   dlsym(handle, "specialize_post") -> post_hook_addr
-            ; stp fp, lr, [sp, #-16]!
-            ; stp x0, x1, [sp, #-16]!
-            ; ldr ip, >dlsym
-            ; adr x1, >post_hook_sym
-            ; blr ip
-            ; adr x1, >post_hook_addr
-            ; str x0, [x1]
-            ; ldp x0, x1, [sp], #16
-            ; ldp fp, lr, [sp], #16
-
-            // Step 4b: Resolve the pre-hook symbol
-            //   dlsym(handle, "specialize_pre") -> x0
-            ; stp fp, lr, [sp, #-16]!
-            ; ldr ip, >dlsym
-            ; adr x1, >pre_hook_sym
-            ; blr ip
-            ; ldp fp, lr, [sp], #16
-
-            // Step 5: Call the pre-hook
-            //   pre_hook(args_on_stack, args_cnt, &bridge_args)
-            ; stp fp, lr, [sp, #-16]!
-            ; mov ip, x0
-            ; add x0, sp, 16
-            ; mov x1, SC_CONFIG.args_cnt as _
-            ; adr x2, >bridge_args
-            ; blr ip
-            ; ldp fp, lr, [sp], #16
-
-            // Step 6: Hijack LR so SpecializeCommon returns to our trampoline
-            //   Save the real LR, then set LR to the trampoline label
-            ; adr x0, >specialize_lr
-            ; str lr, [x0]
-            ; adr lr, >trampoline
-
-            // Step 7: Restore original specialize args and jump to SpecializeCommon
-            ; ldp x0, x1, [sp], #16
-            ; ldp x2, x3, [sp], #16
-            ; ldp x4, x5, [sp], #16
-            ; ldp x6, x7, [sp], #16
-
-            // Tail-call into the real SpecializeCommon
-            ; ldr ip, >specialize
-            ; br ip
-
-            // Step 8: Post-hook trampoline (SpecializeCommon returns here)
-            ; trampoline:
-            ; stp fp, lr, [sp, #-16]!
-            ; ldr ip, >post_hook_addr
-            ; blr ip
-            ; ldp fp, lr, [sp], #16
-
-            // Step 9: Self-cleanup via munmap, then return to the real caller
-            //   Restore original LR, then tail-call munmap(trampoline_addr, size)
-            ; ldr lr, >specialize_lr
-            ; ldr ip, >munmap
-            ; ldr x0, >trampoline_addr
-            ; mov x1, *TRAMPOLINE_SIZE as _
-            ; br ip
-
-            // ---- Data section ----
-
-            // Address of the original SpecializeCommon function
-            ; .align 8
-            ; specialize:
-            ;; ops.push_u64(self.specialize_fn as _)
-
-            // Slot to save/restore the original return address
-            ; .align 8
-            ; specialize_lr:
-            ;; ops.push_u64(0xfee1deadfee1dead)
-
-            // Resolved addresses of dlopen and dlsym
-            ; .align 8
-            ; dlopen:
-            ;; ops.push_u64(self.resolve_fn(("libdl", "android_dlopen_ext"))? as _)
-
-            ; .align 8
-            ; dlsym:
-            ;; ops.push_u64(self.resolve_fn(("libdl", "dlsym"))? as _)
-
-            // Bridge library name (used by android_dlopen_ext)
-            ; .align 8
-            ; lib_name:
-            ;; ops.extend(c"zynx::bridge".to_bytes_with_nul())
-
-            // DlextInfo struct (tells dlopen to load from fd)
-            ; .align align_of::<DlextInfo>()
-            ; lib_info:
-            ;; ops.extend(crate::misc::as_byte_slice(&info))
-
-            // BridgeArgs struct passed to the pre-hook
-            ; .align align_of::<BridgeArgs>()
-            ; bridge_args:
-            ;; ops.extend(crate::misc::as_byte_slice(&bridge_args))
-
-            // Hook symbol name strings
-            ; .align 8
-            ; pre_hook_sym:
-            ;; ops.extend(c"specialize_pre".to_bytes_with_nul())
-
-            ; .align 8
-            ; post_hook_sym:
-            ;; ops.extend(c"specialize_post".to_bytes_with_nul())
-
-            // Slot to store the resolved post-hook function pointer
-            ; .align 8
-            ; post_hook_addr:
-            ;; ops.push_u64(0xfee1deadfee1dead)
-
-            // Resolved address of munmap (for self-cleanup)
-            ; .align 8
-            ; munmap:
-            ;; ops.push_u64(self.resolve_fn(("libc", "munmap"))? as _)
-
-            // Base address of this trampoline (passed to munmap)
-            ; .align 8
-            ; trampoline_addr:
-            ;; ops.push_u64(trampoline_addr as _)
-        );
+        let _slice = atrace::slice("zynx: install bridge");
+        let bridge_fd = self.install_fd(trampoline_addr, &conn, Bridge::instance().as_fd())?;
 
-        // Finalize the assembled bytecode and write it into the trampoline region
-        let bytecode = ops.finalize()?;
+        if keep_conn {
+            return Ok((Some(conn), bridge_fd));
+        }
 
-        trace!("dynasm bytecode: {bytecode:?}");
+        conn.close(self)?;
 
-        self.poke_data(trampoline_addr, &bytecode)?;
+        Ok((None, bridge_fd))
+    }
 
-        mem::forget(unmap_on_fail);
+    /// Same as [`Self::install_bridge`], also naming the trampoline region, batched into two
+    /// remote calls with call chains written to the region.
+    fn install_bridge_batched(
+        &self,
+        trampoline_addr: usize,
+        keep_conn: bool,
+    ) -> Result<(Option<SocketConnection>, RemoteFd)> {
+        let (conn, peer) = {
+            let _slice = atrace::slice("zynx: connect");
+            self.connect_batched(trampoline_addr)?
+        };
 
-        // Redirect execution to the trampoline and release the process
-        regs.set_pc(trampoline_addr);
+        let _slice = atrace::slice("zynx: install bridge");
+        let message = FdMessage::new(trampoline_addr + SCRATCH_MESSAGE_OFFSET);
+        let close = self.resolve_fn(("libc", "__close"))?;
+        let remote_fd = conn.remote_fd.as_raw_fd();
 
-        self.set_regs(&regs)?;
-        self.detach(None)?;
+        conn.send_fd(Bridge::instance().as_fd())?;
 
-        // Send payload over the socket so the bridge can load libraries
-        if let Some(conn_fd) = conn_fd_local {
-            ipc::transfer_data(conn_fd, bundles)?;
+        let mut chain = CallChain::default();
+
+        chain.push(close, build_args!(peer.forget()))?;
+        chain.push(
+            self.resolve_fn(("libc", "recvmsg"))?,
+            build_args!(remote_fd, message.header_addr(), 0),
+        )?;
+
+        if !keep_conn {
+            chain.push(close, build_args!(remote_fd))?;
         }
 
-        Ok(())
+        let mut batch = PokeBatch::default();
+
+        batch.push(message.header_addr(), message.header_bytes());
+
+        self.call_chain(trampoline_addr, &chain, batch)?;
+
+        let bridge_fd = message.receive(self)?;
+
+        if keep_conn {
+            return Ok((Some(conn), bridge_fd));
+        }
+
+        // the remote end is closed by the chain already
+        conn.forget();
+
+        Ok((None, bridge_fd))
+    }
+
+    /// Assemble the trampoline deployed by [`Self::do_inject`], to be placed at `trampoline_addr`.
+    pub(crate) fn assemble_trampoline(
+        &self,
+        trampoline_addr: usize,
+        bridge_fd: RawFd,
+        conn_fd: Option<RawFd>,
+    ) -> Result<Vec<u8>> {
+        let bridge_name = CString::new(Bridge::instance().name())?;
+
+        let inputs = TrampolineInputs {
+            addr: trampoline_addr,
+            size: *TRAMPOLINE_SIZE,
+            slots_offset: *TRAMPOLINE_SLOTS_OFFSET,
+            specialize_fn: self.specialize_fn,
+            dlopen: self.resolve_fn(("libdl", "android_dlopen_ext"))?,
+            dlsym: self.resolve_fn(("libdl", "dlsym"))?,
+            munmap: self.resolve_fn(("libc", "munmap"))?,
+            bridge_name: &bridge_name,
+            bridge_fd,
+            args_cnt: SC_CONFIG.args_cnt,
+            bridge_args: BridgeArgs {
+                conn_fd: conn_fd.unwrap_or(-1),
+                specialize_layout: SC_CONFIG.layout,
+                session: self.session(),
+            },
+        };
+
+        let trampoline = trampoline::assemble(&inputs)
+            .context(format!("failed to assemble the trampoline of {self}"))?;
+
+        trace!(
+            "dynasm bytecode: {:?}, code ends at {:#x}",
+            trampoline.bytecode, trampoline.code_len
+        );
+
+        Ok(trampoline.bytecode)
     }
 }
 
-impl Deref for EmbryoInjector {
-    type Target = RemoteProcess;
+pub(crate) fn outcome_of_error(err: &anyhow::Error) -> InjectionOutcome {
+    if err.downcast_ref::<TraceeVanished>().is_some() {
+        InjectionOutcome::Vanished
+    } else if let Some(err) = err.downcast_ref::<SeccompError>() {
+        InjectionOutcome::Seccomp(err.to_string())
+    } else {
+        InjectionOutcome::Failed(format!("{err:#}"))
+    }
+}
+
+impl<P> Deref for EmbryoInjector<P> {
+    type Target = P;
 
     fn deref(&self) -> &Self::Target {
         &self.tracee
     }
 }
 
-impl Display for EmbryoInjector {
+/// Policy check of an embryo, returning the bundles to inject and the modules dropped by
+/// the caps. Runs on the runtime, the embryo is only read through `tracee`.
+async fn check_policy(
+    pid: Pid,
+    fast_args: EmbryoCheckArgs,
+    forward_compat: bool,
+    tag: String,
+    tracee: Tracee,
+) -> Result<(Option<Vec<ProviderBundle>>, Vec<String>)> {
+    let deadline = PolicyProviderManager::deadline();
+    let manager = PolicyProviderManager::instance();
+    let mut result = manager.check(&fast_args, deadline).await;
+
+    let args = if result.more_info {
+        let slow_args = fast_args.into_slow(tracee.slow_args().await?);
+
+        manager
+            .recheck_slow(&slow_args, &mut result, deadline)
+            .await;
+
+        slow_args
+    } else {
+        fast_args
+    };
+
+    // done with the embryo, let its tracer thread go on
+    drop(tracee);
+
+    trace::on_check(pid.as_raw(), &args, &result.decisions);
+
+    let Some(mut bundles) = manager.aggregate(&result.decisions) else {
+        return Ok((None, Vec::new()));
+    };
+
+    if forward_compat {
+        // they rewrite args, or hand them over to modules
+        bundles.retain(|bundle| {
+            let supported = !matches!(bundle.ty, ProviderType::Debugger | ProviderType::Zygisk);
+
+            if !supported {
+                warn!("{tag} {:?} is disabled in forward-compat mode", bundle.ty);
+            }
+
+            supported
+        });
+    }
+
+    let truncated = caps::enforce(&mut bundles);
+
+    Ok(((!bundles.is_empty()).then_some(bundles), truncated))
+}
+
+impl<P: Display> Display for EmbryoInjector<P> {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
-        Display::fmt(&self.tracee, fmt)
+        Display::fmt(&self.tracee, fmt)?;
+
+        match self.session.get() {
+            Some(session) => write!(fmt, "[session={session}]"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::injector::ptrace::mock::{MockOp, MockOutcome, MockProcess};
+
+    const LIBC: usize = 0x7f00_0000_0000;
+    const STACK: usize = 0x7ff0_0000_0000;
+    const SPECIALIZE_FN: usize = 0x7100_0000_1ff0;
+
+    const MAPS: &str = "\
+7f0000000000-7f0000100000 r-xp 00000000 fe:00 123 /apex/com.android.runtime/lib64/bionic/libc.so
+";
+
+    fn injector() -> EmbryoInjector<MockProcess> {
+        let process = MockProcess::new(Pid::from_raw(1234));
+
+        {
+            let mut state = process.state();
+
+            state.map(STACK, 0x1000);
+            state.regs.set_sp(STACK + 0xff8);
+        }
+
+        process.symbol("libc", "madvise", 0x100);
+
+        let maps = ZygoteMaps::from_content(MAPS).unwrap();
+
+        EmbryoInjector::with_tracee(process, maps, SPECIALIZE_FN, false, false, false)
+    }
+
+    fn madvise_calls(injector: &EmbryoInjector<MockProcess>) -> Vec<[c_long; 8]> {
+        injector
+            .state()
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                MockOp::Call { func, args } if *func == LIBC + 0x100 => Some(*args),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn restore_swbp_drops_patched_pages() {
+        let injector = injector();
+
+        injector.function(LIBC + 0x100, |_, _| MockOutcome::Return(0));
+        injector.restore_swbp().unwrap();
+
+        let pages = Current::patch_pages(SPECIALIZE_FN, Current::BREAKPOINT.len());
+        let mut args = [0; 8];
+
+        args[..3].copy_from_slice(build_args!(pages.start, pages.len(), MADV_DONTNEED));
+
+        assert_eq!(madvise_calls(&injector), [args]);
+    }
+
+    #[test]
+    fn restore_swbp_fails_with_madvise() {
+        let injector = injector();
+
+        injector.function(LIBC + 0x100, |_, _| MockOutcome::Return(-1));
+
+        assert!(injector.restore_swbp().is_err());
+        assert_eq!(madvise_calls(&injector).len(), 1);
     }
 }
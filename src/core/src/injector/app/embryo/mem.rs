@@ -0,0 +1,251 @@
+use crate::injector::app::embryo::{
+    EmbryoInjector, Stop, TRAMPOLINE_SIZE, TRAMPOLINE_SLOTS_OFFSET, loader_socket_name,
+};
+use crate::injector::app::loader::LoaderInputs;
+use crate::injector::app::policy::ProviderBundle;
+use crate::injector::app::zygote::ZygoteMaps;
+use crate::injector::app::{loader, preflight};
+use crate::injector::bridge::Bridge;
+use crate::injector::camouflage;
+use crate::injector::camouflage::NameKind;
+use crate::injector::ptrace::{RegSet, RemoteProcessOps};
+use crate::injector::shutdown::Shutdown;
+use crate::{atrace, misc};
+use anyhow::{Context, Result, bail};
+use nix::sys::signal::Signal;
+use nix::sys::socket;
+use nix::sys::socket::sockopt::{PeerCredentials, ReceiveTimeout};
+use nix::sys::socket::{
+    AddressFamily, Backlog, ControlMessage, MsgFlags, SockFlag, SockType, UnixAddr,
+};
+use nix::sys::time::TimeVal;
+use procfs::process::{ProcState, Process};
+use scopeguard::defer;
+use std::collections::HashSet;
+use std::fs;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, info_span};
+use zynx_ebpf_shared::EntryRegs;
+use zynx_misc::ext::ResultExt;
+
+/// Time the loader has to connect once the embryo is resumed
+const CONNECT_TIMEOUT_SECS: i64 = 2;
+
+/// Time the SIGSTOP sent by eBPF has to take effect
+const STOP_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The embryo resumes past the probed instruction, at most 15 bytes long on x86_64
+const MAX_INSN_LEN: usize = 16;
+
+/// First release honoring `MAP_FIXED_NOREPLACE`, older ones take the address as a hint and the
+/// loader would always fall back
+const FIXED_NOREPLACE_KERNEL: (u32, u32) = (4, 17);
+
+impl EmbryoInjector {
+    /// Entry point of the mem injector, for an embryo stopped by the uprobe of the specialize
+    /// function. It's never attached to: args are read from `entry_regs` and its memory, and
+    /// a loader is written through `/proc/<pid>/mem` over the code it resumes at.
+    pub fn start_mem(&self, entry_regs: &EntryRegs) -> Result<()> {
+        // it runs uninjected or the loader from here
+        defer! {
+            self.kill(Signal::SIGCONT).log_if_error();
+        }
+
+        if Shutdown::instance().is_requested() {
+            info!("{self} released, shutting down");
+            return Ok(());
+        }
+
+        let regs = RegSet::from_entry_regs(entry_regs);
+
+        if regs.get_pc() != self.specialize_fn {
+            bail!(
+                "{self} probed at 0x{:x} instead of specialize",
+                regs.get_pc()
+            );
+        }
+
+        self.on_specialize(regs, Stop::Unattached)
+    }
+
+    /// Counterpart of [`EmbryoInjector::do_inject`] without ptrace: the trampoline is mapped
+    /// and run by a loader (see [`loader`]), which connects to the daemon for the bridge and
+    /// the payload.
+    pub(super) fn do_inject_mem(&self, regs: &RegSet, bundles: Vec<ProviderBundle>) -> Result<()> {
+        info!("injecting process without ptrace: {self}");
+
+        let _slice = atrace::slice("zynx: inject");
+        let _span = info_span!("inject").entered();
+
+        let libraries = preflight::label_libraries(self.pid(), &bundles);
+        preflight::check(self.pid(), &libraries, true)?;
+
+        if misc::kernel_version()? < FIXED_NOREPLACE_KERNEL {
+            bail!(
+                "kernel older than {}.{} can't map the trampoline region at a fixed address",
+                FIXED_NOREPLACE_KERNEL.0,
+                FIXED_NOREPLACE_KERNEL.1
+            );
+        }
+
+        let process = Process::new(self.pid().as_raw())?;
+        let resume_addr = self.stopped_pc(&process)?;
+
+        if !(self.specialize_fn..self.specialize_fn + MAX_INSN_LEN).contains(&resume_addr) {
+            bail!("{self} stopped at 0x{resume_addr:x} instead of specialize");
+        }
+
+        // fds and the free range are predicted, nothing else runs in the embryo until the
+        // loader is done with them
+        if process.stat()?.num_threads != 1 {
+            bail!("{self} has other threads, its fds can't be predicted");
+        }
+
+        // the loader crashes the embryo if the patched pages can't be dropped
+        if process.status()?.vmlck.is_some_and(|locked| locked > 0) {
+            bail!("{self} has locked memory, the patched code can't be dropped");
+        }
+
+        let maps = ZygoteMaps::parse(self.pid())?;
+        let region = maps
+            .find_free_range(self.specialize_fn, *TRAMPOLINE_SIZE)
+            .context(format!("{self} no free range for the trampoline"))?;
+        let (conn_fd, bridge_fd) = free_fds(&process)?;
+
+        debug!("{self} region: 0x{region:x}, conn fd: {conn_fd}, bridge fd: {bridge_fd}");
+
+        let socket_name = camouflage::name(
+            NameKind::Socket {
+                pid: self.pid().as_raw(),
+            },
+            &loader_socket_name(self.pid(), self.session()),
+        );
+        let listener = listen(socket_name.as_bytes())?;
+
+        // Assemble the loader around the trampoline and write it where the embryo resumes
+        let deploy_slice = atrace::slice("zynx: deploy loader");
+        let trampoline = self.assemble_trampoline(region, bridge_fd, Some(conn_fd))?;
+        let entry_regs = regs.to_entry_regs();
+        let loader = loader::assemble(&LoaderInputs {
+            addr: resume_addr,
+            region,
+            region_size: *TRAMPOLINE_SIZE,
+            slots_offset: *TRAMPOLINE_SLOTS_OFFSET,
+            trampoline: &trampoline,
+            specialize_fn: self.specialize_fn,
+            entry_regs: &entry_regs,
+            socket_name: socket_name.as_bytes(),
+            conn_fd,
+            bridge_fd,
+        })
+        .context(format!("failed to assemble the loader of {self}"))?;
+
+        // the pages dropped by the loader must belong to the code it's written over
+        let vma = maps
+            .find_vma(resume_addr)
+            .context(format!("{self} code segment of specialize not found"))?;
+
+        if resume_addr + loader.len() > vma.address.1 as usize {
+            bail!(
+                "{self} loader past the code segment: {} bytes",
+                loader.len()
+            );
+        }
+
+        self.poke_data_ignore_perm(resume_addr, &loader)?;
+        self.kill(Signal::SIGCONT)?;
+
+        drop(deploy_slice);
+
+        let conn_fd = self.accept_loader(&listener)?;
+
+        self.transfer_bundles(conn_fd, bundles)
+    }
+
+    /// Where the embryo resumes, once stopped by the SIGSTOP sent by eBPF. Read from
+    /// `/proc/<pid>/syscall`, `-1 <sp> <pc>` outside of syscalls.
+    fn stopped_pc(&self, process: &Process) -> Result<usize> {
+        let deadline = Instant::now() + STOP_TIMEOUT;
+
+        while !matches!(process.stat()?.state()?, ProcState::Stopped) {
+            if Instant::now() > deadline {
+                bail!("{self} not stopped");
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let syscall = fs::read_to_string(format!("/proc/{}/syscall", self.pid()))?;
+
+        let ["-1", _sp, pc] = syscall.split_whitespace().collect::<Vec<_>>()[..] else {
+            bail!("{self} stopped in a syscall: {syscall:?}");
+        };
+
+        Ok(usize::from_str_radix(pc.trim_start_matches("0x"), 16)?)
+    }
+
+    /// Accept the connection of the loader and send it the bridge, returns the connection to
+    /// transfer the payload over.
+    fn accept_loader(&self, listener: &OwnedFd) -> Result<OwnedFd> {
+        let _slice = atrace::slice("zynx: accept loader");
+
+        let conn = socket::accept4(listener.as_raw_fd(), SockFlag::SOCK_CLOEXEC).context(
+            format!("{self} loader didn't connect, please check your sepolicy rules"),
+        )?;
+        let conn = unsafe { OwnedFd::from_raw_fd(conn) };
+        let peer = socket::getsockopt(&conn, PeerCredentials)?;
+
+        if peer.pid() != self.pid().as_raw() {
+            bail!("{self} unexpected peer connected: {}", peer.pid());
+        }
+
+        socket::sendmsg::<()>(
+            conn.as_raw_fd(),
+            &[],
+            &[ControlMessage::ScmRights(&[Bridge::instance()
+                .as_fd()
+                .as_raw_fd()])],
+            MsgFlags::empty(),
+            None,
+        )?;
+
+        Ok(conn)
+    }
+}
+
+/// Listen on the abstract socket `name` for the loader.
+fn listen(name: &[u8]) -> Result<OwnedFd> {
+    let listener = socket::socket(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+
+    socket::bind(listener.as_raw_fd(), &UnixAddr::new_abstract(name)?)?;
+    socket::listen(&listener, Backlog::new(1)?)?;
+    socket::setsockopt(
+        &listener,
+        ReceiveTimeout,
+        &TimeVal::new(CONNECT_TIMEOUT_SECS, 0),
+    )?;
+
+    Ok(listener)
+}
+
+/// The two lowest free fds of `process`, taken by the socket and the bridge received by the
+/// loader, in this order.
+fn free_fds(process: &Process) -> Result<(RawFd, RawFd)> {
+    let used = process
+        .fd()?
+        .map(|info| Ok(info?.fd))
+        .collect::<Result<HashSet<_>>>()?;
+    let mut free = (0..).filter(|fd| !used.contains(fd));
+
+    match (free.next(), free.next()) {
+        (Some(conn_fd), Some(bridge_fd)) => Ok((conn_fd, bridge_fd)),
+        _ => bail!("no free fds"),
+    }
+}
@@ -1,15 +1,42 @@
+use crate::events::EventLog;
 use crate::injector::app::policy::ProviderBundle;
+use crate::injector::audit;
+use crate::quarantine::Quarantine;
 use anyhow::Result;
-use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
-use zynx_bridge_shared::zygote::{AttachmentWire, IpcPayload, ProviderBundleWire};
+use nix::fcntl;
+use nix::fcntl::{FcntlArg, OFlag};
+use nix::sys::socket;
+use nix::sys::socket::MsgFlags;
+use nix::sys::socket::sockopt::SendTimeout;
+use nix::sys::time::TimeVal;
+use nix::unistd::Pid;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
+use tracing::{debug, error, warn};
+use zynx_bridge_shared::zygote::{
+    AttachmentWire, BridgeReport, CrashReport, IpcPayload, LoadReport, ProviderBundleWire,
+    SessionId,
+};
+
+/// Bound on each frame of a payload sent to the bridge
+const SEND_TIMEOUT_SECS: i64 = 5;
+
+/// Reports are small, anything longer is truncated and fails to decode
+const MAX_REPORT_SIZE: usize = 64 * 1024;
 
 /// Convert business-layer `ProviderBundle`s into transport-layer `(IpcPayload, fds)`.
 ///
 /// The returned `IpcPayload` is the wire-format struct, and `fds` is a flat list
 /// of borrowed file descriptors extracted from attachments in the same order
 /// that the receiver expects (matching `has_fd` markers in the wire struct).
-pub fn bundles_to_payload(bundles: &[ProviderBundle]) -> (IpcPayload, Vec<BorrowedFd<'_>>) {
-    let mut fds = Vec::new();
+/// The optional `log_fd` is always sent first.
+pub fn bundles_to_payload<'a>(
+    bundles: &'a [ProviderBundle],
+    log_fd: Option<BorrowedFd<'a>>,
+) -> (IpcPayload, Vec<BorrowedFd<'a>>) {
+    let mut fds: Vec<_> = log_fd.into_iter().collect();
 
     let providers: Vec<ProviderBundleWire> = bundles
         .iter()
@@ -25,20 +52,108 @@ pub fn bundles_to_payload(bundles: &[ProviderBundle]) -> (IpcPayload, Vec<Borrow
                     AttachmentWire {
                         has_fd: attachment.fd.is_some(),
                         data: attachment.data.clone(),
+                        module: attachment.module.clone(),
                     }
                 })
                 .collect(),
             data: bundle.data.clone(),
+            mutation: bundle.mutation.clone(),
         })
         .collect();
 
-    (IpcPayload { providers }, fds)
+    let payload = IpcPayload {
+        providers,
+        has_log_fd: log_fd.is_some(),
+        audit: None,
+    };
+
+    (payload, fds)
 }
 
 /// Transfer `ProviderBundle`s over a unix socket via SCM_RIGHTS.
 ///
 /// This is a convenience wrapper around [`bundles_to_payload`] + [`IpcPayload::send_to`].
-pub fn transfer_data(conn_fd: OwnedFd, bundles: Vec<ProviderBundle>) -> Result<()> {
-    let (payload, fds) = bundles_to_payload(&bundles);
+/// The app `(pid, session)`, if any, is asked for an audit with `--cfg-audit`.
+pub fn transfer_data(
+    conn_fd: OwnedFd,
+    bundles: Vec<ProviderBundle>,
+    log_fd: Option<BorrowedFd>,
+    app: Option<(Pid, SessionId)>,
+) -> Result<()> {
+    // large payloads are streamed, don't get stuck on a bridge that stopped reading
+    socket::setsockopt(&conn_fd, SendTimeout, &TimeVal::new(SEND_TIMEOUT_SECS, 0))?;
+
+    let (mut payload, fds) = bundles_to_payload(&bundles, log_fd);
+    payload.audit = app.and_then(|(pid, session)| audit::request(pid, session, &fds));
     payload.send_to(conn_fd, fds)
 }
+
+/// Receive reports from the bridge of `pid` over its end of the connection, until the process
+/// exits. Modules named in a crash report are blamed right away, the process survives.
+pub fn watch_reports(pid: Pid, conn_fd: OwnedFd) -> Result<()> {
+    fcntl::fcntl(&conn_fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+
+    let conn = AsyncFd::with_interest(conn_fd, Interest::READABLE)?;
+
+    tokio::spawn(async move {
+        let mut buffer = vec![0u8; MAX_REPORT_SIZE];
+
+        loop {
+            let Ok(mut guard) = conn.readable().await else {
+                break;
+            };
+
+            let received = guard.try_io(|conn| {
+                socket::recv(conn.as_raw_fd(), &mut buffer, MsgFlags::empty())
+                    .map_err(io::Error::from)
+            });
+
+            match received {
+                // closed by the process, or by its exit
+                Ok(Ok(0)) => break,
+                Ok(Ok(len)) => match BridgeReport::decode(&buffer[..len]) {
+                    Ok(BridgeReport::Crash(report)) => on_crash_report(pid, report),
+                    Ok(BridgeReport::Load(report)) => on_load_report(pid, report),
+                    Ok(BridgeReport::Audit(report)) => audit::on_report(pid, report),
+                    Err(err) => debug!("process {pid}: malformed report: {err}"),
+                },
+                Ok(Err(err)) => {
+                    debug!("process {pid}: reports closed: {err}");
+                    break;
+                }
+                Err(_would_block) => continue,
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn on_crash_report(pid: Pid, report: CrashReport) {
+    error!(
+        "process {pid}: {:?} handler panicked, modules {:?}: {}",
+        report.provider, report.modules, report.message
+    );
+
+    Quarantine::instance().on_crash_report(pid, &report.modules, &report.message);
+}
+
+fn on_load_report(pid: Pid, report: LoadReport) {
+    for load in &report.providers {
+        if load.errors.is_empty() {
+            debug!(
+                "process {pid}: {:?} loaded {:?} in {}us",
+                load.provider, load.modules, load.duration_us
+            );
+        } else {
+            warn!(
+                "process {pid}: {:?} failed to load {:?}: {}",
+                load.provider,
+                load.modules,
+                load.errors.join("; ")
+            );
+        }
+    }
+
+    EventLog::instance().on_load_report(pid, report.providers);
+}
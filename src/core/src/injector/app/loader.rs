@@ -0,0 +1,272 @@
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+use aarch64 as arch;
+#[cfg(target_arch = "x86_64")]
+use x86_64 as arch;
+
+use crate::injector::arch::{Arch, Current};
+use anyhow::{Result, bail};
+use nix::libc::{AF_UNIX, CMSG_SPACE, c_int, cmsghdr, msghdr, sa_family_t, sockaddr_un};
+use std::mem;
+use std::os::fd::RawFd;
+use zynx_ebpf_shared::EntryRegs;
+
+/// Not in the libc of every target
+const MAP_FIXED_NOREPLACE: c_int = 0x100000;
+
+/// Smallest cache line the architecture allows, the stride of cache maintenance. Code copied
+/// by the loader is padded to a multiple of it
+const CACHE_LINE_MIN: usize = 16;
+
+/// Offsets in `msghdr`, and of the payload of a control message, hardcoded in the loader
+const MSG_CONTROL_OFFSET: u32 = 32;
+const MSG_CONTROLLEN_OFFSET: u32 = 40;
+const CMSG_DATA_OFFSET: u32 = 16;
+
+const _: () = {
+    assert!(mem::offset_of!(msghdr, msg_control) == MSG_CONTROL_OFFSET as usize);
+    assert!(mem::offset_of!(msghdr, msg_controllen) == MSG_CONTROLLEN_OFFSET as usize);
+    assert!(size_of::<cmsghdr>() == CMSG_DATA_OFFSET as usize);
+};
+
+/// What the loader is assembled from, resolved beforehand like [`super::trampoline`] inputs.
+pub struct LoaderInputs<'a> {
+    /// Where the stopped embryo resumes, the loader is written there
+    pub addr: usize,
+    /// Free range the trampoline region is mapped to, and its size
+    pub region: usize,
+    pub region_size: usize,
+    /// Offset of the writable slots of the trampoline, the loader must not overlap them
+    pub slots_offset: usize,
+    /// Trampoline assembled for `region`
+    pub trampoline: &'a [u8],
+    pub specialize_fn: usize,
+    /// Registers at the entry of the specialize function, recorded by its uprobe
+    pub entry_regs: &'a EntryRegs,
+    /// Abstract name of the socket the daemon listens on
+    pub socket_name: &'a [u8],
+    /// Fds the socket and the received bridge are expected to get, the lowest free ones
+    pub conn_fd: RawFd,
+    pub bridge_fd: RawFd,
+}
+
+/// Assemble the loader of an embryo injected without ptrace, which runs in two stages:
+///
+/// 1. Written over the code the embryo resumes at: maps the trampoline region, copies the
+///    trampoline and the second stage there and jumps to it
+/// 2. Drops the patched pages so that they're reloaded from the file, connects to the daemon
+///    to receive the bridge fd, rewinds the registers to the entry of the specialize function
+///    and jumps to the trampoline. If anything failed, it jumps to the specialize function
+///    instead and the embryo runs uninjected.
+///
+/// If the region can't be mapped, the first stage copies a fallback to any free memory, which
+/// drops the patched pages, rewinds the registers and jumps to the specialize function. Only
+/// running out of memory for the fallback crashes the embryo, and dropping the pages in the
+/// second stage, which can't fail unless memory is locked.
+pub fn assemble(inputs: &LoaderInputs) -> Result<Vec<u8>> {
+    let resume_offset = inputs.trampoline.len().next_multiple_of(CACHE_LINE_MIN);
+    let resume_addr = inputs.region + resume_offset;
+    let mut patch_pages = Current::patch_pages(inputs.addr, 1);
+
+    // the pages dropped by the second stage depend on the size of the loader, which doesn't
+    // depend on them in turn
+    loop {
+        let mut image = inputs.trampoline.to_vec();
+
+        image.resize(resume_offset, 0);
+        image.extend(arch::assemble_resume(inputs, &patch_pages)?);
+        image.resize(image.len().next_multiple_of(CACHE_LINE_MIN), 0);
+
+        if image.len() > inputs.slots_offset {
+            bail!("loader image too large: {} bytes", image.len());
+        }
+
+        let mut fallback = arch::assemble_fallback(inputs, &patch_pages)?;
+
+        fallback.resize(fallback.len().next_multiple_of(CACHE_LINE_MIN), 0);
+
+        let loader = arch::assemble_loader(inputs, &image, resume_addr, &fallback)?;
+        let pages = Current::patch_pages(inputs.addr, loader.len());
+
+        if pages == patch_pages {
+            return Ok(loader);
+        }
+
+        patch_pages = pages;
+    }
+}
+
+/// Abstract address of the socket of the daemon, and its length.
+fn socket_address(name: &[u8]) -> Result<(sockaddr_un, usize)> {
+    let mut address: sockaddr_un = unsafe { mem::zeroed() };
+
+    // the first byte of the path stays 0 for the abstract namespace
+    if name.len() >= address.sun_path.len() {
+        bail!("socket name too long: {} bytes", name.len());
+    }
+
+    address.sun_family = AF_UNIX as sa_family_t;
+
+    for (dst, src) in address.sun_path[1..].iter_mut().zip(name) {
+        *dst = *src as _;
+    }
+
+    Ok((
+        address,
+        mem::offset_of!(sockaddr_un, sun_path) + 1 + name.len(),
+    ))
+}
+
+/// `msghdr` receiving a single fd, whose control buffer is set by the loader, and the length
+/// of the buffer.
+fn fd_message() -> (msghdr, usize) {
+    let control_len = unsafe { CMSG_SPACE(size_of::<i32>() as _) } as usize;
+    let mut message: msghdr = unsafe { mem::zeroed() };
+
+    message.msg_controllen = control_len as _;
+
+    (message, control_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::injector::PAGE_SIZE;
+    use crate::misc;
+    use std::ops::Range;
+
+    const REGION: usize = 0x7100_0000_0000;
+    const SPECIALIZE_FN: usize = 0x7200_0000_1000;
+    const SLOTS_OFFSET: usize = 0x3000;
+    const SOCKET_NAME: &[u8] = b"zynx:1234:5";
+
+    fn entry_regs() -> EntryRegs {
+        std::array::from_fn(|i| 0x5a5a_0000 + i as u64)
+    }
+
+    fn inputs<'a>(addr: usize, trampoline: &'a [u8], regs: &'a EntryRegs) -> LoaderInputs<'a> {
+        LoaderInputs {
+            addr,
+            region: REGION,
+            region_size: 0x4000,
+            slots_offset: SLOTS_OFFSET,
+            trampoline,
+            specialize_fn: SPECIALIZE_FN,
+            entry_regs: regs,
+            socket_name: SOCKET_NAME,
+            conn_fd: 7,
+            bridge_fd: 8,
+        }
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+
+    fn contains_word(haystack: &[u8], word: usize) -> bool {
+        find(haystack, &(word as u64).to_le_bytes()).is_some()
+    }
+
+    /// The image the loader should carry for `patch_pages`, and the fallback.
+    fn expected_parts(inputs: &LoaderInputs, patch_pages: &Range<usize>) -> (Vec<u8>, Vec<u8>) {
+        let mut image = inputs.trampoline.to_vec();
+
+        image.resize(inputs.trampoline.len().next_multiple_of(CACHE_LINE_MIN), 0);
+        image.extend(arch::assemble_resume(inputs, patch_pages).unwrap());
+        image.resize(image.len().next_multiple_of(CACHE_LINE_MIN), 0);
+
+        let mut fallback = arch::assemble_fallback(inputs, patch_pages).unwrap();
+
+        fallback.resize(fallback.len().next_multiple_of(CACHE_LINE_MIN), 0);
+
+        (image, fallback)
+    }
+
+    #[test]
+    fn layout() {
+        let trampoline = vec![0xa5; 200];
+        let regs = entry_regs();
+        let addr = SPECIALIZE_FN + Current::INSN_ALIGN;
+        let inputs = inputs(addr, &trampoline, &regs);
+        let loader = assemble(&inputs).unwrap();
+
+        let patch_pages = Current::patch_pages(addr, loader.len());
+        let (image, fallback) = expected_parts(&inputs, &patch_pages);
+
+        // the first stage is followed by the image, then by the fallback
+        let image_offset = find(&loader, &image).expect("image not embedded");
+        let first_stage = &loader[..image_offset];
+
+        assert_eq!(image_offset % 8, 0);
+        assert!(image.len() <= SLOTS_OFFSET);
+        assert!(loader.ends_with(&fallback));
+        assert!(loader.len() - fallback.len() - (image_offset + image.len()) < 8);
+
+        // the first stage maps the region and resumes at the second stage, right after the
+        // trampoline
+        let resume_offset = trampoline.len().next_multiple_of(CACHE_LINE_MIN);
+
+        assert_eq!(image[..trampoline.len()], trampoline[..]);
+        assert!(contains_word(first_stage, REGION));
+        assert!(contains_word(first_stage, REGION + resume_offset));
+        assert!(contains_word(first_stage, image.len()));
+        assert!(contains_word(first_stage, fallback.len()));
+
+        // both the second stage and the fallback drop the pages the loader is written over,
+        // and rewind to the recorded registers
+        let second_stage = &image[resume_offset..];
+        let (address, address_len) = socket_address(SOCKET_NAME).unwrap();
+
+        for code in [second_stage, &fallback] {
+            assert!(contains_word(code, patch_pages.start));
+            assert!(contains_word(code, patch_pages.len()));
+            assert!(contains_word(code, SPECIALIZE_FN));
+            assert!(find(code, misc::as_byte_slice(&regs)).is_some());
+        }
+
+        assert!(find(second_stage, &misc::as_byte_slice(&address)[..address_len]).is_some());
+        assert_eq!(address_len, 2 + 1 + SOCKET_NAME.len());
+    }
+
+    #[test]
+    fn pages_follow_loader_size() {
+        let trampoline = vec![0xa5; 200];
+        let regs = entry_regs();
+        let page_size = *PAGE_SIZE;
+
+        // written right before a page boundary, the loader spills over to the next page
+        let addr = REGION + 4 * page_size - 4 * Current::INSN_ALIGN;
+        let inputs = inputs(addr, &trampoline, &regs);
+        let loader = assemble(&inputs).unwrap();
+        let patch_pages = Current::patch_pages(addr, loader.len());
+
+        assert_eq!(patch_pages.start, REGION + 3 * page_size);
+        assert!(patch_pages.len() >= 2 * page_size);
+
+        let (image, fallback) = expected_parts(&inputs, &patch_pages);
+
+        assert!(find(&loader, &image).is_some());
+        assert!(loader.ends_with(&fallback));
+    }
+
+    #[test]
+    fn rejects_image_over_slots() {
+        let trampoline = vec![0xa5; SLOTS_OFFSET];
+        let regs = entry_regs();
+
+        assert!(assemble(&inputs(SPECIALIZE_FN, &trampoline, &regs)).is_err());
+    }
+
+    #[test]
+    fn rejects_long_socket_name() {
+        // the path starts with a 0 byte for the abstract namespace
+        assert!(socket_address(&[b'a'; 108]).is_err());
+        assert!(socket_address(&[b'a'; 107]).is_ok());
+    }
+}
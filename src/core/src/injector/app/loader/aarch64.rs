@@ -0,0 +1,315 @@
+use crate::dynasm;
+use crate::injector::app::loader::{
+    CACHE_LINE_MIN, CMSG_DATA_OFFSET, LoaderInputs, MAP_FIXED_NOREPLACE, MSG_CONTROL_OFFSET,
+    MSG_CONTROLLEN_OFFSET, fd_message, socket_address,
+};
+use crate::misc;
+use anyhow::Result;
+use dynasmrt::aarch64::Aarch64Relocation;
+use dynasmrt::{DynasmApi, VecAssembler};
+use nix::libc::{
+    AF_UNIX, MADV_DONTNEED, MAP_ANONYMOUS, MAP_PRIVATE, PROT_EXEC, PROT_READ, PROT_WRITE,
+    SOCK_SEQPACKET,
+};
+use std::ops::Range;
+use syscalls::Sysno;
+
+/// Rewind the registers to the entry of the specialize function, as recorded at the
+/// `entry_regs` label, and jump to x17. x16 and x17 are scratch registers there.
+fn rewind(ops: &mut VecAssembler<Aarch64Relocation>) {
+    dynasm!(ops
+        ; adr x16, >entry_regs
+        ; ldr x0, [x16, #248]
+        ; mov sp, x0
+        ; ldp x0, x1, [x16]
+        ; ldp x2, x3, [x16, #16]
+        ; ldp x4, x5, [x16, #32]
+        ; ldp x6, x7, [x16, #48]
+        ; ldp x8, x9, [x16, #64]
+        ; ldp x10, x11, [x16, #80]
+        ; ldp x12, x13, [x16, #96]
+        ; ldp x14, x15, [x16, #112]
+        ; ldp x18, x19, [x16, #144]
+        ; ldp x20, x21, [x16, #160]
+        ; ldp x22, x23, [x16, #176]
+        ; ldp x24, x25, [x16, #192]
+        ; ldp x26, x27, [x16, #208]
+        ; ldp x28, fp, [x16, #224]
+        ; ldr lr, [x16, #240]
+        ; br x17
+    );
+}
+
+/// First stage, written where the embryo resumes, see [`super::assemble`].
+pub fn assemble_loader(
+    inputs: &LoaderInputs,
+    image: &[u8],
+    resume_addr: usize,
+    fallback: &[u8],
+) -> Result<Vec<u8>> {
+    let mut ops: VecAssembler<Aarch64Relocation> = VecAssembler::new(0);
+
+    dynasm!(ops
+        // Step 1: Map the trampoline region at the free range picked by the daemon
+        ; mov x8, Sysno::mmap as _
+        ; ldr x0, >region
+        ; ldr x1, >region_size
+        ; mov x2, (PROT_READ | PROT_WRITE | PROT_EXEC) as _
+        ; ldr x3, >map_flags
+        ; mvn x4, xzr
+        ; mov x5, xzr
+        ; svc #0
+        ; ldr x1, >region
+        ; cmp x0, x1
+        ; b.ne >fail
+
+        // Step 2: Copy the image, the trampoline followed by the second stage, and jump to
+        // the second stage. No register is live here
+        ; adr x2, >image
+        ; ldr x3, >image_len
+        ; ldr x5, >resume
+
+        // Copy x3 bytes from x2 to x0, make them visible to instruction fetches and jump to x5
+        ; copy:
+        ; mov x6, x0
+        ; mov x7, x3
+        ; copy_next:
+        ; ldr x4, [x2], #8
+        ; str x4, [x0], #8
+        ; subs x3, x3, #8
+        ; b.ne <copy_next
+        ; flush:
+        ; dc cvau, x6
+        ; dsb ish
+        ; ic ivau, x6
+        ; add x6, x6, CACHE_LINE_MIN as u32
+        ; subs x7, x7, CACHE_LINE_MIN as u32
+        ; b.ne <flush
+        ; dsb ish
+        ; isb
+        ; br x5
+
+        // The region is taken, or mapped elsewhere by a kernel ignoring MAP_FIXED_NOREPLACE.
+        // Run the fallback from anywhere, it can't run from here: it drops this page
+        ; fail:
+        ; cmn x0, #4095
+        ; b.lo >stub
+        ; mov x8, Sysno::mmap as _
+        ; mov x0, xzr
+        ; ldr x1, >fallback_len
+        ; mov x2, (PROT_READ | PROT_WRITE | PROT_EXEC) as _
+        ; mov x3, (MAP_PRIVATE | MAP_ANONYMOUS) as _
+        ; mvn x4, xzr
+        ; mov x5, xzr
+        ; svc #0
+        ; cmn x0, #4095
+        ; b.hs >crash
+        ; stub:
+        ; adr x2, >fallback
+        ; ldr x3, >fallback_len
+        ; mov x5, x0
+        ; b <copy
+
+        // Out of memory, nothing can be restored from here
+        ; crash:
+        ; brk #0
+
+        // ---- Data section ----
+        ; .align 8
+        ; region:
+        ;; ops.push_u64(inputs.region as _)
+
+        ; .align 8
+        ; region_size:
+        ;; ops.push_u64(inputs.region_size as _)
+
+        ; .align 8
+        ; map_flags:
+        ;; ops.push_u64((MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED_NOREPLACE) as _)
+
+        ; .align 8
+        ; image_len:
+        ;; ops.push_u64(image.len() as _)
+
+        ; .align 8
+        ; resume:
+        ;; ops.push_u64(resume_addr as _)
+
+        ; .align 8
+        ; fallback_len:
+        ;; ops.push_u64(fallback.len() as _)
+
+        ; .align 8
+        ; image:
+        ;; ops.extend(image)
+
+        ; .align 8
+        ; fallback:
+        ;; ops.extend(fallback)
+    );
+
+    Ok(ops.finalize()?)
+}
+
+/// Run by the first stage if the trampoline region can't be mapped, see [`super::assemble`].
+pub fn assemble_fallback(inputs: &LoaderInputs, patch_pages: &Range<usize>) -> Result<Vec<u8>> {
+    let mut ops: VecAssembler<Aarch64Relocation> = VecAssembler::new(0);
+
+    dynasm!(ops
+        ; mov x8, Sysno::madvise as _
+        ; ldr x0, >pages_start
+        ; ldr x1, >pages_len
+        ; mov x2, MADV_DONTNEED as _
+        ; svc #0
+        ; cbnz x0, >fail
+        ; ldr x17, >specialize
+    );
+
+    rewind(&mut ops);
+
+    dynasm!(ops
+        // see `assemble_resume`
+        ; fail:
+        ; brk #0
+
+        // ---- Data section ----
+        ; .align 8
+        ; pages_start:
+        ;; ops.push_u64(patch_pages.start as _)
+
+        ; .align 8
+        ; pages_len:
+        ;; ops.push_u64(patch_pages.len() as _)
+
+        ; .align 8
+        ; specialize:
+        ;; ops.push_u64(inputs.specialize_fn as _)
+
+        ; .align 8
+        ; entry_regs:
+        ;; ops.extend(misc::as_byte_slice(inputs.entry_regs))
+    );
+
+    Ok(ops.finalize()?)
+}
+
+/// Second stage, run from the trampoline region, see [`super::assemble`]. Any register can be
+/// used, they're all rewound before leaving it.
+pub fn assemble_resume(inputs: &LoaderInputs, patch_pages: &Range<usize>) -> Result<Vec<u8>> {
+    let mut ops: VecAssembler<Aarch64Relocation> = VecAssembler::new(0);
+
+    let (address, address_len) = socket_address(inputs.socket_name)?;
+    let (message, control_len) = fd_message();
+
+    dynasm!(ops
+        // Step 1: Drop the patched pages, they're reloaded from the file
+        ; mov x8, Sysno::madvise as _
+        ; ldr x0, >pages_start
+        ; ldr x1, >pages_len
+        ; mov x2, MADV_DONTNEED as _
+        ; svc #0
+        ; cbnz x0, >fail
+
+        // Step 2: Connect to the daemon, the socket must get the fd expected by the trampoline
+        ; mov x8, Sysno::socket as _
+        ; mov x0, AF_UNIX as _
+        ; mov x1, SOCK_SEQPACKET as _
+        ; mov x2, xzr
+        ; svc #0
+        ; mov x19, x0
+        ; ldr x1, >conn_fd
+        ; cmp x0, x1
+        ; b.ne >fallback
+
+        ; mov x8, Sysno::connect as _
+        ; mov x0, x19
+        ; adr x1, >address
+        ; mov x2, address_len as _
+        ; svc #0
+        ; cbnz x0, >fallback
+
+        // Step 3: Receive the bridge fd, it must get the fd expected by the trampoline too
+        ; adr x1, >message
+        ; adr x2, >control
+        ; str x2, [x1, MSG_CONTROL_OFFSET]
+        ; mov x8, Sysno::recvmsg as _
+        ; mov x0, x19
+        ; mov x2, xzr
+        ; svc #0
+        ; cmp x0, #0
+        ; b.lt >fallback
+        ; ldr x3, [x1, MSG_CONTROLLEN_OFFSET]
+        ; cbz x3, >fallback
+        ; adr x2, >control
+        ; ldr w3, [x2, CMSG_DATA_OFFSET]
+        ; ldr x4, >bridge_fd
+        ; cmp x3, x4
+        ; b.ne >fallback
+
+        ; ldr x17, >trampoline
+        ; b >rewind
+
+        // Leave the embryo uninjected, the region is leaked
+        ; fallback:
+        ; mov x8, Sysno::close as _
+        ; mov x0, x19
+        ; svc #0
+        ; ldr x17, >specialize
+
+        // Step 4: Rewind the registers to the entry of the specialize function and jump to x17
+        ; rewind:
+    );
+
+    rewind(&mut ops);
+
+    dynasm!(ops
+        // Only locked pages can't be dropped, the daemon refuses to inject if any memory is
+        // locked. The patched code would run again otherwise, crash rather than loop
+        ; fail:
+        ; brk #0
+
+        // ---- Data section ----
+        ; .align 8
+        ; pages_start:
+        ;; ops.push_u64(patch_pages.start as _)
+
+        ; .align 8
+        ; pages_len:
+        ;; ops.push_u64(patch_pages.len() as _)
+
+        ; .align 8
+        ; conn_fd:
+        ;; ops.push_u64(inputs.conn_fd as _)
+
+        ; .align 8
+        ; bridge_fd:
+        ;; ops.push_u64(inputs.bridge_fd as _)
+
+        ; .align 8
+        ; trampoline:
+        ;; ops.push_u64(inputs.region as _)
+
+        ; .align 8
+        ; specialize:
+        ;; ops.push_u64(inputs.specialize_fn as _)
+
+        // `regs[31]`, then sp, as recorded by the uprobe
+        ; .align 8
+        ; entry_regs:
+        ;; ops.extend(misc::as_byte_slice(inputs.entry_regs))
+
+        ; .align 8
+        ; address:
+        ;; ops.extend(misc::as_byte_slice(&address))
+
+        ; .align 8
+        ; message:
+        ;; ops.extend(misc::as_byte_slice(&message))
+
+        ; .align 8
+        ; control:
+        ;; ops.extend(vec![0; control_len])
+    );
+
+    Ok(ops.finalize()?)
+}
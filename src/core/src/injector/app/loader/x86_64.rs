@@ -0,0 +1,294 @@
+use crate::injector::app::loader::{
+    CMSG_DATA_OFFSET, LoaderInputs, MAP_FIXED_NOREPLACE, MSG_CONTROL_OFFSET, MSG_CONTROLLEN_OFFSET,
+    fd_message, socket_address,
+};
+use crate::misc;
+use anyhow::Result;
+use dynasmrt::x64::X64Relocation;
+use dynasmrt::{DynasmApi, DynasmLabelApi, VecAssembler, dynasm};
+use nix::libc::{
+    AF_UNIX, MADV_DONTNEED, MAP_ANONYMOUS, MAP_PRIVATE, PROT_EXEC, PROT_READ, PROT_WRITE,
+    SOCK_SEQPACKET,
+};
+use std::ops::Range;
+use syscalls::Sysno;
+
+/// Rewind the registers to the entry of the specialize function by popping them from the
+/// recorded `struct pt_regs` at the `entry_regs` label, whose layout starts with `r15` and
+/// ends with `rsp` and `ss`, and jump to the address at the `target` label.
+fn rewind(ops: &mut VecAssembler<X64Relocation>) {
+    dynasm!(ops
+        ; .arch x64
+        ; lea rsp, [>entry_regs]
+        ; pop r15
+        ; pop r14
+        ; pop r13
+        ; pop r12
+        ; pop rbp
+        ; pop rbx
+        ; pop r11
+        ; pop r10
+        ; pop r9
+        ; pop r8
+        ; pop rax
+        ; pop rcx
+        ; pop rdx
+        ; pop rsi
+        ; pop rdi
+        // skip `orig_rax`, `rip`, `cs` and `eflags`
+        ; mov rsp, QWORD [rsp + 32]
+        ; jmp QWORD [>target]
+    );
+}
+
+/// Same steps as the AArch64 first stage, without cache maintenance.
+pub fn assemble_loader(
+    inputs: &LoaderInputs,
+    image: &[u8],
+    resume_addr: usize,
+    fallback: &[u8],
+) -> Result<Vec<u8>> {
+    let mut ops: VecAssembler<X64Relocation> = VecAssembler::new(0);
+
+    dynasm!(ops
+        ; .arch x64
+
+        // Step 1: Map the trampoline region at the free range picked by the daemon
+        ; mov eax, Sysno::mmap as i32
+        ; mov rdi, QWORD [>region]
+        ; mov rsi, QWORD [>region_size]
+        ; mov edx, PROT_READ | PROT_WRITE | PROT_EXEC
+        ; mov r10d, MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED_NOREPLACE
+        ; mov r8, -1
+        ; xor r9d, r9d
+        ; syscall
+        ; cmp rax, rdi
+        ; jne >fail
+
+        // Step 2: Copy the image, the trampoline followed by the second stage
+        ; cld
+        ; lea rsi, [>image]
+        ; mov rcx, QWORD [>image_len]
+        ; rep movsb
+
+        // Step 3: Jump to the second stage
+        ; jmp QWORD [>resume]
+
+        // The region is taken, or mapped elsewhere by a kernel ignoring MAP_FIXED_NOREPLACE.
+        // Run the fallback from anywhere, it can't run from here: it drops this page
+        ; fail:
+        ; cmp rax, -4095
+        ; jb >stub
+        ; mov eax, Sysno::mmap as i32
+        ; xor edi, edi
+        ; mov rsi, QWORD [>fallback_len]
+        ; mov edx, PROT_READ | PROT_WRITE | PROT_EXEC
+        ; mov r10d, MAP_PRIVATE | MAP_ANONYMOUS
+        ; mov r8, -1
+        ; xor r9d, r9d
+        ; syscall
+        ; cmp rax, -4095
+        ; jae >crash
+        ; stub:
+        ; cld
+        ; mov rdi, rax
+        ; lea rsi, [>fallback]
+        ; mov rcx, QWORD [>fallback_len]
+        ; rep movsb
+        ; jmp rax
+
+        // Out of memory, nothing can be restored from here
+        ; crash:
+        ; ud2
+
+        // ---- Data section ----
+        ; .align 8
+        ; region:
+        ;; ops.push_u64(inputs.region as _)
+
+        ; .align 8
+        ; region_size:
+        ;; ops.push_u64(inputs.region_size as _)
+
+        ; .align 8
+        ; image_len:
+        ;; ops.push_u64(image.len() as _)
+
+        ; .align 8
+        ; resume:
+        ;; ops.push_u64(resume_addr as _)
+
+        ; .align 8
+        ; fallback_len:
+        ;; ops.push_u64(fallback.len() as _)
+
+        ; .align 8
+        ; image:
+        ;; ops.extend(image)
+
+        ; .align 8
+        ; fallback:
+        ;; ops.extend(fallback)
+    );
+
+    Ok(ops.finalize()?)
+}
+
+/// Same as the AArch64 fallback.
+pub fn assemble_fallback(inputs: &LoaderInputs, patch_pages: &Range<usize>) -> Result<Vec<u8>> {
+    let mut ops: VecAssembler<X64Relocation> = VecAssembler::new(0);
+
+    dynasm!(ops
+        ; .arch x64
+        ; mov eax, Sysno::madvise as i32
+        ; mov rdi, QWORD [>pages_start]
+        ; mov rsi, QWORD [>pages_len]
+        ; mov edx, MADV_DONTNEED
+        ; syscall
+        ; test rax, rax
+        ; jnz >fail
+    );
+
+    rewind(&mut ops);
+
+    dynasm!(ops
+        ; .arch x64
+
+        // see `assemble_resume`
+        ; fail:
+        ; ud2
+
+        // ---- Data section ----
+        ; .align 8
+        ; pages_start:
+        ;; ops.push_u64(patch_pages.start as _)
+
+        ; .align 8
+        ; pages_len:
+        ;; ops.push_u64(patch_pages.len() as _)
+
+        ; .align 8
+        ; target:
+        ;; ops.push_u64(inputs.specialize_fn as _)
+
+        ; .align 8
+        ; entry_regs:
+        ;; ops.extend(misc::as_byte_slice(inputs.entry_regs))
+    );
+
+    Ok(ops.finalize()?)
+}
+
+/// Same steps as the AArch64 second stage, see [`rewind`] for the registers.
+pub fn assemble_resume(inputs: &LoaderInputs, patch_pages: &Range<usize>) -> Result<Vec<u8>> {
+    let mut ops: VecAssembler<X64Relocation> = VecAssembler::new(0);
+
+    let (address, address_len) = socket_address(inputs.socket_name)?;
+    let (message, control_len) = fd_message();
+
+    dynasm!(ops
+        ; .arch x64
+
+        // Step 1: Drop the patched pages, they're reloaded from the file
+        ; mov eax, Sysno::madvise as i32
+        ; mov rdi, QWORD [>pages_start]
+        ; mov rsi, QWORD [>pages_len]
+        ; mov edx, MADV_DONTNEED
+        ; syscall
+        ; test rax, rax
+        ; jnz >fail
+
+        // Step 2: Connect to the daemon, the socket must get the fd expected by the trampoline
+        ; mov eax, Sysno::socket as i32
+        ; mov edi, AF_UNIX
+        ; mov esi, SOCK_SEQPACKET
+        ; xor edx, edx
+        ; syscall
+        ; mov rbx, rax
+        ; cmp rax, inputs.conn_fd
+        ; jne >fallback
+
+        ; mov eax, Sysno::connect as i32
+        ; mov rdi, rbx
+        ; lea rsi, [>address]
+        ; mov edx, address_len as i32
+        ; syscall
+        ; test rax, rax
+        ; jnz >fallback
+
+        // Step 3: Receive the bridge fd, it must get the fd expected by the trampoline too
+        ; lea rsi, [>message]
+        ; lea rax, [>control]
+        ; mov [rsi + MSG_CONTROL_OFFSET as i32], rax
+        ; mov eax, Sysno::recvmsg as i32
+        ; mov rdi, rbx
+        ; xor edx, edx
+        ; syscall
+        ; test rax, rax
+        ; js >fallback
+        ; cmp QWORD [rsi + MSG_CONTROLLEN_OFFSET as i32], 0
+        ; je >fallback
+        ; lea rax, [>control]
+        ; cmp DWORD [rax + CMSG_DATA_OFFSET as i32], inputs.bridge_fd
+        ; jne >fallback
+        ; jmp >rewind
+
+        // Leave the embryo uninjected, the region is leaked
+        ; fallback:
+        ; mov eax, Sysno::close as i32
+        ; mov rdi, rbx
+        ; syscall
+        ; mov rax, QWORD [>specialize]
+        ; mov QWORD [>target], rax
+
+        // Step 4: Rewind the registers to the entry of the specialize function and jump to
+        // the target, the trampoline unless overwritten above
+        ; rewind:
+    );
+
+    rewind(&mut ops);
+
+    dynasm!(ops
+        ; .arch x64
+
+        // Only locked pages can't be dropped, the daemon refuses to inject if any memory is
+        // locked. The patched code would run again otherwise, crash rather than loop
+        ; fail:
+        ; ud2
+
+        // ---- Data section ----
+        ; .align 8
+        ; pages_start:
+        ;; ops.push_u64(patch_pages.start as _)
+
+        ; .align 8
+        ; pages_len:
+        ;; ops.push_u64(patch_pages.len() as _)
+
+        ; .align 8
+        ; specialize:
+        ;; ops.push_u64(inputs.specialize_fn as _)
+
+        ; .align 8
+        ; target:
+        ;; ops.push_u64(inputs.region as _)
+
+        ; .align 8
+        ; entry_regs:
+        ;; ops.extend(misc::as_byte_slice(inputs.entry_regs))
+
+        ; .align 8
+        ; address:
+        ;; ops.extend(misc::as_byte_slice(&address))
+
+        ; .align 8
+        ; message:
+        ;; ops.extend(misc::as_byte_slice(&message))
+
+        ; .align 8
+        ; control:
+        ;; ops.extend(vec![0; control_len])
+    );
+
+    Ok(ops.finalize()?)
+}
@@ -0,0 +1,150 @@
+use crate::config::ZynxConfigs;
+use crate::metrics::Metrics;
+use anyhow::{Context, Result, bail};
+use nix::unistd::{Gid, Pid};
+use once_cell::sync::Lazy;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, thread};
+use tokio::runtime::Handle;
+use tokio::sync::{Semaphore, mpsc, oneshot};
+use tokio::time;
+use tracing::{Instrument, Span};
+use zynx_bridge_shared::zygote::arrays::DataInfo;
+
+/// Embryos traced at the same time, the others wait stopped in the queue
+static TRACER_THREADS: Lazy<Arc<Semaphore>> =
+    Lazy::new(|| Arc::new(Semaphore::new(ZynxConfigs::instance().max_tracers.max(1))));
+
+/// The embryo waited too long for a tracer thread, and must be released untraced
+#[derive(Debug)]
+pub struct QueueOverflow(pub Pid);
+
+impl Display for QueueOverflow {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "embryo {} waited too long for a tracer thread", self.0)
+    }
+}
+
+impl std::error::Error for QueueOverflow {}
+
+/// Counts an embryo in the queue depth while alive
+struct Queued;
+
+impl Queued {
+    fn new() -> Self {
+        Metrics::instance().on_queued();
+        Self
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        Metrics::instance().on_dequeued();
+    }
+}
+
+/// Args of the embryo only read for slow checks
+#[derive(Default)]
+pub struct SlowArgs {
+    pub nice_name: Option<String>,
+    pub app_data_dir: Option<String>,
+    /// Supplementary gids, as passed to `SpecializeCommon`
+    pub gids: Option<Vec<Gid>>,
+    /// Data dirs of the packages sharing the process, to be bind-mounted by `SpecializeCommon`
+    pub pkg_data_info_list: Option<Vec<DataInfo>>,
+}
+
+/// Work a policy check needs done on the embryo, only possible from its tracer thread.
+pub enum TraceeRequest {
+    SlowArgs(oneshot::Sender<Result<SlowArgs>>),
+}
+
+/// Handle of a policy check task to the tracer thread of its embryo.
+pub struct Tracee {
+    requests: mpsc::Sender<TraceeRequest>,
+}
+
+impl Tracee {
+    pub async fn slow_args(&self) -> Result<SlowArgs> {
+        let (tx, rx) = oneshot::channel();
+
+        self.requests
+            .send(TraceeRequest::SlowArgs(tx))
+            .await
+            .ok()
+            .context("tracer thread is gone")?;
+
+        rx.await.context("tracer thread is gone")?
+    }
+}
+
+/// Run `f` on a dedicated thread, which stays the tracer of `pid` throughout, once one of
+/// the bounded tracer threads is available. Returns when `f` does, or [`QueueOverflow`]
+/// without running it if none got available within `--cfg-queue-timeout-ms`.
+pub async fn spawn_tracer<F>(pid: Pid, f: F) -> Result<()>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let queue_timeout = Duration::from_millis(ZynxConfigs::instance().queue_timeout_ms);
+    let queued = Queued::new();
+
+    let Ok(permit) = time::timeout(queue_timeout, TRACER_THREADS.clone().acquire_owned()).await
+    else {
+        bail!(QueueOverflow(pid));
+    };
+
+    drop(queued);
+
+    let permit = permit?;
+    let handle = Handle::current();
+    let (tx, rx) = oneshot::channel();
+
+    thread::Builder::new()
+        .name(format!("embryo-{pid}"))
+        .spawn(move || {
+            let _permit = permit;
+            let _runtime = handle.enter();
+
+            f();
+
+            let _ = tx.send(());
+        })?;
+
+    rx.await.context("tracer thread panicked")
+}
+
+/// Run the policy check made by `check` as a task on the runtime, serving its requests with
+/// `serve` on the current tracer thread meanwhile, instead of blocking it on the check.
+pub fn run_check<T, F>(
+    check: impl FnOnce(Tracee) -> F,
+    mut serve: impl FnMut(TraceeRequest),
+) -> Result<T>
+where
+    T: Send + 'static,
+    F: Future<Output = Result<T>> + Send + 'static,
+{
+    let (requests_tx, mut requests_rx) = mpsc::channel(1);
+    let (result_tx, result_rx) = oneshot::channel();
+    let check = check(Tracee {
+        requests: requests_tx,
+    });
+
+    // the check logs in the span of the injection, as if it ran on this thread
+    Handle::current().spawn(
+        async move {
+            let _ = result_tx.send(check.await);
+        }
+        .instrument(Span::current()),
+    );
+
+    // closed once the check is done with the tracee
+    while let Some(request) = requests_rx.blocking_recv() {
+        serve(request);
+    }
+
+    result_rx
+        .blocking_recv()
+        .context("policy check task panicked")?
+}
@@ -1,26 +1,45 @@
-mod debugger;
-mod liteloader;
+mod allowlist;
+pub mod caps;
+pub mod debugger;
+pub mod decision_cache;
+mod denylist;
+pub mod liteloader;
+mod package_list;
 #[cfg(feature = "zygisk")]
 mod zygisk;
 
-use crate::android::packages::PackageInfoListLocked;
+use crate::android::packages::PackageInfoList;
+use crate::config::ZynxConfigs;
+use crate::injector::app::pipeline::SlowArgs;
+use crate::injector::app::policy::allowlist::AllowlistPolicyProvider;
 use crate::injector::app::policy::debugger::DebuggerPolicyProvider;
+use crate::injector::app::policy::decision_cache::DecisionCache;
+use crate::injector::app::policy::denylist::DenylistPolicyProvider;
 use crate::injector::app::policy::liteloader::LiteLoaderPolicyProvider;
 #[cfg(feature = "zygisk")]
 use crate::injector::app::policy::zygisk::ZygiskPolicyProvider;
+use crate::metrics::Metrics;
+use crate::quarantine::Quarantine;
 use anyhow::{Result, anyhow, bail};
 use async_trait::async_trait;
 use futures::future;
-use log::warn;
 use nix::unistd::{Gid, Uid};
 use std::any::Any;
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::HashSet;
 use std::fmt::{Debug, Formatter};
 use std::ops::Deref;
 use std::os::fd::OwnedFd;
 use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use std::{fmt, mem};
-use zynx_bridge_shared::zygote::ProviderType;
+use tokio::time;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+#[cfg(feature = "zygisk")]
+pub use zygisk::zygisk_companion;
+use zynx_bridge_shared::zygote::arrays::DataInfo;
+use zynx_bridge_shared::zygote::{ArgsMutation, ProviderType};
 
 static POLICY_PROVIDER_MANAGER: OnceLock<PolicyProviderManager> = OnceLock::new();
 
@@ -29,52 +48,62 @@ pub mod proto {
 }
 
 #[allow(unused)]
-pub struct EmbryoCheckArgsFast<'a> {
+pub struct EmbryoCheckArgsFast {
     pub uid: Uid,
     pub gid: Gid,
     pub is_system_server: bool,
     pub is_child_zygote: bool,
-    pub package_info: Option<PackageInfoListLocked<'a>>,
+    /// Whether the embryo was forked from an app zygote instead of a system zygote
+    pub from_app_zygote: bool,
+    /// Whether the embryo already contains the bridge, inherited from an injected app zygote
+    pub inherits_bridge: bool,
+    pub package_info: Option<PackageInfoList>,
 }
 
 #[allow(unused)]
-pub struct EmbryoCheckArgsSlow<'a> {
-    fast_args: EmbryoCheckArgsFast<'a>,
+pub struct EmbryoCheckArgsSlow {
+    fast_args: EmbryoCheckArgsFast,
     pub nice_name: Option<String>,
     pub app_data_dir: Option<String>,
+    pub gids: Option<Vec<Gid>>,
+    pub pkg_data_info_list: Option<Vec<DataInfo>>,
 }
 
-impl<'a> Deref for EmbryoCheckArgsSlow<'a> {
-    type Target = EmbryoCheckArgsFast<'a>;
+impl Deref for EmbryoCheckArgsSlow {
+    type Target = EmbryoCheckArgsFast;
 
     fn deref(&self) -> &Self::Target {
         &self.fast_args
     }
 }
 
-pub enum EmbryoCheckArgs<'a> {
-    Fast(EmbryoCheckArgsFast<'a>),
-    Slow(EmbryoCheckArgsSlow<'a>),
+pub enum EmbryoCheckArgs {
+    Fast(EmbryoCheckArgsFast),
+    Slow(EmbryoCheckArgsSlow),
 }
 
-impl<'a> EmbryoCheckArgs<'a> {
+impl EmbryoCheckArgs {
     pub fn new_fast(
         uid: Uid,
         gid: Gid,
         is_system_server: bool,
         is_child_zygote: bool,
-        package_info: Option<PackageInfoListLocked<'a>>,
+        from_app_zygote: bool,
+        inherits_bridge: bool,
+        package_info: Option<PackageInfoList>,
     ) -> Self {
         EmbryoCheckArgs::Fast(EmbryoCheckArgsFast {
             uid,
             gid,
             is_system_server,
             is_child_zygote,
+            from_app_zygote,
+            inherits_bridge,
             package_info,
         })
     }
 
-    pub fn into_slow(self, nice_name: Option<String>, app_data_dir: Option<String>) -> Self {
+    pub fn into_slow(self, slow_args: SlowArgs) -> Self {
         EmbryoCheckArgs::Slow(EmbryoCheckArgsSlow {
             fast_args: match self {
                 EmbryoCheckArgs::Fast(args) => args,
@@ -83,8 +112,10 @@ impl<'a> EmbryoCheckArgs<'a> {
                     return Self::Slow(args);
                 }
             },
-            nice_name,
-            app_data_dir,
+            nice_name: slow_args.nice_name,
+            app_data_dir: slow_args.app_data_dir,
+            gids: slow_args.gids,
+            pkg_data_info_list: slow_args.pkg_data_info_list,
         })
     }
 
@@ -96,7 +127,7 @@ impl<'a> EmbryoCheckArgs<'a> {
     //     !self.is_fast()
     // }
 
-    pub fn assume_fast(&self) -> &EmbryoCheckArgsFast<'a> {
+    pub fn assume_fast(&self) -> &EmbryoCheckArgsFast {
         if let EmbryoCheckArgs::Fast(args) = self {
             return args;
         }
@@ -104,7 +135,7 @@ impl<'a> EmbryoCheckArgs<'a> {
         panic!("unexpected check args: expected `fast` but got `slow`");
     }
 
-    pub fn assume_slow(&self) -> &EmbryoCheckArgsSlow<'a> {
+    pub fn assume_slow(&self) -> &EmbryoCheckArgsSlow {
         if let EmbryoCheckArgs::Slow(args) = self {
             return args;
         }
@@ -113,8 +144,8 @@ impl<'a> EmbryoCheckArgs<'a> {
     }
 }
 
-impl<'a> Deref for EmbryoCheckArgs<'a> {
-    type Target = EmbryoCheckArgsFast<'a>;
+impl Deref for EmbryoCheckArgs {
+    type Target = EmbryoCheckArgsFast;
 
     fn deref(&self) -> &Self::Target {
         match self {
@@ -128,6 +159,10 @@ impl<'a> Deref for EmbryoCheckArgs<'a> {
 pub struct Attachment {
     pub fd: Option<Arc<OwnedFd>>,
     pub data: Option<Vec<u8>>,
+    /// Module this attachment loads, as `<provider>:<name>`, for crash quarantine
+    pub module: Option<String>,
+    /// The fd is a library memfd, labeled for the domain of each embryo it's sent to
+    pub library: bool,
 }
 
 impl Attachment {
@@ -135,6 +170,8 @@ impl Attachment {
         Self {
             fd: Some(fd),
             data: None,
+            module: None,
+            library: false,
         }
     }
 
@@ -142,6 +179,8 @@ impl Attachment {
         Self {
             fd: None,
             data: Some(data),
+            module: None,
+            library: false,
         }
     }
 
@@ -149,6 +188,52 @@ impl Attachment {
         Self {
             fd: Some(fd),
             data: Some(data),
+            module: None,
+            library: false,
+        }
+    }
+
+    pub fn module(mut self, name: String) -> Self {
+        self.module = Some(name);
+        self
+    }
+
+    pub fn library(mut self) -> Self {
+        self.library = true;
+        self
+    }
+}
+
+/// Kind of a policy provider, either one whose bundles reach the bridge, or an access list
+/// only ever vetoing injection in core, kept out of the wire [`ProviderType`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ProviderKind {
+    Bridge(ProviderType),
+    Denylist,
+    /// Strict mode
+    Allowlist,
+}
+
+impl ProviderKind {
+    /// Whether the provider is an access list, whose decisions may veto injection.
+    pub fn vetoes(&self) -> bool {
+        !matches!(self, ProviderKind::Bridge(_))
+    }
+}
+
+impl From<ProviderType> for ProviderKind {
+    fn from(ty: ProviderType) -> Self {
+        ProviderKind::Bridge(ty)
+    }
+}
+
+// named as the provider alone in logs, metrics and traces
+impl Debug for ProviderKind {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderKind::Bridge(ty) => ty.fmt(fmt),
+            ProviderKind::Denylist => fmt.write_str("Denylist"),
+            ProviderKind::Allowlist => fmt.write_str("Allowlist"),
         }
     }
 }
@@ -158,29 +243,28 @@ pub struct ProviderBundle {
     pub ty: ProviderType,
     pub attachments: Vec<Attachment>,
     pub data: Option<Vec<u8>>,
+    pub mutation: Option<ArgsMutation>,
 }
 
 pub enum PolicyDecision {
     Allow {
         data: Option<Vec<u8>>,
         attachments: Option<Vec<Attachment>>,
+        /// Applied to the specialize args by the bridge, even with nothing to load
+        mutation: Option<ArgsMutation>,
     },
     MoreInfo(Option<Box<dyn Any + Send + Sync>>),
     Deny,
+    /// Deny injection for all providers, overrides any `Allow`
+    Veto,
 }
 
 impl PolicyDecision {
-    pub fn allow() -> Self {
-        PolicyDecision::Allow {
-            data: None,
-            attachments: None,
-        }
-    }
-
     pub fn allow_with_attachments(attachments: Vec<Attachment>) -> Self {
         PolicyDecision::Allow {
             data: None,
             attachments: Some(attachments),
+            mutation: None,
         }
     }
 
@@ -188,20 +272,41 @@ impl PolicyDecision {
         PolicyDecision::Allow {
             data: Some(data),
             attachments: None,
+            mutation: None,
         }
     }
+
+    /// Also request `mutation` of the specialize args, if allowed and valid.
+    pub fn with_mutation(mut self, new_mutation: ArgsMutation) -> Self {
+        if let Err(err) = new_mutation.validate() {
+            warn!("ignoring mutation {new_mutation:?}: {err}");
+            return self;
+        }
+
+        if let PolicyDecision::Allow { mutation, .. } = &mut self {
+            *mutation = Some(new_mutation);
+        }
+
+        self
+    }
 }
 
 impl Debug for PolicyDecision {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            PolicyDecision::Allow { data, attachments } => fmt
+            PolicyDecision::Allow {
+                data,
+                attachments,
+                mutation,
+            } => fmt
                 .debug_struct("Allow")
                 .field("attachments", &attachments.as_ref().map(|a| a.len()))
                 .field("data", &data.as_ref().map(|d| d.len()))
+                .field("mutation", mutation)
                 .finish(),
             PolicyDecision::MoreInfo(_) => fmt.write_str("MoreInfo(...)"),
             PolicyDecision::Deny => fmt.write_str("Deny"),
+            PolicyDecision::Veto => fmt.write_str("Veto"),
         }
     }
 }
@@ -214,17 +319,50 @@ pub struct PolicyDecisions {
 
 #[async_trait]
 pub trait PolicyProvider: Send + Sync {
-    fn provider_type(&self) -> ProviderType;
+    fn kind(&self) -> ProviderKind;
 
     async fn init(&self) -> Result<()> {
         Ok(())
     }
 
-    async fn check(&self, args: &EmbryoCheckArgs<'_>) -> PolicyDecision;
+    /// Whether this provider wants to be consulted for processes forked from app zygotes.
+    /// Providers that don't opt in are treated as `Deny` for those processes.
+    fn accepts_app_zygote_children(&self) -> bool {
+        false
+    }
+
+    /// Whether this provider wants to be consulted for processes that already contain the
+    /// bridge, inherited from an injected app zygote. Providers that don't opt in are treated
+    /// as `Deny` for those processes, so their modules don't get loaded twice.
+    fn accepts_bridge_inheritors(&self) -> bool {
+        false
+    }
+
+    /// Providers are consulted and aggregated in descending priority order: the highest
+    /// priority veto is the one reported, and its data wins when several providers of
+    /// the same type allow.
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    /// Whether fast check decisions of this provider may be reused for a while, see
+    /// [`DecisionCache`]. Such providers must invalidate the cache when their state changes.
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    /// Uids of the apps this provider may allow, `None` if it can't tell before their embryos
+    /// are checked. Forks of other apps aren't stopped when every provider can tell, see
+    /// [`PolicyProviderManager::target_uids`].
+    fn target_uids(&self) -> Option<HashSet<Uid>> {
+        None
+    }
+
+    async fn check(&self, args: &EmbryoCheckArgs) -> PolicyDecision;
 
     async fn recheck(
         &self,
-        args: &EmbryoCheckArgs<'_>,
+        args: &EmbryoCheckArgs,
         _state: Box<dyn Any + Send + Sync>,
     ) -> PolicyDecision {
         self.check(args).await
@@ -240,6 +378,8 @@ impl PolicyProviderManager {
     pub async fn init() -> Result<()> {
         let mut instance = Self::default();
 
+        instance.register::<DenylistPolicyProvider>().await?;
+        instance.register::<AllowlistPolicyProvider>().await?;
         instance.register::<DebuggerPolicyProvider>().await?;
         instance.register::<LiteLoaderPolicyProvider>().await?;
 
@@ -257,18 +397,125 @@ impl PolicyProviderManager {
         let provider = P::default();
 
         provider.init().await?;
-        self.providers.push(Box::new(provider));
+        self.add(Box::new(provider));
 
         Ok(())
     }
 
+    fn add(&mut self, provider: Box<dyn PolicyProvider>) {
+        self.providers.push(provider);
+
+        // stable sort, providers with the same priority keep their registration order
+        self.providers
+            .sort_by_key(|provider| Reverse(provider.priority()));
+    }
+
     pub fn instance() -> &'static Self {
         POLICY_PROVIDER_MANAGER.wait()
     }
 
-    /// Run fast check on all providers concurrently.
-    pub async fn check(&self, args: &EmbryoCheckArgs<'_>) -> PolicyDecisions {
-        let futures: Vec<_> = self.providers.iter().map(|p| p.check(args)).collect();
+    /// Kinds of the providers, in the order of their decisions.
+    pub fn kinds(&self) -> Vec<ProviderKind> {
+        self.providers
+            .iter()
+            .map(|provider| provider.kind())
+            .collect()
+    }
+
+    /// Uids of the apps any provider may allow, `None` if some provider can't tell.
+    pub fn target_uids(&self) -> Option<HashSet<Uid>> {
+        let mut uids = HashSet::new();
+
+        for provider in &self.providers {
+            let Some(targets) = provider.target_uids() else {
+                debug!("{:?} can't tell its target uids", provider.kind());
+                return None;
+            };
+
+            uids.extend(targets);
+        }
+
+        Some(uids)
+    }
+
+    /// Deadline of a check starting now on the fork critical path, shared by `check` and
+    /// `recheck_slow`, none if the budget is disabled.
+    pub fn deadline() -> Option<Instant> {
+        let budget = ZynxConfigs::instance().check_budget_ms;
+        (budget > 0).then(|| Instant::now() + Duration::from_millis(budget))
+    }
+
+    /// Wait for the decision of `provider`, treated as `Deny` if it isn't made by `deadline`,
+    /// or as `Veto` for an access list, so that a slow list fails closed.
+    async fn bounded(
+        provider: &dyn PolicyProvider,
+        deadline: Option<Instant>,
+        decision: impl Future<Output = PolicyDecision>,
+    ) -> PolicyDecision {
+        let kind = provider.kind();
+        let start = Instant::now();
+
+        let decision = match deadline {
+            Some(deadline) => match time::timeout_at(deadline, decision).await {
+                Ok(decision) => decision,
+                Err(_) => {
+                    let decision = if kind.vetoes() {
+                        PolicyDecision::Veto
+                    } else {
+                        PolicyDecision::Deny
+                    };
+
+                    warn!(
+                        "{kind:?} exceeded the check budget after {:.2?}, treating as {decision:?}",
+                        start.elapsed()
+                    );
+                    return decision;
+                }
+            },
+            None => decision.await,
+        };
+
+        debug!("{kind:?} decided {decision:?} in {:.2?}", start.elapsed());
+
+        decision
+    }
+
+    /// Run fast check on all providers concurrently, until `deadline` if any.
+    pub async fn check(
+        &self,
+        args: &EmbryoCheckArgs,
+        deadline: Option<Instant>,
+    ) -> PolicyDecisions {
+        let cache = DecisionCache::instance();
+
+        let futures: Vec<_> = self
+            .providers
+            .iter()
+            .map(|p| async move {
+                if args.from_app_zygote && !p.accepts_app_zygote_children() {
+                    return PolicyDecision::Deny;
+                }
+                if args.inherits_bridge && !p.accepts_bridge_inheritors() {
+                    return PolicyDecision::Deny;
+                }
+                if !p.cacheable() {
+                    return Self::bounded(p.as_ref(), deadline, p.check(args)).await;
+                }
+
+                if let Some(decision) = cache.get(args, p.kind()) {
+                    return decision;
+                }
+
+                let decision = Self::bounded(p.as_ref(), deadline, p.check(args)).await;
+
+                // a decision forced by the budget says nothing about the app
+                if deadline.is_none_or(|deadline| Instant::now() < deadline) {
+                    cache.insert(args, p.kind(), &decision);
+                }
+
+                decision
+            })
+            .collect();
 
         let decisions = future::join_all(futures).await;
         let more_info = decisions
@@ -281,9 +528,14 @@ impl PolicyProviderManager {
         }
     }
 
-    /// Re-check providers that returned MoreInfo with slow (full) args.
+    /// Re-check providers that returned MoreInfo with slow (full) args, until `deadline` if any.
     /// Cached state from the fast check is forwarded to `recheck` when available.
-    pub async fn recheck_slow(&self, args: &EmbryoCheckArgs<'_>, result: &mut PolicyDecisions) {
+    pub async fn recheck_slow(
+        &self,
+        args: &EmbryoCheckArgs,
+        result: &mut PolicyDecisions,
+        deadline: Option<Instant>,
+    ) {
         result.more_info = false;
 
         // Extract MoreInfo decisions along with their cached state,
@@ -307,9 +559,10 @@ impl PolicyProviderManager {
         let futures: Vec<_> = recheck_items
             .into_iter()
             .map(|(i, state)| async move {
+                let provider = self.providers[i].as_ref();
                 let decision = match state {
-                    Some(s) => self.providers[i].recheck(args, s).await,
-                    None => self.providers[i].check(args).await,
+                    Some(s) => Self::bounded(provider, deadline, provider.recheck(args, s)).await,
+                    None => Self::bounded(provider, deadline, provider.check(args)).await,
                 };
                 (i, decision)
             })
@@ -328,32 +581,298 @@ impl PolicyProviderManager {
         }
     }
 
+    /// Providers which would inject given `decisions`, like [`Self::aggregate`] without its
+    /// side effects, or `Err` with the vetoing provider. Undecided providers count as denying.
+    pub fn dry_run(&self, decisions: &[PolicyDecision]) -> Result<Vec<ProviderKind>, ProviderKind> {
+        let mut allowing = Vec::new();
+
+        for (provider, decision) in self.providers.iter().zip(decisions) {
+            match decision {
+                PolicyDecision::Veto => return Err(provider.kind()),
+                PolicyDecision::Allow { .. } => allowing.push(provider.kind()),
+                PolicyDecision::Deny | PolicyDecision::MoreInfo(_) => {}
+            }
+        }
+
+        Ok(allowing)
+    }
+
     /// Aggregate decisions from all policy providers.
-    /// Returns None if all denied, Some(bundles) if injection allowed.
+    /// Returns None if all denied or any vetoed, Some(bundles) if injection allowed.
     pub fn aggregate(&self, decisions: &[PolicyDecision]) -> Option<Vec<ProviderBundle>> {
-        let mut providers: HashMap<ProviderType, ProviderBundle> = HashMap::new();
+        let quarantine = Quarantine::instance();
+        let mut providers: Vec<ProviderBundle> = Vec::new();
+
+        if let Some(i) = decisions
+            .iter()
+            .position(|it| matches!(it, PolicyDecision::Veto))
+        {
+            let kind = self.providers[i].kind();
+
+            info!("injection vetoed by {kind:?}");
+            Metrics::instance().on_policy_denied(kind);
+
+            return None;
+        }
 
         for (i, decision) in decisions.iter().enumerate() {
-            if let PolicyDecision::Allow { data, attachments } = decision {
-                let ty = self.providers[i].provider_type();
-                let entry = providers.entry(ty).or_insert_with(|| ProviderBundle {
-                    ty,
-                    attachments: Vec::new(),
-                    data: None,
+            let kind = self.providers[i].kind();
+
+            // access list providers only ever veto, their `Deny` just means "no objection"
+            let ProviderKind::Bridge(ty) = kind else {
+                continue;
+            };
+
+            if matches!(decision, PolicyDecision::Deny) {
+                Metrics::instance().on_policy_denied(kind);
+            }
+
+            if let PolicyDecision::Allow {
+                data,
+                attachments,
+                mutation,
+            } = decision
+            {
+                let attachments = attachments.as_ref().map(|attachments| {
+                    attachments
+                        .iter()
+                        .filter(|it| {
+                            let quarantined = it
+                                .module
+                                .as_deref()
+                                .is_some_and(|module| quarantine.is_quarantined(module));
+
+                            if quarantined {
+                                debug!("skipping quarantined module: {:?}", it.module);
+                            }
+
+                            !quarantined
+                        })
+                        .cloned()
+                        .collect::<Vec<_>>()
                 });
+
+                // nothing left to load if all the modules are quarantined
+                if data.is_none()
+                    && mutation.is_none()
+                    && attachments.as_ref().is_some_and(|it| it.is_empty())
+                {
+                    continue;
+                }
+
+                let index = match providers.iter().position(|bundle| bundle.ty == ty) {
+                    Some(index) => index,
+                    None => {
+                        providers.push(ProviderBundle {
+                            ty,
+                            attachments: Vec::new(),
+                            data: None,
+                            mutation: None,
+                        });
+                        providers.len() - 1
+                    }
+                };
+                let entry = &mut providers[index];
+
                 if let Some(attachments) = attachments {
-                    entry.attachments.extend(attachments.iter().cloned());
+                    entry.attachments.extend(attachments);
                 }
-                if let Some(data) = data {
+                if let Some(data) = data
+                    && entry.data.is_none()
+                {
                     entry.data = Some(data.clone());
                 }
+                if let Some(mutation) = mutation
+                    && entry.mutation.is_none()
+                {
+                    entry.mutation = Some(mutation.clone());
+                }
             }
         }
 
         if providers.is_empty() {
             None
         } else {
-            Some(providers.into_values().collect())
+            Some(providers)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedProvider {
+        kind: ProviderKind,
+        priority: i32,
+    }
+
+    #[async_trait]
+    impl PolicyProvider for FixedProvider {
+        fn kind(&self) -> ProviderKind {
+            self.kind
+        }
+
+        fn priority(&self) -> i32 {
+            self.priority
+        }
+
+        async fn check(&self, _args: &EmbryoCheckArgs) -> PolicyDecision {
+            PolicyDecision::Deny
+        }
+    }
+
+    fn manager(providers: &[(ProviderKind, i32)]) -> PolicyProviderManager {
+        let mut manager = PolicyProviderManager::default();
+
+        for &(kind, priority) in providers {
+            manager.add(Box::new(FixedProvider { kind, priority }));
+        }
+
+        manager
+    }
+
+    fn allow(data: u8, module: &str) -> PolicyDecision {
+        PolicyDecision::Allow {
+            data: Some(vec![data]),
+            attachments: Some(vec![
+                Attachment::with_data(vec![data]).module(module.into()),
+            ]),
+            mutation: None,
+        }
+    }
+
+    #[test]
+    fn providers_are_sorted_by_priority() {
+        let manager = manager(&[
+            (ProviderType::LiteLoader.into(), 0),
+            (ProviderKind::Denylist, 1000),
+            (ProviderType::Zygisk.into(), 0),
+            (ProviderKind::Allowlist, 900),
+            (ProviderType::Debugger.into(), 10),
+        ]);
+
+        // ties keep their registration order
+        assert_eq!(
+            manager.kinds(),
+            [
+                ProviderKind::Denylist,
+                ProviderKind::Allowlist,
+                ProviderType::Debugger.into(),
+                ProviderType::LiteLoader.into(),
+                ProviderType::Zygisk.into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn veto_overrides_allows() {
+        let manager = manager(&[
+            (ProviderKind::Denylist, 1000),
+            (ProviderType::LiteLoader.into(), 0),
+        ]);
+
+        assert!(
+            manager
+                .aggregate(&[PolicyDecision::Veto, allow(1, "liteloader:a")])
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn nothing_allowed_aggregates_to_none() {
+        let manager = manager(&[
+            (ProviderKind::Allowlist, 900),
+            (ProviderType::LiteLoader.into(), 0),
+        ]);
+
+        assert!(
+            manager
+                .aggregate(&[PolicyDecision::Deny, PolicyDecision::Deny])
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn merge_follows_priority() {
+        let manager = manager(&[
+            (ProviderType::Zygisk.into(), 0),
+            (ProviderType::LiteLoader.into(), 5),
+            (ProviderType::LiteLoader.into(), 10),
+        ]);
+
+        assert_eq!(
+            manager.kinds(),
+            [
+                ProviderKind::Bridge(ProviderType::LiteLoader),
+                ProviderKind::Bridge(ProviderType::LiteLoader),
+                ProviderKind::Bridge(ProviderType::Zygisk),
+            ]
+        );
+
+        let bundles = manager
+            .aggregate(&[
+                allow(1, "liteloader:a"),
+                allow(2, "liteloader:b"),
+                allow(3, "zygisk:c"),
+            ])
+            .unwrap();
+
+        let types: Vec<_> = bundles.iter().map(|bundle| bundle.ty).collect();
+        assert_eq!(types, [ProviderType::LiteLoader, ProviderType::Zygisk]);
+
+        // data of the highest priority wins, attachments are merged in priority order
+        let liteloader = &bundles[0];
+        let modules: Vec<_> = liteloader
+            .attachments
+            .iter()
+            .map(|it| it.module.as_deref().unwrap())
+            .collect();
+
+        assert_eq!(liteloader.data.as_deref(), Some(&[1][..]));
+        assert_eq!(modules, ["liteloader:a", "liteloader:b"]);
+    }
+
+    #[test]
+    fn merge_is_deterministic() {
+        let providers = [
+            (ProviderType::Zygisk.into(), 0),
+            (ProviderType::Debugger.into(), 0),
+            (ProviderType::LiteLoader.into(), 0),
+        ];
+
+        let decisions = || {
+            [
+                allow(1, "zygisk:a"),
+                allow(2, "debugger:b"),
+                allow(3, "liteloader:c"),
+            ]
+        };
+        let first: Vec<_> = manager(&providers)
+            .aggregate(&decisions())
+            .unwrap()
+            .iter()
+            .map(|bundle| (bundle.ty, bundle.data.clone()))
+            .collect();
+
+        for _ in 0..16 {
+            let again: Vec<_> = manager(&providers)
+                .aggregate(&decisions())
+                .unwrap()
+                .iter()
+                .map(|bundle| (bundle.ty, bundle.data.clone()))
+                .collect();
+
+            assert_eq!(again, first);
         }
+
+        assert_eq!(
+            first,
+            [
+                (ProviderType::Zygisk, Some(vec![1])),
+                (ProviderType::Debugger, Some(vec![2])),
+                (ProviderType::LiteLoader, Some(vec![3])),
+            ]
+        );
     }
 }
@@ -0,0 +1,69 @@
+use crate::config::ZynxConfigs;
+use crate::injector::app::policy::package_list::PackageList;
+use crate::injector::app::policy::{EmbryoCheckArgs, PolicyDecision, PolicyProvider, ProviderKind};
+use anyhow::Result;
+use async_trait::async_trait;
+use nix::unistd::Uid;
+use std::collections::HashSet;
+
+const ALLOWLIST_FILE: &str = "/data/adb/zynx/allowlist";
+
+/// Strict mode: vetoes injection into everything that is not on the allowlist, so that
+/// other providers only take effect for a handful of explicitly listed apps.
+pub struct AllowlistPolicyProvider {
+    allowlist: PackageList,
+}
+
+impl Default for AllowlistPolicyProvider {
+    fn default() -> Self {
+        Self {
+            allowlist: PackageList::new(ALLOWLIST_FILE),
+        }
+    }
+}
+
+#[async_trait]
+impl PolicyProvider for AllowlistPolicyProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Allowlist
+    }
+
+    async fn init(&self) -> Result<()> {
+        if ZynxConfigs::instance().strict_mode {
+            self.allowlist.refresh();
+        }
+
+        Ok(())
+    }
+
+    fn accepts_app_zygote_children(&self) -> bool {
+        true
+    }
+
+    fn accepts_bridge_inheritors(&self) -> bool {
+        true
+    }
+
+    /// Never allows, only denies or vetoes.
+    fn target_uids(&self) -> Option<HashSet<Uid>> {
+        Some(HashSet::new())
+    }
+
+    fn priority(&self) -> i32 {
+        900
+    }
+
+    async fn check(&self, args: &EmbryoCheckArgs) -> PolicyDecision {
+        if !ZynxConfigs::instance().strict_mode {
+            return PolicyDecision::Deny;
+        }
+
+        self.allowlist.refresh();
+
+        match self.allowlist.lookup(args) {
+            Some(true) => PolicyDecision::Deny,
+            Some(false) => PolicyDecision::Veto,
+            None => PolicyDecision::MoreInfo(None),
+        }
+    }
+}
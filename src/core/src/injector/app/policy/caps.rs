@@ -0,0 +1,86 @@
+use crate::config::ZynxConfigs;
+use crate::injector::app::policy::{Attachment, ProviderBundle};
+use crate::metrics::Metrics;
+use nix::sys::stat;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::os::fd::AsFd;
+use tracing::warn;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Cap {
+    Count,
+    Bytes,
+}
+
+impl Display for Cap {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Cap::Count => fmt.write_str("count"),
+            Cap::Bytes => fmt.write_str("bytes"),
+        }
+    }
+}
+
+/// Size of the library an attachment loads, `0` if it's not known to core.
+fn library_size(attachment: &Attachment) -> u64 {
+    attachment
+        .fd
+        .as_ref()
+        .and_then(|fd| stat::fstat(fd.as_fd()).ok())
+        .map(|stat| stat.st_size as u64)
+        .unwrap_or(0)
+}
+
+/// Enforce the per-process caps on libraries (attachments loading a module) of aggregated
+/// bundles. Libraries are kept in priority order of their providers, then in the order the
+/// providers listed them; the first one exceeding a cap is dropped along with all the
+/// following ones. Returns the dropped modules.
+pub fn enforce(bundles: &mut Vec<ProviderBundle>) -> Vec<String> {
+    let configs = ZynxConfigs::instance();
+    let (max_count, max_bytes) = (configs.max_libraries, configs.max_library_bytes);
+
+    let mut count = 0;
+    let mut bytes = 0;
+    let mut hit = None;
+    let mut dropped = Vec::new();
+
+    for bundle in bundles.iter_mut() {
+        bundle.attachments.retain(|attachment| {
+            let Some(module) = &attachment.module else {
+                return true;
+            };
+
+            if hit.is_none() {
+                let size = library_size(attachment);
+
+                if max_count != 0 && count + 1 > max_count {
+                    hit = Some(Cap::Count);
+                } else if max_bytes != 0 && bytes + size > max_bytes {
+                    hit = Some(Cap::Bytes);
+                } else {
+                    count += 1;
+                    bytes += size;
+                    return true;
+                }
+            }
+
+            dropped.push(module.clone());
+            false
+        });
+    }
+
+    bundles.retain(|bundle| {
+        bundle.data.is_some() || bundle.mutation.is_some() || !bundle.attachments.is_empty()
+    });
+
+    if let Some(cap) = hit {
+        warn!(
+            "{cap} cap hit ({count} libraries, {bytes} bytes loaded), dropped: {}",
+            dropped.join(", ")
+        );
+        Metrics::instance().on_cap_hit(cap, dropped.len());
+    }
+
+    dropped
+}
@@ -1,21 +1,167 @@
 use crate::android::packages::PackageInfoService;
 use crate::config::ZynxConfigs;
-use crate::injector::app::policy::{EmbryoCheckArgs, PolicyDecision, PolicyProvider};
+use crate::injector::app::policy::{EmbryoCheckArgs, PolicyDecision, PolicyProvider, ProviderKind};
+use crate::misc::rooted;
+use anyhow::{Context, Result, bail};
 use async_trait::async_trait;
+use nix::unistd::Uid;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use regex_lite::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::time::SystemTime;
+use tracing::warn;
 use zynx_bridge_shared::policy::debugger::DebuggerParams;
-use zynx_bridge_shared::zygote::ProviderType;
+use zynx_bridge_shared::zygote::{ArgsMutation, ProviderType};
 use zynx_misc::props::prop_on;
 
+/// Packages forced debuggable, persisting across reboots unlike the system properties.
+///
+/// ```toml
+/// packages = ["com.example.app", "com.example.*"]  # `*` and `?` work as wildcards
+/// ```
+pub const DEBUGGABLE_FILE: &str = "/data/adb/zynx/debuggable.toml";
+
+/// `runtime_flags` the activity manager sets for debuggable apps
+/// https://cs.android.com/android/platform/superproject/main/+/main:frameworks/base/services/core/java/com/android/server/am/ProcessList.java;l=1946;drc=61197364367c9e404c7da6900658f1b16c42d0da
+const DEBUGGABLE_RUNTIME_FLAGS: i32 = 1 // DEBUG_ENABLE_JDWP
+    | (1 << 25) // DEBUG_ENABLE_PTRACE
+    | (1 << 8) // DEBUG_JAVA_DEBUGGABLE
+    | (1 << 1); // DEBUG_ENABLE_CHECKJNI
+
+static DEBUGGABLE: Lazy<Mutex<DebuggableList>> = Lazy::new(Mutex::default);
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct DebuggableFile {
+    packages: Vec<String>,
+}
+
+impl DebuggableFile {
+    fn load() -> Result<Self> {
+        match fs::read_to_string(rooted(DEBUGGABLE_FILE)) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = rooted(DEBUGGABLE_FILE);
+        let temp = path.with_extension("tmp");
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&temp, toml::to_string(self)?)?;
+        fs::rename(&temp, &path)?;
+
+        Ok(())
+    }
+}
+
+/// Patterns of [`DEBUGGABLE_FILE`], reloaded when it changes
+#[derive(Default)]
+struct DebuggableList {
+    mtime: Option<SystemTime>,
+    patterns: Vec<Regex>,
+}
+
+impl DebuggableList {
+    fn refresh(&mut self) {
+        let mtime = fs::metadata(rooted(DEBUGGABLE_FILE))
+            .and_then(|meta| meta.modified())
+            .ok();
+
+        if self.mtime == mtime {
+            return;
+        }
+
+        self.mtime = mtime;
+        self.patterns = match DebuggableFile::load() {
+            Ok(file) => file
+                .packages
+                .iter()
+                .filter_map(|pattern| {
+                    let regex = regex_lite::escape(pattern)
+                        .replace(r"\*", ".*")
+                        .replace(r"\?", ".");
+
+                    Regex::new(&format!("^{regex}$"))
+                        .inspect_err(|err| warn!("invalid debuggable pattern {pattern}: {err}"))
+                        .ok()
+                })
+                .collect(),
+            Err(err) => {
+                warn!("failed to load {DEBUGGABLE_FILE}: {err:?}");
+                Vec::new()
+            }
+        };
+    }
+
+    fn contains(&mut self, package: &str) -> bool {
+        self.refresh();
+        self.patterns.iter().any(|regex| regex.is_match(package))
+    }
+}
+
+/// Forced debuggable by its system property, or by [`DEBUGGABLE_FILE`].
+fn is_forced_debuggable(package: &str) -> bool {
+    prop_on(&format!("debug.zynx.debuggable.{package}")) || DEBUGGABLE.lock().contains(package)
+}
+
+/// Print the packages forced debuggable, after adding or removing one.
+pub fn manage_debuggable(add: Option<&str>, remove: Option<&str>) -> Result<()> {
+    let mut file = DebuggableFile::load().context(format!("failed to read {DEBUGGABLE_FILE}"))?;
+
+    if let Some(package) = add
+        && !file.packages.iter().any(|it| it == package)
+    {
+        file.packages.push(package.into());
+        file.save()?;
+    }
+
+    if let Some(package) = remove {
+        let len = file.packages.len();
+        file.packages.retain(|it| it != package);
+
+        if file.packages.len() == len {
+            bail!("not forced debuggable: {package}");
+        }
+
+        file.save()?;
+    }
+
+    if file.packages.is_empty() {
+        println!("no package forced debuggable");
+    }
+
+    for package in &file.packages {
+        println!("{package}");
+    }
+
+    Ok(())
+}
+
 #[derive(Default)]
 pub struct DebuggerPolicyProvider;
 
 #[async_trait]
 impl PolicyProvider for DebuggerPolicyProvider {
-    fn provider_type(&self) -> ProviderType {
-        ProviderType::Debugger
+    fn kind(&self) -> ProviderKind {
+        ProviderType::Debugger.into()
+    }
+
+    /// Forced packages may be added at any time, so targets are only known when disabled.
+    fn target_uids(&self) -> Option<HashSet<Uid>> {
+        (!ZynxConfigs::instance().enable_debugger).then(HashSet::new)
     }
 
-    async fn check(&self, args: &EmbryoCheckArgs<'_>) -> PolicyDecision {
+    async fn check(&self, args: &EmbryoCheckArgs) -> PolicyDecision {
         if !ZynxConfigs::instance().enable_debugger {
             return PolicyDecision::Deny;
         }
@@ -24,22 +170,21 @@ impl PolicyProvider for DebuggerPolicyProvider {
             return PolicyDecision::Deny;
         };
 
-        let enable_debug = pkgs
+        let force_debuggable = pkgs
             .iter()
-            .any(|pkg| !pkg.debuggable && prop_on(&format!("debug.zynx.debuggable.{}", pkg.name)));
+            .any(|pkg| !pkg.debuggable && is_forced_debuggable(&pkg.name));
 
-        if !enable_debug {
+        if !force_debuggable {
             return PolicyDecision::Deny;
         }
 
-        let params = DebuggerParams {
-            force_debuggable: true,
+        let params = DebuggerParams { force_debuggable };
+
+        let Ok(data) = wincode::serialize(&params) else {
+            return PolicyDecision::Deny;
         };
 
-        if let Ok(data) = wincode::serialize(&params) {
-            PolicyDecision::allow_with_data(data)
-        } else {
-            PolicyDecision::Deny
-        }
+        PolicyDecision::allow_with_data(data)
+            .with_mutation(ArgsMutation::runtime_flags(DEBUGGABLE_RUNTIME_FLAGS))
     }
 }
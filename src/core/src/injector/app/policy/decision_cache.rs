@@ -0,0 +1,160 @@
+use crate::config::ZynxConfigs;
+use crate::injector::app::policy::{Attachment, EmbryoCheckArgs, PolicyDecision, ProviderKind};
+use crate::monitor::uid_filter;
+use nix::unistd::Uid;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::debug;
+use zynx_bridge_shared::zygote::ArgsMutation;
+
+/// Expired entries are only swept once the cache grows this large
+const MAX_ENTRIES: usize = 1024;
+
+static INSTANCE: Lazy<DecisionCache> = Lazy::new(DecisionCache::default);
+
+#[derive(Hash, PartialEq, Eq)]
+struct Key {
+    uid: Uid,
+    packages: Vec<String>,
+    provider: ProviderKind,
+}
+
+enum CachedDecision {
+    Allow {
+        data: Option<Vec<u8>>,
+        attachments: Option<Vec<Attachment>>,
+        mutation: Option<ArgsMutation>,
+    },
+    Deny,
+    Veto,
+}
+
+struct Entry {
+    decision: CachedDecision,
+    expires: Instant,
+}
+
+/// Fast check decisions of cacheable providers, keyed by uid, packages and provider, so that
+/// frequently restarted apps don't wait for the same checks on every fork. Entries expire
+/// after `--cfg-decision-cache-ttl` seconds, or when packages or modules change.
+#[derive(Default)]
+pub struct DecisionCache {
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl DecisionCache {
+    pub fn instance() -> &'static Self {
+        &INSTANCE
+    }
+
+    fn ttl() -> Option<Duration> {
+        let secs = ZynxConfigs::instance().decision_cache_ttl;
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    /// Only plain app processes are cached, others are rare or depend on more than their uid.
+    fn key_of(args: &EmbryoCheckArgs, provider: ProviderKind) -> Option<Key> {
+        if args.is_system_server
+            || args.is_child_zygote
+            || args.from_app_zygote
+            || args.inherits_bridge
+        {
+            return None;
+        }
+
+        let packages = args.package_info.as_ref()?;
+
+        Some(Key {
+            uid: args.uid,
+            packages: packages.iter().map(|pkg| pkg.name.clone()).collect(),
+            provider,
+        })
+    }
+
+    pub fn get(&self, args: &EmbryoCheckArgs, provider: ProviderKind) -> Option<PolicyDecision> {
+        Self::ttl()?;
+
+        let key = Self::key_of(args, provider)?;
+        let entries = self.entries.lock();
+        let entry = entries.get(&key).filter(|it| it.expires > Instant::now())?;
+
+        Some(match &entry.decision {
+            CachedDecision::Allow {
+                data,
+                attachments,
+                mutation,
+            } => PolicyDecision::Allow {
+                data: data.clone(),
+                attachments: attachments.clone(),
+                mutation: mutation.clone(),
+            },
+            CachedDecision::Deny => PolicyDecision::Deny,
+            CachedDecision::Veto => PolicyDecision::Veto,
+        })
+    }
+
+    pub fn insert(
+        &self,
+        args: &EmbryoCheckArgs,
+        provider: ProviderKind,
+        decision: &PolicyDecision,
+    ) {
+        let Some(ttl) = Self::ttl() else {
+            return;
+        };
+
+        let Some(key) = Self::key_of(args, provider) else {
+            return;
+        };
+
+        let decision = match decision {
+            PolicyDecision::Allow {
+                data,
+                attachments,
+                mutation,
+            } => CachedDecision::Allow {
+                data: data.clone(),
+                attachments: attachments.clone(),
+                mutation: mutation.clone(),
+            },
+            PolicyDecision::Deny => CachedDecision::Deny,
+            PolicyDecision::Veto => CachedDecision::Veto,
+            // depends on the slow args
+            PolicyDecision::MoreInfo(_) => return,
+        };
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock();
+
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, entry| entry.expires > now);
+        }
+
+        if entries.len() >= MAX_ENTRIES {
+            entries.clear();
+        }
+
+        entries.insert(
+            key,
+            Entry {
+                decision,
+                expires: now + ttl,
+            },
+        );
+    }
+
+    /// Drop all decisions, called when something they depend on changed.
+    pub fn invalidate(&self, reason: &str) {
+        // target uids of the providers depend on the same state
+        uid_filter::request_refresh();
+
+        let mut entries = self.entries.lock();
+
+        if !entries.is_empty() {
+            debug!("decision cache invalidated: {reason}");
+            entries.clear();
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use crate::injector::app::policy::package_list::PackageList;
+use crate::injector::app::policy::{EmbryoCheckArgs, PolicyDecision, PolicyProvider, ProviderKind};
+use anyhow::Result;
+use async_trait::async_trait;
+use nix::unistd::Uid;
+use std::collections::HashSet;
+
+const DENYLIST_FILE: &str = "/data/adb/zynx/denylist";
+
+/// Vetoes injection into listed packages/uids, regardless of what other providers decide.
+pub struct DenylistPolicyProvider {
+    denylist: PackageList,
+}
+
+impl Default for DenylistPolicyProvider {
+    fn default() -> Self {
+        Self {
+            denylist: PackageList::new(DENYLIST_FILE),
+        }
+    }
+}
+
+#[async_trait]
+impl PolicyProvider for DenylistPolicyProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Denylist
+    }
+
+    async fn init(&self) -> Result<()> {
+        self.denylist.refresh();
+        Ok(())
+    }
+
+    fn accepts_app_zygote_children(&self) -> bool {
+        true
+    }
+
+    fn accepts_bridge_inheritors(&self) -> bool {
+        true
+    }
+
+    /// Never allows, only denies or vetoes.
+    fn target_uids(&self) -> Option<HashSet<Uid>> {
+        Some(HashSet::new())
+    }
+
+    fn priority(&self) -> i32 {
+        1000
+    }
+
+    async fn check(&self, args: &EmbryoCheckArgs) -> PolicyDecision {
+        self.denylist.refresh();
+
+        match self.denylist.lookup(args) {
+            Some(true) => PolicyDecision::Veto,
+            Some(false) => PolicyDecision::Deny,
+            None => PolicyDecision::MoreInfo(None),
+        }
+    }
+}
@@ -1,17 +1,24 @@
 use crate::android::inotify::AsyncInotify;
 use crate::android::packages::PackageInfoService;
 use crate::config::ZynxConfigs;
-use crate::injector::app::policy::{Attachment, EmbryoCheckArgs, PolicyDecision, PolicyProvider};
-use crate::misc::create_sealed_memfd;
+use crate::injector::app::policy::decision_cache::DecisionCache;
+use crate::injector::app::policy::{
+    Attachment, EmbryoCheckArgs, PolicyDecision, PolicyProvider, ProviderKind,
+};
+use crate::injector::camouflage;
+use crate::injector::camouflage::NameKind;
+use crate::integrity;
+use crate::misc::{create_sealed_memfd, rooted};
 use anyhow::{Result, bail};
 use async_trait::async_trait;
-use log::{debug, error, info, warn};
+use nix::unistd::Uid;
 use notify::EventKindMask;
 use once_cell::sync::Lazy;
 use parking_lot::RwLock;
 use regex_lite::Regex;
-use std::collections::HashMap;
-use std::env;
+use serde::Deserialize;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt::Debug;
 use std::fs;
 use std::os::fd::OwnedFd;
@@ -21,23 +28,156 @@ use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use std::{fmt, path::Path};
 use tokio::{task, time};
-use zynx_bridge_shared::policy::liteloader::{LibraryKind, LiteLoaderParams};
+use tracing::{debug, error, info, warn};
+use zynx_bridge_shared::policy::liteloader::{LibraryKind, LiteLoaderParams, LoadPhase};
 use zynx_bridge_shared::zygote::ProviderType;
-use zynx_misc::selinux::FileExt;
 
-static LITE_LIBRARIES_DIR: Lazy<PathBuf> = Lazy::new(|| "/data/adb/zynx/liteloader".into());
+pub static LITE_LIBRARIES_DIR: Lazy<PathBuf> = Lazy::new(|| rooted("/data/adb/zynx/liteloader"));
 static LITE_LIBRARY_REGEX: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^(.+)-(.+)\.(so|dex)$").unwrap());
+static LITE_LIBRARY_SUBDIR_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(.+)\.(so|dex)$").unwrap());
+
+/// Marks a subdirectory as a v2 library group
+const GROUP_MANIFEST: &str = "manifest.toml";
+const LAYOUT_VERSION: u32 = 2;
+
+/// Uids of each Android user span this range, see `UserHandle.PER_USER_RANGE`
+const PER_USER_RANGE: u32 = 100000;
+
+/// Manifest of a v2 library group, a subdirectory holding libraries of the same target:
+///
+/// ```toml
+/// version = 2
+/// target = "com.example.app"  # package name, pattern with `*` and `?` wildcards, or uid
+/// users = [0]                 # optional, Android users to inject into, all by default
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GroupManifest {
+    version: u32,
+    target: String,
+    users: Option<Vec<u32>>,
+}
+
+fn load_group_manifest(dir: &Path) -> Result<Option<GroupManifest>> {
+    let manifest: GroupManifest = match fs::read_to_string(dir.join(GROUP_MANIFEST)) {
+        Ok(content) => toml::from_str(&content)?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    if manifest.version != LAYOUT_VERSION {
+        bail!("unsupported layout version {}", manifest.version);
+    }
+
+    Ok(Some(manifest))
+}
+
+/// Optional manifest next to a library, with the same name but a `.toml` extension:
+///
+/// ```toml
+/// entry = "my_entry"  # `extern "C" fn(JNIEnv)` symbol for .so, entry class for .dex
+/// phase = "pre"       # load before (`pre`) or after (`post`, default) SpecializeCommon
+/// data = "..."        # passed to `init(Map)` of .dex, and `zynx_native_entry` of .so
+/// unload = true       # dlclose .so once its entries return, and hide its name from maps
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LibraryManifest {
+    entry: Option<String>,
+    phase: ManifestPhase,
+    data: Option<String>,
+    unload: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ManifestPhase {
+    Pre,
+    #[default]
+    Post,
+}
+
+impl From<ManifestPhase> for LoadPhase {
+    fn from(phase: ManifestPhase) -> Self {
+        match phase {
+            ManifestPhase::Pre => LoadPhase::Pre,
+            ManifestPhase::Post => LoadPhase::Post,
+        }
+    }
+}
+
+fn load_manifest(library: &Path) -> Result<LibraryManifest> {
+    let path = library.with_extension("toml");
+
+    match fs::read_to_string(&path) {
+        Ok(content) => Ok(toml::from_str(&content)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(LibraryManifest::default()),
+        Err(err) => Err(err.into()),
+    }
+}
 
-type Libraries = HashMap<String, Vec<CachedLibraryEntry>>;
+/// Processes a library group applies to
+#[derive(Debug, Clone)]
+enum LibraryTarget {
+    Uid(Uid),
+    /// Package name, `*` and `?` work as wildcards
+    Package(Regex),
+}
+
+impl LibraryTarget {
+    fn parse(pattern: &str) -> Result<Self> {
+        if let Ok(uid) = pattern.parse() {
+            return Ok(LibraryTarget::Uid(Uid::from_raw(uid)));
+        }
+
+        let regex = regex_lite::escape(pattern)
+            .replace(r"\*", ".*")
+            .replace(r"\?", ".");
+
+        Ok(LibraryTarget::Package(Regex::new(&format!("^{regex}$"))?))
+    }
+
+    fn matches(&self, uid: Uid, packages: &[String]) -> bool {
+        match self {
+            LibraryTarget::Uid(target) => *target == uid,
+            LibraryTarget::Package(regex) => packages.iter().any(|name| regex.is_match(name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LibraryGroup {
+    target: LibraryTarget,
+    /// Android users the group applies to, all if `None`
+    users: Option<Vec<u32>>,
+    entries: Vec<CachedLibraryEntry>,
+}
+
+impl LibraryGroup {
+    fn matches(&self, uid: Uid, packages: &[String]) -> bool {
+        self.target.matches(uid, packages)
+            && self
+                .users
+                .as_ref()
+                .is_none_or(|users| users.contains(&(uid.as_raw() / PER_USER_RANGE)))
+    }
+}
+
+/// Library groups keyed by their target pattern (v1), or directory (v2)
+type Libraries = BTreeMap<String, LibraryGroup>;
 type LibrariesArcLocked = Arc<RwLock<Libraries>>;
 
 #[derive(Clone)]
 struct CachedLibraryEntry {
+    /// Unique name, `<pattern>-<library_name>`
+    name: String,
     mtime: SystemTime,
     path: PathBuf,
     fd: Arc<OwnedFd>,
     kind: LibraryKind,
+    manifest: LibraryManifest,
 }
 
 impl Debug for CachedLibraryEntry {
@@ -45,79 +185,241 @@ impl Debug for CachedLibraryEntry {
         fmt.debug_struct("CachedLibEntry")
             .field("path", &self.path)
             .field("kind", &self.kind)
+            .field("manifest", &self.manifest)
             .finish_non_exhaustive()
     }
 }
 
 fn find_cached_entry<'a>(libs: &'a Libraries, path: &Path) -> Option<&'a CachedLibraryEntry> {
-    libs.values().flatten().find(|entry| entry.path == path)
+    libs.values()
+        .flat_map(|group| &group.entries)
+        .find(|entry| entry.path == path)
 }
 
-fn reload_libs(prev_libs: &Libraries) -> Result<Libraries> {
-    let mut libs: Libraries = HashMap::new();
-    let mut loaded = 0usize;
-    let mut reused = 0usize;
+/// Manifests and integrity files next to libraries
+fn is_sidecar(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext == "toml" || integrity::SIDECAR_EXTENSIONS.contains(&ext))
+}
+
+/// A library file found in the liteloader directory, not loaded yet
+struct LibraryFile {
+    /// Key of the group in [`Libraries`]
+    group: String,
+    pattern: String,
+    users: Option<Vec<u32>>,
+    library_name: String,
+    extension: String,
+    path: PathBuf,
+    /// Found in a v2 group
+    v2: bool,
+}
+
+/// Collect libraries from v1 `<pattern>-<library_name>.(so|dex)` files and
+/// `<pattern>/<library_name>.(so|dex)` subdirectories, and from v2 groups, i.e.
+/// subdirectories with a [`GROUP_MANIFEST`].
+///
+/// A v1 library with the same target pattern and name as a v2 one is shadowed by it.
+fn scan_libs() -> Result<Vec<LibraryFile>> {
+    let mut files = Vec::new();
 
     for entry in LITE_LIBRARIES_DIR.read_dir()?.flatten() {
         let path = entry.path();
         let file_name = match path.file_name().and_then(|n| n.to_str()) {
-            Some(name) => name,
-            None => continue,
+            Some(name) if !name.starts_with('.') => name.to_string(),
+            _ => continue, // including migration leftovers
         };
 
-        let (package_name, library_name, extension) = match LITE_LIBRARY_REGEX.captures(file_name) {
-            Some(caps) => (
-                caps.get(1).unwrap().as_str().to_string(),
-                caps.get(2).unwrap().as_str().to_string(),
-                caps.get(3).unwrap().as_str(),
-            ),
-            None => {
-                warn!("skipping file with invalid name: {file_name}");
-                continue;
+        if path.is_dir() {
+            let manifest = match load_group_manifest(&path) {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    warn!(
+                        "skipping {}: invalid {GROUP_MANIFEST}: {err:?}",
+                        path.display()
+                    );
+                    continue;
+                }
+            };
+
+            let (group, pattern, users, v2) = match manifest {
+                Some(manifest) => (
+                    format!("v2:{file_name}"),
+                    manifest.target,
+                    manifest.users,
+                    true,
+                ),
+                None => (file_name.clone(), file_name.clone(), None, false),
+            };
+
+            for entry in path.read_dir()?.flatten() {
+                let path = entry.path();
+                let Some(sub_name) = path.file_name().and_then(|n| n.to_str()) else {
+                    continue;
+                };
+
+                if is_sidecar(&path) || sub_name.starts_with('.') {
+                    continue; // loaded along with their libraries
+                }
+
+                match LITE_LIBRARY_SUBDIR_REGEX.captures(sub_name) {
+                    Some(caps) => files.push(LibraryFile {
+                        group: group.clone(),
+                        pattern: pattern.clone(),
+                        users: users.clone(),
+                        library_name: caps[1].to_string(),
+                        extension: caps[2].to_string(),
+                        path: path.clone(),
+                        v2,
+                    }),
+                    None => warn!("skipping file with invalid name: {}", path.display()),
+                }
             }
-        };
 
+            continue;
+        }
+
+        if is_sidecar(&path) {
+            continue;
+        }
+
+        match LITE_LIBRARY_REGEX.captures(&file_name) {
+            Some(caps) => files.push(LibraryFile {
+                group: caps[1].to_string(),
+                pattern: caps[1].to_string(),
+                users: None,
+                library_name: caps[2].to_string(),
+                extension: caps[3].to_string(),
+                path,
+                v2: false,
+            }),
+            None => warn!("skipping file with invalid name: {file_name}"),
+        }
+    }
+
+    let v2_libs: HashSet<_> = files
+        .iter()
+        .filter(|file| file.v2)
+        .map(|file| (file.pattern.clone(), file.library_name.clone()))
+        .collect();
+
+    files.retain(|file| {
+        let shadowed =
+            !file.v2 && v2_libs.contains(&(file.pattern.clone(), file.library_name.clone()));
+
+        if shadowed {
+            warn!("{} is shadowed by a v2 group", file.path.display());
+        }
+
+        !shadowed
+    });
+
+    Ok(files)
+}
+
+fn reload_libs(prev_libs: &Libraries) -> Result<Libraries> {
+    let mut libs: Libraries = BTreeMap::new();
+    let mut loaded = 0usize;
+    let mut reused = 0usize;
+
+    for file in scan_libs()? {
+        let LibraryFile {
+            group,
+            pattern,
+            users,
+            library_name,
+            extension,
+            path,
+            ..
+        } = file;
+
+        // sidecars count as part of the library, changing them triggers re-verification
         let current_mtime = match fs::metadata(&path).and_then(|m| m.modified()) {
-            Ok(t) => t,
+            Ok(t) => t.max(integrity::sidecars_mtime(&path).unwrap_or(t)),
             Err(err) => {
                 warn!("failed to get mtime for {}: {err}", path.display());
                 continue;
             }
         };
 
+        // manifests are always re-read, they may change without touching the library
+        let manifest = match load_manifest(&path) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                warn!(
+                    "skipping {} due to invalid manifest: {err:?}",
+                    path.display()
+                );
+                continue;
+            }
+        };
+
         let cached_entry = match find_cached_entry(prev_libs, &path) {
-            Some(prev_entry) if prev_entry.mtime == current_mtime => {
+            // the memfd name depends on `unload`
+            Some(prev_entry)
+                if prev_entry.mtime == current_mtime
+                    && prev_entry.manifest.unload == manifest.unload =>
+            {
                 debug!("reusing cached: {}", path.display());
                 reused += 1;
-                prev_entry.clone()
+                CachedLibraryEntry {
+                    manifest,
+                    ..prev_entry.clone()
+                }
             }
             _ => {
                 info!("loading: {}", path.display());
                 loaded += 1;
 
-                let name = format!("liteloader::{library_name}");
-                let fd = create_sealed_memfd(&name, &fs::read(&path)?)?;
+                let data = fs::read(&path)?;
 
-                if env::var("MODDIR").is_ok() {
-                    fd.as_file().mark_as_magisk_file();
+                if let Err(err) = integrity::verify_library(&path, &data) {
+                    error!("refusing to load {}: {err:?}", path.display());
+                    continue;
                 }
 
-                let kind = match extension {
+                // shown in the maps of the process, as the soname is
+                let name =
+                    camouflage::name(NameKind::Library, &format!("liteloader::{library_name}"));
+                let fd = create_sealed_memfd(&name, &data)?;
+
+                let kind = match extension.as_str() {
                     "so" => LibraryKind::Native,
                     "dex" => LibraryKind::Java,
                     _ => unreachable!(),
                 };
 
                 CachedLibraryEntry {
+                    name: format!("{pattern}-{library_name}"),
                     mtime: current_mtime,
                     path: path.clone(),
                     fd: Arc::new(unsafe { std::os::fd::OwnedFd::from_raw_fd(fd.into_raw_fd()) }),
                     kind,
+                    manifest,
                 }
             }
         };
 
-        libs.entry(package_name).or_default().push(cached_entry);
+        let group = match libs.entry(group) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match LibraryTarget::parse(&pattern) {
+                Ok(target) => entry.insert(LibraryGroup {
+                    target,
+                    users,
+                    entries: Vec::new(),
+                }),
+                Err(err) => {
+                    warn!(
+                        "skipping {} due to invalid pattern: {err:?}",
+                        path.display()
+                    );
+                    continue;
+                }
+            },
+        };
+
+        group.entries.push(cached_entry);
     }
 
     info!("reload complete: {loaded} loaded, {reused} reused");
@@ -137,6 +439,7 @@ impl LiteLoaderPolicyProvider {
         match reload_libs(&prev_libs) {
             Ok(map) => {
                 *libs.write() = map;
+                DecisionCache::instance().invalidate("liteloader libraries changed");
             }
             Err(err) => {
                 warn!("failed to reload library list: {err:?}, keeping old data");
@@ -168,8 +471,12 @@ impl LiteLoaderPolicyProvider {
 
 #[async_trait]
 impl PolicyProvider for LiteLoaderPolicyProvider {
-    fn provider_type(&self) -> ProviderType {
-        ProviderType::LiteLoader
+    fn kind(&self) -> ProviderKind {
+        ProviderType::LiteLoader.into()
+    }
+
+    fn cacheable(&self) -> bool {
+        true
     }
 
     async fn init(&self) -> Result<()> {
@@ -194,7 +501,7 @@ impl PolicyProvider for LiteLoaderPolicyProvider {
 
         task::block_in_place(|| Self::reload_libs(self.libs.clone()));
 
-        let inotify = AsyncInotify::new(
+        let inotify = AsyncInotify::new_recursive(
             &*LITE_LIBRARIES_DIR,
             EventKindMask::CREATE
                 | EventKindMask::MODIFY_NAME
@@ -212,37 +519,167 @@ impl PolicyProvider for LiteLoaderPolicyProvider {
         Ok(())
     }
 
-    async fn check(&self, args: &EmbryoCheckArgs<'_>) -> PolicyDecision {
+    /// Uids of the packages matching any group, regardless of the users it's restricted to.
+    fn target_uids(&self) -> Option<HashSet<Uid>> {
+        if !ZynxConfigs::instance().enable_liteloader {
+            return Some(HashSet::new());
+        }
+
+        let packages = PackageInfoService::instance();
+        let mut uids = HashSet::new();
+
+        for group in self.libs.read().values() {
+            match &group.target {
+                LibraryTarget::Uid(uid) => {
+                    uids.insert(*uid);
+                }
+                LibraryTarget::Package(regex) => {
+                    uids.extend(packages.uids_of(|pkg| regex.is_match(&pkg.name)));
+                }
+            }
+        }
+
+        Some(uids)
+    }
+
+    async fn check(&self, args: &EmbryoCheckArgs) -> PolicyDecision {
         if !ZynxConfigs::instance().enable_liteloader {
             return PolicyDecision::Deny;
         }
 
-        let libs = self.libs.read();
-        let inject_libs = PackageInfoService::instance()
+        let packages: Vec<_> = PackageInfoService::instance()
             .query(args.uid)
-            .and_then(|pkgs| pkgs.iter().find_map(|pkg| libs.get(&pkg.name)));
+            .map(|pkgs| pkgs.iter().map(|pkg| pkg.name.clone()).collect())
+            .unwrap_or_default();
 
-        if let Some(libs) = inject_libs {
-            let attachments: Vec<Attachment> = libs
-                .iter()
-                .map(|entry| {
-                    let params = LiteLoaderParams {
-                        lib_name: entry
-                            .path
-                            .file_stem()
-                            .and_then(|stem| stem.to_str())
-                            .unwrap_or("unknown")
-                            .to_string(),
-                        kind: entry.kind.clone(),
-                    };
-                    let data = wincode::serialize(&params).unwrap_or_default();
-
-                    Attachment::with_both(entry.fd.clone(), data)
-                })
-                .collect();
+        let libs = self.libs.read();
+        let attachments: Vec<Attachment> = libs
+            .values()
+            .filter(|group| group.matches(args.uid, &packages))
+            .flat_map(|group| &group.entries)
+            .map(|entry| {
+                let module = format!("liteloader:{}", entry.name);
+                let params = LiteLoaderParams {
+                    lib_name: entry.name.clone(),
+                    kind: entry.kind.clone(),
+                    entry: entry.manifest.entry.clone(),
+                    phase: entry.manifest.phase.into(),
+                    package_name: packages.first().cloned(),
+                    data: entry.manifest.data.clone().map(String::into_bytes),
+                    unload: entry.manifest.unload,
+                };
+                let data = wincode::serialize(&params).unwrap_or_default();
+
+                Attachment::with_both(entry.fd.clone(), data)
+                    .module(module)
+                    .library()
+            })
+            .collect();
+
+        if !attachments.is_empty() {
             return PolicyDecision::allow_with_attachments(attachments);
         }
 
         PolicyDecision::Deny
     }
 }
+
+/// Copy `src` to `dest` through a temporary file, so that `dest` appears complete or not at
+/// all.
+fn copy_atomic(src: &Path, dest: &Path) -> Result<()> {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let temp = dest.with_file_name(format!(".{name}.tmp"));
+
+    fs::copy(src, &temp)?;
+    fs::File::open(&temp)?.sync_all()?;
+    fs::rename(&temp, dest)?;
+
+    Ok(())
+}
+
+/// Move v1 libraries, and their sidecars, into v2 groups named after their target pattern.
+///
+/// Sidecars are copied next to the new place first, each through a temporary file, then the
+/// library is moved with a single rename, and the old sidecars removed last. Libraries keep
+/// their module names once moved, so the daemon may keep running meanwhile: it sees each
+/// library either at its old or at its new place, never both or none, and always along with
+/// all of its sidecars. The group manifest is written last.
+pub fn migrate_layout(dry_run: bool) -> Result<()> {
+    let mut groups: BTreeMap<String, Vec<LibraryFile>> = BTreeMap::new();
+
+    for file in scan_libs()? {
+        if !file.v2 {
+            groups.entry(file.pattern.clone()).or_default().push(file);
+        }
+    }
+
+    if groups.is_empty() {
+        println!("nothing to migrate");
+        return Ok(());
+    }
+
+    for (pattern, files) in groups {
+        let dir = LITE_LIBRARIES_DIR.join(&pattern);
+
+        println!("{pattern} -> {}", dir.display());
+
+        for file in &files {
+            println!("  {}", file.path.display());
+        }
+
+        if dry_run {
+            continue;
+        }
+
+        fs::create_dir_all(&dir)?;
+
+        for file in files {
+            if file.path.parent() == Some(dir.as_path()) {
+                continue; // v1 subdirectory, already in place
+            }
+
+            let dest = dir.join(format!("{}.{}", file.library_name, file.extension));
+
+            if dest.exists() {
+                bail!(
+                    "can't move {} to {}, destination exists",
+                    file.path.display(),
+                    dest.display()
+                );
+            }
+
+            // sidecars first, the library must never be seen without them
+            let sidecars: Vec<_> = integrity::SIDECAR_EXTENSIONS
+                .iter()
+                .chain(&["toml"])
+                .map(|ext| (file.path.with_extension(ext), dest.with_extension(ext)))
+                .filter(|(sidecar, _)| sidecar.exists())
+                .collect();
+
+            for (sidecar, moved) in &sidecars {
+                copy_atomic(sidecar, moved)?;
+            }
+
+            fs::rename(&file.path, &dest)?;
+
+            for (sidecar, _) in &sidecars {
+                fs::remove_file(sidecar)?;
+            }
+        }
+
+        let manifest = format!(
+            "version = {LAYOUT_VERSION}\ntarget = {}\n",
+            toml::Value::String(pattern.clone())
+        );
+        let temp = LITE_LIBRARIES_DIR.join(format!(".{pattern}.manifest.tmp"));
+
+        fs::write(&temp, manifest)?;
+        fs::rename(&temp, dir.join(GROUP_MANIFEST))?;
+    }
+
+    if dry_run {
+        println!("dry run, nothing changed");
+    }
+
+    Ok(())
+}
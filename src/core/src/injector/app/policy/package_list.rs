@@ -0,0 +1,115 @@
+use crate::injector::app::policy::EmbryoCheckArgs;
+use crate::misc::rooted;
+use anyhow::Result;
+use nix::unistd::Uid;
+use parking_lot::RwLock;
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+#[derive(Default)]
+struct Entries {
+    mtime: Option<SystemTime>,
+    packages: HashSet<String>,
+    uids: HashSet<Uid>,
+}
+
+impl Entries {
+    fn load(path: &Path, mtime: Option<SystemTime>) -> Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut entries = Self {
+            mtime,
+            ..Default::default()
+        };
+
+        for line in content.lines() {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+
+            if entry.is_empty() {
+                continue;
+            }
+
+            match entry.parse() {
+                Ok(uid) => entries.uids.insert(Uid::from_raw(uid)),
+                Err(_) => entries.packages.insert(entry.to_string()),
+            };
+        }
+
+        info!(
+            "{} loaded: {} packages, {} uids",
+            path.display(),
+            entries.packages.len(),
+            entries.uids.len()
+        );
+
+        Ok(entries)
+    }
+}
+
+/// User-maintained list of package names or uids, one per line, `#` starts a comment.
+/// The file is reloaded whenever its mtime changes.
+pub struct PackageList {
+    path: PathBuf,
+    entries: RwLock<Entries>,
+}
+
+impl PackageList {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: rooted(path),
+            entries: RwLock::default(),
+        }
+    }
+
+    pub fn refresh(&self) {
+        let mtime = fs::metadata(&self.path)
+            .and_then(|meta| meta.modified())
+            .ok();
+
+        if self.entries.read().mtime == mtime {
+            return;
+        }
+
+        match Entries::load(&self.path, mtime) {
+            Ok(entries) => *self.entries.write() = entries,
+            Err(err) => warn!(
+                "failed to reload {}: {err:?}, keeping old data",
+                self.path.display()
+            ),
+        }
+    }
+
+    /// Whether the process is on the list, `None` if its nice name is required to tell
+    /// (processes without package info, e.g. isolated services).
+    pub fn lookup(&self, args: &EmbryoCheckArgs) -> Option<bool> {
+        let entries = self.entries.read();
+
+        if entries.uids.contains(&args.uid) {
+            return Some(true);
+        }
+
+        if let EmbryoCheckArgs::Slow(args) = args {
+            // e.g. `com.example.app:remote`
+            let package = args
+                .nice_name
+                .as_deref()
+                .map(|name| name.split(':').next().unwrap_or(name));
+
+            return Some(package.is_some_and(|name| entries.packages.contains(name)));
+        }
+
+        match &args.package_info {
+            Some(pkgs) => Some(pkgs.iter().any(|pkg| entries.packages.contains(&pkg.name))),
+            None if entries.packages.is_empty() => Some(false),
+            None => None,
+        }
+    }
+}
@@ -1,35 +1,68 @@
+use crate::android::inotify::AsyncInotify;
 use crate::android::packages::PackageInfoService;
+use crate::android::root;
 use crate::config::ZynxConfigs;
+use crate::injector::app::policy::decision_cache::DecisionCache;
 use crate::injector::app::policy::proto::{
-    CheckArgsFast, CheckArgsSlow, CheckResponse, CheckResult, PackageInfo,
+    CheckArgsFast, CheckArgsSlow, CheckResponse, CheckResult, DataInfo, PackageInfo,
 };
 use crate::injector::app::policy::{
-    Attachment, EmbryoCheckArgs, EmbryoCheckArgsFast, PolicyDecision, PolicyProvider,
+    Attachment, EmbryoCheckArgs, EmbryoCheckArgsFast, PolicyDecision, PolicyProvider, ProviderKind,
 };
-use anyhow::{Result, bail};
+use crate::status::Status;
+use anyhow::{Context, Result, bail};
 use async_trait::async_trait;
-use log::{info, warn};
-use nix::sys::socket::{self, AddressFamily, SockFlag, SockType, UnixAddr};
-use parking_lot::RwLock;
+use companion::Companions;
+use managed::{ManagedFilters, ServiceSpec};
+use nix::fcntl;
+use nix::fcntl::OFlag;
+use nix::sys::stat::Mode;
+use nix::unistd::Uid;
+use notify::EventKindMask;
+use parking_lot::{Mutex, RwLock};
+use pool::{DEFAULT_MAX_CONNECTIONS, FilterPool, Lease};
 use prost::Message;
-use regex_lite::Regex;
+use protocol::Protocol;
+use push::PushedDecisions;
 use serde::Deserialize;
 use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Display};
 use std::fs;
-use std::os::unix::io::AsRawFd;
+use std::hash::{Hash, Hasher};
+use std::os::fd::OwnedFd;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::time::Duration;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::UnixStream;
 use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::task;
+use tokio::time;
 use tokio::time::timeout;
-use zynx_bridge_shared::policy::zygisk::ZygiskParams;
+use tracing::{error, info, warn};
+use zynx_bridge_shared::policy::zygisk::{ZygiskAttachmentKind, ZygiskParams};
 use zynx_bridge_shared::zygote::ProviderType;
+use zynx_misc::debug::panic_message;
+use zynx_misc::ext::ResultExt;
+
+mod abstract_socket;
+mod companion;
+mod managed;
+mod pool;
+mod protocol;
+mod push;
 
-const MODULES_DIR: &str = "/data/adb/modules"; // Fixme: use MODDIR
 const IO_TIMEOUT: Duration = Duration::from_secs(1);
 const MAX_MESSAGE_SIZE: usize = 1024 * 1024; // 1MB
+const MAX_SCAN_WORKERS: usize = 4;
+const SCAN_SLOW_THRESHOLD: Duration = Duration::from_millis(500);
+const STATUS_SECTION: &str = "zygisk";
+
+pub use companion::zygisk_companion;
 
 // ============================================================================
 // Configuration parsing (from zynx-configs.toml)
@@ -38,6 +71,27 @@ const MAX_MESSAGE_SIZE: usize = 1024 * 1024; // 1MB
 #[derive(Debug, Deserialize)]
 struct ZygiskModuleConfig {
     filter: FilterConfig,
+    /// Pass an fd of the module directory to the module, returned by `getModuleDir`
+    #[serde(default)]
+    module_dir: bool,
+    /// Filter process to be started and kept running by the daemon, for socket filters
+    service: Option<ServiceConfig>,
+    /// Keep connections to the filter open and reuse them across checks
+    pool: Option<PoolConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PoolConfig {
+    /// Checks running concurrently on the filter, each on its own connection
+    max_connections: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServiceConfig {
+    /// Relative to the module directory, unless absolute
+    path: PathBuf,
+    #[serde(default)]
+    args: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -63,45 +117,88 @@ enum FilterType {
     UnixAbstract(String),
 }
 
+impl FilterType {
+    /// Name of the kind, as the `type` of the filter in `zynx-configs.toml`
+    fn kind(&self) -> &'static str {
+        match self {
+            FilterType::Stdio(..) => "stdio",
+            FilterType::SocketFile(_) => "socket_file",
+            FilterType::UnixAbstract(_) => "unix_abstract",
+        }
+    }
+}
+
+#[derive(Clone)]
 struct ZygiskAdapter {
     module_id: String,
     filter: FilterType,
+    module_dir: Option<Arc<OwnedFd>>,
+    /// Library exporting `zygisk_companion_entry`, hosted by the daemon
+    companion: Option<PathBuf>,
+    service: Option<ServiceSpec>,
+    pool: Option<Arc<FilterPool>>,
+    /// Negotiated on the first check of the module, kept until the module is rescanned
+    protocol: Arc<Mutex<Option<Protocol>>>,
 }
 
-// ============================================================================
-// Connection abstraction for external filter communication
-// ============================================================================
+/// Open the module directory to be passed into injected processes. Modules get an fd instead
+/// of a path, since the directory is usually not accessible from the app's SELinux domain.
+fn open_module_dir(module_dir: &Path) -> Result<Arc<OwnedFd>> {
+    let fd = fcntl::open(
+        module_dir,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .context(format!("failed to open {}", module_dir.display()))?;
+
+    Ok(Arc::new(fd))
+}
 
-/// Resolve the latest abstract socket matching `<prefix>_<seq>_<random>`.
-/// Returns the socket name as bytes (without the `@` prefix) for use with `UnixAddr::new_abstract`.
-fn resolve_abstract_socket(prefix: &str) -> Result<Vec<u8>> {
-    let pattern = format!(r"^{}_(\d+)_[a-zA-Z0-9-]+$", regex_lite::escape(prefix));
-    let re = Regex::new(&pattern)?;
+/// One attachment per module to load, plus those carrying the module directory if requested,
+/// and the socket to reach the module's companion through.
+fn build_attachments(adapters: &[ZygiskAdapter]) -> Vec<Attachment> {
+    let mut attachments = Vec::new();
+
+    for adapter in adapters {
+        let module_id = &adapter.module_id;
+        let params = |kind| {
+            let params = ZygiskParams {
+                module_name: module_id.clone(),
+                kind,
+            };
+            wincode::serialize(&params).unwrap_or_default()
+        };
 
-    let content = fs::read_to_string("/proc/net/unix")?;
-    let mut best: Option<(u64, &str)> = None;
+        attachments.push(
+            Attachment::with_data(params(ZygiskAttachmentKind::Library))
+                .module(format!("zygisk:{module_id}")),
+        );
 
-    for line in content.lines().skip(1) {
-        let path = match line.rsplit_once(char::is_whitespace) {
-            Some((_, path)) if path.starts_with('@') => &path[1..],
-            _ => continue,
-        };
+        if let Some(fd) = &adapter.module_dir {
+            attachments.push(Attachment::with_both(
+                fd.clone(),
+                params(ZygiskAttachmentKind::ModuleDir),
+            ));
+        }
 
-        if let Some(caps) = re.captures(path)
-            && let Ok(seq) = caps[1].parse::<u64>()
-            && best.is_none_or(|(best_seq, _)| seq > best_seq)
+        if let Some(library) = &adapter.companion
+            && let Some(socket) = Companions::instance().socket(module_id, library)
         {
-            best = Some((seq, path));
+            attachments.push(Attachment::with_both(
+                socket,
+                params(ZygiskAttachmentKind::Companion),
+            ));
         }
     }
 
-    match best {
-        Some((_, name)) => Ok(name.as_bytes().to_vec()),
-        None => bail!("no abstract socket found with prefix \"{prefix}\""),
-    }
+    attachments
 }
 
-enum AdapterConnection {
+// ============================================================================
+// Connection abstraction for external filter communication
+// ============================================================================
+
+enum Transport {
     Socket(UnixStream),
     Stdio {
         child: Child,
@@ -110,27 +207,19 @@ enum AdapterConnection {
     },
 }
 
-impl AdapterConnection {
+impl Transport {
     async fn connect(filter: &FilterType) -> Result<Self> {
         match filter {
             FilterType::SocketFile(path) => {
                 let stream = UnixStream::connect(path).await?;
-                Ok(AdapterConnection::Socket(stream))
+                Ok(Transport::Socket(stream))
             }
             FilterType::UnixAbstract(prefix) => {
-                let name = resolve_abstract_socket(prefix)?;
-                let fd = socket::socket(
-                    AddressFamily::Unix,
-                    SockType::Stream,
-                    SockFlag::SOCK_CLOEXEC,
-                    None,
-                )?;
-                let addr = UnixAddr::new_abstract(&name)?;
-                socket::connect(fd.as_raw_fd(), &addr)?;
+                let fd = abstract_socket::connect(prefix)?;
                 let std_stream = std::os::unix::net::UnixStream::from(fd);
                 std_stream.set_nonblocking(true)?;
                 let stream = UnixStream::from_std(std_stream)?;
-                Ok(AdapterConnection::Socket(stream))
+                Ok(Transport::Socket(stream))
             }
             FilterType::Stdio(path, args) => {
                 let mut child = Command::new(path)
@@ -138,12 +227,13 @@ impl AdapterConnection {
                     .stdin(Stdio::piped())
                     .stdout(Stdio::piped())
                     .stderr(Stdio::null())
+                    .kill_on_drop(true)
                     .spawn()?;
 
                 let stdin = child.stdin.take().expect("stdin was configured as piped");
                 let stdout = child.stdout.take().expect("stdout was configured as piped");
 
-                Ok(AdapterConnection::Stdio {
+                Ok(Transport::Stdio {
                     child,
                     stdin,
                     stdout,
@@ -157,11 +247,11 @@ impl AdapterConnection {
         let len = data.len() as u32;
 
         match self {
-            AdapterConnection::Socket(stream) => {
+            Transport::Socket(stream) => {
                 stream.write_all(&len.to_le_bytes()).await?;
                 stream.write_all(&data).await?;
             }
-            AdapterConnection::Stdio { stdin, .. } => {
+            Transport::Stdio { stdin, .. } => {
                 stdin.write_all(&len.to_le_bytes()).await?;
                 stdin.write_all(&data).await?;
             }
@@ -172,10 +262,10 @@ impl AdapterConnection {
 
     async fn recv_data(&mut self, buffer: &mut [u8]) -> Result<()> {
         match self {
-            AdapterConnection::Socket(stream) => {
+            Transport::Socket(stream) => {
                 stream.read_exact(buffer).await?;
             }
-            AdapterConnection::Stdio { stdout, .. } => {
+            Transport::Stdio { stdout, .. } => {
                 stdout.read_exact(buffer).await?;
             }
         }
@@ -202,16 +292,78 @@ impl AdapterConnection {
 
     async fn close(self) {
         match self {
-            AdapterConnection::Socket(stream) => {
+            Transport::Socket(stream) => {
                 drop(stream);
             }
-            AdapterConnection::Stdio { mut child, .. } => {
+            Transport::Stdio { mut child, .. } => {
                 let _ = child.kill().await;
             }
         }
     }
 }
 
+/// Connection to a filter
+struct AdapterConnection {
+    transport: Transport,
+}
+
+impl AdapterConnection {
+    async fn connect(filter: &FilterType) -> Result<Self> {
+        Ok(Self {
+            transport: Transport::connect(filter).await?,
+        })
+    }
+
+    async fn send_message(&mut self, msg: &impl Message) -> Result<()> {
+        self.transport.send_message(msg).await
+    }
+
+    async fn recv_data(&mut self, buffer: &mut [u8]) -> Result<()> {
+        self.transport.recv_data(buffer).await
+    }
+
+    async fn recv_message<T: Message + Default>(&mut self) -> Result<T> {
+        self.transport.recv_message().await
+    }
+
+    /// Send fast args, along with the handshake until the protocol of the module is known, and
+    /// without the fields the filter doesn't support once it is.
+    async fn check_fast(
+        &mut self,
+        fast_args: &CheckArgsFast,
+        protocol: &Mutex<Option<Protocol>>,
+    ) -> Result<CheckResponse> {
+        let mut args = fast_args.clone();
+
+        match &*protocol.lock() {
+            Some(protocol) => protocol.downgrade(&mut args),
+            None => args.handshake = Some(Protocol::handshake()),
+        }
+
+        timeout(IO_TIMEOUT, self.send_message(&args))
+            .await
+            .context("send timeout")?
+            .context("failed to send args")?;
+
+        let response: CheckResponse = timeout(IO_TIMEOUT, self.recv_message())
+            .await
+            .context("receive timeout")?
+            .context("failed to receive response")?;
+
+        if args.handshake.is_some() {
+            protocol
+                .lock()
+                .get_or_insert_with(|| Protocol::negotiate(&response));
+        }
+
+        Ok(response)
+    }
+
+    async fn close(self) {
+        self.transport.close().await
+    }
+}
+
 // ============================================================================
 // Check state management
 // ============================================================================
@@ -221,7 +373,7 @@ enum AdapterCheckResult {
     /// Already decided in fast phase (ALLOW or DENY)
     Decided(CheckResult),
     /// Needs recheck, connection kept alive
-    Pending(Box<AdapterConnection>),
+    Pending(Box<Lease>),
     /// Failed to connect or communicate
     Failed,
 }
@@ -230,146 +382,495 @@ enum AdapterCheckResult {
 struct ZygiskCheckState {
     /// Results for each adapter (indexed by adapter position)
     results: Vec<AdapterCheckResult>,
-    /// Module IDs for logging in recheck
-    module_ids: Vec<String>,
+    /// Adapters for logging and attachments in recheck
+    adapters: Vec<ZygiskAdapter>,
 }
 
 // ============================================================================
 // Module scanning
 // ============================================================================
 
-fn scan_modules() -> Result<Vec<ZygiskAdapter>> {
-    let modules_dir = Path::new(MODULES_DIR);
-    if !modules_dir.exists() {
-        return Ok(Vec::new());
-    }
+/// Cached scan result of a single module directory
+#[derive(Clone)]
+struct CachedModule {
+    dir_mtime: SystemTime,
+    config_mtime: SystemTime,
+    config_hash: u64,
+    adapter: ZygiskAdapter,
+}
 
-    let mut adapters = Vec::new();
+type ModuleCache = HashMap<String, CachedModule>;
 
-    for entry in modules_dir.read_dir()?.flatten() {
-        let module_dir = entry.path();
-        if !module_dir.is_dir() {
-            continue;
-        }
+enum ScanOutcome {
+    Loaded(CachedModule),
+    Reused(CachedModule),
+    Skipped,
+    Failed(String),
+}
 
-        let module_id = match module_dir
-            .file_name()
-            .and_then(|n| n.to_str())
-            .map(String::from)
-        {
-            Some(id) => id,
-            None => continue,
+/// Statistics of a single module scan
+#[derive(Debug, Default, Clone)]
+struct ScanStats {
+    duration: Duration,
+    loaded: usize,
+    reused: usize,
+    /// Modules with an invalid `zynx-configs.toml`, or whose scan panicked
+    failed: Vec<String>,
+}
+
+/// State of a module seen by the last scan, reported in the status
+#[derive(Default)]
+struct ModuleState {
+    /// Kind of the filter, none if the module failed to load
+    filter: Option<&'static str>,
+    checks: u64,
+    errors: u64,
+    last_latency: Option<Duration>,
+}
+
+impl Display for ModuleState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(filter) = self.filter else {
+            return write!(f, "provider=zygisk failed to load zynx-configs.toml");
         };
 
-        if module_dir.join("disable").exists() {
-            info!("skipping disabled module: {module_id}");
-            continue;
+        write!(
+            f,
+            "provider=zygisk filter={filter} checks={} errors={}",
+            self.checks, self.errors
+        )?;
+
+        match self.last_latency {
+            Some(latency) => write!(f, " last_check={latency:.2?}"),
+            None => write!(f, " last_check=never"),
+        }
+    }
+}
+
+fn hash_config(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn scan_module(module_dir: &Path, cache: &ModuleCache) -> ScanOutcome {
+    let Some(module_id) = module_dir.file_name().and_then(|n| n.to_str()) else {
+        return ScanOutcome::Skipped;
+    };
+
+    let Ok(dir_mtime) = fs::metadata(module_dir).and_then(|m| m.modified()) else {
+        return ScanOutcome::Skipped;
+    };
+
+    if module_dir.join("disable").exists() {
+        info!("skipping disabled module: {module_id}");
+        return ScanOutcome::Skipped;
+    }
+
+    let config_path = module_dir.join("zynx-configs.toml");
+    let Ok(config_mtime) = fs::metadata(&config_path).and_then(|m| m.modified()) else {
+        return ScanOutcome::Skipped;
+    };
+
+    let cached = cache.get(module_id);
+
+    // fast path: nothing changed since last scan
+    if let Some(cached) = cached
+        && cached.dir_mtime == dir_mtime
+        && cached.config_mtime == config_mtime
+    {
+        return ScanOutcome::Reused(cached.clone());
+    }
+
+    let config_content = match fs::read_to_string(&config_path) {
+        Ok(content) => content,
+        Err(err) => {
+            warn!("failed to read config for {module_id}: {err}");
+            return ScanOutcome::Failed(module_id.into());
         }
+    };
+
+    let config_hash = hash_config(&config_content);
+
+    // touched but not modified
+    if let Some(cached) = cached
+        && cached.config_hash == config_hash
+    {
+        return ScanOutcome::Reused(CachedModule {
+            dir_mtime,
+            config_mtime,
+            ..cached.clone()
+        });
+    }
 
-        let config_path = module_dir.join("zynx-configs.toml");
-        if !config_path.exists() {
-            continue;
+    let config: ZygiskModuleConfig = match toml::from_str(&config_content) {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            warn!("failed to parse config for {module_id}: {err}");
+            return ScanOutcome::Failed(module_id.into());
         }
+    };
 
-        let config_content = match fs::read_to_string(&config_path) {
-            Ok(content) => content,
-            Err(err) => {
-                warn!("failed to read config for {module_id}: {err}");
-                continue;
-            }
-        };
+    let filter = match config.filter {
+        FilterConfig::Stdio { path, args } => {
+            FilterType::Stdio(path, args.into_iter().map(|s| s.into()).collect())
+        }
+        FilterConfig::SocketFile { path } => FilterType::SocketFile(path),
+        FilterConfig::UnixAbstract { prefix } => FilterType::UnixAbstract(prefix),
+    };
+
+    let service = match config.service {
+        Some(_) if matches!(filter, FilterType::Stdio(..)) => {
+            warn!("{module_id}: stdio filters are started per check, ignoring service");
+            None
+        }
+        Some(service) => Some(ServiceSpec {
+            path: module_dir.join(service.path),
+            args: service.args,
+            dir: module_dir.to_path_buf(),
+        }),
+        None => None,
+    };
+
+    let companion = Some(companion::library_path(module_dir))
+        .filter(|library| library.exists() && companion::has_entry(library));
+
+    let module_dir = if config.module_dir {
+        open_module_dir(module_dir)
+            .inspect_err(|err| warn!("{module_id}: {err:#}"))
+            .ok()
+    } else {
+        None
+    };
+
+    let pool = config.pool.map(|pool| {
+        let max_connections = pool.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS);
+        FilterPool::new(module_id, filter.clone(), max_connections)
+    });
+
+    info!("loaded module: {module_id}");
+
+    ScanOutcome::Loaded(CachedModule {
+        dir_mtime,
+        config_mtime,
+        config_hash,
+        adapter: ZygiskAdapter {
+            module_id: module_id.into(),
+            filter,
+            module_dir,
+            companion,
+            service,
+            pool,
+            protocol: Arc::default(),
+        },
+    })
+}
 
-        let config: ZygiskModuleConfig = match toml::from_str(&config_content) {
-            Ok(cfg) => cfg,
-            Err(err) => {
-                warn!("failed to parse config for {module_id}: {err}");
+/// Scan all module directories, reusing results from `cache` for modules that
+/// have not changed. Directories are scanned in parallel on a few worker threads.
+fn scan_modules(cache: &ModuleCache) -> Result<(ModuleCache, ScanStats)> {
+    let start = Instant::now();
+    let modules_dir = root::modules_dir();
+    if !modules_dir.exists() {
+        return Ok((ModuleCache::new(), ScanStats::default()));
+    }
+
+    let mut module_dirs: Vec<PathBuf> = modules_dir
+        .read_dir()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+
+    // keep adapter order stable across scans
+    module_dirs.sort();
+
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(MAX_SCAN_WORKERS);
+    let chunk_size = module_dirs.len().div_ceil(workers).max(1);
+
+    let outcomes: Vec<ScanOutcome> = thread::scope(|scope| {
+        let handles: Vec<_> = module_dirs
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|dir| scan_module(dir, cache))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .zip(module_dirs.chunks(chunk_size))
+            .flat_map(|(handle, chunk)| {
+                // the modules of a panicking worker are reported as failed, not left out
+                handle.join().unwrap_or_else(|payload| {
+                    error!(
+                        "scanning modules {chunk:?} panicked: {}",
+                        panic_message(&*payload)
+                    );
+
+                    chunk
+                        .iter()
+                        .filter_map(|dir| dir.file_name())
+                        .map(|name| ScanOutcome::Failed(name.to_string_lossy().into_owned()))
+                        .collect()
+                })
+            })
+            .collect()
+    });
+
+    let mut modules = ModuleCache::new();
+    let mut stats = ScanStats::default();
+
+    for outcome in outcomes {
+        let module = match outcome {
+            ScanOutcome::Loaded(module) => {
+                stats.loaded += 1;
+                module
+            }
+            ScanOutcome::Reused(module) => {
+                stats.reused += 1;
+                module
+            }
+            ScanOutcome::Failed(module_id) => {
+                stats.failed.push(module_id);
                 continue;
             }
+            ScanOutcome::Skipped => continue,
         };
 
-        let filter = match config.filter {
-            FilterConfig::Stdio { path, args } => {
-                FilterType::Stdio(path, args.into_iter().map(|s| s.into()).collect())
-            }
-            FilterConfig::SocketFile { path } => FilterType::SocketFile(path),
-            FilterConfig::UnixAbstract { prefix } => FilterType::UnixAbstract(prefix),
-        };
+        modules.insert(module.adapter.module_id.clone(), module);
+    }
 
-        info!("loaded module: {module_id}");
-        adapters.push(ZygiskAdapter { module_id, filter });
+    stats.duration = start.elapsed();
+
+    info!(
+        "scan complete: {} modules ({} loaded, {} reused, {} failed) in {:.2?}",
+        modules.len(),
+        stats.loaded,
+        stats.reused,
+        stats.failed.len(),
+        stats.duration
+    );
+
+    if stats.duration > SCAN_SLOW_THRESHOLD {
+        warn!(
+            "module scan took {:.2?} (threshold {SCAN_SLOW_THRESHOLD:?})",
+            stats.duration
+        );
     }
 
-    info!("scan complete: {} modules loaded", adapters.len());
-    Ok(adapters)
+    Ok((modules, stats))
 }
 
 // ============================================================================
 // Policy Provider implementation
 // ============================================================================
 
+/// Modules loaded by the last scan, shared with the task watching module directories
 #[derive(Default)]
-pub struct ZygiskPolicyProvider {
+struct ModuleRegistry {
     adapters: RwLock<Vec<ZygiskAdapter>>,
+    scan_cache: RwLock<ModuleCache>,
+    states: Mutex<BTreeMap<String, ModuleState>>,
+}
+
+impl ModuleRegistry {
+    /// Rescan module directories, reusing cached results of unchanged modules.
+    fn rescan(&self) -> Result<()> {
+        let cache = self.scan_cache.read().clone();
+        let (modules, stats) = task::block_in_place(|| scan_modules(&cache))?;
+
+        let mut adapters: Vec<_> = modules.values().map(|m| m.adapter.clone()).collect();
+        adapters.sort_by(|a, b| a.module_id.cmp(&b.module_id));
+
+        let services = adapters
+            .iter()
+            .filter_map(|adapter| Some((adapter.module_id.clone(), adapter.service.clone()?)))
+            .collect();
+
+        let companions = adapters
+            .iter()
+            .filter_map(|adapter| Some((adapter.module_id.clone(), adapter.companion.clone()?)))
+            .collect();
+
+        ManagedFilters::instance().sync(services);
+        Companions::instance().sync(&companions);
+        self.sync_status(&adapters, stats.failed);
+
+        *self.adapters.write() = adapters;
+        *self.scan_cache.write() = modules;
+
+        DecisionCache::instance().invalidate("zygisk modules rescanned");
+
+        Ok(())
+    }
+
+    /// Report modules of the last scan, keeping check counters of those still loaded.
+    fn sync_status(&self, adapters: &[ZygiskAdapter], failed: Vec<String>) {
+        let status = Status::instance();
+        let mut states = self.states.lock();
+        let mut previous = std::mem::take(&mut *states);
+
+        for adapter in adapters {
+            let state = previous.remove(&adapter.module_id).unwrap_or_default();
+            let state = ModuleState {
+                filter: Some(adapter.filter.kind()),
+                ..state
+            };
+
+            states.insert(adapter.module_id.clone(), state);
+        }
+
+        for module_id in failed {
+            previous.remove(&module_id);
+            states.insert(module_id, ModuleState::default());
+        }
+
+        for module_id in previous.keys() {
+            status.remove(STATUS_SECTION, module_id);
+        }
+
+        for (module_id, state) in states.iter() {
+            status.set(STATUS_SECTION, module_id, state);
+        }
+    }
+
+    /// Record a fast phase check of `module_id`, which failed to get a response if `!ok`.
+    fn record_check(&self, module_id: &str, latency: Duration, ok: bool) {
+        let mut states = self.states.lock();
+
+        let Some(state) = states.get_mut(module_id) else {
+            return;
+        };
+
+        state.checks += 1;
+        state.errors += u64::from(!ok);
+        state.last_latency = Some(latency);
+
+        Status::instance().set(STATUS_SECTION, module_id, state);
+    }
+
+    /// Watch the modules directory and each module in it, rescanning when a module is added,
+    /// removed, enabled, disabled or has its `zynx-configs.toml` changed.
+    async fn watch_loop(self: Arc<Self>, mut inotify: AsyncInotify) -> Result<()> {
+        const DEBOUNCE: Duration = Duration::from_millis(200);
+
+        loop {
+            watch_module_dirs(&mut inotify);
+            inotify.wait().await?;
+
+            loop {
+                tokio::select! {
+                    result = inotify.wait() => {
+                        result?;
+                    }
+                    _ = time::sleep(DEBOUNCE) => {
+                        break;
+                    }
+                }
+            }
+
+            info!("module directories changed, rescanning");
+            self.rescan().log_if_error();
+        }
+    }
+}
+
+/// Watch every module directory, for `disable` and `zynx-configs.toml` in them.
+fn watch_module_dirs(inotify: &mut AsyncInotify) {
+    let Ok(entries) = fs::read_dir(root::modules_dir()) else {
+        return;
+    };
+
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            inotify.add_path(&path).ok_or_warn();
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ZygiskPolicyProvider {
+    registry: Arc<ModuleRegistry>,
 }
 
 impl ZygiskPolicyProvider {
+    /// Send slow args and receive the response to them.
+    async fn check_slow(
+        conn: &mut AdapterConnection,
+        slow_args: &CheckArgsSlow,
+    ) -> Result<CheckResponse> {
+        timeout(IO_TIMEOUT, conn.send_message(slow_args))
+            .await
+            .context("send timeout")?
+            .context("failed to send args")?;
+
+        timeout(IO_TIMEOUT, conn.recv_message())
+            .await
+            .context("receive timeout")?
+            .context("failed to receive response")
+    }
+
     /// Check a single adapter in the fast phase
     async fn check_adapter(
-        filter: &FilterType,
-        module_id: &str,
+        adapter: &ZygiskAdapter,
         fast_args: &CheckArgsFast,
     ) -> AdapterCheckResult {
-        // Connect to the external filter
-        let mut conn = match timeout(IO_TIMEOUT, AdapterConnection::connect(filter)).await {
-            Ok(Ok(conn)) => conn,
-            Ok(Err(err)) => {
-                warn!("{module_id}: failed to connect: {err}");
-                return AdapterCheckResult::Failed;
-            }
-            Err(_) => {
-                warn!("{module_id}: connection timeout");
+        let module_id = &adapter.module_id;
+
+        let mut lease = match Lease::acquire(adapter).await {
+            Ok(lease) => lease,
+            Err(err) => {
+                warn!("{module_id}: failed to connect: {err:#}");
                 return AdapterCheckResult::Failed;
             }
         };
 
-        // Send CheckArgsFast
-        if let Err(err) = timeout(IO_TIMEOUT, conn.send_message(fast_args)).await {
-            warn!("{module_id}: failed to send fast args: {err}");
-            conn.close().await;
-            return AdapterCheckResult::Failed;
-        }
+        let mut response = lease.conn().check_fast(fast_args, &adapter.protocol).await;
 
-        // Receive CheckResponse
-        let response: CheckResponse = match timeout(IO_TIMEOUT, conn.recv_message()).await {
-            Ok(Ok(resp)) => resp,
-            Ok(Err(err)) => {
-                warn!("{module_id}: failed to receive response: {err}");
-                conn.close().await;
+        // the filter may have closed the idle connection, retry once on a new one
+        if response.is_err() && lease.is_reused() {
+            if let Err(err) = lease.reconnect(&adapter.filter).await {
+                warn!("{module_id}: failed to reconnect: {err:#}");
+                lease.discard().await;
                 return AdapterCheckResult::Failed;
             }
-            Err(_) => {
-                warn!("{module_id}: receive timeout");
-                conn.close().await;
+
+            response = lease.conn().check_fast(fast_args, &adapter.protocol).await;
+        }
+
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("{module_id}: {err:#}");
+                lease.discard().await;
                 return AdapterCheckResult::Failed;
             }
         };
 
         match CheckResult::try_from(response.result) {
             Ok(CheckResult::Allow) => {
-                conn.close().await;
+                lease.release().await;
                 AdapterCheckResult::Decided(CheckResult::Allow)
             }
             Ok(CheckResult::Deny) => {
-                conn.close().await;
+                lease.release().await;
                 AdapterCheckResult::Decided(CheckResult::Deny)
             }
             Ok(CheckResult::MoreInfo) => {
                 // Keep connection alive for recheck
-                AdapterCheckResult::Pending(Box::new(conn))
+                AdapterCheckResult::Pending(Box::new(lease))
             }
             Err(_) => {
                 warn!("{module_id}: invalid check result: {}", response.result);
-                conn.close().await;
+                lease.discard().await;
                 AdapterCheckResult::Failed
             }
         }
@@ -377,43 +878,36 @@ impl ZygiskPolicyProvider {
 
     /// Recheck a single adapter in the slow phase
     async fn recheck_adapter(
-        mut conn: AdapterConnection,
+        mut lease: Lease,
         module_id: &str,
         slow_args: &CheckArgsSlow,
     ) -> CheckResult {
-        // Send CheckArgsSlow
-        if let Err(err) = timeout(IO_TIMEOUT, conn.send_message(slow_args)).await {
-            warn!("{module_id}: failed to send slow args: {err}");
-            conn.close().await;
-            return CheckResult::Deny;
-        }
-
-        // Receive CheckResponse
-        let response: CheckResponse = match timeout(IO_TIMEOUT, conn.recv_message()).await {
-            Ok(Ok(resp)) => resp,
-            Ok(Err(err)) => {
-                warn!("{module_id}: failed to receive response: {err}");
-                conn.close().await;
-                return CheckResult::Deny;
-            }
-            Err(_) => {
-                warn!("{module_id}: receive timeout");
-                conn.close().await;
+        let response = match Self::check_slow(lease.conn(), slow_args).await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("{module_id}: {err:#}");
+                lease.discard().await;
                 return CheckResult::Deny;
             }
         };
 
-        conn.close().await;
-
         match CheckResult::try_from(response.result) {
-            Ok(CheckResult::Allow) => CheckResult::Allow,
-            Ok(CheckResult::Deny) => CheckResult::Deny,
+            Ok(CheckResult::Allow) => {
+                lease.release().await;
+                CheckResult::Allow
+            }
+            Ok(CheckResult::Deny) => {
+                lease.release().await;
+                CheckResult::Deny
+            }
             Ok(CheckResult::MoreInfo) => {
                 warn!("{module_id}: returned MORE_INFO in slow phase, treating as DENY");
+                lease.discard().await;
                 CheckResult::Deny
             }
             Err(_) => {
                 warn!("{module_id}: invalid check result: {}", response.result);
+                lease.discard().await;
                 CheckResult::Deny
             }
         }
@@ -422,8 +916,17 @@ impl ZygiskPolicyProvider {
 
 #[async_trait]
 impl PolicyProvider for ZygiskPolicyProvider {
-    fn provider_type(&self) -> ProviderType {
-        ProviderType::Zygisk
+    fn kind(&self) -> ProviderKind {
+        ProviderType::Zygisk.into()
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+
+    /// Modules decide in their own code, so targets are only known when disabled.
+    fn target_uids(&self) -> Option<HashSet<Uid>> {
+        (!ZynxConfigs::instance().enable_zygisk).then(HashSet::new)
     }
 
     async fn init(&self) -> Result<()> {
@@ -431,38 +934,78 @@ impl PolicyProvider for ZygiskPolicyProvider {
             return Ok(());
         }
 
-        let adapters = scan_modules()?;
-        *self.adapters.write() = adapters;
+        self.registry.rescan()?;
+
+        let modules_dir = root::modules_dir();
+
+        if !modules_dir.is_dir() {
+            warn!(
+                "{} not found, modules won't be rescanned",
+                modules_dir.display()
+            );
+            return Ok(());
+        }
+
+        let inotify = AsyncInotify::new(
+            modules_dir,
+            EventKindMask::CREATE
+                | EventKindMask::MODIFY_NAME
+                | EventKindMask::ACCESS_CLOSE
+                | EventKindMask::REMOVE,
+        )?;
+
+        PushedDecisions::spawn_listener(self.registry.clone()).log_if_error();
+
+        let registry = self.registry.clone();
+
+        task::spawn(async move {
+            if let Err(err) = registry.watch_loop(inotify).await {
+                error!("module watch loop exited with error: {err:?}")
+            }
+        });
 
         Ok(())
     }
 
-    async fn check(&self, args: &EmbryoCheckArgs<'_>) -> PolicyDecision {
+    async fn check(&self, args: &EmbryoCheckArgs) -> PolicyDecision {
         if !ZynxConfigs::instance().enable_zygisk {
             return PolicyDecision::Deny;
         }
 
-        // Clone adapter data and release lock before any await
-        let adapter_data: Vec<_> = {
-            let adapters = self.adapters.read();
+        // Clone adapters and release lock before any await
+        let adapters = {
+            let adapters = self.registry.adapters.read();
             if adapters.is_empty() {
                 return PolicyDecision::Deny;
             }
-            adapters
-                .iter()
-                .map(|a| (a.filter.clone(), a.module_id.clone()))
-                .collect()
+            adapters.clone()
         };
 
         let fast_args = build_fast_args(args.assume_fast());
 
         // Check all adapters
-        let mut results = Vec::with_capacity(adapter_data.len());
+        let mut results = Vec::with_capacity(adapters.len());
         let mut has_pending = false;
         let mut has_allow = false;
 
-        for (filter, module_id) in &adapter_data {
-            let result = Self::check_adapter(filter, module_id, &fast_args).await;
+        let pushed = PushedDecisions::instance();
+
+        for adapter in &adapters {
+            let result = match pushed.decide(&adapter.module_id, &fast_args.package_info) {
+                Some(result) => AdapterCheckResult::Decided(result),
+                None => {
+                    let start = Instant::now();
+                    let result = Self::check_adapter(adapter, &fast_args).await;
+
+                    self.registry.record_check(
+                        &adapter.module_id,
+                        start.elapsed(),
+                        !matches!(result, AdapterCheckResult::Failed),
+                    );
+
+                    result
+                }
+            };
 
             match &result {
                 AdapterCheckResult::Decided(CheckResult::Allow) => has_allow = true,
@@ -475,25 +1018,11 @@ impl PolicyProvider for ZygiskPolicyProvider {
 
         // Determine decision
         if has_pending {
-            // Need recheck for some adapters, store module_ids for recheck
-            let module_ids: Vec<_> = adapter_data.into_iter().map(|(_, id)| id).collect();
-            PolicyDecision::MoreInfo(Some(Box::new(ZygiskCheckState {
-                results,
-                module_ids,
-            })))
+            // Need recheck for some adapters, store adapters for recheck
+            PolicyDecision::MoreInfo(Some(Box::new(ZygiskCheckState { results, adapters })))
         } else if has_allow {
             // All decided, at least one allowed
-            let attachments: Vec<Attachment> = adapter_data
-                .iter()
-                .map(|(_, module_id)| {
-                    let params = ZygiskParams {
-                        module_name: module_id.clone(),
-                    };
-                    let data = wincode::serialize(&params).unwrap_or_default();
-                    Attachment::with_data(data)
-                })
-                .collect();
-            PolicyDecision::allow_with_attachments(attachments)
+            PolicyDecision::allow_with_attachments(build_attachments(&adapters))
         } else {
             // All decided, none allowed
             PolicyDecision::Deny
@@ -502,7 +1031,7 @@ impl PolicyProvider for ZygiskPolicyProvider {
 
     async fn recheck(
         &self,
-        args: &EmbryoCheckArgs<'_>,
+        args: &EmbryoCheckArgs,
         state: Box<dyn Any + Send + Sync>,
     ) -> PolicyDecision {
         let slow = args.assume_slow();
@@ -516,19 +1045,30 @@ impl PolicyProvider for ZygiskPolicyProvider {
             fast: None, // We don't need to resend fast args
             nice_name: slow.nice_name.clone(),
             app_data_dir: slow.app_data_dir.clone(),
+            gids: slow.gids.iter().flatten().map(|gid| gid.as_raw()).collect(),
+            pkg_data_info_list: slow
+                .pkg_data_info_list
+                .iter()
+                .flatten()
+                .map(|info| DataInfo {
+                    package_name: info.package_name.clone(),
+                    volume_uuid: info.volume_uuid.clone(),
+                    ce_data_inode: info.ce_data_inode,
+                })
+                .collect(),
         };
 
         let mut has_allow = false;
 
-        // Process all results (module_ids are stored in state, no lock needed)
+        // Process all results (adapters are stored in state, no lock needed)
         for (i, result) in check_state.results.drain(..).enumerate() {
             match result {
                 AdapterCheckResult::Decided(CheckResult::Allow) => {
                     has_allow = true;
                 }
-                AdapterCheckResult::Pending(conn) => {
-                    let module_id = &check_state.module_ids[i];
-                    let final_result = Self::recheck_adapter(*conn, module_id, &slow_args).await;
+                AdapterCheckResult::Pending(lease) => {
+                    let module_id = &check_state.adapters[i].module_id;
+                    let final_result = Self::recheck_adapter(*lease, module_id, &slow_args).await;
                     if final_result == CheckResult::Allow {
                         has_allow = true;
                     }
@@ -543,7 +1083,7 @@ impl PolicyProvider for ZygiskPolicyProvider {
         }
 
         if has_allow {
-            PolicyDecision::allow()
+            PolicyDecision::allow_with_attachments(build_attachments(&check_state.adapters))
         } else {
             PolicyDecision::Deny
         }
@@ -572,5 +1112,8 @@ fn build_fast_args(fast: &EmbryoCheckArgsFast) -> CheckArgsFast {
         is_system_server: fast.is_system_server,
         is_child_zygote: fast.is_child_zygote,
         package_info: packages,
+        from_app_zygote: Some(fast.from_app_zygote),
+        inherits_bridge: Some(fast.inherits_bridge),
+        handshake: None,
     }
 }
@@ -0,0 +1,306 @@
+use crate::android::proc_visibility::ProcVisibility;
+use anyhow::{Context, Result, bail};
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::socket::sockopt::PeerCredentials;
+use nix::sys::socket::{self, AddressFamily, MsgFlags, SockFlag, SockProtocol, SockType, UnixAddr};
+use regex_lite::Regex;
+use std::cmp::Reverse;
+use std::fs;
+use std::os::fd::{AsRawFd, OwnedFd};
+use tracing::warn;
+
+// Socket diagnostics of `linux/sock_diag.h` and `linux/unix_diag.h`
+const SOCK_DIAG_BY_FAMILY: u16 = 20;
+const UDIAG_SHOW_NAME: u32 = 0x1;
+const UNIX_DIAG_NAME: u16 = 0;
+
+const NLMSG_HDR_LEN: usize = 16;
+const UNIX_DIAG_MSG_LEN: usize = 16;
+const RTA_HDR_LEN: usize = 4;
+
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn u16_at(buffer: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_ne_bytes(
+        buffer.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(buffer: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(
+        buffer.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Abstract names of the unix sockets listed in `/proc/net/unix`, without the leading `@`.
+fn names_from_procfs() -> Result<Vec<String>> {
+    let content = fs::read_to_string("/proc/net/unix")?;
+
+    Ok(content
+        .lines()
+        .skip(1)
+        .filter_map(|line| match line.rsplit_once(char::is_whitespace) {
+            Some((_, path)) => path.strip_prefix('@').map(String::from),
+            None => None,
+        })
+        .collect())
+}
+
+/// Request of every unix socket with its name, `struct nlmsghdr` and `struct unix_diag_req`.
+fn diag_request() -> Vec<u8> {
+    let len = NLMSG_HDR_LEN + 24;
+    let mut request = Vec::with_capacity(len);
+
+    request.extend((len as u32).to_ne_bytes());
+    request.extend(SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+    request.extend(((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+    request.extend(1u32.to_ne_bytes()); // seq
+    request.extend(0u32.to_ne_bytes()); // pid
+    request.extend([libc::AF_UNIX as u8, 0, 0, 0]); // family, protocol, pad
+    request.extend(u32::MAX.to_ne_bytes()); // states
+    request.extend(0u32.to_ne_bytes()); // ino
+    request.extend(UDIAG_SHOW_NAME.to_ne_bytes());
+    request.extend([0u8; 8]); // cookie
+
+    request
+}
+
+/// Collect the abstract names of the `unix_diag_msg`s in `buffer`, a datagram of the dump.
+/// Returns whether the dump is done.
+fn parse_diag(buffer: &[u8], names: &mut Vec<String>) -> Result<bool> {
+    let mut offset = 0;
+
+    while offset + NLMSG_HDR_LEN <= buffer.len() {
+        let len = u32_at(buffer, offset).context("truncated nlmsghdr")? as usize;
+        let ty = u16_at(buffer, offset + 4).context("truncated nlmsghdr")?;
+
+        if len < NLMSG_HDR_LEN || offset + len > buffer.len() {
+            bail!("malformed netlink message of {len} bytes");
+        }
+
+        let payload = &buffer[offset + NLMSG_HDR_LEN..offset + len];
+
+        match ty as i32 {
+            libc::NLMSG_DONE => return Ok(true),
+            libc::NLMSG_ERROR => {
+                let err = u32_at(payload, 0).context("truncated nlmsgerr")? as i32;
+                return Err(Errno::from_raw(-err)).context("socket diagnostics failed");
+            }
+            _ => {}
+        }
+
+        let mut attr = UNIX_DIAG_MSG_LEN;
+
+        while attr + RTA_HDR_LEN <= payload.len() {
+            let attr_len = u16_at(payload, attr).context("truncated rtattr")? as usize;
+            let attr_ty = u16_at(payload, attr + 2).context("truncated rtattr")?;
+
+            if attr_len < RTA_HDR_LEN || attr + attr_len > payload.len() {
+                bail!("malformed attribute of {attr_len} bytes");
+            }
+
+            let data = &payload[attr + RTA_HDR_LEN..attr + attr_len];
+
+            // abstract names start with a NUL, unlike paths
+            if attr_ty == UNIX_DIAG_NAME
+                && let Some((0, name)) = data.split_first()
+            {
+                names.push(String::from_utf8_lossy(name).into_owned());
+            }
+
+            attr += align4(attr_len);
+        }
+
+        offset += align4(len);
+    }
+
+    Ok(false)
+}
+
+/// Abstract names of the unix sockets of the system, dumped by socket diagnostics, which
+/// procfs restrictions don't apply to.
+fn names_from_diag() -> Result<Vec<String>> {
+    let fd = socket::socket(
+        AddressFamily::Netlink,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        SockProtocol::NetlinkSockDiag,
+    )?;
+
+    // the destination of unbound netlink sockets is the kernel
+    socket::send(fd.as_raw_fd(), &diag_request(), MsgFlags::empty())?;
+
+    let mut names = Vec::new();
+    let mut buffer = vec![0u8; 32 * 1024];
+
+    loop {
+        let len = socket::recv(fd.as_raw_fd(), &mut buffer, MsgFlags::empty())?;
+
+        if len == 0 || parse_diag(&buffer[..len], &mut names)? {
+            return Ok(names);
+        }
+    }
+}
+
+/// Names matching `<prefix>_<seq>_<random>`, latest first.
+fn candidates(prefix: &str, names: Vec<String>) -> Result<Vec<String>> {
+    let pattern = format!(r"^{}_(\d+)_[a-zA-Z0-9-]+$", regex_lite::escape(prefix));
+    let re = Regex::new(&pattern)?;
+
+    let mut candidates: Vec<(u64, String)> = names
+        .into_iter()
+        .filter_map(|name| {
+            let seq = re.captures(&name)?[1].parse().ok()?;
+            Some((seq, name))
+        })
+        .collect();
+
+    candidates.sort_by_key(|(seq, name)| (Reverse(*seq), name.clone()));
+    candidates.dedup();
+
+    Ok(candidates.into_iter().map(|(_, name)| name).collect())
+}
+
+fn connect_as_root(name: &str) -> Result<OwnedFd> {
+    let fd = socket::socket(
+        AddressFamily::Unix,
+        SockType::Stream,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+
+    socket::connect(fd.as_raw_fd(), &UnixAddr::new_abstract(name.as_bytes())?)?;
+
+    let peer = socket::getsockopt(&fd, PeerCredentials)?;
+
+    if peer.uid() != 0 {
+        bail!("bound by uid {}, pid {}", peer.uid(), peer.pid());
+    }
+
+    Ok(fd)
+}
+
+/// Connect to the latest abstract socket matching `<prefix>_<seq>_<random>` bound by root,
+/// since any app can bind an abstract name. Sockets are listed from `/proc/net/unix`, or
+/// dumped by socket diagnostics when procfs hides it.
+pub fn connect(prefix: &str) -> Result<OwnedFd> {
+    let names = match names_from_procfs() {
+        Ok(names) => names,
+        Err(err) => {
+            warn!(
+                "failed to read /proc/net/unix: {err}, {}, listing sockets through netlink",
+                ProcVisibility::instance().hint()
+            );
+
+            names_from_diag().context(format!(
+                "failed to list abstract sockets, use a socket file filter instead: {}",
+                ProcVisibility::instance().hint()
+            ))?
+        }
+    };
+
+    for name in candidates(prefix, names)? {
+        match connect_as_root(&name) {
+            Ok(fd) => return Ok(fd),
+            Err(err) => warn!("skipped abstract socket @{name}: {err}"),
+        }
+    }
+
+    bail!("no abstract socket bound by root found with prefix \"{prefix}\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diag_msg(name: &[u8]) -> Vec<u8> {
+        let attr_len = RTA_HDR_LEN + name.len();
+        let len = NLMSG_HDR_LEN + UNIX_DIAG_MSG_LEN + align4(attr_len);
+        let mut msg = Vec::new();
+
+        msg.extend((len as u32).to_ne_bytes());
+        msg.extend(SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+        msg.extend([0u8; 10]);
+        msg.extend([0u8; UNIX_DIAG_MSG_LEN]);
+        msg.extend((attr_len as u16).to_ne_bytes());
+        msg.extend(UNIX_DIAG_NAME.to_ne_bytes());
+        msg.extend(name);
+        msg.resize(len, 0);
+
+        msg
+    }
+
+    fn done_msg() -> Vec<u8> {
+        let mut msg = Vec::new();
+
+        msg.extend(((NLMSG_HDR_LEN + 4) as u32).to_ne_bytes());
+        msg.extend((libc::NLMSG_DONE as u16).to_ne_bytes());
+        msg.extend([0u8; 14]);
+
+        msg
+    }
+
+    #[test]
+    fn parse_diag_keeps_abstract_names() {
+        let mut buffer = diag_msg(b"\0filter_2_ab");
+        buffer.extend(diag_msg(b"/dev/socket/zygote"));
+        buffer.extend(diag_msg(b"\0x"));
+
+        let mut names = Vec::new();
+
+        assert!(!parse_diag(&buffer, &mut names).unwrap());
+        assert_eq!(names, ["filter_2_ab", "x"]);
+
+        assert!(parse_diag(&done_msg(), &mut names).unwrap());
+        assert_eq!(names.len(), 2);
+    }
+
+    #[test]
+    fn parse_diag_reports_errors() {
+        let mut msg = Vec::new();
+
+        msg.extend(((NLMSG_HDR_LEN + 4) as u32).to_ne_bytes());
+        msg.extend((libc::NLMSG_ERROR as u16).to_ne_bytes());
+        msg.extend([0u8; 10]);
+        msg.extend((-libc::EACCES).to_ne_bytes());
+
+        let err = parse_diag(&msg, &mut Vec::new()).unwrap_err();
+
+        assert_eq!(err.downcast_ref::<Errno>(), Some(&Errno::EACCES));
+    }
+
+    #[test]
+    fn parse_diag_rejects_truncated_messages() {
+        let msg = diag_msg(b"\0filter_1_a");
+
+        assert!(parse_diag(&msg[..msg.len() - 4], &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn candidates_are_latest_first() {
+        let names = [
+            "filter_2_a",
+            "filter_10_b",
+            "other_99_c",
+            "filter_x_d",
+            "filter_3_e",
+        ]
+        .map(String::from)
+        .to_vec();
+
+        assert_eq!(
+            candidates("filter", names).unwrap(),
+            ["filter_10_b", "filter_3_e", "filter_2_a"]
+        );
+    }
+
+    #[test]
+    fn candidates_escape_the_prefix() {
+        let names = ["a.b_1_x", "axb_2_y"].map(String::from).to_vec();
+
+        assert_eq!(candidates("a.b", names).unwrap(), ["a.b_1_x"]);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::injector::bridge::Bridge;
+use anyhow::{Context, Result, bail};
+use nix::libc;
+use nix::sys::prctl;
+use nix::sys::signal::Signal;
+use nix::sys::socket::{self, AddressFamily, ControlMessageOwned, MsgFlags, SockFlag, SockType};
+use nix::sys::stat;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use r3solvr::{BasicResolver, SymbolResolver};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, c_int, c_void};
+use std::io::IoSliceMut;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::{env, mem, thread};
+use tracing::{info, warn};
+
+const COMPANION_ENTRY: &str = "zygisk_companion_entry";
+
+static INSTANCE: Lazy<Companions> = Lazy::new(Companions::default);
+
+/// Library of the module for the current ABI, the one `zygisk_companion_entry` is loaded from.
+pub fn library_path(module_dir: &Path) -> PathBuf {
+    let abi = if cfg!(target_arch = "aarch64") {
+        "arm64-v8a"
+    } else {
+        "x86_64"
+    };
+
+    module_dir.join("zygisk").join(format!("{abi}.so"))
+}
+
+/// Whether `library` exports a companion entry, checked without loading it into the daemon.
+pub fn has_entry(library: &Path) -> bool {
+    BasicResolver::from_file(library)
+        .is_ok_and(|resolver| resolver.lookup_symbol(COMPANION_ENTRY).is_ok())
+}
+
+struct Host {
+    library: PathBuf,
+    child: Child,
+    /// End of the seqpacket socket connections are passed through, attached to injected processes
+    socket: Arc<OwnedFd>,
+}
+
+impl Drop for Host {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Companion processes of zygisk modules, one per module, started on first use. A module's
+/// `connectCompanion` creates a socket pair and passes one end to its companion, which calls
+/// `zygisk_companion_entry` with it on a thread of its own.
+#[derive(Default)]
+pub struct Companions {
+    hosts: Mutex<HashMap<String, Host>>,
+}
+
+impl Companions {
+    pub fn instance() -> &'static Self {
+        &INSTANCE
+    }
+
+    /// Stop companions of modules no longer loaded, or whose library moved.
+    pub fn sync(&self, libraries: &HashMap<String, PathBuf>) {
+        self.hosts.lock().retain(|module_id, host| {
+            if libraries.get(module_id) == Some(&host.library) {
+                return true;
+            }
+
+            info!("{module_id}: stopping companion");
+            false
+        });
+    }
+
+    /// Socket to pass connections to the companion of `module_id` through, restarting the
+    /// companion if it exited.
+    pub fn socket(&self, module_id: &str, library: &Path) -> Option<Arc<OwnedFd>> {
+        let mut hosts = self.hosts.lock();
+
+        if let Some(host) = hosts.get_mut(module_id) {
+            match host.child.try_wait() {
+                Ok(None) => return Some(host.socket.clone()),
+                Ok(Some(exit)) => warn!("{module_id}: companion {exit}, restarting"),
+                Err(err) => warn!("{module_id}: failed to wait companion: {err}, restarting"),
+            }
+        }
+
+        let host = spawn(library)
+            .inspect_err(|err| warn!("{module_id}: failed to start companion: {err:?}"))
+            .ok()?;
+
+        info!("{module_id}: companion started, pid {}", host.child.id());
+
+        let socket = host.socket.clone();
+        hosts.insert(module_id.into(), host);
+
+        Some(socket)
+    }
+}
+
+fn spawn(library: &Path) -> Result<Host> {
+    let (local, remote) = socket::socketpair(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        None,
+        SockFlag::SOCK_CLOEXEC,
+    )?;
+
+    let mut command = Command::new(env::current_exe()?);
+
+    command
+        .arg("zygisk-companion")
+        .arg(library)
+        .stdin(Stdio::from(remote))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    // don't outlive the daemon if it crashes
+    unsafe {
+        command.pre_exec(|| Ok(prctl::set_pdeathsig(Signal::SIGKILL)?));
+    }
+
+    Ok(Host {
+        library: library.into(),
+        child: command.spawn()?,
+        socket: Arc::new(local),
+    })
+}
+
+fn dlopen(path: &Path, flags: c_int) -> Result<*mut c_void> {
+    let path_c = CString::new(path.as_os_str().as_bytes())?;
+    let handle = unsafe { libc::dlopen(path_c.as_ptr(), flags) };
+
+    if handle.is_null() {
+        let err = unsafe { libc::dlerror() };
+        let err = if err.is_null() {
+            "unknown error".into()
+        } else {
+            unsafe { CStr::from_ptr(err) }.to_string_lossy()
+        };
+
+        bail!("failed to load {}: {err}", path.display());
+    }
+
+    Ok(handle)
+}
+
+/// Receive a connection passed by `connectCompanion`, `None` once every peer is gone.
+fn recv_connection(socket: BorrowedFd) -> Result<Option<OwnedFd>> {
+    loop {
+        let mut buffer = [0u8; 1];
+        let mut iov = [IoSliceMut::new(&mut buffer)];
+        let mut cmsg = nix::cmsg_space!([RawFd; 1]);
+
+        let msg = socket::recvmsg::<()>(
+            socket.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )?;
+
+        if msg.bytes == 0 {
+            return Ok(None);
+        }
+
+        for cmsg in msg.cmsgs()? {
+            if let ControlMessageOwned::ScmRights(fds) = cmsg
+                && let Some(&fd) = fds.first()
+            {
+                return Ok(Some(unsafe { OwnedFd::from_raw_fd(fd) }));
+            }
+        }
+
+        warn!("ignored companion connection without fd");
+    }
+}
+
+/// Entry of the `zygisk-companion` process: load the companion of a module, and serve the
+/// connections passed through stdin until the daemon and every injected process closed it.
+pub fn zygisk_companion(library: &Path) -> Result<()> {
+    // modules resolve the RPC helpers of the bridge, e.g. `zynx_companion_serve`
+    let bridge = format!("/proc/self/fd/{}", Bridge::instance().as_fd().as_raw_fd());
+    dlopen(Path::new(&bridge), libc::RTLD_NOW | libc::RTLD_GLOBAL)?;
+
+    let handle = dlopen(library, libc::RTLD_NOW)?;
+    let symbol = CString::new(COMPANION_ENTRY)?;
+    let entry = unsafe { libc::dlsym(handle, symbol.as_ptr()) };
+
+    if entry.is_null() {
+        bail!("{COMPANION_ENTRY} not found in {}", library.display());
+    }
+
+    let entry: extern "C" fn(c_int) = unsafe { mem::transmute(entry) };
+    let socket = unsafe { BorrowedFd::borrow_raw(libc::STDIN_FILENO) };
+
+    while let Some(fd) = recv_connection(socket).context("failed to receive connection")? {
+        thread::spawn(move || {
+            let before = stat::fstat(fd.as_fd()).ok();
+
+            entry(fd.as_raw_fd());
+
+            // the entry may have closed it already, and the number been reused meanwhile
+            let after = stat::fstat(fd.as_fd()).ok();
+            let same = before
+                .zip(after)
+                .is_some_and(|(a, b)| a.st_dev == b.st_dev && a.st_ino == b.st_ino);
+
+            if !same {
+                mem::forget(fd);
+            }
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use crate::status::Status;
+use nix::sys::prctl;
+use nix::sys::signal::Signal;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::{info, warn};
+
+const STATUS_SECTION: &str = "filters";
+
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// A service running at least this long is considered healthy, and the backoff is reset
+const STABLE_UPTIME: Duration = Duration::from_secs(30);
+
+static INSTANCE: Lazy<ManagedFilters> = Lazy::new(ManagedFilters::default);
+
+/// Filter process started by the daemon, declared in `zynx-configs.toml`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceSpec {
+    /// Absolute path of the executable
+    pub path: PathBuf,
+    pub args: Vec<String>,
+    /// The module directory, used as the working directory
+    pub dir: PathBuf,
+}
+
+struct Supervisor {
+    spec: ServiceSpec,
+    task: JoinHandle<()>,
+}
+
+/// Supervises filter processes of modules, restarting them with backoff when they exit.
+#[derive(Default)]
+pub struct ManagedFilters {
+    supervisors: Mutex<HashMap<String, Supervisor>>,
+}
+
+impl ManagedFilters {
+    pub fn instance() -> &'static Self {
+        &INSTANCE
+    }
+
+    /// Start services of new modules, restart changed ones and stop those no longer declared.
+    pub fn sync(&self, services: HashMap<String, ServiceSpec>) {
+        let mut supervisors = self.supervisors.lock();
+
+        supervisors.retain(|module_id, supervisor| {
+            if services.get(module_id) == Some(&supervisor.spec) {
+                return true;
+            }
+
+            info!("{module_id}: stopping filter service");
+            supervisor.task.abort();
+            Status::instance().remove(STATUS_SECTION, module_id);
+
+            false
+        });
+
+        for (module_id, spec) in services {
+            if supervisors.contains_key(&module_id) {
+                continue;
+            }
+
+            let task = tokio::spawn(supervise(module_id.clone(), spec.clone()));
+
+            supervisors.insert(module_id, Supervisor { spec, task });
+        }
+    }
+}
+
+fn spawn(spec: &ServiceSpec) -> io::Result<Child> {
+    let mut command = Command::new(&spec.path);
+
+    command
+        .args(&spec.args)
+        .current_dir(&spec.dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+
+    // don't outlive the daemon if it crashes
+    unsafe {
+        command.pre_exec(|| Ok(prctl::set_pdeathsig(Signal::SIGKILL)?));
+    }
+
+    command.spawn()
+}
+
+/// Run the service of `module_id` until the task is aborted, which kills it.
+async fn supervise(module_id: String, spec: ServiceSpec) {
+    let status = Status::instance();
+    let mut backoff = BACKOFF_MIN;
+    let mut restarts = 0;
+
+    loop {
+        let start = Instant::now();
+
+        let reason = match spawn(&spec) {
+            Ok(mut child) => {
+                let pid = child.id().unwrap_or_default();
+
+                info!("{module_id}: filter service started, pid {pid}");
+                status.set(
+                    STATUS_SECTION,
+                    &module_id,
+                    format_args!("running pid={pid} restarts={restarts}"),
+                );
+
+                match child.wait().await {
+                    Ok(exit) => format!("exited with {exit}"),
+                    Err(err) => format!("failed to wait: {err}"),
+                }
+            }
+            Err(err) => format!("failed to start: {err}"),
+        };
+
+        if start.elapsed() >= STABLE_UPTIME {
+            backoff = BACKOFF_MIN;
+        }
+
+        warn!("{module_id}: filter service {reason}, restarting in {backoff:?}");
+        status.set(
+            STATUS_SECTION,
+            &module_id,
+            format_args!("down ({reason}) restarting in {backoff:?} restarts={restarts}"),
+        );
+
+        time::sleep(backoff).await;
+
+        backoff = (backoff * 2).min(BACKOFF_MAX);
+        restarts += 1;
+    }
+}
@@ -0,0 +1,170 @@
+use super::{AdapterConnection, FilterType, IO_TIMEOUT, ZygiskAdapter};
+use anyhow::{Context, Result, ensure};
+use parking_lot::Mutex;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time;
+use tokio::time::timeout;
+use tracing::debug;
+
+pub const DEFAULT_MAX_CONNECTIONS: usize = 4;
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Persistent connections to the filter of a module, reused across checks instead of
+/// connecting (or spawning a stdio filter) on every fork. At most `max_connections` checks
+/// run concurrently, idle connections are pinged to be dropped once the filter is gone.
+pub struct FilterPool {
+    module_id: String,
+    filter: FilterType,
+    idle: Mutex<Vec<AdapterConnection>>,
+    permits: Arc<Semaphore>,
+    keepalive: AtomicBool,
+}
+
+impl FilterPool {
+    pub fn new(module_id: &str, filter: FilterType, max_connections: usize) -> Arc<Self> {
+        Arc::new(Self {
+            module_id: module_id.into(),
+            filter,
+            idle: Mutex::default(),
+            permits: Arc::new(Semaphore::new(max_connections.max(1))),
+            keepalive: AtomicBool::new(false),
+        })
+    }
+
+    fn spawn_keepalive(self: &Arc<Self>) {
+        if !self.keepalive.swap(true, Ordering::Relaxed) {
+            tokio::spawn(keepalive(Arc::downgrade(self)));
+        }
+    }
+}
+
+/// A connection used by a single check, returned to its pool if any once the check is done.
+pub struct Lease {
+    conn: AdapterConnection,
+    /// Taken from the idle connections, so the filter may have closed it meanwhile
+    reused: bool,
+    pool: Option<(Arc<FilterPool>, OwnedSemaphorePermit)>,
+}
+
+impl Lease {
+    /// Take an idle connection of the pool of `adapter`, or connect to its filter.
+    pub async fn acquire(adapter: &ZygiskAdapter) -> Result<Self> {
+        let Some(pool) = &adapter.pool else {
+            return Ok(Self {
+                conn: connect(&adapter.filter).await?,
+                reused: false,
+                pool: None,
+            });
+        };
+
+        pool.spawn_keepalive();
+
+        let permit = timeout(IO_TIMEOUT, pool.permits.clone().acquire_owned())
+            .await
+            .context("all pooled connections are busy")??;
+
+        let idle = pool.idle.lock().pop();
+        let reused = idle.is_some();
+        let conn = match idle {
+            Some(conn) => conn,
+            None => connect(&pool.filter).await?,
+        };
+
+        Ok(Self {
+            conn,
+            reused,
+            pool: Some((pool.clone(), permit)),
+        })
+    }
+
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+
+    pub fn conn(&mut self) -> &mut AdapterConnection {
+        &mut self.conn
+    }
+
+    /// Replace the connection with a new one, keeping the permit.
+    pub async fn reconnect(&mut self, filter: &FilterType) -> Result<()> {
+        let conn = mem::replace(&mut self.conn, connect(filter).await?);
+        conn.close().await;
+        self.reused = false;
+
+        Ok(())
+    }
+
+    /// The check is done, keep the connection for the next one if pooled.
+    pub async fn release(self) {
+        match self.pool {
+            Some((pool, _permit)) => pool.idle.lock().push(self.conn),
+            None => self.conn.close().await,
+        }
+    }
+
+    /// The connection is broken or in an unknown state, close it.
+    pub async fn discard(self) {
+        self.conn.close().await;
+    }
+}
+
+async fn connect(filter: &FilterType) -> Result<AdapterConnection> {
+    timeout(IO_TIMEOUT, AdapterConnection::connect(filter))
+        .await
+        .context("connection timeout")?
+}
+
+/// An empty frame, answered with an empty frame by filters supporting pooled connections.
+async fn ping(conn: &mut AdapterConnection) -> Result<()> {
+    conn.send_message(&()).await?;
+
+    let mut len = [0u8; 4];
+    conn.recv_data(&mut len).await?;
+
+    ensure!(len == [0; 4], "unexpected reply to ping");
+
+    Ok(())
+}
+
+async fn keepalive(pool: Weak<FilterPool>) {
+    let mut interval = time::interval(KEEPALIVE_INTERVAL);
+
+    // the first tick completes immediately
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        // the module was removed or its config changed
+        let Some(pool) = pool.upgrade() else {
+            break;
+        };
+
+        let idle = mem::take(&mut *pool.idle.lock());
+        let mut alive = Vec::with_capacity(idle.len());
+
+        for mut conn in idle {
+            match timeout(IO_TIMEOUT, ping(&mut conn)).await {
+                Ok(Ok(())) => alive.push(conn),
+                Ok(Err(err)) => {
+                    debug!("{}: dropping pooled connection: {err}", pool.module_id);
+                    conn.close().await;
+                }
+                Err(_) => {
+                    debug!(
+                        "{}: dropping pooled connection: ping timeout",
+                        pool.module_id
+                    );
+                    conn.close().await;
+                }
+            }
+        }
+
+        pool.idle.lock().extend(alive);
+    }
+}
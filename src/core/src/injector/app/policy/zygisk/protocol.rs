@@ -0,0 +1,61 @@
+use crate::injector::app::policy::proto::{CheckArgsFast, CheckResponse, Handshake};
+
+pub const PROTOCOL_VERSION: u32 = 2;
+
+const FROM_APP_ZYGOTE: &str = "from_app_zygote";
+const INHERITS_BRIDGE: &str = "inherits_bridge";
+
+/// Optional fields of [`CheckArgsFast`] added by later protocols
+const FEATURES: [&str; 2] = [FROM_APP_ZYGOTE, INHERITS_BRIDGE];
+
+/// Protocol negotiated with a filter on the first check of its module.
+#[derive(Debug, Clone)]
+pub struct Protocol {
+    version: u32,
+    features: Vec<String>,
+}
+
+impl Protocol {
+    pub fn handshake() -> Handshake {
+        Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            features: FEATURES.map(String::from).into(),
+        }
+    }
+
+    /// The lower version of both sides and the features they both know of. Filters not
+    /// answering the handshake only speak protocol 1.
+    pub fn negotiate(response: &CheckResponse) -> Self {
+        let Some(handshake) = &response.handshake else {
+            return Self {
+                version: 1,
+                features: Vec::new(),
+            };
+        };
+
+        Self {
+            version: handshake.protocol_version.clamp(1, PROTOCOL_VERSION),
+            features: handshake
+                .features
+                .iter()
+                .filter(|feature| FEATURES.contains(&feature.as_str()))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn supports(&self, feature: &str) -> bool {
+        self.version >= 2 && self.features.iter().any(|it| it == feature)
+    }
+
+    /// Strip the fields the filter doesn't know of.
+    pub fn downgrade(&self, args: &mut CheckArgsFast) {
+        if !self.supports(FROM_APP_ZYGOTE) {
+            args.from_app_zygote = None;
+        }
+
+        if !self.supports(INHERITS_BRIDGE) {
+            args.inherits_bridge = None;
+        }
+    }
+}
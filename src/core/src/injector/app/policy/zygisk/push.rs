@@ -0,0 +1,191 @@
+use super::{IO_TIMEOUT, ModuleRegistry, Transport};
+use crate::injector::app::policy::decision_cache::DecisionCache;
+use crate::injector::app::policy::proto::{CheckResult, PackageInfo, PushDecisions, Subscribe};
+use anyhow::{Context, Result, bail};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::time::timeout;
+use tracing::{error, info, warn};
+
+pub const PUSH_SOCKET: &str = "/data/adb/zynx/filters.sock";
+
+static INSTANCE: Lazy<PushedDecisions> = Lazy::new(PushedDecisions::default);
+
+/// Decisions of a module, and the subscription they came from
+struct Subscription {
+    id: u64,
+    decisions: HashMap<String, CheckResult>,
+}
+
+/// Package decisions pushed by subscribed filters, short-circuiting the per-fork check of
+/// their modules. Decisions only last as long as the connection of the filter.
+#[derive(Default)]
+pub struct PushedDecisions {
+    modules: RwLock<HashMap<String, Subscription>>,
+    next_id: AtomicU64,
+}
+
+impl PushedDecisions {
+    pub fn instance() -> &'static Self {
+        &INSTANCE
+    }
+
+    /// Allow if a package is allowed, deny if all are denied, none if some weren't pushed.
+    pub fn decide(&self, module_id: &str, packages: &[PackageInfo]) -> Option<CheckResult> {
+        if packages.is_empty() {
+            return None;
+        }
+
+        let modules = self.modules.read();
+        let decisions = &modules.get(module_id)?.decisions;
+
+        let results: Vec<_> = packages
+            .iter()
+            .map(|pkg| decisions.get(&pkg.package_name).copied())
+            .collect();
+
+        if results.contains(&Some(CheckResult::Allow)) {
+            return Some(CheckResult::Allow);
+        }
+
+        results
+            .iter()
+            .all(|it| *it == Some(CheckResult::Deny))
+            .then_some(CheckResult::Deny)
+    }
+
+    /// Listen on [`PUSH_SOCKET`] for filters of the modules in `registry`.
+    pub fn spawn_listener(registry: Arc<ModuleRegistry>) -> Result<()> {
+        match fs::remove_file(PUSH_SOCKET) {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+
+        let listener =
+            UnixListener::bind(PUSH_SOCKET).context(format!("failed to bind {PUSH_SOCKET}"))?;
+
+        tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        error!("failed to accept push connection: {err}");
+                        continue;
+                    }
+                };
+
+                let registry = registry.clone();
+
+                tokio::spawn(async move {
+                    if let Err(err) = Self::instance().serve(stream, &registry).await {
+                        warn!("push connection closed: {err:#}");
+                    }
+                });
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn serve(&self, stream: UnixStream, registry: &ModuleRegistry) -> Result<()> {
+        // the socket lives in /data/adb, but never trust a decision from an app
+        let cred = stream.peer_cred()?;
+        if cred.uid() != 0 {
+            bail!("rejected push connection of uid {}", cred.uid());
+        }
+
+        let mut transport = Transport::Socket(stream);
+
+        let subscribe: Subscribe = timeout(IO_TIMEOUT, transport.recv_message())
+            .await
+            .context("subscribe timeout")??;
+        let module_id = subscribe.module_id;
+
+        if !registry
+            .adapters
+            .read()
+            .iter()
+            .any(|adapter| adapter.module_id == module_id)
+        {
+            bail!("subscription of unknown module: {module_id}");
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        info!("{module_id}: filter subscribed to push decisions");
+
+        // a new subscription of the module replaces the previous one
+        self.modules.write().insert(
+            module_id.clone(),
+            Subscription {
+                id,
+                decisions: HashMap::new(),
+            },
+        );
+
+        let result = self.receive_loop(&mut transport, &module_id, id).await;
+
+        // decisions can't be kept up to date anymore
+        let mut modules = self.modules.write();
+        if modules.get(&module_id).is_some_and(|it| it.id == id) {
+            modules.remove(&module_id);
+            DecisionCache::instance().invalidate("zygisk filter unsubscribed");
+        }
+
+        info!("{module_id}: filter unsubscribed from push decisions");
+
+        result
+    }
+
+    async fn receive_loop(
+        &self,
+        transport: &mut Transport,
+        module_id: &str,
+        id: u64,
+    ) -> Result<()> {
+        loop {
+            let push: PushDecisions = match transport.recv_message().await {
+                Ok(push) => push,
+                Err(err)
+                    if err
+                        .downcast_ref::<std::io::Error>()
+                        .is_some_and(|err| err.kind() == ErrorKind::UnexpectedEof) =>
+                {
+                    return Ok(());
+                }
+                Err(err) => return Err(err),
+            };
+
+            let mut modules = self.modules.write();
+            let Some(subscription) = modules.get_mut(module_id).filter(|it| it.id == id) else {
+                bail!("{module_id}: replaced by a newer subscription");
+            };
+
+            if push.reset {
+                subscription.decisions.clear();
+            }
+
+            for decision in push.decisions {
+                match CheckResult::try_from(decision.result) {
+                    Ok(CheckResult::MoreInfo) => {
+                        subscription.decisions.remove(&decision.package_name);
+                    }
+                    Ok(result) => {
+                        subscription.decisions.insert(decision.package_name, result);
+                    }
+                    Err(_) => {
+                        warn!("{module_id}: invalid pushed result: {}", decision.result);
+                    }
+                }
+            }
+
+            DecisionCache::instance().invalidate("zygisk decisions pushed");
+        }
+    }
+}
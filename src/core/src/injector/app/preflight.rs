@@ -0,0 +1,213 @@
+use crate::android::root::contexts;
+use crate::injector::app::policy::ProviderBundle;
+use crate::injector::bridge::Bridge;
+use anyhow::{Result, bail};
+use nix::unistd::Pid;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::warn;
+use zynx_bridge_shared::zygote::ProviderType;
+use zynx_misc::selinux;
+
+static QUERY_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Domain of an embryo, contexts of its libraries and whether connecting is checked
+type Checked = (String, BTreeSet<String>, bool);
+
+/// Checks passed, not queried again. Denials are, as rules may have been added live since.
+static PASSED: Lazy<Mutex<HashSet<Checked>>> = Lazy::new(Mutex::default);
+
+enum Target {
+    /// The domain of the embryo itself
+    Domain,
+    /// The domain of the daemon, sending fds to the embryo
+    Daemon,
+    /// The memfd of the bridge library
+    Bridge,
+    /// Library memfds labeled by [`contexts::label`]
+    ModuleFile,
+}
+
+/// Access the embryo needs before it specializes, while still in the zygote's domain.
+struct Requirement {
+    target: Target,
+    class: &'static str,
+    perms: &'static [&'static str],
+    reason: &'static str,
+}
+
+const REQUIREMENTS: &[Requirement] = &[
+    Requirement {
+        target: Target::Domain,
+        class: "process",
+        perms: &["execmem"],
+        reason: "mapping the trampoline executable",
+    },
+    Requirement {
+        target: Target::Daemon,
+        class: "fd",
+        perms: &["use"],
+        reason: "receiving fds from the daemon",
+    },
+    Requirement {
+        target: Target::Bridge,
+        class: "file",
+        perms: &["read", "getattr", "map", "execute"],
+        reason: "loading the bridge",
+    },
+];
+
+const LIBRARY_REQUIREMENT: Requirement = Requirement {
+    target: Target::ModuleFile,
+    class: "file",
+    perms: &["read", "getattr", "map", "execute"],
+    reason: "loading libraries of modules",
+};
+
+const CONNECT_REQUIREMENT: Requirement = Requirement {
+    target: Target::Daemon,
+    class: "unix_stream_socket",
+    perms: &["connectto"],
+    reason: "connecting to the daemon without ptrace",
+};
+
+/// Whether `perm` on `class` is access the injection needs, as checked by [`check`].
+pub fn is_required(class: &str, perm: &str) -> bool {
+    REQUIREMENTS
+        .iter()
+        .chain([&LIBRARY_REQUIREMENT, &CONNECT_REQUIREMENT])
+        .any(|requirement| requirement.class == class && requirement.perms.contains(&perm))
+}
+
+/// Type of a `user:role:type:level` context, as used in policy rules.
+fn type_of(context: &str) -> &str {
+    context.split(':').nth(2).unwrap_or(context)
+}
+
+fn warn_once(err: anyhow::Error) {
+    if !QUERY_FAILED.swap(true, Ordering::Relaxed) {
+        warn!("failed to query SELinux policy, skipping injection preflight checks: {err:?}");
+    }
+}
+
+/// Label the library memfds of `bundles` with contexts the domain of the embryo `pid` may
+/// map. Returns the providers they belong to, for [`check`].
+pub fn label_libraries(pid: Pid, bundles: &[ProviderBundle]) -> Vec<ProviderType> {
+    let libraries: Vec<_> = bundles
+        .iter()
+        .flat_map(|bundle| {
+            bundle
+                .attachments
+                .iter()
+                .filter(|attachment| attachment.library)
+                .filter_map(move |attachment| Some((bundle.ty, attachment.fd.as_ref()?)))
+        })
+        .collect();
+
+    if libraries.is_empty() {
+        return Vec::new();
+    }
+
+    let domain = selinux::getpidcon(Some(pid.as_raw()))
+        .inspect_err(|err| warn!("failed to read the domain of {pid}: {err:?}"))
+        .ok();
+
+    for (provider, fd) in &libraries {
+        contexts::label(fd, *provider, domain.as_deref());
+    }
+
+    let providers: BTreeSet<_> = libraries.iter().map(|(provider, _)| *provider).collect();
+    providers.into_iter().collect()
+}
+
+/// Check the policy allows the embryo `pid` what the injection needs, with libraries of
+/// `libraries` labeled by [`label_libraries`], so that a denial is reported with the missing
+/// rules instead of an obscure failure in the remote process. Skipped if SELinux is
+/// permissive or the policy can't be queried, and for domains already passing the check.
+pub fn check(pid: Pid, libraries: &[ProviderType], with_connect: bool) -> Result<()> {
+    if !selinux::is_enforcing() {
+        return Ok(());
+    }
+
+    let domain = match selinux::getpidcon(Some(pid.as_raw())) {
+        Ok(domain) => domain,
+        Err(err) => {
+            warn_once(err);
+            return Ok(());
+        }
+    };
+
+    let libraries: BTreeSet<_> = libraries
+        .iter()
+        .map(|provider| contexts::selected(*provider, &domain))
+        .collect();
+
+    let key = (domain, libraries, with_connect);
+
+    if PASSED.lock().contains(&key) {
+        return Ok(());
+    }
+
+    match missing_rules(&key.0, &key.1, with_connect) {
+        Ok(missing) if missing.is_empty() => {
+            PASSED.lock().insert(key);
+            Ok(())
+        }
+        Ok(missing) => bail!(
+            "SELinux denies what the injection needs, add the missing rules with \
+            `magiskpolicy --live` or in sepolicy.rule of a module:\n{}",
+            missing.join("\n")
+        ),
+        Err(err) => {
+            warn_once(err);
+            Ok(())
+        }
+    }
+}
+
+fn missing_rules(
+    domain: &str,
+    libraries: &BTreeSet<String>,
+    with_connect: bool,
+) -> Result<Vec<String>> {
+    let daemon = selinux::getpidcon(None)?;
+    let bridge = selinux::fgetcon(Bridge::instance())?;
+
+    let mut missing = Vec::new();
+
+    let requirements = REQUIREMENTS
+        .iter()
+        .map(|requirement| (requirement, None))
+        .chain(
+            libraries
+                .iter()
+                .map(|library| (&LIBRARY_REQUIREMENT, Some(library.as_str()))),
+        )
+        .chain(with_connect.then_some((&CONNECT_REQUIREMENT, None)));
+
+    for (requirement, library) in requirements {
+        let target = match requirement.target {
+            Target::Domain => domain,
+            Target::Daemon => daemon.as_str(),
+            Target::Bridge => bridge.as_str(),
+            Target::ModuleFile => library.unwrap_or_default(),
+        };
+
+        let denied = selinux::check_access(domain, target, requirement.class, requirement.perms)?;
+
+        if !denied.is_empty() {
+            missing.push(format!(
+                "  allow {} {} {} {{ {} }}  # {}",
+                type_of(domain),
+                type_of(target),
+                requirement.class,
+                denied.join(" "),
+                requirement.reason
+            ));
+        }
+    }
+
+    Ok(missing)
+}
@@ -0,0 +1,53 @@
+// assembled on any host by the snapshot test
+#[cfg(any(target_arch = "aarch64", test))]
+mod aarch64;
+#[cfg(test)]
+mod snapshot;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+use aarch64 as arch;
+#[cfg(target_arch = "x86_64")]
+use x86_64 as arch;
+
+use anyhow::{Result, bail};
+use std::ffi::CStr;
+use std::os::fd::RawFd;
+use zynx_bridge_shared::zygote::BridgeArgs;
+
+/// What the trampoline is assembled from, resolved beforehand so that the same inputs always
+/// assemble the same bytes.
+pub struct TrampolineInputs<'a> {
+    /// Where the trampoline is placed, and the size of its region
+    pub addr: usize,
+    pub size: usize,
+    /// Offset of the writable slots in the region, the trampoline must fit before them
+    pub slots_offset: usize,
+    pub specialize_fn: usize,
+    pub dlopen: usize,
+    pub dlsym: usize,
+    pub munmap: usize,
+    pub bridge_name: &'a CStr,
+    pub bridge_fd: RawFd,
+    pub args_cnt: usize,
+    pub bridge_args: BridgeArgs,
+}
+
+pub struct Trampoline {
+    pub bytecode: Vec<u8>,
+    /// Length of the code, followed by the data it loads
+    pub code_len: usize,
+}
+
+/// Assemble the trampoline which loads the bridge, calls its hooks around `SpecializeCommon`
+/// and unmaps itself.
+pub fn assemble(inputs: &TrampolineInputs) -> Result<Trampoline> {
+    let trampoline = arch::assemble(inputs)?;
+
+    if trampoline.bytecode.len() > inputs.slots_offset {
+        bail!("trampoline too large: {} bytes", trampoline.bytecode.len());
+    }
+
+    Ok(trampoline)
+}
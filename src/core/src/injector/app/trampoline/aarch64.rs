@@ -0,0 +1,172 @@
+use crate::dynasm;
+use crate::injector::app::trampoline::{Trampoline, TrampolineInputs};
+use anyhow::Result;
+use dynasmrt::aarch64::Aarch64Relocation;
+use dynasmrt::{DynasmApi, VecAssembler};
+use nix::libc::RTLD_NOW;
+use std::os::fd::FromRawFd;
+use syscalls::aarch64::Sysno;
+use zynx_bridge_shared::remote_lib::DlextInfo;
+use zynx_bridge_shared::zygote::BridgeArgs;
+
+pub fn assemble(inputs: &TrampolineInputs) -> Result<Trampoline> {
+    // Assemble the AArch64 trampoline code using dynasm
+    let mut ops: VecAssembler<Aarch64Relocation> = VecAssembler::new(0);
+
+    // Prepare dlopen info: load bridge library from the installed fd
+    let info = unsafe { DlextInfo::from_raw_fd(inputs.bridge_fd) };
+
+    dynasm!(ops
+        // Step 1: Save specialize args (x0-x7) onto the stack
+        ; stp x6, x7, [sp, #-16]!
+        ; stp x4, x5, [sp, #-16]!
+        ; stp x2, x3, [sp, #-16]!
+        ; stp x0, x1, [sp, #-16]!
+
+        // Step 2: Load the bridge library via android_dlopen_ext
+        //   x0 = library name (see `Bridge::name`), x1 = RTLD_NOW, x2 = DlextInfo
+        ; stp fp, lr, [sp, #-16]!
+        ; ldr ip, >dlopen
+        ; adr x0, >lib_name
+        ; mov x1, RTLD_NOW as _
+        ; adr x2, >lib_info
+        ; blr ip
+        ; ldp fp, lr, [sp], #16
+
+        // Step 3: Close the bridge fd via syscall (no longer needed after dlopen)
+        //   x0 = dlopen handle (saved/restored around the syscall)
+        ; stp x0, xzr, [sp, #-16]!
+        ; mov x8, Sysno::close as _
+        ; mov x0, inputs.bridge_fd as _
+        ; svc #0
+        ; ldp x0, xzr, [sp], #16
+
+        // Step 4a: Resolve the post-hook symbol and store its address
+        //   dlsym(handle, "specialize_post") -> post_hook_addr
+        ; stp fp, lr, [sp, #-16]!
+        ; stp x0, x1, [sp, #-16]!
+        ; ldr ip, >dlsym
+        ; adr x1, >post_hook_sym
+        ; blr ip
+        ; ldr x1, >slots
+        ; str x0, [x1, #8]
+        ; ldp x0, x1, [sp], #16
+        ; ldp fp, lr, [sp], #16
+
+        // Step 4b: Resolve the pre-hook symbol
+        //   dlsym(handle, "specialize_pre") -> x0
+        ; stp fp, lr, [sp, #-16]!
+        ; ldr ip, >dlsym
+        ; adr x1, >pre_hook_sym
+        ; blr ip
+        ; ldp fp, lr, [sp], #16
+
+        // Step 5: Call the pre-hook
+        //   pre_hook(args_on_stack, args_cnt, &bridge_args)
+        ; stp fp, lr, [sp, #-16]!
+        ; mov ip, x0
+        ; add x0, sp, 16
+        ; mov x1, inputs.args_cnt as _
+        ; adr x2, >bridge_args
+        ; blr ip
+        ; ldp fp, lr, [sp], #16
+
+        // Step 6: Hijack LR so SpecializeCommon returns to our trampoline
+        //   Save the real LR, then set LR to the trampoline label
+        ; ldr x0, >slots
+        ; str lr, [x0]
+        ; adr lr, >trampoline
+
+        // Step 7: Restore original specialize args and jump to SpecializeCommon
+        ; ldp x0, x1, [sp], #16
+        ; ldp x2, x3, [sp], #16
+        ; ldp x4, x5, [sp], #16
+        ; ldp x6, x7, [sp], #16
+
+        // Tail-call into the real SpecializeCommon
+        ; ldr ip, >specialize
+        ; br ip
+
+        // Step 8: Post-hook trampoline (SpecializeCommon returns here)
+        ; trampoline:
+        ; stp fp, lr, [sp, #-16]!
+        ; ldr ip, >slots
+        ; ldr ip, [ip, #8]
+        ; blr ip
+        ; ldp fp, lr, [sp], #16
+
+        // Step 9: Self-cleanup via munmap, then return to the real caller
+        //   Restore original LR, then tail-call munmap(trampoline_addr, size)
+        ; ldr lr, >slots
+        ; ldr lr, [lr]
+        ; ldr ip, >munmap
+        ; ldr x0, >trampoline_addr
+        ; mov x1, inputs.size as _
+        ; br ip
+    );
+
+    let code_len = ops.offset().0;
+
+    dynasm!(ops
+        // ---- Data section ----
+
+        // Address of the original SpecializeCommon function
+        ; .align 8
+        ; specialize:
+        ;; ops.push_u64(inputs.specialize_fn as _)
+
+        // Resolved addresses of dlopen and dlsym
+        ; .align 8
+        ; dlopen:
+        ;; ops.push_u64(inputs.dlopen as _)
+
+        ; .align 8
+        ; dlsym:
+        ;; ops.push_u64(inputs.dlsym as _)
+
+        // Bridge library name (used by android_dlopen_ext)
+        ; .align 8
+        ; lib_name:
+        ;; ops.extend(inputs.bridge_name.to_bytes_with_nul())
+
+        // DlextInfo struct (tells dlopen to load from fd)
+        ; .align align_of::<DlextInfo>()
+        ; lib_info:
+        ;; ops.extend(crate::misc::as_byte_slice(&info))
+
+        // BridgeArgs struct passed to the pre-hook
+        ; .align align_of::<BridgeArgs>()
+        ; bridge_args:
+        ;; ops.extend(crate::misc::as_byte_slice(&inputs.bridge_args))
+
+        // Hook symbol name strings
+        ; .align 8
+        ; pre_hook_sym:
+        ;; ops.extend(c"specialize_pre".to_bytes_with_nul())
+
+        ; .align 8
+        ; post_hook_sym:
+        ;; ops.extend(c"specialize_post".to_bytes_with_nul())
+
+        // Writable slots: the original return address, then the resolved post-hook
+        // function pointer
+        ; .align 8
+        ; slots:
+        ;; ops.push_u64((inputs.addr + inputs.slots_offset) as _)
+
+        // Resolved address of munmap (for self-cleanup)
+        ; .align 8
+        ; munmap:
+        ;; ops.push_u64(inputs.munmap as _)
+
+        // Base address of this trampoline (passed to munmap)
+        ; .align 8
+        ; trampoline_addr:
+        ;; ops.push_u64(inputs.addr as _)
+    );
+
+    // Finalize the assembled bytecode
+    let bytecode = ops.finalize()?;
+
+    Ok(Trampoline { bytecode, code_len })
+}
@@ -0,0 +1,122 @@
+use crate::injector::app::trampoline::{TrampolineInputs, aarch64};
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::{env, fs};
+use yaxpeax_arch::{Decoder, U8Reader};
+use yaxpeax_arm::armv8::a64::InstDecoder;
+use zynx_bridge_shared::zygote::{BridgeArgs, SessionId, SpecializeLayout};
+
+/// Code of the trampoline assembled from [`fixed_inputs`], one `<offset>: <word>  <disassembly>`
+/// line per instruction
+const SNAPSHOT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/snapshots/trampoline.txt");
+
+/// Set to rewrite the snapshot instead of checking it, see `just bless-trampoline`
+const BLESS_ENV: &str = "ZYNX_BLESS";
+
+const SNAPSHOT_HEADER: &str = "\
+# Code of the AArch64 trampoline assembled from the fixed inputs of
+# src/injector/app/trampoline/snapshot.rs, checked by `cargo test`. Only the words are compared.
+# Regenerate with `just bless-trampoline`
+";
+
+/// Inputs as they could be on a device with 4K pages, none of them resolved on this one.
+fn fixed_inputs() -> TrampolineInputs<'static> {
+    TrampolineInputs {
+        addr: 0x7000_0000,
+        size: 0x10000,
+        slots_offset: 0xf000,
+        specialize_fn: 0x7100_1000,
+        dlopen: 0x7200_1000,
+        dlsym: 0x7200_2000,
+        munmap: 0x7300_1000,
+        bridge_name: c"libzynx_bridge.so",
+        bridge_fd: 42,
+        args_cnt: 22,
+        bridge_args: BridgeArgs {
+            conn_fd: 43,
+            specialize_layout: SpecializeLayout::forward_compat(),
+            session: SessionId(0x5a5a),
+        },
+    }
+}
+
+fn assemble_code() -> Result<Vec<u32>> {
+    let trampoline = aarch64::assemble(&fixed_inputs())?;
+
+    Ok(trampoline.bytecode[..trampoline.code_len]
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect())
+}
+
+fn disassemble(word: u32) -> String {
+    let bytes = word.to_le_bytes();
+
+    match InstDecoder::default().decode(&mut U8Reader::new(&bytes)) {
+        Ok(inst) => inst.to_string(),
+        Err(err) => format!("<{err}>"),
+    }
+}
+
+fn parse_snapshot(snapshot: &str) -> Result<Vec<u32>> {
+    snapshot
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let word = line
+                .split_whitespace()
+                .nth(1)
+                .context(format!("malformed line {line:?}"))?;
+            Ok(u32::from_str_radix(word, 16)?)
+        })
+        .collect()
+}
+
+fn render(code: &[u32]) -> Result<String> {
+    let mut snapshot = String::from(SNAPSHOT_HEADER);
+
+    for (index, word) in code.iter().enumerate() {
+        writeln!(
+            snapshot,
+            "{:04x}: {word:08x}  {}",
+            index * 4,
+            disassemble(*word)
+        )?;
+    }
+
+    Ok(snapshot)
+}
+
+/// Compare the code of the trampoline with the snapshot, reporting the first instruction
+/// which differs.
+#[test]
+fn code_matches_snapshot() -> Result<()> {
+    let actual = assemble_code()?;
+
+    if env::var_os(BLESS_ENV).is_some() {
+        fs::write(SNAPSHOT_PATH, render(&actual)?)?;
+        return Ok(());
+    }
+
+    let snapshot = fs::read_to_string(SNAPSHOT_PATH).context("failed to read the snapshot")?;
+    let expected = parse_snapshot(&snapshot).context("malformed trampoline snapshot")?;
+
+    let describe = |word: Option<&u32>| match word {
+        Some(word) => format!("{word:08x} ({})", disassemble(*word)),
+        None => "nothing".into(),
+    };
+
+    let len = expected.len().max(actual.len());
+
+    if let Some(index) = (0..len).find(|&index| expected.get(index) != actual.get(index)) {
+        panic!(
+            "code differs at {:#06x}: expected {}, assembled {}, rerun with {BLESS_ENV}=1 if \
+             intended",
+            index * 4,
+            describe(expected.get(index)),
+            describe(actual.get(index))
+        );
+    }
+
+    Ok(())
+}
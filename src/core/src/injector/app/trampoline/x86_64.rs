@@ -0,0 +1,163 @@
+use crate::injector::app::trampoline::{Trampoline, TrampolineInputs};
+use anyhow::Result;
+use dynasmrt::x64::X64Relocation;
+use dynasmrt::{DynasmApi, DynasmLabelApi, VecAssembler, dynasm};
+use nix::libc::RTLD_NOW;
+use std::os::fd::FromRawFd;
+use syscalls::Sysno;
+use zynx_bridge_shared::remote_lib::DlextInfo;
+use zynx_bridge_shared::zygote::BridgeArgs;
+
+/// Same steps as the AArch64 trampoline, except that the return address is on the stack: it's
+/// moved to the slots first, so that the saved register args and the stack args are contiguous.
+pub fn assemble(inputs: &TrampolineInputs) -> Result<Trampoline> {
+    let mut ops: VecAssembler<X64Relocation> = VecAssembler::new(0);
+
+    // Prepare dlopen info: load bridge library from the installed fd
+    let info = unsafe { DlextInfo::from_raw_fd(inputs.bridge_fd) };
+
+    dynasm!(ops
+        ; .arch x64
+
+        // Step 1: Move the return address to the slots, then save specialize args (rdi, rsi,
+        // rdx, rcx, r8, r9) onto the stack, right below the stack args
+        ; pop r11
+        ; mov rax, QWORD [>slots]
+        ; mov [rax], r11
+        ; push r9
+        ; push r8
+        ; push rcx
+        ; push rdx
+        ; push rsi
+        ; push rdi
+
+        // Step 2: Load the bridge library via android_dlopen_ext
+        //   rdi = library name (see `Bridge::name`), rsi = RTLD_NOW, rdx = DlextInfo
+        ; lea rdi, [>lib_name]
+        ; mov esi, RTLD_NOW
+        ; lea rdx, [>lib_info]
+        ; call QWORD [>dlopen]
+
+        // Step 3: Close the bridge fd via syscall (no longer needed after dlopen)
+        //   rax = dlopen handle, saved twice to keep the stack aligned
+        ; push rax
+        ; push rax
+        ; mov eax, Sysno::close as i32
+        ; mov edi, inputs.bridge_fd
+        ; syscall
+
+        // Step 4a: Resolve the post-hook symbol and store its address
+        //   dlsym(handle, "specialize_post") -> post_hook_addr
+        ; mov rdi, [rsp]
+        ; lea rsi, [>post_hook_sym]
+        ; call QWORD [>dlsym]
+        ; mov rcx, QWORD [>slots]
+        ; mov [rcx + 8], rax
+
+        // Step 4b: Resolve the pre-hook symbol
+        //   dlsym(handle, "specialize_pre") -> rax
+        ; pop rdi
+        ; pop rdi
+        ; lea rsi, [>pre_hook_sym]
+        ; call QWORD [>dlsym]
+
+        // Step 5: Call the pre-hook
+        //   pre_hook(args_on_stack, args_cnt, &bridge_args)
+        ; mov rdi, rsp
+        ; mov esi, inputs.args_cnt as i32
+        ; lea rdx, [>bridge_args]
+        ; call rax
+
+        // Step 6: Restore original specialize args
+        ; pop rdi
+        ; pop rsi
+        ; pop rdx
+        ; pop rcx
+        ; pop r8
+        ; pop r9
+
+        // Step 7: Call into the real SpecializeCommon, returning to our trampoline
+        ; lea r11, [>trampoline]
+        ; push r11
+        ; jmp QWORD [>specialize]
+
+        // Step 8: Post-hook trampoline (SpecializeCommon returns here)
+        ; trampoline:
+        ; mov rax, QWORD [>slots]
+        ; call QWORD [rax + 8]
+
+        // Step 9: Self-cleanup via munmap, then return to the real caller
+        //   Push the original return address, then tail-call munmap(trampoline_addr, size)
+        ; mov rax, QWORD [>slots]
+        ; push QWORD [rax]
+        ; mov rdi, QWORD [>trampoline_addr]
+        ; mov rsi, QWORD inputs.size as i64
+        ; jmp QWORD [>munmap]
+    );
+
+    let code_len = ops.offset().0;
+
+    dynasm!(ops
+        ; .arch x64
+
+        // ---- Data section ----
+
+        // Address of the original SpecializeCommon function
+        ; .align 8
+        ; specialize:
+        ;; ops.push_u64(inputs.specialize_fn as _)
+
+        // Resolved addresses of dlopen and dlsym
+        ; .align 8
+        ; dlopen:
+        ;; ops.push_u64(inputs.dlopen as _)
+
+        ; .align 8
+        ; dlsym:
+        ;; ops.push_u64(inputs.dlsym as _)
+
+        // Bridge library name (used by android_dlopen_ext)
+        ; .align 8
+        ; lib_name:
+        ;; ops.extend(inputs.bridge_name.to_bytes_with_nul())
+
+        // DlextInfo struct (tells dlopen to load from fd)
+        ; .align align_of::<DlextInfo>()
+        ; lib_info:
+        ;; ops.extend(crate::misc::as_byte_slice(&info))
+
+        // BridgeArgs struct passed to the pre-hook
+        ; .align align_of::<BridgeArgs>()
+        ; bridge_args:
+        ;; ops.extend(crate::misc::as_byte_slice(&inputs.bridge_args))
+
+        // Hook symbol name strings
+        ; .align 8
+        ; pre_hook_sym:
+        ;; ops.extend(c"specialize_pre".to_bytes_with_nul())
+
+        ; .align 8
+        ; post_hook_sym:
+        ;; ops.extend(c"specialize_post".to_bytes_with_nul())
+
+        // Writable slots: the original return address, then the resolved post-hook
+        // function pointer
+        ; .align 8
+        ; slots:
+        ;; ops.push_u64((inputs.addr + inputs.slots_offset) as _)
+
+        // Resolved address of munmap (for self-cleanup)
+        ; .align 8
+        ; munmap:
+        ;; ops.push_u64(inputs.munmap as _)
+
+        // Base address of this trampoline (passed to munmap)
+        ; .align 8
+        ; trampoline_addr:
+        ;; ops.push_u64(inputs.addr as _)
+    );
+
+    let bytecode = ops.finalize()?;
+
+    Ok(Trampoline { bytecode, code_len })
+}
@@ -1,32 +1,149 @@
+use crate::android::packages::PackageInfoService;
+use crate::android::proc_visibility::ProcVisibility;
+use crate::build_args;
+#[cfg(feature = "mem-inject")]
+use crate::cli::InjectorBackend;
+#[cfg(feature = "mem-inject")]
+use crate::config::ZynxConfigs;
+#[cfg(feature = "mem-inject")]
+use crate::injector::PAGE_SIZE;
 use crate::injector::app::SC_CONFIG;
 use crate::injector::app::embryo::EmbryoInjector;
+use crate::injector::app::pipeline;
+use crate::injector::app::pipeline::QueueOverflow;
+use crate::injector::app::policy::{EmbryoCheckArgs, PolicyProviderManager};
+use crate::injector::arch::{Arch, Current};
+use crate::injector::ptrace;
+use crate::injector::ptrace::ext::ipc::PtraceIpcExt;
+use crate::injector::ptrace::ext::remote_call::PtraceRemoteCallExt;
+use crate::injector::ptrace::ext::syscall::PtraceRemoteSyscallExt;
+use crate::injector::ptrace::{RemoteProcessOps, TraceeVanished};
+use crate::injector::shutdown::Shutdown;
+use crate::metrics::Metrics;
 use crate::monitor::Monitor;
+use crate::status::Status;
 use anyhow::{Context, Result, bail};
-use log::{debug, info, warn};
 use nix::fcntl;
+use nix::libc;
 use nix::sys::signal;
 use nix::sys::signal::Signal;
-use nix::unistd::Pid;
+use nix::unistd::{Gid, Pid, Uid};
 use once_cell::sync::Lazy;
 use parking_lot::RwLock;
-use procfs::process::{MMPermissions, MMapPath, MemoryMap, MemoryMaps, Process};
+use procfs::process::{
+    MMPermissions, MMapPath, MemoryMap, MemoryMaps, Process, Status as ProcStatus,
+};
 use scopeguard::defer;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::mem;
+use std::ops::Deref;
+use std::os::fd::RawFd;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::task;
+use syscalls::Sysno;
 use tokio::time::timeout;
+use tokio::{task, time};
+use tracing::{debug, error, info, warn};
 use zynx_misc::ext::ResultExt;
 
 pub const ZYGOTE_NAME: &str = "zygote64";
+/// Name of the secondary (32-bit) zygote, started alongside the primary one
+pub const SECONDARY_ZYGOTE_NAME: &str = "zygote";
+/// Names of the zygotes matched by the monitor
+pub const ZYGOTE_NAMES: [&str; 2] = [ZYGOTE_NAME, SECONDARY_ZYGOTE_NAME];
 
-static ZYGOTE_TRACER: Lazy<RwLock<Option<ZygoteTracer>>> = Lazy::new(Default::default);
+/// Zygotes the daemon is attached to, checked by `zynx doctor`
+pub const STATUS_SECTION: &str = "zygotes";
+
+/// How long an embryo left running in observe-only mode is given to specialize
+const SPECIALIZE_TIMEOUT: Duration = Duration::from_secs(2);
+const SPECIALIZE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+const REATTACH_MAX_ATTEMPTS: u32 = 5;
+const REATTACH_BASE_DELAY: Duration = Duration::from_millis(20);
+
+static ZYGOTE_TRACERS: Lazy<RwLock<HashMap<Pid, ZygoteTracer>>> = Lazy::new(Default::default);
+static ZYGOTE_HEALTH: Lazy<RwLock<ZygoteHealth>> = Lazy::new(Default::default);
+
+/// Restart bookkeeping of the primary zygote (the one with `--start-system-server`).
+#[derive(Default)]
+struct ZygoteHealth {
+    restarts: u32,
+    last_exit: Option<Instant>,
+}
 
 #[derive(Clone)]
 pub struct ZygoteMaps(Arc<MemoryMaps>);
 
 impl ZygoteMaps {
     pub fn parse(pid: Pid) -> Result<Self> {
-        Ok(Self(Arc::new(Process::new(pid.as_raw())?.maps()?)))
+        let maps = Process::new(pid.as_raw())
+            .and_then(|proc| proc.maps())
+            .context(format!(
+                "failed to read maps of {pid}: {}",
+                ProcVisibility::instance().hint()
+            ))?;
+
+        Ok(Self(Arc::new(maps)))
+    }
+
+    /// Maps of the stopped `tracee`, opened by the tracee itself through `/proc/self/maps`
+    /// when procfs hides them from the daemon.
+    pub fn parse_traced<T>(tracee: &T) -> Result<Self>
+    where
+        T: Deref<Target: RemoteProcessOps> + PtraceRemoteCallExt + Display,
+    {
+        let pid = tracee.pid();
+
+        match Self::parse(pid) {
+            Ok(maps) => return Ok(maps),
+            Err(err) => warn!("{err:?}, opening them from {pid} instead"),
+        }
+
+        Self::open_traced(tracee).context(format!(
+            "failed to read maps of {pid} through ptrace: {}",
+            ProcVisibility::instance().hint()
+        ))
+    }
+
+    fn open_traced<T>(tracee: &T) -> Result<Self>
+    where
+        T: Deref<Target: RemoteProcessOps> + PtraceRemoteCallExt + Display,
+    {
+        const PATH: &[u8] = b"/proc/self/maps\0";
+
+        // libc can't be located without the maps, the syscalls are made in place
+        let mut regs = tracee.get_regs()?;
+        regs.align_sp();
+
+        let path_addr = (regs.get_sp() - PATH.len()) & !(Current::STACK_ALIGN - 1);
+        tracee.poke_data(path_addr, PATH)?;
+
+        #[rustfmt::skip]
+        let remote_fd = tracee.syscall_in_place(
+            Sysno::openat,
+            build_args!(libc::AT_FDCWD, path_addr, libc::O_RDONLY | libc::O_CLOEXEC)
+        )? as RawFd;
+
+        let fd = tracee.take_fd(remote_fd);
+
+        tracee
+            .syscall_in_place(Sysno::close, build_args!(remote_fd))
+            .log_if_error();
+
+        let maps: MemoryMaps = procfs::FromRead::from_read(File::from(fd?))?;
+
+        Ok(Self(Arc::new(maps)))
+    }
+
+    /// Maps in the format of `/proc/<pid>/maps`, for mock tracees.
+    #[cfg(test)]
+    pub fn from_content(content: &str) -> Result<Self> {
+        let maps: MemoryMaps = procfs::FromRead::from_read(content.as_bytes())?;
+
+        Ok(Self(Arc::new(maps)))
     }
 
     pub fn find_vma(&self, addr: usize) -> Option<&MemoryMap> {
@@ -36,6 +153,20 @@ impl ZygoteMaps {
             .find(|vma| vma.address.0 <= addr && vma.address.1 > addr)
     }
 
+    /// Start of a free range of `size` bytes above `addr`, a guard page away from the maps
+    /// around it.
+    #[cfg(feature = "mem-inject")]
+    pub fn find_free_range(&self, addr: usize, size: usize) -> Option<usize> {
+        let guard = *PAGE_SIZE as u64;
+
+        self.0
+            .iter()
+            .zip(self.0.iter().skip(1))
+            .filter(|(vma, _)| vma.address.1 > addr as u64)
+            .find(|(vma, next)| next.address.0 - vma.address.1 >= size as u64 + 2 * guard)
+            .map(|(vma, _)| (vma.address.1 + guard) as usize)
+    }
+
     pub fn find_library_base(&self, path: &str) -> Option<usize> {
         let realpath = fcntl::readlink(path);
         let realpath = realpath
@@ -71,27 +202,53 @@ impl ZygoteMaps {
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// Whether the zygote `pid` has forked system_server already.
+fn has_system_server(pid: Pid) -> bool {
+    // rather miss system_server than take an app of the system uid for it
+    let Ok(processes) = procfs::process::all_processes() else {
+        return true;
+    };
+
+    processes
+        .flatten()
+        .filter_map(|process| process.stat().ok())
+        .any(|stat| stat.ppid == pid.as_raw() && stat.comm == "system_server")
+}
+
 pub struct ZygoteTracer {
+    /// Start time of the process, distinguishes a reused pid from the same zygote
+    start_time: u64,
     maps: ZygoteMaps,
     specialize_fn: usize,
+    /// Whether this zygote was started with `--start-system-server`
+    is_primary: bool,
+    /// Whether this is an app zygote (child zygote) rather than a system zygote
+    is_app_zygote: bool,
+    /// Whether the bridge is loaded in this zygote, either injected directly or inherited
+    /// from an injected parent. Its children inherit the bridge as well.
+    has_bridge: bool,
+    /// Whether system_server is yet to be forked, the first fork of a primary zygote. Only
+    /// known for zygotes traced since they started, or without a system_server child.
+    awaits_system_server: bool,
 }
 
 impl ZygoteTracer {
-    pub fn create(pid: Pid) -> Result<()> {
-        info!("found zygote process: {pid}");
-
-        defer! {
-            signal::kill(pid, Signal::SIGCONT).log_if_error()
+    fn new(pid: Pid) -> Result<Self> {
+        let process = Process::new(pid.as_raw())?;
+        let start_time = process.stat()?.starttime;
+        let cmdline = process.cmdline()?;
+        let is_primary = cmdline.iter().any(|arg| arg == "--start-system-server");
+
+        if !is_primary {
+            info!("found secondary zygote: {pid} -> {cmdline:?}");
         }
 
-        Monitor::instance().attach_zygote(pid.as_raw())?;
-
         let maps = ZygoteMaps::parse(pid)?;
         let library_base = maps
             .find_library_base(SC_CONFIG.lib)
             .context("SpecializeCommon: failed to find libandroid_runtime.so base address")?;
 
-        let sc_addr = library_base + SC_CONFIG.sym.addr;
+        let sc_addr = library_base + SC_CONFIG.addr;
         let Some(sc_vma) = maps.find_vma(sc_addr) else {
             bail!("SpecializeCommon: memory region not found")
         };
@@ -104,17 +261,170 @@ impl ZygoteTracer {
             bail!("SpecializeCommon: memory region is not mapped from file")
         }
 
+        // the breakpoint would land in the middle of an instruction
+        if !sc_addr.is_multiple_of(Current::INSN_ALIGN) {
+            bail!("SpecializeCommon: address 0x{sc_addr:x} is not aligned to instructions")
+        }
+
         info!("SpecializeCommon vma: {sc_vma:?}, addr: {sc_addr}");
 
-        let mut tracer = ZYGOTE_TRACER.write();
-        tracer.replace(Self {
+        Ok(Self {
+            start_time,
             specialize_fn: sc_addr,
             maps,
+            is_primary,
+            is_app_zygote: false,
+            has_bridge: false,
+            awaits_system_server: is_primary && !has_system_server(pid),
+        })
+    }
+
+    fn install(pid: Pid) -> Result<()> {
+        Monitor::instance().attach_zygote(pid.as_raw())?;
+
+        let tracer = Self::new(pid).inspect_err(|_| {
+            Monitor::instance()
+                .detach_zygote(pid.as_raw())
+                .log_if_error()
+        })?;
+
+        if tracer.is_primary {
+            let health = ZYGOTE_HEALTH.read();
+
+            if let Some(last_exit) = health.last_exit {
+                info!(
+                    "primary zygote re-attached: {pid}, restarts = {}, downtime = {:.2?}",
+                    health.restarts,
+                    last_exit.elapsed()
+                );
+            }
+        }
+
+        let kind = if tracer.is_primary {
+            "primary"
+        } else {
+            "secondary"
+        };
+        Status::instance().set(STATUS_SECTION, &pid.to_string(), kind);
+
+        ZYGOTE_TRACERS.write().insert(pid, tracer);
+
+        Ok(())
+    }
+
+    /// Install the tracer, retrying with exponential backoff. A freshly restarted zygote
+    /// may not be fully set up yet, so maps parsing and SpecializeCommon lookup can fail
+    /// transiently. The zygote is resumed between attempts so it can make progress, and
+    /// stopped again before the next one.
+    async fn install_with_backoff(pid: Pid) -> Result<()> {
+        let mut delay = REATTACH_BASE_DELAY;
+        let mut attempt = 1;
+
+        loop {
+            match Self::install(pid) {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < REATTACH_MAX_ATTEMPTS => {
+                    warn!("failed to attach zygote {pid} (attempt {attempt}): {err:?}");
+
+                    if Process::new(pid.as_raw()).is_err() {
+                        bail!("zygote {pid} exited before attached");
+                    }
+
+                    signal::kill(pid, Signal::SIGCONT)?;
+                    time::sleep(delay).await;
+
+                    if Shutdown::instance().is_requested() {
+                        bail!("shutdown requested while attaching zygote {pid}");
+                    }
+
+                    signal::kill(pid, Signal::SIGSTOP)?;
+                    task::spawn_blocking(move || ptrace::spin_wait(pid)).await??;
+
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err.context(format!("failed to attach zygote {pid}"))),
+            }
+        }
+    }
+
+    /// Whether `pid` should be traced: not traced yet, and while re-attaching the restarted
+    /// primary zygote, started with `--start-system-server` if named like it. Secondary
+    /// zygotes are traced as well.
+    fn should_trace(pid: Pid, name: &str) -> Result<bool> {
+        if Self::is_traced(pid)? {
+            debug!("duplicate zygote event ignored: {pid}");
+            return Ok(false);
+        }
+
+        if name != ZYGOTE_NAME || !Self::awaits_primary() {
+            return Ok(true);
+        }
+
+        let cmdline = Process::new(pid.as_raw())?.cmdline()?;
+
+        if !cmdline.iter().any(|arg| arg == "--start-system-server") {
+            bail!("`{ZYGOTE_NAME}` {pid} not started with --start-system-server: {cmdline:?}");
+        }
+
+        Ok(true)
+    }
+
+    /// Whether the primary zygote exited and hasn't been re-attached yet.
+    fn awaits_primary() -> bool {
+        ZYGOTE_HEALTH.read().last_exit.is_some()
+            && !ZYGOTE_TRACERS
+                .read()
+                .values()
+                .any(|tracer| tracer.is_primary)
+    }
+
+    /// Whether `pid` is already traced, `task_rename` may fire multiple times for the
+    /// same zygote (e.g. threads renaming), so `create` must be idempotent.
+    fn is_traced(pid: Pid) -> Result<bool> {
+        let start_time = Process::new(pid.as_raw())?.stat()?.starttime;
+
+        match ZYGOTE_TRACERS.read().get(&pid) {
+            Some(tracer) if tracer.start_time == start_time => Ok(true),
+            Some(_) => {
+                warn!("stale zygote tracer found for reused pid: {pid}");
+                Ok(false)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn create(pid: Pid, name: &str) -> Result<()> {
+        let guard = Shutdown::instance().track(pid);
+        let name = name.to_owned();
+
+        // waiting and attaching may take a while, don't block the event loop meanwhile
+        task::spawn(async move {
+            let _guard = guard;
+
+            // stopped by eBPF, resumed whether attached or not
+            defer! {
+                signal::kill(pid, Signal::SIGCONT).log_if_error()
+            }
+
+            Self::attach_new(pid, &name).await.log_if_error();
         });
 
         Ok(())
     }
 
+    async fn attach_new(pid: Pid, name: &str) -> Result<()> {
+        task::spawn_blocking(move || ptrace::spin_wait(pid)).await??;
+
+        if !Self::should_trace(pid, name)? {
+            return Ok(());
+        }
+
+        info!("found zygote process: {pid}");
+
+        Self::install_with_backoff(pid).await
+    }
+
     pub fn create_attach(pid: Pid) -> Result<()> {
         info!("attaching to running zygote process: {pid}");
 
@@ -125,59 +435,248 @@ impl ZygoteTracer {
             signal::kill(pid, Signal::SIGCONT).log_if_error()
         }
 
+        Self::install(pid)
+    }
+
+    /// Track an app zygote forked from a traced zygote. The child inherits the
+    /// address space of its parent, so the parent's maps are reused as-is.
+    pub fn create_child(
+        pid: Pid,
+        maps: ZygoteMaps,
+        specialize_fn: usize,
+        has_bridge: bool,
+    ) -> Result<()> {
+        info!("found app zygote process: {pid}");
+
+        let start_time = Process::new(pid.as_raw())?.stat()?.starttime;
+
         Monitor::instance().attach_zygote(pid.as_raw())?;
 
-        let maps = ZygoteMaps::parse(pid)?;
-        let library_base = maps
-            .find_library_base(SC_CONFIG.lib)
-            .context("SpecializeCommon: failed to find libandroid_runtime.so base address")?;
+        ZYGOTE_TRACERS.write().insert(
+            pid,
+            Self {
+                start_time,
+                maps,
+                specialize_fn,
+                is_primary: false,
+                is_app_zygote: true,
+                has_bridge,
+                awaits_system_server: false,
+            },
+        );
 
-        let sc_addr = library_base + SC_CONFIG.sym.addr;
-        let Some(sc_vma) = maps.find_vma(sc_addr) else {
-            bail!("SpecializeCommon: memory region not found")
-        };
+        Ok(())
+    }
 
-        if (sc_vma.perms & MMPermissions::EXECUTE) == MMPermissions::empty() {
-            bail!("SpecializeCommon: memory region is not executable")
+    /// Record that the bridge has been injected into the app zygote `pid`, so that its
+    /// children are known to inherit it.
+    pub fn mark_injected(pid: Pid) {
+        if let Some(tracer) = ZYGOTE_TRACERS.write().get_mut(&pid) {
+            debug!("app zygote {pid} now contains the bridge");
+            tracer.has_bridge = true;
         }
+    }
 
-        if !matches!(sc_vma.pathname, MMapPath::Path(_)) {
-            bail!("SpecializeCommon: memory region is not mapped from file")
+    pub fn reset(pid: Pid) -> Result<()> {
+        let Some(tracer) = ZYGOTE_TRACERS.write().remove(&pid) else {
+            return Ok(());
+        };
+
+        info!("zygote tracer removed: {pid}");
+        Status::instance().remove(STATUS_SECTION, &pid.to_string());
+
+        if tracer.is_primary {
+            let mut health = ZYGOTE_HEALTH.write();
+
+            health.restarts += 1;
+            health.last_exit = Some(Instant::now());
+
+            warn!(
+                "primary zygote {pid} exited, waiting for restart (restarts = {})",
+                health.restarts
+            );
         }
 
-        info!("SpecializeCommon vma: {sc_vma:?}, addr: {sc_addr}");
+        Ok(())
+    }
 
-        let mut tracer = ZYGOTE_TRACER.write();
-        tracer.replace(Self {
-            specialize_fn: sc_addr,
-            maps,
+    /// Report which providers would inject into an embryo left running in observe-only mode,
+    /// once it has specialized. Only fast checks are run, as if it were a plain app.
+    pub fn on_fork_observed(zygote: Pid, pid: Pid) -> Result<()> {
+        Metrics::instance().on_fork();
+
+        let lock = ZYGOTE_TRACERS.read();
+        let tracer = lock
+            .get(&zygote)
+            .context(format!("zygote tracer not initialized for {zygote}"))?;
+
+        let from_app_zygote = tracer.is_app_zygote;
+        let inherits_bridge = tracer.has_bridge;
+
+        drop(lock);
+
+        let zygote_uid = Process::new(zygote.as_raw())?.uid()?;
+
+        task::spawn(async move {
+            let status = match Self::wait_specialized(pid, zygote_uid).await {
+                Ok(status) => status,
+                Err(err) => {
+                    info!("observed embryo {pid} didn't specialize: {err:#}");
+                    return;
+                }
+            };
+
+            let uid = Uid::from_raw(status.ruid);
+            let package_info = PackageInfoService::instance().query(uid);
+            let packages: Vec<_> = package_info
+                .iter()
+                .flat_map(|pkgs| pkgs.iter().map(|pkg| pkg.name.clone()))
+                .collect();
+            let args = EmbryoCheckArgs::new_fast(
+                uid,
+                Gid::from_raw(status.rgid),
+                false,
+                false,
+                from_app_zygote,
+                inherits_bridge,
+                package_info,
+            );
+
+            let manager = PolicyProviderManager::instance();
+            let decisions = manager
+                .check(&args, PolicyProviderManager::deadline())
+                .await;
+
+            match manager.dry_run(&decisions.decisions) {
+                Ok(providers) if providers.is_empty() => {
+                    info!("observed embryo {pid} (uid {uid}, {packages:?}): nothing to inject")
+                }
+                Ok(providers) => {
+                    info!(
+                        "observed embryo {pid} (uid {uid}, {packages:?}): would inject {providers:?}"
+                    )
+                }
+                Err(vetoed_by) => {
+                    info!(
+                        "observed embryo {pid} (uid {uid}, {packages:?}): vetoed by {vetoed_by:?}"
+                    )
+                }
+            }
         });
 
         Ok(())
     }
 
-    pub fn reset() -> Result<()> {
-        ZYGOTE_TRACER.write().take();
-        Ok(())
+    /// Wait for the embryo to leave the uid of its zygote, which it does while specializing.
+    async fn wait_specialized(pid: Pid, zygote_uid: u32) -> Result<ProcStatus> {
+        let process = Process::new(pid.as_raw())?;
+        let start = Instant::now();
+
+        loop {
+            let status = process.status()?;
+
+            if status.ruid != zygote_uid {
+                return Ok(status);
+            }
+
+            if start.elapsed() > SPECIALIZE_TIMEOUT {
+                bail!("still running as uid {zygote_uid} after {SPECIALIZE_TIMEOUT:?}");
+            }
+
+            time::sleep(SPECIALIZE_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Break on the specialize function of an embryo stopped by eBPF after fork.
+    pub fn on_fork(zygote: Pid, pid: Pid) -> Result<()> {
+        Self::spawn_injector(zygote, pid, EmbryoInjector::start)
+    }
+
+    /// Handle an embryo stopped by eBPF at the specialize function already, caught by its
+    /// uprobe, nothing is patched into it unless injected by the mem injector.
+    pub fn on_specialize(zygote: Pid, pid: Pid) -> Result<()> {
+        let entry_regs = Monitor::instance()
+            .take_entry_regs(pid)
+            .inspect_err(|_| signal::kill(pid, Signal::SIGCONT).log_if_error())?;
+
+        Self::spawn_injector(zygote, pid, move |injector| {
+            #[cfg(feature = "mem-inject")]
+            if matches!(ZynxConfigs::instance().injector, InjectorBackend::Mem) {
+                return injector.start_mem(&entry_regs);
+            }
+
+            injector.start_probed(&entry_regs)
+        })
     }
 
-    pub fn on_fork(pid: Pid) -> Result<()> {
-        let lock = ZYGOTE_TRACER.read();
-        let tracer = lock.as_ref().context("zygote tracer not initialized")?;
+    /// Run the injector of an embryo on a tracer thread, entering it with `run`.
+    fn spawn_injector(
+        zygote: Pid,
+        pid: Pid,
+        run: impl FnOnce(&EmbryoInjector) -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        Metrics::instance().on_fork();
+
+        let mut lock = ZYGOTE_TRACERS.write();
+        let tracer = lock
+            .get_mut(&zygote)
+            .context(format!("zygote tracer not initialized for {zygote}"))?;
 
         let specialize_fn = tracer.specialize_fn;
         let maps = tracer.maps.clone();
+        let from_app_zygote = tracer.is_app_zygote;
+        let inherits_bridge = tracer.has_bridge;
+        let first_fork = mem::take(&mut tracer.awaits_system_server);
 
         drop(lock);
 
+        // the embryo has been stopped by eBPF and waits for us
+        let guard = Shutdown::instance().track(pid);
+
         task::spawn(async move {
-            let task_handle = task::spawn_blocking(move || {
-                let start = Instant::now();
-                EmbryoInjector::new(pid, maps, specialize_fn)
-                    .start()
-                    .log_if_error();
-                let elapsed = start.elapsed();
-                debug!("embryo {pid} check/injection completed in {elapsed:.2?}");
+            // spawned on its own, so that timing out never leaves the embryo stopped untraced
+            let task_handle = task::spawn(async move {
+                // held until the tracer is done, so that code it patched before failing is
+                // restored before the embryo resumes
+                let _guard = guard;
+                let tracer = pipeline::spawn_tracer(pid, move || {
+                    let start = Instant::now();
+                    let injector = EmbryoInjector::new(
+                        pid,
+                        maps,
+                        specialize_fn,
+                        from_app_zygote,
+                        inherits_bridge,
+                        first_fork,
+                    );
+
+                    if let Err(err) = run(&injector) {
+                        let err = injector.classify_error(err);
+
+                        if err.downcast_ref::<TraceeVanished>().is_some() {
+                            info!("embryo {pid} vanished before injection completed: {err:#}");
+                        } else {
+                            error!("embryo {pid} injection failed: {err:?}");
+                        }
+                    }
+
+                    let elapsed = start.elapsed();
+                    debug!("embryo {pid} check/injection completed in {elapsed:.2?}");
+                });
+
+                match tracer.await {
+                    Err(err) if err.downcast_ref::<QueueOverflow>().is_some() => {
+                        // better an app without modules than one slow to start
+                        warn!("{err}, starting it without injection");
+                        Metrics::instance().on_inject_skipped();
+                        Shutdown::instance().release(pid);
+                    }
+                    Err(err) => {
+                        error!("failed to trace embryo {pid}: {err:?}");
+                        Shutdown::instance().release(pid);
+                    }
+                    Ok(()) => {}
+                }
             });
 
             if timeout(Duration::from_secs(5), task_handle).await.is_err() {
@@ -0,0 +1,48 @@
+use crate::injector::misc;
+use std::ops::Range;
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::AArch64 as Current;
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::X86_64 as Current;
+
+/// Instruction set conventions the injector relies on when patching code of tracees and
+/// setting up their registers.
+pub trait Arch {
+    /// Instruction stopping the tracee with `SIGTRAP`
+    const BREAKPOINT: &'static [u8];
+
+    /// How far past the breakpoint pc is once it's hit
+    const BREAKPOINT_PC_OFFSET: usize;
+
+    /// Instruction making the syscall set up in registers
+    const SYSCALL_INSN: &'static [u8];
+
+    /// Instructions start at multiples of this
+    const INSN_ALIGN: usize;
+
+    /// Args passed in registers to functions
+    const REG_ARGS: usize;
+
+    /// Offset of the first stack arg from sp, at the entry of a function
+    const STACK_ARGS_OFFSET: usize;
+
+    /// Below sp, the interrupted function may keep data there
+    const RED_ZONE: usize;
+
+    /// Alignment of sp at function calls
+    const STACK_ALIGN: usize;
+
+    /// Leading words of the kernel's `struct pt_regs` laid out like `user_regs_struct`
+    const PT_REGS_WORDS: usize;
+
+    /// Pages holding `len` bytes patched at `addr`, as passed to `madvise`.
+    fn patch_pages(addr: usize, len: usize) -> Range<usize> {
+        misc::floor_to_page_size(addr)..misc::ceil_to_page_size(addr + len)
+    }
+}
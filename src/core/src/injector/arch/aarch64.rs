@@ -0,0 +1,29 @@
+use crate::injector::arch::Arch;
+
+pub struct AArch64;
+
+impl Arch for AArch64 {
+    /// `brk #0`
+    const BREAKPOINT: &'static [u8] = &[0x00, 0x00, 0x20, 0xd4];
+
+    /// `brk` leaves pc on the breakpoint
+    const BREAKPOINT_PC_OFFSET: usize = 0;
+
+    /// `svc #0`
+    const SYSCALL_INSN: &'static [u8] = &[0x01, 0x00, 0x00, 0xd4];
+
+    const INSN_ALIGN: usize = 4;
+
+    /// x0-x7
+    const REG_ARGS: usize = 8;
+
+    /// The return address is in lr
+    const STACK_ARGS_OFFSET: usize = 0;
+
+    const RED_ZONE: usize = 0;
+
+    const STACK_ALIGN: usize = 16;
+
+    /// x0-x30, sp, pc and pstate
+    const PT_REGS_WORDS: usize = 34;
+}
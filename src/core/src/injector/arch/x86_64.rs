@@ -0,0 +1,29 @@
+use crate::injector::arch::Arch;
+
+pub struct X86_64;
+
+impl Arch for X86_64 {
+    /// `int3`
+    const BREAKPOINT: &'static [u8] = &[0xcc];
+
+    /// `int3` leaves pc after the breakpoint
+    const BREAKPOINT_PC_OFFSET: usize = 1;
+
+    /// `syscall`
+    const SYSCALL_INSN: &'static [u8] = &[0x0f, 0x05];
+
+    const INSN_ALIGN: usize = 1;
+
+    /// rdi, rsi, rdx, rcx, r8 and r9
+    const REG_ARGS: usize = 6;
+
+    /// Past the return address
+    const STACK_ARGS_OFFSET: usize = 8;
+
+    const RED_ZONE: usize = 128;
+
+    const STACK_ALIGN: usize = 16;
+
+    /// r15 up to ss, without the segment bases
+    const PT_REGS_WORDS: usize = 21;
+}
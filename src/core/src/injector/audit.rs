@@ -0,0 +1,175 @@
+use crate::config::ZynxConfigs;
+use crate::injector::app::bridge_log;
+use crate::injector::app::embryo::{TRAMPOLINE_NAME, loader_socket_name};
+use crate::injector::bridge::Bridge;
+use crate::injector::camouflage;
+use crate::injector::camouflage::NameKind;
+use anyhow::{Context, Result, bail};
+use nix::sys::stat;
+use nix::unistd::Pid;
+use procfs::process::Process;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::path::Path;
+use tracing::{info, warn};
+use zynx_bridge_shared::zygote::{ArtifactKind, AuditReport, AuditRequest, SessionId};
+use zynx_misc::ext::ResultExt;
+
+/// Reports of audited processes, one `<pid>.toml` each, pruned once the process exits
+const AUDIT_DIR: &str = "/data/adb/zynx/audit";
+
+/// Plain names of memfds, mappings and threads of zynx, see [`camouflage::name`]
+const MARKERS: &[&str] = &["zynx", "liteloader::", "native::"];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Artifact {
+    kind: String,
+    detail: String,
+    reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Report {
+    pid: i32,
+    context: String,
+    artifacts: Vec<Artifact>,
+}
+
+/// Names zynx leaves in the app `pid`, those of LiteLoader and native libraries starting with
+/// the markers. Camouflaged, memfds and mappings share their names with real ones, so that
+/// only the inodes of the memfds tell them apart, and the trampoline is gone by the audit.
+fn known_names(pid: Pid, session: SessionId) -> Vec<String> {
+    let camouflaged = ZynxConfigs::instance().camouflage_names;
+    let mut names: Vec<String> = MARKERS.iter().map(|it| it.to_string()).collect();
+
+    for (kind, plain) in [
+        (NameKind::Library, "zynx::bridge".into()),
+        (NameKind::Mapping, TRAMPOLINE_NAME.into()),
+        (NameKind::Buffer, bridge_log::buffer_name(pid)),
+        (
+            NameKind::Socket { pid: pid.as_raw() },
+            loader_socket_name(pid, session),
+        ),
+    ] {
+        if camouflaged && !matches!(kind, NameKind::Socket { .. }) {
+            continue;
+        }
+
+        names.push(camouflage::name(kind, &plain));
+    }
+
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// What the bridge of the app `pid` should look for once loaded, with `--cfg-audit`. `fds`
+/// are those sent along the payload.
+pub fn request(pid: Pid, session: SessionId, fds: &[BorrowedFd]) -> Option<AuditRequest> {
+    if !ZynxConfigs::instance().audit {
+        return None;
+    }
+
+    let inodes = fds
+        .iter()
+        .copied()
+        .chain([Bridge::instance().as_fd()])
+        .filter_map(|fd| stat::fstat(fd).ok())
+        .map(|stat| stat.st_ino as _)
+        .collect();
+
+    Some(AuditRequest {
+        names: known_names(pid, session),
+        inodes,
+    })
+}
+
+fn report_path(pid: i32) -> String {
+    format!("{AUDIT_DIR}/{pid}.toml")
+}
+
+fn prune() -> Result<()> {
+    for entry in fs::read_dir(AUDIT_DIR)?.flatten() {
+        let path = entry.path();
+        let pid = path
+            .file_stem()
+            .and_then(|stem| stem.to_str()?.parse().ok());
+
+        if pid.is_some_and(|pid| !Process::new(pid).is_ok_and(|it| it.is_alive())) {
+            fs::remove_file(&path).log_if_error();
+        }
+    }
+
+    Ok(())
+}
+
+/// Keep the report of the bridge of `pid` for `zynx audit`.
+pub fn on_report(pid: Pid, report: AuditReport) {
+    if report.artifacts.is_empty() {
+        info!("process {pid}: audit found nothing");
+    } else {
+        warn!(
+            "process {pid}: audit found {} artifacts",
+            report.artifacts.len()
+        );
+    }
+
+    let report = Report {
+        pid: pid.as_raw(),
+        context: report.context,
+        artifacts: report
+            .artifacts
+            .into_iter()
+            .map(|artifact| Artifact {
+                kind: kind_name(artifact.kind).into(),
+                detail: artifact.detail,
+                reason: artifact.reason,
+            })
+            .collect(),
+    };
+
+    let save = || -> Result<()> {
+        fs::create_dir_all(AUDIT_DIR)?;
+        prune().log_if_error();
+        fs::write(report_path(report.pid), toml::to_string(&report)?)?;
+        Ok(())
+    };
+
+    save().log_if_error();
+}
+
+fn kind_name(kind: ArtifactKind) -> &'static str {
+    match kind {
+        ArtifactKind::Map => "map",
+        ArtifactKind::Fd => "fd",
+        ArtifactKind::Thread => "thread",
+        ArtifactKind::Socket => "socket",
+        ArtifactKind::Env => "env",
+        ArtifactKind::Selinux => "selinux",
+    }
+}
+
+/// Print what the bridge of `pid` found in its own process as TOML, e.g. for stealth
+/// regression tests. Fails if there's anything to report.
+pub fn audit(pid: i32) -> Result<()> {
+    let path = report_path(pid);
+
+    if !Path::new(&path).exists() {
+        bail!("process {pid} wasn't audited, restart it with the daemon running with --cfg-audit");
+    }
+
+    let content = fs::read_to_string(&path).context(format!("failed to read {path}"))?;
+    let report: Report = toml::from_str(&content).context(format!("malformed {path}"))?;
+
+    print!("{content}");
+
+    if !report.artifacts.is_empty() {
+        bail!(
+            "{} artifacts found in process {pid}",
+            report.artifacts.len()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,369 @@
+use crate::build_args;
+use crate::injector::PAGE_SIZE;
+use crate::injector::app::SC_CONFIG;
+use crate::injector::app::embryo::EmbryoInjector;
+use crate::injector::app::pipeline;
+use crate::injector::app::pipeline::{SlowArgs, TraceeRequest};
+use crate::injector::app::zygote::ZygoteMaps;
+use crate::injector::bridge::Bridge;
+use crate::injector::ptrace::ext::batch::{CallChain, PokeBatch, PtraceBatchExt};
+use crate::injector::ptrace::ext::ipc::{MmapOptions, PtraceIpcExt};
+use crate::injector::ptrace::ext::remote_call::PtraceRemoteCallExt;
+use crate::monitor;
+use crate::monitor::sys_enter::SysEnterAttach;
+use anyhow::{Context, Result, bail};
+use criterion::measurement::WallTime;
+use criterion::{BatchSize, BenchmarkGroup, BenchmarkId, Criterion, Throughput};
+use futures::future;
+use nix::libc::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_EXEC, PROT_READ, PROT_WRITE};
+use nix::sys::signal;
+use nix::sys::signal::{SigSet, SigmaskHow};
+use nix::unistd;
+use nix::unistd::Pid;
+use once_cell::sync::Lazy;
+use std::hint::black_box;
+use std::path::Path;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Builder;
+use tracing::warn;
+
+pub const BENCH_DIR: &str = "/data/adb/zynx/bench";
+
+/// Buffer sizes for peek/poke throughput, in ascending order
+const TRANSFER_SIZES: [usize; 3] = [64, 4096, 64 * 1024];
+
+/// Number of operations compared one by one against batched
+const BATCH_SIZE: usize = 4;
+
+/// Embryos forked at once in the pipeline stress test
+const PIPELINE_EMBRYOS: usize = 32;
+
+/// A freshly spawned process standing in for an embryo, killed on drop.
+struct SyntheticTarget {
+    child: Child,
+    maps: ZygoteMaps,
+}
+
+impl SyntheticTarget {
+    fn spawn() -> Result<Self> {
+        let child = Command::new("/system/bin/sleep").arg("3600").spawn()?;
+        let pid = Pid::from_raw(child.id() as _);
+
+        // the linker may not have mapped libc yet when `spawn` returns
+        for _ in 0..100 {
+            let maps = ZygoteMaps::parse(pid)?;
+
+            if maps.find_library_base_by_name("libc").is_some() {
+                return Ok(Self { child, maps });
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        bail!("libc never got mapped into synthetic target {pid}")
+    }
+
+    fn pid(&self) -> Pid {
+        Pid::from_raw(self.child.id() as _)
+    }
+
+    /// Attach to the target as if it were an embryo. There's no SpecializeCommon to call, so
+    /// the target may crash in the trampoline after [`EmbryoInjector::do_inject`] releases it,
+    /// which is harmless for a `sleep` process.
+    fn attach(&self) -> Result<EmbryoInjector> {
+        let injector = EmbryoInjector::new(self.pid(), self.maps.clone(), 0, false, false, false);
+
+        injector.seize_stopped()?;
+
+        Ok(injector)
+    }
+}
+
+impl Drop for SyntheticTarget {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn bench_trampoline(criterion: &mut Criterion, injector: &EmbryoInjector) {
+    criterion.bench_function("dynasm/trampoline", |bencher| {
+        bencher.iter(|| {
+            injector
+                .assemble_trampoline(black_box(0x7000_0000), 3, Some(4))
+                .expect("failed to assemble trampoline")
+        })
+    });
+}
+
+fn bench_transfer(criterion: &mut Criterion, injector: &EmbryoInjector) -> Result<()> {
+    let map_size = TRANSFER_SIZES[TRANSFER_SIZES.len() - 1].next_multiple_of(*PAGE_SIZE);
+    let addr = injector.mmap_ex(MmapOptions::new(
+        map_size,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+    ))?;
+
+    let mut group = criterion.benchmark_group("ptrace");
+
+    for size in TRANSFER_SIZES {
+        let mut buffer = vec![0x5a; size];
+
+        group.throughput(Throughput::Bytes(size as _));
+        group.bench_with_input(BenchmarkId::new("poke", size), &size, |bencher, _| {
+            bencher.iter(|| injector.poke_data(addr, &buffer).expect("poke failed"))
+        });
+        group.bench_with_input(BenchmarkId::new("peek", size), &size, |bencher, _| {
+            bencher.iter(|| injector.peek_data(addr, &mut buffer).expect("peek failed"))
+        });
+    }
+
+    group.finish();
+
+    injector.munmap(addr, map_size)
+}
+
+fn bench_call_remote(criterion: &mut Criterion, injector: &EmbryoInjector) -> Result<()> {
+    let getpid = injector.resolve_fn(("libc", "getpid"))?;
+
+    criterion.bench_function("ptrace/call_remote", |bencher| {
+        bencher.iter(|| {
+            injector
+                .call_remote(getpid, build_args!())
+                .expect("remote call failed")
+        })
+    });
+
+    Ok(())
+}
+
+/// Compare remote operations made one by one, as before batching, against batched ones.
+fn bench_batch(criterion: &mut Criterion, injector: &EmbryoInjector) -> Result<()> {
+    let addr = injector.mmap_ex(MmapOptions::new(
+        *PAGE_SIZE,
+        PROT_READ | PROT_WRITE | PROT_EXEC,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+    ))?;
+    let data_addr = addr + *PAGE_SIZE / 2;
+
+    let getpid = injector.resolve_fn(("libc", "getpid"))?;
+    let mut chain = CallChain::default();
+
+    for _ in 0..BATCH_SIZE {
+        chain.push(getpid, build_args!())?;
+    }
+
+    let buffer = [0x5a; 64];
+    let mut batch = PokeBatch::default();
+
+    for i in 0..BATCH_SIZE {
+        batch.push(data_addr + i * buffer.len(), &buffer);
+    }
+
+    let mut group = criterion.benchmark_group("batch");
+
+    group.bench_function("call_remote", |bencher| {
+        bencher.iter(|| {
+            for _ in 0..BATCH_SIZE {
+                injector
+                    .call_remote(getpid, build_args!())
+                    .expect("remote call failed");
+            }
+        })
+    });
+    group.bench_function("call_chain", |bencher| {
+        bencher.iter(|| {
+            injector
+                .call_chain(addr, &chain, PokeBatch::default())
+                .expect("call chain failed")
+        })
+    });
+    group.bench_function("poke", |bencher| {
+        bencher.iter(|| {
+            for i in 0..BATCH_SIZE {
+                injector
+                    .poke_data(data_addr + i * buffer.len(), &buffer)
+                    .expect("poke failed");
+            }
+        })
+    });
+    group.bench_function("poke_batch", |bencher| {
+        bencher.iter(|| batch.flush(injector).expect("poke batch failed"))
+    });
+
+    group.finish();
+
+    injector.munmap(addr, *PAGE_SIZE)
+}
+
+fn bench_do_inject(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("inject");
+
+    // every iteration needs a fresh target, keep the sample count low
+    group.sample_size(10);
+    group.bench_function("do_inject", |bencher| {
+        bencher.iter_batched(
+            || {
+                let target = SyntheticTarget::spawn().expect("failed to spawn target");
+                let injector = target.attach().expect("failed to attach target");
+                let regs = injector.get_regs().expect("failed to get regs");
+                let raw_args = vec![0; SC_CONFIG.args_cnt];
+
+                (target, injector, regs, raw_args)
+            },
+            |(target, injector, regs, raw_args)| {
+                injector
+                    .do_inject(regs, &raw_args, Vec::new())
+                    .expect("do_inject failed");
+
+                // the target gets killed on drop, outside of the measurement
+                (target, injector)
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.finish();
+}
+
+/// Stress the check pipeline with many embryos at once, each traced by its own thread
+/// while its check runs on the runtime and asks for a remote call, as slow checks do.
+fn bench_pipeline(criterion: &mut Criterion) -> Result<()> {
+    let runtime = Builder::new_multi_thread().enable_all().build()?;
+    let mut group = criterion.benchmark_group("pipeline");
+
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(PIPELINE_EMBRYOS as _));
+    group.bench_function("concurrent_checks", |bencher| {
+        bencher.iter_batched(
+            || {
+                (0..PIPELINE_EMBRYOS)
+                    .map(|_| SyntheticTarget::spawn().expect("failed to spawn target"))
+                    .collect::<Vec<_>>()
+            },
+            |targets| {
+                let tracers = targets.iter().map(|target| {
+                    let pid = target.pid();
+                    let maps = target.maps.clone();
+
+                    pipeline::spawn_tracer(pid, move || {
+                        let injector = EmbryoInjector::new(pid, maps, 0, false, false, false);
+                        injector.seize_stopped().expect("failed to attach target");
+
+                        let getpid = injector
+                            .resolve_fn(("libc", "getpid"))
+                            .expect("failed to resolve getpid");
+
+                        pipeline::run_check(
+                            |tracee| async move { tracee.slow_args().await },
+                            |request| match request {
+                                TraceeRequest::SlowArgs(reply) => {
+                                    let pid = injector.call_remote(getpid, build_args!());
+                                    let _ = reply.send(pid.map(|pid| SlowArgs {
+                                        nice_name: Some(pid.to_string()),
+                                        ..SlowArgs::default()
+                                    }));
+                                }
+                            },
+                        )
+                        .expect("policy check failed");
+
+                        injector.detach(None).expect("failed to detach target");
+                    })
+                });
+
+                for result in runtime.block_on(future::join_all(tracers)) {
+                    result.expect("failed to spawn tracer");
+                }
+
+                // the targets get killed on drop, outside of the measurement
+                targets
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.finish();
+
+    Ok(())
+}
+
+fn bench_syscalls(group: &mut BenchmarkGroup<WallTime>, variant: &str) {
+    group.bench_function(BenchmarkId::new(variant, "getppid"), |bencher| {
+        bencher.iter(|| black_box(unistd::getppid()))
+    });
+    group.bench_function(BenchmarkId::new(variant, "rt_sigprocmask"), |bencher| {
+        bencher.iter(|| signal::sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&SigSet::empty()), None))
+    });
+}
+
+/// Syscall overhead of each variant of the `sys_enter` program, against none attached.
+/// `getppid` stands for unrelated syscalls, `rt_sigprocmask` for the one the program handles.
+fn bench_sys_enter(criterion: &mut Criterion) -> Result<()> {
+    let mut ebpf = monitor::load_for_bench()?;
+    let mut group = criterion.benchmark_group("sys_enter");
+
+    bench_syscalls(&mut group, "none");
+
+    for attach in SysEnterAttach::ALL {
+        if let Err(err) = attach.attach(&mut ebpf) {
+            warn!("sys_enter can't be attached as {attach:?}: {err:#}");
+            let _ = attach.detach(&mut ebpf);
+            continue;
+        }
+
+        bench_syscalls(&mut group, &format!("{attach:?}"));
+        attach.detach(&mut ebpf)?;
+    }
+
+    group.finish();
+
+    Ok(())
+}
+
+/// Baselines let results be compared across changes, see `zynx bench --help`.
+pub enum Baseline {
+    Save(String),
+    Compare(String),
+}
+
+/// Run benchmarks on the device, results are written to [`BENCH_DIR`].
+pub fn bench(filter: Option<&str>, baseline: Option<Baseline>) -> Result<()> {
+    let mut criterion = Criterion::default().output_directory(Path::new(BENCH_DIR));
+
+    if let Some(filter) = filter {
+        criterion = criterion.with_filter(filter);
+    }
+
+    criterion = match baseline {
+        Some(Baseline::Save(name)) => criterion.save_baseline(name),
+        Some(Baseline::Compare(name)) => criterion.retain_baseline(name, true),
+        None => criterion,
+    };
+
+    // make sure lazy initialization doesn't end up in the measurements
+    Bridge::instance();
+    Lazy::force(&SC_CONFIG);
+
+    {
+        let target = SyntheticTarget::spawn().context("failed to spawn synthetic target")?;
+        let injector = target.attach()?;
+
+        bench_trampoline(&mut criterion, &injector);
+        bench_transfer(&mut criterion, &injector)?;
+        bench_call_remote(&mut criterion, &injector)?;
+        bench_batch(&mut criterion, &injector)?;
+
+        injector.detach(None)?;
+    }
+
+    bench_do_inject(&mut criterion);
+    bench_pipeline(&mut criterion)?;
+    bench_sys_enter(&mut criterion)?;
+
+    criterion.final_summary();
+
+    Ok(())
+}
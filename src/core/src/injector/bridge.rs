@@ -1,3 +1,5 @@
+use crate::injector::camouflage;
+use crate::injector::camouflage::NameKind;
 use crate::misc::create_sealed_memfd;
 use anyhow::Result;
 use memfd::Memfd;
@@ -6,27 +8,80 @@ use std::os::fd::{AsFd, BorrowedFd};
 
 static DATA: &[u8] = include_bytes!(concat!(
     env!("ROOT_DIR"),
-    "/target/aarch64-linux-android/",
+    "/target/",
+    env!("TARGET_TRIPLE"),
+    "/",
     env!("PROFILE"),
     "/libzynx_bridge.so"
 ));
 
+const FEATURES: &str = include_str!(concat!(
+    env!("ROOT_DIR"),
+    "/target/",
+    env!("TARGET_TRIPLE"),
+    "/",
+    env!("PROFILE"),
+    "/libzynx_bridge.features"
+));
+
+const EXPECTED_FEATURES: &str = if cfg!(feature = "zygisk") {
+    "zygisk=1"
+} else {
+    "zygisk=0"
+};
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+
+    true
+}
+
+// The embedded bridge must be built with the same features as core, or the IPC between
+// them may break in subtle ways.
+const _: () = assert!(
+    str_eq(FEATURES, EXPECTED_FEATURES),
+    "embedded bridge was built with different features, rebuild core and bridge together"
+);
+
 static INSTANCE: Lazy<Bridge> =
     Lazy::new(|| Bridge::new(DATA).expect("failed to load zynx bridge"));
 
 pub struct Bridge {
     fd: Memfd,
+    name: String,
 }
 
 impl Bridge {
     fn new(data: &[u8]) -> Result<Self> {
-        let fd = create_sealed_memfd("zynx::bridge", data)?;
-        Ok(Self { fd })
+        let name = camouflage::name(NameKind::Library, "zynx::bridge");
+        let fd = create_sealed_memfd(&name, data)?;
+        Ok(Self { fd, name })
     }
 
     pub fn instance() -> &'static Self {
         &INSTANCE
     }
+
+    /// Of both the memfd and the loaded library, telling injected processes apart
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn size(&self) -> u64 {
+        DATA.len() as u64
+    }
 }
 
 impl AsFd for Bridge {
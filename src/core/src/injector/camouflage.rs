@@ -0,0 +1,81 @@
+use crate::config::ZynxConfigs;
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use std::fs;
+
+const BOOT_ID: &str = "/proc/sys/kernel/random/boot_id";
+
+/// Memfds of code, as left by ART and graphics drivers
+const LIBRARY_NAMES: &[&str] = &[
+    "jit-cache",
+    "jit-zygote-cache",
+    "dalvik-data-code-cache",
+    "hwui-shader-cache",
+    "gpu-program-binary",
+];
+
+/// Anonymous mappings of allocators and ART
+const MAPPING_NAMES: &[&str] = &[
+    "dalvik-LinearAlloc",
+    "dalvik-indirect ref table",
+    "scudo:secondary",
+    "libc_malloc",
+    "thread signal stack",
+];
+
+/// Shared buffers of framework services
+const BUFFER_NAMES: &[&str] = &[
+    "ashmem-region",
+    "CursorWindow",
+    "gralloc-buffer",
+    "AudioTrack-shared",
+];
+
+/// Abstract sockets of debugging endpoints of apps, suffixed with the pid of the app like
+/// Chromium does
+const SOCKET_NAMES: &[&str] = &["webview_devtools_remote", "chrome_devtools_remote"];
+
+/// Changes on every boot, but survives restarts of the daemon, so that processes injected
+/// by a previous daemon are still recognized
+static SALT: Lazy<Vec<u8>> = Lazy::new(|| fs::read(BOOT_ID).unwrap_or_default());
+
+#[derive(Debug, Copy, Clone)]
+pub enum NameKind {
+    /// Memfd of a library
+    Library,
+    /// Anonymous mapping, e.g. the trampoline
+    Mapping,
+    /// Memfd of a buffer shared with the daemon
+    Buffer,
+    /// Abstract socket the daemon listens on for the injected process `pid`
+    Socket { pid: i32 },
+}
+
+/// Name of a memfd, anonymous mapping or socket left in injected processes: `plain`, or with
+/// `--cfg-camouflage-names`, a real name of the same kind picked by hashing it with a per-boot
+/// salt. Camouflaged names aren't unique, memfds and mappings may share them with those they
+/// imitate, sockets are told apart by the pid.
+pub fn name(kind: NameKind, plain: &str) -> String {
+    if !ZynxConfigs::instance().camouflage_names {
+        return plain.into();
+    }
+
+    let digest = Sha256::new()
+        .chain_update(&*SALT)
+        .chain_update(plain)
+        .finalize();
+
+    let names = match kind {
+        NameKind::Library => LIBRARY_NAMES,
+        NameKind::Mapping => MAPPING_NAMES,
+        NameKind::Buffer => BUFFER_NAMES,
+        NameKind::Socket { .. } => SOCKET_NAMES,
+    };
+
+    let base = names[digest[0] as usize % names.len()];
+
+    match kind {
+        NameKind::Socket { pid } => format!("{base}_{pid}"),
+        _ => base.into(),
+    }
+}
@@ -0,0 +1,348 @@
+use crate::android::proc_visibility::ProcVisibility;
+use crate::android::root;
+use crate::android::root::RootManager;
+use crate::cli::{InjectorBackend, MonitorBackend};
+use crate::config::ZynxConfigs;
+use crate::injector::app::policy::liteloader::LITE_LIBRARIES_DIR;
+use crate::injector::app::zygote::{self, ZYGOTE_NAME};
+use crate::injector::app::{SC_LIBRARY_PATH, SpecializeCommonConfig, preflight};
+use crate::injector::service::policy::SERVICES_DIR;
+use crate::monitor::MapSizes;
+use crate::monitor::features::{KernelFeatures, RINGBUF_KERNEL};
+use crate::{monitor, status, update};
+use anyhow::{Result, bail};
+use nix::sys::utsname;
+use nix::unistd::{Pid, Uid};
+use procfs::process::Process;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::warn;
+use zynx_bridge_shared::zygote::ProviderType;
+use zynx_ebpf_shared::SignalMode;
+use zynx_misc::selinux;
+
+const CAP_SYS_PTRACE: u32 = 19;
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_PERFMON: u32 = 38;
+const CAP_BPF: u32 = 39;
+
+enum Verdict {
+    Pass(String),
+    /// Works, but with a fallback or a caveat
+    Warn(String),
+    Fail(String),
+}
+
+use Verdict::{Fail, Pass, Warn};
+
+#[derive(Default)]
+struct Report {
+    warnings: usize,
+    failures: usize,
+}
+
+impl Report {
+    fn add(&mut self, check: &str, verdict: Verdict) {
+        match verdict {
+            Pass(detail) => println!("[ ok ] {check}: {detail}"),
+            Warn(detail) => {
+                println!("[warn] {check}: {detail}");
+                self.warnings += 1;
+            }
+            Fail(detail) => {
+                println!("[fail] {check}: {detail}");
+                self.failures += 1;
+            }
+        }
+    }
+
+    fn add_result(&mut self, check: &str, result: Result<Verdict>) {
+        self.add(check, result.unwrap_or_else(|err| Fail(format!("{err:#}"))));
+    }
+}
+
+/// Verdict for a kernel lacking what the eBPF monitor needs, depending on the configured backend.
+fn without_ebpf(missing: String) -> Verdict {
+    match ZynxConfigs::instance().monitor {
+        MonitorBackend::Auto => Warn(format!("{missing}: the proc connector monitor is used")),
+        MonitorBackend::Ebpf => Fail(format!("{missing}: the eBPF monitor can't run")),
+        MonitorBackend::ProcConnector => Pass(format!("{missing}, unused by the proc connector")),
+    }
+}
+
+fn check_kernel(features: &KernelFeatures) -> Result<Verdict> {
+    let uname = utsname::uname()?;
+    let release = uname.release().to_string_lossy();
+
+    Ok(if features.has_ringbuf {
+        Pass(release.into_owned())
+    } else {
+        without_ebpf(format!(
+            "{release}, older than {}.{}",
+            RINGBUF_KERNEL.0, RINGBUF_KERNEL.1
+        ))
+    })
+}
+
+fn check_permissions() -> Result<Verdict> {
+    if !Uid::effective().is_root() {
+        return Ok(Fail(format!(
+            "running as uid {}, not root",
+            Uid::effective()
+        )));
+    }
+
+    let capeff = Process::myself()?.status()?.capeff;
+    let has = |cap: u32| capeff & (1 << cap) != 0;
+
+    let mut missing = Vec::new();
+
+    if !has(CAP_SYS_PTRACE) {
+        missing.push("CAP_SYS_PTRACE");
+    }
+
+    if !has(CAP_SYS_ADMIN) && !(has(CAP_BPF) && has(CAP_PERFMON)) {
+        missing.push("CAP_SYS_ADMIN (or CAP_BPF and CAP_PERFMON)");
+    }
+
+    Ok(if missing.is_empty() {
+        Pass(format!("root, capabilities {capeff:#x}"))
+    } else {
+        Fail(format!("missing {}", missing.join(", ")))
+    })
+}
+
+fn check_tracepoints(features: &KernelFeatures) -> Verdict {
+    if monitor::layout::tracefs().is_none() {
+        return Warn("tracefs not found, tracepoints are assumed to be there".into());
+    }
+
+    if !features.missing_tracepoints.is_empty() {
+        return without_ebpf(format!(
+            "missing {}",
+            features.missing_tracepoints.join(", ")
+        ));
+    }
+
+    if !features.has_watchdog {
+        return Warn("all required, but no syscalls/sys_enter_getppid for the watchdog".into());
+    }
+
+    Pass("all present".into())
+}
+
+fn check_bpf(features: &KernelFeatures) -> Result<Verdict> {
+    if !features.supports_ebpf() {
+        return Ok(Warn(format!(
+            "skipped, missing {}",
+            features.missing_for_ebpf().join(", ")
+        )));
+    }
+
+    let configs = ZynxConfigs::instance();
+    let map_sizes = MapSizes {
+        targets: configs.max_targets,
+        tasks: configs.max_tasks,
+    };
+    let loaded = monitor::verify_programs(features, configs.channel_size, map_sizes)?;
+
+    Ok(match features.signal_mode {
+        SignalMode::Thread => Pass(format!("{loaded} programs loaded, stopping threads")),
+        SignalMode::Process => Warn(format!(
+            "{loaded} programs loaded, no bpf_send_signal_thread: whole processes are stopped"
+        )),
+        SignalMode::Daemon => Warn(format!(
+            "{loaded} programs loaded, no bpf_send_signal: processes are stopped by the daemon"
+        )),
+    })
+}
+
+fn check_selinux() -> Result<Verdict> {
+    let context = selinux::getpidcon(None)?;
+
+    Ok(if selinux::is_enforcing() {
+        Pass(format!("enforcing, running as {context}"))
+    } else {
+        Warn(format!("not enforcing, running as {context}"))
+    })
+}
+
+fn check_procfs() -> Verdict {
+    let visibility = ProcVisibility::instance();
+
+    if visibility.is_restricted() {
+        Warn(format!("{visibility}: {}", visibility.hint()))
+    } else {
+        Pass("unrestricted".into())
+    }
+}
+
+fn check_symbols(config: &SpecializeCommonConfig) -> Verdict {
+    let detail = format!(
+        "SpecializeCommon @ {:#x} in {SC_LIBRARY_PATH}, {} args{}",
+        config.addr,
+        config.args_cnt,
+        if config.user_provided {
+            ", from offsets.toml"
+        } else {
+            ""
+        }
+    );
+
+    if config.is_forward_compat() {
+        Warn(format!("{detail}, unknown signature: forward-compat mode"))
+    } else {
+        Pass(detail)
+    }
+}
+
+fn find_zygotes() -> Result<Vec<Process>> {
+    let mut zygotes = Vec::new();
+
+    for process in procfs::process::all_processes()?.flatten() {
+        if process
+            .cmdline()
+            .is_ok_and(|cmdline| cmdline.iter().any(|arg| arg == ZYGOTE_NAME))
+        {
+            zygotes.push(process);
+        }
+    }
+
+    Ok(zygotes)
+}
+
+/// `attached` are the zygotes the running daemon reports in its status, `None` if it isn't running.
+fn check_zygote(process: &Process, attached: Option<&BTreeMap<String, String>>) -> Result<Verdict> {
+    let pid = Pid::from_raw(process.pid);
+    let status = process.status()?;
+
+    if status.tracerpid != 0 {
+        let tracer = Process::new(status.tracerpid)
+            .and_then(|it| it.stat())
+            .map(|stat| stat.comm)
+            .unwrap_or_default();
+
+        return Ok(Fail(format!(
+            "traced by {} ({tracer}), zynx can't attach alongside another tracer",
+            status.tracerpid
+        )));
+    }
+
+    if status.state.starts_with('T') {
+        return Ok(Fail("stopped, apps can't be started".into()));
+    }
+
+    let configs = ZynxConfigs::instance();
+    let with_connect = matches!(configs.injector, InjectorBackend::Mem);
+    // providers labeling libraries they send to apps
+    let libraries: Vec<_> = configs
+        .enable_liteloader
+        .then_some(ProviderType::LiteLoader)
+        .into_iter()
+        .collect();
+
+    if let Err(err) = preflight::check(pid, &libraries, with_connect) {
+        return Ok(Fail(format!("{err:#}")));
+    }
+
+    let Some(attached) = attached else {
+        return Ok(Warn("running, the daemon isn't".into()));
+    };
+
+    Ok(match attached.get(&pid.to_string()) {
+        Some(kind) => Pass(format!("running, attached by the daemon as {kind}")),
+        None => Warn("running, not attached by the daemon".into()),
+    })
+}
+
+fn check_dir(enabled: bool, dir: &Path) -> Verdict {
+    if !enabled {
+        Pass("disabled".into())
+    } else if dir.is_dir() {
+        Pass(dir.display().to_string())
+    } else {
+        Warn(format!(
+            "{} doesn't exist, nothing to inject",
+            dir.display()
+        ))
+    }
+}
+
+/// Check what the daemon needs of the device, one line per prerequisite, to triage setups
+/// where nothing gets injected. Fails if any prerequisite is missing.
+pub fn doctor() -> Result<()> {
+    let mut report = Report::default();
+    let configs = ZynxConfigs::instance();
+
+    let features = KernelFeatures::probe(monitor::layout::tracefs());
+
+    report.add_result("kernel", check_kernel(&features));
+    report.add_result("permissions", check_permissions());
+
+    report.add("tracepoints", check_tracepoints(&features));
+    report.add_result("bpf", check_bpf(&features));
+    report.add_result("selinux", check_selinux());
+    report.add("procfs", check_procfs());
+
+    report.add(
+        "root manager",
+        match RootManager::instance() {
+            Some(manager) => Pass(manager.to_string()),
+            None => Warn("not recognized".into()),
+        },
+    );
+
+    let config = SpecializeCommonConfig::load();
+
+    match &config {
+        Ok(config) => report.add("symbols", check_symbols(config)),
+        Err(err) => report.add("symbols", Fail(format!("{err:#}"))),
+    }
+
+    match find_zygotes() {
+        Ok(zygotes) if zygotes.is_empty() => {
+            report.add("zygote", Fail(format!("no {ZYGOTE_NAME} process found")))
+        }
+        Ok(zygotes) => {
+            let attached = match update::find_daemon() {
+                Ok(Some(_)) => status::read_section(zygote::STATUS_SECTION)
+                    .inspect_err(|err| warn!("failed to read the daemon's status: {err:#}"))
+                    .ok(),
+                Ok(None) => None,
+                Err(err) => {
+                    warn!("failed to find the daemon: {err:#}");
+                    None
+                }
+            };
+
+            for zygote in &zygotes {
+                report.add_result(
+                    &format!("zygote {}", zygote.pid),
+                    check_zygote(zygote, attached.as_ref()),
+                );
+            }
+        }
+        Err(err) => report.add("zygote", Fail(format!("{err:#}"))),
+    }
+
+    report.add(
+        "liteloader",
+        check_dir(configs.enable_liteloader, &LITE_LIBRARIES_DIR),
+    );
+    report.add(
+        "zygisk",
+        check_dir(configs.enable_zygisk, root::modules_dir()),
+    );
+    report.add(
+        "services",
+        check_dir(configs.enable_services, Path::new(SERVICES_DIR)),
+    );
+
+    println!("{} failed, {} warnings", report.failures, report.warnings);
+
+    if report.failures > 0 {
+        bail!("{} prerequisites are missing", report.failures);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,294 @@
+use crate::android::packages::PackageInfoService;
+use crate::atrace;
+use crate::config::ZynxConfigs;
+use crate::events::{EventLog, InjectionEvent, InjectionOutcome};
+use crate::injector::app::embryo::outcome_of_error;
+use crate::injector::app::pipeline::SlowArgs;
+use crate::injector::app::policy::{
+    Attachment, EmbryoCheckArgs, PolicyProviderManager, ProviderBundle, caps,
+};
+use crate::injector::app::zygote::ZYGOTE_NAME;
+use crate::injector::bridge::Bridge;
+use crate::injector::service::ServiceInjector;
+use crate::injector::shutdown::Shutdown;
+use crate::metrics::Metrics;
+use crate::quarantine::Quarantine;
+use anyhow::Result;
+use nix::unistd::{Gid, Pid, Uid};
+use procfs::process::{MMapPath, Process};
+use std::collections::HashSet;
+use std::fs;
+use std::time::{Instant, SystemTime};
+use tokio::runtime::Handle;
+use tokio::task;
+use tracing::{debug, info, warn};
+use zynx_bridge_shared::policy::liteloader::{LibraryKind, LiteLoaderParams};
+use zynx_bridge_shared::policy::native::NativeParams;
+use zynx_bridge_shared::zygote::{ProviderType, SessionId};
+
+/// Apps run with uids from `Process.FIRST_APPLICATION_UID`, system_server does not
+const FIRST_APPLICATION_UID: u32 = 10000;
+
+/// An app process forked by zygote before the daemon started.
+struct RunningApp {
+    pid: Pid,
+    uid: Uid,
+    gid: Gid,
+    nice_name: String,
+}
+
+fn has_bridge(process: &Process) -> bool {
+    let Ok(maps) = process.maps() else {
+        return false;
+    };
+
+    let bridge = Bridge::instance();
+
+    maps.iter().any(|map| {
+        let named = match &map.pathname {
+            MMapPath::Path(path) => path.to_string_lossy().contains(bridge.name()),
+            MMapPath::Other(name) => name.contains(bridge.name()),
+            _ => false,
+        };
+
+        // camouflaged, the bridge shares its name with real memfds, its size tells them apart
+        let (start, end) = map.address;
+        let path = format!("/proc/{}/map_files/{start:x}-{end:x}", process.pid);
+
+        named
+            && (!ZynxConfigs::instance().camouflage_names
+                || fs::metadata(path).is_ok_and(|meta| meta.len() == bridge.size()))
+    })
+}
+
+fn find_running_apps() -> Result<Vec<RunningApp>> {
+    let processes: Vec<_> = procfs::process::all_processes()?.flatten().collect();
+
+    let zygotes: HashSet<_> = processes
+        .iter()
+        .filter(|process| {
+            process
+                .cmdline()
+                .is_ok_and(|cmdline| cmdline.first().is_some_and(|arg0| arg0 == ZYGOTE_NAME))
+        })
+        .map(|process| process.pid)
+        .collect();
+
+    let mut apps = Vec::new();
+
+    for process in processes {
+        let Ok(stat) = process.stat() else {
+            continue;
+        };
+
+        if !zygotes.contains(&stat.ppid) {
+            continue;
+        }
+
+        let Ok(status) = process.status() else {
+            continue;
+        };
+
+        // traced ones are being injected by the daemon right now
+        if status.ruid < FIRST_APPLICATION_UID || status.tracerpid != 0 {
+            continue;
+        }
+
+        let nice_name = process
+            .cmdline()
+            .ok()
+            .and_then(|cmdline| cmdline.into_iter().next())
+            .unwrap_or(stat.comm);
+
+        // app zygotes are left alone, their children are handled when they fork
+        if nice_name.ends_with("_zygote") {
+            continue;
+        }
+
+        if has_bridge(&process) {
+            debug!("{nice_name} ({}) already injected", process.pid);
+            continue;
+        }
+
+        apps.push(RunningApp {
+            pid: Pid::from_raw(process.pid),
+            uid: Uid::from_raw(status.ruid),
+            gid: Gid::from_raw(status.rgid),
+            nice_name,
+        });
+    }
+
+    Ok(apps)
+}
+
+async fn check_app(app: &RunningApp) -> Option<Vec<ProviderBundle>> {
+    let fast_args = EmbryoCheckArgs::new_fast(
+        app.uid,
+        app.gid,
+        false,
+        false,
+        false,
+        false,
+        PackageInfoService::instance().query(app.uid),
+    );
+
+    let manager = PolicyProviderManager::instance();
+    // not on the fork path, the app is already running
+    let mut result = manager.check(&fast_args, None).await;
+
+    if result.more_info {
+        let slow_args = fast_args.into_slow(SlowArgs {
+            nice_name: Some(app.nice_name.clone()),
+            ..SlowArgs::default()
+        });
+        manager.recheck_slow(&slow_args, &mut result, None).await;
+    }
+
+    manager.aggregate(&result.decisions)
+}
+
+/// Only native LiteLoader libraries can be loaded without `SpecializeCommon`, and their
+/// entries are never called, as there's no `JNIEnv` to call them with.
+fn to_native_bundle(app: &RunningApp, bundles: Vec<ProviderBundle>) -> Option<ProviderBundle> {
+    let mut attachments = Vec::new();
+
+    for bundle in bundles {
+        if bundle.ty != ProviderType::LiteLoader {
+            debug!("{} late injection skips {:?}", app.nice_name, bundle.ty);
+            continue;
+        }
+
+        for attachment in bundle.attachments {
+            let Some(params) = attachment
+                .data
+                .as_ref()
+                .and_then(|data| wincode::deserialize::<LiteLoaderParams>(data).ok())
+            else {
+                continue;
+            };
+
+            if !matches!(params.kind, LibraryKind::Native) {
+                info!("{} late injection skips {}", app.nice_name, params.lib_name);
+                continue;
+            }
+
+            if let Some(entry) = &params.entry {
+                warn!("{} late injection won't call {entry}", params.lib_name);
+            }
+
+            let params = NativeParams {
+                lib_name: params.lib_name,
+                entry: None,
+            };
+
+            attachments.push(Attachment {
+                fd: attachment.fd,
+                data: wincode::serialize(&params).ok(),
+                module: attachment.module,
+                library: attachment.library,
+            });
+        }
+    }
+
+    (!attachments.is_empty()).then_some(ProviderBundle {
+        ty: ProviderType::Native,
+        attachments,
+        data: None,
+        mutation: None,
+    })
+}
+
+fn inject_app(handle: &Handle, app: RunningApp) {
+    let _slice = atrace::slice(format_args!("zynx: late injection pid={}", app.pid));
+    let check_start = Instant::now();
+
+    let Some(mut bundles) = handle.block_on(check_app(&app)) else {
+        return;
+    };
+
+    let truncated = caps::enforce(&mut bundles);
+
+    let Some(bundle) = to_native_bundle(&app, bundles) else {
+        return;
+    };
+
+    let metrics = Metrics::instance();
+    let session = SessionId::generate();
+    let modules: Vec<_> = bundle
+        .attachments
+        .iter()
+        .filter_map(|attachment| attachment.module.clone())
+        .collect();
+    let start = Instant::now();
+
+    info!(
+        "late injecting {} ({}) [session={session}]",
+        app.nice_name, app.pid
+    );
+    metrics.on_inject_start();
+
+    let result = {
+        let _guard = Shutdown::instance().track(app.pid);
+        ServiceInjector::inject_running(app.pid, app.nice_name.clone(), session, bundle)
+    };
+
+    let outcome = match &result {
+        Ok(()) => InjectionOutcome::Injected,
+        Err(err) => outcome_of_error(err),
+    };
+
+    metrics.on_inject_finish(&outcome, start.elapsed());
+
+    if matches!(outcome, InjectionOutcome::Injected) {
+        Quarantine::watch(app.pid, modules);
+    } else {
+        warn!("late injection into {} failed: {outcome}", app.nice_name);
+    }
+
+    let packages = PackageInfoService::instance()
+        .query(app.uid)
+        .map(|list| list.iter().map(|info| info.name.clone()).collect())
+        .unwrap_or_default();
+
+    EventLog::instance().record(InjectionEvent {
+        time: SystemTime::now(),
+        pid: app.pid,
+        session,
+        uid: app.uid,
+        packages,
+        providers: vec![ProviderType::Native],
+        outcome,
+        duration: check_start.elapsed(),
+        truncated,
+        loads: Vec::new(),
+    });
+}
+
+/// Inject app processes which were already running when the daemon started.
+pub fn spawn_scan() {
+    if !ZynxConfigs::instance().late_injection {
+        return;
+    }
+
+    let handle = Handle::current();
+
+    task::spawn_blocking(move || {
+        let apps = match find_running_apps() {
+            Ok(apps) => apps,
+            Err(err) => {
+                warn!("failed to scan running apps: {err:?}");
+                return;
+            }
+        };
+
+        info!("found {} running apps for late injection", apps.len());
+
+        for app in apps {
+            if Shutdown::instance().is_requested() {
+                break;
+            }
+
+            inject_app(&handle, app);
+        }
+    });
+}
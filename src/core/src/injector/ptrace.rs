@@ -1,7 +1,12 @@
 pub mod ext;
+#[cfg(test)]
+pub mod mock;
 
+use crate::android::proc_visibility::ProcVisibility;
+use crate::binary::library::SystemLibraryResolver;
+use crate::injector::arch::{Arch, Current};
+use crate::injector::ptrace::ext::WaitStatusExt;
 use anyhow::{Context, Result, bail};
-use log::{debug, trace};
 use nix::errno::Errno;
 use nix::libc;
 use nix::libc::{PTRACE_GETREGSET, PTRACE_SETREGSET, c_int, c_long, iovec, user_regs_struct};
@@ -14,12 +19,17 @@ use procfs::ProcError;
 use procfs::process::{ProcState, Process};
 use std::ffi::c_void;
 use std::fmt::{Display, Formatter};
-use std::fs::OpenOptions;
-use std::io::{IoSlice, IoSliceMut, Seek, SeekFrom, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+use std::mem;
 use std::mem::MaybeUninit;
+use std::slice;
+use std::sync::OnceLock;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use std::{fmt, thread};
+use tracing::{debug, trace};
+use zynx_ebpf_shared::EntryRegs;
 
 #[derive(Clone)]
 pub struct RegSet(user_regs_struct);
@@ -28,6 +38,9 @@ pub struct RegSet(user_regs_struct);
 impl RegSet {
     const SIZE: usize = size_of::<user_regs_struct>();
 
+    /// Args passed in registers
+    pub const ARGS: usize = Current::REG_ARGS;
+
     fn new(regs: user_regs_struct) -> Self {
         Self(regs)
     }
@@ -36,6 +49,52 @@ impl RegSet {
         &self.0 as *const user_regs_struct as _
     }
 
+    /// Skip the red zone, if any, and align sp for a call.
+    pub fn align_sp(&mut self) {
+        self.set_sp((self.get_sp() - Current::RED_ZONE) & !(Current::STACK_ALIGN - 1));
+    }
+
+    /// Move pc back on the breakpoint which has been hit.
+    pub fn rewind_breakpoint(&mut self) {
+        self.set_pc(self.get_pc() - Current::BREAKPOINT_PC_OFFSET);
+    }
+
+    /// First arg passed on the stack, at the entry of a function.
+    pub fn stack_args(&self) -> usize {
+        self.get_sp() + Current::STACK_ARGS_OFFSET
+    }
+
+    /// Overwrite the registers with those recorded by a uprobe, the leading words of
+    /// `struct pt_regs`.
+    pub fn load_entry_regs(&mut self, entry: &EntryRegs) {
+        let words =
+            unsafe { slice::from_raw_parts_mut(&mut self.0 as *mut _ as *mut u64, Self::SIZE / 8) };
+
+        words[..Current::PT_REGS_WORDS].copy_from_slice(&entry[..Current::PT_REGS_WORDS]);
+    }
+
+    /// Registers recorded by a uprobe, for a process which isn't attached to.
+    pub fn from_entry_regs(entry: &EntryRegs) -> Self {
+        let mut regs = Self::new(unsafe { mem::zeroed() });
+
+        regs.load_entry_regs(entry);
+        regs
+    }
+
+    /// The leading words of `struct pt_regs`, as recorded by a uprobe.
+    pub fn to_entry_regs(&self) -> EntryRegs {
+        let words =
+            unsafe { slice::from_raw_parts(&self.0 as *const _ as *const u64, Self::SIZE / 8) };
+        let mut entry = [0; size_of::<EntryRegs>() / 8];
+
+        entry[..Current::PT_REGS_WORDS].copy_from_slice(&words[..Current::PT_REGS_WORDS]);
+        entry
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+#[allow(unused)]
+impl RegSet {
     pub fn get_fp(&self) -> usize {
         self.0.regs[29] as _
     }
@@ -48,10 +107,6 @@ impl RegSet {
         self.0.sp = sp as _
     }
 
-    pub fn align_sp(&mut self) {
-        self.0.sp &= !0xf;
-    }
-
     pub fn get_pc(&self) -> usize {
         self.0.pc as _
     }
@@ -61,7 +116,7 @@ impl RegSet {
     }
 
     pub fn get_arg(&self, index: usize) -> c_long {
-        if index < 8 {
+        if index < Self::ARGS {
             self.0.regs[index] as _
         } else {
             unreachable!("up to 8 parameters can be passed through registers")
@@ -69,13 +124,17 @@ impl RegSet {
     }
 
     pub fn set_arg(&mut self, index: usize, value: c_long) {
-        if index < 8 {
+        if index < Self::ARGS {
             self.0.regs[index] = value as _
         } else {
             unreachable!("up to 8 parameters can be passed through registers")
         }
     }
 
+    pub fn set_syscall_arg(&mut self, index: usize, value: c_long) {
+        self.set_arg(index, value);
+    }
+
     pub fn get_lr(&self) -> usize {
         self.0.regs[30] as _
     }
@@ -87,14 +146,196 @@ impl RegSet {
     pub fn return_value(&self) -> c_long {
         self.0.regs[0] as _
     }
+
+    pub fn get_syscall_nr(&self) -> c_long {
+        self.0.regs[8] as _
+    }
+
+    pub fn set_syscall_nr(&mut self, nr: c_long) {
+        self.0.regs[8] = nr as _
+    }
 }
 
+#[cfg(target_arch = "x86_64")]
+#[allow(unused)]
+impl RegSet {
+    pub fn get_fp(&self) -> usize {
+        self.0.rbp as _
+    }
+
+    pub fn get_sp(&self) -> usize {
+        self.0.rsp as _
+    }
+
+    pub fn set_sp(&mut self, sp: usize) {
+        self.0.rsp = sp as _
+    }
+
+    pub fn get_pc(&self) -> usize {
+        self.0.rip as _
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.0.rip = pc as _;
+    }
+
+    pub fn get_arg(&self, index: usize) -> c_long {
+        (match index {
+            0 => self.0.rdi,
+            1 => self.0.rsi,
+            2 => self.0.rdx,
+            3 => self.0.rcx,
+            4 => self.0.r8,
+            5 => self.0.r9,
+            _ => unreachable!("up to 6 parameters can be passed through registers"),
+        }) as _
+    }
+
+    pub fn set_arg(&mut self, index: usize, value: c_long) {
+        let reg = match index {
+            0 => &mut self.0.rdi,
+            1 => &mut self.0.rsi,
+            2 => &mut self.0.rdx,
+            3 => &mut self.0.rcx,
+            4 => &mut self.0.r8,
+            5 => &mut self.0.r9,
+            _ => unreachable!("up to 6 parameters can be passed through registers"),
+        };
+
+        *reg = value as _;
+    }
+
+    /// Syscalls take their fourth arg in r10, `syscall` clobbers rcx.
+    pub fn set_syscall_arg(&mut self, index: usize, value: c_long) {
+        if index == 3 {
+            self.0.r10 = value as _;
+        } else {
+            self.set_arg(index, value);
+        }
+    }
+
+    pub fn return_value(&self) -> c_long {
+        self.0.rax as _
+    }
+
+    /// Number of the syscall being made, rax holds its result already.
+    pub fn get_syscall_nr(&self) -> c_long {
+        self.0.orig_rax as _
+    }
+
+    /// Number of the syscall to make with `syscall`.
+    pub fn set_syscall_nr(&mut self, nr: c_long) {
+        self.0.rax = nr as _
+    }
+}
+
+/// `NT_ARM_HW_BREAK`, regset of the hardware breakpoints
+#[cfg(target_arch = "aarch64")]
+const NT_ARM_HW_BREAK: usize = 0x402;
+
+/// Enabled, at EL0, on all 4 bytes of the instruction
+#[cfg(target_arch = "aarch64")]
+const HW_BREAK_CTRL: u32 = (0xf << 5) | (0b10 << 1) | 1;
+
+/// `struct user_hwdebug_state`
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+#[allow(unused)]
+struct HwDebugState {
+    dbg_info: u32,
+    pad: u32,
+    dbg_regs: [HwDebugReg; 16],
+}
+
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+#[allow(unused)]
+struct HwDebugReg {
+    addr: u64,
+    ctrl: u32,
+    pad: u32,
+}
+
+/// `NT_ARM_SYSTEM_CALL`, regset of the number of the syscall being made
+#[cfg(target_arch = "aarch64")]
+const NT_ARM_SYSTEM_CALL: usize = 0x404;
+
+/// `offsetof(struct user, u_debugreg)`, for `PTRACE_POKEUSER`
+#[cfg(target_arch = "x86_64")]
+const DEBUGREG_OFFSET: usize = 848;
+
+/// Local enable of dr0, shifted by 2 bits for each following slot
+#[cfg(target_arch = "x86_64")]
+const DR7_L0: usize = 1;
+
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// The tracee died (e.g. OOM-killed) while we were operating on it. This is not a bug
+/// of ours, so callers should abort remaining steps and report it separately.
+#[derive(Debug)]
+pub struct TraceeVanished(pub Pid);
+
+impl Display for TraceeVanished {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "tracee {} vanished", self.0)
+    }
+}
+
+impl std::error::Error for TraceeVanished {}
+
+/// The `Seccomp` field of `/proc/<pid>/status`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SeccompMode {
+    Disabled,
+    /// Only `read`, `write`, `_exit` and `sigreturn` are allowed
+    Strict,
+    /// Syscalls are checked by BPF filters, as for every app forked by zygote
+    Filter,
+}
+
+impl Display for SeccompMode {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SeccompMode::Disabled => fmt.write_str("disabled"),
+            SeccompMode::Strict => fmt.write_str("strict"),
+            SeccompMode::Filter => fmt.write_str("filter"),
+        }
+    }
+}
+
+/// A remote call ran into the seccomp policy of the tracee.
+#[derive(Debug)]
+pub enum SeccompError {
+    /// The tracee is in strict mode, any remote call would get it killed
+    Strict(Pid),
+    /// A syscall was trapped (`SECCOMP_RET_TRAP`), the `SIGSYS` was suppressed and the
+    /// tracee is still alive
+    Trapped(Pid, c_long),
+    /// The tracee was killed by a syscall (`SECCOMP_RET_KILL_*`)
+    Killed(Pid),
+}
+
+impl Display for SeccompError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SeccompError::Strict(pid) => {
+                write!(fmt, "tracee {pid} is in seccomp strict mode")
+            }
+            SeccompError::Trapped(pid, nr) => {
+                write!(fmt, "syscall {nr} of tracee {pid} trapped by seccomp")
+            }
+            SeccompError::Killed(pid) => write!(fmt, "tracee {pid} killed by seccomp"),
+        }
+    }
+}
+
+impl std::error::Error for SeccompError {}
+
 #[derive(Debug)]
 pub struct RemoteProcess {
     pub pid: Pid,
     attached: AtomicBool,
+    seccomp: OnceLock<SeccompMode>,
 }
 
 #[allow(unused)]
@@ -103,6 +344,7 @@ impl RemoteProcess {
         Self {
             pid: Pid::from_raw(pid.as_raw()),
             attached: AtomicBool::new(false),
+            seccomp: OnceLock::new(),
         }
     }
 
@@ -118,6 +360,83 @@ impl RemoteProcess {
         Ok(())
     }
 
+    /// Stop the seized tracee alone, leaving its other threads running, and wait for it.
+    pub fn interrupt(&self) -> Result<()> {
+        self.ptrace_raw(0x4207 /* PTRACE_INTERRUPT */, 0, 0)
+            .context("ptrace::interrupt")?;
+
+        loop {
+            match self.wait()? {
+                WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_STOP) => return Ok(()),
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                    bail!(TraceeVanished(self.pid))
+                }
+                status => self.cont(status.sig())?,
+            }
+        }
+    }
+
+    /// Number of the syscall the stopped tracee is in, which the kernel may restart on
+    /// resume, -1 if none: `orig_rax` on x86_64, `NT_ARM_SYSTEM_CALL` on AArch64.
+    #[cfg(target_arch = "aarch64")]
+    pub fn get_syscall_state(&self) -> Result<c_long> {
+        let mut nr: c_int = -1;
+        let iov = iovec {
+            iov_base: &mut nr as *mut _ as _,
+            iov_len: size_of::<c_int>(),
+        };
+
+        self.ptrace_raw(PTRACE_GETREGSET, NT_ARM_SYSTEM_CALL, &iov as *const _ as _)
+            .context("failed to read the syscall number")?;
+
+        Ok(nr as _)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn set_syscall_state(&self, nr: c_long) -> Result<()> {
+        let mut nr = nr as c_int;
+        let iov = iovec {
+            iov_base: &mut nr as *mut _ as _,
+            iov_len: size_of::<c_int>(),
+        };
+
+        self.ptrace_raw(PTRACE_SETREGSET, NT_ARM_SYSTEM_CALL, &iov as *const _ as _)
+            .context("failed to write the syscall number")?;
+
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_syscall_state(&self) -> Result<c_long> {
+        Ok(self.get_regs()?.get_syscall_nr())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_syscall_state(&self, nr: c_long) -> Result<()> {
+        let mut regs = self.get_regs()?;
+
+        regs.0.orig_rax = nr as _;
+        self.set_regs(&regs)
+    }
+
+    /// Seize the process and wait for it to be stopped by `SIGSTOP`.
+    pub fn seize_stopped(&self) -> Result<()> {
+        self.seize()?;
+        self.kill(Signal::SIGSTOP)?;
+
+        loop {
+            match self.wait()? {
+                WaitStatus::Stopped(_, Signal::SIGSTOP) => break,
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                    bail!(TraceeVanished(self.pid))
+                }
+                status => self.cont(status.sig())?,
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn wait(&self) -> Result<WaitStatus> {
         let status = wait::waitpid(self.pid, Some(WaitPidFlag::__WALL)).context("ptrace::wait");
         trace!("{self} wait status: {status:?}");
@@ -129,6 +448,11 @@ impl RemoteProcess {
         Ok(())
     }
 
+    pub fn step<T: Into<Option<Signal>>>(&self, sig: T) -> Result<()> {
+        ptrace::step(self.pid, sig).context("ptrace::step")?;
+        Ok(())
+    }
+
     pub fn kill<T: Into<Option<Signal>>>(&self, sig: T) -> Result<()> {
         signal::kill(self.pid, sig).context("signal::kill")?;
         Ok(())
@@ -169,6 +493,37 @@ impl RemoteProcess {
         Ok(())
     }
 
+    /// Write several regions with a single `process_vm_writev`.
+    pub fn poke_vectored(&self, writes: &[(usize, &[u8])]) -> Result<()> {
+        let iov_local: Vec<_> = writes.iter().map(|(_, data)| IoSlice::new(data)).collect();
+        let iov_remote: Vec<_> = writes
+            .iter()
+            .map(|(addr, data)| RemoteIoVec {
+                base: *addr,
+                len: data.len(),
+            })
+            .collect();
+
+        let written = uio::process_vm_writev(self.pid, &iov_local, &iov_remote)
+            .context("failed to write memory")?;
+        let expected: usize = writes.iter().map(|(_, data)| data.len()).sum();
+
+        if written != expected {
+            bail!("{self} partial write: {written} of {expected} bytes");
+        }
+
+        Ok(())
+    }
+
+    pub fn peek_data_ignore_perm(&self, addr: usize, data: &mut [u8]) -> Result<()> {
+        let mut file = File::open(format!("/proc/{}/mem", self.pid))?;
+
+        file.seek(SeekFrom::Start(addr as _))?;
+        file.read_exact(data)?;
+
+        Ok(())
+    }
+
     pub fn poke_data_ignore_perm(&self, addr: usize, data: &[u8]) -> Result<()> {
         let mut file = OpenOptions::new()
             .write(true)
@@ -212,15 +567,172 @@ impl RemoteProcess {
         Ok(())
     }
 
+    /// Arm a free hardware breakpoint of the stopped tracee at `addr`. Returns the slot taken,
+    /// `None` if the CPU has none or all of them are in use.
+    #[cfg(target_arch = "aarch64")]
+    pub fn set_hw_breakpoint(&self, addr: usize) -> Result<Option<usize>> {
+        let mut state = self.read_hw_breakpoints()?;
+
+        // the low byte of dbg_info holds the number of slots
+        let slots = (state.dbg_info as usize & 0xff).min(state.dbg_regs.len());
+        let Some(slot) = state.dbg_regs[..slots]
+            .iter()
+            .position(|reg| reg.ctrl & 1 == 0)
+        else {
+            return Ok(None);
+        };
+
+        state.dbg_regs[slot] = HwDebugReg {
+            addr: addr as _,
+            ctrl: HW_BREAK_CTRL,
+            pad: 0,
+        };
+        self.write_hw_breakpoints(&state, slot)?;
+
+        Ok(Some(slot))
+    }
+
+    /// Disarm the hardware breakpoint in `slot`, taken by [`Self::set_hw_breakpoint`].
+    #[cfg(target_arch = "aarch64")]
+    pub fn clear_hw_breakpoint(&self, slot: usize) -> Result<()> {
+        let mut state = self.read_hw_breakpoints()?;
+
+        state.dbg_regs[slot] = HwDebugReg {
+            addr: 0,
+            ctrl: 0,
+            pad: 0,
+        };
+        self.write_hw_breakpoints(&state, slot)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn read_hw_breakpoints(&self) -> Result<HwDebugState> {
+        let mut state: MaybeUninit<HwDebugState> = MaybeUninit::zeroed();
+        let iov = iovec {
+            iov_base: state.as_mut_ptr() as _,
+            iov_len: size_of::<HwDebugState>(),
+        };
+
+        self.ptrace_raw(PTRACE_GETREGSET, NT_ARM_HW_BREAK, &iov as *const _ as _)
+            .context("failed to read hardware breakpoints")?;
+
+        Ok(unsafe { state.assume_init() })
+    }
+
+    /// Write the slots of `state` up to `last`, those before it as read, so that breakpoints
+    /// set by others are kept.
+    #[cfg(target_arch = "aarch64")]
+    fn write_hw_breakpoints(&self, state: &HwDebugState, last: usize) -> Result<()> {
+        let iov = iovec {
+            iov_base: state as *const _ as _,
+            iov_len: mem::offset_of!(HwDebugState, dbg_regs) + (last + 1) * size_of::<HwDebugReg>(),
+        };
+
+        self.ptrace_raw(PTRACE_SETREGSET, NT_ARM_HW_BREAK, &iov as *const _ as _)
+            .context("failed to write hardware breakpoints")?;
+
+        Ok(())
+    }
+
+    /// Arm a free one of dr0-dr3 of the stopped tracee at `addr`. Returns the slot taken,
+    /// `None` if all of them are enabled in dr7.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_hw_breakpoint(&self, addr: usize) -> Result<Option<usize>> {
+        let dr7 = self.read_dr7()?;
+
+        // a slot is free if neither its local nor its global enable bit is set
+        let Some(slot) = (0..4).find(|slot| dr7 & (0b11 << (slot * 2)) == 0) else {
+            return Ok(None);
+        };
+
+        self.ptrace_raw(libc::PTRACE_POKEUSER, DEBUGREG_OFFSET + slot * 8, addr)
+            .context(format!("failed to write dr{slot}"))?;
+
+        // break on execution (R/W and LEN bits cleared)
+        let dr7 = (dr7 & !(0xf << (16 + slot * 4))) | (DR7_L0 << (slot * 2));
+        self.write_dr7(dr7)?;
+
+        Ok(Some(slot))
+    }
+
+    /// Disarm the hardware breakpoint in `slot`, taken by [`Self::set_hw_breakpoint`].
+    #[cfg(target_arch = "x86_64")]
+    pub fn clear_hw_breakpoint(&self, slot: usize) -> Result<()> {
+        let dr7 = self.read_dr7()?;
+        self.write_dr7(dr7 & !(DR7_L0 << (slot * 2)))
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn read_dr7(&self) -> Result<usize> {
+        let dr7 = ptrace::read_user(self.pid, (DEBUGREG_OFFSET + 7 * 8) as _)
+            .context("failed to read dr7")?;
+
+        Ok(dr7 as _)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn write_dr7(&self, dr7: usize) -> Result<()> {
+        self.ptrace_raw(libc::PTRACE_POKEUSER, DEBUGREG_OFFSET + 7 * 8, dr7)
+            .context("failed to write dr7")?;
+
+        Ok(())
+    }
+
     pub fn detach<T: Into<Option<Signal>>>(&self, sig: T) -> Result<()> {
         if self.attached.load(Ordering::Acquire) {
-            ptrace::detach(self.pid, sig)?;
+            match ptrace::detach(self.pid, sig) {
+                Ok(()) => debug!("detached from {self}"),
+                Err(Errno::ESRCH) if !self.is_alive() => debug!("{self} already gone"),
+                Err(err) => return Err(err.into()),
+            }
             self.attached.store(false, Ordering::Release);
-            debug!("detached from {self}");
         }
 
         Ok(())
     }
+
+    pub fn is_alive(&self) -> bool {
+        Process::new(self.pid.as_raw()).is_ok_and(|proc| {
+            proc.stat()
+                .and_then(|stat| stat.state())
+                .is_ok_and(|state| !matches!(state, ProcState::Zombie | ProcState::Dead))
+        })
+    }
+
+    /// Seccomp mode of the tracee, read once: only the tracee itself can change it, and
+    /// the mode never gets less strict.
+    pub fn seccomp_mode(&self) -> Result<SeccompMode> {
+        if let Some(mode) = self.seccomp.get() {
+            return Ok(*mode);
+        }
+
+        let status = Process::new(self.pid.as_raw())
+            .and_then(|proc| proc.status())
+            .context(format!("failed to read status of {self}"))?;
+
+        let mode = match status.seccomp {
+            Some(0) | None => SeccompMode::Disabled,
+            Some(1) => SeccompMode::Strict,
+            Some(2) => SeccompMode::Filter,
+            Some(mode) => bail!("{self} in unknown seccomp mode: {mode}"),
+        };
+
+        Ok(*self.seccomp.get_or_init(|| mode))
+    }
+
+    /// Turn `err` into [`TraceeVanished`] if the tracee is gone, so that errors caused by
+    /// the tracee dying at an arbitrary point are reported uniformly. Seccomp errors are
+    /// kept as is, as they tell why the tracee died.
+    pub fn classify_error(&self, err: anyhow::Error) -> anyhow::Error {
+        if err.downcast_ref::<TraceeVanished>().is_some()
+            || err.downcast_ref::<SeccompError>().is_some()
+            || self.is_alive()
+        {
+            err
+        } else {
+            anyhow::Error::new(TraceeVanished(self.pid)).context(format!("{err:#}"))
+        }
+    }
 }
 
 impl Display for RemoteProcess {
@@ -229,19 +741,110 @@ impl Display for RemoteProcess {
     }
 }
 
+/// Primitive operations on a tracee the extensions in [`ext`] are built upon, implemented
+/// by [`RemoteProcess`] and, for off-device tests, by `mock::MockProcess`.
+pub trait RemoteProcessOps: Display {
+    fn pid(&self) -> Pid;
+    fn wait(&self) -> Result<WaitStatus>;
+    fn cont(&self, sig: Option<Signal>) -> Result<()>;
+    fn step(&self, sig: Option<Signal>) -> Result<()>;
+    fn detach(&self, sig: Option<Signal>) -> Result<()>;
+    fn peek(&self, addr: usize) -> Result<c_long>;
+    fn peek_data(&self, addr: usize, data: &mut [u8]) -> Result<()>;
+    fn poke_data(&self, addr: usize, data: &[u8]) -> Result<()>;
+    fn poke_vectored(&self, writes: &[(usize, &[u8])]) -> Result<()>;
+    fn get_regs(&self) -> Result<RegSet>;
+    fn set_regs(&self, regs: &RegSet) -> Result<()>;
+    fn is_alive(&self) -> bool;
+    fn seccomp_mode(&self) -> Result<SeccompMode>;
+
+    /// Offset of `symbol` in the system library `library`, the same in every process.
+    fn symbol_offset(&self, library: &str, symbol: &str) -> Result<usize> {
+        SystemLibraryResolver::instance().resolve(library, symbol)
+    }
+}
+
+impl RemoteProcessOps for RemoteProcess {
+    fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    fn wait(&self) -> Result<WaitStatus> {
+        RemoteProcess::wait(self)
+    }
+
+    fn cont(&self, sig: Option<Signal>) -> Result<()> {
+        RemoteProcess::cont(self, sig)
+    }
+
+    fn step(&self, sig: Option<Signal>) -> Result<()> {
+        RemoteProcess::step(self, sig)
+    }
+
+    fn detach(&self, sig: Option<Signal>) -> Result<()> {
+        RemoteProcess::detach(self, sig)
+    }
+
+    fn peek(&self, addr: usize) -> Result<c_long> {
+        RemoteProcess::peek(self, addr)
+    }
+
+    fn peek_data(&self, addr: usize, data: &mut [u8]) -> Result<()> {
+        RemoteProcess::peek_data(self, addr, data)
+    }
+
+    fn poke_data(&self, addr: usize, data: &[u8]) -> Result<()> {
+        RemoteProcess::poke_data(self, addr, data)
+    }
+
+    fn poke_vectored(&self, writes: &[(usize, &[u8])]) -> Result<()> {
+        RemoteProcess::poke_vectored(self, writes)
+    }
+
+    fn get_regs(&self) -> Result<RegSet> {
+        RemoteProcess::get_regs(self)
+    }
+
+    fn set_regs(&self, regs: &RegSet) -> Result<()> {
+        RemoteProcess::set_regs(self, regs)
+    }
+
+    fn is_alive(&self) -> bool {
+        RemoteProcess::is_alive(self)
+    }
+
+    fn seccomp_mode(&self) -> Result<SeccompMode> {
+        RemoteProcess::seccomp_mode(self)
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// How many times [`spin_wait`] tolerates a missing procfs entry (~1s) before giving up
+const SPIN_WAIT_MAX_MISSING: usize = 100;
+
 pub fn spin_wait(pid: Pid) -> Result<()> {
     let mut count = 0;
     let sleep_duration = Duration::from_millis(10);
 
     loop {
-        let proc = Process::new(pid.as_raw())?;
+        let state = Process::new(pid.as_raw()).and_then(|proc| proc.stat()?.state());
 
-        match proc.stat().and_then(|stat| stat.state()) {
+        match state {
             Ok(ProcState::Stopped) => break,
             Ok(_) => {}
-            Err(ProcError::NotFound(_)) => {}
+            Err(ProcError::NotFound(_)) if count < SPIN_WAIT_MAX_MISSING => {}
+            Err(ProcError::NotFound(_)) => {
+                // the process is there but its procfs entry is not visible to us
+                if signal::kill(pid, None).is_ok() {
+                    bail!(
+                        "process {pid} is invisible in procfs: {}",
+                        ProcVisibility::instance().hint()
+                    );
+                }
+
+                bail!(TraceeVanished(pid));
+            }
             Err(err) => bail!(err),
         }
 
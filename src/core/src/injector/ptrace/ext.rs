@@ -1,7 +1,10 @@
 pub mod base;
+pub mod batch;
 pub mod ipc;
 pub mod jni;
 pub mod remote_call;
+pub mod syscall;
+pub mod unwind;
 
 use nix::sys::signal::Signal;
 use nix::sys::wait::WaitStatus;
@@ -1,36 +1,45 @@
-use crate::injector::ptrace::RemoteProcess;
+use crate::injector::ptrace::{RegSet, RemoteProcessOps};
+use crate::misc;
 use anyhow::Result;
 use nix::libc::c_long;
 
+/// Arguments past [`RegSet::ARGS`] are read from the stack
 #[allow(unused)]
 pub trait PtraceExt {
     fn get_arg(&self, index: usize) -> Result<c_long>;
     fn get_args(&self, args: &mut [c_long]) -> Result<()>;
+    fn get_args_with_regs(&self, regs: &RegSet, args: &mut [c_long]) -> Result<()>;
 }
 
-impl PtraceExt for RemoteProcess {
+impl<P: RemoteProcessOps> PtraceExt for P {
     fn get_arg(&self, index: usize) -> Result<c_long> {
         let regs = self.get_regs()?;
-        let arg = if index < 8 {
+        let arg = if index < RegSet::ARGS {
             regs.get_arg(index)
         } else {
-            let n = index - 8;
-            self.peek(regs.get_sp() + 8 * n)?
+            let n = index - RegSet::ARGS;
+            self.peek(regs.stack_args() + 8 * n)?
         };
 
         Ok(arg)
     }
 
     fn get_args(&self, args: &mut [c_long]) -> Result<()> {
-        let regs = self.get_regs()?;
+        self.get_args_with_regs(&self.get_regs()?, args)
+    }
+
+    /// Read args with already captured registers, stack args are read with a single
+    /// `process_vm_readv` straight into `args`.
+    fn get_args_with_regs(&self, regs: &RegSet, args: &mut [c_long]) -> Result<()> {
+        let split = args.len().min(RegSet::ARGS);
+        let (reg_args, stack_args) = args.split_at_mut(split);
+
+        for (index, arg) in reg_args.iter_mut().enumerate() {
+            *arg = regs.get_arg(index);
+        }
 
-        for (index, arg) in args.iter_mut().enumerate() {
-            if index < 8 {
-                *arg = regs.get_arg(index);
-            } else {
-                let n = index - 8;
-                *arg = self.peek(regs.get_sp() + 8 * n)?;
-            }
+        if !stack_args.is_empty() {
+            self.peek_data(regs.stack_args(), misc::as_byte_slice_mut(stack_args))?;
         }
 
         Ok(())
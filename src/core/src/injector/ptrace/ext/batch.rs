@@ -0,0 +1,199 @@
+use crate::injector::ptrace::ext::remote_call::PtraceRemoteCallExt;
+use crate::injector::ptrace::{RegSet, RemoteProcessOps};
+use anyhow::{Result, bail};
+use dynasmrt::{DynamicLabel, DynasmApi, DynasmLabelApi, VecAssembler};
+use nix::libc::c_long;
+use std::fmt::Display;
+use std::ops::Deref;
+use tracing::trace;
+
+#[cfg(target_arch = "aarch64")]
+type Relocation = dynasmrt::aarch64::Aarch64Relocation;
+#[cfg(target_arch = "x86_64")]
+type Relocation = dynasmrt::x64::X64Relocation;
+
+/// Per call table read by the chain stub: 8 register args, function, result
+const TABLE_SIZE: usize = 10 * 8;
+const TABLE_RESULT_OFFSET: usize = 9 * 8;
+
+/// Memory writes flushed with a single `process_vm_writev`.
+#[derive(Default)]
+pub struct PokeBatch {
+    writes: Vec<(usize, Vec<u8>)>,
+}
+
+impl PokeBatch {
+    pub fn push(&mut self, addr: usize, data: &[u8]) -> &mut Self {
+        self.writes.push((addr, data.to_vec()));
+        self
+    }
+
+    pub fn flush(&self, tracee: &impl Deref<Target: RemoteProcessOps>) -> Result<()> {
+        let writes: Vec<_> = self
+            .writes
+            .iter()
+            .map(|(addr, data)| (*addr, data.as_slice()))
+            .collect();
+
+        tracee.poke_vectored(&writes)
+    }
+}
+
+/// Remote calls made back to back by a stub in the remote process, which costs a single
+/// stop of the tracee instead of one per call.
+#[derive(Default)]
+pub struct CallChain {
+    calls: Vec<(usize, [c_long; 8])>,
+}
+
+impl CallChain {
+    pub fn push(&mut self, func: usize, args: &[c_long]) -> Result<&mut Self> {
+        if args.len() > RegSet::ARGS {
+            bail!("too many args: {} > {}", args.len(), RegSet::ARGS);
+        }
+
+        let mut regs = [0; 8];
+        regs[..args.len()].copy_from_slice(args);
+        self.calls.push((func, regs));
+
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Code calling the function of each table in turn, storing its result.
+    #[cfg(target_arch = "aarch64")]
+    fn assemble_stub(ops: &mut VecAssembler<Relocation>, tables: &[DynamicLabel]) {
+        crate::dynasm!(ops
+            ; stp fp, lr, [sp, #-16]!
+        );
+
+        for &table in tables {
+            crate::dynasm!(ops
+                ; adr x16, =>table
+                ; ldp x0, x1, [x16]
+                ; ldp x2, x3, [x16, #16]
+                ; ldp x4, x5, [x16, #32]
+                ; ldp x6, x7, [x16, #48]
+                ; ldr ip, [x16, #64]
+                ; blr ip
+                ; adr x16, =>table
+                ; str x0, [x16, #72]
+            );
+        }
+
+        crate::dynasm!(ops
+            ; ldp fp, lr, [sp], #16
+            ; ret
+            ; .align 8
+        );
+    }
+
+    /// Code calling the function of each table in turn, storing its result. rbp is pushed to
+    /// align the stack for the calls.
+    #[cfg(target_arch = "x86_64")]
+    fn assemble_stub(ops: &mut VecAssembler<Relocation>, tables: &[DynamicLabel]) {
+        dynasmrt::dynasm!(ops
+            ; .arch x64
+            ; push rbp
+        );
+
+        for &table in tables {
+            dynasmrt::dynasm!(ops
+                ; .arch x64
+                ; lea r11, [=>table]
+                ; mov rdi, [r11]
+                ; mov rsi, [r11 + 8]
+                ; mov rdx, [r11 + 16]
+                ; mov rcx, [r11 + 24]
+                ; mov r8, [r11 + 32]
+                ; mov r9, [r11 + 40]
+                ; call QWORD [r11 + 64]
+                ; lea r11, [=>table]
+                ; mov [r11 + 72], rax
+            );
+        }
+
+        dynasmrt::dynasm!(ops
+            ; .arch x64
+            ; pop rbp
+            ; ret
+            ; .align 8
+        );
+    }
+
+    /// Assemble the stub, returns it with the offset of its call tables.
+    fn assemble(&self) -> Result<(Vec<u8>, usize)> {
+        let mut ops: VecAssembler<Relocation> = VecAssembler::new(0);
+        let labels: Vec<_> = self.calls.iter().map(|_| ops.new_dynamic_label()).collect();
+
+        Self::assemble_stub(&mut ops, &labels);
+
+        let tables_offset = ops.offset().0;
+
+        for ((func, args), &table) in self.calls.iter().zip(&labels) {
+            ops.dynamic_label(table);
+
+            for arg in args {
+                ops.push_u64(*arg as _);
+            }
+
+            ops.push_u64(*func as _);
+            ops.push_u64(0xfee1deadfee1dead);
+        }
+
+        let bytecode = ops.finalize()?;
+
+        trace!("call chain bytecode: {bytecode:?}");
+
+        Ok((bytecode, tables_offset))
+    }
+}
+
+pub trait PtraceBatchExt {
+    /// Write `chain` to `addr`, along with `batch`, and run it. Returns the results of
+    /// the calls, in order.
+    fn call_chain(&self, addr: usize, chain: &CallChain, batch: PokeBatch) -> Result<Vec<c_long>>;
+}
+
+impl<T> PtraceBatchExt for T
+where
+    T: Deref<Target: RemoteProcessOps> + PtraceRemoteCallExt + Display,
+{
+    fn call_chain(
+        &self,
+        addr: usize,
+        chain: &CallChain,
+        mut batch: PokeBatch,
+    ) -> Result<Vec<c_long>> {
+        if chain.is_empty() {
+            batch.flush(self)?;
+            return Ok(Vec::new());
+        }
+
+        let (bytecode, tables_offset) = chain.assemble()?;
+
+        batch.push(addr, &bytecode);
+        batch.flush(self)?;
+
+        self.call_remote(addr, &[])?;
+
+        let mut tables = vec![0u8; chain.len() * TABLE_SIZE];
+
+        self.peek_data(addr + tables_offset, &mut tables)?;
+
+        Ok(tables
+            .chunks_exact(TABLE_SIZE)
+            .map(|table| {
+                let result = &table[TABLE_RESULT_OFFSET..TABLE_RESULT_OFFSET + 8];
+                c_long::from_le_bytes(result.try_into().unwrap())
+            })
+            .collect())
+    }
+}
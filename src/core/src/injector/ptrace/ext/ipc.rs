@@ -1,12 +1,11 @@
-use crate::injector::ptrace::RemoteProcess;
+use crate::injector::ptrace::RemoteProcessOps;
 use crate::injector::ptrace::ext::remote_call::PtraceRemoteCallExt;
+use crate::injector::ptrace::ext::syscall::PtraceRemoteSyscallExt;
 use crate::{build_args, misc};
-use anyhow::Result;
-use anyhow::bail;
-use log::warn;
+use anyhow::{Context, Result, bail};
 use nix::libc::{
-    AF_UNIX, CMSG_DATA, CMSG_FIRSTHDR, CMSG_SPACE, MAP_ANONYMOUS, MAP_FAILED, PR_SET_VMA,
-    PR_SET_VMA_ANON_NAME, SOCK_SEQPACKET, c_int, c_long, msghdr,
+    AF_UNIX, CMSG_DATA, CMSG_FIRSTHDR, CMSG_SPACE, MAP_ANONYMOUS, PR_SET_VMA, PR_SET_VMA_ANON_NAME,
+    SOCK_SEQPACKET, c_int, msghdr,
 };
 use nix::sys::socket;
 use nix::sys::socket::{ControlMessage, MsgFlags};
@@ -16,6 +15,7 @@ use std::ops::Deref;
 use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
 use std::{mem, ptr};
 use syscalls::{Sysno, syscall};
+use tracing::warn;
 
 #[derive(Debug)]
 pub struct RemoteFd {
@@ -28,8 +28,8 @@ impl RemoteFd {
         Self { fd, leak: true }
     }
 
-    pub fn close<T: PtraceRemoteCallExt>(mut self, tracee: &T) -> Result<()> {
-        tracee.call_remote_auto(("libc", "__close"), build_args!(self.fd))?;
+    pub fn close<T: PtraceRemoteSyscallExt>(mut self, tracee: &T) -> Result<()> {
+        tracee.call_remote_or_syscall(("libc", "__close"), Sysno::close, build_args!(self.fd))?;
         self.leak = false;
         Ok(())
     }
@@ -105,14 +105,27 @@ pub struct SocketConnection {
 }
 
 impl SocketConnection {
-    fn new(local_fd: OwnedFd, remote_fd: RemoteFd) -> Self {
+    pub fn new(local_fd: OwnedFd, remote_fd: RemoteFd) -> Self {
         Self {
             local_fd,
             remote_fd,
         }
     }
 
-    pub fn close<T: PtraceRemoteCallExt>(self, tracee: &T) -> Result<()> {
+    /// Send `fd`, to be received remotely with the `msghdr` of a [`FdMessage`].
+    pub fn send_fd(&self, fd: BorrowedFd) -> Result<()> {
+        socket::sendmsg::<()>(
+            self.local_fd.as_raw_fd(),
+            &[],
+            &[ControlMessage::ScmRights(&[fd.as_raw_fd()])],
+            MsgFlags::empty(),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn close<T: PtraceRemoteSyscallExt>(self, tracee: &T) -> Result<()> {
         self.remote_fd.close(tracee)?;
         Ok(())
     }
@@ -125,6 +138,67 @@ impl SocketConnection {
     }
 }
 
+/// A `msghdr` receiving a single fd, with its control buffer at `buffer_addr` in the remote
+/// process, followed by the header itself.
+pub struct FdMessage {
+    header: msghdr,
+    header_addr: usize,
+    buffer_len: usize,
+}
+
+impl FdMessage {
+    pub fn new(buffer_addr: usize) -> Self {
+        let buffer_len = unsafe { CMSG_SPACE(size_of::<i32>() as _) } as usize;
+
+        let header = msghdr {
+            msg_name: ptr::null_mut(),
+            msg_namelen: 0,
+            msg_iov: ptr::null_mut(),
+            msg_iovlen: 0,
+            msg_control: buffer_addr as _,
+            msg_controllen: buffer_len as _,
+            msg_flags: 0,
+        };
+
+        Self {
+            header,
+            header_addr: (buffer_addr + buffer_len + 0xf) & !0xf, // align to 16 bytes
+            buffer_len,
+        }
+    }
+
+    pub fn header_addr(&self) -> usize {
+        self.header_addr
+    }
+
+    /// The header to be written to [`Self::header_addr`] before `recvmsg` is called.
+    pub fn header_bytes(&self) -> &[u8] {
+        misc::as_byte_slice(&self.header)
+    }
+
+    /// Read back the fd received by `recvmsg`.
+    pub fn receive(&self, tracee: &impl Deref<Target: RemoteProcessOps>) -> Result<RemoteFd> {
+        let controllen = mem::offset_of!(msghdr, msg_controllen);
+
+        if tracee.peek(self.header_addr + controllen)? == 0 {
+            bail!("failed to install fd, please check your sepolicy rules")
+        }
+
+        let mut buffer = vec![0; self.buffer_len];
+
+        tracee.peek_data(self.header.msg_control as _, &mut buffer)?;
+
+        let mut header = self.header;
+
+        header.msg_control = buffer.as_ptr() as _;
+
+        let cmsg = unsafe { CMSG_FIRSTHDR(&header) };
+        let data = unsafe { CMSG_DATA(cmsg) };
+
+        Ok(RemoteFd::new(unsafe { *(data as *const i32) }))
+    }
+}
+
 pub trait PtraceIpcExt {
     fn mmap(
         &self,
@@ -140,6 +214,8 @@ pub trait PtraceIpcExt {
 
     fn munmap(&self, addr: usize, size: usize) -> Result<()>;
 
+    fn mprotect(&self, addr: usize, size: usize, prot: c_int) -> Result<()>;
+
     fn take_fd(&self, remote_fd: RawFd) -> Result<OwnedFd>;
 
     fn install_fd(
@@ -154,7 +230,7 @@ pub trait PtraceIpcExt {
 
 impl<T> PtraceIpcExt for T
 where
-    T: Deref<Target = RemoteProcess> + PtraceRemoteCallExt + Display,
+    T: Deref<Target: RemoteProcessOps> + PtraceRemoteCallExt + Display,
 {
     fn mmap(
         &self,
@@ -166,15 +242,11 @@ where
         offset: usize,
     ) -> Result<usize> {
         #[rustfmt::skip]
-        let result = self.call_remote_auto(
+        let result = self.call_remote_or_syscall(
             ("libc", "mmap"),
+            Sysno::mmap,
             build_args!(addr, size, prot, flags, fd.map(|it| it.fd).unwrap_or(-1), offset)
-        )?;
-
-        if result == MAP_FAILED as c_long {
-            let errno = self.errno()?;
-            bail!("failed to call mmap: {errno}");
-        }
+        ).context("failed to call mmap")?;
 
         Ok(result as _)
     }
@@ -209,14 +281,25 @@ where
     }
 
     fn munmap(&self, addr: usize, size: usize) -> Result<()> {
-        self.call_remote_auto(("libc", "munmap"), build_args!(addr, size))?;
+        self.call_remote_or_syscall(("libc", "munmap"), Sysno::munmap, build_args!(addr, size))?;
+        Ok(())
+    }
+
+    fn mprotect(&self, addr: usize, size: usize, prot: c_int) -> Result<()> {
+        #[rustfmt::skip]
+        self.call_remote_or_syscall(
+            ("libc", "mprotect"),
+            Sysno::mprotect,
+            build_args!(addr, size, prot)
+        ).context("failed to call mprotect")?;
+
         Ok(())
     }
 
     fn take_fd(&self, remote_fd: RawFd) -> Result<OwnedFd> {
         unsafe {
             let pfd =
-                OwnedFd::from_raw_fd(syscall!(Sysno::pidfd_open, self.pid.as_raw(), 0)? as RawFd);
+                OwnedFd::from_raw_fd(syscall!(Sysno::pidfd_open, self.pid().as_raw(), 0)? as RawFd);
 
             Ok(OwnedFd::from_raw_fd(
                 syscall!(Sysno::pidfd_getfd, pfd.as_raw_fd(), remote_fd, 0)? as RawFd,
@@ -230,50 +313,19 @@ where
         conn: &SocketConnection,
         fd: BorrowedFd,
     ) -> Result<RemoteFd> {
-        let buffer_len = unsafe { CMSG_SPACE(size_of::<i32>() as _) } as usize;
-
-        let mut header = msghdr {
-            msg_name: ptr::null_mut(),
-            msg_namelen: 0,
-            msg_iov: ptr::null_mut(),
-            msg_iovlen: 0,
-            msg_control: buffer_addr as _,
-            msg_controllen: buffer_len as _,
-            msg_flags: 0,
-        };
+        let message = FdMessage::new(buffer_addr);
 
-        let header_addr = (buffer_addr + buffer_len + 0xf) & !0xf; // align to 16 bytes
+        conn.send_fd(fd)?;
 
-        socket::sendmsg::<()>(
-            conn.local_fd.as_raw_fd(),
-            &[],
-            &[ControlMessage::ScmRights(&[fd.as_raw_fd()])],
-            MsgFlags::empty(),
-            None,
-        )?;
-
-        self.poke_data(header_addr, misc::as_byte_slice(&header))?;
+        self.poke_data(message.header_addr(), message.header_bytes())?;
 
         #[rustfmt::skip]
         self.call_remote_auto(
             ("libc", "recvmsg"),
-            build_args!(conn.remote_fd.as_raw_fd(), header_addr, 0)
+            build_args!(conn.remote_fd.as_raw_fd(), message.header_addr(), 0)
         )?;
 
-        if self.peek(header_addr + mem::offset_of!(msghdr, msg_controllen))? == 0 {
-            bail!("failed to install fd, please check your sepolicy rules")
-        }
-
-        let mut buffer = vec![0; buffer_len];
-
-        self.peek_data(buffer_addr, &mut buffer)?;
-
-        header.msg_control = buffer.as_ptr() as _;
-
-        let cmsg = unsafe { CMSG_FIRSTHDR(&header) };
-        let data = unsafe { CMSG_DATA(cmsg) };
-
-        Ok(RemoteFd::new(unsafe { *(data as *const i32) }))
+        message.receive(self)
     }
 
     fn connect(&self, buffer_addr: usize) -> Result<SocketConnection> {
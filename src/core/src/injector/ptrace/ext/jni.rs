@@ -1,14 +1,17 @@
-use crate::injector::ptrace::RemoteProcess;
+use crate::injector::ptrace::RemoteProcessOps;
 use crate::injector::ptrace::ext::remote_call::PtraceRemoteCallExt;
 use crate::{build_args, misc};
-use anyhow::Result;
-use jni::sys::{JNIEnv, jchar, jstring};
+use anyhow::{Result, bail};
+use jni::sys::{JNI_ABORT, JNIEnv, jchar, jint, jintArray, jobjectArray, jstring};
 use nix::libc::c_long;
 use scopeguard::defer;
 use std::fmt::Display;
 use std::ops::Deref;
 use zynx_misc::ext::ResultExt;
 
+/// Longest int array read, `NGROUPS_MAX` as the gids of a process are the longest one
+const MAX_INT_ARRAY_LENGTH: usize = 65536;
+
 #[macro_export]
 macro_rules! jni_fn {
     ($func: ident) => {
@@ -19,11 +22,17 @@ macro_rules! jni_fn {
 pub trait PtraceJniExt {
     fn call_remote_jni(&self, env: JNIEnv, fn_offset: usize, args: &[c_long]) -> Result<c_long>;
     fn read_jstring(&self, env: JNIEnv, str: jstring) -> Result<Option<String>>;
+    fn read_jint_array(&self, env: JNIEnv, array: jintArray) -> Result<Option<Vec<jint>>>;
+    fn read_jstring_array(
+        &self,
+        env: JNIEnv,
+        array: jobjectArray,
+    ) -> Result<Option<Vec<Option<String>>>>;
 }
 
 impl<T> PtraceJniExt for T
 where
-    T: Deref<Target = RemoteProcess> + PtraceRemoteCallExt + Display,
+    T: Deref<Target: RemoteProcessOps> + PtraceRemoteCallExt + Display,
 {
     fn call_remote_jni(&self, env: JNIEnv, fn_offset: usize, args: &[c_long]) -> Result<c_long> {
         let table = self.peek(env as _)? as usize;
@@ -53,4 +62,70 @@ where
 
         Ok(Some(String::from_utf16_lossy(&buffer)))
     }
+
+    fn read_jint_array(&self, env: JNIEnv, array: jintArray) -> Result<Option<Vec<jint>>> {
+        if array.is_null() {
+            return Ok(None);
+        }
+
+        let length =
+            self.call_remote_jni(env, jni_fn!(GetArrayLength), build_args!(env, array))? as usize;
+
+        if length > MAX_INT_ARRAY_LENGTH {
+            bail!("int array of {self} too long: {length}");
+        }
+
+        let ptr = self.call_remote_jni(
+            env,
+            jni_fn!(GetPrimitiveArrayCritical),
+            build_args!(env, array, 0),
+        )? as usize;
+
+        if ptr == 0 {
+            bail!("GetPrimitiveArrayCritical failed in {self}");
+        }
+
+        defer! {
+            // nothing was written, no need to copy back
+            self.call_remote_jni(env, jni_fn!(ReleasePrimitiveArrayCritical), build_args!(env, array, ptr, JNI_ABORT)).log_if_error();
+        }
+
+        let mut buffer: Vec<jint> = vec![0; length];
+
+        self.peek_data(ptr, misc::as_byte_slice_mut(buffer.as_mut_slice()))?;
+
+        Ok(Some(buffer))
+    }
+
+    fn read_jstring_array(
+        &self,
+        env: JNIEnv,
+        array: jobjectArray,
+    ) -> Result<Option<Vec<Option<String>>>> {
+        if array.is_null() {
+            return Ok(None);
+        }
+
+        let length =
+            self.call_remote_jni(env, jni_fn!(GetArrayLength), build_args!(env, array))? as usize;
+        let mut result = Vec::with_capacity(length);
+
+        for index in 0..length {
+            let element = self.call_remote_jni(
+                env,
+                jni_fn!(GetObjectArrayElement),
+                build_args!(env, array, index),
+            )?;
+
+            defer! {
+                if element != 0 {
+                    self.call_remote_jni(env, jni_fn!(DeleteLocalRef), build_args!(env, element)).log_if_error();
+                }
+            }
+
+            result.push(self.read_jstring(env, element as _)?);
+        }
+
+        Ok(Some(result))
+    }
 }
@@ -1,9 +1,10 @@
-use crate::binary::library::SystemLibraryResolver;
-use crate::injector::ptrace::RemoteProcess;
 use crate::injector::ptrace::ext::WaitStatusExt;
+use crate::injector::ptrace::ext::unwind::PtraceUnwindExt;
+use crate::injector::ptrace::{
+    RegSet, RemoteProcessOps, SeccompError, SeccompMode, TraceeVanished,
+};
 use anyhow::Result;
 use anyhow::bail;
-use log::trace;
 use nix::errno::Errno;
 use nix::libc::c_long;
 use nix::sys::signal::Signal;
@@ -11,7 +12,7 @@ use nix::sys::wait::WaitStatus;
 use scopeguard::defer;
 use std::fmt::Display;
 use std::ops::Deref;
-use zynx_misc::ext::ResultExt;
+use tracing::{error, trace, trace_span};
 
 #[derive(Debug)]
 pub enum RemoteFn {
@@ -58,19 +59,30 @@ pub trait PtraceRemoteCallExt {
 
 impl<T> PtraceRemoteCallExt for T
 where
-    T: Deref<Target = RemoteProcess> + RemoteLibraryResolver + Display,
+    T: Deref<Target: RemoteProcessOps> + RemoteLibraryResolver + Display,
 {
     fn call_remote(&self, func: usize, args: &[c_long]) -> Result<c_long> {
-        if args.len() > 8 {
-            bail!("{self} too many args: {} > 8", args.len());
+        if args.len() > RegSet::ARGS {
+            bail!("{self} too many args: {} > {}", args.len(), RegSet::ARGS);
         }
 
+        // every libc function is likely to make syscalls other than the four allowed
+        if self.seccomp_mode()? == SeccompMode::Strict {
+            bail!(SeccompError::Strict(self.pid()));
+        }
+
+        let _span = trace_span!("remote_call", func = format_args!("{func:#x}")).entered();
+
         trace!("call remote with args: {args:?}");
 
         let regs_backup = self.get_regs()?;
 
         defer! {
-            self.set_regs(&regs_backup).log_if_error();
+            if let Err(err) = self.set_regs(&regs_backup)
+                && self.is_alive()
+            {
+                error!("{self} failed to restore regs: {err:?}");
+            }
         }
 
         let mut regs = regs_backup.clone();
@@ -84,7 +96,16 @@ where
             regs.set_arg(i, *arg);
         }
 
+        #[cfg(target_arch = "aarch64")]
         regs.set_lr(token);
+
+        // returns to the address on top of the stack, pushed as `call` would
+        #[cfg(target_arch = "x86_64")]
+        {
+            regs.set_sp(regs.get_sp() - 8);
+            self.poke_data(regs.get_sp(), &token.to_le_bytes())?;
+        }
+
         self.set_regs(&regs)?;
         self.cont(None)?;
 
@@ -97,7 +118,24 @@ where
                 WaitStatus::Stopped(_, Signal::SIGSEGV) => break,
                 WaitStatus::Stopped(_, Signal::SIGCHLD) => {}
                 WaitStatus::Stopped(_, Signal::SIGCONT) => {}
-                _ => bail!("{self} stopped by {status:?}, expected SIGSEGV"),
+                WaitStatus::Stopped(_, Signal::SIGSYS) => {
+                    // not delivered, the tracee would crash on it
+                    let nr = self.get_regs()?.get_syscall_nr();
+                    return Err(SeccompError::Trapped(self.pid(), nr).into());
+                }
+                WaitStatus::Signaled(_, Signal::SIGSYS, _) => {
+                    return Err(SeccompError::Killed(self.pid()).into());
+                }
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                    return Err(TraceeVanished(self.pid()).into());
+                }
+                _ => {
+                    if let Ok(regs) = self.get_regs() {
+                        self.deref().log_backtrace(&regs);
+                    }
+
+                    bail!("{self} stopped by {status:?}, expected SIGSEGV")
+                }
             }
 
             self.cont(status.sig())?;
@@ -107,6 +145,7 @@ where
         regs = self.get_regs()?;
 
         if regs.get_pc() != token {
+            self.deref().log_backtrace(&regs);
             bail!("{self} wrong return address: 0x{:0>12x}", regs.get_pc());
         }
 
@@ -118,8 +157,7 @@ where
             RemoteFn::BaseOffset(base, offset) => base + offset,
             RemoteFn::LibraryOffset(library, offset) => self.find_library_base(library)? + offset,
             RemoteFn::LibrarySymbol(library, symbol) => {
-                let resolver = SystemLibraryResolver::instance();
-                self.find_library_base(library)? + resolver.resolve(library, symbol)?.addr
+                self.find_library_base(library)? + self.symbol_offset(library, symbol)?
             }
             RemoteFn::Absolute(addr) => addr,
         })
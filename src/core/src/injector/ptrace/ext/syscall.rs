@@ -0,0 +1,202 @@
+use crate::injector::arch::{Arch, Current};
+use crate::injector::ptrace::ext::remote_call::{PtraceRemoteCallExt, RemoteFn};
+use crate::injector::ptrace::{RegSet, RemoteProcessOps, SeccompError, TraceeVanished};
+use anyhow::{Context, Result, bail};
+use nix::errno::Errno;
+use nix::libc::c_long;
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitStatus;
+use scopeguard::defer;
+use std::fmt::Display;
+use std::ops::Deref;
+use syscalls::Sysno;
+use tracing::{error, trace, warn};
+
+/// How far into bionic's `syscall` to look for [`Arch::SYSCALL_INSN`], it's preceded by the `mov`s
+/// of the args
+const SVC_SEARCH_LEN: usize = 64;
+
+/// Largest errno returned by the kernel, as `-errno`
+const MAX_ERRNO: c_long = 4095;
+
+pub trait PtraceRemoteSyscallExt {
+    /// Make a bare syscall by single-stepping the tracee over a syscall instruction in libc,
+    /// so that exactly one syscall is made, whatever the libc wrapper would do around it.
+    /// Kernel errors are returned as [`Errno`].
+    fn syscall_remote(&self, nr: Sysno, args: &[c_long]) -> Result<c_long>;
+
+    /// Like [`syscall_remote`](Self::syscall_remote), over a syscall instruction written at pc
+    /// for the duration of the syscall instead, for when libc can't be located, e.g. before
+    /// the maps of the tracee are read. Other threads running the same code meanwhile would
+    /// make syscalls of their own, so that it's only meant for single-threaded tracees.
+    fn syscall_in_place(&self, nr: Sysno, args: &[c_long]) -> Result<c_long>;
+
+    /// Syscall the tracee stopped at `regs` was interrupted in, if the syscall instruction is
+    /// right before pc, or at pc once rewound to be restarted.
+    fn interrupted_syscall(&self, regs: &RegSet) -> Option<Sysno>;
+
+    /// Call `func`, the libc wrapper of syscall `nr`. If seccomp traps a syscall the wrapper
+    /// makes besides `nr` (e.g. for fdsan), the call is retried as a bare syscall. Failures
+    /// of either are returned as [`Errno`].
+    fn call_remote_or_syscall<F: Into<RemoteFn>>(
+        &self,
+        func: F,
+        nr: Sysno,
+        args: &[c_long],
+    ) -> Result<c_long>;
+}
+
+fn find_svc<T: PtraceRemoteCallExt + Deref<Target: RemoteProcessOps> + Display>(
+    tracee: &T,
+) -> Result<usize> {
+    let func = tracee.resolve_fn(("libc", "syscall"))?;
+    let mut code = [0u8; SVC_SEARCH_LEN];
+
+    tracee.peek_data(func, &mut code)?;
+
+    code.windows(Current::SYSCALL_INSN.len())
+        .step_by(Current::INSN_ALIGN)
+        .position(|insn| insn == Current::SYSCALL_INSN)
+        .map(|index| func + index * Current::INSN_ALIGN)
+        .context(format!("{tracee} no syscall instruction found in libc"))
+}
+
+/// Make syscall `nr` by single-stepping the tracee over the syscall instruction at `svc`.
+fn syscall_at<T: Deref<Target: RemoteProcessOps> + Display>(
+    tracee: &T,
+    svc: usize,
+    nr: Sysno,
+    args: &[c_long],
+) -> Result<c_long> {
+    if args.len() > 6 {
+        bail!("{tracee} too many syscall args: {} > 6", args.len());
+    }
+
+    trace!("syscall remote {nr} with args: {args:?}");
+
+    let regs_backup = tracee.get_regs()?;
+
+    defer! {
+        if let Err(err) = tracee.set_regs(&regs_backup)
+            && tracee.is_alive()
+        {
+            error!("{tracee} failed to restore regs: {err:?}");
+        }
+    }
+
+    let mut regs = regs_backup.clone();
+
+    regs.set_pc(svc);
+    regs.set_syscall_nr(nr.id() as _);
+
+    for (i, arg) in args.iter().enumerate() {
+        regs.set_syscall_arg(i, *arg);
+    }
+
+    tracee.set_regs(&regs)?;
+    tracee.step(None)?;
+
+    loop {
+        let status = tracee.wait()?;
+
+        trace!("status = {status:?}");
+
+        match status {
+            WaitStatus::Stopped(_, Signal::SIGTRAP) => break,
+            // the syscall is yet to be made, delivering the signal would run its handler
+            WaitStatus::Stopped(_, Signal::SIGCHLD | Signal::SIGCONT) => tracee.step(None)?,
+            WaitStatus::Stopped(_, Signal::SIGSYS) => {
+                bail!(SeccompError::Trapped(tracee.pid(), nr.id() as _));
+            }
+            WaitStatus::Signaled(_, Signal::SIGSYS, _) => {
+                bail!(SeccompError::Killed(tracee.pid()));
+            }
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                bail!(TraceeVanished(tracee.pid()));
+            }
+            _ => bail!("{tracee} stopped by {status:?}, expected SIGTRAP"),
+        }
+    }
+
+    regs = tracee.get_regs()?;
+
+    if regs.get_pc() != svc + Current::SYSCALL_INSN.len() {
+        bail!("{tracee} wrong pc after syscall: 0x{:0>12x}", regs.get_pc());
+    }
+
+    let result = regs.return_value();
+
+    if (-MAX_ERRNO..0).contains(&result) {
+        return Err(Errno::from_raw(-result as _)).context(format!("{tracee} {nr} failed"));
+    }
+
+    Ok(result)
+}
+
+impl<T> PtraceRemoteSyscallExt for T
+where
+    T: Deref<Target: RemoteProcessOps> + PtraceRemoteCallExt + Display,
+{
+    fn syscall_remote(&self, nr: Sysno, args: &[c_long]) -> Result<c_long> {
+        syscall_at(self, find_svc(self)?, nr, args)
+    }
+
+    fn syscall_in_place(&self, nr: Sysno, args: &[c_long]) -> Result<c_long> {
+        let pc = self.get_regs()?.get_pc();
+        let mut code = [0u8; Current::SYSCALL_INSN.len()];
+
+        self.peek_data(pc, &mut code)?;
+        self.poke_data(pc, Current::SYSCALL_INSN)?;
+
+        defer! {
+            if let Err(err) = self.poke_data(pc, &code)
+                && self.is_alive()
+            {
+                error!("{self} failed to restore code at 0x{pc:x}: {err:?}");
+            }
+        }
+
+        syscall_at(self, pc, nr, args)
+    }
+
+    fn interrupted_syscall(&self, regs: &RegSet) -> Option<Sysno> {
+        let pc = regs.get_pc();
+        let at_syscall = [pc.wrapping_sub(Current::SYSCALL_INSN.len()), pc]
+            .into_iter()
+            .any(|addr| {
+                let mut insn = [0u8; Current::SYSCALL_INSN.len()];
+                self.peek_data(addr, &mut insn).is_ok() && insn == Current::SYSCALL_INSN
+            });
+
+        if !at_syscall {
+            return None;
+        }
+
+        Sysno::new(usize::try_from(regs.get_syscall_nr()).ok()?)
+    }
+
+    fn call_remote_or_syscall<F: Into<RemoteFn>>(
+        &self,
+        func: F,
+        nr: Sysno,
+        args: &[c_long],
+    ) -> Result<c_long> {
+        let result = match self.call_remote_auto(func, args) {
+            Err(err) => match err.downcast_ref::<SeccompError>() {
+                Some(SeccompError::Trapped(_, trapped)) if *trapped != nr.id() as c_long => {
+                    warn!("{self} {err}, retrying {nr} as a bare syscall");
+                    return self.syscall_remote(nr, args);
+                }
+                _ => return Err(err),
+            },
+            Ok(result) => result,
+        };
+
+        if result == -1 {
+            let errno = self.errno()?;
+            return Err(errno).context(format!("{self} {nr} failed"));
+        }
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,115 @@
+use crate::injector::app::zygote::ZygoteMaps;
+use crate::injector::ptrace::{RegSet, RemoteProcessOps};
+use crate::misc;
+use procfs::process::MMapPath;
+use std::fmt::{Display, Formatter};
+use std::{fmt, mem};
+use tracing::warn;
+
+/// Frames walked at most, a corrupted chain may loop
+const MAX_FRAMES: usize = 32;
+
+/// User addresses fit in 48 bits, anything above is a pointer authentication code or a tag
+const ADDRESS_MASK: usize = 0x0000_ffff_ffff_ffff;
+
+/// Single frame of a remote backtrace, symbolicated as `path+offset` when mapped from a file.
+pub struct Frame {
+    pub pc: usize,
+    pub location: Option<(String, usize)>,
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some((path, offset)) => write!(f, "0x{:0>12x} {path}+{offset:#x}", self.pc),
+            None => write!(f, "0x{:0>12x} ???", self.pc),
+        }
+    }
+}
+
+pub trait PtraceUnwindExt {
+    fn backtrace(&self, regs: &RegSet, maps: &ZygoteMaps) -> Vec<Frame>;
+    fn log_backtrace(&self, regs: &RegSet);
+}
+
+impl<P: RemoteProcessOps + ?Sized> PtraceUnwindExt for P {
+    /// Walk the frame record chain (`[fp] = next fp`, `[fp + 8] = lr`, rbp and the return
+    /// address on x86_64) from `regs`. Frames of code built without frame pointers are skipped,
+    /// the walk stops at the first bad record.
+    fn backtrace(&self, regs: &RegSet, maps: &ZygoteMaps) -> Vec<Frame> {
+        let symbolicate = |pc: usize| {
+            let location = maps.find_vma(pc).and_then(|vma| match &vma.pathname {
+                MMapPath::Path(path) => {
+                    let offset = pc - vma.address.0 as usize + vma.offset as usize;
+                    Some((path.to_string_lossy().into_owned(), offset))
+                }
+                _ => None,
+            });
+
+            Frame { pc, location }
+        };
+
+        let mut frames = vec![symbolicate(regs.get_pc())];
+
+        // the caller of a leaf function, or of one stopped before its prologue
+        #[cfg(target_arch = "aarch64")]
+        if regs.get_lr() & ADDRESS_MASK != 0 {
+            frames.push(symbolicate(regs.get_lr() & ADDRESS_MASK));
+        }
+
+        let mut fp = regs.get_fp();
+
+        while frames.len() < MAX_FRAMES && fp != 0 && fp.is_multiple_of(mem::align_of::<usize>()) {
+            let mut record = [0usize; 2];
+
+            if self
+                .peek_data(fp, misc::as_byte_slice_mut(&mut record))
+                .is_err()
+            {
+                break;
+            }
+
+            let [next_fp, lr] = record;
+
+            // strip the pointer authentication code, if any
+            let lr = lr & ADDRESS_MASK;
+
+            if lr == 0 {
+                break;
+            }
+
+            if frames.last().is_none_or(|frame| frame.pc != lr) {
+                frames.push(symbolicate(lr));
+            }
+
+            // the stack grows down, callers' records live above
+            if next_fp <= fp {
+                break;
+            }
+
+            fp = next_fp;
+        }
+
+        frames
+    }
+
+    /// Log the backtrace of the tracee stopped at `regs`, for failures only.
+    fn log_backtrace(&self, regs: &RegSet) {
+        let maps = match ZygoteMaps::parse(self.pid()) {
+            Ok(maps) => maps,
+            Err(err) => {
+                warn!("{self} no backtrace: {err:#}");
+                return;
+            }
+        };
+
+        let frames = self.backtrace(regs, &maps);
+        let mut message = format!("{self} backtrace:");
+
+        for (index, frame) in frames.iter().enumerate() {
+            message += &format!("\n  #{index:02} {frame}");
+        }
+
+        warn!("{message}");
+    }
+}
@@ -0,0 +1,823 @@
+use crate::injector::arch::{Arch, Current};
+use crate::injector::ptrace::ext::remote_call::RemoteLibraryResolver;
+use crate::injector::ptrace::{RegSet, RemoteProcessOps, SeccompMode};
+use anyhow::{Context, Result, bail};
+use nix::errno::Errno;
+use nix::libc::c_long;
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+use parking_lot::{Mutex, MutexGuard};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::mem;
+use std::ops::Deref;
+use std::{fmt, iter};
+use syscalls::Sysno;
+
+/// Code of the tracee, run with the register args when it's resumed at the function or steps
+/// over a syscall.
+pub type MockFn = Box<dyn FnMut(&mut MockState, [c_long; 8]) -> MockOutcome + Send>;
+
+pub enum MockOutcome {
+    /// Return the value in x0 (rax), to lr (the popped return address) for a function
+    Return(c_long),
+    /// Stop with this status instead, e.g. `SIGSYS` of a syscall trapped by seccomp
+    Stop(WaitStatus),
+}
+
+/// What was done to the tracee, in order, for assertions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockOp {
+    Call { func: usize, args: [c_long; 8] },
+    Syscall { nr: c_long, args: [c_long; 8] },
+    Poke { addr: usize, data: Vec<u8> },
+    Detach,
+}
+
+pub struct MockState {
+    pub regs: RegSet,
+    /// Bytes of mapped memory, reading or writing others faults
+    pub memory: BTreeMap<usize, u8>,
+    pub alive: bool,
+    pub seccomp: SeccompMode,
+    pub ops: Vec<MockOp>,
+    functions: HashMap<usize, MockFn>,
+    syscalls: HashMap<c_long, MockFn>,
+    symbols: HashMap<(String, String), usize>,
+    pending: VecDeque<WaitStatus>,
+}
+
+impl MockState {
+    /// Map `len` zeroed bytes at `addr`.
+    pub fn map(&mut self, addr: usize, len: usize) {
+        self.memory.extend((addr..addr + len).zip(iter::repeat(0)));
+    }
+
+    pub fn read(&self, addr: usize, data: &mut [u8]) -> Result<()> {
+        for (offset, byte) in data.iter_mut().enumerate() {
+            *byte = *self
+                .memory
+                .get(&(addr + offset))
+                .context(format!("fault reading {:#x}", addr + offset))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, addr: usize, data: &[u8]) -> Result<()> {
+        if let Some(offset) = (0..data.len()).find(|it| !self.memory.contains_key(&(addr + it))) {
+            bail!("fault writing {:#x}", addr + offset);
+        }
+
+        self.memory.extend((addr..).zip(data.iter().copied()));
+
+        Ok(())
+    }
+
+    /// Stop on the `SIGSYS` of seccomp trapping syscall `nr`, made by the code at pc.
+    pub fn trap(&mut self, nr: Sysno) -> MockOutcome {
+        #[cfg(target_arch = "aarch64")]
+        self.regs.set_syscall_nr(nr.id() as _);
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.regs.0.orig_rax = nr.id() as _;
+        }
+
+        MockOutcome::Stop(WaitStatus::Stopped(Pid::from_raw(0), Signal::SIGSYS))
+    }
+
+    fn args(&self) -> [c_long; 8] {
+        std::array::from_fn(|index| {
+            if index < RegSet::ARGS {
+                self.regs.get_arg(index)
+            } else {
+                0
+            }
+        })
+    }
+
+    /// Where the function at pc returns to.
+    #[cfg(target_arch = "aarch64")]
+    fn pop_return_address(&mut self) -> Result<usize> {
+        Ok(self.regs.get_lr())
+    }
+
+    /// Where the function at pc returns to, popped off the stack as `ret` would.
+    #[cfg(target_arch = "x86_64")]
+    fn pop_return_address(&mut self) -> Result<usize> {
+        let sp = self.regs.get_sp();
+        let mut addr = [0u8; 8];
+
+        self.read(sp, &mut addr)?;
+        self.regs.set_sp(sp + 8);
+
+        Ok(usize::from_le_bytes(addr))
+    }
+
+    /// Run `outcome` of the code at pc, the tracee stopping at `next` if it returns.
+    fn finish(&mut self, outcome: MockOutcome, next: usize, stop: Signal) {
+        match outcome {
+            MockOutcome::Return(value) => {
+                #[cfg(target_arch = "aarch64")]
+                self.regs.set_arg(0, value);
+                #[cfg(target_arch = "x86_64")]
+                {
+                    self.regs.0.rax = value as _;
+                }
+                self.regs.set_pc(next);
+                self.pending
+                    .push_back(WaitStatus::Stopped(Pid::from_raw(0), stop));
+            }
+            MockOutcome::Stop(status) => {
+                if matches!(status, WaitStatus::Exited(..) | WaitStatus::Signaled(..)) {
+                    self.alive = false;
+                }
+
+                self.pending.push_back(status);
+            }
+        }
+    }
+}
+
+/// A tracee simulated in memory, to run the extensions in [`super::ext`] off-device. Resuming
+/// it runs the [`MockFn`] at pc, then stops it on `SIGSEGV` at its return address like the
+/// token of remote calls does. Anything else resumed faults at pc.
+pub struct MockProcess {
+    pid: Pid,
+    state: Mutex<MockState>,
+}
+
+impl MockProcess {
+    pub fn new(pid: Pid) -> Self {
+        Self {
+            pid,
+            state: Mutex::new(MockState {
+                regs: RegSet::new(unsafe { mem::zeroed() }),
+                memory: BTreeMap::new(),
+                alive: true,
+                seccomp: SeccompMode::Filter,
+                ops: Vec::new(),
+                functions: HashMap::new(),
+                syscalls: HashMap::new(),
+                symbols: HashMap::new(),
+                pending: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock()
+    }
+
+    /// Run `func` when the tracee is resumed at `addr`.
+    pub fn function(
+        &self,
+        addr: usize,
+        func: impl FnMut(&mut MockState, [c_long; 8]) -> MockOutcome + Send + 'static,
+    ) {
+        self.state().functions.insert(addr, Box::new(func));
+    }
+
+    /// Run `func` when the tracee steps over a syscall `nr`, others fail with `ENOSYS`.
+    pub fn syscall(
+        &self,
+        nr: Sysno,
+        func: impl FnMut(&mut MockState, [c_long; 8]) -> MockOutcome + Send + 'static,
+    ) {
+        self.state().syscalls.insert(nr.id() as _, Box::new(func));
+    }
+
+    /// Place `symbol` at `offset` of `library`.
+    pub fn symbol(&self, library: &str, symbol: &str, offset: usize) {
+        self.state()
+            .symbols
+            .insert((library.into(), symbol.into()), offset);
+    }
+
+    /// Stop the tracee with `status` on the next wait, before anything it would run.
+    pub fn push_status(&self, status: WaitStatus) {
+        self.state().pending.push_back(status);
+    }
+
+    fn check_alive(&self, state: &MockState) -> Result<()> {
+        if !state.alive {
+            return Err(Errno::ESRCH).context(format!("{self} is gone"));
+        }
+
+        Ok(())
+    }
+}
+
+impl RemoteProcessOps for MockProcess {
+    fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    fn wait(&self) -> Result<WaitStatus> {
+        let mut state = self.state();
+
+        let status = state
+            .pending
+            .pop_front()
+            .context(format!("{self} would never stop"))?;
+
+        // statuses are queued before the pid is known to the code of the tracee
+        Ok(match status {
+            WaitStatus::Stopped(_, sig) => WaitStatus::Stopped(self.pid, sig),
+            WaitStatus::Exited(_, code) => WaitStatus::Exited(self.pid, code),
+            WaitStatus::Signaled(_, sig, core) => WaitStatus::Signaled(self.pid, sig, core),
+            status => status,
+        })
+    }
+
+    fn cont(&self, _sig: Option<Signal>) -> Result<()> {
+        let mut state = self.state();
+
+        self.check_alive(&state)?;
+
+        if !state.pending.is_empty() {
+            return Ok(());
+        }
+
+        let pc = state.regs.get_pc();
+        let args = state.args();
+
+        let Some(mut func) = state.functions.remove(&pc) else {
+            state
+                .pending
+                .push_back(WaitStatus::Stopped(self.pid, Signal::SIGSEGV));
+            return Ok(());
+        };
+
+        let lr = state.pop_return_address()?;
+
+        state.ops.push(MockOp::Call { func: pc, args });
+
+        let outcome = func(&mut state, args);
+
+        state.functions.insert(pc, func);
+        state.finish(outcome, lr, Signal::SIGSEGV);
+
+        Ok(())
+    }
+
+    fn step(&self, _sig: Option<Signal>) -> Result<()> {
+        let mut state = self.state();
+
+        self.check_alive(&state)?;
+
+        if !state.pending.is_empty() {
+            return Ok(());
+        }
+
+        let pc = state.regs.get_pc();
+        let next = pc + Current::SYSCALL_INSN.len();
+        let mut insn = [0u8; Current::SYSCALL_INSN.len()];
+
+        state.read(pc, &mut insn)?;
+
+        // anything else is stepped over as if it were as long
+        if insn != Current::SYSCALL_INSN {
+            state.regs.set_pc(next);
+            state
+                .pending
+                .push_back(WaitStatus::Stopped(self.pid, Signal::SIGTRAP));
+            return Ok(());
+        }
+
+        // `syscall` reads the number from rax, it's reported in orig_rax once made
+        #[cfg(target_arch = "x86_64")]
+        let nr = state.regs.return_value();
+        #[cfg(target_arch = "aarch64")]
+        let nr = state.regs.get_syscall_nr();
+        let args = state.args();
+
+        state.ops.push(MockOp::Syscall { nr, args });
+
+        let outcome = match state.syscalls.remove(&nr) {
+            Some(mut func) => {
+                let outcome = func(&mut state, args);
+                state.syscalls.insert(nr, func);
+                outcome
+            }
+            None => MockOutcome::Return(-(Errno::ENOSYS as c_long)),
+        };
+
+        state.finish(outcome, next, Signal::SIGTRAP);
+
+        Ok(())
+    }
+
+    fn detach(&self, _sig: Option<Signal>) -> Result<()> {
+        self.state().ops.push(MockOp::Detach);
+        Ok(())
+    }
+
+    fn peek(&self, addr: usize) -> Result<c_long> {
+        let mut data = [0u8; 8];
+        self.peek_data(addr, &mut data)?;
+        Ok(c_long::from_le_bytes(data))
+    }
+
+    fn peek_data(&self, addr: usize, data: &mut [u8]) -> Result<()> {
+        let state = self.state();
+
+        self.check_alive(&state)?;
+        state.read(addr, data)
+    }
+
+    fn poke_data(&self, addr: usize, data: &[u8]) -> Result<()> {
+        let mut state = self.state();
+
+        self.check_alive(&state)?;
+        state.write(addr, data)?;
+        state.ops.push(MockOp::Poke {
+            addr,
+            data: data.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    fn poke_vectored(&self, writes: &[(usize, &[u8])]) -> Result<()> {
+        for (addr, data) in writes {
+            self.poke_data(*addr, data)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_regs(&self) -> Result<RegSet> {
+        let state = self.state();
+
+        self.check_alive(&state)?;
+
+        Ok(state.regs.clone())
+    }
+
+    fn set_regs(&self, regs: &RegSet) -> Result<()> {
+        let mut state = self.state();
+
+        self.check_alive(&state)?;
+        state.regs = regs.clone();
+
+        Ok(())
+    }
+
+    fn is_alive(&self) -> bool {
+        self.state().alive
+    }
+
+    fn seccomp_mode(&self) -> Result<SeccompMode> {
+        Ok(self.state().seccomp)
+    }
+
+    fn symbol_offset(&self, library: &str, symbol: &str) -> Result<usize> {
+        self.state()
+            .symbols
+            .get(&(library.into(), symbol.into()))
+            .copied()
+            .context(format!("no symbol {symbol} in {library}"))
+    }
+}
+
+impl Display for MockProcess {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "Mock({})", self.pid)
+    }
+}
+
+/// [`MockProcess`] with libraries at fixed bases, for the extensions expecting a tracee which
+/// resolves them, as injectors do.
+pub struct MockTracee {
+    process: MockProcess,
+    libraries: HashMap<String, usize>,
+}
+
+impl MockTracee {
+    pub fn new(process: MockProcess) -> Self {
+        Self {
+            process,
+            libraries: HashMap::new(),
+        }
+    }
+
+    pub fn library(&mut self, library: &str, base: usize) -> &mut Self {
+        self.libraries.insert(library.into(), base);
+        self
+    }
+}
+
+impl RemoteLibraryResolver for MockTracee {
+    fn find_library_base(&self, library: &str) -> Result<usize> {
+        self.libraries
+            .get(library)
+            .copied()
+            .context(format!("failed to resolve library: {library}"))
+    }
+}
+
+impl Deref for MockTracee {
+    type Target = MockProcess;
+
+    fn deref(&self) -> &Self::Target {
+        &self.process
+    }
+}
+
+impl Display for MockTracee {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.process, fmt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::injector::ptrace::ext::batch::{CallChain, PokeBatch, PtraceBatchExt};
+    use crate::injector::ptrace::ext::remote_call::PtraceRemoteCallExt;
+    use crate::injector::ptrace::ext::syscall::PtraceRemoteSyscallExt;
+    use crate::injector::ptrace::{SeccompError, TraceeVanished};
+
+    const LIBC: usize = 0x7f00_0000_0000;
+    const STACK: usize = 0x7ff0_0000_0000;
+    const STACK_SIZE: usize = 0x1000;
+    const SCRATCH: usize = 0x6000_0000_0000;
+
+    /// Where the tracee was stopped, restored after each remote call
+    const PC: usize = 0x5555_0000_1234;
+
+    fn tracee() -> MockTracee {
+        let process = MockProcess::new(Pid::from_raw(1234));
+
+        {
+            let mut state = process.state();
+
+            state.map(STACK, STACK_SIZE);
+            state.regs.set_sp(STACK + STACK_SIZE - 8);
+            state.regs.set_pc(PC);
+        }
+
+        let mut tracee = MockTracee::new(process);
+
+        tracee.library("libc", LIBC);
+        tracee
+    }
+
+    /// Place `symbol` in libc and run `func` when it's called.
+    fn libc_function(
+        tracee: &MockTracee,
+        symbol: &str,
+        offset: usize,
+        func: impl FnMut(&mut MockState, [c_long; 8]) -> MockOutcome + Send + 'static,
+    ) {
+        tracee.symbol("libc", symbol, offset);
+        tracee.function(LIBC + offset, func);
+    }
+
+    /// Place bionic's `syscall` in libc, with a syscall instruction past the `mov`s of its args.
+    fn libc_syscall(tracee: &MockTracee) {
+        let func = LIBC + 0x1000;
+
+        tracee.symbol("libc", "syscall", 0x1000);
+
+        let mut state = tracee.state();
+
+        state.map(func, 64);
+        state.write(func + 8, Current::SYSCALL_INSN).unwrap();
+    }
+
+    fn calls(tracee: &MockTracee) -> Vec<MockOp> {
+        tracee
+            .state()
+            .ops
+            .iter()
+            .filter(|op| !matches!(op, MockOp::Poke { .. }))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn call_remote_passes_args_and_restores_regs() {
+        let tracee = tracee();
+
+        libc_function(&tracee, "strtol", 0x100, |_, args| {
+            MockOutcome::Return(args[0] + args[1] * args[2])
+        });
+
+        let sp = tracee.get_regs().unwrap().get_sp();
+        let result = tracee
+            .call_remote_auto(("libc", "strtol"), &[1, 2, 3])
+            .unwrap();
+
+        assert_eq!(result, 7);
+        assert_eq!(
+            calls(&tracee),
+            [MockOp::Call {
+                func: LIBC + 0x100,
+                args: [1, 2, 3, 0, 0, 0, 0, 0],
+            }]
+        );
+
+        let regs = tracee.get_regs().unwrap();
+
+        assert_eq!(regs.get_pc(), PC);
+        assert_eq!(regs.get_sp(), sp);
+    }
+
+    #[test]
+    fn call_remote_resumes_after_unrelated_stops() {
+        let tracee = tracee();
+
+        libc_function(&tracee, "getuid", 0x100, |_, _| MockOutcome::Return(1000));
+        tracee.push_status(WaitStatus::Stopped(Pid::from_raw(0), Signal::SIGCHLD));
+
+        assert_eq!(
+            tracee.call_remote_auto(("libc", "getuid"), &[]).unwrap(),
+            1000
+        );
+        assert_eq!(calls(&tracee).len(), 1);
+    }
+
+    #[test]
+    fn call_remote_rejects_too_many_args() {
+        let tracee = tracee();
+        let args = vec![0; RegSet::ARGS + 1];
+
+        assert!(tracee.call_remote(LIBC, &args).is_err());
+        assert!(tracee.state().ops.is_empty());
+    }
+
+    #[test]
+    fn call_remote_refuses_strict_seccomp() {
+        let tracee = tracee();
+
+        tracee.state().seccomp = SeccompMode::Strict;
+
+        let err = tracee.call_remote(LIBC, &[]).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<SeccompError>(),
+            Some(SeccompError::Strict(_))
+        ));
+        assert!(tracee.state().ops.is_empty());
+    }
+
+    #[test]
+    fn call_remote_reports_trapped_syscall() {
+        let tracee = tracee();
+
+        libc_function(&tracee, "close", 0x100, |state, _| state.trap(Sysno::fcntl));
+
+        let err = tracee
+            .call_remote_auto(("libc", "close"), &[3])
+            .unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<SeccompError>(),
+            Some(SeccompError::Trapped(_, nr)) if *nr == Sysno::fcntl.id() as c_long
+        ));
+        assert!(tracee.is_alive());
+        assert_eq!(tracee.get_regs().unwrap().get_pc(), PC);
+    }
+
+    #[test]
+    fn call_remote_reports_vanished_tracee() {
+        let tracee = tracee();
+
+        libc_function(&tracee, "abort", 0x100, |_, _| {
+            MockOutcome::Stop(WaitStatus::Signaled(
+                Pid::from_raw(0),
+                Signal::SIGKILL,
+                false,
+            ))
+        });
+
+        let err = tracee.call_remote_auto(("libc", "abort"), &[]).unwrap_err();
+
+        assert!(err.downcast_ref::<TraceeVanished>().is_some());
+        assert!(!tracee.is_alive());
+    }
+
+    #[test]
+    fn call_remote_reads_errno() {
+        let tracee = tracee();
+        let errno = SCRATCH;
+
+        tracee.state().map(errno, 8);
+        tracee
+            .state()
+            .write(errno, &(Errno::EBADF as i32).to_le_bytes())
+            .unwrap();
+
+        libc_function(&tracee, "close", 0x100, |_, _| MockOutcome::Return(-1));
+        libc_function(&tracee, "__errno", 0x200, move |_, _| {
+            MockOutcome::Return(errno as _)
+        });
+
+        let err = tracee
+            .call_remote_or_syscall(("libc", "close"), Sysno::close, &[3])
+            .unwrap_err();
+
+        assert_eq!(err.downcast_ref::<Errno>(), Some(&Errno::EBADF));
+    }
+
+    #[test]
+    fn syscall_remote_steps_over_libc_syscall() {
+        let tracee = tracee();
+
+        libc_syscall(&tracee);
+
+        tracee.syscall(Sysno::getpid, |_, _| MockOutcome::Return(1234));
+
+        assert_eq!(tracee.syscall_remote(Sysno::getpid, &[]).unwrap(), 1234);
+        assert_eq!(
+            calls(&tracee),
+            [MockOp::Syscall {
+                nr: Sysno::getpid.id() as _,
+                args: [0; 8],
+            }]
+        );
+        assert_eq!(tracee.get_regs().unwrap().get_pc(), PC);
+    }
+
+    #[test]
+    fn syscall_in_place_restores_code_at_pc() {
+        let tracee = tracee();
+        let code = [0xaa; Current::SYSCALL_INSN.len()];
+
+        {
+            let mut state = tracee.state();
+
+            state.map(PC, code.len());
+            state.write(PC, &code).unwrap();
+        }
+
+        tracee.syscall(Sysno::openat, |_, args| MockOutcome::Return(args[2] + 3));
+
+        assert_eq!(
+            tracee
+                .syscall_in_place(Sysno::openat, &[-100, 0, 4])
+                .unwrap(),
+            7
+        );
+        assert_eq!(
+            calls(&tracee),
+            [MockOp::Syscall {
+                nr: Sysno::openat.id() as _,
+                args: [-100, 0, 4, 0, 0, 0, 0, 0],
+            }]
+        );
+
+        let mut restored = [0u8; Current::SYSCALL_INSN.len()];
+
+        tracee.state().read(PC, &mut restored).unwrap();
+
+        assert_eq!(restored, code);
+        assert_eq!(tracee.get_regs().unwrap().get_pc(), PC);
+    }
+
+    #[test]
+    fn interrupted_syscall_is_found_around_pc() {
+        let tracee = tracee();
+        let len = Current::SYSCALL_INSN.len();
+
+        {
+            let mut state = tracee.state();
+
+            state.map(PC - len, len * 2);
+            state.write(PC - len, Current::SYSCALL_INSN).unwrap();
+            state.trap(Sysno::epoll_pwait);
+        }
+
+        let mut regs = tracee.get_regs().unwrap();
+
+        assert_eq!(tracee.interrupted_syscall(&regs), Some(Sysno::epoll_pwait));
+
+        // rewound to be restarted
+        regs.set_pc(PC - len);
+        assert_eq!(tracee.interrupted_syscall(&regs), Some(Sysno::epoll_pwait));
+
+        // running code elsewhere
+        regs.set_pc(PC + len);
+        assert_eq!(tracee.interrupted_syscall(&regs), None);
+    }
+
+    #[test]
+    fn syscall_remote_returns_kernel_errors() {
+        let tracee = tracee();
+
+        libc_syscall(&tracee);
+        tracee.syscall(Sysno::close, |_, _| {
+            MockOutcome::Return(-(Errno::EBADF as c_long))
+        });
+
+        let err = tracee.syscall_remote(Sysno::close, &[3]).unwrap_err();
+
+        assert_eq!(err.downcast_ref::<Errno>(), Some(&Errno::EBADF));
+    }
+
+    #[test]
+    fn trapped_wrapper_is_retried_as_bare_syscall() {
+        let tracee = tracee();
+
+        libc_syscall(&tracee);
+        libc_function(&tracee, "close", 0x100, |state, _| state.trap(Sysno::fcntl));
+        tracee.syscall(Sysno::close, |_, _| MockOutcome::Return(0));
+
+        let result = tracee.call_remote_or_syscall(("libc", "close"), Sysno::close, &[3]);
+
+        assert_eq!(result.unwrap(), 0);
+        assert_eq!(
+            calls(&tracee),
+            [
+                MockOp::Call {
+                    func: LIBC + 0x100,
+                    args: [3, 0, 0, 0, 0, 0, 0, 0],
+                },
+                MockOp::Syscall {
+                    nr: Sysno::close.id() as _,
+                    args: [3, 0, 0, 0, 0, 0, 0, 0],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn call_chain_lays_out_tables() {
+        const SENTINEL: u64 = 0xfee1deadfee1dead;
+
+        let tracee = tracee();
+        let data = SCRATCH + 0x800;
+
+        tracee.state().map(SCRATCH, 0x1000);
+
+        // the stub calls the function of each table with its args, storing the result after it
+        tracee.function(SCRATCH, |state, _| {
+            let mut code = vec![0u8; 0x800];
+            state.read(SCRATCH, &mut code).unwrap();
+
+            let words: Vec<u64> = code
+                .chunks_exact(8)
+                .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+                .collect();
+
+            for (index, _) in words.iter().enumerate().filter(|(_, it)| **it == SENTINEL) {
+                let table = &words[index - 9..index];
+                let result = table.iter().sum::<u64>();
+
+                state
+                    .write(SCRATCH + index * 8, &result.to_le_bytes())
+                    .unwrap();
+            }
+
+            MockOutcome::Return(0)
+        });
+
+        let mut chain = CallChain::default();
+
+        chain.push(LIBC + 0x10, &[1, 2]).unwrap();
+        chain.push(LIBC + 0x20, &[3]).unwrap();
+
+        let mut batch = PokeBatch::default();
+
+        batch.push(data, b"data");
+
+        let results = tracee.call_chain(SCRATCH, &chain, batch).unwrap();
+
+        assert_eq!(results, [(LIBC + 0x13) as c_long, (LIBC + 0x23) as c_long]);
+        assert!(tracee.state().ops.contains(&MockOp::Poke {
+            addr: data,
+            data: b"data".to_vec(),
+        }));
+        assert_eq!(
+            calls(&tracee),
+            [MockOp::Call {
+                func: SCRATCH,
+                args: [0; 8]
+            }]
+        );
+    }
+
+    #[test]
+    fn writes_outside_mapped_memory_fault() {
+        let tracee = tracee();
+
+        assert!(tracee.poke_data(SCRATCH, b"data").is_err());
+        assert!(tracee.state().ops.is_empty());
+
+        let mut data = [0u8; 4];
+
+        assert!(tracee.peek_data(SCRATCH, &mut data).is_err());
+    }
+
+    #[test]
+    fn detached_tracee_is_recorded() {
+        let tracee = tracee();
+
+        tracee.detach(None).unwrap();
+
+        assert_eq!(calls(&tracee), [MockOp::Detach]);
+    }
+}
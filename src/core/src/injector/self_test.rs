@@ -0,0 +1,414 @@
+use crate::injector::app::SC_CONFIG;
+use crate::injector::app::embryo::EmbryoInjector;
+use crate::injector::app::zygote::ZygoteMaps;
+use crate::injector::arch::{Arch, Current};
+use crate::injector::ptrace::ext::WaitStatusExt;
+use crate::injector::ptrace::ext::base::PtraceExt;
+use crate::monitor;
+use crate::monitor::{Message, Monitor};
+use anyhow::{Context, Result, anyhow, bail};
+use nix::libc;
+use nix::sys::signal;
+use nix::sys::signal::{SigSet, SigmaskHow, Signal};
+use nix::sys::wait;
+use nix::sys::wait::WaitStatus;
+use nix::unistd;
+use nix::unistd::{ForkResult, Pid};
+use std::ffi::{CStr, CString};
+use std::io::{BufRead, BufReader, Lines, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::{env, mem, process, ptr};
+use tokio::{task, time};
+use zynx_bridge_shared::policy::liteloader::{NATIVE_CONTEXT_VERSION, NATIVE_ENTRY, NativeContext};
+use zynx_ebpf_shared::ObserveOnly;
+use zynx_misc::props;
+
+/// Hidden subcommand running [`fake_zygote`]
+const FAKE_ZYGOTE_COMMAND: &str = "fake-zygote";
+
+const FORK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Exit code of fake embryos returning from their specialize function
+const SPECIALIZED: i32 = 0;
+const NOT_SPECIALIZED: i32 = 1;
+
+/// Nice name of fake embryos, as seen by test modules
+const FAKE_NICE_NAME: &CStr = c"zynx.self_test";
+
+static SPECIALIZE_CALLED: AtomicBool = AtomicBool::new(false);
+
+/// Stands in for `SpecializeCommon`, the trampoline calls it with the args it was entered
+/// with, which it ignores.
+#[inline(never)]
+extern "C" fn fake_specialize() {
+    SPECIALIZE_CALLED.store(true, Ordering::Relaxed);
+}
+
+/// Native module loaded into fake embryos once specialized, e.g. one made by `new-module`
+#[derive(Clone, Debug)]
+pub struct TestModule {
+    pub library: PathBuf,
+    /// As the `data` of the library manifest
+    pub data: Option<String>,
+}
+
+fn dlerror() -> String {
+    let err = unsafe { libc::dlerror() };
+
+    if err.is_null() {
+        "unknown error".into()
+    } else {
+        unsafe { CStr::from_ptr(err) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Load `module` and call its [`NATIVE_ENTRY`] with a context laid out as the bridge does,
+/// without a `JNIEnv` nor ART hooks, which fake embryos don't have.
+fn call_native_entry(module: &TestModule) -> Result<String> {
+    let library = &module.library;
+    let path = CString::new(library.as_os_str().as_bytes())?;
+    let handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW) };
+
+    if handle.is_null() {
+        bail!("failed to load {}: {}", library.display(), dlerror());
+    }
+
+    let symbol = CString::new(NATIVE_ENTRY)?;
+    let entry = unsafe { libc::dlsym(handle, symbol.as_ptr()) };
+
+    if entry.is_null() {
+        bail!("{} doesn't export {NATIVE_ENTRY}", library.display());
+    }
+
+    let entry_fn: extern "C" fn(*const NativeContext) = unsafe { mem::transmute(entry) };
+    let data = module.data.as_deref().unwrap_or_default().as_bytes();
+
+    let context = NativeContext {
+        version: NATIVE_CONTEXT_VERSION,
+        uid: unistd::getuid().as_raw() as _,
+        api_level: props::get("ro.build.version.sdk")
+            .and_then(|sdk| sdk.parse().ok())
+            .unwrap_or_default(),
+        env: ptr::null_mut(),
+        nice_name: FAKE_NICE_NAME.as_ptr(),
+        app_data_dir: ptr::null(),
+        package_name: FAKE_NICE_NAME.as_ptr(),
+        data: if module.data.is_some() {
+            data.as_ptr()
+        } else {
+            ptr::null()
+        },
+        data_len: data.len(),
+        art: ptr::null(),
+    };
+
+    entry_fn(&context);
+
+    Ok(format!(
+        "{NATIVE_ENTRY} of {} returned, given {} bytes of data",
+        library.display(),
+        data.len()
+    ))
+}
+
+/// Mimic a zygote: fork on each `fork` line of stdin, the child unblocking signals as zygote
+/// forks do and then calling [`fake_specialize`], and the entry of `module` if any. The address
+/// of the former is printed on start, the outcome of the entry as a `module` line, and the
+/// exit status of each child once it's gone.
+pub fn fake_zygote(module: Option<TestModule>) -> Result<()> {
+    let mut stdout = std::io::stdout();
+
+    writeln!(stdout, "ready {:#x}", fake_specialize as *const () as usize)?;
+    stdout.flush()?;
+
+    for line in std::io::stdin().lines() {
+        if line? != "fork" {
+            continue;
+        }
+
+        match unsafe { unistd::fork()? } {
+            ForkResult::Child => {
+                let _ = signal::sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&SigSet::empty()), None);
+
+                fake_specialize();
+
+                if let Some(module) = &module {
+                    match call_native_entry(module) {
+                        Ok(detail) => writeln!(stdout, "module ok {detail}")?,
+                        Err(err) => writeln!(stdout, "module err {err:#}")?,
+                    }
+
+                    stdout.flush()?;
+                }
+
+                process::exit(if SPECIALIZE_CALLED.load(Ordering::Relaxed) {
+                    SPECIALIZED
+                } else {
+                    NOT_SPECIALIZED
+                });
+            }
+            ForkResult::Parent { child } => {
+                match wait::waitpid(child, None)? {
+                    WaitStatus::Exited(_, code) => writeln!(stdout, "exit {code}")?,
+                    status => writeln!(stdout, "exit {status:?}")?,
+                }
+
+                stdout.flush()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A fake zygote spawned from the current binary, killed on drop.
+struct FakeZygote {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    specialize_fn: usize,
+}
+
+impl FakeZygote {
+    fn spawn(module: Option<&TestModule>) -> Result<Self> {
+        let mut command = Command::new(env::current_exe()?);
+
+        command.arg(FAKE_ZYGOTE_COMMAND);
+
+        if let Some(module) = module {
+            command.arg("--module").arg(&module.library);
+
+            if let Some(data) = &module.data {
+                command.arg("--data").arg(data);
+            }
+        }
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let stdin = child.stdin.take().context("no stdin")?;
+        let stdout = BufReader::new(child.stdout.take().context("no stdout")?).lines();
+        let mut zygote = Self {
+            child,
+            stdin,
+            stdout,
+            specialize_fn: 0,
+        };
+
+        let line = zygote.read_line("ready ")?;
+        zygote.specialize_fn = usize::from_str_radix(line.trim_start_matches("0x"), 16)?;
+
+        Ok(zygote)
+    }
+
+    fn pid(&self) -> Pid {
+        Pid::from_raw(self.child.id() as _)
+    }
+
+    /// Next line of the fake zygote starting with `prefix`, without it.
+    fn read_line(&mut self, prefix: &str) -> Result<String> {
+        Ok(self.read_any(&[prefix])?.1)
+    }
+
+    /// Next line of the fake zygote starting with any of `prefixes`, split after the prefix.
+    fn read_any<'p>(&mut self, prefixes: &[&'p str]) -> Result<(&'p str, String)> {
+        for line in &mut self.stdout {
+            let line = line?;
+
+            for prefix in prefixes {
+                if let Some(rest) = line.strip_prefix(prefix) {
+                    return Ok((prefix, rest.into()));
+                }
+            }
+        }
+
+        bail!("fake zygote exited")
+    }
+
+    fn fork(&mut self) -> Result<()> {
+        writeln!(self.stdin, "fork")?;
+        Ok(self.stdin.flush()?)
+    }
+}
+
+impl Drop for FakeZygote {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Trace the stopped embryo as the injector does: break on its specialize function, then
+/// inject nothing through the trampoline and let it go.
+fn trace_embryo(zygote: Pid, pid: Pid, specialize_fn: usize) -> Result<()> {
+    let injector = EmbryoInjector::new(
+        pid,
+        ZygoteMaps::parse(zygote)?,
+        specialize_fn,
+        false,
+        false,
+        false,
+    );
+
+    injector.poke_data_ignore_perm(specialize_fn, Current::BREAKPOINT)?;
+    injector.seize()?;
+    injector.kill(Signal::SIGCONT)?;
+
+    loop {
+        match injector.wait()? {
+            WaitStatus::Stopped(_, Signal::SIGTRAP) => break,
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                bail!("embryo exited before hitting the breakpoint")
+            }
+            status => injector.cont(status.sig())?,
+        }
+    }
+
+    let mut regs = injector.get_regs()?;
+    regs.rewind_breakpoint();
+
+    if regs.get_pc() != specialize_fn {
+        injector.detach(None)?;
+        bail!(
+            "stopped at {:#x} instead of the breakpoint at {specialize_fn:#x}",
+            regs.get_pc()
+        );
+    }
+
+    report("breakpoint", Ok(format!("hit at {specialize_fn:#x}")));
+
+    let mut raw_args = vec![0; SC_CONFIG.args_cnt];
+
+    injector.get_args_with_regs(&regs, &mut raw_args)?;
+    injector.restore_swbp()?;
+
+    let result = injector.do_inject(regs, &raw_args, Vec::new());
+
+    injector.detach(None)?;
+
+    result
+}
+
+fn report(stage: &str, result: Result<String>) -> bool {
+    match result {
+        Ok(detail) => {
+            println!("[ ok ] {stage}: {detail}");
+            true
+        }
+        Err(err) => {
+            println!("[fail] {stage}: {err:#}");
+            false
+        }
+    }
+}
+
+/// Wait for the monitor to report a fork of `zygote`.
+async fn wait_fork(monitor: &Monitor, zygote: Pid) -> Result<Pid> {
+    let wait = async {
+        loop {
+            match monitor.recv_msg().await {
+                Some(Message::ZygoteFork(parent, pid)) if parent == zygote => return Ok(pid),
+                Some(_) => continue,
+                None => bail!("monitor exited unexpectedly"),
+            }
+        }
+    };
+
+    time::timeout(FORK_TIMEOUT, wait)
+        .await
+        .context(format!("no fork reported within {FORK_TIMEOUT:?}"))?
+}
+
+/// Run the pipeline end-to-end against a fake zygote: fork detection by the monitor, ptrace
+/// attach, breakpoint on the specialize function and the trampoline. Name and path matching
+/// are left out, they only apply to processes started by init. With `module`, the embryo calls
+/// its native entry once specialized, checking the module can read its payload.
+pub async fn self_test(module: Option<TestModule>) -> Result<()> {
+    let config = monitor::Config {
+        uid_filter: false,
+        specialize_probe: false,
+        require_specialize_probe: false,
+        observe_only: ObserveOnly::default(),
+        ..monitor::Config::new(vec![], &[])
+    };
+
+    if !report(
+        "monitor",
+        Monitor::init(config).map(|_| "started".to_string()),
+    ) {
+        bail!("self-test failed");
+    }
+
+    let monitor = Monitor::instance();
+    let mut zygote =
+        FakeZygote::spawn(module.as_ref()).context("failed to spawn the fake zygote")?;
+    let zygote_pid = zygote.pid();
+
+    monitor.attach_zygote(zygote_pid.as_raw())?;
+    zygote.fork()?;
+
+    let pid = match wait_fork(monitor, zygote_pid).await {
+        Ok(pid) => {
+            report("fork detection", Ok(format!("{zygote_pid} -> {pid}")));
+            pid
+        }
+        Err(err) => {
+            report("fork detection", Err(err));
+            monitor.detach_zygote(zygote_pid.as_raw())?;
+            bail!("self-test failed");
+        }
+    };
+
+    let specialize_fn = zygote.specialize_fn;
+
+    // ptrace requests are only accepted from the thread which seized the tracee, all of them
+    // are made from this one
+    let traced = task::spawn_blocking(move || trace_embryo(zygote_pid, pid, specialize_fn)).await?;
+    let traced = report("injection", traced.map(|_| "bridge loaded".to_string()));
+    let loaded = match &module {
+        // the embryo may die within the entry, leaving only its exit status
+        Some(_) => match zygote.read_any(&["module ", "exit "])? {
+            ("module ", outcome) => report(
+                "module",
+                match outcome.split_once(' ') {
+                    Some(("ok", detail)) => Ok(detail.into()),
+                    _ => Err(anyhow!("{}", outcome.trim_start_matches("err "))),
+                },
+            ),
+            (_, exit) => {
+                report(
+                    "module",
+                    Err(anyhow!("embryo ended with {exit} within the entry")),
+                );
+                monitor.detach_zygote(zygote_pid.as_raw())?;
+                bail!("self-test failed");
+            }
+        },
+        None => true,
+    };
+    let exit = zygote.read_line("exit ")?;
+    let specialized = report(
+        "specialize",
+        if exit == SPECIALIZED.to_string() {
+            Ok("returned through the trampoline".into())
+        } else {
+            Err(anyhow!("embryo ended with {exit}"))
+        },
+    );
+
+    monitor.detach_zygote(zygote_pid.as_raw())?;
+
+    if !traced || !loaded || !specialized {
+        bail!("self-test failed");
+    }
+
+    Ok(())
+}
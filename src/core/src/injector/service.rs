@@ -0,0 +1,406 @@
+use crate::injector::PAGE_SIZE;
+use crate::injector::app::ipc;
+use crate::injector::app::policy::ProviderBundle;
+use crate::injector::app::zygote::ZygoteMaps;
+use crate::injector::arch::{Arch, Current};
+use crate::injector::bridge::Bridge;
+use crate::injector::camouflage;
+use crate::injector::camouflage::NameKind;
+use crate::injector::ptrace::ext::WaitStatusExt;
+use crate::injector::ptrace::ext::ipc::{MmapOptions, PtraceIpcExt};
+use crate::injector::ptrace::ext::remote_call::{PtraceRemoteCallExt, RemoteLibraryResolver};
+use crate::injector::ptrace::ext::syscall::PtraceRemoteSyscallExt;
+use crate::injector::ptrace::{RemoteProcess, TraceeVanished};
+use crate::injector::shutdown::Shutdown;
+use crate::{atrace, build_args, misc};
+use anyhow::{Context, Result, bail};
+use nix::libc::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE, RTLD_NOW};
+use nix::sys::signal;
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+use policy::NativePolicyProvider;
+use procfs::process::Process;
+use scopeguard::defer;
+use std::ffi::CString;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+use syscalls::Sysno;
+use tokio::task;
+use tokio::time::timeout;
+use tracing::{debug, error, info, trace, warn};
+use zynx_bridge_shared::remote_lib::DlextInfo;
+use zynx_bridge_shared::zygote::{NativeBridgeArgs, SessionId};
+use zynx_misc::ext::ResultExt;
+
+pub mod policy;
+
+/// `AT_ENTRY` in the auxiliary vector, entry point of the executable
+const AT_ENTRY: u64 = 9;
+
+/// Syscalls the main thread of an app blocks in while idle, `epoll_wait` is made with
+/// `epoll_pwait` on AArch64
+#[cfg(target_arch = "aarch64")]
+const IDLE_SYSCALLS: &[Sysno] = &[Sysno::epoll_pwait];
+#[cfg(target_arch = "x86_64")]
+const IDLE_SYSCALLS: &[Sysno] = &[Sysno::epoll_wait, Sysno::epoll_pwait];
+
+/// Times the main thread of a running process is interrupted at most, until found idle
+const SAFE_POINT_ATTEMPTS: usize = 50;
+const SAFE_POINT_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Layout of the scratch page used for remote calls, the head is taken by `install_fd`
+const SCRATCH_STRING_OFFSET: usize = 0x100;
+const SCRATCH_STRING_MAX: usize = 0x100;
+const SCRATCH_DLEXT_OFFSET: usize = 0x200;
+const SCRATCH_ARGS_OFFSET: usize = 0x280;
+
+/// Injects libraries into a native service stopped by eBPF right after `execve`. Works by:
+/// 1. Installing a software breakpoint at the entry point of the executable
+/// 2. Waiting for the breakpoint, by then the linker has loaded and initialized all
+///    dependencies
+/// 3. Restoring the entry point, loading the bridge through remote calls and handing it the
+///    libraries allowed by [`NativePolicyProvider`]
+pub struct ServiceInjector {
+    tracee: RemoteProcess,
+    path: String,
+    session: SessionId,
+    /// Parsed once the linker is done, remote calls resolve libraries with it
+    maps: OnceLock<ZygoteMaps>,
+}
+
+impl RemoteLibraryResolver for ServiceInjector {
+    fn find_library_base(&self, library: &str) -> Result<usize> {
+        self.maps
+            .get()
+            .context("memory maps not parsed yet")?
+            .find_library_base_by_name(library)
+            .context(format!("failed to resolve library: {library}"))
+    }
+}
+
+impl ServiceInjector {
+    fn new(pid: Pid, path: String) -> Self {
+        Self {
+            tracee: RemoteProcess::new(pid),
+            path,
+            session: SessionId::generate(),
+            maps: OnceLock::new(),
+        }
+    }
+
+    /// Report whether a service left running in observe-only mode would be injected.
+    pub fn on_exec_observed(pid: Pid, path: &str) {
+        if NativePolicyProvider::instance().has_target(path) {
+            info!("observed service {pid} -> {path}: would inject");
+        } else {
+            info!("observed service {pid} -> {path}: no library targets it");
+        }
+    }
+
+    /// Handle a process which exec-ed one of the target paths.
+    pub fn on_exec(pid: Pid, path: &str) -> Result<()> {
+        if !NativePolicyProvider::instance().has_target(path) {
+            warn!("no library targets {path}, releasing {pid}");
+            return Ok(signal::kill(pid, Signal::SIGCONT)?);
+        }
+
+        let guard = Shutdown::instance().track(pid);
+        let path = path.to_string();
+
+        task::spawn(async move {
+            let task_handle = task::spawn_blocking(move || {
+                let _guard = guard;
+                let injector = Self::new(pid, path);
+                let _slice =
+                    atrace::slice(format_args!("zynx: service {injector} {}", injector.path));
+
+                if let Err(err) = injector.start() {
+                    let err = injector.classify_error(err);
+
+                    // never leave the service stopped, or with the breakpoint in place
+                    Shutdown::instance().restore(&injector).log_if_error();
+                    injector.detach(None).log_if_error();
+                    injector.kill(Signal::SIGCONT).log_if_error();
+
+                    if err.downcast_ref::<TraceeVanished>().is_some() {
+                        info!("service {pid} vanished before injection completed: {err:#}");
+                    } else {
+                        error!("service {pid} injection failed: {err:?}");
+                    }
+                }
+            });
+
+            if timeout(Duration::from_secs(5), task_handle).await.is_err() {
+                warn!("service injector for {pid} take too long to run...")
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Stop the main thread of a running process where no lock of malloc or the linker can be
+    /// held by it: idle in the `epoll_wait` of its `Looper`. Other threads keep running, so
+    /// that locks they hold get released. Retries while the thread is busy.
+    fn interrupt_at_safe_point(&self) -> Result<()> {
+        self.seize()?;
+
+        for attempt in 1..=SAFE_POINT_ATTEMPTS {
+            self.interrupt()?;
+
+            let regs = self.get_regs()?;
+
+            if let Some(nr) = self.interrupted_syscall(&regs)
+                && IDLE_SYSCALLS.contains(&nr)
+            {
+                return Ok(());
+            }
+
+            // left stopped after the last attempt, to be detached by the caller
+            if attempt == SAFE_POINT_ATTEMPTS {
+                break;
+            }
+
+            trace!("{self} busy at 0x{:x}, retrying", regs.get_pc());
+
+            self.cont(None)?;
+            thread::sleep(SAFE_POINT_INTERVAL);
+        }
+
+        bail!(
+            "{self} never idle within {:?}",
+            SAFE_POINT_INTERVAL * SAFE_POINT_ATTEMPTS as u32
+        )
+    }
+
+    /// Load the bridge into an already running process and hand it `bundle`, for late
+    /// injection, at a safe point of its main thread. Blocks until the bridge returns.
+    pub fn inject_running(
+        pid: Pid,
+        label: String,
+        session: SessionId,
+        bundle: ProviderBundle,
+    ) -> Result<()> {
+        let injector = Self {
+            session,
+            ..Self::new(pid, label)
+        };
+
+        defer! {
+            injector.detach(None).log_if_error();
+        }
+
+        injector.interrupt_at_safe_point()?;
+
+        let regs = injector.get_regs()?;
+        let syscall = injector.get_syscall_state()?;
+
+        // the kernel would restart the interrupted syscall over the first remote call
+        injector.set_syscall_state(-1)?;
+
+        let result = ZygoteMaps::parse_traced(&injector).and_then(|maps| {
+            let _ = injector.maps.set(maps);
+            injector.inject(bundle)
+        });
+
+        // resumed where it was interrupted, the syscall restarted if it would have been
+        injector.set_regs(&regs)?;
+        injector.set_syscall_state(syscall)?;
+
+        result
+    }
+
+    fn start(&self) -> Result<()> {
+        let auxv = Process::new(self.pid.as_raw())?.auxv()?;
+        let entry = *auxv.get(&AT_ENTRY).context("no AT_ENTRY in auxv")? as usize;
+
+        debug!("{self} entry point: 0x{entry:x}");
+
+        if !Shutdown::instance().patch(self, entry, Current::BREAKPOINT)? {
+            info!("{self} not traced, shutting down");
+            return self.kill(Signal::SIGCONT);
+        }
+
+        self.seize()?;
+        self.kill(Signal::SIGCONT)?;
+
+        defer! {
+            self.detach(None).log_if_error();
+        }
+
+        loop {
+            let status = self.wait()?;
+
+            trace!("{self} status = {status:?}");
+
+            if Shutdown::instance().is_requested()
+                && !matches!(status, WaitStatus::Exited(..) | WaitStatus::Signaled(..))
+            {
+                Shutdown::instance().restore(self)?;
+                info!("{self} released, shutting down");
+                break;
+            }
+
+            match status {
+                WaitStatus::Exited(_, code) => {
+                    warn!("service exited with code: {code}");
+                    break;
+                }
+                WaitStatus::Signaled(_, sig, _) => {
+                    warn!("service killed by {sig}");
+                    break;
+                }
+                WaitStatus::Stopped(_, Signal::SIGTRAP) => {
+                    let mut regs = self.get_regs()?;
+                    regs.rewind_breakpoint();
+
+                    if regs.get_pc() != entry {
+                        bail!("{self} unexpected SIGTRAP at 0x{:x}", regs.get_pc());
+                    }
+
+                    Shutdown::instance().restore(self)?;
+
+                    let _ = self.maps.set(ZygoteMaps::parse_traced(self)?);
+
+                    if let Some(bundle) = NativePolicyProvider::instance().check(&self.path) {
+                        self.inject(bundle)?;
+                    } else {
+                        info!("{self} nothing to inject");
+                    }
+
+                    self.set_regs(&regs)?;
+
+                    break;
+                }
+                _ => {}
+            }
+
+            self.cont(status.sig())?;
+        }
+
+        Ok(())
+    }
+
+    fn inject(&self, bundle: ProviderBundle) -> Result<()> {
+        info!("injecting bridge: {self} ({})", self.path);
+
+        let scratch = self.mmap_ex(
+            MmapOptions::new(
+                *PAGE_SIZE,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+            )
+            .name(&camouflage::name(NameKind::Mapping, "zynx::scratch")),
+        )?;
+
+        defer! {
+            if self.is_alive() {
+                self.munmap(scratch, *PAGE_SIZE).log_if_error();
+            }
+        }
+
+        let conn = self.connect(scratch)?;
+        let bridge_fd = self.install_fd(scratch, &conn, Bridge::instance().as_fd())?;
+        let info = unsafe { DlextInfo::from_raw_fd(bridge_fd.as_raw_fd()) };
+        let name_addr = self.poke_string(scratch, Bridge::instance().name())?;
+        let info_addr = scratch + SCRATCH_DLEXT_OFFSET;
+
+        self.poke_data(info_addr, misc::as_byte_slice(&info))?;
+
+        let handle = self.call_remote_auto(
+            ("libdl", "android_dlopen_ext"),
+            build_args!(name_addr, RTLD_NOW, info_addr),
+        );
+
+        bridge_fd.close(self)?;
+
+        let handle = handle?;
+
+        if handle == 0 {
+            conn.close(self)?;
+            bail!("failed to load bridge: {}", self.dlerror()?);
+        }
+
+        let entry_addr = self.poke_string(scratch, "native_start")?;
+        let entry = self.call_remote_auto(("libdl", "dlsym"), build_args!(handle, entry_addr))?;
+
+        if entry == 0 {
+            conn.close(self)?;
+            bail!("native_start not found: {}", self.dlerror()?);
+        }
+
+        // the payload is queued in the socket until the bridge reads it in `native_start`,
+        // which owns the remote end from then on
+        let (conn_fd, remote_conn_fd) = conn.forget();
+
+        let reports_fd = conn_fd.try_clone()?;
+
+        ipc::transfer_data(conn_fd, vec![bundle], None, None)?;
+        ipc::watch_reports(self.pid, reports_fd).log_if_error();
+
+        let args = NativeBridgeArgs {
+            conn_fd: remote_conn_fd,
+            session: self.session,
+        };
+        let args_addr = scratch + SCRATCH_ARGS_OFFSET;
+
+        self.poke_data(args_addr, misc::as_byte_slice(&args))?;
+        self.call_remote(entry as _, build_args!(args_addr))?;
+
+        info!("{self} bridge started");
+
+        Ok(())
+    }
+
+    /// Copy `value` into the string slot of the scratch page.
+    fn poke_string(&self, scratch: usize, value: &str) -> Result<usize> {
+        let value = CString::new(value)?;
+        let bytes = value.as_bytes_with_nul();
+
+        if bytes.len() > SCRATCH_STRING_MAX {
+            bail!("string too long: {value:?}");
+        }
+
+        let addr = scratch + SCRATCH_STRING_OFFSET;
+
+        self.poke_data(addr, bytes)?;
+
+        Ok(addr)
+    }
+
+    fn dlerror(&self) -> Result<String> {
+        let message = self.call_remote_auto(("libdl", "dlerror"), &[])?;
+
+        if message == 0 {
+            return Ok("unknown error".into());
+        }
+
+        let mut buffer = vec![0; SCRATCH_STRING_MAX];
+
+        self.peek_data(message as _, &mut buffer)?;
+
+        let len = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+
+        Ok(String::from_utf8_lossy(&buffer[..len]).into_owned())
+    }
+}
+
+impl Deref for ServiceInjector {
+    type Target = RemoteProcess;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tracee
+    }
+}
+
+impl Display for ServiceInjector {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.tracee, fmt)?;
+        write!(fmt, "[session={}]", self.session)
+    }
+}
@@ -0,0 +1,260 @@
+use crate::android::root::contexts;
+use crate::config::ZynxConfigs;
+use crate::injector::app::policy::{Attachment, ProviderBundle};
+use crate::injector::camouflage;
+use crate::injector::camouflage::NameKind;
+use crate::integrity;
+use crate::misc::create_sealed_memfd;
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::os::fd::OwnedFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tracing::{info, warn};
+use zynx_bridge_shared::policy::native::NativeParams;
+use zynx_bridge_shared::zygote::ProviderType;
+
+pub const SERVICES_DIR: &str = "/data/adb/zynx/services";
+pub const NATIVE_RULES_FILE: &str = "/data/adb/zynx/native.toml";
+
+/// Longest path matched by eBPF, which compares NUL-terminated 128-byte buffers
+const MAX_TARGET_PATH: usize = 127;
+
+static INSTANCE: OnceLock<NativePolicyProvider> = OnceLock::new();
+
+/// Manifest next to a library in [`SERVICES_DIR`], with the same name but a `.toml` extension:
+///
+/// ```toml
+/// target = "/vendor/bin/hw/android.hardware.foo-service"  # executable as started by init
+/// entry = "my_entry"                                      # optional `extern "C" fn()` symbol
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServiceManifest {
+    target: String,
+    entry: Option<String>,
+}
+
+/// Rules in [`NATIVE_RULES_FILE`], for libraries living anywhere:
+///
+/// ```toml
+/// [[inject]]
+/// target = "/system/bin/surfaceflinger"
+/// library = "/data/adb/modules/foo/libfoo.so"
+/// entry = "my_entry"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NativeRules {
+    #[serde(default)]
+    inject: Vec<NativeRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NativeRule {
+    target: String,
+    library: PathBuf,
+    entry: Option<String>,
+}
+
+#[derive(Debug)]
+struct NativeLibrary {
+    name: String,
+    path: PathBuf,
+    entry: Option<String>,
+}
+
+/// Decides which libraries get injected into native processes, by the executable they run.
+/// Kept apart from the app policy providers, which are consulted per zygote fork.
+///
+/// Loaded once at startup, since the paths have to be handed to eBPF before monitoring starts.
+#[derive(Default)]
+pub struct NativePolicyProvider {
+    libraries: HashMap<String, Vec<NativeLibrary>>,
+}
+
+impl NativePolicyProvider {
+    fn add(&mut self, target: String, library: NativeLibrary) {
+        if target.len() > MAX_TARGET_PATH {
+            warn!(
+                "skipping {}: target path longer than {MAX_TARGET_PATH} bytes",
+                library.path.display()
+            );
+            return;
+        }
+
+        let libraries = self.libraries.entry(target).or_default();
+
+        if libraries.iter().any(|it| it.path == library.path) {
+            warn!(
+                "skipping {}: already targets the same executable",
+                library.path.display()
+            );
+            return;
+        }
+
+        libraries.push(library);
+    }
+
+    fn scan_services(&mut self, dir: &Path) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            if path.extension().is_none_or(|ext| ext != "so") {
+                continue;
+            }
+
+            let manifest_path = path.with_extension("toml");
+            let manifest: ServiceManifest = match fs::read_to_string(&manifest_path)
+                .context(format!("missing {}", manifest_path.display()))
+                .and_then(|content| Ok(toml::from_str(&content)?))
+            {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    warn!("skipping {}: {err:?}", path.display());
+                    continue;
+                }
+            };
+
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            info!("service library: {name} -> {}", manifest.target);
+
+            self.add(
+                manifest.target,
+                NativeLibrary {
+                    name,
+                    path,
+                    entry: manifest.entry,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    fn load_rules(&mut self, file: &Path) -> Result<()> {
+        let rules: NativeRules = toml::from_str(&fs::read_to_string(file)?)?;
+
+        for rule in rules.inject {
+            if !rule.library.is_absolute() {
+                warn!(
+                    "skipping {}: library path must be absolute",
+                    rule.library.display()
+                );
+                continue;
+            }
+
+            let name = rule
+                .library
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            info!("native rule: {name} -> {}", rule.target);
+
+            self.add(
+                rule.target,
+                NativeLibrary {
+                    name,
+                    path: rule.library,
+                    entry: rule.entry,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn init() -> Result<()> {
+        let mut instance = Self::default();
+
+        if ZynxConfigs::instance().enable_services {
+            let dir = Path::new(SERVICES_DIR);
+            let rules = Path::new(NATIVE_RULES_FILE);
+
+            if dir.is_dir() {
+                instance.scan_services(dir)?;
+            }
+
+            if rules.is_file() {
+                instance
+                    .load_rules(rules)
+                    .context(format!("invalid {NATIVE_RULES_FILE}"))?;
+            }
+        }
+
+        INSTANCE
+            .set(instance)
+            .map_err(|_| anyhow!("NativePolicyProvider already initialized"))
+    }
+
+    pub fn instance() -> &'static Self {
+        INSTANCE
+            .get()
+            .expect("native policy provider not initialized")
+    }
+
+    /// Executable paths to be matched by eBPF.
+    pub fn target_paths(&self) -> Vec<String> {
+        self.libraries.keys().cloned().collect()
+    }
+
+    pub fn has_target(&self, path: &str) -> bool {
+        self.libraries.contains_key(path)
+    }
+
+    /// Bundle of the libraries to be loaded into a process running `path`, `None` if there's
+    /// nothing to load. Libraries are read at every exec, so updates to them take effect on
+    /// the next start of the service.
+    pub fn check(&self, path: &str) -> Option<ProviderBundle> {
+        let attachments: Vec<_> = self
+            .libraries
+            .get(path)?
+            .iter()
+            .filter_map(|library| {
+                Self::attach(library)
+                    .inspect_err(|err| warn!("skipping {}: {err:?}", library.path.display()))
+                    .ok()
+            })
+            .collect();
+
+        if attachments.is_empty() {
+            return None;
+        }
+
+        Some(ProviderBundle {
+            ty: ProviderType::Native,
+            attachments,
+            data: None,
+            mutation: None,
+        })
+    }
+
+    fn attach(library: &NativeLibrary) -> Result<Attachment> {
+        let data = fs::read(&library.path)?;
+
+        integrity::verify_library(&library.path, &data)?;
+
+        let name = format!("native::{}", library.name);
+        let memfd = create_sealed_memfd(&camouflage::name(NameKind::Library, &name), &data)?;
+
+        contexts::label(memfd.as_file(), ProviderType::Native, None);
+
+        let params = NativeParams {
+            lib_name: name,
+            entry: library.entry.clone(),
+        };
+
+        Ok(Attachment::with_both(
+            Arc::new(OwnedFd::from(memfd.into_file())),
+            wincode::serialize(&params)?,
+        ))
+    }
+}
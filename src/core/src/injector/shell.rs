@@ -0,0 +1,214 @@
+use crate::binary::library::SystemLibraryResolver;
+use crate::injector::app::zygote::ZygoteMaps;
+use crate::injector::ptrace::ext::remote_call::{PtraceRemoteCallExt, RemoteLibraryResolver};
+use crate::injector::ptrace::{RegSet, RemoteProcess};
+use anyhow::{Context, Result, bail};
+use nix::libc::c_long;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use procfs::process::{MMapPath, Process};
+use scopeguard::defer;
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, Write};
+use std::ops::Deref;
+use std::{fmt, io};
+use zynx_misc::ext::ResultExt;
+
+const HELP: &str = "\
+commands:
+  maps [filter]              show memory mappings (optionally filtered by path)
+  sym <lib> <symbol>         resolve a symbol, e.g. `sym libc getpid`
+  regs                       show pc/sp/lr and argument registers
+  peek <addr> [len]          dump remote memory (default 64 bytes)
+  poke <addr> <hex>          write bytes to remote memory, e.g. `poke 0x1234 deadbeef`
+  call <func> [args...]      call a remote function, `func` is an address or `lib!symbol`
+  errno                      read remote errno
+  help                       show this message
+  quit                       detach and exit";
+
+/// Interactive shell for poking at a stopped process with the ptrace ext traits.
+struct DebugShell {
+    tracee: RemoteProcess,
+}
+
+impl RemoteLibraryResolver for DebugShell {
+    fn find_library_base(&self, library: &str) -> Result<usize> {
+        // maps are parsed on every lookup, remote calls (e.g. dlopen) may change them
+        ZygoteMaps::parse(self.pid)?
+            .find_library_base_by_name(library)
+            .context(format!("failed to resolve library: {library}"))
+    }
+}
+
+impl Deref for DebugShell {
+    type Target = RemoteProcess;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tracee
+    }
+}
+
+impl Display for DebugShell {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "Shell({})", self.pid)
+    }
+}
+
+fn parse_number(value: &str) -> Result<c_long> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+
+    let number = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16)? as c_long,
+        None => value.parse::<u64>()? as c_long,
+    };
+
+    Ok(if negative { -number } else { number })
+}
+
+fn parse_hex(value: &str) -> Result<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        bail!("odd number of hex digits");
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&value[i..i + 2], 16)?))
+        .collect()
+}
+
+impl DebugShell {
+    fn attach(pid: Pid) -> Result<Self> {
+        let shell = Self {
+            tracee: RemoteProcess::new(pid),
+        };
+
+        shell.seize_stopped()?;
+
+        Ok(shell)
+    }
+
+    fn resolve(&self, func: &str) -> Result<usize> {
+        match func.split_once('!') {
+            Some((library, symbol)) => {
+                let offset = SystemLibraryResolver::instance().resolve(library, symbol)?;
+                Ok(self.find_library_base(library)? + offset)
+            }
+            None => Ok(parse_number(func)? as _),
+        }
+    }
+
+    fn execute(&self, command: &str, args: &[&str]) -> Result<()> {
+        match (command, args) {
+            ("help", _) => println!("{HELP}"),
+            ("maps", filter) => {
+                for vma in Process::new(self.pid.as_raw())?.maps()? {
+                    let path = match &vma.pathname {
+                        MMapPath::Path(path) => path.to_string_lossy().into_owned(),
+                        other => format!("{other:?}"),
+                    };
+
+                    if filter.first().is_none_or(|filter| path.contains(filter)) {
+                        println!(
+                            "{:0>12x}-{:0>12x} {} {path}",
+                            vma.address.0,
+                            vma.address.1,
+                            vma.perms.as_str()
+                        );
+                    }
+                }
+            }
+            ("sym", [library, symbol]) => {
+                println!("0x{:x}", self.resolve(&format!("{library}!{symbol}"))?);
+            }
+            ("regs", _) => {
+                let regs = self.get_regs()?;
+
+                println!("pc = 0x{:x}", regs.get_pc());
+                println!("sp = 0x{:x}", regs.get_sp());
+                #[cfg(target_arch = "aarch64")]
+                println!("lr = 0x{:x}", regs.get_lr());
+
+                for i in 0..RegSet::ARGS {
+                    println!("arg{i} = 0x{:x}", regs.get_arg(i));
+                }
+            }
+            ("peek", [addr, len @ ..]) => {
+                let addr = parse_number(addr)? as usize;
+                let len = len.first().map(|len| parse_number(len)).transpose()?;
+                let mut data = vec![0u8; len.unwrap_or(64) as _];
+
+                self.peek_data(addr, &mut data)?;
+
+                for (i, chunk) in data.chunks(16).enumerate() {
+                    let hex: Vec<_> = chunk.iter().map(|byte| format!("{byte:02x}")).collect();
+                    println!("{:0>12x}  {}", addr + i * 16, hex.join(" "));
+                }
+            }
+            ("poke", [addr, data]) => {
+                self.poke_data(parse_number(addr)? as _, &parse_hex(data)?)?;
+            }
+            ("call", [func, args @ ..]) => {
+                let func = self.resolve(func)?;
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|arg| parse_number(arg))
+                    .collect::<Result<_>>()?;
+                let result = self.call_remote(func, &args)?;
+
+                println!("{result} (0x{result:x})");
+            }
+            ("errno", _) => println!("{}", self.errno()?),
+            _ => bail!("invalid command, type `help` for usage"),
+        }
+
+        Ok(())
+    }
+
+    fn run(&self) -> Result<()> {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+
+        println!("attached to {}, type `help` for usage", self.pid);
+
+        loop {
+            print!("zynx({})> ", self.pid);
+            io::stdout().flush()?;
+
+            let Some(line) = lines.next() else {
+                break;
+            };
+
+            let line = line?;
+            let mut parts = line.split_whitespace();
+
+            let Some(command) = parts.next() else {
+                continue;
+            };
+
+            if command == "quit" || command == "exit" {
+                break;
+            }
+
+            let args: Vec<_> = parts.collect();
+
+            if let Err(err) = self.execute(command, &args) {
+                println!("error: {err:?}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn debug_shell(pid: i32) -> Result<()> {
+    let shell = DebugShell::attach(Pid::from_raw(pid))?;
+
+    defer! {
+        shell.detach(Signal::SIGCONT).log_if_error();
+    }
+
+    shell.run()
+}
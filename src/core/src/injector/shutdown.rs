@@ -0,0 +1,193 @@
+use crate::injector::ptrace::RemoteProcess;
+use anyhow::Result;
+use nix::sys::signal;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::signal::unix;
+use tokio::signal::unix::SignalKind;
+use tokio::sync::Notify;
+use tokio::{task, time};
+use tracing::{info, warn};
+use zynx_misc::ext::ResultExt;
+
+/// How long tracer threads get to detach from their tracees before the daemon exits
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Upper bound on waiting for the tracked set, the panicking thread may be holding it
+const LOCK_TIMEOUT: Duration = Duration::from_millis(100);
+
+static INSTANCE: Lazy<Shutdown> = Lazy::new(Default::default);
+
+/// Bytes overwritten in a tracee, restored by writing back the original content
+struct Patch {
+    addr: usize,
+    original: Vec<u8>,
+}
+
+impl Patch {
+    fn restore(self, pid: Pid) {
+        // the breakpoint lives in the private copy of the page, zygote is untouched
+        RemoteProcess::new(pid)
+            .poke_data_ignore_perm(self.addr, &self.original)
+            .log_if_error();
+    }
+}
+
+/// Processes stopped by us (eBPF `SIGSTOP`, breakpoints), which must not be left behind
+/// when the daemon exits.
+#[derive(Default)]
+pub struct Shutdown {
+    requested: AtomicBool,
+    notify: Notify,
+    tracked: Mutex<HashMap<Pid, Option<Patch>>>,
+}
+
+/// Stops tracking the process on drop, hold it until the process is released.
+pub struct TrackGuard {
+    pid: Pid,
+}
+
+impl Drop for TrackGuard {
+    fn drop(&mut self) {
+        Shutdown::instance().tracked.lock().remove(&self.pid);
+    }
+}
+
+impl Shutdown {
+    pub fn instance() -> &'static Self {
+        &INSTANCE
+    }
+
+    /// Release everything on SIGTERM/SIGINT.
+    pub fn install() -> Result<()> {
+        let mut sigterm = unix::signal(SignalKind::terminate())?;
+        let mut sigint = unix::signal(SignalKind::interrupt())?;
+
+        task::spawn(async move {
+            tokio::select! {
+                _ = sigterm.recv() => info!("received SIGTERM, shutting down"),
+                _ = sigint.recv() => info!("received SIGINT, shutting down"),
+            }
+
+            Self::instance().release_all();
+        });
+
+        Ok(())
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Acquire)
+    }
+
+    /// Wait until shutdown is requested.
+    pub async fn requested(&self) {
+        self.notify.notified().await
+    }
+
+    pub fn track(&self, pid: Pid) -> TrackGuard {
+        self.tracked.lock().insert(pid, None);
+        TrackGuard { pid }
+    }
+
+    /// Overwrite code of a tracked process, remembering the original bytes. Returns `false`
+    /// without touching the process if shutdown has been requested.
+    pub fn patch(&self, process: &RemoteProcess, addr: usize, data: &[u8]) -> Result<bool> {
+        let mut tracked = self.tracked.lock();
+
+        if self.is_requested() {
+            return Ok(false);
+        }
+
+        let mut original = vec![0; data.len()];
+
+        process.peek_data_ignore_perm(addr, &mut original)?;
+        process.poke_data_ignore_perm(addr, data)?;
+
+        tracked.insert(process.pid, Some(Patch { addr, original }));
+
+        Ok(true)
+    }
+
+    /// Write back the original bytes if they haven't been restored yet.
+    pub fn restore(&self, process: &RemoteProcess) -> Result<()> {
+        if let Some(patch) = self.tracked.lock().get_mut(&process.pid)
+            && let Some(Patch { addr, original }) = patch.take()
+        {
+            process.poke_data_ignore_perm(addr, &original)?;
+        }
+
+        Ok(())
+    }
+
+    /// The patched code has been restored by other means (e.g. `MADV_DONTNEED`).
+    pub fn on_restored(&self, pid: Pid) {
+        if let Some(patch) = self.tracked.lock().get_mut(&pid) {
+            *patch = None;
+        }
+    }
+
+    /// Restore patched code of a tracked process, if any, and resume it, for tracers failing
+    /// before they did.
+    pub fn release(&self, pid: Pid) {
+        let patch = self.tracked.lock().get_mut(&pid).and_then(Option::take);
+
+        if let Some(patch) = patch {
+            patch.restore(pid);
+        }
+
+        signal::kill(pid, Signal::SIGCONT).log_if_error();
+    }
+
+    /// Restore patched code of all tracked processes and resume them. Tracer threads notice
+    /// the request on the next stop (the `SIGCONT` sent here at the latest) and detach, since
+    /// ptrace requests are only accepted from the thread that seized the tracee.
+    pub fn release_all(&self) {
+        if self.requested.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let Some(mut tracked) = self.tracked.try_lock_for(LOCK_TIMEOUT) else {
+            warn!("tracked processes are locked, unable to release them");
+            self.notify.notify_one();
+            return;
+        };
+
+        for (pid, patch) in tracked.iter_mut() {
+            if let Some(patch) = patch.take() {
+                patch.restore(*pid);
+            }
+
+            signal::kill(*pid, Signal::SIGCONT).log_if_error();
+        }
+
+        info!("released {} tracked processes", tracked.len());
+
+        drop(tracked);
+
+        self.notify.notify_one();
+    }
+
+    /// Give tracer threads a chance to detach, tracees left over are detached by the kernel
+    /// when the daemon exits.
+    pub async fn drain(&self) {
+        let start = Instant::now();
+
+        while start.elapsed() < DRAIN_TIMEOUT {
+            if self.tracked.lock().is_empty() {
+                return;
+            }
+
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        warn!(
+            "{} processes still traced after {DRAIN_TIMEOUT:?}",
+            self.tracked.lock().len()
+        );
+    }
+}
@@ -0,0 +1,384 @@
+use crate::android::packages::{PackageInfo, PackageInfoService};
+use crate::events::InjectionEvent;
+use crate::injector::app::pipeline::SlowArgs;
+use crate::injector::app::policy::{EmbryoCheckArgs, PolicyDecision, PolicyProviderManager};
+use crate::misc;
+use crate::monitor::Message;
+use anyhow::{Context, Result, anyhow, bail};
+use nix::unistd::{Gid, Uid};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::info;
+use zynx_bridge_shared::zygote::arrays::DataInfo;
+use zynx_misc::ext::ResultExt;
+
+static RECORDER: OnceLock<Recorder> = OnceLock::new();
+
+/// Events of a trace, each one appended as a `[[record]]` table of a TOML file as it happens,
+/// so that the trace stays readable when the daemon dies. Each run of the daemon appends to the
+/// same file, starting with a [`TraceEvent::Started`] record.
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    /// Milliseconds since the recording started
+    time_ms: u64,
+    #[serde(flatten)]
+    event: TraceEvent,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum TraceEvent {
+    /// A daemon started recording, times of the following records are relative to it
+    Started {
+        pid: u32,
+        /// Seconds since the Unix epoch
+        unix_time: u64,
+    },
+    PathMatches {
+        pid: i32,
+        path: String,
+    },
+    NameMatches {
+        pid: i32,
+        name: String,
+    },
+    ZygoteFork {
+        zygote: i32,
+        pid: i32,
+    },
+    ZygoteSpecialize {
+        zygote: i32,
+        pid: i32,
+    },
+    ZygoteCrashed {
+        pid: i32,
+    },
+    Check(CheckRecord),
+    Outcome {
+        pid: i32,
+        session: String,
+        uid: u32,
+        providers: Vec<String>,
+        outcome: String,
+    },
+}
+
+/// Args of a policy check of an embryo, with what the providers made of them
+#[derive(Debug, Serialize, Deserialize)]
+struct CheckRecord {
+    pid: i32,
+    uid: u32,
+    gid: u32,
+    is_system_server: bool,
+    is_child_zygote: bool,
+    from_app_zygote: bool,
+    inherits_bridge: bool,
+    /// Lines of packages.list of the packages sharing the uid
+    packages: Vec<String>,
+    /// Slow args, only read if some provider asked for more info
+    nice_name: Option<String>,
+    app_data_dir: Option<String>,
+    gids: Option<Vec<u32>>,
+    /// Data dirs as `<package>,<volume uuid>,<ce data inode>`, the uuid empty for the
+    /// internal storage
+    data_info: Option<Vec<String>>,
+    /// Decision of each provider, by priority
+    decisions: Vec<String>,
+    /// Providers which would inject, empty if vetoed
+    providers: Vec<String>,
+    vetoed_by: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Trace {
+    #[serde(default)]
+    record: Vec<Record>,
+}
+
+struct Recorder {
+    file: Mutex<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    fn record(&self, event: TraceEvent) -> Result<()> {
+        let record = Record {
+            time_ms: self.start.elapsed().as_millis() as _,
+            event,
+        };
+        let content = format!("[[record]]\n{}\n", toml::to_string(&record)?);
+
+        self.file.lock().write_all(content.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Record monitor messages, policy checks and injection outcomes into `path`, for [`replay`].
+/// Appends to the trace of previous runs, if any.
+pub fn start_recording(path: &Path) -> Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("failed to open trace {path:?}"))?;
+    let recorder = Recorder {
+        file: Mutex::new(file),
+        start: Instant::now(),
+    };
+
+    recorder.record(TraceEvent::Started {
+        pid: process::id(),
+        unix_time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    })?;
+
+    RECORDER
+        .set(recorder)
+        .map_err(|_| anyhow!("already recording"))?;
+
+    info!("recording trace into {path:?}");
+
+    Ok(())
+}
+
+fn record(event: impl FnOnce() -> TraceEvent) {
+    if let Some(recorder) = RECORDER.get() {
+        recorder.record(event()).log_if_error();
+    }
+}
+
+pub fn on_message(message: &Message) {
+    record(|| match message {
+        Message::PathMatches(pid, path) => TraceEvent::PathMatches {
+            pid: pid.as_raw(),
+            path: path.clone(),
+        },
+        Message::NameMatches(pid, name) => TraceEvent::NameMatches {
+            pid: pid.as_raw(),
+            name: name.clone(),
+        },
+        Message::ZygoteFork(zygote, pid) => TraceEvent::ZygoteFork {
+            zygote: zygote.as_raw(),
+            pid: pid.as_raw(),
+        },
+        Message::ZygoteSpecialize(zygote, pid) => TraceEvent::ZygoteSpecialize {
+            zygote: zygote.as_raw(),
+            pid: pid.as_raw(),
+        },
+        Message::ZygoteCrashed(pid) => TraceEvent::ZygoteCrashed { pid: pid.as_raw() },
+    });
+}
+
+/// Providers which would inject given `decisions`, or the vetoing one, as recorded.
+fn summarize(decisions: &[PolicyDecision]) -> (Vec<String>, Option<String>) {
+    match PolicyProviderManager::instance().dry_run(decisions) {
+        Ok(providers) => (providers.iter().map(|ty| format!("{ty:?}")).collect(), None),
+        Err(vetoed_by) => (Vec::new(), Some(format!("{vetoed_by:?}"))),
+    }
+}
+
+pub fn on_check(pid: i32, args: &EmbryoCheckArgs, decisions: &[PolicyDecision]) {
+    record(|| {
+        let manager = PolicyProviderManager::instance();
+        let (providers, vetoed_by) = summarize(decisions);
+        let slow = match args {
+            EmbryoCheckArgs::Slow(args) => Some(args),
+            EmbryoCheckArgs::Fast(_) => None,
+        };
+
+        TraceEvent::Check(CheckRecord {
+            pid,
+            uid: args.uid.as_raw(),
+            gid: args.gid.as_raw(),
+            is_system_server: args.is_system_server,
+            is_child_zygote: args.is_child_zygote,
+            from_app_zygote: args.from_app_zygote,
+            inherits_bridge: args.inherits_bridge,
+            packages: args
+                .package_info
+                .iter()
+                .flat_map(|pkgs| pkgs.iter().map(PackageInfo::to_line))
+                .collect(),
+            nice_name: slow.and_then(|args| args.nice_name.clone()),
+            app_data_dir: slow.and_then(|args| args.app_data_dir.clone()),
+            gids: slow.and_then(|args| {
+                let gids = args.gids.as_ref()?;
+                Some(gids.iter().map(|gid| gid.as_raw()).collect())
+            }),
+            data_info: slow.and_then(|args| {
+                let list = args.pkg_data_info_list.as_ref()?;
+                Some(
+                    list.iter()
+                        .map(|info| {
+                            format!(
+                                "{},{},{}",
+                                info.package_name,
+                                info.volume_uuid.as_deref().unwrap_or_default(),
+                                info.ce_data_inode
+                            )
+                        })
+                        .collect(),
+                )
+            }),
+            decisions: manager
+                .kinds()
+                .iter()
+                .zip(decisions)
+                .map(|(ty, decision)| format!("{ty:?}: {decision:?}"))
+                .collect(),
+            providers,
+            vetoed_by,
+        })
+    });
+}
+
+pub fn on_outcome(event: &InjectionEvent) {
+    record(|| TraceEvent::Outcome {
+        pid: event.pid.as_raw(),
+        session: event.session.to_string(),
+        uid: event.uid.as_raw(),
+        providers: event.providers.iter().map(|ty| format!("{ty:?}")).collect(),
+        outcome: event.outcome.to_string(),
+    });
+}
+
+fn parse_data_info(entry: &str) -> Option<DataInfo> {
+    let mut fields = entry.split(',');
+    let (package_name, volume_uuid, inode) = (fields.next()?, fields.next()?, fields.next()?);
+
+    Some(DataInfo {
+        package_name: package_name.into(),
+        volume_uuid: (!volume_uuid.is_empty()).then(|| volume_uuid.into()),
+        ce_data_inode: inode.parse().ok()?,
+    })
+}
+
+/// Run a recorded check through the providers again, returning whether they still decide
+/// as recorded.
+async fn replay_check(time_ms: u64, check: CheckRecord) -> bool {
+    let uid = Uid::from_raw(check.uid);
+    let args = EmbryoCheckArgs::new_fast(
+        uid,
+        Gid::from_raw(check.gid),
+        check.is_system_server,
+        check.is_child_zygote,
+        check.from_app_zygote,
+        check.inherits_bridge,
+        PackageInfoService::instance().query(uid),
+    );
+
+    let manager = PolicyProviderManager::instance();
+    let mut result = manager.check(&args, None).await;
+
+    let args = if result.more_info {
+        let args = args.into_slow(SlowArgs {
+            nice_name: check.nice_name,
+            app_data_dir: check.app_data_dir,
+            gids: check
+                .gids
+                .map(|gids| gids.into_iter().map(Gid::from_raw).collect()),
+            pkg_data_info_list: check
+                .data_info
+                .map(|list| list.iter().filter_map(|it| parse_data_info(it)).collect()),
+        });
+
+        manager.recheck_slow(&args, &mut result, None).await;
+        args
+    } else {
+        args
+    };
+
+    let (providers, vetoed_by) = summarize(&result.decisions);
+    let packages: Vec<_> = args
+        .package_info
+        .iter()
+        .flat_map(|pkgs| pkgs.iter().map(|pkg| pkg.name.as_str()))
+        .collect();
+    let matches = providers == check.providers && vetoed_by == check.vetoed_by;
+    let describe = |providers: &[String], vetoed_by: &Option<String>| match vetoed_by {
+        Some(ty) => format!("vetoed by {ty}"),
+        None if providers.is_empty() => "nothing".into(),
+        None => format!("{providers:?}"),
+    };
+
+    println!(
+        "{time_ms:>8} check {} (uid {uid}, {packages:?}): recorded {}, replayed {}{}",
+        check.pid,
+        describe(&check.providers, &check.vetoed_by),
+        describe(&providers, &vetoed_by),
+        if matches { "" } else { "  <-- differs" }
+    );
+
+    matches
+}
+
+/// Feed the checks of a recorded trace through the policy providers, without tracing any
+/// process, and report those deciding otherwise than recorded. Packages are those recorded,
+/// lists and modules are read below `root` if given, e.g. a copy of `/data/adb` pulled off the
+/// device into `<root>/data/adb`.
+pub async fn replay(path: &Path, root: Option<&Path>) -> Result<()> {
+    let content = fs::read_to_string(path).context(format!("failed to read trace {path:?}"))?;
+    let trace: Trace = toml::from_str(&content).context(format!("malformed trace {path:?}"))?;
+
+    let packages: BTreeSet<_> = trace
+        .record
+        .iter()
+        .filter_map(|record| match &record.event {
+            TraceEvent::Check(check) => Some(check.packages.iter()),
+            _ => None,
+        })
+        .flatten()
+        .cloned()
+        .collect();
+
+    if let Some(root) = root {
+        misc::set_files_root(root.into());
+    }
+
+    PackageInfoService::init_static(packages.iter().map(String::as_str))?;
+    PolicyProviderManager::init().await?;
+
+    let mut checks = 0;
+    let mut differing = 0;
+
+    for Record { time_ms, event } in trace.record {
+        match event {
+            TraceEvent::Check(check) => {
+                checks += 1;
+
+                if !replay_check(time_ms, check).await {
+                    differing += 1;
+                }
+            }
+            TraceEvent::Outcome {
+                pid,
+                session,
+                providers,
+                outcome,
+                ..
+            } => {
+                println!("{time_ms:>8} outcome {pid} (session {session}): {outcome} {providers:?}");
+            }
+            event => println!("{time_ms:>8} {event:?}"),
+        }
+    }
+
+    println!("{checks} checks replayed, {differing} decided otherwise");
+
+    if differing > 0 {
+        bail!("{differing} checks decided otherwise than recorded");
+    }
+
+    Ok(())
+}
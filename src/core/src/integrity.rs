@@ -0,0 +1,130 @@
+use crate::config::ZynxConfigs;
+use crate::misc::rooted;
+use anyhow::{Context, Result, anyhow, bail};
+use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::SystemTime;
+use tracing::debug;
+
+/// Hex-encoded ed25519 public keys, one per line, `#` starts a comment.
+pub const TRUSTED_KEYS_FILE: &str = "/data/adb/zynx/trusted_keys";
+
+/// Extensions of the sidecar files holding a library's SHA-256 digest (as printed by
+/// `sha256sum`) and ed25519 signature (raw or hex-encoded)
+pub const SIDECAR_EXTENSIONS: [&str; 2] = ["sha256", "sig"];
+
+#[derive(Debug)]
+pub enum Verification {
+    Digest,
+    Signature,
+    Unverified,
+}
+
+fn read_sidecar(library: &Path, extension: &str) -> Result<Option<Vec<u8>>> {
+    match fs::read(library.with_extension(extension)) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Latest modification time of the library's sidecar files, if any.
+pub fn sidecars_mtime(library: &Path) -> Option<SystemTime> {
+    SIDECAR_EXTENSIONS
+        .iter()
+        .filter_map(|ext| fs::metadata(library.with_extension(ext)).ok())
+        .filter_map(|meta| meta.modified().ok())
+        .max()
+}
+
+fn trusted_keys() -> Result<Vec<VerifyingKey>> {
+    let content = match fs::read_to_string(rooted(TRUSTED_KEYS_FILE)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let bytes: [u8; 32] = hex::decode(line)?
+                .try_into()
+                .map_err(|_| anyhow!("ed25519 public keys are 32 bytes"))?;
+
+            VerifyingKey::from_bytes(&bytes).context(format!("invalid trusted key: {line}"))
+        })
+        .collect()
+}
+
+fn verify_digest(data: &[u8], sidecar: &[u8]) -> Result<()> {
+    // `sha256sum` output: `<digest>  <file name>`
+    let expected = String::from_utf8_lossy(sidecar);
+    let expected = expected.split_whitespace().next().unwrap_or_default();
+    let expected = hex::decode(expected).context("malformed sha256 file")?;
+
+    if Sha256::digest(data).as_slice() != expected {
+        bail!("sha256 mismatch");
+    }
+
+    Ok(())
+}
+
+fn verify_signature(data: &[u8], sidecar: &[u8]) -> Result<()> {
+    let signature = if sidecar.len() == Signature::BYTE_SIZE {
+        Signature::from_slice(sidecar)?
+    } else {
+        let hex = hex::decode(String::from_utf8_lossy(sidecar).trim())
+            .context("malformed signature file")?;
+        Signature::from_slice(&hex)?
+    };
+
+    let keys = trusted_keys().context(format!("failed to load {TRUSTED_KEYS_FILE}"))?;
+
+    if !keys
+        .iter()
+        .any(|key| key.verify_strict(data, &signature).is_ok())
+    {
+        bail!("signature doesn't match any key in {TRUSTED_KEYS_FILE}");
+    }
+
+    Ok(())
+}
+
+/// Verify file content against its sidecar files, those that exist must match.
+pub fn verify_sidecars(library: &Path, data: &[u8]) -> Result<Verification> {
+    let mut verification = Verification::Unverified;
+
+    if let Some(sidecar) = read_sidecar(library, "sha256")? {
+        verify_digest(data, &sidecar)?;
+        verification = Verification::Digest;
+    }
+
+    if let Some(sidecar) = read_sidecar(library, "sig")? {
+        verify_signature(data, &sidecar)?;
+        verification = Verification::Signature;
+    }
+
+    Ok(verification)
+}
+
+/// Verify library content against its sidecar files, to be called before sealing it.
+///
+/// Sidecars that exist must match. Libraries without any are only accepted when library
+/// verification is not enabled.
+pub fn verify_library(library: &Path, data: &[u8]) -> Result<Verification> {
+    let verification = verify_sidecars(library, data)?;
+    let required = ZynxConfigs::instance().verify_libraries;
+
+    if required && matches!(verification, Verification::Unverified) {
+        bail!("no .sha256 or .sig file, refusing to inject unverified library");
+    }
+
+    debug!("{}: {verification:?}", library.display());
+
+    Ok(verification)
+}
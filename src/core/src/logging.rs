@@ -0,0 +1,180 @@
+mod file;
+
+use crate::android::root;
+use crate::logging::file::{DUMP_FILE, FileLog};
+use crate::update;
+use anyhow::{Context, Result, bail};
+use log::{LevelFilter, Log, Metadata, Record};
+use nix::sys::signal;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use once_cell::sync::Lazy;
+use std::time::{Duration, Instant};
+use std::{fs, thread};
+use tokio::signal::unix;
+use tokio::signal::unix::SignalKind;
+use tracing::level_filters::LevelFilter as TracingLevelFilter;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_log::LogTracer;
+use tracing_subscriber::Layer;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use zynx_misc::ext::ResultExt;
+
+pub const LOGS_DIR: &str = "/data/adb/zynx/logs";
+
+/// Daily JSON log files kept, older ones are removed on rotation
+const MAX_LOG_FILES: usize = 7;
+
+const DUMP_TIMEOUT: Duration = Duration::from_secs(2);
+
+static FILE_LOG: Lazy<FileLog> = Lazy::new(FileLog::default);
+
+/// Forwards to logcat (or stderr off-device), keeping a copy for [`FileLog`]. Records of other
+/// crates also go into tracing, so they show up in the JSON logs.
+struct ZynxLogger {
+    inner: Box<dyn Log>,
+    tracer: LogTracer,
+}
+
+/// Whether `record` was emitted by a tracing event of this crate, which already reached the
+/// subscriber.
+fn from_tracing(record: &Record) -> bool {
+    record
+        .module_path()
+        .is_some_and(|path| path == "zynx" || path.starts_with("zynx::"))
+}
+
+impl Log for ZynxLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+            FILE_LOG.append(record);
+
+            if !from_tracing(record) {
+                self.tracer.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Events go through `log` to logcat (or stderr off-device) and to the in-memory buffer of the
+/// log file, spans only show up in the JSON logs. Records logged through `log` by dependencies
+/// are bridged into tracing.
+pub fn init() {
+    let (inner, level): (Box<dyn Log>, _) = if root::module_dir().is_some() {
+        let level = if cfg!(debug_assertions) {
+            LevelFilter::Trace
+        } else {
+            LevelFilter::Info
+        };
+        let logger = android_logger::AndroidLogger::new(
+            android_logger::Config::default()
+                .with_max_level(level)
+                .with_tag("zynx::core"),
+        );
+
+        (Box::new(logger), level)
+    } else {
+        let logger = env_logger::Builder::from_default_env().build();
+        let level = logger.filter();
+
+        (Box::new(logger), level)
+    };
+
+    let logger = ZynxLogger {
+        inner,
+        tracer: LogTracer::new(),
+    };
+
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Also write logs into the rotated log file, 0 `max_size` for memory only.
+pub fn init_file(max_size: u64) -> Result<()> {
+    if max_size == 0 {
+        return Ok(());
+    }
+
+    FILE_LOG.enable(max_size)
+}
+
+/// Dump the in-memory logs on SIGUSR2, see [`print_logs`].
+pub fn spawn_dump_handler() -> Result<()> {
+    let mut sigusr2 = unix::signal(SignalKind::user_defined2())?;
+
+    tokio::spawn(async move {
+        while sigusr2.recv().await.is_some() {
+            FILE_LOG.dump().log_if_error();
+        }
+    });
+
+    Ok(())
+}
+
+/// Have the running daemon dump its in-memory logs, and print them.
+pub fn print_logs() -> Result<()> {
+    let daemon = update::find_daemon()?.context("daemon is not running")?;
+    let modified = || {
+        fs::metadata(DUMP_FILE)
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
+    let before = modified();
+
+    signal::kill(Pid::from_raw(daemon.pid), Signal::SIGUSR2)?;
+
+    let start = Instant::now();
+    while modified() == before {
+        if start.elapsed() > DUMP_TIMEOUT {
+            bail!(
+                "daemon {} didn't dump its logs in {DUMP_TIMEOUT:?}",
+                daemon.pid
+            );
+        }
+
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    print!("{}", fs::read_to_string(DUMP_FILE)?);
+
+    Ok(())
+}
+
+/// Write events as JSON lines into [`LOGS_DIR`], with the spans (pid, uid, package of an
+/// injection) they happened in and the timing of each span on close.
+pub fn init_json() -> Result<()> {
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("zynx")
+        .filename_suffix("json")
+        .max_log_files(MAX_LOG_FILES)
+        .build(LOGS_DIR)?;
+
+    let layer = tracing_subscriber::fmt::layer()
+        .json()
+        .with_writer(appender)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_thread_ids(true)
+        .with_filter(if cfg!(debug_assertions) {
+            TracingLevelFilter::TRACE
+        } else {
+            TracingLevelFilter::INFO
+        });
+
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use crate::logging::LOGS_DIR;
+use anyhow::Result;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use log::Record;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io, thread};
+use tracing::warn;
+
+pub const LOG_FILE: &str = "/data/adb/zynx/logs/zynx.log";
+pub const DUMP_FILE: &str = "/data/adb/zynx/logs/dump.log";
+
+/// Recent lines kept in memory, also those logged before the file is opened
+const BUFFER_CAPACITY: usize = 2048;
+
+/// Compressed logs kept besides the current one, as `zynx.log.<n>.gz`
+const ROTATED_FILES: usize = 3;
+
+#[derive(Default)]
+struct State {
+    buffer: VecDeque<String>,
+    /// Lines to the [`Writer`], dropped if it falls behind by a whole buffer
+    writer: Option<SyncSender<String>>,
+}
+
+/// Log lines of the daemon, in a ring buffer and, once enabled, in [`LOG_FILE`] rotated by
+/// size. Nothing in here may log, it runs inside the logger, and the files are only touched by
+/// the [`Writer`] thread so logging never waits for them.
+#[derive(Default)]
+pub struct FileLog {
+    state: Mutex<State>,
+}
+
+fn rotated(index: usize) -> PathBuf {
+    PathBuf::from(format!("{LOG_FILE}.{index}.gz"))
+}
+
+fn compress(source: &Path, target: &Path) -> Result<()> {
+    let mut reader = BufReader::new(File::open(source)?);
+    let mut encoder = GzEncoder::new(File::create(target)?, Compression::default());
+
+    io::copy(&mut reader, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(source)?;
+
+    Ok(())
+}
+
+fn open_log() -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(LOG_FILE)
+}
+
+/// Owner of [`LOG_FILE`], writing the lines it receives and rotating the file once it reaches
+/// `max_size`.
+struct Writer {
+    file: File,
+    written: u64,
+    max_size: u64,
+    /// Compression of the last rotated log, finished before the next rotation renames it
+    compressor: Option<JoinHandle<()>>,
+}
+
+impl Writer {
+    /// Write lines until the [`FileLog`] is dropped, or the file can't be rotated.
+    fn run(mut self, lines: Receiver<String>) {
+        for line in lines {
+            if self.file.write_all(line.as_bytes()).is_ok() {
+                self.written += line.len() as u64;
+            }
+
+            if self.written >= self.max_size {
+                match self.rotate() {
+                    Ok(file) => self.file = file,
+                    Err(_) => return,
+                }
+
+                self.written = 0;
+            }
+        }
+    }
+
+    /// Move the current log aside, compressed in the background, and open a new one.
+    fn rotate(&mut self) -> io::Result<File> {
+        if let Some(compressor) = self.compressor.take() {
+            let _ = compressor.join();
+        }
+
+        for index in (1..ROTATED_FILES).rev() {
+            let _ = fs::rename(rotated(index), rotated(index + 1));
+        }
+
+        let pending = PathBuf::from(format!("{LOG_FILE}.0"));
+
+        fs::rename(LOG_FILE, &pending)?;
+
+        self.compressor = Some(thread::spawn(move || {
+            if let Err(err) = compress(&pending, &rotated(1)) {
+                warn!("failed to compress {}: {err:?}", pending.display());
+            }
+        }));
+
+        open_log()
+    }
+}
+
+impl FileLog {
+    pub fn append(&self, record: &Record) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format!(
+            "{}.{:03} {:<5} {}: {}\n",
+            now.as_secs(),
+            now.subsec_millis(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        let mut state = self.state.lock();
+
+        if let Some(writer) = &state.writer {
+            let _ = writer.try_send(line.clone());
+        }
+
+        if state.buffer.len() >= BUFFER_CAPACITY {
+            state.buffer.pop_front();
+        }
+
+        state.buffer.push_back(line);
+    }
+
+    /// Start writing to [`LOG_FILE`], beginning with the lines logged so far.
+    pub fn enable(&self, max_size: u64) -> Result<()> {
+        fs::create_dir_all(LOGS_DIR)?;
+
+        let file = open_log()?;
+        let writer = Writer {
+            written: file.metadata()?.len(),
+            file,
+            max_size,
+            compressor: None,
+        };
+
+        let (sender, receiver) = mpsc::sync_channel(BUFFER_CAPACITY);
+        let mut state = self.state.lock();
+
+        for line in &state.buffer {
+            let _ = sender.try_send(line.clone());
+        }
+
+        thread::spawn(move || writer.run(receiver));
+
+        state.writer = Some(sender);
+
+        Ok(())
+    }
+
+    /// Write the in-memory lines to [`DUMP_FILE`].
+    pub fn dump(&self) -> Result<()> {
+        let content: String = self
+            .state
+            .lock()
+            .buffer
+            .iter()
+            .map(String::as_str)
+            .collect();
+        let path = Path::new(DUMP_FILE);
+        let temp = path.with_extension("tmp");
+
+        fs::create_dir_all(LOGS_DIR)?;
+        fs::write(&temp, content)?;
+        fs::rename(&temp, path)?;
+
+        Ok(())
+    }
+}
@@ -1,38 +1,32 @@
 mod android;
+mod atrace;
 mod binary;
 mod cli;
 mod config;
 mod daemon;
+mod events;
 mod injector;
+mod integrity;
+mod logging;
+mod metrics;
 mod misc;
 mod monitor;
+mod quarantine;
+mod scaffold;
+mod sepolicy;
+mod status;
+mod update;
 
 use crate::cli::{Cli, Command};
 use crate::config::ZynxConfigs;
 use crate::misc::inject_panic_handler;
 use anyhow::Result;
-use log::LevelFilter;
-use std::env;
+use std::path::Path;
 use tokio::runtime::Builder;
-
-fn init_logger() {
-    if env::var("MODDIR").is_ok() {
-        android_logger::init_once(
-            android_logger::Config::default()
-                .with_max_level(if cfg!(debug_assertions) {
-                    LevelFilter::Trace
-                } else {
-                    LevelFilter::Info
-                })
-                .with_tag("zynx::core"),
-        );
-    } else {
-        env_logger::init();
-    }
-}
+use zynx_misc::ext::ResultExt;
 
 fn main() -> Result<()> {
-    init_logger();
+    logging::init();
 
     let cli = Cli::parse_args();
 
@@ -40,6 +34,50 @@ fn main() -> Result<()> {
         Some(Command::Daemon) => {
             daemon::launch_daemon()?;
         }
+        Some(Command::Metrics) => {
+            metrics::print_metrics()?;
+        }
+        Some(Command::Status) => {
+            status::print_status()?;
+        }
+        Some(Command::Logs) => {
+            logging::print_logs()?;
+        }
+        Some(Command::Events { filter }) => {
+            events::print_events(filter.as_deref())?;
+        }
+        Some(Command::Quarantine { release }) => {
+            quarantine::manage_quarantine(release.as_deref())?;
+        }
+        Some(Command::Debuggable { add, remove }) => {
+            injector::manage_debuggable(add.as_deref(), remove.as_deref())?;
+        }
+        Some(Command::Sepolicy { pid, apply }) => {
+            sepolicy::print_rules(pid, apply)?;
+        }
+        Some(Command::MigrateLiteloader { dry_run }) => {
+            injector::migrate_layout(dry_run)?;
+        }
+        Some(Command::Update { binary, restart }) => {
+            update::run_update(&binary, restart)?;
+        }
+        Some(Command::NewModule { name, path }) => {
+            scaffold::new_module(&name, path.as_deref().unwrap_or(Path::new(".")))?;
+        }
+        Some(Command::Audit { pid }) => {
+            injector::audit(pid)?;
+        }
+        Some(Command::Doctor) => {
+            ZynxConfigs::init(&cli.configs)?;
+            injector::doctor()?;
+        }
+        Some(Command::Replay { trace, root }) => {
+            ZynxConfigs::init(&cli.configs)?;
+            Builder::new_multi_thread()
+                .enable_all()
+                .build()?
+                .block_on(injector::replay(&trace, root.as_deref()))?;
+        }
         Some(Command::AttachZygote { pid }) => {
             ZynxConfigs::init(&cli.configs)?;
             Builder::new_multi_thread()
@@ -50,9 +88,53 @@ fn main() -> Result<()> {
                     injector::attach_zygote(pid).await
                 })?;
         }
+        #[cfg(feature = "zygisk")]
+        Some(Command::ZygiskCompanion { library }) => {
+            injector::zygisk_companion(&library)?;
+        }
+        #[cfg(feature = "debug-shell")]
+        Some(Command::DebugShell { pid }) => {
+            injector::debug_shell(pid)?;
+        }
+        #[cfg(feature = "self-test")]
+        Some(Command::SelfTest { module, data }) => {
+            ZynxConfigs::init(&cli.configs)?;
+            Builder::new_multi_thread()
+                .enable_all()
+                .build()?
+                .block_on(injector::self_test(
+                    module.map(|library| injector::TestModule { library, data }),
+                ))?;
+        }
+        #[cfg(feature = "self-test")]
+        Some(Command::FakeZygote { module, data }) => {
+            injector::fake_zygote(module.map(|library| injector::TestModule { library, data }))?;
+        }
+        #[cfg(feature = "bench")]
+        Some(Command::Bench {
+            filter,
+            save_baseline,
+            baseline,
+        }) => {
+            let baseline = match (save_baseline, baseline) {
+                (Some(name), _) => Some(injector::Baseline::Save(name)),
+                (None, Some(name)) => Some(injector::Baseline::Compare(name)),
+                (None, None) => None,
+            };
+
+            ZynxConfigs::init(&cli.configs)?;
+            injector::bench(filter.as_deref(), baseline)?;
+        }
         None => {
             ZynxConfigs::init(&cli.configs)?;
             daemon::daemonize_if_needed()?;
+
+            logging::init_file(ZynxConfigs::instance().log_file_size).log_if_error();
+
+            if ZynxConfigs::instance().json_logs {
+                logging::init_json().log_if_error();
+            }
+
             Builder::new_multi_thread()
                 .enable_all()
                 .build()?
@@ -0,0 +1,352 @@
+use crate::events::InjectionOutcome;
+use crate::injector::{Cap, ProviderKind};
+use crate::monitor::map_stats::MapStat;
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time;
+use tracing::debug;
+use zynx_misc::ext::ResultExt;
+
+pub const METRICS_FILE: &str = "/data/adb/zynx/metrics";
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+static INSTANCE: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+/// Daemon counters, periodically dumped to [`METRICS_FILE`] in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    forks_observed: AtomicU64,
+    injections_attempted: AtomicU64,
+    injections_succeeded: AtomicU64,
+    injections_failed: AtomicU64,
+    injections_vanished: AtomicU64,
+    injection_latency_us: AtomicU64,
+    ebpf_reloads: AtomicU64,
+    ebpf_messages_dropped: AtomicU64,
+    libraries_truncated: AtomicU64,
+    /// Unix time of the last injection hitting a cap, `0` if none did
+    cap_last_hit: AtomicU64,
+    /// Forked processes waiting for a tracer thread
+    queue_depth: AtomicU64,
+    queue_depth_max: AtomicU64,
+    injections_skipped: AtomicU64,
+    policy_denials: Mutex<BTreeMap<String, u64>>,
+    cap_hits: Mutex<BTreeMap<String, u64>>,
+    ebpf_maps: Mutex<Vec<MapStat>>,
+}
+
+impl Metrics {
+    pub fn instance() -> &'static Self {
+        &INSTANCE
+    }
+
+    pub fn on_fork(&self) {
+        self.forks_observed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn on_inject_start(&self) {
+        self.injections_attempted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn on_inject_finish(&self, outcome: &InjectionOutcome, latency: Duration) {
+        match outcome {
+            InjectionOutcome::Injected => {
+                self.injections_succeeded.fetch_add(1, Ordering::Relaxed);
+                self.injection_latency_us
+                    .fetch_add(latency.as_micros() as _, Ordering::Relaxed);
+            }
+            InjectionOutcome::Vanished => {
+                self.injections_vanished.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {
+                self.injections_failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn on_queued(&self) {
+        let depth = self.queue_depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.queue_depth_max.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    pub fn on_dequeued(&self) {
+        self.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn on_inject_skipped(&self) {
+        self.injections_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn on_ebpf_reload(&self) {
+        self.ebpf_reloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn on_messages_dropped(&self, total: u64) {
+        self.ebpf_messages_dropped.store(total, Ordering::Relaxed);
+    }
+
+    pub fn on_map_stats(&self, stats: &[MapStat]) {
+        *self.ebpf_maps.lock() = stats.to_vec();
+    }
+
+    pub fn on_cap_hit(&self, cap: Cap, dropped: usize) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        self.libraries_truncated
+            .fetch_add(dropped as _, Ordering::Relaxed);
+        self.cap_last_hit.store(now, Ordering::Relaxed);
+        *self.cap_hits.lock().entry(cap.to_string()).or_default() += 1;
+    }
+
+    pub fn on_policy_denied(&self, provider: ProviderKind) {
+        *self
+            .policy_denials
+            .lock()
+            .entry(format!("{provider:?}"))
+            .or_default() += 1;
+    }
+
+    fn render(&self) -> String {
+        let mut output = String::new();
+
+        let mut counter = |name: &str, help: &str, value: u64| {
+            let _ = writeln!(output, "# HELP {name} {help}");
+            let _ = writeln!(output, "# TYPE {name} counter");
+            let _ = writeln!(output, "{name} {value}");
+        };
+
+        let succeeded = self.injections_succeeded.load(Ordering::Relaxed);
+        let latency_us = self.injection_latency_us.load(Ordering::Relaxed);
+
+        counter(
+            "zynx_forks_observed_total",
+            "Zygote forks observed",
+            self.forks_observed.load(Ordering::Relaxed),
+        );
+        counter(
+            "zynx_injections_attempted_total",
+            "Injections attempted",
+            self.injections_attempted.load(Ordering::Relaxed),
+        );
+        counter(
+            "zynx_injections_succeeded_total",
+            "Injections succeeded",
+            succeeded,
+        );
+        counter(
+            "zynx_injections_failed_total",
+            "Injections failed",
+            self.injections_failed.load(Ordering::Relaxed),
+        );
+        counter(
+            "zynx_injections_vanished_total",
+            "Injections aborted because the target process died",
+            self.injections_vanished.load(Ordering::Relaxed),
+        );
+        counter(
+            "zynx_injections_skipped_total",
+            "Injections skipped because the process waited too long in the queue",
+            self.injections_skipped.load(Ordering::Relaxed),
+        );
+        counter(
+            "zynx_ebpf_reloads_total",
+            "eBPF program reloads triggered by the watchdog",
+            self.ebpf_reloads.load(Ordering::Relaxed),
+        );
+        counter(
+            "zynx_ebpf_messages_dropped_total",
+            "eBPF messages dropped because the channel was full",
+            self.ebpf_messages_dropped.load(Ordering::Relaxed),
+        );
+
+        counter(
+            "zynx_libraries_truncated_total",
+            "Libraries not injected because a per-process cap was hit",
+            self.libraries_truncated.load(Ordering::Relaxed),
+        );
+
+        let _ = writeln!(
+            output,
+            "# HELP zynx_policy_denials_total Policy denials per provider"
+        );
+        let _ = writeln!(output, "# TYPE zynx_policy_denials_total counter");
+
+        for (provider, count) in self.policy_denials.lock().iter() {
+            let _ = writeln!(
+                output,
+                "zynx_policy_denials_total{{provider=\"{provider}\"}} {count}"
+            );
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP zynx_cap_hits_total Injections hitting a per-process cap, per cap"
+        );
+        let _ = writeln!(output, "# TYPE zynx_cap_hits_total counter");
+
+        for (cap, count) in self.cap_hits.lock().iter() {
+            let _ = writeln!(output, "zynx_cap_hits_total{{cap=\"{cap}\"}} {count}");
+        }
+
+        let ebpf_maps = self.ebpf_maps.lock();
+
+        let _ = writeln!(
+            output,
+            "# HELP zynx_ebpf_map_entries Tasks tracked in each eBPF map"
+        );
+        let _ = writeln!(output, "# TYPE zynx_ebpf_map_entries gauge");
+
+        for stat in ebpf_maps.iter() {
+            let _ = writeln!(
+                output,
+                "zynx_ebpf_map_entries{{map=\"{}\"}} {}",
+                stat.map.name(),
+                stat.entries
+            );
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP zynx_ebpf_map_capacity Capacity of each eBPF map tracking tasks"
+        );
+        let _ = writeln!(output, "# TYPE zynx_ebpf_map_capacity gauge");
+
+        for stat in ebpf_maps.iter() {
+            let _ = writeln!(
+                output,
+                "zynx_ebpf_map_capacity{{map=\"{}\"}} {}",
+                stat.map.name(),
+                stat.capacity
+            );
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP zynx_ebpf_map_insert_failures_total Tasks not tracked by each eBPF map, mostly because it was full"
+        );
+        let _ = writeln!(output, "# TYPE zynx_ebpf_map_insert_failures_total counter");
+
+        for stat in ebpf_maps.iter() {
+            let _ = writeln!(
+                output,
+                "zynx_ebpf_map_insert_failures_total{{map=\"{}\"}} {}",
+                stat.map.name(),
+                stat.failures
+            );
+        }
+
+        drop(ebpf_maps);
+
+        let _ = writeln!(
+            output,
+            "# HELP zynx_cap_last_hit_timestamp_seconds Last time an injection hit a cap"
+        );
+        let _ = writeln!(output, "# TYPE zynx_cap_last_hit_timestamp_seconds gauge");
+        let _ = writeln!(
+            output,
+            "zynx_cap_last_hit_timestamp_seconds {}",
+            self.cap_last_hit.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            output,
+            "# HELP zynx_injection_queue_depth Forked processes waiting for a tracer thread"
+        );
+        let _ = writeln!(output, "# TYPE zynx_injection_queue_depth gauge");
+        let _ = writeln!(
+            output,
+            "zynx_injection_queue_depth {}",
+            self.queue_depth.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            output,
+            "# HELP zynx_injection_queue_depth_max Most forked processes waiting at once"
+        );
+        let _ = writeln!(output, "# TYPE zynx_injection_queue_depth_max gauge");
+        let _ = writeln!(
+            output,
+            "zynx_injection_queue_depth_max {}",
+            self.queue_depth_max.load(Ordering::Relaxed)
+        );
+
+        let average = if succeeded == 0 {
+            0.0
+        } else {
+            latency_us as f64 / succeeded as f64 / 1e6
+        };
+
+        let _ = writeln!(
+            output,
+            "# HELP zynx_injection_latency_seconds_avg Average latency of successful injections"
+        );
+        let _ = writeln!(output, "# TYPE zynx_injection_latency_seconds_avg gauge");
+        let _ = writeln!(output, "zynx_injection_latency_seconds_avg {average:.6}");
+
+        output
+    }
+
+    fn flush(&self) -> Result<()> {
+        let path = Path::new(METRICS_FILE);
+        let temp = path.with_extension("tmp");
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&temp, self.render())?;
+        fs::rename(&temp, path)?;
+
+        debug!("metrics flushed to {METRICS_FILE}");
+
+        Ok(())
+    }
+
+    /// Spawn the background task dumping metrics to [`METRICS_FILE`].
+    pub fn spawn_writer() {
+        tokio::spawn(async {
+            let mut interval = time::interval(FLUSH_INTERVAL);
+
+            loop {
+                interval.tick().await;
+                Self::instance().flush().log_if_error();
+            }
+        });
+    }
+}
+
+/// Pretty-print the metrics file written by a running daemon.
+pub fn print_metrics() -> Result<()> {
+    let content = fs::read_to_string(METRICS_FILE).context(format!(
+        "failed to read {METRICS_FILE}, is the daemon running?"
+    ))?;
+
+    let samples: Vec<_> = content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.rsplit_once(' '))
+        .collect();
+
+    let width = samples
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+
+    for (name, value) in samples {
+        println!("{name:<width$}  {value}");
+    }
+
+    Ok(())
+}
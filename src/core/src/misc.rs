@@ -1,9 +1,15 @@
-use anyhow::Result;
+use crate::injector::Shutdown;
+use anyhow::{Context, Result};
 use memfd::{FileSeal, Memfd, MemfdOptions};
 use nix::libc;
+use nix::sys::utsname;
 use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::OnceLock;
 use std::{panic, slice};
 
+static FILES_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
 pub fn create_sealed_memfd(name: &str, data: &[u8]) -> Result<Memfd> {
     let fd = MemfdOptions::default().allow_sealing(true).create(name)?;
 
@@ -22,10 +28,28 @@ pub fn create_sealed_memfd(name: &str, data: &[u8]) -> Result<Memfd> {
     Ok(fd)
 }
 
+/// Read and write the files of zynx and the root manager below `root` instead of `/`, so that
+/// `replay` can run off the device against a copy of them. Must be set before their first use.
+pub fn set_files_root(root: PathBuf) {
+    FILES_ROOT.set(root).ok();
+}
+
+/// `path` below the root set by [`set_files_root`], or as is.
+pub fn rooted(path: &str) -> PathBuf {
+    match FILES_ROOT.get() {
+        Some(root) => root.join(path.trim_start_matches('/')),
+        None => path.into(),
+    }
+}
+
 pub fn inject_panic_handler() {
     let original = panic::take_hook();
 
     panic::set_hook(Box::new(move |info| {
+        // don't leave stopped or patched processes behind: this also requests shutdown, so
+        // the main loop stops handling events and the daemon exits once tracers detached
+        Shutdown::instance().release_all();
+
         // dump tombstone on panic
         // https://cs.android.com/android/platform/superproject/+/android14-release:bionic/libc/platform/bionic/reserved_signals.h;l=41
         unsafe {
@@ -43,3 +67,16 @@ pub fn as_byte_slice<T: ?Sized>(value: &T) -> &[u8] {
 pub fn as_byte_slice_mut<T: ?Sized>(value: &mut T) -> &mut [u8] {
     unsafe { slice::from_raw_parts_mut(value as *mut _ as *mut u8, size_of_val(value)) }
 }
+
+fn parse_release(release: &str) -> Option<(u32, u32)> {
+    let mut numbers = release.split(['.', '-']).map(|it| it.parse().ok());
+    Some((numbers.next()??, numbers.next()??))
+}
+
+/// `(major, minor)` of the running kernel.
+pub fn kernel_version() -> Result<(u32, u32)> {
+    let uname = utsname::uname()?;
+    let release = uname.release().to_string_lossy();
+
+    parse_release(&release).context(format!("malformed kernel release {release:?}"))
+}
@@ -1,44 +1,169 @@
-use anyhow::{Context, Result, anyhow};
-use aya::maps::{Array, HashMap, Map, MapData, RingBuf};
+use crate::cli::{InjectorBackend, MonitorBackend};
+use crate::config::ZynxConfigs;
+use crate::injector::PAGE_SIZE;
+use crate::monitor::features::{KernelFeatures, RINGBUF_KERNEL};
+use crate::monitor::layout::LayoutGlobal;
+use crate::monitor::map_stats::MapStat;
+use crate::monitor::proc_connector::ProcConnector;
+use crate::monitor::specialize_probe::SpecializeProbe;
+use crate::monitor::sys_enter::SysEnterAttach;
+use crate::monitor::uid_filter::UidFilter;
+use crate::monitor::watchdog::Watchdog;
+use crate::status::Status;
+use anyhow::{Context, Result, anyhow, bail};
+use aya::maps::{HashMap, Map, MapData, PerCpuArray, RingBuf};
 use aya::programs::TracePoint;
-use aya::{Ebpf, include_bytes_aligned};
+use aya::{Ebpf, EbpfLoader, Pod, include_bytes_aligned};
 use aya_log::EbpfLogger;
-use log::{error, info, warn};
 use nix::libc::RLIM_INFINITY;
 use nix::sys::resource;
 use nix::sys::resource::Resource;
+use nix::sys::signal;
+use nix::sys::signal::Signal;
 use nix::unistd::Pid;
 use parking_lot::Mutex;
 use std::ffi::CStr;
-use std::mem;
 use std::sync::OnceLock;
+use std::{mem, process};
 use tokio::io::Interest;
 use tokio::io::unix::AsyncFd;
 use tokio::sync::Mutex as AsyncMutex;
 use tokio::task;
-use zynx_ebpf_shared::Message as EbpfMessage;
+use tracing::{error, info, warn};
+use zynx_ebpf_shared::{EntryRegs, Message as EbpfMessage, ObserveOnly, SignalMode, TrackedMap};
+use zynx_misc::ext::ResultExt;
+
+pub mod features;
+pub mod layout;
+pub mod map_stats;
+mod proc_connector;
+pub mod specialize_probe;
+pub mod sys_enter;
+pub mod uid_filter;
+mod watchdog;
 
 static INSTANCE: OnceLock<Monitor> = OnceLock::new();
 
 pub struct Config {
     pub target_paths: Vec<String>,
     pub target_names: Vec<String>,
+    /// Size of the message channel in bytes, rounded up to a power of two multiple of the
+    /// page size
+    pub channel_size: u32,
+    /// Depth below init of the processes matched by path and name, at least 1
+    pub service_depth: u32,
+    pub map_sizes: MapSizes,
+    pub backend: MonitorBackend,
+    /// Skip zygote forks of apps no policy provider targets, see [`UidFilter`]
+    pub uid_filter: bool,
+    /// Stop embryos at `SpecializeCommon` instead of after fork, see [`SpecializeProbe`]
+    pub specialize_probe: bool,
+    /// Fail instead of stopping embryos after fork if the probe can't be attached, the mem
+    /// injector can't handle them
+    pub require_specialize_probe: bool,
+    pub observe_only: ObserveOnly,
+}
+
+impl Config {
+    /// Settings of [`ZynxConfigs`], matching processes by `target_paths` and `target_names`.
+    pub fn new(target_paths: Vec<String>, target_names: &[&str]) -> Self {
+        let configs = ZynxConfigs::instance();
+
+        Self {
+            target_paths,
+            target_names: target_names.iter().map(|name| name.to_string()).collect(),
+            channel_size: configs.channel_size,
+            service_depth: configs.service_depth,
+            map_sizes: MapSizes {
+                targets: configs.max_targets,
+                tasks: configs.max_tasks,
+            },
+            backend: configs.monitor,
+            uid_filter: configs.uid_filter,
+            specialize_probe: configs.specialize_uprobe,
+            require_specialize_probe: matches!(configs.injector, InjectorBackend::Mem),
+            observe_only: configs.observe_only.into(),
+        }
+    }
 }
 
+/// Capacities of the eBPF maps, applied at load time
+#[derive(Copy, Clone, Debug)]
+pub struct MapSizes {
+    /// Target paths and names, each
+    pub targets: u32,
+    /// Tasks tracked by the programs, see [`TrackedMap`]
+    pub tasks: u32,
+}
+
+const STATUS_SECTION: &str = "monitor";
+
+/// [`SignalMode`] as a global of the eBPF programs.
+#[repr(transparent)]
+#[derive(Copy, Clone)]
+struct SignalModeGlobal(SignalMode);
+
+unsafe impl Pod for SignalModeGlobal {}
+
+/// [`ObserveOnly`] as a global of the eBPF programs.
+#[repr(transparent)]
+#[derive(Copy, Clone)]
+struct ObserveOnlyGlobal(ObserveOnly);
+
+unsafe impl Pod for ObserveOnlyGlobal {}
+
 pub struct Monitor {
+    backend: Backend,
+    features: KernelFeatures,
+    observe_only: ObserveOnly,
+}
+
+// there is a single monitor, boxing a variant saves nothing
+#[allow(clippy::large_enum_variant)]
+enum Backend {
+    Ebpf(EbpfMonitor),
+    /// Used when the kernel lacks tracepoints of the eBPF programs
+    ProcConnector(ProcConnector),
+}
+
+struct EbpfMonitor {
     channel: AsyncMutex<AsyncFd<RingBuf<MapData>>>,
-    zygote_info: Mutex<Array<MapData, i32>>,
-    _ebpf: Ebpf,
+    zygote_pids: Mutex<HashMap<MapData, i32, u8>>,
+    dropped_messages: Mutex<PerCpuArray<MapData, u64>>,
+    map_failures: Mutex<PerCpuArray<MapData, u64>>,
+    map_sizes: MapSizes,
+    ebpf: Mutex<Ebpf>,
+    /// Absent if the kernel lacks the tracepoint probed by the watchdog
+    watchdog: Option<Watchdog>,
+    sys_enter: SysEnterAttach,
+    /// Absent if disabled, or `nativeForkAndSpecialize` can't be probed
+    uid_filter: Option<UidFilter>,
+    /// Absent if disabled, or `SpecializeCommon` can't be probed
+    specialize_probe: Option<SpecializeProbe>,
 }
 
 #[derive(Debug)]
 pub enum Message {
     PathMatches(Pid, String),
     NameMatches(Pid, String),
-    ZygoteFork(Pid),
+    ZygoteFork(Pid, Pid),
+    ZygoteSpecialize(Pid, Pid),
     ZygoteCrashed(Pid),
 }
 
+impl Message {
+    /// Process stopped by the monitor when it emitted this message, none if only observed.
+    pub fn stopped_pid(&self, observe_only: ObserveOnly) -> Option<Pid> {
+        match self {
+            Message::PathMatches(pid, _) if !observe_only.services => Some(*pid),
+            Message::ZygoteFork(_, pid) if !observe_only.embryos => Some(*pid),
+            Message::ZygoteSpecialize(_, pid) if !observe_only.embryos => Some(*pid),
+            Message::NameMatches(pid, _) => Some(*pid),
+            _ => None,
+        }
+    }
+}
+
 fn parse_string(data: &[u8]) -> String {
     let cstr = CStr::from_bytes_until_nul(data).expect("failed to parse string");
     cstr.to_string_lossy().to_string()
@@ -53,8 +178,14 @@ impl From<EbpfMessage> for Message {
             EbpfMessage::NameMatches(pid, name) => {
                 Message::NameMatches(Pid::from_raw(pid), parse_string(&name))
             }
-            EbpfMessage::ZygoteFork(pid) => Message::ZygoteFork(Pid::from_raw(pid)),
+            EbpfMessage::ZygoteFork(zygote, pid) => {
+                Message::ZygoteFork(Pid::from_raw(zygote), Pid::from_raw(pid))
+            }
+            EbpfMessage::ZygoteSpecialize(zygote, pid) => {
+                Message::ZygoteSpecialize(Pid::from_raw(zygote), Pid::from_raw(pid))
+            }
             EbpfMessage::ZygoteCrashed(pid) => Message::ZygoteCrashed(Pid::from_raw(pid)),
+            EbpfMessage::Heartbeat(_) => unreachable!("heartbeats are consumed by the monitor"),
         }
     }
 }
@@ -68,14 +199,168 @@ where
         .and_then(|map| map.try_into().map_err(Into::into))
 }
 
-impl Monitor {
-    fn new(config: Config) -> Result<Self> {
+/// Attach all programs, `sys_enter` as `sys_enter` or the cheapest variant if `None`.
+fn attach_programs(
+    ebpf: &mut Ebpf,
+    features: &KernelFeatures,
+    sys_enter: Option<SysEnterAttach>,
+) -> Result<SysEnterAttach> {
+    for (name, program) in ebpf.programs_mut() {
+        let parts: Vec<_> = name.split("__").collect();
+
+        if parts[0] == "tracepoint" && !SysEnterAttach::is_variant(name) {
+            let program: &mut TracePoint = program.try_into()?;
+            let (category, name) = (parts[1], parts[2]);
+
+            if !features.can_attach(category, name) {
+                warn!("skip tracepoint missing in this kernel: {category}/{name}");
+                continue;
+            }
+
+            info!("attaching tracepoint: {category}/{name}");
+
+            program.load()?;
+            program.attach(category, name)?;
+        }
+    }
+
+    match sys_enter {
+        Some(attach) => attach.attach(ebpf).map(|_| attach),
+        None => SysEnterAttach::attach_best(ebpf),
+    }
+}
+
+/// Load the programs with the globals probed for this kernel, attaching none of them.
+fn load_programs(
+    features: &KernelFeatures,
+    channel_size: u32,
+    map_sizes: MapSizes,
+    service_depth: u32,
+    observe_only: ObserveOnly,
+) -> Result<Ebpf> {
+    let daemon_pid = process::id() as i32;
+    let layout = LayoutGlobal(layout::probe()?);
+    let signal_mode = SignalModeGlobal(features.signal_mode);
+    let observe_only = ObserveOnlyGlobal(observe_only);
+    let mut loader = EbpfLoader::new();
+
+    loader
+        .set_global("DAEMON_PID", &daemon_pid, true)
+        .set_global("KERNEL_LAYOUT", &layout, true)
+        .set_global("SIGNAL_MODE", &signal_mode, true)
+        .set_global("SERVICE_DEPTH", &service_depth, true)
+        .set_global("OBSERVE_ONLY", &observe_only, true)
+        .set_max_entries("MESSAGE_CHANNEL", channel_size)
+        .set_max_entries("TARGET_PATHS", map_sizes.targets)
+        .set_max_entries("TARGET_NAMES", map_sizes.targets);
+
+    for map in TrackedMap::ALL {
+        loader.set_max_entries(map.name(), map_sizes.tasks);
+    }
+
+    Ok(loader.load(include_bytes_aligned!(concat!(
+        env!("OUT_DIR"),
+        "/zynx-ebpf"
+    )))?)
+}
+
+/// Load the programs for benchmarks of the [`SysEnterAttach`] variants.
+#[cfg(feature = "bench")]
+pub fn load_for_bench() -> Result<Ebpf> {
+    resource::setrlimit(Resource::RLIMIT_MEMLOCK, RLIM_INFINITY, RLIM_INFINITY)?;
+
+    let features = KernelFeatures::probe(layout::tracefs());
+
+    let map_sizes = MapSizes {
+        targets: 0x100,
+        tasks: 0x1000,
+    };
+
+    load_programs(
+        &features,
+        *PAGE_SIZE as _,
+        map_sizes,
+        1,
+        ObserveOnly::default(),
+    )
+}
+
+/// Load the tracepoint programs without attaching them, for the verifier to check them
+/// against this kernel. Returns the number of programs loaded.
+pub fn verify_programs(
+    features: &KernelFeatures,
+    channel_size: u32,
+    map_sizes: MapSizes,
+) -> Result<usize> {
+    resource::setrlimit(Resource::RLIMIT_MEMLOCK, RLIM_INFINITY, RLIM_INFINITY)?;
+
+    let mut ebpf = load_programs(features, channel_size, map_sizes, 1, ObserveOnly::default())?;
+    let mut loaded = 0;
+
+    for (name, program) in ebpf.programs_mut() {
+        let parts: Vec<_> = name.split("__").collect();
+
+        if parts[0] != "tracepoint"
+            || SysEnterAttach::is_variant(name)
+            || !features.can_attach(parts[1], parts[2])
+        {
+            continue;
+        }
+
+        let program: &mut TracePoint = program.try_into()?;
+
+        program
+            .load()
+            .context(format!("failed to load program {name}"))?;
+        loaded += 1;
+    }
+
+    Ok(loaded)
+}
+
+impl EbpfMonitor {
+    fn new(config: &Config, features: &KernelFeatures) -> Result<Self> {
         resource::setrlimit(Resource::RLIMIT_MEMLOCK, RLIM_INFINITY, RLIM_INFINITY)?;
 
-        let mut ebpf = Ebpf::load(include_bytes_aligned!(concat!(
-            env!("OUT_DIR"),
-            "/zynx-ebpf"
-        )))?;
+        let Some(channel_size) = config
+            .channel_size
+            .max(*PAGE_SIZE as u32)
+            .checked_next_power_of_two()
+        else {
+            bail!(
+                "channel size {} too large, at most {} bytes",
+                config.channel_size,
+                1u32 << 31
+            );
+        };
+
+        if channel_size != config.channel_size {
+            warn!(
+                "channel size {} is not a power of two multiple of the page size, using {channel_size}",
+                config.channel_size
+            );
+        }
+
+        let targets = config.target_paths.len().max(config.target_names.len()) as u32;
+        let map_sizes = MapSizes {
+            targets: config.map_sizes.targets.max(targets),
+            tasks: config.map_sizes.tasks.max(1),
+        };
+
+        if map_sizes.targets != config.map_sizes.targets {
+            warn!(
+                "{targets} targets don't fit into maps of {} entries, raised to fit them",
+                config.map_sizes.targets
+            );
+        }
+
+        let mut ebpf = load_programs(
+            features,
+            channel_size,
+            map_sizes,
+            config.service_depth,
+            config.observe_only,
+        )?;
 
         match EbpfLogger::init(&mut ebpf) {
             Ok(logger) => {
@@ -115,32 +400,64 @@ impl Monitor {
             target_names.insert(buffer, 0, 0)?;
         }
 
-        for (name, program) in ebpf.programs_mut() {
-            let parts: Vec<_> = name.split("__").collect();
-
-            if parts[0] == "tracepoint" {
-                let program: &mut TracePoint = program.try_into()?;
-                let (category, name) = (parts[1], parts[2]);
-
-                info!("attaching tracepoint: {category}/{name}");
+        let sys_enter = attach_programs(&mut ebpf, features, None)?;
+        let uid_filter = if config.uid_filter {
+            UidFilter::new(&mut ebpf)
+                .context("failed to set up the uid filter, every fork will be stopped")
+                .ok_or_warn()
+        } else {
+            None
+        };
+        let specialize_probe = if config.require_specialize_probe {
+            Some(
+                SpecializeProbe::new(&mut ebpf)
+                    .context("failed to probe SpecializeCommon, needed by the mem injector")?,
+            )
+        } else if config.specialize_probe {
+            SpecializeProbe::new(&mut ebpf)
+                .context("failed to probe SpecializeCommon, embryos will be stopped after fork")
+                .ok_or_warn()
+        } else {
+            None
+        };
 
-                program.load()?;
-                program.attach(category, name)?;
-            }
+        if config.specialize_probe {
+            let state = if specialize_probe.is_some() {
+                "on"
+            } else {
+                "error"
+            };
+            Status::instance().set(STATUS_SECTION, "specialize_probe", state);
         }
 
+        Status::instance().set(STATUS_SECTION, "sys_enter", format!("{sys_enter:?}"));
+
         let channel =
             AsyncFd::with_interest(take_map(&mut ebpf, "MESSAGE_CHANNEL")?, Interest::READABLE)?;
-        let zygote_info = take_map(&mut ebpf, "ZYGOTE_INFO")?;
+        let zygote_pids = take_map(&mut ebpf, "ZYGOTE_PIDS")?;
+        let heartbeats = take_map(&mut ebpf, "HEARTBEATS")?;
+        let dropped_messages = take_map(&mut ebpf, "DROPPED_MESSAGES")?;
+        let map_failures = take_map(&mut ebpf, "MAP_FAILURES")?;
 
         Ok(Self {
             channel: AsyncMutex::new(channel),
-            zygote_info: Mutex::new(zygote_info),
-            _ebpf: ebpf,
+            zygote_pids: Mutex::new(zygote_pids),
+            dropped_messages: Mutex::new(dropped_messages),
+            map_failures: Mutex::new(map_failures),
+            map_sizes,
+            ebpf: Mutex::new(ebpf),
+            watchdog: features.has_watchdog.then(|| Watchdog::new(heartbeats)),
+            sys_enter,
+            uid_filter,
+            specialize_probe,
         })
     }
 
-    pub async fn recv_msg(&self) -> Option<Message> {
+    async fn recv_msg(
+        &self,
+        signal_mode: SignalMode,
+        observe_only: ObserveOnly,
+    ) -> Option<Message> {
         loop {
             let mut channel = self.channel.lock().await;
             let mut asyncfd = channel.readable_mut().await.ok()?;
@@ -158,21 +475,300 @@ impl Monitor {
                 .ok()?;
             let message: EbpfMessage = unsafe { mem::transmute(buffer) };
 
-            break Some(message.into());
+            if let EbpfMessage::Heartbeat(_) = message {
+                if let Some(watchdog) = &self.watchdog {
+                    watchdog.on_heartbeat();
+                }
+                continue;
+            }
+
+            let message = Message::from(message);
+
+            // the kernel has no helper to stop it from eBPF
+            if signal_mode == SignalMode::Daemon
+                && let Some(pid) = message.stopped_pid(observe_only)
+            {
+                signal::kill(pid, Signal::SIGSTOP).log_if_error();
+            }
+
+            break Some(message);
         }
     }
 
+    fn map_stats(&self) -> Result<Vec<MapStat>> {
+        let ebpf = self.ebpf.lock();
+        let map_failures = self.map_failures.lock();
+
+        TrackedMap::ALL
+            .into_iter()
+            .map(|map| {
+                let failures = map_failures.get(&(map as u32), 0)?;
+
+                Ok(MapStat {
+                    map,
+                    entries: map_stats::count_entries(&ebpf, map)?,
+                    capacity: self.map_sizes.tasks,
+                    failures: failures
+                        .iter()
+                        .fold(0, |sum, value| sum.wrapping_add(*value)),
+                })
+            })
+            .collect()
+    }
+
+    fn detach_programs(&self, features: &KernelFeatures) -> Result<()> {
+        let mut ebpf = self.ebpf.lock();
+
+        for (name, program) in ebpf.programs_mut() {
+            let parts: Vec<_> = name.split("__").collect();
+
+            if parts[0] == "tracepoint"
+                && features.can_attach(parts[1], parts[2])
+                && !SysEnterAttach::is_variant(name)
+            {
+                let program: &mut TracePoint = program.try_into()?;
+
+                info!("detaching tracepoint: {name}");
+
+                program.unload()?;
+            }
+        }
+
+        if let Some(probe) = &self.specialize_probe {
+            probe.detach(&mut ebpf).log_if_error();
+        }
+
+        info!("detaching sys_enter");
+
+        self.sys_enter.detach(&mut ebpf)
+    }
+
+    fn reload_programs(&self, features: &KernelFeatures) -> Result<()> {
+        let mut ebpf = self.ebpf.lock();
+
+        for (name, program) in ebpf.programs_mut() {
+            if name.starts_with("tracepoint__") && !SysEnterAttach::is_variant(name) {
+                let program: &mut TracePoint = program.try_into()?;
+                program.unload().log_if_error();
+            }
+        }
+
+        self.sys_enter.detach(&mut ebpf).log_if_error();
+
+        attach_programs(&mut ebpf, features, Some(self.sys_enter)).map(|_| ())
+    }
+}
+
+impl Monitor {
+    fn new(mut config: Config) -> Result<Self> {
+        if config.service_depth == 0 {
+            warn!("service depth must be at least 1, only tracking children of init");
+            config.service_depth = 1;
+        }
+
+        let features = KernelFeatures::probe(layout::tracefs());
+
+        info!("kernel features: {features}");
+
+        let connector = || -> Result<Backend> {
+            Ok(Backend::ProcConnector(ProcConnector::new(
+                &config.target_paths,
+                &config.target_names,
+                config.service_depth,
+                config.observe_only,
+            )?))
+        };
+
+        let backend = match config.backend {
+            MonitorBackend::Ebpf if !features.has_ringbuf => {
+                bail!(
+                    "the eBPF monitor needs the ring buffer of kernel {}.{}+",
+                    RINGBUF_KERNEL.0,
+                    RINGBUF_KERNEL.1
+                );
+            }
+            MonitorBackend::Ebpf => Backend::Ebpf(EbpfMonitor::new(&config, &features)?),
+            MonitorBackend::ProcConnector => connector()?,
+            MonitorBackend::Auto if !features.supports_ebpf() => {
+                warn!(
+                    "kernel lacks {:?}, falling back to the proc connector",
+                    features.missing_for_ebpf()
+                );
+
+                connector()?
+            }
+            // loading may be blocked, e.g. by SELinux or a locked down kernel
+            MonitorBackend::Auto => match EbpfMonitor::new(&config, &features) {
+                Ok(monitor) => Backend::Ebpf(monitor),
+                Err(err) => {
+                    warn!(
+                        "failed to load eBPF programs, falling back to the proc connector: {err:?}"
+                    );
+                    connector()?
+                }
+            },
+        };
+
+        if config.uid_filter && matches!(backend, Backend::ProcConnector(_)) {
+            warn!("the uid filter needs the eBPF backend, every fork will be stopped");
+        }
+
+        if config.require_specialize_probe && matches!(backend, Backend::ProcConnector(_)) {
+            bail!("the mem injector needs the SpecializeCommon probe of the eBPF backend");
+        }
+
+        if config.specialize_probe && matches!(backend, Backend::ProcConnector(_)) {
+            warn!(
+                "the SpecializeCommon probe needs the eBPF backend, embryos will be stopped after fork"
+            );
+        }
+
+        let status = Status::instance();
+        let mode = match &backend {
+            Backend::Ebpf(_) => "ebpf",
+            Backend::ProcConnector(_) => "proc-connector",
+        };
+
+        status.set(STATUS_SECTION, "mode", mode);
+        status.set(STATUS_SECTION, "features", &features);
+
+        if config.observe_only != ObserveOnly::default() {
+            warn!("observe-only mode: {:?}", config.observe_only);
+            status.set(
+                STATUS_SECTION,
+                "observe_only",
+                format!("{:?}", config.observe_only),
+            );
+        }
+
+        Ok(Self {
+            backend,
+            features,
+            observe_only: config.observe_only,
+        })
+    }
+
+    pub async fn recv_msg(&self) -> Option<Message> {
+        match &self.backend {
+            Backend::Ebpf(monitor) => {
+                monitor
+                    .recv_msg(self.features.signal_mode, self.observe_only)
+                    .await
+            }
+            Backend::ProcConnector(connector) => connector.recv_msg().await,
+        }
+    }
+
+    /// Matches reported without stopping the processes.
+    pub fn observe_only(&self) -> ObserveOnly {
+        self.observe_only
+    }
+
     pub fn attach_zygote(&self, pid: i32) -> Result<()> {
-        let mut zygote_info = self.zygote_info.lock();
-        zygote_info.set(0, pid, 0 /* BPF_ANY */)?;
+        match &self.backend {
+            Backend::Ebpf(monitor) => {
+                monitor.zygote_pids.lock().insert(pid, 0, 0 /* BPF_ANY */)?;
+            }
+            Backend::ProcConnector(connector) => connector.attach_zygote(pid),
+        }
+
+        Ok(())
+    }
+
+    pub fn detach_zygote(&self, pid: i32) -> Result<()> {
+        match &self.backend {
+            Backend::Ebpf(monitor) => {
+                monitor.zygote_pids.lock().remove(&pid)?;
+            }
+            Backend::ProcConnector(connector) => connector.detach_zygote(pid),
+        }
+
         Ok(())
     }
 
+    fn watchdog(&self) -> Option<&Watchdog> {
+        match &self.backend {
+            Backend::Ebpf(monitor) => monitor.watchdog.as_ref(),
+            Backend::ProcConnector(_) => None,
+        }
+    }
+
+    fn uid_filter(&self) -> Option<&UidFilter> {
+        match &self.backend {
+            Backend::Ebpf(monitor) => monitor.uid_filter.as_ref(),
+            Backend::ProcConnector(_) => None,
+        }
+    }
+
+    /// Registers at `SpecializeCommon` of an embryo stopped by [`SpecializeProbe`].
+    pub fn take_entry_regs(&self, pid: Pid) -> Result<EntryRegs> {
+        match &self.backend {
+            Backend::Ebpf(EbpfMonitor {
+                specialize_probe: Some(probe),
+                ..
+            }) => probe.take_entry_regs(pid),
+            _ => bail!("SpecializeCommon is not probed"),
+        }
+    }
+
+    /// Total number of messages dropped by eBPF because the channel was full, or by the proc
+    /// connector because its socket overran.
+    pub fn dropped_messages(&self) -> Result<u64> {
+        let monitor = match &self.backend {
+            Backend::Ebpf(monitor) => monitor,
+            Backend::ProcConnector(connector) => return Ok(connector.dropped_messages()),
+        };
+
+        let values = monitor.dropped_messages.lock().get(&0, 0)?;
+        Ok(values.iter().fold(0, |sum, value| sum.wrapping_add(*value)))
+    }
+
+    /// Occupancy of the maps tracking tasks, none without the eBPF backend.
+    pub fn map_stats(&self) -> Result<Vec<MapStat>> {
+        match &self.backend {
+            Backend::Ebpf(monitor) => monitor.map_stats(),
+            Backend::ProcConnector(_) => Ok(Vec::new()),
+        }
+    }
+
+    /// Detach all tracepoints, so that no more processes get stopped. Messages already in the
+    /// channel can still be received.
+    pub fn detach_programs(&self) -> Result<()> {
+        match &self.backend {
+            Backend::Ebpf(monitor) => monitor.detach_programs(&self.features),
+            Backend::ProcConnector(_) => Ok(()),
+        }
+    }
+
+    /// Detach and re-attach all tracepoints. Maps are kept, so tracked zygotes and embryos
+    /// survive, but processes forked meanwhile are missed.
+    pub fn reload_programs(&self) -> Result<()> {
+        match &self.backend {
+            Backend::Ebpf(monitor) => monitor.reload_programs(&self.features),
+            Backend::ProcConnector(_) => Ok(()),
+        }
+    }
+
     pub fn init(config: Config) -> Result<()> {
         let monitor = Self::new(config)?;
+        let has_watchdog = monitor.watchdog().is_some();
+
         INSTANCE
             .set(monitor)
             .map_err(|_| anyhow!("Monitor already initialized"))?;
+
+        if has_watchdog {
+            Watchdog::spawn();
+        }
+
+        if let Some(uid_filter) = Self::instance().uid_filter() {
+            uid_filter.spawn();
+        }
+
+        if matches!(Self::instance().backend, Backend::Ebpf(_)) {
+            map_stats::spawn();
+        }
+
         Ok(())
     }
 
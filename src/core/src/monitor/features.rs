@@ -0,0 +1,194 @@
+use crate::misc;
+use nix::libc;
+use nix::libc::{SYS_bpf, c_long};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::{fmt, mem};
+use tracing::warn;
+use zynx_ebpf_shared::SignalMode;
+
+const BPF_PROG_LOAD: c_long = 5;
+const BPF_PROG_TYPE_TRACEPOINT: u32 = 5;
+
+const BPF_FUNC_SEND_SIGNAL: i32 = 109;
+const BPF_FUNC_SEND_SIGNAL_THREAD: i32 = 117;
+
+/// First release with the eBPF ring buffer the monitor reads messages from
+pub const RINGBUF_KERNEL: (u32, u32) = (5, 8);
+
+/// Tracepoints the monitor can't work without
+pub const REQUIRED_TRACEPOINTS: [(&str, &str); 5] = [
+    ("task", "task_newtask"),
+    ("sched", "sched_process_exec"),
+    ("task", "task_rename"),
+    ("raw_syscalls", "sys_enter"),
+    ("sched", "sched_process_exit"),
+];
+
+/// Leading fields of `union bpf_attr` for `BPF_PROG_LOAD`, the kernel expects the rest zeroed.
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    _reserved: [u64; 12],
+}
+
+/// Whether the verifier accepts a tracepoint program calling helper `func`, the way bpftool
+/// probes helpers.
+fn probe_helper(func: i32) -> bool {
+    let insns: [u64; 4] = [
+        // r1 = 0
+        0x0000_0000_0000_01b7,
+        // call func
+        0x0000_0000_0000_0085 | ((func as u64) << 32),
+        // r0 = 0
+        0x0000_0000_0000_00b7,
+        // exit
+        0x0000_0000_0000_0095,
+    ];
+    let license = c"GPL";
+    let attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_TRACEPOINT,
+        insn_cnt: insns.len() as _,
+        insns: insns.as_ptr() as _,
+        license: license.as_ptr() as _,
+        ..Default::default()
+    };
+
+    let fd = unsafe {
+        libc::syscall(
+            SYS_bpf,
+            BPF_PROG_LOAD,
+            &attr as *const ProgLoadAttr,
+            mem::size_of::<ProgLoadAttr>(),
+        )
+    };
+
+    if fd < 0 {
+        return false;
+    }
+
+    unsafe { libc::close(fd as _) };
+
+    true
+}
+
+fn has_tracepoint(tracefs: &Path, category: &str, name: &str) -> bool {
+    tracefs.join("events").join(category).join(name).is_dir()
+}
+
+/// Kernel features the monitor depends on, probed at startup.
+#[derive(Debug)]
+pub struct KernelFeatures {
+    pub signal_mode: SignalMode,
+    /// The kernel is at least [`RINGBUF_KERNEL`], assumed if its release can't be parsed
+    pub has_ringbuf: bool,
+    /// `category/name` of required tracepoints the kernel lacks
+    pub missing_tracepoints: Vec<String>,
+    /// `signal/signal_deliver`, only used to debug
+    pub has_signal_deliver: bool,
+    /// `syscalls/sys_enter_getppid` probed by the watchdog, needs `CONFIG_FTRACE_SYSCALLS`
+    pub has_watchdog: bool,
+}
+
+impl KernelFeatures {
+    pub fn probe(tracefs: Option<&Path>) -> Self {
+        let signal_mode = if probe_helper(BPF_FUNC_SEND_SIGNAL_THREAD) {
+            SignalMode::Thread
+        } else if probe_helper(BPF_FUNC_SEND_SIGNAL) {
+            SignalMode::Process
+        } else {
+            SignalMode::Daemon
+        };
+
+        let has_ringbuf = match misc::kernel_version() {
+            Ok(version) => version >= RINGBUF_KERNEL,
+            Err(err) => {
+                warn!("{err:#}, assuming the ring buffer is supported");
+                true
+            }
+        };
+
+        // without tracefs, assume everything is there and let attaching tell otherwise
+        let Some(tracefs) = tracefs else {
+            return Self {
+                signal_mode,
+                has_ringbuf,
+                missing_tracepoints: Vec::new(),
+                has_signal_deliver: true,
+                has_watchdog: true,
+            };
+        };
+
+        let missing_tracepoints = REQUIRED_TRACEPOINTS
+            .iter()
+            .filter(|(category, name)| !has_tracepoint(tracefs, category, name))
+            .map(|(category, name)| format!("{category}/{name}"))
+            .collect();
+
+        Self {
+            signal_mode,
+            has_ringbuf,
+            missing_tracepoints,
+            has_signal_deliver: has_tracepoint(tracefs, "signal", "signal_deliver"),
+            has_watchdog: has_tracepoint(tracefs, "syscalls", "sys_enter_getppid"),
+        }
+    }
+
+    /// Whether the eBPF programs can be used at all, otherwise the proc connector takes over.
+    pub fn supports_ebpf(&self) -> bool {
+        self.has_ringbuf && self.missing_tracepoints.is_empty()
+    }
+
+    /// What the eBPF programs need and the kernel lacks, empty if [`Self::supports_ebpf`].
+    pub fn missing_for_ebpf(&self) -> Vec<String> {
+        let mut missing = Vec::new();
+
+        if !self.has_ringbuf {
+            missing.push(format!(
+                "ring buffer (kernel {}.{}+)",
+                RINGBUF_KERNEL.0, RINGBUF_KERNEL.1
+            ));
+        }
+
+        missing.extend(self.missing_tracepoints.iter().cloned());
+        missing
+    }
+
+    /// Whether a tracepoint program may be attached, optional ones are skipped when missing.
+    pub fn can_attach(&self, category: &str, name: &str) -> bool {
+        match (category, name) {
+            ("signal", "signal_deliver") => self.has_signal_deliver,
+            ("syscalls", "sys_enter_getppid") => self.has_watchdog,
+            _ => true,
+        }
+    }
+}
+
+impl Display for KernelFeatures {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "signal={:?}", self.signal_mode)?;
+
+        if !self.has_ringbuf {
+            f.write_str(" no-ringbuf")?;
+        }
+
+        if !self.missing_tracepoints.is_empty() {
+            write!(f, " missing=[{}]", self.missing_tracepoints.join(","))?;
+        }
+
+        if !self.has_watchdog {
+            f.write_str(" no-watchdog")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::{Context, Result, bail};
+use aya::Pod;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use tracing::{debug, info, warn};
+use zynx_ebpf_shared::{FIELD_ABSENT, KernelLayout};
+
+const TRACEFS_DIRS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// [`KernelLayout`] as a global of the eBPF programs.
+#[repr(transparent)]
+#[derive(Copy, Clone)]
+pub struct LayoutGlobal(pub KernelLayout);
+
+unsafe impl Pod for LayoutGlobal {}
+
+/// Fields of a tracepoint record by name, as `(offset, size)`.
+struct EventFormat {
+    event: String,
+    fields: HashMap<String, (u32, u32)>,
+}
+
+impl EventFormat {
+    fn read(tracefs: &Path, category: &str, name: &str) -> Result<Self> {
+        let path = tracefs
+            .join("events")
+            .join(category)
+            .join(name)
+            .join("format");
+        let content =
+            fs::read_to_string(&path).context(format!("failed to read {}", path.display()))?;
+
+        Ok(Self::parse(&format!("{category}/{name}"), &content))
+    }
+
+    /// Parse lines like `field:pid_t pid;    offset:8;    size:4;    signed:1;`.
+    fn parse(event: &str, content: &str) -> Self {
+        let mut fields = HashMap::new();
+
+        for line in content.lines() {
+            let Some(line) = line.trim().strip_prefix("field:") else {
+                continue;
+            };
+
+            let mut parts = line.split(';').map(str::trim);
+            let declaration = parts.next().unwrap_or_default();
+            let mut offset = None;
+            let mut size = None;
+
+            for part in parts {
+                if let Some(value) = part.strip_prefix("offset:") {
+                    offset = value.parse().ok();
+                } else if let Some(value) = part.strip_prefix("size:") {
+                    size = value.parse().ok();
+                }
+            }
+
+            // e.g. `char comm[16]` or `__data_loc char[] filename`
+            let name = declaration
+                .rsplit(' ')
+                .next()
+                .and_then(|name| name.split('[').next())
+                .unwrap_or_default();
+
+            if let (Some(offset), Some(size)) = (offset, size) {
+                fields.insert(name.to_string(), (offset, size));
+            }
+        }
+
+        Self {
+            event: event.into(),
+            fields,
+        }
+    }
+
+    /// Offset of `name`, which the programs read as `size` bytes.
+    fn offset(&self, name: &str, size: u32) -> Result<u32> {
+        match self.fields.get(name) {
+            Some(&(offset, actual)) if actual == size => Ok(offset),
+            Some(&(_, actual)) => {
+                bail!(
+                    "{}: field {name} has {actual} bytes, expected {size}",
+                    self.event
+                )
+            }
+            None => bail!("{}: no field {name}", self.event),
+        }
+    }
+
+    fn offset_or_absent(&self, name: &str, size: u32) -> Result<u32> {
+        if self.fields.contains_key(name) {
+            self.offset(name, size)
+        } else {
+            Ok(FIELD_ABSENT)
+        }
+    }
+}
+
+fn probe_in(tracefs: &Path) -> Result<KernelLayout> {
+    let newtask = EventFormat::read(tracefs, "task", "task_newtask")?;
+    let exec = EventFormat::read(tracefs, "sched", "sched_process_exec")?;
+    let rename = EventFormat::read(tracefs, "task", "task_rename")?;
+    let sys_enter = EventFormat::read(tracefs, "raw_syscalls", "sys_enter")?;
+    let deliver = EventFormat::read(tracefs, "signal", "signal_deliver")?;
+    let exit = EventFormat::read(tracefs, "sched", "sched_process_exit")?;
+
+    Ok(KernelLayout {
+        tif_32bit: KernelLayout::DEFAULT.tif_32bit,
+        task_newtask_pid: newtask.offset("pid", 4)?,
+        task_newtask_clone_flags: newtask.offset("clone_flags", 8)?,
+        sched_process_exec_filename: exec.offset("filename", 4)?,
+        sched_process_exec_pid: exec.offset("pid", 4)?,
+        task_rename_pid: rename.offset_or_absent("pid", 4)?,
+        task_rename_newcomm: rename.offset("newcomm", 16)?,
+        sys_enter_id: sys_enter.offset("id", 8)?,
+        sys_enter_args: sys_enter.offset("args", 48)?,
+        signal_deliver_sig: deliver.offset("sig", 4)?,
+        signal_deliver_code: deliver.offset("code", 4)?,
+        sched_process_exit_pid: exit.offset("pid", 4)?,
+    })
+}
+
+/// Mount point of tracefs, if readable.
+pub fn tracefs() -> Option<&'static Path> {
+    TRACEFS_DIRS
+        .iter()
+        .map(Path::new)
+        .find(|dir| dir.join("events").is_dir())
+}
+
+/// Probe the record layouts of the tracepoints the programs attach to. Falls back to the
+/// default layout only if tracefs can't be read at all; a field that moved to an unexpected
+/// size or is gone fails instead of being misread.
+pub fn probe() -> Result<KernelLayout> {
+    let Some(tracefs) = tracefs() else {
+        warn!("tracefs not found, assuming the default tracepoint layout");
+        return Ok(KernelLayout::DEFAULT);
+    };
+
+    let layout = probe_in(tracefs).context("unsupported tracepoint layout")?;
+
+    if layout != KernelLayout::DEFAULT {
+        info!("tracepoint layout differs from the default: {layout:?}");
+    } else {
+        debug!("tracepoint layout: {layout:?}");
+    }
+
+    Ok(layout)
+}
@@ -0,0 +1,89 @@
+use crate::injector::Shutdown;
+use crate::metrics::Metrics;
+use crate::monitor::{Monitor, STATUS_SECTION};
+use crate::status::Status;
+use anyhow::{Context, Result};
+use aya::maps::HashMap;
+use aya::{Ebpf, Pod};
+use std::time::Duration;
+use tokio::{task, time};
+use tracing::warn;
+use zynx_ebpf_shared::TrackedMap;
+use zynx_misc::ext::ResultExt;
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Occupancy of a map tracking tasks
+#[derive(Copy, Clone, Debug)]
+pub struct MapStat {
+    pub map: TrackedMap,
+    pub entries: usize,
+    pub capacity: u32,
+    /// Inserts failed since the programs were loaded, mostly because the map was full
+    pub failures: u64,
+}
+
+fn count<V: Pod>(ebpf: &Ebpf, name: &str) -> Result<usize> {
+    let map = ebpf.map(name).context(format!("no map {name}"))?;
+    let map: HashMap<_, i32, V> = HashMap::try_from(map)?;
+
+    Ok(map.keys().filter(Result::is_ok).count())
+}
+
+/// Number of entries in `map`, all of them keyed by pid. Values are never read, only their
+/// size matters.
+pub fn count_entries(ebpf: &Ebpf, map: TrackedMap) -> Result<usize> {
+    match map {
+        TrackedMap::InitChildren => count::<u8>(ebpf, map.name()),
+        TrackedMap::ServiceTree => count::<u32>(ebpf, map.name()),
+        // `EmbryoInfo` of the programs
+        TrackedMap::ZygoteChildren => count::<[u8; 8]>(ebpf, map.name()),
+    }
+}
+
+/// Spawn the background task reporting the occupancy of the maps to the status and metrics,
+/// warning whenever a task couldn't be tracked.
+pub fn spawn() {
+    task::spawn(async {
+        let mut interval = time::interval(REPORT_INTERVAL);
+        let mut failures = [0u64; TrackedMap::ALL.len()];
+
+        loop {
+            interval.tick().await;
+
+            if Shutdown::instance().is_requested() {
+                break;
+            }
+
+            let Some(stats) = Monitor::instance().map_stats().inspect_log_error().ok() else {
+                continue;
+            };
+
+            for stat in &stats {
+                let last = &mut failures[stat.map as usize];
+
+                if stat.failures != *last {
+                    warn!(
+                        "{} tasks not tracked by {} of {} entries, consider raising --cfg-max-tasks",
+                        stat.failures.wrapping_sub(*last),
+                        stat.map.name(),
+                        stat.capacity
+                    );
+
+                    *last = stat.failures;
+                }
+
+                Status::instance().set(
+                    STATUS_SECTION,
+                    &format!("map_{}", stat.map.name().to_lowercase()),
+                    format!(
+                        "{}/{} ({} failed)",
+                        stat.entries, stat.capacity, stat.failures
+                    ),
+                );
+            }
+
+            Metrics::instance().on_map_stats(&stats);
+        }
+    });
+}
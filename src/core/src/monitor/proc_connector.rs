@@ -0,0 +1,355 @@
+use crate::monitor::Message;
+use anyhow::{Result, bail};
+use nix::errno::Errno;
+use nix::libc;
+use nix::libc::{AF_NETLINK, NETLINK_CONNECTOR, SOCK_CLOEXEC, SOCK_DGRAM, SOCK_NONBLOCK};
+use nix::sys::signal;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{mem, process, ptr};
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::{debug, info};
+use zynx_ebpf_shared::ObserveOnly;
+use zynx_misc::ext::ResultExt;
+
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+
+const PROC_EVENT_FORK: u32 = 0x00000001;
+const PROC_EVENT_EXEC: u32 = 0x00000002;
+const PROC_EVENT_COMM: u32 = 0x00000200;
+const PROC_EVENT_EXIT: u32 = 0x80000000;
+
+const INIT_PID: i32 = 1;
+const BUFFER_SIZE: usize = 4096;
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct CnMsg {
+    idx: u32,
+    val: u32,
+    seq: u32,
+    ack: u32,
+    len: u16,
+    flags: u16,
+}
+
+#[repr(C)]
+struct ListenRequest {
+    header: libc::nlmsghdr,
+    msg: CnMsg,
+    op: u32,
+}
+
+/// Leading fields of `struct proc_event`, followed by the data of the event
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ProcEvent {
+    what: u32,
+    _cpu: u32,
+    _timestamp_ns: u64,
+    data: [u32; 8],
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ServiceState {
+    PostFork,
+    PostExec,
+}
+
+#[derive(Default)]
+struct Tracking {
+    init_children: HashMap<i32, ServiceState>,
+    /// Descendants of init whose children are tracked too, by their depth below init
+    service_tree: HashMap<i32, u32>,
+    zygotes: HashSet<i32>,
+}
+
+/// Fallback of the eBPF programs on kernels lacking their tracepoints, following forks, execs
+/// and renames through the netlink proc connector. Processes are stopped once the daemon gets
+/// the event, so they may have run for a little while already.
+pub struct ProcConnector {
+    socket: AsyncMutex<AsyncFd<OwnedFd>>,
+    tracking: Mutex<Tracking>,
+    target_paths: HashSet<String>,
+    target_names: HashSet<String>,
+    service_depth: u32,
+    observe_only: ObserveOnly,
+    /// Times the socket overran, each losing an unknown number of events
+    overruns: AtomicU64,
+}
+
+fn stop(pid: i32) {
+    signal::kill(Pid::from_raw(pid), Signal::SIGSTOP).log_if_error();
+}
+
+fn comm_of(data: &[u32]) -> String {
+    let bytes: Vec<u8> = data
+        .iter()
+        .flat_map(|word| word.to_ne_bytes())
+        .take_while(|byte| *byte != 0)
+        .collect();
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl ProcConnector {
+    pub fn new(
+        target_paths: &[String],
+        target_names: &[String],
+        service_depth: u32,
+        observe_only: ObserveOnly,
+    ) -> Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                AF_NETLINK,
+                SOCK_DGRAM | SOCK_NONBLOCK | SOCK_CLOEXEC,
+                NETLINK_CONNECTOR,
+            )
+        };
+
+        if fd < 0 {
+            bail!("failed to create netlink socket: {}", Errno::last());
+        }
+
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = AF_NETLINK as _;
+        address.nl_pid = process::id();
+        address.nl_groups = CN_IDX_PROC;
+
+        let result = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &address as *const _ as _,
+                size_of::<libc::sockaddr_nl>() as _,
+            )
+        };
+
+        if result < 0 {
+            bail!("failed to bind proc connector: {}", Errno::last());
+        }
+
+        let request = ListenRequest {
+            header: libc::nlmsghdr {
+                nlmsg_len: size_of::<ListenRequest>() as _,
+                nlmsg_type: libc::NLMSG_DONE as _,
+                nlmsg_flags: 0,
+                nlmsg_seq: 0,
+                nlmsg_pid: process::id(),
+            },
+            msg: CnMsg {
+                idx: CN_IDX_PROC,
+                val: CN_VAL_PROC,
+                len: size_of::<u32>() as _,
+                ..Default::default()
+            },
+            op: PROC_CN_MCAST_LISTEN,
+        };
+
+        let sent = unsafe {
+            libc::send(
+                socket.as_raw_fd(),
+                &request as *const _ as _,
+                size_of::<ListenRequest>(),
+                0,
+            )
+        };
+
+        if sent < 0 {
+            bail!("failed to subscribe to proc events: {}", Errno::last());
+        }
+
+        info!("listening to the proc connector");
+
+        Ok(Self {
+            socket: AsyncMutex::new(AsyncFd::with_interest(socket, Interest::READABLE)?),
+            tracking: Mutex::default(),
+            target_paths: target_paths.iter().cloned().collect(),
+            target_names: target_names.iter().cloned().collect(),
+            service_depth,
+            observe_only,
+            overruns: AtomicU64::new(0),
+        })
+    }
+
+    pub fn attach_zygote(&self, pid: i32) {
+        self.tracking.lock().zygotes.insert(pid);
+    }
+
+    pub fn detach_zygote(&self, pid: i32) {
+        self.tracking.lock().zygotes.remove(&pid);
+    }
+
+    pub async fn recv_msg(&self) -> Option<Message> {
+        let mut socket = self.socket.lock().await;
+        let mut buffer = [0u8; BUFFER_SIZE];
+
+        loop {
+            let mut guard = socket.readable_mut().await.ok()?;
+
+            let received = guard.try_io(|fd| {
+                let len = unsafe {
+                    libc::recv(fd.as_raw_fd(), buffer.as_mut_ptr() as _, buffer.len(), 0)
+                };
+
+                Errno::result(len)
+                    .map(|len| len as usize)
+                    .map_err(Into::into)
+            });
+
+            let len = match received {
+                Ok(Ok(len)) => len,
+                // the receive buffer filled up and the kernel dropped events
+                Ok(Err(err)) if err.raw_os_error() == Some(libc::ENOBUFS) => {
+                    self.overruns.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            let offset = size_of::<libc::nlmsghdr>() + size_of::<CnMsg>();
+
+            if len < offset + size_of::<ProcEvent>() {
+                continue;
+            }
+
+            let event: ProcEvent =
+                unsafe { ptr::read_unaligned(buffer[offset..].as_ptr() as *const ProcEvent) };
+
+            if let Some(message) = self.handle(&event) {
+                return Some(message);
+            }
+        }
+    }
+
+    /// Times events were dropped because the socket overran, a lower bound of the number of
+    /// events lost.
+    pub fn dropped_messages(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    fn handle(&self, event: &ProcEvent) -> Option<Message> {
+        let mut tracking = self.tracking.lock();
+        let data = &event.data;
+
+        match event.what {
+            PROC_EVENT_FORK => {
+                let [_, parent_tgid, child_pid, child_tgid, ..] = *data;
+                let (parent_tgid, child_pid) = (parent_tgid as i32, child_pid as i32);
+
+                // skip for threads
+                if child_pid != child_tgid as i32 {
+                    return None;
+                }
+
+                // forks of zygotes are apps, never services
+                let depth = if parent_tgid == INIT_PID {
+                    Some(1)
+                } else if tracking.zygotes.contains(&parent_tgid) {
+                    None
+                } else {
+                    tracking
+                        .service_tree
+                        .get(&parent_tgid)
+                        .map(|depth| depth + 1)
+                };
+
+                if let Some(depth) = depth {
+                    tracking
+                        .init_children
+                        .insert(child_pid, ServiceState::PostFork);
+
+                    if depth < self.service_depth {
+                        tracking.service_tree.insert(child_pid, depth);
+                    }
+                }
+
+                if tracking.zygotes.contains(&parent_tgid) {
+                    if !self.observe_only.embryos {
+                        stop(child_pid);
+                    }
+
+                    return Some(Message::ZygoteFork(
+                        Pid::from_raw(parent_tgid),
+                        Pid::from_raw(child_pid),
+                    ));
+                }
+            }
+            PROC_EVENT_EXEC => {
+                let pid = data[1] as i32;
+
+                if tracking.init_children.get(&pid) != Some(&ServiceState::PostFork) {
+                    return None;
+                }
+
+                let path = fs::read_link(format!("/proc/{pid}/exe"))
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+
+                debug!("process exec: {pid} -> {path}");
+
+                if self.target_paths.contains(&path) {
+                    info!("path matches: {pid} -> {path}");
+
+                    tracking.init_children.remove(&pid);
+
+                    if !self.observe_only.services {
+                        stop(pid);
+                    }
+
+                    return Some(Message::PathMatches(Pid::from_raw(pid), path));
+                }
+
+                tracking.init_children.insert(pid, ServiceState::PostExec);
+            }
+            PROC_EVENT_COMM => {
+                let pid = data[1] as i32;
+
+                if tracking.init_children.get(&pid) != Some(&ServiceState::PostExec) {
+                    return None;
+                }
+
+                tracking.init_children.remove(&pid);
+
+                let name = comm_of(&data[2..6]);
+
+                if self.target_names.contains(&name) {
+                    info!("name matches: {pid} -> {name}");
+
+                    stop(pid);
+
+                    return Some(Message::NameMatches(Pid::from_raw(pid), name));
+                }
+            }
+            PROC_EVENT_EXIT => {
+                let [pid, tgid, ..] = *data;
+
+                if pid != tgid {
+                    return None;
+                }
+
+                let pid = pid as i32;
+
+                tracking.init_children.remove(&pid);
+                tracking.service_tree.remove(&pid);
+
+                if tracking.zygotes.remove(&pid) {
+                    return Some(Message::ZygoteCrashed(Pid::from_raw(pid)));
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
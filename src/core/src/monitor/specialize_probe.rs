@@ -0,0 +1,78 @@
+use crate::injector::SC_CONFIG;
+use crate::monitor::take_map;
+use crate::monitor::uid_filter::file_offset;
+use anyhow::{Context, Result};
+use aya::Ebpf;
+use aya::maps::{Array, HashMap, MapData};
+use aya::programs::UProbe;
+use nix::unistd::Pid;
+use parking_lot::Mutex;
+use tracing::info;
+use zynx_ebpf_shared::EntryRegs;
+
+const PROGRAM: &str = "uprobe__specialize_common";
+
+/// Stops embryos at `SpecializeCommon` with a uprobe, instead of after fork for the injector
+/// to patch a breakpoint into them. The registers at the probe are kept by the program, since
+/// embryos only stop once the probed instruction has run.
+pub struct SpecializeProbe {
+    enabled: Mutex<Array<MapData, u8>>,
+    entry_regs: Mutex<HashMap<MapData, i32, EntryRegs>>,
+}
+
+impl SpecializeProbe {
+    pub fn new(ebpf: &mut Ebpf) -> Result<Self> {
+        let offset = file_offset(SC_CONFIG.lib, SC_CONFIG.addr)?;
+
+        let program: &mut UProbe = ebpf
+            .program_mut(PROGRAM)
+            .context(format!("no program {PROGRAM}"))?
+            .try_into()?;
+
+        program.load()?;
+        program.attach(offset, SC_CONFIG.lib, None, None)?;
+
+        let mut enabled: Array<MapData, u8> = take_map(ebpf, "SPECIALIZE_PROBE")?;
+
+        enabled.set(0, 1, 0)?;
+
+        info!(
+            "SpecializeCommon probed at {:#x} (file offset {offset:#x})",
+            SC_CONFIG.addr
+        );
+
+        Ok(Self {
+            enabled: Mutex::new(enabled),
+            entry_regs: Mutex::new(take_map(ebpf, "ENTRY_REGS")?),
+        })
+    }
+
+    /// Stop probing, letting embryos be stopped after fork again, e.g. once the programs
+    /// catching forks are detached. The program checks the flag too, in case detaching fails.
+    pub fn detach(&self, ebpf: &mut Ebpf) -> Result<()> {
+        self.enabled.lock().set(0, 0, 0)?;
+
+        let program: &mut UProbe = ebpf
+            .program_mut(PROGRAM)
+            .context(format!("no program {PROGRAM}"))?
+            .try_into()?;
+
+        info!("detaching uprobe: {PROGRAM}");
+
+        program.unload()?;
+
+        Ok(())
+    }
+
+    /// Registers of the embryo at the probe, once.
+    pub fn take_entry_regs(&self, pid: Pid) -> Result<EntryRegs> {
+        let mut entry_regs = self.entry_regs.lock();
+        let regs = entry_regs
+            .get(&pid.as_raw(), 0)
+            .context(format!("no entry registers recorded for {pid}"))?;
+
+        entry_regs.remove(&pid.as_raw())?;
+
+        Ok(regs)
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::{Context, Result, anyhow};
+use aya::programs::{FEntry, RawTracePoint, TracePoint};
+use aya::{Btf, Ebpf};
+use tracing::{debug, info};
+
+#[cfg(target_arch = "aarch64")]
+const FENTRY_FUNCTION: &str = "__arm64_sys_rt_sigprocmask";
+#[cfg(target_arch = "x86_64")]
+const FENTRY_FUNCTION: &str = "__x64_sys_rt_sigprocmask";
+
+/// How the `sys_enter` program is attached, from the cheapest. The tracepoint copies its record
+/// on every syscall, the raw tracepoint doesn't, and fentry only runs on `rt_sigprocmask`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SysEnterAttach {
+    FEntry,
+    RawTracePoint,
+    TracePoint,
+}
+
+impl SysEnterAttach {
+    pub const ALL: [SysEnterAttach; 3] = [
+        SysEnterAttach::FEntry,
+        SysEnterAttach::RawTracePoint,
+        SysEnterAttach::TracePoint,
+    ];
+
+    pub fn program(self) -> &'static str {
+        match self {
+            SysEnterAttach::FEntry => "fentry__rt_sigprocmask",
+            SysEnterAttach::RawTracePoint => "raw_tracepoint__sys_enter",
+            SysEnterAttach::TracePoint => "tracepoint__raw_syscalls__sys_enter",
+        }
+    }
+
+    /// Whether `name` is one of the variants, which are attached apart from other programs.
+    pub fn is_variant(name: &str) -> bool {
+        Self::ALL.iter().any(|attach| attach.program() == name)
+    }
+
+    pub fn attach(self, ebpf: &mut Ebpf) -> Result<()> {
+        let name = self.program();
+        let program = ebpf
+            .program_mut(name)
+            .context(format!("no program {name}"))?;
+
+        info!("attaching sys_enter as {self:?}");
+
+        match self {
+            SysEnterAttach::FEntry => {
+                let btf = Btf::from_sys_fs()?;
+                let program: &mut FEntry = program.try_into()?;
+
+                program.load(FENTRY_FUNCTION, &btf)?;
+                program.attach()?;
+            }
+            SysEnterAttach::RawTracePoint => {
+                let program: &mut RawTracePoint = program.try_into()?;
+
+                program.load()?;
+                program.attach("sys_enter")?;
+            }
+            SysEnterAttach::TracePoint => {
+                let program: &mut TracePoint = program.try_into()?;
+
+                program.load()?;
+                program.attach("raw_syscalls", "sys_enter")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn detach(self, ebpf: &mut Ebpf) -> Result<()> {
+        let name = self.program();
+        let program = ebpf
+            .program_mut(name)
+            .context(format!("no program {name}"))?;
+
+        match self {
+            SysEnterAttach::FEntry => <&mut FEntry>::try_from(program)?.unload()?,
+            SysEnterAttach::RawTracePoint => <&mut RawTracePoint>::try_from(program)?.unload()?,
+            SysEnterAttach::TracePoint => <&mut TracePoint>::try_from(program)?.unload()?,
+        }
+
+        Ok(())
+    }
+
+    /// Attach the cheapest variant the kernel supports.
+    pub fn attach_best(ebpf: &mut Ebpf) -> Result<Self> {
+        let mut last_err = None;
+
+        for attach in Self::ALL {
+            match attach.attach(ebpf) {
+                Ok(()) => return Ok(attach),
+                Err(err) => {
+                    debug!("sys_enter can't be attached as {attach:?}: {err:#}");
+                    let _ = attach.detach(ebpf);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err
+            .unwrap_or_else(|| anyhow!("no variant to attach"))
+            .context("failed to attach sys_enter"))
+    }
+}
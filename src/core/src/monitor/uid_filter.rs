@@ -0,0 +1,204 @@
+use crate::binary::cache::SymbolCache;
+use crate::binary::offsets;
+use crate::injector::{PolicyProviderManager, SC_LIBRARY_PATH};
+use crate::monitor::{STATUS_SECTION, take_map};
+use crate::status::Status;
+use anyhow::{Context, Result, bail};
+use aya::Ebpf;
+use aya::maps::{Array, HashMap, MapData};
+use aya::programs::UProbe;
+use nix::unistd::Uid;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use r3solvr::{BasicResolver, Query, SymbolResolver};
+use std::collections::HashSet;
+use std::fs;
+use tokio::sync::Notify;
+use tokio::task;
+use tracing::{debug, info, warn};
+use zynx_ebpf_shared::PER_USER_RANGE;
+
+/// Mangled `nativeForkAndSpecialize` of known releases, the uid is the third arg of all of them
+const FORK_SYMBOLS: [&str; 2] = [
+    "_ZN7androidL54com_android_internal_os_Zygote_nativeForkAndSpecializeEP7_JNIEnvP7_jclassiiP10_jintArrayiP13_jobjectArrayiP8_jstringS9_S5_S5_hS9_S9_hS7_S7_hh",
+    "_ZN7androidL54com_android_internal_os_Zygote_nativeForkAndSpecializeEP7_JNIEnvP7_jclassiiP10_jintArrayiP13_jobjectArrayiP8_jstringS9_S5_S5_hS9_S9_hS7_S7_hhh",
+];
+
+/// Offset of `nativeForkAndSpecialize` may also be provided in the offsets file, as
+/// `[rom.symbols.libandroid_runtime] nativeForkAndSpecialize = 0x...`
+const OFFSETS_LIBRARY: &str = "libandroid_runtime";
+const OFFSETS_SYMBOL: &str = "nativeForkAndSpecialize";
+
+const PROGRAM: &str = "uprobe__fork_and_specialize";
+
+const PT_LOAD: u32 = 1;
+
+static REFRESH: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Reload the target uids of the policy providers, whenever their state changes.
+pub fn request_refresh() {
+    REFRESH.notify_one();
+}
+
+fn resolve() -> Result<usize> {
+    if let Some(addr) = offsets::current().and_then(|it| it.symbol(OFFSETS_LIBRARY, OFFSETS_SYMBOL))
+    {
+        return Ok(addr);
+    }
+
+    let cache = SymbolCache::instance();
+
+    if let Some(addr) = FORK_SYMBOLS
+        .iter()
+        .find_map(|name| cache.get(SC_LIBRARY_PATH, name))
+    {
+        return Ok(addr);
+    }
+
+    let resolver = BasicResolver::from_file(SC_LIBRARY_PATH)?;
+    let sym = FORK_SYMBOLS
+        .iter()
+        .find_map(|&name| {
+            resolver
+                .lookup_symbol(Query::new(name).with_debugdata(true))
+                .ok()
+        })
+        .context("no nativeForkAndSpecialize symbol found in libandroid_runtime.so")?;
+
+    cache.insert(SC_LIBRARY_PATH, &sym.name, sym.addr);
+
+    Ok(sym.addr)
+}
+
+/// Offset in the file of `vaddr`, by the `PT_LOAD` segment mapping it. Uprobes are attached at
+/// file offsets, which differ from addresses past the first segment.
+pub(super) fn file_offset(path: &str, vaddr: usize) -> Result<u64> {
+    let data = fs::read(path)?;
+    let vaddr = vaddr as u64;
+
+    let read = |offset: u64, size: usize| -> Option<u64> {
+        let mut bytes = [0u8; 8];
+        let offset = usize::try_from(offset).ok()?;
+
+        bytes[..size].copy_from_slice(data.get(offset..offset + size)?);
+
+        Some(u64::from_le_bytes(bytes))
+    };
+
+    if data.get(..5) != Some(b"\x7fELF\x02".as_slice()) {
+        bail!("{path} is not a 64-bit ELF");
+    }
+
+    let (Some(phoff), Some(phentsize), Some(phnum)) = (read(0x20, 8), read(0x36, 2), read(0x38, 2))
+    else {
+        bail!("{path} is truncated");
+    };
+
+    for index in 0..phnum {
+        let header = phoff + index * phentsize;
+
+        let (Some(ty), Some(offset), Some(start), Some(size)) = (
+            read(header, 4),
+            read(header + 0x08, 8),
+            read(header + 0x10, 8),
+            read(header + 0x20, 8),
+        ) else {
+            bail!("{path} is truncated");
+        };
+
+        if ty == PT_LOAD as u64 && (start..start + size).contains(&vaddr) {
+            return Ok(vaddr - start + offset);
+        }
+    }
+
+    bail!("{vaddr:#x} isn't mapped from {path}")
+}
+
+/// Skips zygote forks of apps no policy provider targets, so that they are never stopped. Their
+/// uid isn't known at fork time yet, it's recorded from `nativeForkAndSpecialize` of the zygote
+/// by a uprobe. The filter is off while some provider can't tell its targets.
+pub struct UidFilter {
+    enabled: Mutex<Array<MapData, u8>>,
+    target_app_ids: Mutex<HashMap<MapData, u32, u8>>,
+}
+
+impl UidFilter {
+    pub fn new(ebpf: &mut Ebpf) -> Result<Self> {
+        let addr = resolve()?;
+        let offset = file_offset(SC_LIBRARY_PATH, addr)?;
+
+        let program: &mut UProbe = ebpf
+            .program_mut(PROGRAM)
+            .context(format!("no program {PROGRAM}"))?
+            .try_into()?;
+
+        program.load()?;
+        program.attach(offset, SC_LIBRARY_PATH, None, None)?;
+
+        info!("nativeForkAndSpecialize probed at {addr:#x} (file offset {offset:#x})");
+
+        Ok(Self {
+            enabled: Mutex::new(take_map(ebpf, "UID_FILTER")?),
+            target_app_ids: Mutex::new(take_map(ebpf, "TARGET_APP_IDS")?),
+        })
+    }
+
+    /// Filter by `uids`, or stop filtering if `None`. The filter is off while being updated,
+    /// and stays off if the update fails, so that no target is ever skipped.
+    fn apply(&self, uids: Option<HashSet<Uid>>) -> Result<()> {
+        let mut enabled = self.enabled.lock();
+
+        enabled.set(0, 0, 0)?;
+
+        let Some(uids) = uids else {
+            Status::instance().set(STATUS_SECTION, "uid_filter", "off");
+            return Ok(());
+        };
+
+        let app_ids: HashSet<u32> = uids
+            .iter()
+            .map(|uid| uid.as_raw() % PER_USER_RANGE)
+            .collect();
+        let mut targets = self.target_app_ids.lock();
+        let stale: Vec<u32> = targets
+            .keys()
+            .filter_map(Result::ok)
+            .filter(|app_id| !app_ids.contains(app_id))
+            .collect();
+
+        for app_id in stale {
+            targets.remove(&app_id)?;
+        }
+
+        for app_id in &app_ids {
+            targets.insert(app_id, 0, 0)?;
+        }
+
+        enabled.set(0, 1, 0)?;
+
+        debug!("uid filter targets: {app_ids:?}");
+        Status::instance().set(
+            STATUS_SECTION,
+            "uid_filter",
+            format!("{} apps", app_ids.len()),
+        );
+
+        Ok(())
+    }
+
+    /// Keep the targets in sync with the policy providers.
+    pub fn spawn(&'static self) {
+        task::spawn(async move {
+            loop {
+                let uids = PolicyProviderManager::instance().target_uids();
+
+                if let Err(err) = self.apply(uids) {
+                    warn!("failed to update the uid filter, disabled: {err:?}");
+                    Status::instance().set(STATUS_SECTION, "uid_filter", "error");
+                }
+
+                REFRESH.notified().await;
+            }
+        });
+    }
+}
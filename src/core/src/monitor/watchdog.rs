@@ -0,0 +1,171 @@
+use crate::injector::Shutdown;
+use crate::metrics::Metrics;
+use crate::monitor::Monitor;
+use anyhow::Result;
+use aya::maps::{MapData, PerCpuArray};
+use nix::sys::signal;
+use nix::sys::signal::{SigSet, SigmaskHow};
+use nix::unistd;
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+use tokio::{task, time};
+use tracing::{debug, warn};
+use zynx_ebpf_shared::Program;
+use zynx_misc::ext::ResultExt;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Heartbeat messages are expected within this window, otherwise the ring buffer is
+/// considered stalled
+const STALL_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Forks and exits happen all the time, silence this long means the program is gone
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Detects tracepoints detached behind our back (e.g. by another root tool) and stalls of
+/// the message channel, by probing the watchdog program with `getppid` and watching the
+/// hit counters of all programs. Messages dropped because the channel was full are reported
+/// along the way.
+pub struct Watchdog {
+    heartbeats: Mutex<PerCpuArray<MapData, u64>>,
+    last_heartbeat: Mutex<Instant>,
+}
+
+struct ProgramState {
+    hits: u64,
+    last_hit: Instant,
+}
+
+impl Watchdog {
+    pub fn new(heartbeats: PerCpuArray<MapData, u64>) -> Self {
+        Self {
+            heartbeats: Mutex::new(heartbeats),
+            last_heartbeat: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn on_heartbeat(&self) {
+        *self.last_heartbeat.lock() = Instant::now();
+    }
+
+    fn read_hits(&self, program: Program) -> Result<u64> {
+        let values = self.heartbeats.lock().get(&(program as u32), 0)?;
+        Ok(values.iter().fold(0, |sum, value| sum.wrapping_add(*value)))
+    }
+
+    /// How long the program may stay silent, `None` for programs which may legitimately
+    /// never fire.
+    fn max_silence(program: Program) -> Option<Duration> {
+        match program {
+            // triggered by our own probe
+            Program::Watchdog | Program::SysEnter => Some(Duration::ZERO),
+            Program::TaskNewTask | Program::SchedProcessExit => Some(IDLE_TIMEOUT),
+            Program::SchedProcessExec | Program::TaskRename | Program::SignalDeliver => None,
+        }
+    }
+
+    fn check(&self, states: &mut [ProgramState]) -> Result<Vec<String>> {
+        let now = Instant::now();
+        let mut problems = Vec::new();
+
+        for (program, state) in Program::ALL.into_iter().zip(states.iter_mut()) {
+            let hits = self.read_hits(program)?;
+
+            if hits != state.hits {
+                state.hits = hits;
+                state.last_hit = now;
+                continue;
+            }
+
+            if let Some(max_silence) = Self::max_silence(program)
+                && now.duration_since(state.last_hit) > max_silence
+            {
+                problems.push(format!("{program:?} stopped firing"));
+            }
+        }
+
+        let silence = self.last_heartbeat.lock().elapsed();
+
+        if silence > STALL_TIMEOUT {
+            problems.push(format!("message channel stalled for {silence:.2?}"));
+        }
+
+        Ok(problems)
+    }
+
+    fn reset(&self, states: &mut [ProgramState]) {
+        let now = Instant::now();
+
+        for state in states {
+            state.last_hit = now;
+        }
+
+        *self.last_heartbeat.lock() = now;
+    }
+
+    /// Spawn the background task probing the programs, reloading them when unhealthy.
+    pub fn spawn() {
+        task::spawn(async {
+            let monitor = Monitor::instance();
+            let Some(watchdog) = monitor.watchdog() else {
+                return;
+            };
+            let mut interval = time::interval(CHECK_INTERVAL);
+            let mut states: Vec<_> = Program::ALL
+                .into_iter()
+                .map(|program| ProgramState {
+                    hits: watchdog.read_hits(program).unwrap_or_default(),
+                    last_hit: Instant::now(),
+                })
+                .collect();
+
+            let mut dropped = monitor.dropped_messages().unwrap_or_default();
+
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+
+                if Shutdown::instance().is_requested() {
+                    break;
+                }
+
+                if let Some(total) = monitor.dropped_messages().inspect_log_error().ok()
+                    && total != dropped
+                {
+                    warn!(
+                        "{} eBPF messages dropped, consider raising --cfg-channel-size",
+                        total.wrapping_sub(dropped)
+                    );
+
+                    Metrics::instance().on_messages_dropped(total);
+                    dropped = total;
+                }
+
+                // hits `sys_enter` and the watchdog program, the latter replies with a heartbeat
+                let _ = unistd::getppid();
+
+                // the fentry variant of `sys_enter` only runs on `rt_sigprocmask`
+                let _ = signal::sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&SigSet::empty()), None);
+
+                let Some(problems) = watchdog.check(&mut states).inspect_log_error().ok() else {
+                    continue;
+                };
+
+                if problems.is_empty() {
+                    debug!("eBPF programs healthy");
+                    continue;
+                }
+
+                warn!(
+                    "health event: {}, reloading eBPF programs",
+                    problems.join(", ")
+                );
+
+                Metrics::instance().on_ebpf_reload();
+                monitor.reload_programs().log_if_error();
+                watchdog.reset(&mut states);
+            }
+        });
+    }
+}
@@ -0,0 +1,384 @@
+use crate::config::ZynxConfigs;
+use crate::misc::rooted;
+use crate::status::Status;
+use anyhow::{Context, Result, bail};
+use nix::unistd::Pid;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use procfs::process::Process;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::time::{Duration, SystemTime};
+use tokio::time;
+use tracing::{debug, info, warn};
+use zynx_misc::ext::ResultExt;
+
+pub const QUARANTINE_FILE: &str = "/data/adb/zynx/quarantine.toml";
+
+/// An injected process crashing within this window is counted as a failure of its modules
+const CRASH_WINDOW: Duration = Duration::from_secs(10);
+
+/// Written by debuggerd for processes killed by a fatal signal, as `tombstone_<nn>` along with
+/// a `.pb` twin
+const TOMBSTONE_DIR: &str = "/data/tombstones";
+
+/// Bytes of a tombstone holding its header, with the `pid: <pid>, tid: ...` line
+const TOMBSTONE_HEADER_LEN: u64 = 4096;
+
+static INSTANCE: Lazy<Quarantine> = Lazy::new(Quarantine::default);
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct ModuleRecord {
+    /// Consecutive crashes of processes the module was injected into
+    failures: u32,
+    total_failures: u64,
+    /// Panics of its handlers caught by the bridge, counted in failures as well
+    panics: u64,
+    last_panic: Option<String>,
+    quarantined: bool,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Records {
+    modules: BTreeMap<String, ModuleRecord>,
+}
+
+impl Records {
+    fn load() -> Result<Self> {
+        match fs::read_to_string(rooted(QUARANTINE_FILE)) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = rooted(QUARANTINE_FILE);
+        let temp = path.with_extension("tmp");
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&temp, toml::to_string(self)?)?;
+        fs::rename(&temp, path)?;
+
+        Ok(())
+    }
+
+    /// Mirror quarantined modules to the status, released ones drop out.
+    fn publish(&self) {
+        let status = Status::instance();
+
+        for (module, record) in &self.modules {
+            if record.quarantined {
+                status.set(
+                    "quarantine",
+                    module,
+                    format_args!(
+                        "{} failures, {} panics in total",
+                        record.total_failures, record.panics
+                    ),
+                );
+            } else {
+                status.remove("quarantine", module);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    mtime: Option<SystemTime>,
+    records: Records,
+}
+
+/// Per-module failure bookkeeping, persisted to [`QUARANTINE_FILE`] so quarantined modules
+/// stay disabled across reboots. Modules are named `<provider>:<name>`, e.g.
+/// `liteloader:com.example.app-hook`.
+#[derive(Default)]
+pub struct Quarantine {
+    state: Mutex<State>,
+    /// Modules that panicked in watched processes, not to be blamed again or cleared
+    reported: Mutex<HashMap<Pid, Vec<String>>>,
+}
+
+fn file_mtime() -> Option<SystemTime> {
+    fs::metadata(rooted(QUARANTINE_FILE))
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+impl Quarantine {
+    pub fn instance() -> &'static Self {
+        &INSTANCE
+    }
+
+    /// Pick up manual changes (e.g. `zynx quarantine --release`) made since the last access.
+    fn refresh(state: &mut State) {
+        let mtime = file_mtime();
+
+        if state.mtime == mtime {
+            return;
+        }
+
+        match Records::load() {
+            Ok(records) => {
+                // entries removed by hand don't show up in the new records
+                for module in state.records.modules.keys() {
+                    Status::instance().remove("quarantine", module);
+                }
+
+                records.publish();
+                state.records = records;
+                state.mtime = mtime;
+            }
+            Err(err) => warn!("failed to reload {QUARANTINE_FILE}: {err:?}, keeping old data"),
+        }
+    }
+
+    /// Apply `f` to the records, and persist them if it reports a change.
+    fn update(&self, f: impl FnOnce(&mut Records) -> bool) {
+        let mut state = self.state.lock();
+
+        Self::refresh(&mut state);
+
+        if !f(&mut state.records) {
+            return;
+        }
+
+        state.records.publish();
+
+        if state.records.save().inspect_log_error().is_ok() {
+            state.mtime = file_mtime();
+        }
+    }
+
+    /// Show quarantined modules in the status, kept up to date from then on.
+    pub fn publish(&self) {
+        Self::refresh(&mut self.state.lock());
+    }
+
+    pub fn is_quarantined(&self, module: &str) -> bool {
+        let mut state = self.state.lock();
+
+        Self::refresh(&mut state);
+
+        state
+            .records
+            .modules
+            .get(module)
+            .is_some_and(|record| record.quarantined)
+    }
+
+    fn on_failure(&self, modules: &[String], panic: Option<&str>) {
+        let threshold = ZynxConfigs::instance().quarantine_threshold;
+
+        self.update(|records| {
+            for module in modules {
+                let record = records.modules.entry(module.clone()).or_default();
+
+                record.failures += 1;
+                record.total_failures += 1;
+
+                if let Some(message) = panic {
+                    record.panics += 1;
+                    record.last_panic = Some(message.into());
+                }
+
+                if threshold > 0 && !record.quarantined && record.failures >= threshold {
+                    record.quarantined = true;
+                    warn!(
+                        "module {module} quarantined after {} consecutive failures, release it with `zynx quarantine --release {module}`",
+                        record.failures
+                    );
+                }
+            }
+
+            true
+        });
+    }
+
+    fn on_survival(&self, modules: &[String]) {
+        self.update(|records| {
+            let mut changed = false;
+
+            for module in modules {
+                if let Some(record) = records.modules.get_mut(module)
+                    && record.failures > 0
+                {
+                    record.failures = 0;
+                    changed = true;
+                }
+            }
+
+            changed
+        });
+    }
+
+    /// The bridge of `pid` caught a panic of `modules`, blame them even if the process lives.
+    pub fn on_crash_report(&self, pid: Pid, modules: &[String], message: &str) {
+        if modules.is_empty() {
+            return;
+        }
+
+        self.on_failure(modules, Some(message));
+
+        let mut reported = self.reported.lock();
+
+        // entries of processes not watched, or which exited before, are left behind otherwise
+        reported.retain(|pid, _| Process::new(pid.as_raw()).is_ok_and(|proc| proc.is_alive()));
+
+        // usually reported before the process is watched
+        reported.entry(pid).or_default().extend_from_slice(modules);
+    }
+
+    /// Watch a process just injected with `modules`, and blame them if it crashes soon after.
+    /// Exits, and kills e.g. by the low memory killer, leave no tombstone and blame nobody.
+    pub fn watch(pid: Pid, modules: Vec<String>) {
+        if modules.is_empty() {
+            return;
+        }
+
+        let Some(start_time) = Process::new(pid.as_raw())
+            .and_then(|proc| proc.stat())
+            .map(|stat| stat.starttime)
+            .ok()
+        else {
+            return;
+        };
+
+        let since = SystemTime::now();
+
+        tokio::spawn(async move {
+            time::sleep(CRASH_WINDOW).await;
+
+            let alive = Process::new(pid.as_raw())
+                .and_then(|proc| proc.stat())
+                .is_ok_and(|stat| stat.starttime == start_time);
+
+            let reported = Self::instance()
+                .reported
+                .lock()
+                .remove(&pid)
+                .unwrap_or_default();
+
+            // already blamed
+            let modules: Vec<_> = modules
+                .into_iter()
+                .filter(|module| !reported.contains(module))
+                .collect();
+
+            if alive {
+                Self::instance().on_survival(&modules);
+            } else if has_tombstone(pid, since) {
+                info!("process {pid} crashed within {CRASH_WINDOW:?} after injecting {modules:?}");
+                Self::instance().on_failure(&modules, None);
+            } else {
+                debug!("process {pid} exited within {CRASH_WINDOW:?} without crashing");
+            }
+        });
+    }
+}
+
+fn is_tombstone_of(header: &str, pid: Pid) -> bool {
+    let prefix = format!("pid: {pid}, ");
+    header.lines().any(|line| line.starts_with(&prefix))
+}
+
+/// Whether a tombstone of `pid` was written since `since`, that is it was killed by a fatal
+/// signal.
+fn has_tombstone(pid: Pid, since: SystemTime) -> bool {
+    let Ok(entries) = fs::read_dir(TOMBSTONE_DIR) else {
+        return false;
+    };
+
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_none())
+        .filter(|path| {
+            fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .is_ok_and(|mtime| mtime >= since)
+        })
+        .any(|path| {
+            let mut header = Vec::new();
+
+            File::open(&path)
+                .and_then(|file| file.take(TOMBSTONE_HEADER_LEN).read_to_end(&mut header))
+                .is_ok_and(|_| is_tombstone_of(&String::from_utf8_lossy(&header), pid))
+        })
+}
+
+/// Print module failure counters, or release a quarantined module.
+pub fn manage_quarantine(release: Option<&str>) -> Result<()> {
+    let mut records = Records::load().context(format!("failed to read {QUARANTINE_FILE}"))?;
+
+    if let Some(module) = release {
+        let Some(record) = records.modules.get_mut(module) else {
+            bail!("unknown module: {module}");
+        };
+
+        record.failures = 0;
+        record.quarantined = false;
+        records.save()?;
+
+        println!("released {module}");
+        return Ok(());
+    }
+
+    if records.modules.is_empty() {
+        println!("no module failures recorded");
+    }
+
+    for (module, record) in &records.modules {
+        let status = if record.quarantined {
+            "quarantined"
+        } else {
+            "active"
+        };
+
+        println!(
+            "{module}: {status}, {} consecutive failures, {} in total, {} panics",
+            record.failures, record.total_failures, record.panics
+        );
+
+        if let Some(message) = &record.last_panic {
+            println!("  last panic: {message}");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "\
+*** *** *** *** *** *** *** *** *** *** *** *** *** *** *** ***
+Build fingerprint: 'google/husky/husky:14/AP1A/1:user/release-keys'
+Revision: 'MP1.0'
+ABI: 'arm64'
+Timestamp: 2026-10-18 10:00:00.000000000+0000
+Process uptime: 3s
+Cmdline: com.example.app
+pid: 12345, tid: 12360, name: RenderThread  >>> com.example.app <<<
+uid: 10123
+signal 11 (SIGSEGV), code 1 (SEGV_MAPERR), fault addr 0x0
+";
+
+    #[test]
+    fn tombstone_matches_its_pid() {
+        assert!(is_tombstone_of(HEADER, Pid::from_raw(12345)));
+        assert!(!is_tombstone_of(HEADER, Pid::from_raw(1234)));
+        // tids aren't pids
+        assert!(!is_tombstone_of(HEADER, Pid::from_raw(12360)));
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::Path;
+
+const TEMPLATES: &[(&str, &str)] = &[
+    (
+        "Cargo.toml",
+        include_str!("../templates/module/Cargo.toml.tmpl"),
+    ),
+    (
+        "src/lib.rs",
+        include_str!("../templates/module/lib.rs.tmpl"),
+    ),
+    (
+        "{{name}}.toml",
+        include_str!("../templates/module/manifest.toml.tmpl"),
+    ),
+    (
+        "README.md",
+        include_str!("../templates/module/README.md.tmpl"),
+    ),
+];
+
+fn is_valid_name(name: &str) -> bool {
+    name.starts_with(|ch: char| ch.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '-' || ch == '_')
+}
+
+/// Create a buildable LiteLoader native module crate named `name` under `parent`.
+pub fn new_module(name: &str, parent: &Path) -> Result<()> {
+    if !is_valid_name(name) {
+        bail!("invalid module name: {name}, use lowercase letters, digits, `-` and `_`");
+    }
+
+    let root = parent.join(name);
+
+    if root.exists() {
+        bail!("{} already exists", root.display());
+    }
+
+    let lib_name = name.replace('-', "_");
+    let render = |template: &str| {
+        template
+            .replace("{{name}}", name)
+            .replace("{{lib_name}}", &lib_name)
+    };
+
+    for (path, template) in TEMPLATES {
+        let path = root.join(render(path));
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&path, render(template))
+            .context(format!("failed to write {}", path.display()))?;
+    }
+
+    println!("created module {name} at {}", root.display());
+    println!(
+        "see {} to build and test it",
+        root.join("README.md").display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,249 @@
+use crate::android::root::RootManager;
+use crate::config::ZynxConfigs;
+use crate::injector::preflight;
+use anyhow::{Context, Result, bail};
+use nix::libc::O_NONBLOCK;
+use nix::time::{ClockId, clock_gettime};
+use nix::unistd::Pid;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::time::Duration;
+use tracing::{info, warn};
+use zynx_misc::ext::ResultExt;
+
+/// Kernel log, where logd also writes the audit records it receives
+const KMSG: &str = "/dev/kmsg";
+
+/// Rules applied by the running daemon, not to apply them again on every failure
+static APPLIED: Lazy<Mutex<HashSet<String>>> = Lazy::new(Mutex::default);
+
+/// An `avc: denied` audit record.
+#[derive(Debug)]
+struct Denial {
+    pid: i32,
+    perms: Vec<String>,
+    source: String,
+    target: String,
+    class: String,
+}
+
+impl Denial {
+    fn parse(record: &str) -> Option<Self> {
+        let (_, record) = record.split_once("avc:")?;
+        let record = record.trim_start().strip_prefix("denied")?;
+
+        let (perms, fields) = record.split_once('}')?;
+        let perms = perms.trim_start().strip_prefix('{')?;
+
+        let fields: BTreeMap<_, _> = fields
+            .split_whitespace()
+            .filter_map(|field| field.split_once('='))
+            .collect();
+
+        // allowed anyway, so not what made the injection fail
+        if fields.get("permissive") == Some(&"1") {
+            return None;
+        }
+
+        Some(Self {
+            pid: fields.get("pid")?.parse().ok()?,
+            perms: perms.split_whitespace().map(String::from).collect(),
+            source: type_of(fields.get("scontext")?).into(),
+            target: type_of(fields.get("tcontext")?).into(),
+            class: fields.get("tclass")?.to_string(),
+        })
+    }
+}
+
+/// Type of a `user:role:type:level` context, as used in policy rules.
+fn type_of(context: &str) -> &str {
+    context.split(':').nth(2).unwrap_or(context)
+}
+
+/// Current time on the clock of kernel log records, which stops while suspended.
+pub fn log_time() -> Duration {
+    clock_gettime(ClockId::CLOCK_MONOTONIC)
+        .map(Duration::from)
+        .unwrap_or_default()
+}
+
+/// Records currently in the kernel log buffer with their time, parsed from the
+/// `prio,seq,time,flags;` prefix.
+fn read_kmsg() -> Result<Vec<(Duration, String)>> {
+    let mut kmsg = OpenOptions::new()
+        .read(true)
+        .custom_flags(O_NONBLOCK)
+        .open(KMSG)
+        .context(format!("failed to open {KMSG}"))?;
+
+    let mut records = Vec::new();
+    let mut buffer = vec![0u8; 8192];
+
+    loop {
+        // each read returns a single record
+        let len = match kmsg.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            // records were overwritten while reading, continues with the oldest one
+            Err(err) if err.raw_os_error() == Some(nix::libc::EPIPE) => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        let record = String::from_utf8_lossy(&buffer[..len]);
+
+        let Some((prefix, message)) = record.split_once(';') else {
+            continue;
+        };
+
+        let Some(time) = prefix.split(',').nth(2).and_then(|time| time.parse().ok()) else {
+            continue;
+        };
+
+        records.push((
+            Duration::from_micros(time),
+            message.lines().next().unwrap_or_default().into(),
+        ));
+    }
+
+    Ok(records)
+}
+
+/// Denials of `pid`, or of any process, found in the kernel log, logged at `since` or later.
+fn collect_denials(pid: Option<Pid>, since: Duration) -> Result<Vec<Denial>> {
+    Ok(read_kmsg()?
+        .iter()
+        .filter(|(time, _)| *time >= since)
+        .filter_map(|(_, record)| Denial::parse(record))
+        .filter(|denial| pid.is_none_or(|pid| denial.pid == pid.as_raw()))
+        .collect())
+}
+
+/// The minimal `allow` rules covering `denials`, one per source, target and class.
+fn rules_of(denials: &[Denial]) -> Vec<String> {
+    let mut merged: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
+
+    for denial in denials {
+        merged
+            .entry((&denial.source, &denial.target, &denial.class))
+            .or_default()
+            .extend(denial.perms.iter().map(String::as_str));
+    }
+
+    merged
+        .into_iter()
+        .map(|((source, target, class), perms)| {
+            let perms: Vec<_> = perms.into_iter().collect();
+            format!("allow {source} {target} {class} {{ {} }}", perms.join(" "))
+        })
+        .collect()
+}
+
+/// Patch the live policy through the root manager.
+fn apply(rules: &[String]) -> Result<()> {
+    let mut command = RootManager::instance()
+        .context("no root manager found to patch the policy")?
+        .sepolicy_command(rules);
+
+    let output = command
+        .output()
+        .context(format!("failed to run {command:?}"))?;
+
+    if !output.status.success() {
+        bail!(
+            "{command:?} failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+/// Look for SELinux denials of `pid` logged since its injection started at `since`, and report
+/// the rules that would allow them. Only rules granting access the preflight checks for are
+/// applied, if enabled; anything else the process was denied may have nothing to do with us.
+pub fn on_injection_failed(pid: Pid, since: Duration) {
+    let Some(mut denials) = collect_denials(Some(pid), since).ok_or_warn() else {
+        return;
+    };
+
+    let others = rules_of(&denials);
+
+    for denial in &mut denials {
+        denial
+            .perms
+            .retain(|perm| preflight::is_required(&denial.class, perm));
+    }
+
+    denials.retain(|denial| !denial.perms.is_empty());
+
+    let rules = rules_of(&denials);
+    let others: Vec<_> = others
+        .into_iter()
+        .filter(|rule| !rules.contains(rule))
+        .collect();
+
+    if !others.is_empty() {
+        info!(
+            "{pid} was also denied, not needed by the injection:\n{}",
+            others.join("\n")
+        );
+    }
+
+    if rules.is_empty() {
+        return;
+    }
+
+    warn!(
+        "{pid} injection failed with SELinux denials, missing rules:\n{}",
+        rules.join("\n")
+    );
+
+    if !ZynxConfigs::instance().apply_sepolicy {
+        return;
+    }
+
+    let rules: Vec<_> = {
+        let applied = APPLIED.lock();
+        rules
+            .into_iter()
+            .filter(|rule| !applied.contains(rule))
+            .collect()
+    };
+
+    if rules.is_empty() {
+        return;
+    }
+
+    if apply(&rules).inspect_log_error().is_ok() {
+        info!("applied {} sepolicy rules", rules.len());
+        APPLIED.lock().extend(rules);
+    }
+}
+
+/// Print rules for the SELinux denials in the kernel log, of `pid` if given, and apply them
+/// if `apply_rules` is set.
+pub fn print_rules(pid: Option<i32>, apply_rules: bool) -> Result<()> {
+    let rules = rules_of(&collect_denials(pid.map(Pid::from_raw), Duration::ZERO)?);
+
+    if rules.is_empty() {
+        println!("no SELinux denials found");
+        return Ok(());
+    }
+
+    for rule in &rules {
+        println!("{rule}");
+    }
+
+    if apply_rules {
+        apply(&rules)?;
+        println!("applied {} rules", rules.len());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time;
+use tracing::debug;
+use zynx_misc::ext::ResultExt;
+
+pub const STATUS_FILE: &str = "/data/adb/zynx/status";
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The status is rewritten at least this often, so that a stale `updated` tells the daemon
+/// is gone
+const MAX_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+static INSTANCE: Lazy<Status> = Lazy::new(Status::default);
+
+/// Health of daemon components, as `key: value` lines per section, periodically dumped to
+/// [`STATUS_FILE`]. Unlike metrics, entries describe the current state and are overwritten.
+#[derive(Default)]
+pub struct Status {
+    sections: Mutex<BTreeMap<&'static str, BTreeMap<String, String>>>,
+    dirty: AtomicBool,
+}
+
+impl Status {
+    pub fn instance() -> &'static Self {
+        &INSTANCE
+    }
+
+    pub fn set(&self, section: &'static str, key: &str, value: impl Display) {
+        self.sections
+            .lock()
+            .entry(section)
+            .or_default()
+            .insert(key.into(), value.to_string());
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn remove(&self, section: &'static str, key: &str) {
+        let mut sections = self.sections.lock();
+
+        if let Some(entries) = sections.get_mut(section) {
+            entries.remove(key);
+
+            if entries.is_empty() {
+                sections.remove(section);
+            }
+        }
+
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    fn render(&self) -> String {
+        let mut output = String::new();
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let _ = writeln!(output, "updated: {now}");
+
+        for (section, entries) in self.sections.lock().iter() {
+            let _ = writeln!(output, "\n[{section}]");
+
+            for (key, value) in entries {
+                let _ = writeln!(output, "{key}: {value}");
+            }
+        }
+
+        output
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.dirty.store(false, Ordering::Relaxed);
+
+        let path = Path::new(STATUS_FILE);
+        let temp = path.with_extension("tmp");
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&temp, self.render())?;
+        fs::rename(&temp, path)?;
+
+        debug!("status flushed to {STATUS_FILE}");
+
+        Ok(())
+    }
+
+    /// Spawn the background task dumping the status to [`STATUS_FILE`].
+    pub fn spawn_writer() {
+        tokio::spawn(async {
+            let mut interval = time::interval(FLUSH_INTERVAL);
+            let mut last_flush: Option<Instant> = None;
+
+            loop {
+                interval.tick().await;
+
+                let status = Self::instance();
+
+                if status.dirty.load(Ordering::Relaxed)
+                    || last_flush.is_none_or(|time| time.elapsed() >= MAX_FLUSH_INTERVAL)
+                {
+                    status.flush().log_if_error();
+                    last_flush = Some(Instant::now());
+                }
+            }
+        });
+    }
+}
+
+/// Entries of `section` in the status file written by a running daemon.
+pub fn read_section(section: &str) -> Result<BTreeMap<String, String>> {
+    let content = fs::read_to_string(STATUS_FILE).context(format!(
+        "failed to read {STATUS_FILE}, is the daemon running?"
+    ))?;
+
+    let header = format!("[{section}]");
+    let entries = content
+        .lines()
+        .skip_while(|line| *line != header)
+        .skip(1)
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(|line| line.split_once(": "))
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect();
+
+    Ok(entries)
+}
+
+/// Print the status file written by a running daemon.
+pub fn print_status() -> Result<()> {
+    let content = fs::read_to_string(STATUS_FILE).context(format!(
+        "failed to read {STATUS_FILE}, is the daemon running?"
+    ))?;
+
+    print!("{content}");
+
+    Ok(())
+}
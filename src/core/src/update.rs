@@ -0,0 +1,200 @@
+use crate::daemon::ENV_LAUNCHER_PID;
+use crate::integrity;
+use crate::integrity::Verification;
+use anyhow::{Context, Result, bail, ensure};
+use nix::sys::signal;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use sha2::{Digest, Sha256};
+use std::fs::{File, Permissions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+use std::{fs, process, thread};
+use tracing::info;
+
+pub const UPDATE_DIR: &str = "/data/adb/zynx/update";
+
+/// The next daemon binary, the bridge is embedded in it
+const STAGED_BINARY: &str = "/data/adb/zynx/update/zynx";
+
+/// How long the running daemon gets to release its tracees and exit
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn write_synced(path: &Path, data: &[u8], mode: u32) -> Result<()> {
+    let mut file = File::create(path)?;
+
+    file.write_all(data)?;
+    file.set_permissions(Permissions::from_mode(mode))?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Verify `binary` against its `.sig` file, and stage it to replace the daemon binary the next
+/// time the daemon starts. A `.sha256` file alone only proves the copy is intact, not where
+/// the binary comes from, so it's not enough for the daemon running as root.
+pub fn stage(binary: &Path) -> Result<()> {
+    let data = fs::read(binary).context(format!("failed to read {}", binary.display()))?;
+
+    ensure!(
+        data.starts_with(b"\x7fELF"),
+        "{} is not an ELF",
+        binary.display()
+    );
+
+    if !matches!(
+        integrity::verify_sidecars(binary, &data)?,
+        Verification::Signature
+    ) {
+        bail!("no .sig file, refusing to stage an unsigned update");
+    }
+
+    let staged = Path::new(STAGED_BINARY);
+    let temp = staged.with_extension("tmp");
+
+    fs::create_dir_all(UPDATE_DIR)?;
+
+    // the digest is checked again when applied, the binary appearing commits the update
+    let digest = hex::encode(Sha256::digest(&data));
+    write_synced(&staged.with_extension("sha256"), digest.as_bytes(), 0o600)?;
+    write_synced(&temp, &data, 0o755)?;
+    fs::rename(&temp, staged)?;
+
+    info!("staged update from {}", binary.display());
+
+    Ok(())
+}
+
+/// Swap `exe` for the staged binary, if any, keeping the replaced one as `<exe>.old`.
+/// Returns whether an update got applied.
+pub fn apply_staged(exe: &Path) -> Result<bool> {
+    let staged = Path::new(STAGED_BINARY);
+    let digest = staged.with_extension("sha256");
+
+    let data = match fs::read(staged) {
+        Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+
+    let verified = integrity::verify_sidecars(staged, &data);
+
+    remove_if_exists(staged)?;
+    remove_if_exists(&digest)?;
+
+    if !matches!(verified?, Verification::Digest) {
+        bail!("staged update has no digest, discarded");
+    }
+
+    // copied next to `exe` first, so that the final rename stays on the same filesystem
+    let new = exe.with_extension("new");
+    let old = exe.with_extension("old");
+
+    write_synced(&new, &data, 0o755)?;
+    remove_if_exists(&old)?;
+    fs::hard_link(exe, &old)?;
+    fs::rename(&new, exe)?;
+
+    info!("applied staged update to {}", exe.display());
+
+    Ok(true)
+}
+
+/// The running daemon, found by the environment variable set by its launcher
+pub fn find_daemon() -> Result<Option<procfs::process::Process>> {
+    let me = process::id() as i32;
+
+    for process in procfs::process::all_processes()?.flatten() {
+        if process.pid == me {
+            continue;
+        }
+
+        let Ok(environ) = process.environ() else {
+            continue;
+        };
+
+        let is_zynx = process
+            .exe()
+            .ok()
+            .and_then(|exe| {
+                exe.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .is_some_and(|name| name.starts_with("zynx"));
+
+        // only the daemon has it, set by its launcher
+        if is_zynx && environ.keys().any(|key| *key == *ENV_LAUNCHER_PID) {
+            return Ok(Some(process));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Stop the running daemon and launch it again with the same args, applying the staged
+/// update on the way.
+pub fn restart_daemon() -> Result<()> {
+    let daemon = find_daemon()?.context("daemon is not running")?;
+    let args: Vec<String> = daemon.cmdline()?.into_iter().skip(1).collect();
+    let envs: Vec<_> = daemon
+        .environ()?
+        .into_iter()
+        .filter(|(key, _)| *key != *ENV_LAUNCHER_PID)
+        .collect();
+
+    // replaced binaries get a ` (deleted)` suffix, relaunch from the same path all the same
+    let exe = daemon.exe()?;
+    let exe = PathBuf::from(
+        exe.to_string_lossy()
+            .trim_end_matches(" (deleted)")
+            .to_string(),
+    );
+
+    info!("stopping daemon {}", daemon.pid);
+
+    signal::kill(Pid::from_raw(daemon.pid), Signal::SIGTERM)?;
+
+    let start = Instant::now();
+    while daemon.is_alive() {
+        if start.elapsed() > STOP_TIMEOUT {
+            bail!("daemon {} didn't exit in {STOP_TIMEOUT:?}", daemon.pid);
+        }
+
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let status = Command::new(exe)
+        .arg("daemon")
+        .args(&args)
+        .env_clear()
+        .envs(envs)
+        .status()?;
+
+    ensure!(status.success(), "failed to launch daemon: {status}");
+
+    Ok(())
+}
+
+/// Stage `binary`, and restart the daemon right away if asked to, instead of on next boot.
+pub fn run_update(binary: &Path, restart: bool) -> Result<()> {
+    stage(binary)?;
+
+    if restart {
+        restart_daemon()?;
+        println!("daemon restarted with the update");
+    } else {
+        println!("update staged, applied on the next start of the daemon");
+    }
+
+    Ok(())
+}
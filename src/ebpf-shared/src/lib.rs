@@ -4,6 +4,140 @@
 pub enum Message {
     PathMatches(i32, [u8; 128]),
     NameMatches(i32, [u8; 16]),
-    ZygoteFork(i32),
+    ZygoteFork(i32, i32),
+    /// An embryo reached `SpecializeCommon`, caught by its uprobe instead of a fork
+    ZygoteSpecialize(i32, i32),
     ZygoteCrashed(i32),
+    /// Emitted by the watchdog program when probed by the daemon
+    Heartbeat(u64),
+}
+
+/// Slots of the `HEARTBEATS` map, bumped by each program on every hit
+#[repr(u32)]
+#[derive(Copy, Clone, Debug)]
+pub enum Program {
+    TaskNewTask,
+    SchedProcessExec,
+    TaskRename,
+    SysEnter,
+    SignalDeliver,
+    SchedProcessExit,
+    Watchdog,
+}
+
+impl Program {
+    pub const ALL: [Program; 7] = [
+        Program::TaskNewTask,
+        Program::SchedProcessExec,
+        Program::TaskRename,
+        Program::SysEnter,
+        Program::SignalDeliver,
+        Program::SchedProcessExit,
+        Program::Watchdog,
+    ];
+}
+
+/// Slots of the `MAP_FAILURES` map, bumped when a program fails to record a task, mostly because
+/// the map is full. All of them hold up to `max_tasks` entries.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug)]
+pub enum TrackedMap {
+    InitChildren,
+    ServiceTree,
+    ZygoteChildren,
+}
+
+impl TrackedMap {
+    pub const ALL: [TrackedMap; 3] = [
+        TrackedMap::InitChildren,
+        TrackedMap::ServiceTree,
+        TrackedMap::ZygoteChildren,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            TrackedMap::InitChildren => "INIT_CHILDREN",
+            TrackedMap::ServiceTree => "SERVICE_TREE",
+            TrackedMap::ZygoteChildren => "ZYGOTE_CHILDREN",
+        }
+    }
+}
+
+/// Registers of an embryo at the uprobe of `SpecializeCommon`, the leading words of
+/// `struct pt_regs`, as many as the architecture lays out like `user_regs_struct`
+pub type EntryRegs = [u64; 34];
+
+/// Offset of a tracepoint field the running kernel doesn't have
+pub const FIELD_ABSENT: u32 = u32::MAX;
+
+/// Uids of each Android user span this range, apps share their app id (`uid % PER_USER_RANGE`)
+pub const PER_USER_RANGE: u32 = 100000;
+
+#[cfg(not(target_arch = "x86_64"))]
+const TIF_32BIT: u32 = 22;
+#[cfg(target_arch = "x86_64")]
+const TIF_32BIT: u32 = 29;
+
+/// Offsets of the tracepoint fields read by the programs, from the start of the record. Probed
+/// by the daemon from tracefs at load time, as the programs can't be relocated with BTF.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct KernelLayout {
+    /// Bit of `TIF_32BIT` in `thread_info.flags`, at the start of `task_struct` on arm64
+    /// (`TIF_ADDR32` on x86_64)
+    pub tif_32bit: u32,
+    pub task_newtask_pid: u32,
+    pub task_newtask_clone_flags: u32,
+    /// `__data_loc` of the filename
+    pub sched_process_exec_filename: u32,
+    pub sched_process_exec_pid: u32,
+    /// Absent on recent kernels, the renamed task is the current one then
+    pub task_rename_pid: u32,
+    pub task_rename_newcomm: u32,
+    pub sys_enter_id: u32,
+    pub sys_enter_args: u32,
+    pub signal_deliver_sig: u32,
+    pub signal_deliver_code: u32,
+    pub sched_process_exit_pid: u32,
+}
+
+impl KernelLayout {
+    /// Layout of GKI kernels up to 6.12
+    pub const DEFAULT: KernelLayout = KernelLayout {
+        tif_32bit: TIF_32BIT,
+        task_newtask_pid: 8,
+        task_newtask_clone_flags: 32,
+        sched_process_exec_filename: 8,
+        sched_process_exec_pid: 12,
+        task_rename_pid: 8,
+        task_rename_newcomm: 28,
+        sys_enter_id: 8,
+        sys_enter_args: 16,
+        signal_deliver_sig: 8,
+        signal_deliver_code: 16,
+        sched_process_exit_pid: 24,
+    };
+}
+
+/// How the programs stop the processes they report, by the helpers the kernel has
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SignalMode {
+    /// `bpf_send_signal_thread`, Linux 5.5
+    Thread,
+    /// `bpf_send_signal`, Linux 5.3, stopping the whole thread group
+    Process,
+    /// No helper, the daemon stops the processes once it receives the message
+    Daemon,
+}
+
+/// Matches which are only reported, leaving the processes running, to watch what would be
+/// injected without injecting anything
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ObserveOnly {
+    /// Services matched by path
+    pub services: bool,
+    /// Processes forked from zygotes
+    pub embryos: bool,
 }
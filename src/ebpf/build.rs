@@ -14,4 +14,7 @@ use which::which;
 fn main() {
     let bpf_linker = which("bpf-linker").expect("bpf-linker not found");
     println!("cargo:rerun-if-changed={}", bpf_linker.to_str().unwrap());
+
+    // set by aya-build to the architecture of the daemon
+    println!("cargo::rustc-check-cfg=cfg(bpf_target_arch, values(\"aarch64\", \"x86_64\"))");
 }
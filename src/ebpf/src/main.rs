@@ -3,39 +3,123 @@
 #![allow(static_mut_refs)]
 #![allow(non_snake_case)]
 
-use aya_ebpf::bindings::{BPF_ANY, BPF_EXIST, BPF_NOEXIST};
-use aya_ebpf::macros::{map, tracepoint};
-use aya_ebpf::maps::{Array, HashMap, RingBuf};
-use aya_ebpf::programs::TracePointContext;
+use aya_ebpf::bindings::{BPF_EXIST, BPF_NOEXIST};
+use aya_ebpf::macros::{fentry, map, raw_tracepoint, tracepoint, uprobe};
+use aya_ebpf::maps::{Array, HashMap, PerCpuArray, RingBuf};
+use aya_ebpf::programs::{FEntryContext, ProbeContext, RawTracePointContext, TracePointContext};
 use aya_ebpf::{EbpfContext, helpers};
 use aya_log_ebpf::{debug, info, warn};
-use zynx_ebpf_shared::Message;
+use core::ptr;
+use zynx_ebpf_shared::{
+    EntryRegs, FIELD_ABSENT, KernelLayout, Message, ObserveOnly, PER_USER_RANGE, Program,
+    SignalMode, TrackedMap,
+};
 
 const DEBUG: bool = option_env!("DEBUG_EBPF").is_some();
-const EVENT_PARAMS_OFFSET: usize = 8;
 const INIT_PID: i32 = 1;
 const FIRST_APP_UID: u64 = 10000;
 const SIGSTOP: u32 = 19;
 const SIGCONT: u32 = 18;
 const SIGTRAP: u32 = 5;
-
+#[cfg(not(bpf_target_arch = "x86_64"))]
+const NR_RT_SIGPROCMASK: i64 = 135;
+#[cfg(bpf_target_arch = "x86_64")]
+const NR_RT_SIGPROCMASK: i64 = 14;
+
+/// Words of `struct pt_regs` kept in [`EntryRegs`], `regs[31]`, `sp`, `pc` and `pstate` on arm64,
+/// up to `ss` on x86_64
+#[cfg(not(bpf_target_arch = "x86_64"))]
+const PT_REGS_WORDS: usize = 34;
+#[cfg(bpf_target_arch = "x86_64")]
+const PT_REGS_WORDS: usize = 21;
+
+/// Offset of the first syscall arg in `struct pt_regs`, `regs[0]` on arm64 and `di` on x86_64
+#[cfg(not(bpf_target_arch = "x86_64"))]
+const PT_REGS_ARG0: usize = 0;
+#[cfg(bpf_target_arch = "x86_64")]
+const PT_REGS_ARG0: usize = 14 * 8;
+
+/// Capacities of the target and task maps are set by the daemon at load time
 #[map]
 static mut TARGET_PATHS: HashMap<[u8; 128], u8> = HashMap::with_max_entries(0x100, 0);
 
 #[map]
 static mut TARGET_NAMES: HashMap<[u8; 16], u8> = HashMap::with_max_entries(0x100, 0);
 
+/// Resized by the daemon at load time
 #[map]
 static mut MESSAGE_CHANNEL: RingBuf = RingBuf::with_byte_size(0x1000, 0);
 
+/// Messages which didn't fit into `MESSAGE_CHANNEL`
+#[map]
+static mut DROPPED_MESSAGES: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
 #[map]
 static mut INIT_CHILDREN: HashMap<i32, u8> = HashMap::with_max_entries(0x1000, 0);
 
+/// Descendants of init whose children are tracked too, by their depth below init
+#[map]
+static mut SERVICE_TREE: HashMap<i32, u32> = HashMap::with_max_entries(0x1000, 0);
+
+#[map]
+static mut ZYGOTE_PIDS: HashMap<i32, u8> = HashMap::with_max_entries(0x10, 0);
+
+#[map]
+static mut ZYGOTE_CHILDREN: HashMap<i32, EmbryoInfo> = HashMap::with_max_entries(0x1000, 0);
+
+/// App ids some policy provider may inject into, maintained by the daemon
+#[map]
+static mut TARGET_APP_IDS: HashMap<u32, u8> = HashMap::with_max_entries(0x1000, 0);
+
+/// Whether zygote forks are filtered by `TARGET_APP_IDS`, toggled by the daemon at runtime
+#[map]
+static mut UID_FILTER: Array<u8> = Array::with_max_entries(1, 0);
+
+/// Whether embryos are stopped by the uprobe of `SpecializeCommon` instead of after fork,
+/// set by the daemon once it's attached
 #[map]
-static mut ZYGOTE_INFO: Array<i32> = Array::with_max_entries(1, 0);
+static mut SPECIALIZE_PROBE: Array<u8> = Array::with_max_entries(1, 0);
 
+/// Registers of embryos stopped by the uprobe of `SpecializeCommon`, consumed by the daemon
 #[map]
-static mut ZYGOTE_CHILDREN: HashMap<i32, u8> = HashMap::with_max_entries(0x1000, 0);
+static mut ENTRY_REGS: HashMap<i32, EntryRegs> = HashMap::with_max_entries(0x400, 0);
+
+/// Uid of the `nativeForkAndSpecialize` each zygote is in, consumed by its next fork
+#[map]
+static mut PENDING_UIDS: HashMap<i32, u32> = HashMap::with_max_entries(0x10, 0);
+
+#[map]
+static mut HEARTBEATS: PerCpuArray<u64> =
+    PerCpuArray::with_max_entries(Program::ALL.len() as u32, 0);
+
+/// Failed inserts into the maps tracking tasks, see [`TrackedMap`]
+#[map]
+static mut MAP_FAILURES: PerCpuArray<u64> =
+    PerCpuArray::with_max_entries(TrackedMap::ALL.len() as u32, 0);
+
+/// Set by the daemon at load time, only its probes are answered by the watchdog
+#[unsafe(no_mangle)]
+static DAEMON_PID: i32 = 0;
+
+/// Set by the daemon at load time, probed from the formats of the tracepoints
+#[unsafe(no_mangle)]
+static KERNEL_LAYOUT: KernelLayout = KernelLayout::DEFAULT;
+
+/// Set by the daemon at load time, helpers the kernel lacks are never reached
+#[unsafe(no_mangle)]
+static SIGNAL_MODE: SignalMode = SignalMode::Thread;
+
+/// Set by the daemon at load time, processes up to this depth below init are matched by path and
+/// name, e.g. services started by a launcher wrapper
+#[unsafe(no_mangle)]
+static SERVICE_DEPTH: u32 = 1;
+
+/// Set by the daemon at load time, these matches are reported without being stopped
+#[unsafe(no_mangle)]
+static OBSERVE_ONLY: ObserveOnly = ObserveOnly {
+    services: false,
+    embryos: false,
+};
 
 #[repr(u8)]
 #[derive(Copy, Clone)]
@@ -62,14 +146,28 @@ impl From<EmbryoState> for u8 {
     }
 }
 
-trait TracePointEvent {
-    fn from_context(ctx: &TracePointContext) -> &Self;
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct EmbryoInfo {
+    zygote: i32,
+    state: u8,
+}
+
+#[inline(always)]
+fn layout() -> KernelLayout {
+    unsafe { ptr::read_volatile(&KERNEL_LAYOUT) }
 }
 
-impl<T> TracePointEvent for T {
-    fn from_context(ctx: &TracePointContext) -> &Self {
-        unsafe { &*(ctx.as_ptr().add(EVENT_PARAMS_OFFSET) as *const _) }
-    }
+#[inline(always)]
+fn observe_only() -> ObserveOnly {
+    unsafe { ptr::read_volatile(&OBSERVE_ONLY) }
+}
+
+/// Read a field of the tracepoint record, offsets aren't known to the verifier so the record is
+/// read like any kernel memory.
+#[inline(always)]
+fn read_field<T>(ctx: &TracePointContext, offset: u32) -> Option<T> {
+    unsafe { ctx.read_at(offset as usize).ok() }
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -79,6 +177,11 @@ fn current_pid() -> i32 {
     (helpers::bpf_get_current_pid_tgid() & 0xffffffff) as i32
 }
 
+#[inline(always)]
+fn current_tgid() -> i32 {
+    (helpers::bpf_get_current_pid_tgid() >> 32) as i32
+}
+
 #[inline(always)]
 fn current_is_privileged() -> bool {
     helpers::bpf_get_current_uid_gid() & 0xffffffff < FIRST_APP_UID
@@ -110,7 +213,7 @@ fn current_is_32bit() -> bool {
 
         if let Ok(thread_info) = helpers::bpf_probe_read_kernel(&(*task).thread_info) {
             let flags = thread_info.flags;
-            is32bit = (flags >> 22/* TIF_32BIT */) & 1 != 0
+            is32bit = (flags >> layout().tif_32bit) & 1 != 0
         }
     }
 
@@ -142,27 +245,74 @@ fn hashmap_contains<K, V>(map: &HashMap<K, V>, key: &K) -> bool {
     map.get_ptr(key).is_some()
 }
 
+/// Whether a zygote child specialized to `uid` may be injected into, unknown uids are.
 #[inline(always)]
-fn sigstop() {
+fn is_target_uid(uid: Option<u32>) -> bool {
+    let Some(uid) = uid else {
+        return true;
+    };
+
     unsafe {
-        helpers::bpf_send_signal_thread(SIGSTOP);
+        let enabled = UID_FILTER.get(0).is_some_and(|enabled| *enabled != 0);
+
+        !enabled || hashmap_contains(&TARGET_APP_IDS, &(uid % PER_USER_RANGE))
     }
 }
 
 #[inline(always)]
-fn sigcont() {
+fn send_signal(sig: u32) {
     unsafe {
-        helpers::bpf_send_signal_thread(SIGCONT);
+        match ptr::read_volatile(&SIGNAL_MODE) {
+            SignalMode::Thread => {
+                helpers::bpf_send_signal_thread(sig);
+            }
+            SignalMode::Process => {
+                helpers::bpf_send_signal(sig);
+            }
+            SignalMode::Daemon => {}
+        }
+    }
+}
+
+#[inline(always)]
+fn sigstop() {
+    send_signal(SIGSTOP);
+}
+
+#[inline(always)]
+fn sigcont() {
+    send_signal(SIGCONT);
+}
+
+/// Counters are per-CPU to keep hot paths (e.g. `sys_enter`) cheap, the daemon only looks at
+/// their sum.
+#[inline(always)]
+fn bump(counters: &PerCpuArray<u64>, index: u32) {
+    if let Some(counter) = counters.get_ptr_mut(index) {
+        unsafe { *counter = (*counter).wrapping_add(1) };
     }
 }
 
+#[inline(always)]
+fn heartbeat(program: Program) {
+    unsafe { bump(&HEARTBEATS, program as u32) };
+}
+
+#[inline(always)]
+fn map_failed(map: TrackedMap) {
+    unsafe { bump(&MAP_FAILURES, map as u32) };
+}
+
 #[inline(always)]
 fn emit(message: Message) -> bool {
     unsafe {
         let entry = MESSAGE_CHANNEL.reserve::<Message>(0);
         let mut entry = match entry {
             Some(entry) => entry,
-            None => return false,
+            None => {
+                bump(&DROPPED_MESSAGES, 0);
+                return false;
+            }
         };
 
         entry.write(message);
@@ -174,34 +324,44 @@ fn emit(message: Message) -> bool {
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-#[repr(C)]
-struct TaskNewTaskEvent {
-    pid: i32,
-    _comm: [u8; 16],
-    clone_flags: u64,
-    _oom_score_adj: i16,
-}
-
 #[tracepoint]
 pub fn tracepoint__task__task_newtask(ctx: TracePointContext) -> u32 {
-    let event = TaskNewTaskEvent::from_context(&ctx);
+    heartbeat(Program::TaskNewTask);
 
-    // skip for threads
-    if event.clone_flags & 0x00010000 /* CLONE_THREAD */ != 0 {
+    let layout = layout();
+    let (Some(clone_flags), Some(child_pid)) = (
+        read_field::<u64>(&ctx, layout.task_newtask_clone_flags),
+        read_field::<i32>(&ctx, layout.task_newtask_pid),
+    ) else {
         return 0;
-    }
+    };
 
-    if !current_is_privileged() {
+    // skip for threads
+    if clone_flags & 0x00010000 /* CLONE_THREAD */ != 0 {
         return 0;
     }
 
     let parent_pid = current_pid();
-    let child_pid = event.pid;
+    let parent_tgid = current_tgid();
+
+    // app zygotes run with unprivileged uids, but are tracked explicitly
+    if !current_is_privileged() && unsafe { !hashmap_contains(&ZYGOTE_PIDS, &parent_pid) } {
+        return 0;
+    }
 
     unsafe {
-        if parent_pid == INIT_PID {
+        // forks of zygotes are apps, never services
+        let depth = if parent_pid == INIT_PID {
+            Some(1)
+        } else if hashmap_contains(&ZYGOTE_PIDS, &parent_tgid) {
+            None
+        } else {
+            hashmap_load(&SERVICE_TREE, &parent_tgid).map(|depth| *depth + 1)
+        };
+
+        if let Some(depth) = depth {
             if DEBUG {
-                debug!(&ctx, "init fork: {}", child_pid)
+                debug!(&ctx, "init fork: {} (depth {})", child_pid, depth)
             }
 
             if !hashmap_create(
@@ -209,20 +369,47 @@ pub fn tracepoint__task__task_newtask(ctx: TracePointContext) -> u32 {
                 &child_pid,
                 &ServiceState::PostFork.into(),
             ) {
+                map_failed(TrackedMap::InitChildren);
                 warn!(&ctx, "failed to record init child: {}", child_pid)
             }
+
+            if depth < ptr::read_volatile(&SERVICE_DEPTH)
+                && !hashmap_create(&mut SERVICE_TREE, &child_pid, &depth)
+            {
+                map_failed(TrackedMap::ServiceTree);
+
+                if DEBUG {
+                    debug!(&ctx, "failed to record service tree: {}", child_pid)
+                }
+            }
         }
 
-        if ZYGOTE_INFO.get(0) == Some(&parent_pid) {
+        if hashmap_contains(&ZYGOTE_PIDS, &parent_pid) {
+            let uid = hashmap_load(&PENDING_UIDS, &parent_pid).copied();
+
+            if uid.is_some() {
+                hashmap_remove(&mut PENDING_UIDS, &parent_pid);
+            }
+
+            if !is_target_uid(uid) {
+                if DEBUG {
+                    debug!(&ctx, "zygote fork skipped: {} -> {}", parent_pid, child_pid);
+                }
+
+                return 0;
+            }
+
             if DEBUG {
                 debug!(&ctx, "zygote fork: {} -> {}", parent_pid, child_pid);
             }
 
-            if !hashmap_create(
-                &mut ZYGOTE_CHILDREN,
-                &child_pid,
-                &EmbryoState::PreFork.into(),
-            ) {
+            let info = EmbryoInfo {
+                zygote: parent_pid,
+                state: EmbryoState::PreFork.into(),
+            };
+
+            if !hashmap_create(&mut ZYGOTE_CHILDREN, &child_pid, &info) {
+                map_failed(TrackedMap::ZygoteChildren);
                 warn!(&ctx, "failed to record zygote child: {}", child_pid);
             }
         }
@@ -231,26 +418,27 @@ pub fn tracepoint__task__task_newtask(ctx: TracePointContext) -> u32 {
     0
 }
 
-#[repr(C)]
-struct SchedProcessExecEvent {
-    filename: (i16, u16), // __data_loc
-    pid: i32,
-    _old_pid: i32,
-}
-
 #[tracepoint]
 pub fn tracepoint__sched__sched_process_exec(ctx: TracePointContext) -> u32 {
+    heartbeat(Program::SchedProcessExec);
+
     if !current_is_privileged() {
         return 0;
     }
 
-    let event = SchedProcessExecEvent::from_context(&ctx);
-    let pid = event.pid;
+    let layout = layout();
+    let (Some(filename), Some(pid)) = (
+        read_field::<u32>(&ctx, layout.sched_process_exec_filename),
+        read_field::<i32>(&ctx, layout.sched_process_exec_pid),
+    ) else {
+        return 0;
+    };
 
     unsafe {
         if let Some(state) = hashmap_load(&INIT_CHILDREN, &pid) {
             if *state == ServiceState::PostFork.into() {
-                let ptr = ctx.as_ptr().add(event.filename.0 as _) as *const u8;
+                // __data_loc: offset in the low 16 bits, length in the high ones
+                let ptr = ctx.as_ptr().add((filename & 0xffff) as _) as *const u8;
                 let mut buffer = [0u8; 128];
 
                 if helpers::bpf_probe_read_kernel_str_bytes(ptr, &mut buffer).is_ok() {
@@ -263,12 +451,20 @@ pub fn tracepoint__sched__sched_process_exec(ctx: TracePointContext) -> u32 {
                     if hashmap_contains(&TARGET_PATHS, &buffer) {
                         info!(&ctx, "path matches: {} -> {}", pid, path);
 
+                        let stop = !observe_only().services;
+
                         hashmap_remove(&mut INIT_CHILDREN, &pid);
-                        sigstop();
+
+                        if stop {
+                            sigstop();
+                        }
 
                         if !emit(Message::PathMatches(pid, buffer)) {
                             warn!(&ctx, "failed to emit path matches message");
-                            sigcont();
+
+                            if stop {
+                                sigcont();
+                            }
                         }
 
                         return 0;
@@ -293,30 +489,29 @@ pub fn tracepoint__sched__sched_process_exec(ctx: TracePointContext) -> u32 {
     0
 }
 
-#[repr(C)]
-struct TaskRenameEvent {
-    pid: i32,
-    old_comm: [u8; 16],
-    new_comm: [u8; 16],
-}
-
 #[tracepoint]
 pub fn tracepoint__task__task_rename(ctx: TracePointContext) -> u32 {
+    heartbeat(Program::TaskRename);
+
     if !current_is_privileged() {
         return 0;
     }
 
-    let event = TaskRenameEvent::from_context(&ctx);
-    let pid = event.pid;
+    let layout = layout();
+    let pid = if layout.task_rename_pid == FIELD_ABSENT {
+        current_pid()
+    } else {
+        match read_field::<i32>(&ctx, layout.task_rename_pid) {
+            Some(pid) => pid,
+            None => return 0,
+        }
+    };
 
     unsafe {
         if let Some(state) = hashmap_load(&INIT_CHILDREN, &pid)
             && *state == ServiceState::PostExec.into()
         {
-            let ptr = ctx
-                .as_ptr()
-                .add(EVENT_PARAMS_OFFSET + core::mem::offset_of!(TaskRenameEvent, new_comm))
-                as *const u8;
+            let ptr = ctx.as_ptr().add(layout.task_rename_newcomm as _) as *const u8;
             let mut buffer = [0u8; 16];
 
             if helpers::bpf_probe_read_kernel_str_bytes(ptr, &mut buffer).is_ok() {
@@ -345,45 +540,189 @@ pub fn tracepoint__task__task_rename(ctx: TracePointContext) -> u32 {
     0
 }
 
-#[repr(C)]
-struct SysEnterEvent {
-    id: i64,
-    args: [u64; 6],
+/// `rt_sigprocmask(SIG_UNBLOCK, ...)` right after a zygote fork, shared by the variants of the
+/// `sys_enter` program, the daemon attaches the cheapest one the kernel supports.
+///
+/// https://cs.android.com/android/platform/superproject/main/+/main:frameworks/base/core/jni/com_android_internal_os_Zygote.cpp;l=2506;drc=00e40a9ebff41f5b55b8f1743058a7accb0bad8e
+#[inline(always)]
+fn on_sigprocmask<C: EbpfContext>(ctx: &C, how: Option<u64>) -> u32 {
+    if how != Some(1 /* SIG_UNBLOCK */) {
+        return 0;
+    }
+
+    if current_is_32bit() {
+        return 0;
+    }
+
+    let pid = current_pid();
+
+    // embryos of app zygotes are unprivileged until specialized
+    if !current_is_privileged() && unsafe { !hashmap_contains(&ZYGOTE_CHILDREN, &pid) } {
+        return 0;
+    }
+
+    unsafe {
+        if let Some(info) = hashmap_load(&ZYGOTE_CHILDREN, &pid)
+            && info.state == EmbryoState::PreFork.into()
+        {
+            // stopped later, by the uprobe of `SpecializeCommon`
+            if SPECIALIZE_PROBE.get(0).is_some_and(|enabled| *enabled != 0) {
+                return 0;
+            }
+
+            let zygote = info.zygote;
+
+            hashmap_remove(&mut ZYGOTE_CHILDREN, &pid);
+
+            if DEBUG {
+                debug!(ctx, "post zygote fork: {} -> {}", zygote, pid)
+            }
+
+            let stop = !observe_only().embryos;
+
+            if stop {
+                sigstop();
+            }
+
+            if !emit(Message::ZygoteFork(zygote, pid)) {
+                warn!(ctx, "failed to emit zygote fork message");
+
+                if stop {
+                    sigcont();
+                }
+            }
+        }
+    }
+
+    0
 }
 
 #[tracepoint]
 pub fn tracepoint__raw_syscalls__sys_enter(ctx: TracePointContext) -> u32 {
-    let event = SysEnterEvent::from_context(&ctx);
+    heartbeat(Program::SysEnter);
+
+    let layout = layout();
 
-    // https://cs.android.com/android/platform/superproject/main/+/main:frameworks/base/core/jni/com_android_internal_os_Zygote.cpp;l=2506;drc=00e40a9ebff41f5b55b8f1743058a7accb0bad8e
-    if event.id != 135 /* rt_sigprocmask */ || event.args[0] != 1
-    /* SIG_UNBLOCK */
-    {
+    if read_field::<i64>(&ctx, layout.sys_enter_id) != Some(NR_RT_SIGPROCMASK) {
         return 0;
     }
 
-    if !current_is_privileged() {
+    on_sigprocmask(&ctx, read_field(&ctx, layout.sys_enter_args))
+}
+
+/// Raw variant of [`tracepoint__raw_syscalls__sys_enter`], skipping the copy of the record,
+/// args are `(struct pt_regs *regs, long id)`.
+#[raw_tracepoint(tracepoint = "sys_enter")]
+pub fn raw_tracepoint__sys_enter(ctx: RawTracePointContext) -> u32 {
+    heartbeat(Program::SysEnter);
+
+    let args = ctx.as_ptr() as *const u64;
+    let (regs, id) = unsafe { (*args, *args.add(1) as i64) };
+
+    if id != NR_RT_SIGPROCMASK {
         return 0;
     }
 
-    if current_is_32bit() {
-        return 0;
+    let arg0 = (regs as usize + PT_REGS_ARG0) as *const u64;
+    let how = unsafe { helpers::bpf_probe_read_kernel(arg0).ok() };
+
+    on_sigprocmask(&ctx, how)
+}
+
+/// Only runs on `rt_sigprocmask` instead of every syscall, needs BTF and fentry support
+/// (Linux 6.0 on arm64).
+#[cfg_attr(
+    not(bpf_target_arch = "x86_64"),
+    fentry(function = "__arm64_sys_rt_sigprocmask")
+)]
+#[cfg_attr(
+    bpf_target_arch = "x86_64",
+    fentry(function = "__x64_sys_rt_sigprocmask")
+)]
+pub fn fentry__rt_sigprocmask(ctx: FEntryContext) -> u32 {
+    heartbeat(Program::SysEnter);
+
+    let regs: *const u8 = unsafe { ctx.arg(0) };
+    let arg0 = regs.wrapping_add(PT_REGS_ARG0) as *const u64;
+    let how = unsafe { helpers::bpf_probe_read_kernel(arg0).ok() };
+
+    on_sigprocmask(&ctx, how)
+}
+
+/// Entry of `nativeForkAndSpecialize` in zygotes, the uid the child will be specialized to is
+/// its third arg. Forks of USAPs and system_server don't go through it, and are never filtered.
+#[uprobe]
+pub fn uprobe__fork_and_specialize(ctx: ProbeContext) -> u32 {
+    let pid = current_pid();
+
+    unsafe {
+        if !hashmap_contains(&ZYGOTE_PIDS, &pid) {
+            return 0;
+        }
+
+        if let Some(uid) = ctx.arg::<u32>(2)
+            && PENDING_UIDS.insert(&pid, &uid, 0 /* BPF_ANY */).is_err()
+        {
+            warn!(&ctx, "failed to record pending uid: {}", pid);
+        }
     }
 
+    0
+}
+
+/// Entry of `SpecializeCommon` in embryos, instead of the breakpoint patched by the daemon. The
+/// embryo only stops once the probed instruction has been run out of line, its registers at
+/// the probe are kept in `ENTRY_REGS` for the daemon to rewind it.
+#[uprobe]
+pub fn uprobe__specialize_common(ctx: ProbeContext) -> u32 {
     let pid = current_pid();
 
     unsafe {
-        if hashmap_load(&ZYGOTE_CHILDREN, &pid) == Some(&EmbryoState::PreFork.into()) {
-            hashmap_remove(&mut ZYGOTE_CHILDREN, &pid);
+        // cleared once the daemon detaches, embryos are stopped after fork again, if at all
+        if !SPECIALIZE_PROBE.get(0).is_some_and(|enabled| *enabled != 0) {
+            return 0;
+        }
 
-            if DEBUG {
-                debug!(&ctx, "post zygote fork: {}", pid)
+        let Some(info) = hashmap_load(&ZYGOTE_CHILDREN, &pid) else {
+            return 0;
+        };
+
+        if info.state != EmbryoState::PreFork.into() {
+            return 0;
+        }
+
+        let zygote = info.zygote;
+
+        hashmap_remove(&mut ZYGOTE_CHILDREN, &pid);
+
+        if DEBUG {
+            debug!(&ctx, "zygote child specialize: {} -> {}", zygote, pid)
+        }
+
+        let stop = !observe_only().embryos;
+
+        if stop {
+            let mut regs: EntryRegs = [0; 34];
+            let words = core::slice::from_raw_parts_mut(
+                regs.as_mut_ptr() as *mut u8,
+                PT_REGS_WORDS * size_of::<u64>(),
+            );
+
+            if helpers::bpf_probe_read_kernel_buf(ctx.regs as *const u8, words).is_err()
+                || !hashmap_create(&mut ENTRY_REGS, &pid, &regs)
+            {
+                warn!(&ctx, "failed to record entry registers: {}", pid);
+                return 0;
             }
 
             sigstop();
+        }
 
-            if !emit(Message::ZygoteFork(pid)) {
-                warn!(&ctx, "failed to emit zygote fork message");
+        if !emit(Message::ZygoteSpecialize(zygote, pid)) {
+            warn!(&ctx, "failed to emit zygote specialize message");
+
+            if stop {
+                hashmap_remove(&mut ENTRY_REGS, &pid);
                 sigcont();
             }
         }
@@ -392,23 +731,22 @@ pub fn tracepoint__raw_syscalls__sys_enter(ctx: TracePointContext) -> u32 {
     0
 }
 
-#[repr(C)]
-struct SignalDeliverEvent {
-    sig: i32,
-    _errno: i32,
-    code: i32,
-    _sa_handler: u64,
-    _sa_flags: u64,
-}
-
 #[tracepoint]
 pub fn tracepoint__signal__signal_deliver(ctx: TracePointContext) -> u32 {
+    heartbeat(Program::SignalDeliver);
+
     if !DEBUG {
         return 0;
     }
 
-    let event = SignalDeliverEvent::from_context(&ctx);
-    let sig = event.sig as u32;
+    let layout = layout();
+    let (Some(sig), Some(code)) = (
+        read_field::<i32>(&ctx, layout.signal_deliver_sig),
+        read_field::<i32>(&ctx, layout.signal_deliver_code),
+    ) else {
+        return 0;
+    };
+    let sig = sig as u32;
 
     if sig != SIGSTOP && sig != SIGCONT && sig != SIGTRAP {
         return 0;
@@ -422,49 +760,61 @@ pub fn tracepoint__signal__signal_deliver(ctx: TracePointContext) -> u32 {
 
     debug!(
         &ctx,
-        "signal deliver to process {}: sig={}, code={}", pid, event.sig, event.code
+        "signal deliver to process {}: sig={}, code={}", pid, sig, code
     );
 
     0
 }
 
-#[repr(C)]
-struct SchedProcessExitEvent {
-    _comm: [u8; 16],
-    pid: i32,
-    _prio: i32,
-}
-
 #[tracepoint]
 pub fn tracepoint__sched__sched_process_exit(ctx: TracePointContext) -> u32 {
-    let event = SchedProcessExitEvent::from_context(&ctx);
-    let pid = event.pid;
+    heartbeat(Program::SchedProcessExit);
+
+    let Some(pid) = read_field::<i32>(&ctx, layout().sched_process_exit_pid) else {
+        return 0;
+    };
 
     unsafe {
         if hashmap_remove(&mut INIT_CHILDREN, &pid) && DEBUG {
             debug!(&ctx, "init child exit: {}", pid);
         }
 
+        hashmap_remove(&mut SERVICE_TREE, &pid);
+
         if hashmap_remove(&mut ZYGOTE_CHILDREN, &pid) && DEBUG {
             debug!(&ctx, "zygote child exit: {}", pid);
         }
 
-        if ZYGOTE_INFO.get(0) == Some(&pid) {
+        hashmap_remove(&mut PENDING_UIDS, &pid);
+        hashmap_remove(&mut ENTRY_REGS, &pid);
+
+        if hashmap_remove(&mut ZYGOTE_PIDS, &pid) {
             warn!(&ctx, "zygote crashed: {}", pid);
 
             if !emit(Message::ZygoteCrashed(pid)) {
                 warn!(&ctx, "failed to emit zygote crash message");
             }
-
-            if ZYGOTE_INFO.set(0, 0, BPF_ANY as _).is_err() {
-                warn!(&ctx, "failed to clear zygote pid")
-            }
         }
     }
 
     0
 }
 
+#[tracepoint]
+pub fn tracepoint__syscalls__sys_enter_getppid(ctx: TracePointContext) -> u32 {
+    if current_tgid() != unsafe { ptr::read_volatile(&DAEMON_PID) } {
+        return 0;
+    }
+
+    heartbeat(Program::Watchdog);
+
+    if !emit(Message::Heartbeat(unsafe { helpers::bpf_ktime_get_ns() })) {
+        warn!(&ctx, "failed to emit heartbeat message");
+    }
+
+    0
+}
+
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(not(test))]
@@ -1,3 +1,5 @@
+use std::any::Any;
+
 #[macro_export]
 macro_rules! debug_on {
     ($key: expr) => {{
@@ -11,3 +13,14 @@ macro_rules! debug_on {
         }
     }};
 }
+
+/// Message of a caught panic, from the payload of `catch_unwind` or a joined thread.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "(unknown panic payload)".into()
+    }
+}
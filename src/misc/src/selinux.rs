@@ -1,26 +1,22 @@
 use crate::debug_on;
-use crate::ext::ResultExt;
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 use log::debug;
 use nix::libc;
 use std::borrow::Cow;
 use std::ffi::{CStr, CString};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
 use std::os::fd::{AsFd, AsRawFd};
 use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 
 const SELINUX_XATTR: &CStr = c"security.selinux";
-const MAGISK_FILE_CONTEXT: &str = "u:object_r:magisk_file:s0";
+pub const MAGISK_FILE_CONTEXT: &str = "u:object_r:magisk_file:s0";
 
-pub trait FileExt {
-    fn mark_as_magisk_file(&self);
-}
+const SELINUXFS: &str = "/sys/fs/selinux";
 
-impl<F: AsFd> FileExt for F {
-    fn mark_as_magisk_file(&self) {
-        fsetcon(self.as_fd(), MAGISK_FILE_CONTEXT).log_if_error();
-    }
-}
+/// `SELINUX_AVD_FLAGS_PERMISSIVE`, set in access decisions of permissive domains
+const AVD_FLAGS_PERMISSIVE: u32 = 1;
 
 pub fn getcon<P: AsRef<Path>>(path: P) -> Result<String> {
     let path = CString::new(path.as_ref().as_os_str().as_bytes())?;
@@ -102,3 +98,76 @@ pub fn fsetcon<F: AsFd>(file: F, context: &str) -> Result<()> {
 
     Ok(())
 }
+
+/// Context of the process `pid`, or the current process if `None`.
+pub fn getpidcon(pid: Option<i32>) -> Result<String> {
+    let path = match pid {
+        Some(pid) => format!("/proc/{pid}/attr/current"),
+        None => "/proc/self/attr/current".into(),
+    };
+
+    let context = fs::read_to_string(&path).context(format!("failed to read {path}"))?;
+
+    Ok(context.trim_end_matches(['\0', '\n']).into())
+}
+
+pub fn is_enforcing() -> bool {
+    fs::read_to_string(format!("{SELINUXFS}/enforce")).is_ok_and(|value| value.trim() == "1")
+}
+
+fn read_index(path: String) -> Result<u32> {
+    let value = fs::read_to_string(&path).context(format!("failed to read {path}"))?;
+    Ok(value.trim().parse()?)
+}
+
+/// Query the loaded policy for `perms` of `class` from `scon` to `tcon`, returns the
+/// denied ones. Permissive domains are denied nothing.
+pub fn check_access<'a>(
+    scon: &str,
+    tcon: &str,
+    class: &str,
+    perms: &[&'a str],
+) -> Result<Vec<&'a str>> {
+    let class_dir = format!("{SELINUXFS}/class/{class}");
+    let class_index = read_index(format!("{class_dir}/index"))?;
+
+    let mut access = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(format!("{SELINUXFS}/access"))?;
+
+    access.write_all(format!("{scon} {tcon} {class_index}").as_bytes())?;
+
+    let mut decision = String::new();
+    access.read_to_string(&mut decision)?;
+
+    // allowed decided auditallow auditdeny seqno flags
+    let fields: Vec<_> = decision.split_whitespace().collect();
+
+    let (Some(allowed), Some(flags)) = (fields.first(), fields.get(5)) else {
+        bail!("malformed access decision: {decision:?}")
+    };
+
+    let allowed = u32::from_str_radix(allowed, 16)?;
+    let flags = u32::from_str_radix(flags, 16)?;
+
+    if flags & AVD_FLAGS_PERMISSIVE != 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut denied = Vec::new();
+
+    for perm in perms {
+        let bit = read_index(format!("{class_dir}/perms/{perm}"))?;
+
+        if allowed & (1 << (bit - 1)) == 0 {
+            denied.push(*perm);
+        }
+    }
+
+    if debug_on!("selinux") {
+        debug!("access: {scon} -> {tcon} {class} {perms:?}, denied {denied:?}");
+    }
+
+    Ok(denied)
+}
@@ -4,8 +4,11 @@ use crate::module::ZygiskModule;
 use jni::sys::{JNIEnv, JNINativeMethod};
 use log::warn;
 use nix::libc::{c_char, c_int, c_long, dev_t, ino_t};
+use nix::sys::socket::{self, AddressFamily, ControlMessage, MsgFlags, SockFlag, SockType};
 use std::ffi::c_void;
+use std::io::IoSlice;
 use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, IntoRawFd, OwnedFd};
 use std::ptr;
 
 #[repr(C)]
@@ -33,6 +36,64 @@ impl ApiAbiV4 {
     extern "C" fn set_option(module: *mut ZygiskModule, option: ZygiskOption) {
         unsafe { (*module).options[option.index()] = true }
     }
+
+    /// Create a socket pair and pass one end to the module's companion, the other end is
+    /// returned and owned by the module.
+    extern "C" fn connect_companion(module: *mut ZygiskModule) -> c_int {
+        let companion = unsafe { &(*module).companion };
+        let Some(socket) = companion.take() else {
+            warn!("companion is not available, or connected after specialization");
+            return -1;
+        };
+
+        let result = connect(&socket);
+        companion.set(Some(socket));
+
+        match result {
+            Ok(fd) => fd.into_raw_fd(),
+            Err(err) => {
+                warn!("failed to connect companion: {err}");
+                -1
+            }
+        }
+    }
+
+    /// The returned fd is owned by the module.
+    extern "C" fn get_module_dir(module: *mut ZygiskModule) -> c_int {
+        let Some(dir) = (unsafe { &(*module).module_dir }) else {
+            warn!(
+                "module directory is not available, set `module_dir = true` in zynx-configs.toml"
+            );
+            return -1;
+        };
+
+        match dir.try_clone() {
+            Ok(fd) => fd.into_raw_fd(),
+            Err(err) => {
+                warn!("failed to duplicate module directory fd: {err}");
+                -1
+            }
+        }
+    }
+}
+
+fn connect(socket: &OwnedFd) -> nix::Result<OwnedFd> {
+    let (local, remote) = socket::socketpair(
+        AddressFamily::Unix,
+        SockType::Stream,
+        None,
+        SockFlag::SOCK_CLOEXEC,
+    )?;
+
+    socket::sendmsg::<()>(
+        socket.as_raw_fd(),
+        &[IoSlice::new(&[0])],
+        &[ControlMessage::ScmRights(&[remote.as_raw_fd()])],
+        MsgFlags::empty(),
+        None,
+    )?;
+
+    Ok(local)
 }
 
 pub type ApiAbiV5 = ApiAbiV4;
@@ -52,9 +113,9 @@ impl ApiAbiSpec {
                     plt_hook_register: MaybeUninit::zeroed(),
                     exempt_fd: MaybeUninit::zeroed(),
                     plt_hook_commit: MaybeUninit::zeroed(),
-                    connect_companion: MaybeUninit::zeroed(),
+                    connect_companion: MaybeUninit::new(ApiAbiV4::connect_companion),
                     set_option: MaybeUninit::new(ApiAbiV4::set_option),
-                    get_module_dir: MaybeUninit::zeroed(),
+                    get_module_dir: MaybeUninit::new(ApiAbiV4::get_module_dir),
                     get_flags: MaybeUninit::zeroed(),
                 },
             },
@@ -0,0 +1,229 @@
+//! Zynx extensions to the zygisk API, exported as plain C symbols from the bridge.
+
+use jni::sys::{JNIEnv, jobjectArray};
+use log::error;
+use nix::libc::{c_char, c_int, c_void, size_t, ssize_t};
+use std::os::fd::BorrowedFd;
+use std::time::Duration;
+use std::{cmp, ptr, slice};
+use zynx_bridge_shared::rpc::RpcChannel;
+use zynx_bridge_shared::zygote::arrays;
+use zynx_bridge_shared::zygote::arrays::Rlimit;
+use zynx_misc::ext::ResultExt;
+
+/// Entry of `pkg_data_info_list`/`allowlisted_data_info_list`, strings are NUL-terminated
+/// and truncated if too long.
+#[repr(C)]
+pub struct ZynxDataInfo {
+    pub package_name: [c_char; 256],
+    /// Empty for the internal storage
+    pub volume_uuid: [c_char; 64],
+    pub ce_data_inode: u64,
+}
+
+fn copy_c_string(dst: &mut [c_char], src: &str) {
+    let len = cmp::min(src.len(), dst.len() - 1);
+
+    for (dst, src) in dst.iter_mut().zip(&src.as_bytes()[..len]) {
+        *dst = *src as _;
+    }
+
+    dst[len] = 0;
+}
+
+pub type CompanionHandler = unsafe extern "C" fn(
+    request: *const u8,
+    request_len: size_t,
+    reply: *mut u8,
+    reply_cap: size_t,
+    userdata: *mut c_void,
+) -> ssize_t;
+
+fn rpc_channel(fd: c_int, timeout_ms: c_int) -> Option<RpcChannel<'static>> {
+    if fd < 0 {
+        return None;
+    }
+
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+    let mut channel = RpcChannel::new(fd);
+
+    if timeout_ms > 0 {
+        channel = channel.timeout(Duration::from_millis(timeout_ms as _));
+    }
+
+    Some(channel)
+}
+
+/// Module side: send a request over a companion socket (from `connectCompanion`)
+/// and wait for the reply.
+///
+/// Returns the reply length, or -1 on error, timeout, or if the reply does not fit
+/// into `reply_cap` bytes. A `timeout_ms` <= 0 selects the default timeout.
+///
+/// # Safety
+///
+/// `request` must be valid for `request_len` bytes, and `reply` for `reply_cap` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zynx_companion_call(
+    fd: c_int,
+    request: *const u8,
+    request_len: size_t,
+    reply: *mut u8,
+    reply_cap: size_t,
+    timeout_ms: c_int,
+) -> ssize_t {
+    let Some(channel) = rpc_channel(fd, timeout_ms) else {
+        return -1;
+    };
+
+    let request = if request_len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(request, request_len) }
+    };
+
+    let Some(data) = channel.call(request, reply_cap).ok_or_warn() else {
+        return -1;
+    };
+
+    unsafe { ptr::copy_nonoverlapping(data.as_ptr(), reply, data.len()) };
+
+    data.len() as _
+}
+
+/// Companion side: serve requests on `fd` until the module closes the connection.
+///
+/// `handler` writes the reply into the provided buffer and returns its length, or a
+/// negative value to send an empty reply. Returns 0 on a clean shutdown, -1 on error.
+///
+/// # Safety
+///
+/// `handler` must be safe to call with a request valid for `request_len` bytes, a reply
+/// buffer valid for `reply_cap` bytes and `userdata`, and must write at most `reply_cap`
+/// bytes into the reply. `userdata` is passed to it as is.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zynx_companion_serve(
+    fd: c_int,
+    handler: CompanionHandler,
+    userdata: *mut c_void,
+    timeout_ms: c_int,
+) -> c_int {
+    let Some(channel) = rpc_channel(fd, timeout_ms) else {
+        return -1;
+    };
+
+    loop {
+        let result = channel.serve_one(|request, reply_cap| {
+            let mut reply = vec![0u8; reply_cap];
+            let len = unsafe {
+                handler(
+                    request.as_ptr(),
+                    request.len(),
+                    reply.as_mut_ptr(),
+                    reply.len(),
+                    userdata,
+                )
+            };
+
+            reply.truncate(len.clamp(0, reply.len() as _) as _);
+            reply
+        });
+
+        match result {
+            Ok(true) => {}
+            Ok(false) => return 0,
+            Err(err) => {
+                error!("companion serve failed: {err:?}");
+                return -1;
+            }
+        }
+    }
+}
+
+/// Read `rlimits` of the specialize args into `out`, as `{resource, soft, hard}` triples.
+///
+/// Returns the number of entries, of which at most `cap` are written, 0 if `rlimits` is
+/// null, or -1 on error.
+///
+/// # Safety
+///
+/// `out` must be valid for `cap` entries.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zynx_read_rlimits(
+    env: *mut JNIEnv,
+    rlimits: jobjectArray,
+    out: *mut Rlimit,
+    cap: size_t,
+) -> ssize_t {
+    let Some(rlimits) = arrays::read_rlimits(env as _, rlimits).ok_or_warn() else {
+        return -1;
+    };
+
+    let rlimits = rlimits.unwrap_or_default();
+    let count = cmp::min(rlimits.len(), cap);
+
+    if count > 0 {
+        unsafe { ptr::copy_nonoverlapping(rlimits.as_ptr(), out, count) };
+    }
+
+    rlimits.len() as _
+}
+
+/// Build a new `int[][]` from `count` entries, to replace `rlimits` in pre-specialize.
+///
+/// Returns null on error.
+///
+/// # Safety
+///
+/// `rlimits` must be valid for `count` entries.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zynx_new_rlimits(
+    env: *mut JNIEnv,
+    rlimits: *const Rlimit,
+    count: size_t,
+) -> jobjectArray {
+    let rlimits = if count == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(rlimits, count) }
+    };
+
+    arrays::new_rlimits(env as _, rlimits)
+        .ok_or_warn()
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Read `pkg_data_info_list` or `allowlisted_data_info_list` of the specialize args into `out`.
+///
+/// Returns the number of entries, of which at most `cap` are written, 0 if `list` is null,
+/// or -1 on error.
+///
+/// # Safety
+///
+/// `out` must be valid for `cap` entries.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zynx_read_data_info_list(
+    env: *mut JNIEnv,
+    list: jobjectArray,
+    out: *mut ZynxDataInfo,
+    cap: size_t,
+) -> ssize_t {
+    let Some(list) = arrays::read_data_info_list(env as _, list).ok_or_warn() else {
+        return -1;
+    };
+
+    let list = list.unwrap_or_default();
+
+    for (index, info) in list.iter().take(cap).enumerate() {
+        let out = unsafe { &mut *out.add(index) };
+
+        copy_c_string(&mut out.package_name, &info.package_name);
+        copy_c_string(
+            &mut out.volume_uuid,
+            info.volume_uuid.as_deref().unwrap_or_default(),
+        );
+        out.ce_data_inode = info.ce_data_inode;
+    }
+
+    list.len() as _
+}
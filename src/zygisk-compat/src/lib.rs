@@ -1,15 +1,18 @@
 use crate::module::{PinnedZygiskModule, ZygiskModule};
 use anyhow::Result;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use zynx_bridge_api::injector::ProviderHandler;
 use zynx_bridge_api::zygote::ProviderBundle;
-use zynx_bridge_shared::policy::zygisk::ZygiskParams;
+use zynx_bridge_shared::policy::zygisk::{ZygiskAttachmentKind, ZygiskParams};
 use zynx_bridge_shared::remote_lib::NativeLibrary;
 use zynx_bridge_shared::zygote::{ProviderType, SpecializeArgs};
 use zynx_misc::ext::ResultExt;
 
 mod abi;
+pub mod ext;
 mod module;
+mod unmount;
 
 pub struct ZygiskProviderHandler;
 
@@ -22,6 +25,9 @@ impl ProviderHandler for ZygiskProviderHandler {
 
     fn on_specialize_pre(args: &mut SpecializeArgs, bundle: &mut ProviderBundle) -> Result<()> {
         let mut modules = Vec::new();
+        let mut libraries = Vec::new();
+        let mut module_dirs = HashMap::new();
+        let mut companions = HashMap::new();
 
         for attachment in bundle.attachments.iter_mut() {
             if let Some(fd) = attachment.fd.take() {
@@ -37,19 +43,37 @@ impl ProviderHandler for ZygiskProviderHandler {
                     }
                 };
 
-                let mut lib = NativeLibrary::new(params.module_name, fd);
+                match params.kind {
+                    ZygiskAttachmentKind::Library => {
+                        libraries.push((params.module_name, attachment.module.clone(), fd))
+                    }
+                    ZygiskAttachmentKind::ModuleDir => {
+                        module_dirs.insert(params.module_name, fd);
+                    }
+                    ZygiskAttachmentKind::Companion => {
+                        companions.insert(params.module_name, fd);
+                    }
+                }
+            }
+        }
 
-                let Ok(()) = lib.open().inspect_log_error() else {
-                    continue;
-                };
+        for (module_name, name, fd) in libraries {
+            let module_dir = module_dirs.remove(&module_name);
+            let companion = companions.remove(&module_name);
+            let mut lib = NativeLibrary::new(module_name, fd);
 
-                let Ok(module) = ZygiskModule::new(lib).inspect_log_error() else {
-                    continue;
-                };
+            let Ok(()) = lib.open().inspect_log_error() else {
+                continue;
+            };
 
-                if module.call_entry(args.env) {
-                    modules.push(module);
-                }
+            let Ok(module) =
+                ZygiskModule::new(lib, name, module_dir, companion).inspect_log_error()
+            else {
+                continue;
+            };
+
+            if module.call_entry(args.env) {
+                modules.push(module);
             }
         }
 
@@ -57,6 +81,13 @@ impl ProviderHandler for ZygiskProviderHandler {
             .iter()
             .for_each(|module| module.call_specialize_pre(args));
 
+        // `connectCompanion` is only available before specialization
+        modules.iter().for_each(|module| module.close_companion());
+
+        if !args.is_system_server && modules.iter().any(|module| module.requests_unmount()) {
+            unmount::revert_unmount().log_if_error();
+        }
+
         G_MODULES.with(|cell| {
             cell.borrow_mut().extend(modules);
         });
@@ -5,10 +5,12 @@ use crate::abi::flags::ZygiskOption;
 use crate::abi::module::ModuleAbi;
 use anyhow::Result;
 use jni::sys::JNIEnv;
-use log::warn;
+use std::cell::Cell;
 use std::marker::PhantomPinned;
+use std::os::fd::OwnedFd;
 use std::pin::Pin;
 use std::{mem, ptr};
+use zynx_bridge_api::injector;
 use zynx_bridge_shared::remote_lib::NativeLibrary;
 use zynx_bridge_shared::zygote::SpecializeArgs;
 
@@ -16,24 +18,38 @@ pub type PinnedZygiskModule = Pin<Box<ZygiskModule>>;
 
 pub struct ZygiskModule {
     pub library: NativeLibrary,
+    /// Name of the module in crash reports, see [`injector::in_module`]
+    pub name: Option<String>,
     pub entry_fn: extern "C" fn(*const ApiAbi, JNIEnv),
     pub api: ApiAbi,
     pub module: *const ModuleAbi,
     pub options: [bool; ZygiskOption::MAX_INDEX + 1],
+    /// Root directory of the module, if its config asks for it
+    pub module_dir: Option<OwnedFd>,
+    /// Socket to the module's companion hosted by the daemon, see `connectCompanion`
+    pub companion: Cell<Option<OwnedFd>>,
     _pin: PhantomPinned,
 }
 
 impl ZygiskModule {
-    pub fn new(library: NativeLibrary) -> Result<PinnedZygiskModule> {
+    pub fn new(
+        library: NativeLibrary,
+        name: Option<String>,
+        module_dir: Option<OwnedFd>,
+        companion: Option<OwnedFd>,
+    ) -> Result<PinnedZygiskModule> {
         let entry_fn: extern "C" fn(*const ApiAbi, JNIEnv) =
             unsafe { mem::transmute(library.dlsym("zygisk_module_entry")?) };
 
         let mut instance = Box::pin(Self {
             library,
+            name,
             entry_fn,
             api: ApiAbi::new(),
             module: ptr::null(),
             options: [false; ZygiskOption::MAX_INDEX + 1],
+            module_dir,
+            companion: Cell::new(companion),
             _pin: Default::default(),
         });
 
@@ -46,33 +62,47 @@ impl ZygiskModule {
     }
 
     pub fn call_entry(&self, env: JNIEnv) -> bool {
-        (self.entry_fn)(&self.api, env);
+        injector::in_module(self.name.as_deref(), || (self.entry_fn)(&self.api, env));
         self.api.ready
     }
 
+    /// Whether the module asked for root mounts to be reverted in the app's mount namespace,
+    /// only meaningful after `preAppSpecialize` returns.
+    pub fn requests_unmount(&self) -> bool {
+        self.options[ZygiskOption::ForceDenylistUnmount.index()]
+    }
+
+    pub fn close_companion(&self) {
+        drop(self.companion.take());
+    }
+
     pub fn call_specialize_pre(&self, args: &mut SpecializeArgs) {
         let module = unsafe { &*self.module };
 
-        if args.is_system_server {
-            let args = ServerSpecializeArgs::new(args, module.version);
-            (module.server_pre)(module.remote_impl, &args);
-        } else {
-            let args = AppSpecializeArgs::new(args, module.version);
-            (module.app_pre)(module.remote_impl, &args);
-        }
+        injector::in_module(self.name.as_deref(), || {
+            if args.is_system_server {
+                let args = ServerSpecializeArgs::new(args, module.version);
+                (module.server_pre)(module.remote_impl, &args);
+            } else {
+                let args = AppSpecializeArgs::new(args, module.version);
+                (module.app_pre)(module.remote_impl, &args);
+            }
+        });
     }
 
     pub fn call_specialize_post(&self, args: &SpecializeArgs) {
         let args = &mut args.clone();
         let module = unsafe { &*self.module };
 
-        if args.is_system_server {
-            let args = ServerSpecializeArgs::new(args, module.version);
-            (module.server_pos)(module.remote_impl, &args);
-        } else {
-            let args = AppSpecializeArgs::new(args, module.version);
-            (module.app_pos)(module.remote_impl, &args);
-        }
+        injector::in_module(self.name.as_deref(), || {
+            if args.is_system_server {
+                let args = ServerSpecializeArgs::new(args, module.version);
+                (module.server_pos)(module.remote_impl, &args);
+            } else {
+                let args = AppSpecializeArgs::new(args, module.version);
+                (module.app_pos)(module.remote_impl, &args);
+            }
+        });
     }
 }
 
@@ -81,13 +111,5 @@ impl Drop for ZygiskModule {
         if self.options[ZygiskOption::DlcloseModuleLibrary.index()] {
             self.library.auto_close_on_drop();
         }
-
-        if self.options[ZygiskOption::ForceDenylistUnmount.index()] {
-            warn!(
-                "[{}] the FORCE_DENYLIST_UNMOUNT option is unsupported by zynx, modules should NOT inject into processes they don't care about!",
-                self.library.name()
-            );
-            self.library.auto_close_on_drop();
-        }
     }
 }
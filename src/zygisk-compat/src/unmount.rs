@@ -0,0 +1,120 @@
+//! Emulation of Magisk's denylist unmount, for modules setting `FORCE_DENYLIST_UNMOUNT`.
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use nix::mount::{MntFlags, MsFlags};
+use nix::sched::CloneFlags;
+use nix::{mount, sched};
+use std::fs;
+
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+/// Mount sources used by root solutions for their overlays and bind mounts
+const ROOT_SOURCES: [&str; 3] = ["magisk", "KSU", "APatch"];
+
+/// Paths whose content only exists because of root solutions and their modules
+const ROOT_PREFIXES: [&str; 2] = ["/data/adb", "/debug_ramdisk"];
+
+/// Decode the octal escapes of a mountinfo field, e.g. `\040` for the spaces of a path.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let escape = bytes.get(index + 1..index + 4).and_then(|digits| {
+            let digits = std::str::from_utf8(digits).ok()?;
+            u8::from_str_radix(digits, 8).ok()
+        });
+
+        match escape {
+            Some(byte) if bytes[index] == b'\\' => {
+                result.push(byte);
+                index += 4;
+            }
+            _ => {
+                result.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+struct MountEntry {
+    root: String,
+    mount_point: String,
+    source: String,
+}
+
+impl MountEntry {
+    /// Parse a line of `/proc/self/mountinfo`:
+    /// `<id> <parent> <dev> <root> <mount point> <options> [optional...] - <fstype> <source> <super options>`
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let root = fields.nth(3)?;
+        let mount_point = fields.next()?;
+        let source = fields.skip_while(|it| *it != "-").nth(2)?;
+
+        Some(Self {
+            root: unescape(root),
+            mount_point: unescape(mount_point),
+            source: unescape(source),
+        })
+    }
+
+    fn is_root_mount(&self) -> bool {
+        ROOT_SOURCES.contains(&self.source.as_str())
+            || ROOT_PREFIXES
+                .iter()
+                .any(|prefix| self.root.starts_with(prefix) || self.mount_point.starts_with(prefix))
+    }
+}
+
+/// Move the process into a private mount namespace and unmount everything mounted by root
+/// solutions there.
+///
+/// Must run before SpecializeCommon, which drops the privileges required for unmounting. The
+/// mount namespace it unshares for the app is copied from this one, so the unmounts carry over.
+pub fn revert_unmount() -> Result<()> {
+    sched::unshare(CloneFlags::CLONE_NEWNS).context("failed to unshare mount namespace")?;
+
+    // keep the unmounts from propagating back to zygote
+    mount::mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_SLAVE,
+        None::<&str>,
+    )
+    .context("failed to make mounts slave")?;
+
+    let mountinfo = fs::read_to_string(MOUNTINFO).context(format!("failed to read {MOUNTINFO}"))?;
+    let targets: Vec<_> = mountinfo
+        .lines()
+        .filter_map(MountEntry::parse)
+        .filter(MountEntry::is_root_mount)
+        .map(|entry| entry.mount_point)
+        .collect();
+
+    let mut unmounted = 0;
+
+    // children come after their parents in mountinfo, unmount them first
+    for target in targets.iter().rev() {
+        match mount::umount2(target.as_str(), MntFlags::MNT_DETACH) {
+            Ok(()) => {
+                debug!("unmounted: {target}");
+                unmounted += 1;
+            }
+            Err(err) => warn!("failed to unmount {target}: {err}"),
+        }
+    }
+
+    info!(
+        "denylist unmount: {unmounted}/{} mounts reverted",
+        targets.len()
+    );
+
+    Ok(())
+}